@@ -0,0 +1,47 @@
+//! Fuzz the WebSocket envelope parser and the blobs it carries
+//!
+//! Run with `cargo fuzz run envelope` from `client/rust`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mls_chat_client::envelope;
+use mls_chat_client::models::MlsMessageEnvelope;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    match envelope::parse_envelope(text) {
+        Ok(MlsMessageEnvelope::ApplicationMessage {
+            group_id,
+            encrypted_content,
+            ..
+        }) => {
+            let _ = envelope::decode_base64(&group_id);
+            let _ = envelope::decode_mls_blob(&encrypted_content);
+        }
+        Ok(MlsMessageEnvelope::WelcomeMessage {
+            welcome_blob,
+            ratchet_tree_blob,
+            ..
+        }) => {
+            let _ = envelope::decode_mls_blob(&welcome_blob);
+            let _ = envelope::decode_ratchet_tree(&ratchet_tree_blob);
+        }
+        Ok(MlsMessageEnvelope::CommitMessage {
+            group_id,
+            commit_blob,
+            ..
+        }) => {
+            let _ = envelope::decode_base64(&group_id);
+            let _ = envelope::decode_mls_blob(&commit_blob);
+        }
+        Err(_) => {
+            // The raw text may itself be a blob
+            let _ = envelope::decode_mls_blob(text);
+            let _ = envelope::decode_ratchet_tree(text);
+        }
+    }
+});
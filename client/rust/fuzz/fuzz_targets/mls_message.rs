@@ -0,0 +1,13 @@
+//! Fuzz the TLS decoder for Welcome/Commit/application messages
+//!
+//! Run with `cargo fuzz run mls_message` from `client/rust`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mls_chat_client::envelope;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = envelope::decode_mls_message(data);
+    let _ = envelope::parse_json::<openmls::prelude::RatchetTreeIn>(data);
+});
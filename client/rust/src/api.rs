@@ -4,17 +4,70 @@
 //! reservation/spend flows, and health/status queries against the MLS
 //! chat server.
 
-use crate::error::{KeyPackageError, NetworkError, Result};
+use crate::error::{ClientError, KeyPackageError, MlsError, NetworkError, Result};
+use crate::identity::PublicIdentityBundle;
 use base64::{engine::general_purpose, Engine as _};
-use reqwest::{Client, StatusCode};
+use openmls_traits::signatures::Signer;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::watch;
 
 /// Server API client
 #[derive(Clone)]
 pub struct ServerApi {
     client: Client,
     base_url: String,
+    config: ServerApiConfig,
+    status: Arc<watch::Sender<ServerStatus>>,
+}
+
+/// Availability of the server as last seen by `ServerApi`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerStatus {
+    /// The last request was not refused for load
+    Available,
+    /// The server asked us to back off; the request is retried after `retry_after`
+    Busy { retry_after: Duration },
+}
+
+impl std::fmt::Display for ServerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerStatus::Available => f.write_str("available"),
+            ServerStatus::Busy { retry_after } => {
+                write!(f, "server busy, retrying in {}s", retry_after.as_secs())
+            }
+        }
+    }
+}
+
+/// Timeouts applied to every request made by `ServerApi`
+///
+/// The defaults are short enough that a hung server surfaces as
+/// `NetworkError::Timeout` while the user is waiting; raise them for slow links.
+#[derive(Debug, Clone)]
+pub struct ServerApiConfig {
+    /// Total time allowed for a request, from connecting to the end of the response
+    pub request_timeout: Duration,
+    /// Time allowed to establish the TCP connection
+    pub connect_timeout: Duration,
+    /// How many times a request answered with `Retry-After` is retried
+    pub max_busy_retries: u32,
+    /// Upper bound on a single `Retry-After` wait
+    pub max_retry_after: Duration,
+}
+
+impl Default for ServerApiConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            max_busy_retries: 3,
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
 }
 
 /// Payload record for uploading a KeyPackage to the server
@@ -25,165 +78,1182 @@ pub struct KeyPackageUpload {
     pub not_after: i64,
 }
 
-/// Response returned after uploading a batch of KeyPackages
-#[derive(Debug, Clone, Deserialize)]
-pub struct UploadKeyPackagesResponse {
-    pub accepted: usize,
-    pub rejected: Vec<String>,
-    pub pool_size: usize,
-}
+/// Response returned after uploading a batch of KeyPackages
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadKeyPackagesResponse {
+    pub accepted: usize,
+    pub rejected: Vec<String>,
+    pub pool_size: usize,
+    /// Set by the server when some packages were rejected for a known reason
+    /// (e.g. `keypackage_quota_exceeded`)
+    #[serde(default)]
+    pub error_code: Option<String>,
+}
+
+/// Reserved KeyPackage details returned when the server grants a reservation
+#[derive(Debug, Clone)]
+pub struct ReservedKeyPackage {
+    pub keypackage_ref: Vec<u8>,
+    pub keypackage: Vec<u8>,
+    pub reservation_id: String,
+    pub reservation_expires_at: i64,
+    pub not_after: i64,
+}
+
+/// Aggregate pool status information returned by the server
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyPackagePoolStatus {
+    pub available: usize,
+    pub reserved: usize,
+    #[serde(default)]
+    pub spent: usize,
+    #[serde(default)]
+    pub expired: usize,
+    pub total: usize,
+    #[serde(default)]
+    pub expiring_soon: usize,
+    #[serde(default)]
+    pub pool_health: Option<String>,
+    #[serde(default)]
+    pub recommended_action: Option<String>,
+    #[serde(default)]
+    pub last_upload: Option<String>,
+}
+
+/// Lifecycle metadata of one KeyPackage as tracked by the server
+///
+/// The listing never includes the KeyPackage bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPackageSummary {
+    pub keypackage_ref: Vec<u8>,
+    /// Server status: available, reserved or spent
+    pub status: String,
+    pub not_after: i64,
+}
+
+/// A Commit the server accepted as the one moving a group out of `epoch`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SequencedCommit {
+    pub epoch: u64,
+    pub sender: String,
+    /// Base64 TLS-serialized Commit, as sent in a `CommitMessage` envelope
+    pub commit_blob: String,
+}
+
+/// A message the server stored for a group, as fetched by `ServerApi::messages_since`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StoredMessage {
+    /// Position in the group's history; increases with every stored message
+    pub seq: i64,
+    pub sender: String,
+    /// Base64 TLS-serialized MLS message, as sent in an `ApplicationMessage` envelope
+    pub encrypted_content: String,
+    #[serde(default)]
+    pub content_type: crate::models::ContentType,
+}
+
+/// A Welcome the server kept for us, as fetched by `ServerApi::pending_welcomes`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StoredWelcome {
+    /// Id to pass to `ServerApi::delete_welcome` once joined
+    pub id: i64,
+    pub inviter: String,
+    /// Base64 TLS-serialized Welcome, as sent in a `WelcomeMessage` envelope
+    pub welcome_blob: String,
+    /// Base64 exported ratchet tree
+    pub ratchet_tree_blob: String,
+}
+
+/// A single-use join token, as created by `ServerApi::create_join_token`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct JoinToken {
+    /// Secret to hand to the person who should join
+    pub token: String,
+    /// Unix seconds after which the token can no longer be redeemed
+    pub expires_at: i64,
+}
+
+/// A redeemed join token whose redeemer still has to be added to the group
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct JoinRequest {
+    pub token: String,
+    /// User who redeemed the token
+    pub username: String,
+    /// Unix seconds when the token was redeemed
+    pub redeemed_at: i64,
+}
+
+/// Public metadata the server keeps about a group
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ServerGroup {
+    /// Base64 group id
+    pub group_id: String,
+    /// Name the group was first registered under (its id if never registered)
+    pub name: String,
+    /// When the server first saw the group (RFC 3339)
+    pub created_at: String,
+}
+
+/// A member the server has recorded for a group (see
+/// `ServerApi::get_group_members`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ServerGroupMember {
+    pub username: String,
+    /// When the server recorded the membership (RFC 3339)
+    pub joined_at: String,
+}
+
+/// A group's GroupInfo as published for external joiners
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedGroupInfo {
+    pub epoch: u64,
+    /// TLS-serialized GroupInfo message
+    pub group_info: Vec<u8>,
+}
+
+/// A backup version the server keeps for a user, as listed by
+/// `ServerApi::list_backups`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BackupInfo {
+    /// Per-user version, increasing with every stored backup
+    pub version: i64,
+    /// When the server stored it
+    pub timestamp: String,
+    /// Length of the stored state in bytes; absent from older servers
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+/// One backup version with its contents, as fetched by `ServerApi::get_backup`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StoredBackup {
+    pub version: i64,
+    /// State as uploaded with `ServerApi::store_backup`; opaque to the server
+    pub encrypted_state: String,
+    pub timestamp: String,
+}
+
+/// Outcome of submitting a Commit with `ServerApi::sequence_commit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitSequencing {
+    /// The Commit is the first for its epoch and may be merged
+    Accepted,
+    /// Another Commit holds the epoch; these are the accepted Commits from
+    /// that epoch onward, to apply before rebuilding the change
+    Superseded(Vec<SequencedCommit>),
+}
+
+/// Delay requested by a 429/503 response through its `Retry-After` header (in seconds)
+fn retry_after(response: &Response) -> Option<Duration> {
+    if !matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+    let seconds = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Turn a 403 "group limit reached" response into `MlsError::GroupLimitReached`
+async fn group_limit_reached(response: Response, username: &str) -> ClientError {
+    #[derive(Deserialize)]
+    struct LimitResponse {
+        limit: Option<usize>,
+        error: Option<String>,
+    }
+
+    match response.json::<LimitResponse>().await {
+        Ok(LimitResponse {
+            limit: Some(limit), ..
+        }) => MlsError::GroupLimitReached {
+            username: username.to_string(),
+            limit,
+        }
+        .into(),
+        Ok(body) => {
+            NetworkError::Server(format!("Forbidden: {}", body.error.unwrap_or_default())).into()
+        }
+        Err(e) => NetworkError::Server(format!("Unexpected 403 response: {}", e)).into(),
+    }
+}
+
+/// Map a request failure, reporting timeouts as `NetworkError::Timeout`
+fn request_error(e: reqwest::Error) -> ClientError {
+    if e.is_timeout() {
+        NetworkError::Timeout.into()
+    } else {
+        e.into()
+    }
+}
+
+/// A group id as a URL path segment
+///
+/// Base64 may contain '/', '+' and '=', which a path segment must escape.
+fn group_id_path_segment(group_id: &[u8]) -> String {
+    general_purpose::STANDARD
+        .encode(group_id)
+        .replace('/', "%2F")
+        .replace('+', "%2B")
+        .replace('=', "%3D")
+}
+
+#[derive(Serialize)]
+struct RegisterUserRequest {
+    username: String,
+    key_package: Vec<u8>,
+}
+
+/// A user as registered on the server
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RegisterUserResponse {
+    /// Server-assigned user id, stable across re-registrations
+    pub id: i64,
+    pub username: String,
+    pub created_at: String,
+}
+
+#[derive(Deserialize)]
+struct UserKeyResponse {
+    username: String,
+    key_package: Vec<u8>,
+}
+
+impl ServerApi {
+    /// Create a new server API client with the default timeouts
+    pub fn new(base_url: &str) -> Self {
+        Self::with_config(base_url, ServerApiConfig::default())
+    }
+
+    /// Create a new server API client with explicit timeouts
+    pub fn with_config(base_url: &str, config: ServerApiConfig) -> Self {
+        let client = Client::builder()
+            .timeout(config.request_timeout)
+            .connect_timeout(config.connect_timeout)
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            client,
+            base_url: base_url.to_string(),
+            config,
+            status: Arc::new(watch::Sender::new(ServerStatus::Available)),
+        }
+    }
+
+    /// Current server availability (busy while waiting out a `Retry-After`)
+    pub fn status(&self) -> ServerStatus {
+        *self.status.borrow()
+    }
+
+    /// Subscribe to server availability changes
+    pub fn watch_status(&self) -> watch::Receiver<ServerStatus> {
+        self.status.subscribe()
+    }
+
+    /// Send a request, honoring `Retry-After` on 429/503 responses
+    ///
+    /// While waiting the status is `ServerStatus::Busy`. After
+    /// `max_busy_retries` retries the last response is returned as-is, so the
+    /// caller reports it like any other server error.
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let mut retries = 0;
+        loop {
+            let attempt = request
+                .try_clone()
+                .ok_or_else(|| NetworkError::Server("Request cannot be retried".to_string()))?;
+            let response = attempt.send().await.map_err(request_error)?;
+
+            let retry_after = match retry_after(&response) {
+                Some(delay) if retries < self.config.max_busy_retries => {
+                    delay.min(self.config.max_retry_after)
+                }
+                _ => {
+                    self.status.send_replace(ServerStatus::Available);
+                    return Ok(response);
+                }
+            };
+
+            retries += 1;
+            log::warn!(
+                "Server busy ({}), retrying in {}s (attempt {}/{})",
+                response.status(),
+                retry_after.as_secs(),
+                retries,
+                self.config.max_busy_retries
+            );
+            self.status.send_replace(ServerStatus::Busy { retry_after });
+            tokio::time::sleep(retry_after).await;
+        }
+    }
+
+    /// Get the timeouts this client was built with
+    pub fn config(&self) -> &ServerApiConfig {
+        &self.config
+    }
+
+    /// Register a user with the server, sending their KeyPackage
+    ///
+    /// Idempotent: registering again with the same KeyPackage returns the
+    /// existing user, so the server-assigned id is stable across sessions.
+    /// 409 Conflict means the username is taken with a different KeyPackage,
+    /// which indicates identity compromise or key material mismatch.
+    pub async fn register_user(
+        &self,
+        username: &str,
+        key_package: &[u8],
+    ) -> Result<RegisterUserResponse> {
+        let request = RegisterUserRequest {
+            username: username.to_string(),
+            key_package: key_package.to_vec(),
+        };
+
+        let response = self
+            .send(
+                self.client
+                    .post(format!("{}/users", self.base_url))
+                    .json(&request),
+            )
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let registered: RegisterUserResponse = response.json().await?;
+                log::info!(
+                    "User {} registered with server (id {})",
+                    username,
+                    registered.id
+                );
+                Ok(registered)
+            }
+            StatusCode::CONFLICT => {
+                log::error!(
+                    "SECURITY: Key package mismatch for user {}. Local key differs from server.",
+                    username
+                );
+                Err(NetworkError::Server(format!(
+                    "Key package mismatch for user '{}': local key differs from stored key on server. \
+                    This may indicate identity compromise. Please use a different username.",
+                    username
+                ))
+                .into())
+            }
+            status => Err(NetworkError::Server(format!("Registration failed: {}", status)).into()),
+        }
+    }
+
+    /// Get a user's KeyPackage from the server
+    pub async fn get_user_key(&self, username: &str) -> Result<Vec<u8>> {
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/users/{}", self.base_url, username)),
+            )
+            .await?;
+
+        if response.status().is_success() {
+            let user_key: UserKeyResponse = response.json().await?;
+            assert!(user_key.username == username);
+            Ok(user_key.key_package)
+        } else if response.status() == 404 {
+            Err(NetworkError::Server("User not found".to_string()).into())
+        } else {
+            Err(
+                NetworkError::Server(format!("Failed to get user key: {}", response.status()))
+                    .into(),
+            )
+        }
+    }
+
+    /// Fetch a user's public identity from the server's directory
+    ///
+    /// The server reads it out of the KeyPackage the user registered with, so
+    /// it only ever contains public material. Returns `None` for an unknown
+    /// user.
+    pub async fn get_user_identity(&self, username: &str) -> Result<Option<PublicIdentityBundle>> {
+        #[derive(Deserialize)]
+        struct UserIdentityResponse {
+            username: String,
+            credential: String,
+            signature_key: String,
+        }
+
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/users/{}/identity", self.base_url, username)),
+            )
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: UserIdentityResponse = response.json().await?;
+                let decode = |field: &str| {
+                    general_purpose::STANDARD.decode(field).map_err(|e| {
+                        NetworkError::Server(format!("Invalid identity from server: {}", e))
+                    })
+                };
+                Ok(Some(PublicIdentityBundle {
+                    credential: decode(&body.credential)?,
+                    signature_key: decode(&body.signature_key)?,
+                    username: body.username,
+                }))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(NetworkError::Server(format!(
+                "Failed to fetch user identity: {}",
+                status
+            ))
+            .into()),
+        }
+    }
+
+    /// Register `name` (a `user:group` key) as the name of `group_id`
+    ///
+    /// Idempotent for the group that owns the name. If a different group was
+    /// registered under `name` first (e.g. from another device), returns
+    /// `MlsError::DuplicateGroup` with the existing group id.
+    pub async fn register_group_name(&self, name: &str, group_id: &[u8]) -> Result<()> {
+        #[derive(Deserialize)]
+        struct ConflictResponse {
+            existing_group_id: String,
+        }
+
+        let request =
+            self.client
+                .post(format!("{}/groups", self.base_url))
+                .json(&serde_json::json!({
+                    "group_id": general_purpose::STANDARD.encode(group_id),
+                    "name": name,
+                }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::CONFLICT => {
+                let conflict: ConflictResponse = response.json().await?;
+                Err(MlsError::DuplicateGroup {
+                    name: name.to_string(),
+                    existing_group_id: conflict.existing_group_id,
+                }
+                .into())
+            }
+            status => Err(NetworkError::Server(format!(
+                "Failed to register group name: {}",
+                status
+            ))
+            .into()),
+        }
+    }
+
+    /// Record on the server that `username` created or joined `group_id`
+    ///
+    /// The request is signed with `signer`, the signature key of the
+    /// KeyPackage `username` registered with. A group that already has
+    /// members can only be joined once an existing member has reserved one
+    /// of the user's KeyPackages for it. Returns `MlsError::GroupLimitReached`
+    /// if the user is already in the server's maximum number of groups.
+    /// Repeating the call for the same group succeeds.
+    pub async fn join_group(
+        &self,
+        username: &str,
+        group_id: &[u8],
+        signer: &impl Signer,
+    ) -> Result<()> {
+        let group_id = general_purpose::STANDARD.encode(group_id);
+        let timestamp = chrono::Utc::now().timestamp();
+        let payload = format!(
+            "mls-chat-join-group:{}:{}:{}",
+            username, group_id, timestamp
+        );
+        let signature = signer
+            .sign(payload.as_bytes())
+            .map_err(|e| MlsError::OpenMls(format!("Failed to sign group join: {:?}", e)))?;
+        let request = self
+            .client
+            .post(format!("{}/groups/members", self.base_url))
+            .json(&serde_json::json!({
+                "username": username,
+                "group_id": group_id,
+                "timestamp": timestamp,
+                "signature": general_purpose::STANDARD.encode(signature),
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::FORBIDDEN => Err(group_limit_reached(response, username).await),
+            status => Err(NetworkError::Server(format!("Failed to join group: {}", status)).into()),
+        }
+    }
+
+    /// Tell the server how long to keep messages of `group_id`
+    ///
+    /// The authoritative TTL lives in the group metadata (changed by Commit);
+    /// this only lets the server prune its copies. Applies to messages stored
+    /// from now on; `None` keeps them indefinitely.
+    pub async fn set_group_message_ttl(
+        &self,
+        group_id: &[u8],
+        ttl_secs: Option<u64>,
+    ) -> Result<()> {
+        let request = self
+            .client
+            .post(format!("{}/groups/retention", self.base_url))
+            .json(&serde_json::json!({
+                "group_id": general_purpose::STANDARD.encode(group_id),
+                "message_ttl_secs": ttl_secs,
+            }));
+        let response = self.send(request).await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(NetworkError::Server(format!(
+                "Failed to set message retention: {}",
+                response.status()
+            ))
+            .into())
+        }
+    }
+
+    /// Submit a Commit moving `group_id` out of `epoch` before merging it
+    ///
+    /// The server accepts one Commit per epoch, so of two members changing
+    /// the group concurrently only the first wins; the other gets
+    /// `CommitSequencing::Superseded` with the Commits it missed.
+    pub async fn sequence_commit(
+        &self,
+        group_id: &[u8],
+        epoch: u64,
+        sender: &str,
+        commit_blob: &str,
+    ) -> Result<CommitSequencing> {
+        #[derive(Deserialize)]
+        struct ConflictResponse {
+            commits: Vec<SequencedCommit>,
+        }
+
+        let request = self
+            .client
+            .post(format!("{}/groups/commits", self.base_url))
+            .json(&serde_json::json!({
+                "group_id": general_purpose::STANDARD.encode(group_id),
+                "epoch": epoch,
+                "sender": sender,
+                "commit_blob": commit_blob,
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(CommitSequencing::Accepted),
+            StatusCode::CONFLICT => {
+                let conflict: ConflictResponse = response.json().await?;
+                Ok(CommitSequencing::Superseded(conflict.commits))
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to sequence commit: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch the Commits the server accepted for `group_id` from `epoch` onward,
+    /// in epoch order
+    pub async fn commits_since(&self, group_id: &[u8], epoch: u64) -> Result<Vec<SequencedCommit>> {
+        #[derive(Deserialize)]
+        struct CommitsResponse {
+            commits: Vec<SequencedCommit>,
+        }
+
+        let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+        let request = self
+            .client
+            .get(format!("{}/groups/commits", self.base_url))
+            .query(&[
+                ("group_id", group_id_b64.as_str()),
+                ("epoch", epoch.to_string().as_str()),
+            ]);
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: CommitsResponse = response.json().await?;
+                Ok(body.commits)
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch commits: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch up to `limit` messages of `group_id` stored after `after_seq`,
+    /// oldest first
+    ///
+    /// Only members may read a group's messages; `username` is the member asking.
+    pub async fn messages_since(
+        &self,
+        group_id: &[u8],
+        username: &str,
+        after_seq: i64,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        #[derive(Deserialize)]
+        struct MessagesResponse {
+            messages: Vec<StoredMessage>,
+        }
+
+        let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+        let request = self
+            .client
+            .get(format!("{}/groups/messages", self.base_url))
+            .query(&[
+                ("group_id", group_id_b64.as_str()),
+                ("username", username),
+                ("after", after_seq.to_string().as_str()),
+                ("limit", limit.to_string().as_str()),
+            ]);
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: MessagesResponse = response.json().await?;
+                Ok(body.messages)
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch messages: {}", status)).into())
+            }
+        }
+    }
+
+    /// Publish the GroupInfo of `group_id` at `epoch` for external joiners
+    ///
+    /// `group_info` is a TLS-serialized GroupInfo message; only public group
+    /// state may be published (see `crypto::export_public_group_info`).
+    /// Returns false if the server already has one from a later epoch.
+    pub async fn publish_group_info(
+        &self,
+        group_id: &[u8],
+        epoch: u64,
+        group_info: &[u8],
+    ) -> Result<bool> {
+        let request = self
+            .client
+            .post(format!("{}/groups/info", self.base_url))
+            .json(&serde_json::json!({
+                "group_id": general_purpose::STANDARD.encode(group_id),
+                "epoch": epoch,
+                "group_info_blob": general_purpose::STANDARD.encode(group_info),
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::CONFLICT => Ok(false),
+            status => {
+                Err(NetworkError::Server(format!("Failed to publish GroupInfo: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch the latest GroupInfo published for `group_id`, if any
+    pub async fn get_group_info(&self, group_id: &[u8]) -> Result<Option<PublishedGroupInfo>> {
+        #[derive(Deserialize)]
+        struct GroupInfoResponse {
+            epoch: u64,
+            group_info_blob: String,
+        }
+
+        let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+        let request = self
+            .client
+            .get(format!("{}/groups/info", self.base_url))
+            .query(&[("group_id", group_id_b64.as_str())]);
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: GroupInfoResponse = response.json().await?;
+                let group_info = general_purpose::STANDARD
+                    .decode(&body.group_info_blob)
+                    .map_err(|e| {
+                        NetworkError::Server(format!("Invalid GroupInfo from server: {}", e))
+                    })?;
+                Ok(Some(PublishedGroupInfo {
+                    epoch: body.epoch,
+                    group_info,
+                }))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch GroupInfo: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch the highest epoch the server has seen a Commit move `group_id` into
+    ///
+    /// Advisory: the server reads epochs from Commit headers it cannot
+    /// verify. `None` if it has not seen a Commit for the group.
+    pub async fn group_epoch(&self, group_id: &[u8]) -> Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct GroupEpochResponse {
+            epoch: u64,
+        }
+
+        let response = self
+            .send(self.client.get(format!(
+                "{}/groups/{}/epoch",
+                self.base_url,
+                group_id_path_segment(group_id)
+            )))
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: GroupEpochResponse = response.json().await?;
+                Ok(Some(body.epoch))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch group epoch: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch the members the server has recorded for `group_id`, by username
+    ///
+    /// Best effort and unverified: the server records memberships when
+    /// users create, join or are added to a group, and cannot see removals.
+    /// Empty if the server has recorded none.
+    pub async fn get_group_members(&self, group_id: &[u8]) -> Result<Vec<ServerGroupMember>> {
+        #[derive(Deserialize)]
+        struct GroupMembersResponse {
+            members: Vec<ServerGroupMember>,
+        }
+
+        let response = self
+            .send(self.client.get(format!(
+                "{}/groups/{}/members",
+                self.base_url,
+                group_id_path_segment(group_id)
+            )))
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: GroupMembersResponse = response.json().await?;
+                Ok(body.members)
+            }
+            StatusCode::NOT_FOUND => Ok(Vec::new()),
+            status => Err(NetworkError::Server(format!(
+                "Failed to fetch group members: {}",
+                status
+            ))
+            .into()),
+        }
+    }
+
+    /// Look up what the server knows about `group_id`
+    ///
+    /// Lets a client show which group an invitation is for before joining.
+    /// `None` if the server has never seen the group.
+    pub async fn get_group(&self, group_id: &[u8]) -> Result<Option<ServerGroup>> {
+        let response = self
+            .send(self.client.get(format!(
+                "{}/groups/{}",
+                self.base_url,
+                group_id_path_segment(group_id)
+            )))
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                Err(NetworkError::Server(format!("Failed to look up group: {}", status)).into())
+            }
+        }
+    }
+
+    /// Keep a Welcome on the server for `invitee` to fetch on their next login
+    ///
+    /// The blobs are the base64 strings of a `WelcomeMessage` envelope. The
+    /// server expires them after its configured Welcome TTL.
+    pub async fn store_welcome(
+        &self,
+        inviter: &str,
+        invitee: &str,
+        welcome_blob: &str,
+        ratchet_tree_blob: &str,
+    ) -> Result<()> {
+        let request = self
+            .client
+            .post(format!("{}/welcomes", self.base_url))
+            .json(&serde_json::json!({
+                "inviter": inviter,
+                "invitee": invitee,
+                "welcome_blob": welcome_blob,
+                "ratchet_tree_blob": ratchet_tree_blob,
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => {
+                Err(NetworkError::Server(format!("Failed to store Welcome: {}", status)).into())
+            }
+        }
+    }
+
+    /// Fetch the unexpired Welcomes stored for `username`, oldest first
+    pub async fn pending_welcomes(&self, username: &str) -> Result<Vec<StoredWelcome>> {
+        #[derive(Deserialize)]
+        struct WelcomesResponse {
+            welcomes: Vec<StoredWelcome>,
+        }
+
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/welcomes/{}", self.base_url, username)),
+            )
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: WelcomesResponse = response.json().await?;
+                Ok(body.welcomes)
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch Welcomes: {}", status)).into())
+            }
+        }
+    }
+
+    /// Delete a stored Welcome of `username`
+    ///
+    /// Returns false if the server no longer has it (already deleted or expired).
+    pub async fn delete_welcome(&self, username: &str, id: i64) -> Result<bool> {
+        let response = self
+            .send(
+                self.client
+                    .delete(format!("{}/welcomes/{}/{}", self.base_url, username, id)),
+            )
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => {
+                Err(NetworkError::Server(format!("Failed to delete Welcome: {}", status)).into())
+            }
+        }
+    }
+
+    /// Delete `username`'s account on the server
+    ///
+    /// Removes the user with their KeyPackages, backups, stored Welcomes and
+    /// group memberships. The request is signed with `signer`, which must be
+    /// the signature key the user registered with. Groups the user is in
+    /// keep their leaf until a member removes it. Returns false if the server
+    /// did not know the user.
+    ///
+    /// # Errors
+    /// * `NetworkError::Server` if the server refuses the signature
+    pub async fn deregister(&self, username: &str, signer: &impl Signer) -> Result<bool> {
+        let timestamp = chrono::Utc::now().timestamp();
+        let payload = format!("mls-chat-deregister:{}:{}", username, timestamp);
+        let signature = signer
+            .sign(payload.as_bytes())
+            .map_err(|e| MlsError::OpenMls(format!("Failed to sign deregistration: {:?}", e)))?;
+        let request = self
+            .client
+            .delete(format!("{}/users/{}", self.base_url, username))
+            .json(&serde_json::json!({
+                "timestamp": timestamp,
+                "signature": general_purpose::STANDARD.encode(signature),
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(NetworkError::Server(format!("Failed to deregister: {}", status)).into()),
+        }
+    }
+
+    /// Replace `username`'s registered KeyPackage after a credential rotation
+    ///
+    /// `key_package` must be bound to the new credential; the request is
+    /// signed with `old_signer`, the signature key of the KeyPackage
+    /// registered so far. The server revokes every unspent KeyPackage of the
+    /// user, so invites can no longer reserve ones under the old credential.
+    /// Returns the number of KeyPackages revoked.
+    ///
+    /// # Errors
+    /// * `NetworkError::Server` if the server refuses the signature or the
+    ///   KeyPackage
+    pub async fn rotate_registration(
+        &self,
+        username: &str,
+        key_package: &[u8],
+        old_signer: &impl Signer,
+    ) -> Result<usize> {
+        let timestamp = chrono::Utc::now().timestamp();
+        let mut payload = format!("mls-chat-rotate:{}:{}:", username, timestamp).into_bytes();
+        payload.extend_from_slice(key_package);
+        let signature = old_signer
+            .sign(&payload)
+            .map_err(|e| MlsError::OpenMls(format!("Failed to sign key rotation: {:?}", e)))?;
+        let request = self
+            .client
+            .put(format!("{}/users/{}", self.base_url, username))
+            .json(&serde_json::json!({
+                "key_package": key_package,
+                "timestamp": timestamp,
+                "signature": general_purpose::STANDARD.encode(signature),
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let body: serde_json::Value = response.json().await?;
+                Ok(body["revoked"].as_u64().unwrap_or(0) as usize)
+            }
+            status => Err(NetworkError::Server(format!(
+                "Failed to re-register rotated key: {}",
+                status
+            ))
+            .into()),
+        }
+    }
+
+    /// Store a new backup version of `username`'s state, returning its version
+    ///
+    /// The server keeps a bounded number of versions and drops the oldest.
+    pub async fn store_backup(&self, username: &str, encrypted_state: &str) -> Result<i64> {
+        let request = self
+            .client
+            .post(format!("{}/backup/{}", self.base_url, username))
+            .json(&serde_json::json!({ "encrypted_state": encrypted_state }));
+        let response = self.send(request).await?;
 
-/// Reserved KeyPackage details returned when the server grants a reservation
-#[derive(Debug, Clone)]
-pub struct ReservedKeyPackage {
-    pub keypackage_ref: Vec<u8>,
-    pub keypackage: Vec<u8>,
-    pub reservation_id: String,
-    pub reservation_expires_at: i64,
-    pub not_after: i64,
-}
+        match response.status() {
+            status if status.is_success() => {
+                let body: StoredBackup = response.json().await?;
+                Ok(body.version)
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to store backup: {}", status)).into())
+            }
+        }
+    }
 
-/// Aggregate pool status information returned by the server
-#[derive(Debug, Clone, Deserialize)]
-pub struct KeyPackagePoolStatus {
-    pub available: usize,
-    pub reserved: usize,
-    #[serde(default)]
-    pub spent: usize,
-    #[serde(default)]
-    pub expired: usize,
-    pub total: usize,
-    #[serde(default)]
-    pub expiring_soon: usize,
-    #[serde(default)]
-    pub pool_health: Option<String>,
-    #[serde(default)]
-    pub recommended_action: Option<String>,
-    #[serde(default)]
-    pub last_upload: Option<String>,
-}
+    /// List the backup versions the server keeps for `username`, newest first
+    pub async fn list_backups(&self, username: &str) -> Result<Vec<BackupInfo>> {
+        #[derive(Deserialize)]
+        struct VersionsResponse {
+            versions: Vec<BackupInfo>,
+        }
 
-#[derive(Serialize)]
-struct RegisterUserRequest {
-    username: String,
-    key_package: Vec<u8>,
-}
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/backups/{}/versions", self.base_url, username)),
+            )
+            .await?;
 
-#[derive(Deserialize)]
-struct UserKeyResponse {
-    username: String,
-    key_package: Vec<u8>,
-}
+        match response.status() {
+            status if status.is_success() => {
+                let body: VersionsResponse = response.json().await?;
+                Ok(body.versions)
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to list backups: {}", status)).into())
+            }
+        }
+    }
 
-impl ServerApi {
-    /// Create a new server API client
-    pub fn new(base_url: &str) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .expect("Failed to create HTTP client");
+    /// Fetch backup `version` of `username`
+    ///
+    /// `None` if the server has no such version (never stored or pruned).
+    pub async fn get_backup(&self, username: &str, version: i64) -> Result<Option<StoredBackup>> {
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/backups/{}", self.base_url, username))
+                    .query(&[("version", version)]),
+            )
+            .await?;
 
-        Self {
-            client,
-            base_url: base_url.to_string(),
+        match response.status() {
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => {
+                Err(NetworkError::Server(format!("Failed to fetch backup: {}", status)).into())
+            }
         }
     }
 
-    /// Register a user with the server, sending their KeyPackage
+    /// Create a single-use token that lets its holder ask to join `group_id`
     ///
-    /// Idempotent: 409 Conflict (user already exists) is treated as success only if
-    /// the stored key package matches the local one. If they differ, returns an error
-    /// indicating identity compromise or key material mismatch.
-    pub async fn register_user(&self, username: &str, key_package: &[u8]) -> Result<()> {
-        let request = RegisterUserRequest {
-            username: username.to_string(),
-            key_package: key_package.to_vec(),
-        };
+    /// `created_by` must be a member of the group. The token expires after
+    /// `ttl_secs`, or the server's default when `None`.
+    pub async fn create_join_token(
+        &self,
+        group_id: &[u8],
+        created_by: &str,
+        ttl_secs: Option<u64>,
+    ) -> Result<JoinToken> {
+        let request = self
+            .client
+            .post(format!("{}/groups/tokens", self.base_url))
+            .json(&serde_json::json!({
+                "group_id": general_purpose::STANDARD.encode(group_id),
+                "created_by": created_by,
+                "ttl_seconds": ttl_secs,
+            }));
+        let response = self.send(request).await?;
 
-        let response = self
+        match response.status() {
+            status if status.is_success() => Ok(response.json().await?),
+            status => {
+                Err(NetworkError::Server(format!("Failed to create join token: {}", status)).into())
+            }
+        }
+    }
+
+    /// Redeem a join token as `username`, returning the group it is for
+    ///
+    /// A member of the group still has to add the user (see
+    /// `join_requests`). Returns `MlsError::JoinTokenRejected` if the token
+    /// is unknown, expired, revoked or was already redeemed.
+    pub async fn redeem_join_token(&self, token: &str, username: &str) -> Result<Vec<u8>> {
+        #[derive(Deserialize)]
+        struct RedeemResponse {
+            group_id: String,
+        }
+
+        #[derive(Deserialize)]
+        struct RejectedResponse {
+            reason: String,
+        }
+
+        let request = self
             .client
-            .post(format!("{}/users", self.base_url))
-            .json(&request)
-            .send()
-            .await?;
+            .post(format!("{}/groups/tokens/redeem", self.base_url))
+            .json(&serde_json::json!({
+                "token": token,
+                "username": username,
+            }));
+        let response = self.send(request).await?;
 
         match response.status() {
             status if status.is_success() => {
-                log::info!("User {} registered with server", username);
-                Ok(())
+                let body: RedeemResponse = response.json().await?;
+                general_purpose::STANDARD
+                    .decode(&body.group_id)
+                    .map_err(|e| {
+                        NetworkError::Server(format!("Invalid group id in redeem response: {}", e))
+                            .into()
+                    })
             }
-            StatusCode::CONFLICT => {
-                // User already registered - verify the stored key package matches
-                log::info!(
-                    "User {} already registered, validating key package",
-                    username
-                );
-
-                match self.get_user_key(username).await {
-                    Ok(remote_key_package) => {
-                        if remote_key_package == key_package {
-                            log::info!("User {} identity verified - key packages match", username);
-                            Ok(())
-                        } else {
-                            log::error!(
-                                "SECURITY: Key package mismatch for user {}. Local key differs from server.",
-                                username
-                            );
-                            Err(NetworkError::Server(
-                                format!(
-                                    "Key package mismatch for user '{}': local key differs from stored key on server. \
-                                    This may indicate identity compromise. Please use a different username.",
-                                    username
-                                )
-                            ).into())
-                        }
-                    }
-                    Err(e) => {
-                        log::error!(
-                            "Failed to verify key package for user {} on conflict: {}",
-                            username,
-                            e
-                        );
-                        Err(NetworkError::Server(format!(
-                            "Cannot verify existing user {}: {}",
-                            username, e
-                        ))
-                        .into())
-                    }
+            StatusCode::NOT_FOUND => Err(MlsError::JoinTokenRejected {
+                reason: "not found".to_string(),
+            }
+            .into()),
+            StatusCode::GONE => {
+                let body: RejectedResponse = response.json().await?;
+                Err(MlsError::JoinTokenRejected {
+                    reason: body.reason.replace('_', " "),
                 }
+                .into())
+            }
+            status => {
+                Err(NetworkError::Server(format!("Failed to redeem join token: {}", status)).into())
             }
-            status => Err(NetworkError::Server(format!("Registration failed: {}", status)).into()),
         }
     }
 
-    /// Get a user's KeyPackage from the server
-    pub async fn get_user_key(&self, username: &str) -> Result<Vec<u8>> {
+    /// Revoke a join token that has not been redeemed yet
+    ///
+    /// Returns false if no such unredeemed token exists in a group of `username`.
+    pub async fn revoke_join_token(&self, token: &str, username: &str) -> Result<bool> {
+        self.join_token_action("revoke", token, username).await
+    }
+
+    /// Redeemed join tokens of `group_id` whose redeemer has not been added yet
+    pub async fn join_requests(&self, group_id: &[u8], username: &str) -> Result<Vec<JoinRequest>> {
+        #[derive(Deserialize)]
+        struct RequestsResponse {
+            requests: Vec<JoinRequest>,
+        }
+
         let response = self
-            .client
-            .get(format!("{}/users/{}", self.base_url, username))
-            .send()
+            .send(
+                self.client
+                    .get(format!(
+                        "{}/groups/{}/join-requests",
+                        self.base_url,
+                        group_id_path_segment(group_id)
+                    ))
+                    .query(&[("username", username)]),
+            )
             .await?;
 
-        if response.status().is_success() {
-            let user_key: UserKeyResponse = response.json().await?;
-            assert!(user_key.username == username);
-            Ok(user_key.key_package)
-        } else if response.status() == 404 {
-            Err(NetworkError::Server("User not found".to_string()).into())
-        } else {
-            Err(
-                NetworkError::Server(format!("Failed to get user key: {}", response.status()))
-                    .into(),
-            )
+        match response.status() {
+            status if status.is_success() => {
+                let body: RequestsResponse = response.json().await?;
+                Ok(body.requests)
+            }
+            status => Err(NetworkError::Server(format!(
+                "Failed to list join requests: {}",
+                status
+            ))
+            .into()),
+        }
+    }
+
+    /// Mark the join request of `token` as done once its redeemer was added
+    ///
+    /// Returns false if there is no such pending request.
+    pub async fn complete_join_request(&self, token: &str, username: &str) -> Result<bool> {
+        self.join_token_action("complete", token, username).await
+    }
+
+    /// POST `{token, username}` to `/groups/tokens/{action}`
+    async fn join_token_action(&self, action: &str, token: &str, username: &str) -> Result<bool> {
+        let request = self
+            .client
+            .post(format!("{}/groups/tokens/{}", self.base_url, action))
+            .json(&serde_json::json!({
+                "token": token,
+                "username": username,
+            }));
+        let response = self.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(NetworkError::Server(format!(
+                "Failed to {} join token: {}",
+                action, status
+            ))
+            .into()),
         }
     }
 
     /// Check if the server is healthy
     pub async fn health_check(&self) -> Result<()> {
         let response = self
-            .client
-            .get(format!("{}/health", self.base_url))
-            .send()
+            .send(self.client.get(format!("{}/health", self.base_url)))
             .await?;
 
         if response.status().is_success() {
@@ -231,10 +1301,11 @@ impl ServerApi {
         };
 
         let response = self
-            .client
-            .post(format!("{}/keypackages/upload", self.base_url))
-            .json(&request)
-            .send()
+            .send(
+                self.client
+                    .post(format!("{}/keypackages/upload", self.base_url))
+                    .json(&request),
+            )
             .await?;
 
         if response.status().is_success() {
@@ -246,17 +1317,23 @@ impl ServerApi {
     }
 
     /// Reserve a KeyPackage for inviting `target_username` into `group_id`
+    ///
+    /// `reserved_by` must be a member of the group; the request is signed
+    /// with `signer`, the signature key of their registered KeyPackage.
     pub async fn reserve_key_package(
         &self,
         target_username: &str,
         group_id: &[u8],
         reserved_by: &str,
+        signer: &impl Signer,
     ) -> Result<ReservedKeyPackage> {
         #[derive(Serialize)]
         struct ReserveRequest<'a> {
             target_username: &'a str,
             reserved_by: &'a str,
             group_id: String,
+            timestamp: i64,
+            signature: String,
         }
 
         #[derive(Deserialize)]
@@ -268,17 +1345,29 @@ impl ServerApi {
             not_after: i64,
         }
 
+        let group_id = general_purpose::STANDARD.encode(group_id);
+        let timestamp = chrono::Utc::now().timestamp();
+        let payload = format!(
+            "mls-chat-reserve:{}:{}:{}:{}",
+            reserved_by, target_username, group_id, timestamp
+        );
+        let signature = signer
+            .sign(payload.as_bytes())
+            .map_err(|e| MlsError::OpenMls(format!("Failed to sign reservation: {:?}", e)))?;
         let request = ReserveRequest {
             target_username,
             reserved_by,
-            group_id: general_purpose::STANDARD.encode(group_id),
+            group_id,
+            timestamp,
+            signature: general_purpose::STANDARD.encode(signature),
         };
 
         let response = self
-            .client
-            .post(format!("{}/keypackages/reserve", self.base_url))
-            .json(&request)
-            .send()
+            .send(
+                self.client
+                    .post(format!("{}/keypackages/reserve", self.base_url))
+                    .json(&request),
+            )
             .await?;
 
         match response.status() {
@@ -313,6 +1402,7 @@ impl ServerApi {
                     not_after: payload.not_after,
                 })
             }
+            StatusCode::FORBIDDEN => Err(group_limit_reached(response, target_username).await),
             StatusCode::NOT_FOUND => {
                 Err(NetworkError::KeyPackage(KeyPackageError::PoolExhausted {
                     username: target_username.to_string(),
@@ -347,10 +1437,11 @@ impl ServerApi {
         };
 
         let response = self
-            .client
-            .post(format!("{}/keypackages/spend", self.base_url))
-            .json(&request)
-            .send()
+            .send(
+                self.client
+                    .post(format!("{}/keypackages/spend", self.base_url))
+                    .json(&request),
+            )
             .await?;
 
         match response.status() {
@@ -374,12 +1465,196 @@ impl ServerApi {
         }
     }
 
+    /// Spend the KeyPackage held by `reservation` once its Welcome is out
+    pub async fn spend_reservation(
+        &self,
+        reservation: &ReservedKeyPackage,
+        group_id: &[u8],
+        spent_by: &str,
+    ) -> Result<()> {
+        self.spend_key_package(&reservation.keypackage_ref, group_id, spent_by)
+            .await
+    }
+
+    /// Release a reservation so the invitee's KeyPackage can be reserved again
+    ///
+    /// Returns whether a reservation was released. Releasing a reservation
+    /// that was already spent, released or expired is a no-op returning
+    /// `false`, so this is safe to call on any abort path.
+    pub async fn release_reservation(&self, reservation_id: &str) -> Result<bool> {
+        #[derive(Serialize)]
+        struct ReleaseRequest<'a> {
+            reservation_id: &'a str,
+        }
+
+        #[derive(Deserialize)]
+        struct ReleaseResponse {
+            released: bool,
+        }
+
+        let response = self
+            .send(
+                self.client
+                    .post(format!("{}/keypackages/release", self.base_url))
+                    .json(&ReleaseRequest { reservation_id }),
+            )
+            .await?;
+
+        if response.status().is_success() {
+            let payload: ReleaseResponse = response.json().await.map_err(|e| {
+                NetworkError::KeyPackage(KeyPackageError::InvalidResponse {
+                    message: format!("Failed to parse release response: {}", e),
+                })
+            })?;
+            Ok(payload.released)
+        } else {
+            Err(NetworkError::KeyPackage(KeyPackageError::ServerError {
+                message: format!("Failed to release reservation: {}", response.status()),
+            })
+            .into())
+        }
+    }
+
+    /// Revoke `username`'s KeyPackages with the given refs on the server
+    ///
+    /// Revoked KeyPackages can no longer be reserved, and spending an
+    /// existing reservation on one fails. Spent, unknown and other users'
+    /// refs are ignored. Returns the number of KeyPackages revoked.
+    pub async fn revoke_key_packages(
+        &self,
+        username: &str,
+        keypackage_refs: &[Vec<u8>],
+    ) -> Result<usize> {
+        #[derive(Serialize)]
+        struct RevokeRequest<'a> {
+            username: &'a str,
+            keypackage_refs: Vec<String>,
+        }
+
+        #[derive(Deserialize)]
+        struct RevokeResponse {
+            revoked: usize,
+        }
+
+        let request = RevokeRequest {
+            username,
+            keypackage_refs: keypackage_refs
+                .iter()
+                .map(|keypackage_ref| general_purpose::STANDARD.encode(keypackage_ref))
+                .collect(),
+        };
+
+        let response = self
+            .send(
+                self.client
+                    .post(format!("{}/keypackages/revoke", self.base_url))
+                    .json(&request),
+            )
+            .await?;
+
+        if response.status().is_success() {
+            let payload: RevokeResponse = response.json().await.map_err(|e| {
+                NetworkError::KeyPackage(KeyPackageError::InvalidResponse {
+                    message: format!("Failed to parse revoke response: {}", e),
+                })
+            })?;
+            Ok(payload.revoked)
+        } else {
+            Err(NetworkError::KeyPackage(KeyPackageError::ServerError {
+                message: format!("Failed to revoke keypackages: {}", response.status()),
+            })
+            .into())
+        }
+    }
+
+    /// Fetch the number of KeyPackages currently available for inviting `username`
+    ///
+    /// Unknown users report zero available KeyPackages.
+    pub async fn available_keypackages(&self, username: &str) -> Result<usize> {
+        #[derive(Deserialize)]
+        struct CountResponse {
+            available: usize,
+        }
+
+        let response = self
+            .send(self.client.get(format!(
+                "{}/users/{}/keypackages/count",
+                self.base_url, username
+            )))
+            .await?;
+
+        if response.status().is_success() {
+            let count: CountResponse = response.json().await?;
+            Ok(count.available)
+        } else {
+            Err(NetworkError::Server(format!(
+                "Failed to fetch available keypackage count: {}",
+                response.status()
+            ))
+            .into())
+        }
+    }
+
+    /// List the refs, statuses and expiry of every KeyPackage the server holds for `username`
+    ///
+    /// Intended for debugging divergence from the local pool; unknown users
+    /// have an empty list.
+    pub async fn list_keypackages(&self, username: &str) -> Result<Vec<KeyPackageSummary>> {
+        #[derive(Deserialize)]
+        struct ListEntry {
+            keypackage_ref: String,
+            status: String,
+            not_after: i64,
+        }
+
+        #[derive(Deserialize)]
+        struct ListResponse {
+            keypackages: Vec<ListEntry>,
+        }
+
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/keypackages/list/{}", self.base_url, username)),
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(NetworkError::Server(format!(
+                "Failed to list keypackages: {}",
+                response.status()
+            ))
+            .into());
+        }
+
+        let payload: ListResponse = response.json().await?;
+        payload
+            .keypackages
+            .into_iter()
+            .map(|entry| {
+                let keypackage_ref = general_purpose::STANDARD
+                    .decode(entry.keypackage_ref)
+                    .map_err(|e| {
+                        NetworkError::KeyPackage(KeyPackageError::InvalidResponse {
+                            message: format!("Invalid keypackage_ref in list response: {}", e),
+                        })
+                    })?;
+                Ok(KeyPackageSummary {
+                    keypackage_ref,
+                    status: entry.status,
+                    not_after: entry.not_after,
+                })
+            })
+            .collect()
+    }
+
     /// Fetch aggregate KeyPackage pool status for `username`
     pub async fn get_key_package_status(&self, username: &str) -> Result<KeyPackagePoolStatus> {
         let response = self
-            .client
-            .get(format!("{}/keypackages/status/{}", self.base_url, username))
-            .send()
+            .send(
+                self.client
+                    .get(format!("{}/keypackages/status/{}", self.base_url, username)),
+            )
             .await?;
 
         if response.status().is_success() {
@@ -0,0 +1,251 @@
+//! Compliance archive of a group's history
+//!
+//! The local history records the sender the server relayed each message
+//! as. An archive entry additionally carries the sender MLS authenticated:
+//! the username from the credential that signed the message and the
+//! fingerprint of that credential and key (see
+//! `crypto::credential_fingerprint`), kept when the message was sent or
+//! received. Entries without such a proof, such as notices or messages
+//! received before proofs were kept, are marked
+//! `SenderAuthentication::Unverified`. Entries whose signer differs from
+//! the recorded sender are marked `SenderAuthentication::Mismatch`.
+//!
+//! Entries are chained. Each `entry_hash` is SHA-256 over the previous
+//! entry's hash, or the group id for the first entry, and the entry's JSON.
+//! `GroupArchive::verify_chain` therefore detects entries edited, removed
+//! or reordered after the export. The chain is not signed: it shows that
+//! the archive is internally consistent, not who exported it.
+
+use crate::error::{MlsError, Result};
+use crate::models::{ContentType, HistoryEntry, MessageProof};
+use base64::{engine::general_purpose, Engine as _};
+use openmls::prelude::OpenMlsProvider;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How far the sender of an archive entry is backed by MLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SenderAuthentication {
+    /// Signed by the recorded sender's credential
+    Verified,
+    /// Signed by a credential of someone other than the recorded sender
+    Mismatch,
+    /// No proof of who sent it is kept (notices, older messages)
+    Unverified,
+}
+
+/// One history entry with what MLS authenticated about its sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Unix timestamp (seconds) when the entry was recorded
+    pub recorded_at: i64,
+    /// Id of the MLS message (see `crypto::message_id`), `None` for notices
+    pub message_id: Option<String>,
+    /// Sender as recorded in the history
+    pub sender: String,
+    pub content_type: ContentType,
+    pub content: String,
+    /// Username from the credential that signed the message
+    pub authenticated_sender: Option<String>,
+    /// Fingerprint of the signer's credential and key
+    pub sender_fingerprint: Option<String>,
+    /// Epoch the message was sent in
+    pub epoch: Option<u64>,
+    pub authentication: SenderAuthentication,
+    /// Lowercase hex SHA-256 chaining this entry to the previous one
+    pub entry_hash: String,
+}
+
+/// Read-only export of a group's history for archiving
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupArchive {
+    /// Base64 MLS group id
+    pub group_id: String,
+    pub group_name: String,
+    /// Member whose device produced the archive
+    pub exported_by: String,
+    /// Unix timestamp (seconds) of the export
+    pub exported_at: i64,
+    /// Entries, oldest first
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl GroupArchive {
+    /// Build the archive of `history`, matching entries to `proofs` by
+    /// message id
+    ///
+    /// # Errors
+    /// * Hashing or serialization failures
+    pub fn build(
+        provider: &impl OpenMlsProvider,
+        group_id: &[u8],
+        group_name: &str,
+        exported_by: &str,
+        history: Vec<HistoryEntry>,
+        proofs: &HashMap<String, MessageProof>,
+    ) -> Result<Self> {
+        let group_id = general_purpose::STANDARD.encode(group_id);
+        let mut previous = group_id.clone();
+        let mut entries = Vec::with_capacity(history.len());
+        for entry in history {
+            let proof = entry
+                .message_id
+                .as_ref()
+                .and_then(|message_id| proofs.get(message_id));
+            let authentication = match proof {
+                Some(proof) if proof.sender_identity == entry.sender => {
+                    SenderAuthentication::Verified
+                }
+                Some(_) => SenderAuthentication::Mismatch,
+                None => SenderAuthentication::Unverified,
+            };
+            let mut entry = ArchiveEntry {
+                recorded_at: entry.recorded_at,
+                message_id: entry.message_id,
+                sender: entry.sender,
+                content_type: entry.content_type,
+                content: entry.content,
+                authenticated_sender: proof.map(|proof| proof.sender_identity.clone()),
+                sender_fingerprint: proof.and_then(|proof| proof.sender_fingerprint.clone()),
+                epoch: proof.map(|proof| proof.epoch),
+                authentication,
+                entry_hash: String::new(),
+            };
+            entry.entry_hash = chain_hash(provider, &previous, &entry)?;
+            previous = entry.entry_hash.clone();
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            group_id,
+            group_name: group_name.to_string(),
+            exported_by: exported_by.to_string(),
+            exported_at: chrono::Utc::now().timestamp(),
+            entries,
+        })
+    }
+
+    /// Index of the first entry whose hash does not match the chain, `None`
+    /// if the archive is unchanged since the export
+    ///
+    /// # Errors
+    /// * Hashing or serialization failures
+    pub fn verify_chain(&self, provider: &impl OpenMlsProvider) -> Result<Option<usize>> {
+        let mut previous = self.group_id.clone();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if chain_hash(provider, &previous, entry)? != entry.entry_hash {
+                return Ok(Some(index));
+            }
+            previous = entry.entry_hash.clone();
+        }
+        Ok(None)
+    }
+
+    /// The archive as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// SHA-256 over `previous` and the JSON of `entry` without its hash
+fn chain_hash(
+    provider: &impl OpenMlsProvider,
+    previous: &str,
+    entry: &ArchiveEntry,
+) -> Result<String> {
+    use openmls_traits::crypto::OpenMlsCrypto;
+    use openmls_traits::types::HashType;
+
+    let unhashed = ArchiveEntry {
+        entry_hash: String::new(),
+        ..entry.clone()
+    };
+    let mut data = previous.as_bytes().to_vec();
+    data.extend_from_slice(&serde_json::to_vec(&unhashed)?);
+    let digest = provider
+        .crypto()
+        .hash(HashType::Sha2_256, &data)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to hash archive entry: {:?}", e)))?;
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+
+    fn history_entry(sender: &str, content: &str, message_id: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            sender: sender.to_string(),
+            content: content.to_string(),
+            content_type: ContentType::Text,
+            recorded_at: 1_700_000_000,
+            expires_at: None,
+            message_id: message_id.map(str::to_string),
+            pinned: false,
+            quote: None,
+        }
+    }
+
+    #[test]
+    fn test_entries_are_marked_and_chained() {
+        let provider = &OpenMlsRustCrypto::default();
+        let proofs = HashMap::from([
+            (
+                "aa".to_string(),
+                MessageProof {
+                    sender_identity: "alice".to_string(),
+                    sender_fingerprint: Some("f1".to_string()),
+                    epoch: 1,
+                },
+            ),
+            (
+                "bb".to_string(),
+                MessageProof {
+                    sender_identity: "mallory".to_string(),
+                    sender_fingerprint: Some("f2".to_string()),
+                    epoch: 2,
+                },
+            ),
+        ]);
+        let history = vec![
+            history_entry("alice", "hi", Some("aa")),
+            history_entry("bob", "relayed as bob", Some("bb")),
+            history_entry("bob", "bob joined", None),
+        ];
+        let archive =
+            GroupArchive::build(provider, b"group", "general", "alice", history, &proofs).unwrap();
+
+        let marks: Vec<_> = archive.entries.iter().map(|e| e.authentication).collect();
+        assert_eq!(
+            marks,
+            [
+                SenderAuthentication::Verified,
+                SenderAuthentication::Mismatch,
+                SenderAuthentication::Unverified
+            ]
+        );
+        assert_eq!(
+            archive.entries[1].authenticated_sender.as_deref(),
+            Some("mallory")
+        );
+        assert_eq!(archive.entries[2].sender_fingerprint, None);
+        assert_eq!(archive.verify_chain(provider).unwrap(), None);
+
+        // Survives a JSON round trip
+        let parsed: GroupArchive = serde_json::from_str(&archive.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, archive);
+
+        // Editing, dropping or reordering entries breaks the chain
+        let mut edited = archive.clone();
+        edited.entries[1].content = "something else".to_string();
+        assert_eq!(edited.verify_chain(provider).unwrap(), Some(1));
+        let mut dropped = archive.clone();
+        dropped.entries.remove(0);
+        assert_eq!(dropped.verify_chain(provider).unwrap(), Some(0));
+        let mut reordered = archive;
+        reordered.entries.swap(1, 2);
+        assert_eq!(reordered.verify_chain(provider).unwrap(), Some(1));
+    }
+}
@@ -1,22 +1,685 @@
 //! CLI interface for the MLS client
 //!
-//! Provides command parsing and async stdin reading for concurrent I/O
-//! in the main message loop.
+//! Provides the command-line argument definitions (one subcommand per
+//! capability), their execution, and the interactive `run` loop with command
+//! parsing and async stdin reading for concurrent I/O.
 
+use crate::api::{BackupInfo, KeyPackageSummary, ServerApi, ServerStatus};
 use crate::client::MlsClient;
-use crate::error::Result;
-use crate::models::Command;
+use crate::error::{ClientError, Result};
+use crate::message_processing::{format_timestamp, render_quote, DisplayConfig, TimestampStyle};
+use crate::models::{
+    CancelOutcome, Command, GroupListing, GroupSort, HistoryEntry, IdentityInfo, IntegrityReport,
+    MemberActivity, PendingOpId, SearchHit, SelfTestReport, StorageStats,
+};
+use crate::payload::TextFormat;
+use crate::websocket::{ConnectionState, ReconnectSchedule};
 use base64::{engine::general_purpose, Engine as _};
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
 use std::io::Write;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::{sleep_until, Instant};
 
+/// How long Commits that arrived ahead of their epoch wait for the missing
+/// one before it is fetched from the server; checked every
+/// `COMMIT_GAP_CHECK_INTERVAL`
+const COMMIT_GAP_TIMEOUT: Duration = Duration::from_secs(5);
+const COMMIT_GAP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most matches `/search` shows, newest first
+const SEARCH_RESULT_LIMIT: usize = 50;
+
+/// Command-line arguments for the `mls-client` binary
+///
+/// Either a subcommand, or the legacy form `mls-client <GROUP_NAME> <USERNAME>`
+/// which is equivalent to `mls-client run <GROUP_NAME> <USERNAME>`.
+#[derive(Parser, Debug)]
+#[command(name = "mls-client")]
+#[command(about = "MLS Chat Client - Secure group messaging", long_about = None)]
+pub struct Args {
+    /// Server URL (default: http://localhost:4000)
+    #[arg(long, default_value = "http://localhost:4000", global = true)]
+    pub server: String,
+
+    /// Config directory for state database (default: ~/.mlschat)
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    /// Enable verbose logging (DEBUG level)
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Show decrypted message text in debug logs (debug builds only)
+    #[arg(long, global = true)]
+    pub unsafe_debug_plaintext: bool,
+
+    /// Timestamps in front of displayed messages
+    #[arg(long, value_enum, default_value_t = TimestampStyle::Off, global = true)]
+    pub timestamps: TimestampStyle,
+
+    /// Omit the `#group` prefix from displayed messages
+    #[arg(long, global = true)]
+    pub no_group_prefix: bool,
+
+    /// Color timestamps, group names and senders
+    #[arg(long, global = true)]
+    pub color: bool,
+
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Group name to join or create (legacy form of `run`)
+    pub group_name: Option<String>,
+
+    /// Username for this client (legacy form of `run`)
+    pub username: Option<String>,
+}
+
+/// Subcommands of the `mls-client` binary
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    /// Register the user with the server and publish KeyPackages
+    Register {
+        /// Username for this client
+        username: String,
+    },
+    /// Create a group (or load it if it already exists)
+    CreateGroup {
+        /// Group name to create
+        group_name: String,
+        /// Username for this client
+        username: String,
+    },
+    /// Wait for an invitation (Welcome) and join the group it refers to
+    Join {
+        /// Username for this client
+        username: String,
+        /// Seconds to wait for the Welcome
+        #[arg(long, default_value = "60")]
+        timeout: u64,
+    },
+    /// Send one message to a group
+    Send {
+        /// Group name to send to
+        group_name: String,
+        /// Username for this client
+        username: String,
+        /// Message text
+        message: String,
+    },
+    /// Invite a user to a group
+    Invite {
+        /// Group name to invite into
+        group_name: String,
+        /// Username for this client
+        username: String,
+        /// Username to invite
+        invitee: String,
+        /// Also store the Welcome on the server in case the invitee is offline
+        #[arg(long)]
+        store_welcome: bool,
+    },
+    /// List the members of a group
+    List {
+        /// Group name to list
+        group_name: String,
+        /// Username for this client
+        username: String,
+        /// Also show the members the server has recorded (unverified)
+        #[arg(long)]
+        server_view: bool,
+    },
+    /// Print a group's history as a JSON archive with the authenticated
+    /// sender of every message
+    Archive {
+        /// Group name to archive
+        group_name: String,
+        /// Username for this client
+        username: String,
+    },
+    /// Show how much local storage this user's chat data uses
+    Stats {
+        /// Username whose local storage is summarized
+        username: String,
+    },
+    /// Compare the server's KeyPackages for this user with the local pool
+    Keypackages {
+        /// Username whose KeyPackages are listed
+        username: String,
+        /// First replace the whole pool: revoke every unspent KeyPackage and upload a fresh batch
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Check that this user's stored groups load, optionally pruning dangling mappings
+    CheckStorage {
+        /// Username whose local storage is checked
+        username: String,
+        /// Remove mappings to groups with no stored state (never deletes group state)
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Check identity, server, WebSocket, KeyPackages and local storage without changing anything
+    Doctor {
+        /// Username whose setup is checked
+        username: String,
+    },
+    /// List this user's groups with their unread message counts and last activity
+    Groups {
+        /// Username whose groups are listed
+        username: String,
+        /// Order of the list
+        #[arg(long, value_enum, default_value_t = GroupSort::Name)]
+        sort: GroupSort,
+    },
+    /// Delete this user's keys and group state from this device
+    Wipe {
+        /// Username whose local state is removed
+        username: String,
+        /// Required: confirms that the local keys should be destroyed
+        #[arg(long)]
+        confirm: bool,
+        /// Also delete the account, its KeyPackages and backups on the server
+        #[arg(long)]
+        deregister: bool,
+    },
+    /// List the backups the server keeps for this user
+    Backups {
+        /// Username whose backups are listed
+        username: String,
+        #[command(subcommand)]
+        action: Option<BackupAction>,
+    },
+    /// Join or create a group and start the interactive loop
+    Run {
+        /// Group name to join or create
+        group_name: String,
+        /// Username for this client
+        username: String,
+    },
+}
+
+/// Actions on one stored backup, under `backups <USERNAME>`
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum BackupAction {
+    /// Replace the local MLS state with a stored backup version
+    Restore {
+        /// Backup version to restore, as listed by `backups`
+        #[arg(long)]
+        version: i64,
+        /// Required: confirms that the current local state should be overwritten
+        #[arg(long)]
+        confirm: bool,
+    },
+}
+
+impl Args {
+    /// Resolve the command to execute, mapping the legacy positional form to `run`
+    ///
+    /// # Errors
+    /// * Neither a subcommand nor both legacy positional arguments were given
+    pub fn into_command(self) -> Result<CliCommand> {
+        match (self.command, self.group_name, self.username) {
+            (Some(command), None, None) => Ok(command),
+            (None, Some(group_name), Some(username)) => Ok(CliCommand::Run {
+                group_name,
+                username,
+            }),
+            _ => Err(ClientError::InvalidCommand(
+                "expected a subcommand or <GROUP_NAME> <USERNAME>".to_string(),
+            )),
+        }
+    }
+
+    /// Display options from `--timestamps`, `--no-group-prefix` and `--color`
+    pub fn display_config(&self) -> DisplayConfig {
+        DisplayConfig {
+            timestamps: self.timestamps,
+            show_group: !self.no_group_prefix,
+            color: self.color,
+        }
+    }
+
+    /// Storage directory from `--config`, defaulting to `~/.mlschat`
+    ///
+    /// # Errors
+    /// * The home directory cannot be determined
+    pub fn storage_dir(&self) -> Result<PathBuf> {
+        if let Some(config_path) = &self.config {
+            return Ok(PathBuf::from(config_path));
+        }
+
+        use directories::BaseDirs;
+        let base_dirs = BaseDirs::new()
+            .ok_or_else(|| ClientError::Config("Failed to get home directory".to_string()))?;
+        Ok(base_dirs.home_dir().join(".mlschat"))
+    }
+}
+
+impl CliCommand {
+    /// This command with message text passed through `plaintext_log::redact`,
+    /// for logging
+    pub fn redacted(&self) -> CliCommand {
+        match self {
+            CliCommand::Send {
+                group_name,
+                username,
+                message,
+            } => CliCommand::Send {
+                group_name: group_name.clone(),
+                username: username.clone(),
+                message: crate::plaintext_log::redact(message).to_string(),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+/// Execute a subcommand against `server_url` using state in `storage_dir`
+///
+/// One-shot subcommands initialize the client (loading or creating the
+/// identity), perform their action, and flush the WebSocket before returning.
+/// `Run` enters the interactive loop.
+///
+/// # Errors
+/// * Initialization, network, and MLS errors from the underlying operation
+/// * `Join` timing out before a Welcome arrives
+/// * `Wipe` or `backups restore` without `--confirm`
+/// * `backups restore` of a version the server does not have
+pub async fn execute_command(
+    command: CliCommand,
+    server_url: &str,
+    storage_dir: &Path,
+) -> Result<()> {
+    execute_command_with_display(command, server_url, storage_dir, DisplayConfig::default()).await
+}
+
+/// Like `execute_command`, printing the `Run` loop's lines with `display`
+///
+/// # Errors
+/// * As for `execute_command`
+pub async fn execute_command_with_display(
+    command: CliCommand,
+    server_url: &str,
+    storage_dir: &Path,
+    display: DisplayConfig,
+) -> Result<()> {
+    match command {
+        CliCommand::Register { username } => {
+            let mut client =
+                MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            client.initialize().await?;
+            println!("Registered {}", username);
+            Ok(())
+        }
+        CliCommand::CreateGroup {
+            group_name,
+            username,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            client.shutdown().await;
+            println!("{}", format_control(&group_name, "group ready"));
+            Ok(())
+        }
+        CliCommand::Join { username, timeout } => {
+            let mut client =
+                MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            client.initialize().await?;
+            client.get_connection_mut().connect_websocket().await?;
+
+            // A Welcome stored while we were offline needs no waiting
+            let stored = client
+                .get_connection_mut()
+                .fetch_pending_welcomes()
+                .await?
+                .into_iter()
+                .next();
+
+            let joined = tokio::time::timeout(Duration::from_secs(timeout), async {
+                if let Some(group_id) = stored {
+                    return Ok::<_, ClientError>(group_id);
+                }
+                loop {
+                    let envelope = client
+                        .get_connection_mut()
+                        .next_envelope()
+                        .await?
+                        .ok_or_else(|| {
+                            ClientError::Config("WebSocket connection closed".to_string())
+                        })?;
+                    if let Some(group_id) = client
+                        .get_connection_mut()
+                        .process_incoming_envelope(envelope)
+                        .await?
+                    {
+                        return Ok::<_, ClientError>(group_id);
+                    }
+                }
+            })
+            .await
+            .map_err(|_| {
+                ClientError::Config(format!("No invitation received within {}s", timeout))
+            })??;
+
+            client.set_selected_group_id(joined);
+            let group_name = client.get_current_group_name()?;
+            client.shutdown().await;
+            println!("Joined group: {}", group_name);
+            Ok(())
+        }
+        CliCommand::Send {
+            group_name,
+            username,
+            message,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            client.send_message(&message).await?;
+            client.shutdown().await;
+            Ok(())
+        }
+        CliCommand::Invite {
+            group_name,
+            username,
+            invitee,
+            store_welcome,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            client.set_store_welcomes(store_welcome);
+            let result = client.invite_user(&invitee).await;
+            client.shutdown().await;
+            result?;
+            println!(
+                "{}",
+                format_control(&group_name, &format!("invited {}", invitee))
+            );
+            Ok(())
+        }
+        CliCommand::List {
+            group_name,
+            username,
+            server_view,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            if !server_view {
+                let members = client.list_members();
+                client.shutdown().await;
+                println!(
+                    "{}",
+                    format_control(&group_name, &format!("members: {}", members.join(", ")))
+                );
+                return Ok(());
+            }
+            let comparison = client.compare_rosters().await;
+            client.shutdown().await;
+            let comparison = comparison?;
+            println!(
+                "{}",
+                format_control(
+                    &group_name,
+                    &format!("members: {}", comparison.verified.join(", "))
+                )
+            );
+            println!(
+                "{}",
+                format_control(
+                    &group_name,
+                    &format!(
+                        "server view (unverified): {}",
+                        comparison.server_view.join(", ")
+                    )
+                )
+            );
+            if !comparison.only_on_server.is_empty() {
+                println!(
+                    "{}",
+                    format_control(
+                        &group_name,
+                        &format!(
+                            "listed by the server but not in the group: {}",
+                            comparison.only_on_server.join(", ")
+                        )
+                    )
+                );
+            }
+            if !comparison.only_in_roster.is_empty() {
+                println!(
+                    "{}",
+                    format_control(
+                        &group_name,
+                        &format!(
+                            "in the group but not listed by the server: {}",
+                            comparison.only_in_roster.join(", ")
+                        )
+                    )
+                );
+            }
+            Ok(())
+        }
+        CliCommand::Archive {
+            group_name,
+            username,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            let archive = client.export_archive();
+            client.shutdown().await;
+            println!("{}", archive?.to_json()?);
+            Ok(())
+        }
+        CliCommand::Stats { username } => {
+            if !storage_dir.join(format!("mls-{}.db", username)).exists() {
+                return Err(ClientError::InvalidCommand(format!(
+                    "no local data for '{}' in {}",
+                    username,
+                    storage_dir.display()
+                )));
+            }
+            let client = MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            for line in format_storage_stats(&client.storage_stats()?) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::Keypackages { username, reset } => {
+            let mut client =
+                MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            if reset {
+                client.initialize().await?;
+                let uploaded = client.reset_key_package_pool().await?;
+                println!("Reset the KeyPackage pool: {} uploaded", uploaded);
+            }
+            let server = client.get_api().list_keypackages(&username).await?;
+            let local_refs = client.get_metadata_store().get_all_pool_refs()?;
+            for line in
+                format_keypackage_report(&client.keypackage_pool_stats()?, &server, &local_refs)
+            {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::CheckStorage { username, repair } => {
+            if !storage_dir.join(format!("mls-{}.db", username)).exists() {
+                return Err(ClientError::InvalidCommand(format!(
+                    "no local data for '{}' in {}",
+                    username,
+                    storage_dir.display()
+                )));
+            }
+            let client = MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            let report = if repair {
+                client.repair_storage()?
+            } else {
+                client.check_storage_integrity()?
+            };
+            for line in format_integrity_report(&report, repair) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::Doctor { username } => {
+            if !storage_dir.join(format!("mls-{}.db", username)).exists() {
+                return Err(ClientError::InvalidCommand(format!(
+                    "no local data for '{}' in {}",
+                    username,
+                    storage_dir.display()
+                )));
+            }
+            let client = MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            for line in format_self_test_report(&client.self_test().await) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::Groups { username, sort } => {
+            if !storage_dir.join(format!("mls-{}.db", username)).exists() {
+                return Err(ClientError::InvalidCommand(format!(
+                    "no local data for '{}' in {}",
+                    username,
+                    storage_dir.display()
+                )));
+            }
+            let client = MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            let now = chrono::Utc::now().timestamp();
+            for line in format_group_list(&client.list_groups(sort)?, now) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::Wipe {
+            username,
+            confirm,
+            deregister,
+        } => {
+            if !confirm {
+                return Err(ClientError::InvalidCommand(format!(
+                    "wipe permanently deletes the local keys of '{}'; re-run with --confirm",
+                    username
+                )));
+            }
+            let client = MlsClient::new_with_storage_path(server_url, &username, "", storage_dir)?;
+            if deregister {
+                // Before the wipe, which destroys the key that signs the request
+                if client.deregister().await? {
+                    println!("Deleted the account of {} on the server", username);
+                }
+            }
+            client.wipe().await?;
+            println!("Wiped local state for {}", username);
+            Ok(())
+        }
+        CliCommand::Backups {
+            username,
+            action: None,
+        } => {
+            let backups = ServerApi::new(server_url).list_backups(&username).await?;
+            for line in format_backup_list(&backups) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        CliCommand::Backups {
+            username,
+            action: Some(BackupAction::Restore { version, confirm }),
+        } => {
+            if !confirm {
+                return Err(ClientError::InvalidCommand(format!(
+                    "restoring backup version {} overwrites the local state of '{}'; re-run with --confirm",
+                    version, username
+                )));
+            }
+            MlsClient::restore_backup(server_url, &username, storage_dir, version).await?;
+            println!("Restored backup version {} for {}", version, username);
+            Ok(())
+        }
+        CliCommand::Run {
+            group_name,
+            username,
+        } => {
+            let mut client =
+                connect_client(server_url, &username, &group_name, storage_dir).await?;
+            client.set_display_config(display);
+            // Note: group_name is used to create the initial group if it doesn't exist
+            // After Welcome messages are processed, the client may switch to a different group
+            run_client_loop(&mut client).await
+        }
+    }
+}
+
+/// Create and initialize a client, then connect it to `group_name` (creating the group if needed)
+async fn connect_client(
+    server_url: &str,
+    username: &str,
+    group_name: &str,
+    storage_dir: &Path,
+) -> Result<MlsClient> {
+    let mut client =
+        MlsClient::new_with_storage_path(server_url, username, group_name, storage_dir)?;
+    client.initialize().await?;
+    client.connect_to_group(group_name).await?;
+    Ok(client)
+}
+
+/// Tracks the WebSocket connection state for the interactive loop
+///
+/// Turns state transitions into user-facing notices and decides whether
+/// commands that need the server (sending, inviting) may run.
+#[derive(Debug)]
+pub struct ConnectionMonitor {
+    state: ConnectionState,
+}
+
+impl ConnectionMonitor {
+    /// Start from the current connection state
+    pub fn new(state: ConnectionState) -> Self {
+        Self { state }
+    }
+
+    /// Current connection state
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Whether messages can be sent right now
+    pub fn can_send(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    /// Record a state change, returning the notice to show the user (if any)
+    ///
+    /// Repeated reports of the same state produce no notice.
+    pub fn update(&mut self, state: ConnectionState) -> Option<&'static str> {
+        let previous = std::mem::replace(&mut self.state, state);
+        if previous == state {
+            return None;
+        }
+
+        match state {
+            ConnectionState::Connecting => Some("connecting..."),
+            ConnectionState::Connected if previous == ConnectionState::Connecting => None,
+            ConnectionState::Connected => Some("reconnected"),
+            ConnectionState::Reconnecting => Some("reconnecting..."),
+            ConnectionState::Disconnected => {
+                Some("disconnected from server; messages will be queued until reconnected")
+            }
+        }
+    }
+}
+
 /// Run the main client control loop
 ///
 /// Implements the concurrent I/O event loop:
 /// - Reads user commands from stdin (parse and execute)
 /// - Processes incoming messages from WebSocket
+/// - Reports connection state changes, queues plain messages written while
+///   offline (sent once reconnected), refuses other sends, and reconnects
+///   with backoff
+/// - Reports when the server is busy and requests are being retried
 /// - Delegates MLS operations to client (which delegates to connection/membership)
 /// - Displays messages per approved architecture (membership returns data, cli displays)
 ///
@@ -26,54 +689,92 @@ use tokio::time::{sleep_until, Instant};
 /// * Command execution errors
 pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
     let group_name = client.get_current_group_name()?;
+    let display = client.display_config();
 
     println!("Connected to group: {}", group_name);
-    println!("Commands: /invite <username>, /list, /quit");
+    println!(
+        "Commands: /invite <username>, /remove <username>, /list, /members [active], /md <message>, /quote <message id> <message>, /pins, /more, /pending, /cancel <id>, /token [seconds], /redeem <token>, /admit, /whoami, /quit"
+    );
+    for line in format_history_entries(client.loaded_history()) {
+        println!("{}", line);
+    }
     println!("Type messages to send to the group");
 
     // Initialize async stdin reader
     let stdin = tokio::io::stdin();
     let mut stdin_reader = BufReader::new(stdin);
 
-    // Helper function to calculate next refresh deadline
-    let calculate_next_refresh = |client: &MlsClient| -> Instant {
-        let now_system = SystemTime::now();
-        let refresh_period = client.get_refresh_period();
+    // Connection state changes are pushed by the WebSocket tasks
+    let mut state_rx = client.get_connection().watch_connection_state();
+    let mut monitor = ConnectionMonitor::new(*state_rx.borrow_and_update());
+    let mut reconnect = ReconnectSchedule::new(client.get_connection().reconnect_backoff());
 
-        match client.get_last_refresh_time() {
-            None => {
-                // No refresh yet, trigger immediately
-                Instant::now()
-            }
-            Some(last_refresh) => {
-                // Calculate time until next refresh
-                match now_system.duration_since(last_refresh) {
-                    Ok(elapsed) => {
-                        if elapsed >= refresh_period {
-                            // Overdue, trigger immediately
-                            Instant::now()
-                        } else {
-                            // Schedule for remaining time
-                            let remaining = refresh_period - elapsed;
-                            Instant::now() + remaining
-                        }
-                    }
-                    Err(_) => {
-                        // Clock went backwards, trigger immediately
-                        log::warn!("System clock went backwards while calculating refresh time");
-                        Instant::now()
-                    }
-                }
+    // Requests waiting out a server Retry-After may be awaited inside this
+    // loop, so busy notices are printed from their own task
+    let mut status_rx = client.get_api().watch_status();
+    let status_group_name = group_name.clone();
+    tokio::spawn(async move {
+        let mut was_busy = false;
+        while status_rx.changed().await.is_ok() {
+            let status = *status_rx.borrow_and_update();
+            let busy = matches!(status, ServerStatus::Busy { .. });
+            if busy {
+                println!(
+                    "{}",
+                    display_control(&display, &status_group_name, &status.to_string())
+                );
+            } else if was_busy {
+                println!(
+                    "{}",
+                    display_control(&display, &status_group_name, "server available again")
+                );
             }
+            was_busy = busy;
         }
-    };
+    });
+
+    let mut commit_gap_check = tokio::time::interval(COMMIT_GAP_CHECK_INTERVAL);
 
     // Main concurrent I/O loop
     loop {
-        // Calculate next refresh deadline
-        let next_refresh = calculate_next_refresh(client);
+        // Calculate next refresh deadline (backs off after failed refreshes)
+        let next_refresh = Instant::now() + client.next_refresh_delay();
 
         tokio::select! {
+            // === Handle connection state changes ===
+            Ok(()) = state_rx.changed() => {
+                let state = *state_rx.borrow_and_update();
+                if let Some(notice) = monitor.update(state) {
+                    println!("{}", display_control(&display, &group_name, notice));
+                }
+                reconnect.on_state(state, Instant::now());
+                if state == ConnectionState::Connected {
+                    match client.flush_offline_queue().await {
+                        Ok((sent, undeliverable)) => {
+                            if sent > 0 {
+                                log::info!("Sent {} queued message(s)", sent);
+                            }
+                            for message in undeliverable {
+                                eprintln!("Not sent (no longer a member of that group): {}", message.text);
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("Failed to send queued messages: {}", e);
+                            eprintln!("Error: Failed to send queued messages: {}", e);
+                        }
+                    }
+                }
+            }
+
+            // === Reconnect after the connection was lost ===
+            _ = sleep_until(reconnect.next_attempt().unwrap_or_else(Instant::now)), if reconnect.next_attempt().is_some() => {
+                reconnect.attempt_started();
+                if let Err(e) = client.get_connection_mut().reconnect_websocket().await {
+                    log::warn!("Reconnection failed: {}", e);
+                    reconnect.attempt_failed(Instant::now());
+                }
+            }
+
             // === Handle user input ===
             user_input = read_line_async(&mut stdin_reader) => {
                 match user_input {
@@ -82,6 +783,23 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                         match parse_command(&input) {
                             Ok(command) => {
                                 match command {
+                                    Command::Message(text) if !monitor.can_send() => {
+                                        match client.queue_message(&text) {
+                                            Ok(dropped) => {
+                                                println!("{}", display_control(&display, &group_name, "message queued until reconnected"));
+                                                for message in dropped {
+                                                    eprintln!("Queue full, dropped queued message: {}", message.text);
+                                                }
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to queue message: {}", e);
+                                                eprintln!("Error: message not sent: {}", e);
+                                            }
+                                        }
+                                    }
+                                    Command::Invite(_) | Command::Remove(_) | Command::SetMessageTtl(_) | Command::MarkRead | Command::Markdown(_) | Command::Announce(_) | Command::Pin(_) | Command::Unpin(_) | Command::Quote { .. } | Command::JoinToken(_) | Command::Redeem(_) | Command::Admit if !monitor.can_send() => {
+                                        eprintln!("Error: not connected ({}); try again once reconnected", monitor.state());
+                                    }
                                     Command::Invite(invitee) => {
                                         match client.invite_user(&invitee).await {
                                             Ok(()) => {
@@ -90,20 +808,78 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                                             Err(e) => {
                                                 log::error!("Failed to invite {}: {}", invitee, e);
                                                 eprintln!("Error: Failed to invite {}: {}", invitee, e);
+                                                if let ClientError::Invite(ref invite_err) = e {
+                                                    if invite_err.group_state_advanced {
+                                                        eprintln!(
+                                                            "Warning: the group was updated locally but {} did not complete; other members may be out of sync",
+                                                            invite_err.stage
+                                                        );
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    Command::Remove(username) => {
+                                        match client.remove_member(&username).await {
+                                            Ok(epoch) => {
+                                                println!("{}", display_control(
+                                                    &display,
+                                                    &group_name,
+                                                    &format!("{} can no longer read new messages (group is at epoch {})", username, epoch)
+                                                ));
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to remove {}: {}", username, e);
+                                                eprintln!("Error: Failed to remove {}: {}", username, e);
                                             }
                                         }
                                     }
                                     Command::List => {
                                         let members = client.list_members();
                                         if members.is_empty() {
-                                            println!("{}", format_control(&group_name, "no members yet"));
+                                            println!("{}", display_control(&display, &group_name, "no members yet"));
                                         } else {
-                                            println!("{}", format_control(
+                                            println!("{}", display_control(
+                                                &display,
                                                 &group_name,
                                                 &format!("members: {}", members.join(", "))
                                             ));
                                         }
                                     }
+                                    Command::Members { by_activity } => {
+                                        match client.member_activity() {
+                                            Ok(members) => {
+                                                let now = chrono::Utc::now().timestamp();
+                                                for line in format_member_activity(members, by_activity, now) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to list members: {}", e),
+                                        }
+                                    }
+                                    Command::SetMessageTtl(ttl) => {
+                                        if let Err(e) = client.set_message_ttl(ttl).await {
+                                            log::error!("Failed to set message TTL: {}", e);
+                                            eprintln!("Error: Failed to set message TTL: {}", e);
+                                        }
+                                    }
+                                    Command::MarkRead => {
+                                        if let Err(e) = client.mark_all_read().await {
+                                            log::error!("Failed to mark group as read: {}", e);
+                                            eprintln!("Error: Failed to mark group as read: {}", e);
+                                        }
+                                    }
+                                    Command::Groups(order) => {
+                                        match client.list_groups(order) {
+                                            Ok(groups) => {
+                                                let now = chrono::Utc::now().timestamp();
+                                                for line in format_group_list(&groups, now) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to list groups: {}", e),
+                                        }
+                                    }
                                     Command::Message(text) => {
                                         match client.send_message(&text).await {
                                             Ok(()) => {
@@ -115,6 +891,150 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                                             }
                                         }
                                     }
+                                    Command::Markdown(text) => {
+                                        if let Err(e) = client.send_formatted_message(&text, TextFormat::Markdown).await {
+                                            log::error!("Failed to send message: {}", e);
+                                            eprintln!("Error: Failed to send message: {}", e);
+                                        }
+                                    }
+                                    Command::Quote { message_id, text } => {
+                                        if let Err(e) = client.send_reply(&message_id, &text).await {
+                                            log::error!("Failed to send reply: {}", e);
+                                            eprintln!("Error: Failed to send reply: {}", e);
+                                        }
+                                    }
+                                    Command::Announce(text) => {
+                                        if let Err(e) = client.send_announcement(&text).await {
+                                            log::error!("Failed to post announcement: {}", e);
+                                            eprintln!("Error: Failed to post announcement: {}", e);
+                                        }
+                                    }
+                                    Command::Pin(message_id) => {
+                                        if let Err(e) = client.pin_message(&message_id).await {
+                                            log::error!("Failed to pin {}: {}", message_id, e);
+                                            eprintln!("Error: Failed to pin {}: {}", message_id, e);
+                                        }
+                                    }
+                                    Command::Unpin(message_id) => {
+                                        if let Err(e) = client.unpin_message(&message_id).await {
+                                            log::error!("Failed to unpin {}: {}", message_id, e);
+                                            eprintln!("Error: Failed to unpin {}: {}", message_id, e);
+                                        }
+                                    }
+                                    Command::Pins => {
+                                        match client.get_pinned_messages() {
+                                            Ok(pins) if pins.is_empty() => {
+                                                println!("{}", display_control(&display, &group_name, "no pinned messages"));
+                                            }
+                                            Ok(pins) => {
+                                                for line in format_pinned_messages(&pins) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to list pinned messages: {}", e),
+                                        }
+                                    }
+                                    Command::Search(search) => {
+                                        match client.search_messages(&search, SEARCH_RESULT_LIMIT) {
+                                            Ok(hits) if hits.is_empty() => {
+                                                println!("{}", display_control(&display, &group_name, "no matching messages"));
+                                            }
+                                            Ok(hits) => {
+                                                for line in format_search_hits(&hits) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to search messages: {}", e),
+                                        }
+                                    }
+                                    Command::More => {
+                                        match client.load_older_history() {
+                                            Ok(page) if page.is_empty() => {
+                                                println!("{}", display_control(&display, &group_name, "no older messages"));
+                                            }
+                                            Ok(page) => {
+                                                for line in format_history_entries(&page) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to load older messages: {}", e),
+                                        }
+                                    }
+                                    Command::Pending => {
+                                        match client.pending_operations() {
+                                            Ok(operations) if operations.is_empty() => {
+                                                println!("{}", display_control(&display, &group_name, "nothing pending"));
+                                            }
+                                            Ok(operations) => {
+                                                for operation in operations {
+                                                    println!("[{}] {}", operation.id, operation.description);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to list pending operations: {}", e),
+                                        }
+                                    }
+                                    Command::Cancel(id) => match PendingOpId::parse(&id) {
+                                        None => eprintln!("Error: not an operation id: {} (see /pending)", id),
+                                        Some(op_id) => match client.cancel_operation(&op_id) {
+                                            Ok(outcome) => {
+                                                println!("{}", display_control(&display, &group_name, &format_cancel_outcome(&id, outcome)));
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to cancel {}: {}", id, e);
+                                                eprintln!("Error: Failed to cancel {}: {}", id, e);
+                                            }
+                                        },
+                                    },
+                                    Command::JoinToken(ttl) => {
+                                        match client.create_join_token(ttl).await {
+                                            Ok(token) => {
+                                                let expires = chrono::DateTime::from_timestamp(token.expires_at, 0)
+                                                    .map(|at| at.to_rfc3339())
+                                                    .unwrap_or_else(|| token.expires_at.to_string());
+                                                println!("{}", display_control(
+                                                    &display,
+                                                    &group_name,
+                                                    &format!("join token {} (single use, expires {}); redeem with /redeem, then /admit", token.token, expires)
+                                                ));
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to create join token: {}", e);
+                                                eprintln!("Error: Failed to create join token: {}", e);
+                                            }
+                                        }
+                                    }
+                                    Command::Redeem(token) => {
+                                        match client.redeem_join_token(&token).await {
+                                            Ok(_) => {
+                                                println!("{}", display_control(&display, &group_name, "join token accepted; you will join once an admin lets you in"));
+                                            }
+                                            Err(e) => eprintln!("Error: {}", e),
+                                        }
+                                    }
+                                    Command::Admit => {
+                                        match client.admit_join_requests().await {
+                                            Ok(admitted) if admitted.is_empty() => {
+                                                println!("{}", display_control(&display, &group_name, "no join requests"));
+                                            }
+                                            Ok(admitted) => {
+                                                println!("{}", display_control(&display, &group_name, &format!("admitted: {}", admitted.join(", "))));
+                                            }
+                                            Err(e) => {
+                                                log::error!("Failed to admit join requests: {}", e);
+                                                eprintln!("Error: Failed to admit join requests: {}", e);
+                                            }
+                                        }
+                                    }
+                                    Command::Whoami => {
+                                        match client.whoami() {
+                                            Ok(info) => {
+                                                for line in format_identity_info(&info) {
+                                                    println!("{}", line);
+                                                }
+                                            }
+                                            Err(e) => eprintln!("Error: Failed to inspect identity: {}", e),
+                                        }
+                                    }
                                     Command::Quit => {
                                         println!("Goodbye!");
                                         return Ok(());
@@ -141,8 +1061,8 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                 }
             }
 
-            // === Handle incoming messages ===
-            incoming = client.get_connection_mut().next_envelope() => {
+            // === Handle incoming messages (only while connected) ===
+            incoming = client.get_connection_mut().next_envelope(), if monitor.can_send() => {
                 match incoming {
                     Ok(Some(envelope)) => {
                         // Process the incoming envelope via connection's routing hub
@@ -169,9 +1089,12 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                         }
                     }
                     Ok(None) => {
+                        // The state watcher reports the loss and schedules the reconnection
                         log::info!("WebSocket connection closed by server");
-                        eprintln!("WebSocket connection closed");
-                        return Ok(());
+                        if let Some(notice) = monitor.update(ConnectionState::Disconnected) {
+                            println!("{}", display_control(&display, &group_name, notice));
+                        }
+                        reconnect.on_state(ConnectionState::Disconnected, Instant::now());
                     }
                     Err(e) => {
                         log::error!("WebSocket error: {}", e);
@@ -181,21 +1104,32 @@ pub async fn run_client_loop(client: &mut MlsClient) -> Result<()> {
                 }
             }
 
+            // === Sync groups whose next Commit never arrived ===
+            _ = commit_gap_check.tick() => {
+                match client.get_connection_mut().resolve_commit_gaps(COMMIT_GAP_TIMEOUT).await {
+                    Ok(0) => {}
+                    Ok(synced) => log::info!("Synced missing Commits in {} group(s)", synced),
+                    Err(e) => log::error!("Failed to sync missing Commits: {}", e),
+                }
+            }
+
             // === Handle periodic KeyPackage pool refresh ===
             _ = sleep_until(next_refresh) => {
                 log::debug!("KeyPackage pool refresh timer triggered");
 
-                match client.refresh_key_packages().await {
-                    Ok(()) => {
-                        // Update refresh time after successful refresh
-                        client.update_refresh_time();
+                match client.refresh_if_due().await {
+                    Ok(true) => {
                         log::info!("KeyPackage pool refreshed successfully");
                     }
+                    Ok(false) => {}
                     Err(e) => {
-                        // Log error but continue execution (refresh is background maintenance)
-                        log::error!("Failed to refresh KeyPackage pool: {}", e);
-                        // Update refresh time even on error to prevent tight retry loop
-                        client.update_refresh_time();
+                        // Log error but continue execution (refresh is background maintenance);
+                        // the next attempt is scheduled with backoff
+                        log::error!(
+                            "Failed to refresh KeyPackage pool ({} consecutive failures): {}",
+                            client.get_refresh_failures(),
+                            e
+                        );
                     }
                 }
             }
@@ -218,6 +1152,294 @@ pub fn format_control(group: &str, action: &str) -> String {
     format!("#{} {}", group, action)
 }
 
+/// Format a control message happening now with the client's display options
+fn display_control(display: &DisplayConfig, group: &str, action: &str) -> String {
+    let now = chrono::Utc::now().timestamp();
+    display.format_control(group, action, now, now)
+}
+
+/// Format local storage usage for display, one line per figure
+pub fn format_storage_stats(stats: &StorageStats) -> Vec<String> {
+    let mut lines = vec![
+        format!("groups: {}", stats.groups),
+        format!("messages: {}", stats.messages),
+        format!("disk usage: {} bytes", stats.total_bytes),
+    ];
+    for (group, count) in &stats.per_group_message_counts {
+        lines.push(format!("  #{}: {} messages", group, count));
+    }
+    lines
+}
+
+/// Format a storage integrity report, one line per problem
+pub fn format_integrity_report(report: &IntegrityReport, repaired: bool) -> Vec<String> {
+    if report.is_clean() {
+        return vec!["storage ok".to_string()];
+    }
+    let dangling = if repaired { "pruned" } else { "dangling" };
+    let mut lines = Vec::new();
+    for key in &report.dangling_mappings {
+        lines.push(format!("{} mapping: {}", dangling, key));
+    }
+    for key in &report.unloadable_mappings {
+        lines.push(format!("unloadable group (kept): {}", key));
+    }
+    let hex = |group_id: &[u8]| -> String {
+        group_id
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    };
+    for group_id in &report.unreferenced_groups {
+        lines.push(format!("unreferenced group (kept): {}", hex(group_id)));
+    }
+    for conflict in &report.name_conflicts {
+        let earlier: Vec<String> = conflict
+            .superseded
+            .iter()
+            .map(|id| hex(id.as_slice()))
+            .collect();
+        lines.push(format!(
+            "name conflict: {} maps to {}, earlier {}",
+            conflict.group_name_key,
+            hex(&conflict.current),
+            earlier.join(", ")
+        ));
+    }
+    lines
+}
+
+/// Format a self-test report, one line per check plus a hint for each failure
+pub fn format_self_test_report(report: &SelfTestReport) -> Vec<String> {
+    let mut lines = Vec::new();
+    for result in &report.results {
+        let status = if result.passed { "ok" } else { "FAIL" };
+        lines.push(format!(
+            "{:<4} {}: {}",
+            status,
+            result.check.name(),
+            result.detail
+        ));
+        if let Some(hint) = &result.hint {
+            lines.push(format!("     hint: {}", hint));
+        }
+    }
+    lines
+}
+
+/// Format local pool counts next to the server's KeyPackage list
+///
+/// Server entries the local pool does not track are flagged, since those are
+/// the ones that can no longer be used to join.
+pub fn format_keypackage_report(
+    local: &BTreeMap<String, usize>,
+    server: &[KeyPackageSummary],
+    local_refs: &[Vec<u8>],
+) -> Vec<String> {
+    let mut lines = vec!["local pool:".to_string()];
+    for (status, count) in local {
+        lines.push(format!("  {}: {}", status, count));
+    }
+
+    let mut server_counts = BTreeMap::new();
+    for summary in server {
+        *server_counts.entry(summary.status.as_str()).or_insert(0) += 1;
+    }
+    lines.push("server:".to_string());
+    for (status, count) in &server_counts {
+        lines.push(format!("  {}: {}", status, count));
+    }
+
+    for summary in server {
+        let hex: String = summary
+            .keypackage_ref
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut line = format!(
+            "  {} {} (not after {})",
+            hex, summary.status, summary.not_after
+        );
+        if !local_refs.contains(&summary.keypackage_ref) {
+            line.push_str(" [not in local pool]");
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Format groups with an unread badge and their last message, e.g.
+/// `#team (3 unread, last message 5m ago)`
+///
+/// Groups are printed in the order given.
+pub fn format_group_list(groups: &[GroupListing], now: i64) -> Vec<String> {
+    groups
+        .iter()
+        .map(|group| {
+            let mut details = Vec::new();
+            if group.unread > 0 {
+                details.push(format!("{} unread", group.unread));
+            }
+            if let Some(last) = group
+                .last_activity
+                .and_then(|at| format_timestamp(TimestampStyle::Relative, at, now))
+            {
+                details.push(format!("last message {}", last));
+            }
+            if details.is_empty() {
+                format!("#{}", group.name)
+            } else {
+                format!("#{} ({})", group.name, details.join(", "))
+            }
+        })
+        .collect()
+}
+
+/// Format backup versions, one per line, e.g.
+/// `version 3  2026-10-17T09:30:00+00:00  5120 bytes`
+///
+/// Versions are printed in the order given (the server lists newest first).
+pub fn format_backup_list(backups: &[BackupInfo]) -> Vec<String> {
+    if backups.is_empty() {
+        return vec!["no backups".to_string()];
+    }
+    backups
+        .iter()
+        .map(|backup| {
+            let size = backup
+                .size
+                .map_or("size unknown".to_string(), |size| format!("{} bytes", size));
+            format!("version {}  {}  {}", backup.version, backup.timestamp, size)
+        })
+        .collect()
+}
+
+/// Format members with when they joined and were last seen, e.g.
+/// `alice (member since 2026-10-17 09:30:00, last seen 5m ago)`
+///
+/// Members are listed by name, or most recently active first (never-seen
+/// members last) when `by_activity` is set.
+pub fn format_member_activity(
+    mut members: Vec<MemberActivity>,
+    by_activity: bool,
+    now: i64,
+) -> Vec<String> {
+    if by_activity {
+        members.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then_with(|| a.username.cmp(&b.username))
+        });
+    } else {
+        members.sort_by(|a, b| a.username.cmp(&b.username));
+    }
+
+    members
+        .iter()
+        .map(|member| {
+            let mut details = Vec::new();
+            if let Some(joined_at) = member.joined_at {
+                if let Some(since) = format_timestamp(TimestampStyle::Absolute, joined_at, now) {
+                    details.push(format!("member since {}", since));
+                }
+            }
+            details.push(
+                match member
+                    .last_seen
+                    .and_then(|seen| format_timestamp(TimestampStyle::Relative, seen, now))
+                {
+                    Some(seen) => format!("last seen {}", seen),
+                    None => "not seen yet".to_string(),
+                },
+            );
+            format!("{} ({})", member.username, details.join(", "))
+        })
+        .collect()
+}
+
+/// Format pinned messages for `/pins`, e.g. `[<message id>] alice: hi`
+///
+/// The full message id is shown so it can be passed to `/unpin` or
+/// `/quote`. A pinned reply shows its quote snapshot below it.
+pub fn format_pinned_messages(pins: &[HistoryEntry]) -> Vec<String> {
+    pins.iter()
+        .map(|entry| {
+            let line = format!(
+                "[{}] {}: {}",
+                entry.message_id.as_deref().unwrap_or("?"),
+                entry.sender,
+                entry.content
+            );
+            render_quote(&line, entry.quote.as_ref())
+        })
+        .collect()
+}
+
+/// Format `/search` results, e.g. `#general 2024-01-02 10:00 alice: hi`
+pub fn format_search_hits(hits: &[SearchHit]) -> Vec<String> {
+    hits.iter()
+        .map(|hit| {
+            let at = chrono::DateTime::from_timestamp(hit.entry.recorded_at, 0)
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| hit.entry.recorded_at.to_string());
+            format!(
+                "#{} {} {}: {}",
+                hit.group_name.as_deref().unwrap_or("?"),
+                at,
+                hit.entry.sender,
+                hit.entry.content
+            )
+        })
+        .collect()
+}
+
+/// Format loaded history for scrollback, e.g. `alice: hi`
+///
+/// Replies show their quote snapshot below them.
+pub fn format_history_entries(entries: &[HistoryEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let line = format!("{}: {}", entry.sender, entry.content);
+            render_quote(&line, entry.quote.as_ref())
+        })
+        .collect()
+}
+
+/// Notice shown after `/cancel <id>`
+pub fn format_cancel_outcome(id: &str, outcome: CancelOutcome) -> String {
+    match outcome {
+        CancelOutcome::Cancelled => format!("cancelled {}", id),
+        CancelOutcome::AlreadySent => {
+            format!("{} was already sent and can no longer be recalled", id)
+        }
+        CancelOutcome::NotFound => format!("nothing pending with id {}", id),
+    }
+}
+
+/// Format the local identity for `/whoami`, one field per line
+pub fn format_identity_info(info: &IdentityInfo) -> Vec<String> {
+    let credential = match info.credential_kind {
+        crate::crypto::CredentialKind::Basic => "basic",
+        crate::crypto::CredentialKind::X509 => "x509",
+    };
+    let registered = match info.server_user_id {
+        Some(id) => format!("yes (server id {})", id),
+        None => "no".to_string(),
+    };
+    vec![
+        format!("username: {}", info.username),
+        format!("credential: {}", credential),
+        format!("fingerprint: {}", info.credential_fingerprint),
+        format!(
+            "signature key: {}",
+            general_purpose::STANDARD.encode(&info.signature_public_key)
+        ),
+        format!("registered: {}", registered),
+    ]
+}
+
 /// Async stdin reader that yields one line at a time
 ///
 /// Uses tokio's async stdin to enable concurrent I/O with WebSocket messages.
@@ -286,9 +1508,276 @@ mod tests {
         assert_eq!(formatted, "#testgroup alice joined the group");
     }
 
+    #[test]
+    fn test_format_storage_stats() {
+        let stats = StorageStats {
+            groups: 1,
+            messages: 2,
+            total_bytes: 4096,
+            per_group_message_counts: [("general".to_string(), 2)].into_iter().collect(),
+        };
+        assert_eq!(
+            format_storage_stats(&stats),
+            vec![
+                "groups: 1",
+                "messages: 2",
+                "disk usage: 4096 bytes",
+                "  #general: 2 messages"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_group_list() {
+        let groups = [
+            GroupListing {
+                name: "team".to_string(),
+                last_activity: Some(700),
+                unread: 3,
+            },
+            GroupListing {
+                name: "quiet".to_string(),
+                last_activity: None,
+                unread: 0,
+            },
+        ];
+        assert_eq!(
+            format_group_list(&groups, 1000),
+            vec![
+                "#team (3 unread, last message 5m ago)".to_string(),
+                "#quiet".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_backup_list() {
+        assert_eq!(format_backup_list(&[]), vec!["no backups".to_string()]);
+        let backups = [
+            BackupInfo {
+                version: 3,
+                timestamp: "2026-10-17T09:30:00+00:00".to_string(),
+                size: Some(5120),
+            },
+            BackupInfo {
+                version: 2,
+                timestamp: "2026-10-16T18:00:00+00:00".to_string(),
+                size: None,
+            },
+        ];
+        assert_eq!(
+            format_backup_list(&backups),
+            vec![
+                "version 3  2026-10-17T09:30:00+00:00  5120 bytes".to_string(),
+                "version 2  2026-10-16T18:00:00+00:00  size unknown".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_pinned_messages() {
+        let agenda = HistoryEntry {
+            sender: "alice".to_string(),
+            content: "Agenda is in the doc".to_string(),
+            content_type: crate::models::ContentType::Text,
+            recorded_at: 0,
+            expires_at: None,
+            message_id: Some("0a1b2c".to_string()),
+            pinned: true,
+            quote: None,
+        };
+        let reply = HistoryEntry {
+            sender: "bob".to_string(),
+            content: "Which doc?".to_string(),
+            message_id: Some("3d4e5f".to_string()),
+            quote: Some(crate::models::Quote::new("alice", "Agenda is in the doc")),
+            ..agenda.clone()
+        };
+        assert_eq!(
+            format_pinned_messages(&[agenda, reply]),
+            vec![
+                "[0a1b2c] alice: Agenda is in the doc".to_string(),
+                "[3d4e5f] bob: Which doc?\n  > alice: Agenda is in the doc".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_search_hits() {
+        let hit = SearchHit {
+            group_id: b"group".to_vec(),
+            group_name: Some("general".to_string()),
+            entry: HistoryEntry {
+                sender: "alice".to_string(),
+                content: "lunch at noon?".to_string(),
+                content_type: crate::models::ContentType::Text,
+                recorded_at: 1_704_189_600,
+                expires_at: None,
+                message_id: None,
+                pinned: false,
+                quote: None,
+            },
+        };
+        let orphan = SearchHit {
+            group_name: None,
+            ..hit.clone()
+        };
+        assert_eq!(
+            format_search_hits(&[hit, orphan]),
+            vec![
+                "#general 2024-01-02 10:00 alice: lunch at noon?".to_string(),
+                "#? 2024-01-02 10:00 alice: lunch at noon?".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_member_activity() {
+        let now = 1_800_000_000;
+        let members = vec![
+            MemberActivity {
+                username: "alice".to_string(),
+                joined_at: Some(1_700_000_000),
+                last_seen: Some(now - 7200),
+            },
+            MemberActivity {
+                username: "bob".to_string(),
+                joined_at: None,
+                last_seen: None,
+            },
+            MemberActivity {
+                username: "carol".to_string(),
+                joined_at: Some(now - 600),
+                last_seen: Some(now - 300),
+            },
+        ];
+
+        assert_eq!(
+            format_member_activity(members.clone(), false, now),
+            vec![
+                "alice (member since 2023-11-14 22:13:20, last seen 2h ago)".to_string(),
+                "bob (not seen yet)".to_string(),
+                "carol (member since 2027-01-15 07:50:00, last seen 5m ago)".to_string(),
+            ]
+        );
+        let by_activity: Vec<String> = format_member_activity(members, true, now)
+            .into_iter()
+            .map(|line| line.split(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(by_activity, vec!["carol", "alice", "bob"]);
+    }
+
+    #[test]
+    fn test_format_keypackage_report() {
+        let local = BTreeMap::from([("available".to_string(), 1), ("spent".to_string(), 1)]);
+        let server = vec![
+            KeyPackageSummary {
+                keypackage_ref: vec![0x01, 0x02],
+                status: "available".to_string(),
+                not_after: 100,
+            },
+            KeyPackageSummary {
+                keypackage_ref: vec![0xff],
+                status: "reserved".to_string(),
+                not_after: 200,
+            },
+        ];
+        assert_eq!(
+            format_keypackage_report(&local, &server, &[vec![0x01, 0x02]]),
+            vec![
+                "local pool:",
+                "  available: 1",
+                "  spent: 1",
+                "server:",
+                "  available: 1",
+                "  reserved: 1",
+                "  0102 available (not after 100)",
+                "  ff reserved (not after 200) [not in local pool]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_self_test_report() {
+        use crate::models::{SelfTestCheck, SelfTestResult};
+
+        let report = SelfTestReport {
+            results: vec![
+                SelfTestResult::passed(SelfTestCheck::Identity, "loads"),
+                SelfTestResult::failed(SelfTestCheck::Server, "unreachable", "start the server"),
+            ],
+        };
+        assert_eq!(
+            format_self_test_report(&report),
+            vec![
+                "ok   identity: loads",
+                "FAIL server: unreachable",
+                "     hint: start the server",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_integrity_report() {
+        assert_eq!(
+            format_integrity_report(&IntegrityReport::default(), false),
+            vec!["storage ok".to_string()]
+        );
+        let report = IntegrityReport {
+            dangling_mappings: vec!["alice:ghost".to_string()],
+            unloadable_mappings: vec!["alice:partial".to_string()],
+            unreferenced_groups: vec![vec![0xab, 0x01]],
+            name_conflicts: vec![crate::models::GroupNameConflict {
+                group_name_key: "alice:team".to_string(),
+                current: vec![0x02],
+                superseded: vec![vec![0x01]],
+            }],
+        };
+        assert_eq!(
+            format_integrity_report(&report, true),
+            vec![
+                "pruned mapping: alice:ghost".to_string(),
+                "unloadable group (kept): alice:partial".to_string(),
+                "unreferenced group (kept): ab01".to_string(),
+                "name conflict: alice:team maps to 02, earlier 01".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn test_invalid_command() {
         let result = parse_command("/unknown");
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_connection_monitor_gates_sending_on_state_changes() {
+        // Drive state transitions through a watch channel, as the connection does
+        let (tx, mut rx) = tokio::sync::watch::channel(ConnectionState::Connecting);
+        let mut monitor = ConnectionMonitor::new(*rx.borrow_and_update());
+        assert!(!monitor.can_send());
+
+        let transitions = [
+            // (new state, expected notice prefix, can send)
+            (ConnectionState::Connected, None, true),
+            (ConnectionState::Disconnected, Some("disconnected"), false),
+            (ConnectionState::Reconnecting, Some("reconnecting"), false),
+            (ConnectionState::Connected, Some("reconnected"), true),
+        ];
+        for (state, notice, can_send) in transitions {
+            tx.send_replace(state);
+            rx.changed().await.unwrap();
+            let received = *rx.borrow_and_update();
+
+            let shown = monitor.update(received);
+            match notice {
+                Some(prefix) => assert!(shown.unwrap().starts_with(prefix), "{:?}", state),
+                None => assert_eq!(shown, None, "{:?}", state),
+            }
+            assert_eq!(monitor.can_send(), can_send, "{:?}", state);
+        }
+
+        // Repeated reports of the same state are not shown twice
+        assert_eq!(monitor.update(ConnectionState::Connected), None);
+    }
 }
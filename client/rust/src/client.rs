@@ -3,16 +3,40 @@
 //! Provides a high-level API for MLS operations by delegating to MlsConnection.
 //! MlsClient is a thin wrapper that manages the selected group for single-group CLI usage.
 
-use crate::api::ServerApi;
-use crate::error::{ClientError, Result};
+use crate::api::{JoinToken, ServerApi, ServerApiConfig};
+use crate::archive::GroupArchive;
+use crate::crypto::CredentialKind;
+use crate::error::{ClientError, MlsError, Result, StorageError};
+use crate::framing::Framing;
+use crate::metrics::MetricsSnapshot;
 use crate::mls::connection::MlsConnection;
 use crate::mls::keypackage_pool::KeyPackagePoolConfig;
-use crate::models::Identity;
+use crate::models::{
+    CancelOutcome, GroupListing, GroupSort, GroupSummary, HistoryEntry, Identity, IdentityInfo,
+    IntegrityReport, LostGroupPolicy, MessageSearch, PendingInvitation, PendingOp, PendingOpId,
+    RosterComparison, SearchHit, SelfTestReport, StorageStats, WelcomePolicy,
+};
+use crate::payload::TextFormat;
 use crate::provider::MlsProvider;
-use crate::storage::LocalStore;
+use crate::secret_store::{IdentityStorage, SecretStore};
+use crate::storage::{
+    LocalStore, OutboundQueueConfig, QueuedMessage, StagedWelcome, StorageTuning,
+};
+use crate::websocket::ReconnectBackoff;
+use crate::x509::TrustAnchors;
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+/// Delay before the first retry of a failed KeyPackage pool refresh.
+/// Doubles with each consecutive failure, capped at the refresh period.
+const REFRESH_RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// Number of recent history entries loaded when a group is selected
+const DEFAULT_INITIAL_HISTORY_LIMIT: usize = 50;
+
 /// Main MLS client
 ///
 /// Thin wrapper around MlsConnection that provides a high-level API for MLS operations.
@@ -52,6 +76,18 @@ pub struct MlsClient {
 
     /// Period between KeyPackage pool refreshes (default: 1 hour)
     refresh_period: Duration,
+
+    /// Number of consecutive failed refresh attempts (drives retry backoff)
+    refresh_failures: u32,
+
+    /// Number of history entries loaded eagerly when a group is selected
+    initial_history_limit: usize,
+
+    /// Loaded local history of the selected group, oldest first
+    history_window: Vec<HistoryEntry>,
+
+    /// Cursor of the history page before `history_window`, if any
+    older_history: Option<i64>,
 }
 
 impl MlsClient {
@@ -71,6 +107,29 @@ impl MlsClient {
         username: &str,
         group_name: &str,
         storage_dir: &Path,
+    ) -> Result<Self> {
+        Self::new_with_storage_tuning(
+            server_url,
+            username,
+            group_name,
+            storage_dir,
+            &StorageTuning::default(),
+        )
+    }
+
+    /// Create a new MLS client whose databases use the given SQLite settings
+    ///
+    /// See `StorageTuning` for the settings and their defaults.
+    ///
+    /// # Errors
+    /// * File system errors when creating storage directories
+    /// * Database initialization errors
+    pub fn new_with_storage_tuning(
+        server_url: &str,
+        username: &str,
+        group_name: &str,
+        storage_dir: &Path,
+        tuning: &StorageTuning,
     ) -> Result<Self> {
         log::info!(
             "Creating MlsClient for {} (group: {})",
@@ -79,13 +138,18 @@ impl MlsClient {
         );
 
         // Create MlsConnection with infrastructure
-        let connection = MlsConnection::new_with_storage_path(server_url, username, storage_dir)?;
+        let connection =
+            MlsConnection::new_with_storage_tuning(server_url, username, storage_dir, tuning)?;
 
         Ok(Self {
             connection,
             selected_group_id: None,
             last_refresh_time: None,
             refresh_period: Duration::from_secs(3600), // Default: 1 hour
+            refresh_failures: 0,
+            initial_history_limit: DEFAULT_INITIAL_HISTORY_LIMIT,
+            history_window: Vec::new(),
+            older_history: None,
         })
     }
 
@@ -113,27 +177,99 @@ impl MlsClient {
         self.connection.refresh_key_packages().await
     }
 
+    /// Replace the whole KeyPackage pool with freshly generated KeyPackages
+    ///
+    /// For debugging pool problems: see `MlsConnection::reset_key_package_pool`.
+    /// Returns the number of KeyPackages uploaded.
+    pub async fn reset_key_package_pool(&mut self) -> Result<usize> {
+        let uploaded = self.connection.reset_key_package_pool().await?;
+        self.update_refresh_time();
+        Ok(uploaded)
+    }
+
+    /// Rotate this user's credential and re-register under it
+    ///
+    /// See `MlsConnection::rotate_identity`. Returns the ids of groups whose
+    /// leaf still carries the old key.
+    pub async fn rotate_identity(&mut self) -> Result<Vec<Vec<u8>>> {
+        let not_rotated = self.connection.rotate_identity().await?;
+        self.update_refresh_time();
+        Ok(not_rotated)
+    }
+
     /// Check if the refresh period has elapsed since the last refresh
     ///
     /// Returns true if:
     /// - No refresh has occurred yet (last_refresh_time is None), OR
-    /// - The elapsed time since last refresh >= refresh_period
+    /// - The elapsed time since last refresh >= refresh_period (or the retry
+    ///   delay, after failed attempts)
     pub fn should_refresh(&self) -> bool {
-        match self.last_refresh_time {
-            None => true, // First refresh should happen immediately
-            Some(last_time) => {
-                match SystemTime::now().duration_since(last_time) {
-                    Ok(elapsed) => elapsed >= self.refresh_period,
-                    Err(_) => {
-                        // Clock went backwards, trigger refresh to be safe
-                        log::warn!("System clock went backwards, triggering refresh");
-                        true
-                    }
-                }
+        self.next_refresh_delay().is_zero()
+    }
+
+    /// Time remaining until the next scheduled refresh (zero when overdue)
+    ///
+    /// After a successful refresh the next one is due after `refresh_period`.
+    /// After consecutive failures the retry delay starts at 5 seconds and
+    /// doubles with each failure, capped at `refresh_period`.
+    pub fn next_refresh_delay(&self) -> Duration {
+        let Some(last_time) = self.last_refresh_time else {
+            return Duration::ZERO; // First refresh should happen immediately
+        };
+
+        let interval = if self.refresh_failures == 0 {
+            self.refresh_period
+        } else {
+            let backoff = REFRESH_RETRY_BASE_DELAY
+                .saturating_mul(2u32.saturating_pow(self.refresh_failures - 1));
+            backoff.min(self.refresh_period)
+        };
+
+        match SystemTime::now().duration_since(last_time) {
+            Ok(elapsed) => interval.saturating_sub(elapsed),
+            Err(_) => {
+                // Clock went backwards, trigger refresh to be safe
+                log::warn!("System clock went backwards, triggering refresh");
+                Duration::ZERO
             }
         }
     }
 
+    /// Run the scheduled KeyPackage pool refresh if it is due
+    ///
+    /// Called periodically by the client loop so the pool is replenished
+    /// without user action. Failures are counted to back off retries and the
+    /// count resets on the next success (e.g. when the server is reachable again).
+    ///
+    /// # Returns
+    /// `Ok(true)` if a refresh ran and succeeded, `Ok(false)` if none was due
+    ///
+    /// # Errors
+    /// * Errors from `refresh_key_packages()` (the attempt is still recorded)
+    pub async fn refresh_if_due(&mut self) -> Result<bool> {
+        if !self.should_refresh() {
+            return Ok(false);
+        }
+
+        let result = self.connection.refresh_key_packages().await;
+        self.update_refresh_time();
+        match result {
+            Ok(()) => {
+                self.refresh_failures = 0;
+                Ok(true)
+            }
+            Err(e) => {
+                self.refresh_failures = self.refresh_failures.saturating_add(1);
+                Err(e)
+            }
+        }
+    }
+
+    /// Number of consecutive failed scheduled refreshes (for testing/debugging)
+    pub fn get_refresh_failures(&self) -> u32 {
+        self.refresh_failures
+    }
+
     /// Update the last refresh time to now
     ///
     /// Should be called after successfully calling refresh_key_packages()
@@ -164,10 +300,187 @@ impl MlsClient {
         self.connection.set_keypackage_pool_config(config);
     }
 
+    /// Override the HTTP request and connect timeouts used for server calls
+    pub fn set_server_api_config(&mut self, config: ServerApiConfig) {
+        self.connection.set_server_api_config(config);
+    }
+
+    /// Enable or disable compression of large outgoing messages (enabled by default)
+    pub fn set_message_compression(&mut self, enabled: bool) {
+        self.connection.set_message_compression(enabled);
+    }
+
+    /// Set the maximum number of groups this user may create or join (default 100)
+    pub fn set_max_groups(&mut self, max_groups: usize) {
+        self.connection.set_max_groups(max_groups);
+    }
+
+    /// Join groups whose Welcome lacks group metadata under a placeholder name
+    ///
+    /// Off by default, so strict deployments keep refusing such Welcomes.
+    pub fn set_allow_missing_group_metadata(&mut self, allow: bool) {
+        self.connection.set_allow_missing_group_metadata(allow);
+    }
+
+    /// Set how messages and notices are printed (see `DisplayConfig`)
+    pub fn set_display_config(&mut self, display: crate::message_processing::DisplayConfig) {
+        self.connection.set_display_config(display);
+    }
+
+    /// Current display options
+    pub fn display_config(&self) -> crate::message_processing::DisplayConfig {
+        self.connection.display_config()
+    }
+
+    /// Publish public GroupInfos for external joiners (see
+    /// `MlsConnection::set_publish_group_info`)
+    pub fn set_publish_group_info(&mut self, publish: bool) {
+        self.connection.set_publish_group_info(publish);
+    }
+
+    /// Also store Welcomes on the server for invitees who may be offline (see
+    /// `MlsConnection::set_store_welcomes`)
+    pub fn set_store_welcomes(&mut self, store: bool) {
+        self.connection.set_store_welcomes(store);
+    }
+
+    /// Follow member removals with a self-update Commit (see
+    /// `MlsConnection::set_self_update_after_remove`)
+    pub fn set_self_update_after_remove(&mut self, enabled: bool) {
+        self.connection.set_self_update_after_remove(enabled);
+    }
+
+    /// Ask for binary WebSocket frames when connecting (see
+    /// `MlsConnection::set_websocket_framing`)
+    pub fn set_websocket_framing(&mut self, framing: Framing) {
+        self.connection.set_websocket_framing(framing);
+    }
+
+    /// Set the delays between reconnection attempts (see
+    /// `MlsConnection::set_reconnect_backoff`)
+    pub fn set_reconnect_backoff(&mut self, backoff: ReconnectBackoff) {
+        self.connection.set_reconnect_backoff(backoff);
+    }
+
+    /// Choose what `connect_to_group` does when the group's state was lost
+    /// (see `LostGroupPolicy`)
+    pub fn set_lost_group_policy(&mut self, policy: LostGroupPolicy) {
+        self.connection.set_lost_group_policy(policy);
+    }
+
+    /// Choose whether incoming Welcomes are joined right away or staged for
+    /// `accept_welcome` / `decline_welcome` (see `WelcomePolicy`)
+    pub fn set_welcome_policy(&mut self, policy: WelcomePolicy) {
+        self.connection.set_welcome_policy(policy);
+    }
+
+    /// Welcomes waiting for this user to accept or decline, oldest first
+    pub fn staged_welcomes(&self) -> Result<Vec<StagedWelcome>> {
+        self.connection.staged_welcomes()
+    }
+
+    /// Groups this user is invited to but has not joined, oldest first
+    ///
+    /// See `MlsConnection::pending_invitations_for_me`.
+    pub fn pending_invitations_for_me(&self) -> Result<Vec<PendingInvitation>> {
+        self.connection.pending_invitations_for_me()
+    }
+
+    /// Join the group of a staged Welcome and select it
+    ///
+    /// # Errors
+    /// * as for `MlsConnection::accept_welcome`
+    pub async fn accept_welcome(&mut self, id: i64) -> Result<()> {
+        let group_id = self.connection.accept_welcome(id).await?;
+        self.selected_group_id = Some(group_id);
+        Ok(())
+    }
+
+    /// Discard a staged Welcome; false if no Welcome with that id is staged
+    pub async fn decline_welcome(&mut self, id: i64) -> Result<bool> {
+        self.connection.decline_welcome(id).await
+    }
+
+    /// Limit the queue of messages written while offline (see
+    /// `OutboundQueueConfig`)
+    pub fn set_outbound_queue_config(&mut self, config: OutboundQueueConfig) {
+        self.connection.set_outbound_queue_config(config);
+    }
+
+    /// Reject messages from senders who are not in the group (see
+    /// `MlsConnection::set_strict_senders`)
+    pub fn set_strict_senders(&mut self, strict: bool) {
+        self.connection.set_strict_senders(strict);
+    }
+
+    /// Refuse to send while a Commit of ours is pending (see
+    /// `MlsConnection::set_strict_epoch`)
+    pub fn set_strict_epoch(&mut self, strict: bool) {
+        self.connection.set_strict_epoch(strict);
+    }
+
+    /// Bound the envelopes from later epochs each group holds (see
+    /// `MlsConnection::set_max_held_envelopes`)
+    pub fn set_max_held_envelopes(&mut self, max: usize) {
+        self.connection.set_max_held_envelopes(max);
+    }
+
+    /// Choose Basic (default) or X.509 credentials for a new identity
+    ///
+    /// Must be called before `initialize()`.
+    pub fn set_credential_kind(&mut self, kind: CredentialKind) {
+        self.connection.set_credential_kind(kind);
+    }
+
+    /// Require members' X.509 credentials to chain to one of `anchors` (see
+    /// `MlsConnection::set_trust_anchors`)
+    pub fn set_trust_anchors(&mut self, anchors: TrustAnchors) {
+        self.connection.set_trust_anchors(anchors);
+    }
+
+    /// Use a certificate and key issued by an external CA as this user's
+    /// identity
+    ///
+    /// Must be called before `initialize()`. See
+    /// `MlsConnection::import_x509_identity`.
+    pub fn import_x509_identity(&mut self, chain_pem: &[u8], key_pem: &str) -> Result<()> {
+        self.connection.import_x509_identity(chain_pem, key_pem)
+    }
+
+    /// Keep the identity in the database files (default) or the OS keyring
+    ///
+    /// Must be called before `initialize()`. See
+    /// `MlsConnection::set_identity_storage`.
+    pub fn set_identity_storage(&mut self, storage: IdentityStorage) {
+        self.connection.set_identity_storage(storage);
+    }
+
+    /// Keep the identity in a custom secret store
+    ///
+    /// Must be called before `initialize()`.
+    pub fn set_identity_keyring(&mut self, keyring: Arc<dyn SecretStore>) {
+        self.connection.set_identity_keyring(keyring);
+    }
+
+    /// Set how many recent history entries `connect_to_group` loads (default 50)
+    ///
+    /// Older entries are loaded on demand with `load_older_history`.
+    pub fn set_initial_history_limit(&mut self, limit: usize) {
+        self.initial_history_limit = limit;
+    }
+
     /// Connect to group (create or load existing)
     ///
     /// Creates or loads a group membership and connects WebSocket for real-time messaging.
-    /// Delegates to MlsConnection and MlsMembership.
+    /// Delegates to MlsConnection and MlsMembership. Only the most recent
+    /// local history is loaded (see `set_initial_history_limit`); older
+    /// entries come from `load_older_history`.
+    ///
+    /// The group name is claimed on the server for this user. If the claim
+    /// fails for another reason than a conflict (e.g. the server is
+    /// unreachable), the group is still connected and the claim is retried on
+    /// the next connect; until then another device could create a second
+    /// group under the name.
     ///
     /// # Arguments
     /// * `group_name` - Name of the group to create or load
@@ -175,12 +488,25 @@ impl MlsClient {
     /// # Errors
     /// * WebSocket connection errors
     /// * MLS errors when creating/loading group
+    /// * `MlsError::DuplicateGroup` if this user already created a group with
+    ///   the same name from another device
+    /// * `MlsError::GroupLimitReached` if creating the group would exceed the
+    ///   local or server group limit
+    /// * `MlsError::GroupStateLost` if the group is known locally but its state
+    ///   is gone, unless `LostGroupPolicy::Recreate` is set; the user is also
+    ///   notified through `GroupEvent::GroupStateLost`
     pub async fn connect_to_group(&mut self, group_name: &str) -> Result<()> {
         log::info!("Connecting to group: {}", group_name);
 
         // Connect WebSocket first
         self.connection.connect_websocket().await?;
 
+        // Join groups we were invited to while offline; the requested group
+        // may be one of them
+        if let Err(e) = self.connection.fetch_pending_welcomes().await {
+            log::warn!("Failed to fetch stored Welcomes: {}", e);
+        }
+
         // Get user from connection
         let user = self.connection.get_user().ok_or_else(|| {
             ClientError::Config("User not initialized - call initialize() first".to_string())
@@ -188,12 +514,114 @@ impl MlsClient {
 
         // Try to load or create membership for the specified group
         use crate::mls::membership::MlsMembership;
-        let membership =
-            MlsMembership::create_new_group(group_name, user, self.connection.get_provider())?;
-
-        // Store the group ID as selected
+        let provider = self.connection.get_provider();
+        let group_name_key = format!("{}:{}", user.get_username(), group_name);
+        let previous_group_id = provider.load_group_by_name(&group_name_key)?;
+        if previous_group_id.is_none() {
+            self.connection.ensure_group_capacity()?;
+        }
+        let mut recreated = false;
+        let past_epochs = self.connection.past_epochs();
+        let membership = match MlsMembership::create_new_group_with_past_epochs(
+            group_name,
+            user,
+            provider,
+            past_epochs,
+        ) {
+            Err(e @ ClientError::Mls(MlsError::GroupStateLost { .. })) => {
+                let lost_group_id = previous_group_id.clone().unwrap_or_default();
+                match self.connection.lost_group_policy() {
+                    LostGroupPolicy::Rejoin => {
+                        self.connection.report_lost_group(
+                            group_name,
+                            &lost_group_id,
+                            &lost_group_id,
+                            false,
+                        );
+                        return Err(e);
+                    }
+                    LostGroupPolicy::Recreate => {
+                        let membership = MlsMembership::recreate_lost_group(
+                            group_name,
+                            user,
+                            provider,
+                            past_epochs,
+                        )?;
+                        self.connection.report_lost_group(
+                            group_name,
+                            &lost_group_id,
+                            membership.get_group_id(),
+                            true,
+                        );
+                        recreated = true;
+                        membership
+                    }
+                }
+            }
+            result => result?,
+        };
         let group_id = membership.get_group_id().to_vec();
+        let reused = previous_group_id.as_deref() == Some(group_id.as_slice());
+
+        // The server enforces its own group limit; forget a new group if refused
+        if let Err(e) = self.connection.record_group_membership(&group_id).await {
+            if !reused {
+                provider.purge_group(&group_id)?;
+            }
+            return Err(e);
+        }
+
+        // Claim the group name on the server so the same user creating the
+        // group from another device gets an error instead of a second, hidden
+        // group that silently splits the conversation
+        match self
+            .connection
+            .get_api()
+            .register_group_name(&group_name_key, &group_id)
+            .await
+        {
+            Ok(()) => {}
+            Err(ClientError::Mls(MlsError::DuplicateGroup {
+                existing_group_id, ..
+            })) if recreated => {
+                // The server still knows the name as the lost group
+                log::warn!(
+                    "Group '{}' was recreated; the server still lists the previous group ({}) under this name",
+                    group_name,
+                    existing_group_id
+                );
+            }
+            Err(ClientError::Mls(MlsError::DuplicateGroup {
+                existing_group_id, ..
+            })) if reused => {
+                // Created before names were registered; keep using the local
+                // group, but make the split visible
+                log::warn!(
+                    "Group '{}' is also registered on the server as a different group ({}); \
+                     messages sent there are not visible from this device",
+                    group_name,
+                    existing_group_id
+                );
+            }
+            Err(e @ ClientError::Mls(MlsError::DuplicateGroup { .. })) => {
+                // Delete the group we just created, with its mapping, so a
+                // later invitation to the existing group can take its place
+                provider.purge_group(&group_id)?;
+                return Err(e);
+            }
+            Err(e) => {
+                // The name is claimed again on the next connect
+                log::warn!(
+                    "Failed to register group name '{}' on the server: {}",
+                    group_name,
+                    e
+                );
+            }
+        }
+
+        // Store the group ID as selected and load its recent history
         self.selected_group_id = Some(group_id.clone());
+        self.load_history_window(&group_id)?;
 
         // Add membership to connection's HashMap
         self.connection.add_membership(membership);
@@ -206,6 +634,128 @@ impl MlsClient {
         Ok(())
     }
 
+    /// Shut down the client's connection to the server
+    ///
+    /// Flushes messages still queued on the WebSocket before closing it, so
+    /// one-shot commands can exit right after sending.
+    pub async fn shutdown(&mut self) {
+        self.connection.disconnect_websocket().await;
+    }
+
+    /// Describe the loaded identity: username, credential fingerprint,
+    /// signature public key and server registration
+    ///
+    /// Useful to check that the expected identity was loaded from storage.
+    /// Never includes the signature private key.
+    ///
+    /// # Errors
+    /// * User not initialized (call `initialize()` first)
+    pub fn whoami(&self) -> Result<IdentityInfo> {
+        self.connection.whoami()
+    }
+
+    /// Run read-only diagnostics and report pass/fail per check
+    /// (see `MlsConnection::self_test`)
+    pub async fn self_test(&self) -> SelfTestReport {
+        self.connection.self_test().await
+    }
+
+    /// Report how much local storage this user's chat data uses
+    pub fn storage_stats(&self) -> Result<StorageStats> {
+        self.connection.storage_stats()
+    }
+
+    /// Count the locally tracked KeyPackages per status
+    pub fn keypackage_pool_stats(&self) -> Result<BTreeMap<String, usize>> {
+        self.connection.keypackage_pool_stats()
+    }
+
+    /// Check that every local group mapping refers to a group that loads
+    pub fn check_storage_integrity(&self) -> Result<IntegrityReport> {
+        self.connection.check_storage_integrity()
+    }
+
+    /// Prune group mappings whose state is gone entirely
+    ///
+    /// Unloadable and unreferenced group state is reported but never deleted.
+    pub fn repair_storage(&self) -> Result<IntegrityReport> {
+        self.connection.repair_storage()
+    }
+
+    /// Keep one of the groups reported under the same name by
+    /// `check_storage_integrity`; the others stay stored but unmapped
+    pub fn resolve_group_name_conflict(&self, group_name: &str, keep: &[u8]) -> Result<()> {
+        self.connection
+            .resolve_group_name_conflict(group_name, keep)
+    }
+
+    /// Remove this user's account data from the device
+    ///
+    /// Deletes the user's MLS provider database and metadata (see
+    /// `MlsConnection::wipe`). The server-side registration is kept; creating
+    /// a new client for the same username starts from a fresh identity.
+    ///
+    /// # Errors
+    /// * Database or I/O errors while deleting local state
+    pub async fn wipe(mut self) -> Result<()> {
+        self.shutdown().await;
+        self.connection.wipe()
+    }
+
+    /// Delete this user's account, KeyPackages and backups on the server
+    ///
+    /// See `MlsConnection::deregister`. Other members still see the user in
+    /// their groups until an admin removes them. Call before `wipe` to
+    /// remove the account everywhere.
+    ///
+    /// # Errors
+    /// * No identity stored locally
+    /// * Network errors, including the server refusing the signature
+    pub async fn deregister(&self) -> Result<bool> {
+        self.connection.deregister().await
+    }
+
+    /// Replace `username`'s local MLS state with backup `version` from the server
+    ///
+    /// The backup's state is the base64 image of the provider database (see
+    /// `MlsConnection::restore_provider_database`). Call with no client for
+    /// `username` open; the current state is overwritten.
+    ///
+    /// # Errors
+    /// * `ClientError::InvalidCommand` if the server has no such version
+    /// * `StorageError::InvalidBackup` if the version is not a usable database
+    /// * Network and I/O errors
+    pub async fn restore_backup(
+        server_url: &str,
+        username: &str,
+        storage_dir: &Path,
+        version: i64,
+    ) -> Result<()> {
+        let api = ServerApi::new(server_url);
+        let Some(backup) = api.get_backup(username, version).await? else {
+            let available: Vec<String> = api
+                .list_backups(username)
+                .await?
+                .iter()
+                .map(|backup| backup.version.to_string())
+                .collect();
+            return Err(ClientError::InvalidCommand(format!(
+                "no backup version {} for '{}' (available: {})",
+                version,
+                username,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )));
+        };
+        let image = general_purpose::STANDARD
+            .decode(&backup.encrypted_state)
+            .map_err(|e| StorageError::InvalidBackup(format!("not base64: {}", e)))?;
+        MlsConnection::restore_provider_database(storage_dir, username, &image)
+    }
+
     /// Send a message to the group
     ///
     /// Delegates to the selected membership to send the message.
@@ -225,6 +775,179 @@ impl MlsClient {
         self.connection.send_message_to_group(group_id, text).await
     }
 
+    /// Queue a message for the selected group until the connection is back
+    ///
+    /// Returns the queued messages evicted to make room for it.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `StorageError::OutboundQueueFull` if the policy refuses new messages
+    pub fn queue_message(&self, text: &str) -> Result<Vec<QueuedMessage>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.queue_offline_message(group_id, text)
+    }
+
+    /// Send the messages queued while offline (see
+    /// `MlsConnection::flush_offline_queue`)
+    pub async fn flush_offline_queue(&mut self) -> Result<(usize, Vec<QueuedMessage>)> {
+        self.connection.flush_offline_queue().await
+    }
+
+    /// Outbound operations of every group that have not completed (see
+    /// `MlsConnection::pending_operations`)
+    pub fn pending_operations(&self) -> Result<Vec<PendingOp>> {
+        self.connection.pending_operations()
+    }
+
+    /// Cancel an operation listed by `pending_operations` (see
+    /// `MlsConnection::cancel_operation`)
+    pub fn cancel_operation(&mut self, id: &PendingOpId) -> Result<CancelOutcome> {
+        self.connection.cancel_operation(id)
+    }
+
+    /// Post an announcement to the selected group (admins only)
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    pub async fn send_announcement(&mut self, text: &str) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .send_announcement_to_group(group_id, text)
+            .await
+    }
+
+    /// Pin a message of the selected group (admins only)
+    ///
+    /// `message_id` is the `HistoryEntry::message_id` of the message.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    /// * `MlsError::MessageNotFound` if the message is unknown or expired
+    pub async fn pin_message(&mut self, message_id: &str) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .pin_message_in_group(group_id, message_id, false)
+            .await
+    }
+
+    /// Unpin a message of the selected group (admins only)
+    ///
+    /// Unpinning a message that is not pinned succeeds.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    pub async fn unpin_message(&mut self, message_id: &str) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .pin_message_in_group(group_id, message_id, true)
+            .await
+    }
+
+    /// The pinned messages of the selected group, oldest first
+    ///
+    /// # Errors
+    /// * No group selected
+    pub fn get_pinned_messages(&self) -> Result<Vec<HistoryEntry>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.get_pinned_messages(group_id)
+    }
+
+    /// Search the local history of every group, newest match first
+    ///
+    /// See `MlsConnection::search_messages`.
+    pub fn search_messages(&self, search: &MessageSearch, limit: usize) -> Result<Vec<SearchHit>> {
+        self.connection.search_messages(search, limit)
+    }
+
+    /// Store settings for the selected group, e.g. a notification sound or
+    /// theme
+    ///
+    /// The settings are arbitrary JSON kept on this device only and are
+    /// replaced as a whole; `Value::Null` removes them.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `StorageError::GroupSettingsTooLarge` above
+    ///   `provider::MAX_GROUP_SETTINGS_BYTES`
+    pub fn set_group_settings(&self, settings: &serde_json::Value) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.set_group_settings(group_id, settings)
+    }
+
+    /// The settings stored for the selected group, if any
+    ///
+    /// # Errors
+    /// * No group selected
+    pub fn get_group_settings(&self) -> Result<Option<serde_json::Value>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.get_group_settings(group_id)
+    }
+
+    /// Send a message to the group that receivers should render in `format`
+    ///
+    /// # Errors
+    /// * as for `send_message`
+    pub async fn send_formatted_message(&mut self, text: &str, format: TextFormat) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .send_formatted_message_to_group(group_id, text, format)
+            .await
+    }
+
+    /// Reply to a message of the selected group, quoting it
+    ///
+    /// `quoted_message_id` is the `HistoryEntry::message_id` of the message.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::MessageNotFound` if the message is unknown or expired
+    /// * as for `send_message`
+    pub async fn send_reply(&mut self, quoted_message_id: &str, text: &str) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .send_reply_to_group(group_id, quoted_message_id, text)
+            .await
+    }
+
     /// Invite a user to the group
     ///
     /// Delegates to the selected membership to invite the user.
@@ -246,6 +969,248 @@ impl MlsClient {
             .await
     }
 
+    /// Remove a member from the selected group (admins only)
+    ///
+    /// Returns the epoch the group moved into; the removed member cannot
+    /// decrypt anything sent from then on.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    /// * `MlsError::MemberNotFound` if `username` is not in the group
+    pub async fn remove_member(&mut self, username: &str) -> Result<u64> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .remove_member_from_group(group_id, username)
+            .await
+    }
+
+    /// Resend the Welcome to a member of the selected group who never received it
+    ///
+    /// Use this to recover from an invite that failed after the member was
+    /// added (see `InviteError::group_state_advanced`).
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::WelcomeStale` if the group changed since the member was added
+    pub async fn resend_welcome(&self, username: &str) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .resend_welcome_in_group(group_id, username)
+            .await
+    }
+
+    /// Join time and last activity of the selected group's members
+    ///
+    /// # Errors
+    /// * No group selected
+    pub fn member_activity(&self) -> Result<Vec<crate::models::MemberActivity>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.member_activity(group_id)
+    }
+
+    /// Invites of the selected group whose invitee has not shown up yet
+    ///
+    /// # Errors
+    /// * No group selected
+    pub fn pending_invites(&self) -> Result<Vec<crate::models::PendingInvite>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.pending_invites(group_id)
+    }
+
+    /// Resend the Welcomes of the selected group's pending invites
+    ///
+    /// Returns the invitees the Welcome was resent to; see
+    /// `MlsConnection::resend_pending_invites`.
+    ///
+    /// # Errors
+    /// * No group selected
+    pub async fn resend_pending_invites(&self) -> Result<Vec<String>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.resend_pending_invites(group_id).await
+    }
+
+    /// Set the disappearing-message TTL of the selected group
+    ///
+    /// See `MlsMembership::set_message_ttl`; `None` turns expiry off.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * MLS or WebSocket errors while committing the change
+    pub async fn set_message_ttl(&mut self, ttl_secs: Option<u64>) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .set_message_ttl_in_group(group_id, ttl_secs)
+            .await
+    }
+
+    /// Loaded history of the selected group, oldest first
+    ///
+    /// Holds the recent window loaded by `connect_to_group` plus any older
+    /// pages fetched since with `load_older_history`.
+    pub fn loaded_history(&self) -> &[HistoryEntry] {
+        &self.history_window
+    }
+
+    /// Whether the selected group has history older than `loaded_history`
+    pub fn has_older_history(&self) -> bool {
+        self.older_history.is_some()
+    }
+
+    /// Load the page of history before `loaded_history`
+    ///
+    /// Loads up to the initial history limit of older entries, prepends them
+    /// to `loaded_history` and returns them. Returns an empty page at the
+    /// start of history.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * Database errors
+    pub fn load_older_history(&mut self) -> Result<Vec<HistoryEntry>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+        let Some(before) = self.older_history else {
+            return Ok(Vec::new());
+        };
+
+        let page = self.connection.get_provider().load_history_page(
+            group_id,
+            Some(before),
+            self.initial_history_limit,
+        )?;
+        self.older_history = page.older;
+        self.history_window
+            .splice(0..0, page.entries.iter().cloned());
+        Ok(page.entries)
+    }
+
+    /// Replace the loaded history with the most recent window of `group_id`
+    fn load_history_window(&mut self, group_id: &[u8]) -> Result<()> {
+        let page = self.connection.get_provider().load_history_page(
+            group_id,
+            None,
+            self.initial_history_limit,
+        )?;
+        self.history_window = page.entries;
+        self.older_history = page.older;
+        Ok(())
+    }
+
+    /// Create a single-use join token for the selected group (admins only)
+    ///
+    /// See `MlsConnection::create_join_token_in_group`.
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    pub async fn create_join_token(&self, ttl_secs: Option<u64>) -> Result<JoinToken> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection
+            .create_join_token_in_group(group_id, ttl_secs)
+            .await
+    }
+
+    /// Revoke an unredeemed join token (see `MlsConnection::revoke_join_token`)
+    pub async fn revoke_join_token(&self, token: &str) -> Result<bool> {
+        self.connection.revoke_join_token(token).await
+    }
+
+    /// Redeem a join token (see `MlsConnection::redeem_join_token`)
+    pub async fn redeem_join_token(&mut self, token: &str) -> Result<Vec<u8>> {
+        self.connection.redeem_join_token(token).await
+    }
+
+    /// Add everyone who redeemed a join token of the selected group (see
+    /// `MlsConnection::admit_join_requests_in_group`)
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * As for `MlsConnection::admit_join_requests_in_group`
+    pub async fn admit_join_requests(&mut self) -> Result<Vec<String>> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.admit_join_requests_in_group(group_id).await
+    }
+
+    /// Number of unread messages from other members in the selected group
+    ///
+    /// # Errors
+    /// * No group selected
+    pub fn unread_count(&self) -> Result<usize> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.unread_count(group_id)
+    }
+
+    /// Unread message counts of all local groups, keyed by group name
+    pub fn unread_counts(&self) -> Result<BTreeMap<String, usize>> {
+        self.connection.unread_counts()
+    }
+
+    /// All local groups with their last activity and unread counts
+    ///
+    /// Groups without messages sort last by activity; ties fall back to the
+    /// group name (see `models::sort_group_listings`).
+    pub fn list_groups(&self, order: GroupSort) -> Result<Vec<GroupListing>> {
+        self.connection.list_groups(order)
+    }
+
+    /// Every local group with its details in one call (see
+    /// `MlsConnection::list_groups_detailed`)
+    pub fn list_groups_detailed(&self) -> Result<Vec<GroupSummary>> {
+        self.connection.list_groups_detailed()
+    }
+
+    /// Mark the selected group as read and send a read receipt
+    ///
+    /// # Errors
+    /// * No group selected
+    /// * WebSocket errors while sending the receipt
+    pub async fn mark_all_read(&mut self) -> Result<()> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+
+        self.connection.mark_all_read(group_id).await
+    }
+
     /// List group members
     ///
     /// Returns the members from the currently selected group.
@@ -261,6 +1226,55 @@ impl MlsClient {
         vec![]
     }
 
+    /// Export the selected group's history as a compliance archive (see
+    /// `MlsConnection::export_archive`)
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if no group is selected
+    /// * as for `MlsConnection::export_archive`
+    pub fn export_archive(&self) -> Result<GroupArchive> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+        self.connection.export_archive(group_id)
+    }
+
+    /// Compare the selected group's MLS roster with the server's member list
+    /// (see `MlsConnection::compare_rosters`)
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if no group is selected
+    /// * as for `MlsConnection::compare_rosters`
+    pub async fn compare_rosters(&self) -> Result<RosterComparison> {
+        let group_id = self
+            .selected_group_id
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("No group selected".to_string()))?;
+        self.connection.compare_rosters(group_id).await
+    }
+
+    /// Number of members in the currently selected group (0 if none selected)
+    pub fn member_count(&self) -> usize {
+        self.selected_group_id
+            .as_ref()
+            .and_then(|group_id| self.connection.get_membership(group_id))
+            .map_or(0, |membership| membership.member_count())
+    }
+
+    /// List one page of the currently selected group's members
+    ///
+    /// See `MlsMembership::list_members_paged` for the ordering guarantees.
+    /// Returns an empty page if no group is selected.
+    pub fn list_members_paged(&self, offset: usize, limit: usize) -> Vec<String> {
+        self.selected_group_id
+            .as_ref()
+            .and_then(|group_id| self.connection.get_membership(group_id))
+            .map_or_else(Vec::new, |membership| {
+                membership.list_members_paged(offset, limit)
+            })
+    }
+
     /// Expose metadata store reference (primarily for integration tests)
     pub fn get_metadata_store(&self) -> &LocalStore {
         self.connection.get_metadata_store()
@@ -296,6 +1310,9 @@ impl MlsClient {
     /// # Arguments
     /// * `group_id` - The group ID to select
     pub fn set_selected_group_id(&mut self, group_id: Vec<u8>) {
+        if let Err(e) = self.load_history_window(&group_id) {
+            log::warn!("Failed to load history of the selected group: {}", e);
+        }
         self.selected_group_id = Some(group_id);
     }
 
@@ -313,6 +1330,34 @@ impl MlsClient {
         &mut self.connection
     }
 
+    /// Register an observer for group changes (see `MlsConnection::subscribe_events`)
+    pub fn subscribe_events(
+        &self,
+    ) -> (
+        crate::events::ObserverId,
+        tokio::sync::mpsc::UnboundedReceiver<crate::events::GroupEvent>,
+    ) {
+        self.connection.subscribe_events()
+    }
+
+    /// Remove an observer registered with `subscribe_events`
+    pub fn unsubscribe_events(&self, id: crate::events::ObserverId) -> bool {
+        self.connection.unsubscribe_events(id)
+    }
+
+    /// Add a transform of chat text (see `MlsConnection::add_message_transform`)
+    pub fn add_message_transform(
+        &self,
+        transform: std::sync::Arc<dyn crate::transform::MessageTransform>,
+    ) {
+        self.connection.add_message_transform(transform);
+    }
+
+    /// Message counters of this client (see `MlsConnection::metrics`)
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.connection.metrics()
+    }
+
     // ========== Test Helpers ==========
 
     /// Test helper: get reference to identity
@@ -1,11 +1,70 @@
 //! MLS cryptographic operations using OpenMLS
 
 use crate::error::{MlsError, Result};
-use openmls::messages::group_info::GroupInfo;
+use crate::x509::TrustAnchors;
+use openmls::messages::group_info::{GroupInfo, VerifiableGroupInfo};
 use openmls::prelude::*;
 use openmls_basic_credential::SignatureKeyPair;
 use openmls_rust_crypto::OpenMlsRustCrypto;
 
+/// Earlier epochs whose message secrets a group keeps with strict sender
+/// checks on
+///
+/// Other groups keep none, so a leaked group state cannot decrypt messages
+/// of past epochs. OpenMLS sizes this store only when a group is created or
+/// joined, so the choice is made there (see `create_group_with_past_epochs`
+/// and `join_config`).
+pub const STRICT_SENDERS_PAST_EPOCHS: usize = 1;
+
+/// Which MLS credential type an identity uses
+///
+/// Basic credentials carry the bare username. X.509 credentials carry a
+/// certificate whose subject CN is the username, for deployments that bind
+/// identities to a PKI. A group only accepts members of its creator's type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CredentialKind {
+    #[default]
+    Basic,
+    X509,
+}
+
+impl CredentialKind {
+    /// The OpenMLS credential type for this kind
+    pub fn credential_type(self) -> CredentialType {
+        match self {
+            CredentialKind::Basic => CredentialType::Basic,
+            CredentialKind::X509 => CredentialType::X509,
+        }
+    }
+
+    /// The kind of an OpenMLS credential type, if this client supports it
+    pub fn from_credential_type(credential_type: CredentialType) -> Option<Self> {
+        match credential_type {
+            CredentialType::Basic => Some(CredentialKind::Basic),
+            CredentialType::X509 => Some(CredentialKind::X509),
+            _ => None,
+        }
+    }
+}
+
+/// Leaf capabilities advertising only the credential type in use
+///
+/// OpenMLS rejects a leaf whose credential type is not supported by every
+/// member, so this is what keeps Basic and X.509 groups apart. The group
+/// metadata extension is listed because every group requires it (see
+/// [`metadata_required_capabilities`]).
+pub fn capabilities_for(credential_type: CredentialType) -> Capabilities {
+    Capabilities::new(
+        None,
+        None,
+        Some(&[ExtensionType::Unknown(
+            crate::extensions::GROUP_METADATA_EXTENSION_TYPE,
+        )]),
+        None,
+        Some(&[credential_type]),
+    )
+}
+
 /// Generate a credential with key for a username
 pub fn generate_credential_with_key(
     username: &str,
@@ -33,6 +92,24 @@ pub fn generate_credential_with_key(
     Ok((credential_with_key, signature_keys))
 }
 
+/// Generate an X.509 credential with key for a username
+///
+/// The credential holds a self-signed certificate with the username as
+/// subject CN, bound to the generated signature key.
+pub fn generate_x509_credential_with_key(
+    username: &str,
+) -> Result<(CredentialWithKey, SignatureKeyPair)> {
+    let (certificate, signature_keys) = crate::x509::self_signed_identity(username)?;
+    let credential = crate::x509::credential_from_chain(&[certificate])?;
+
+    let credential_with_key = CredentialWithKey {
+        credential,
+        signature_key: signature_keys.to_public_vec().into(),
+    };
+
+    Ok((credential_with_key, signature_keys))
+}
+
 /// Generate a key package bundle for the given credential and signature key
 pub fn generate_key_package_bundle(
     credential: &CredentialWithKey,
@@ -41,7 +118,9 @@ pub fn generate_key_package_bundle(
 ) -> Result<KeyPackageBundle> {
     let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
 
+    let capabilities = capabilities_for(credential.credential.credential_type());
     let key_package = KeyPackage::builder()
+        .leaf_node_capabilities(capabilities)
         .build(ciphersuite, provider, signer, credential.clone())
         .map_err(|e| MlsError::OpenMls(e.to_string()))?;
 
@@ -49,27 +128,53 @@ pub fn generate_key_package_bundle(
 }
 
 /// Create a new MLS group with configuration
+///
+/// The group keeps no message secrets of past epochs.
 pub fn create_group_with_config(
     credential: &CredentialWithKey,
     signer: &SignatureKeyPair,
     provider: &impl OpenMlsProvider,
     group_name: &str,
 ) -> Result<MlsGroup> {
-    // Create group metadata extension (encrypted in group state)
-    let metadata = crate::extensions::GroupMetadata::new(group_name.to_string());
+    create_group_with_past_epochs(credential, signer, provider, group_name, 0)
+}
+
+/// Create a new MLS group that keeps the message secrets of `past_epochs`
+/// earlier epochs
+pub fn create_group_with_past_epochs(
+    credential: &CredentialWithKey,
+    signer: &SignatureKeyPair,
+    provider: &impl OpenMlsProvider,
+    group_name: &str,
+    past_epochs: usize,
+) -> Result<MlsGroup> {
+    // Create group metadata extension (encrypted in group state); the
+    // creator is the group's first admin
+    let mut metadata = crate::extensions::GroupMetadata::new(group_name.to_string());
+    metadata
+        .admins
+        .extend(credential_identity(&credential.credential));
     let metadata_bytes = metadata
         .to_bytes()
         .map_err(|e| MlsError::OpenMls(format!("Failed to serialize group metadata: {}", e)))?;
 
-    let group_metadata_ext = Extensions::single(Extension::Unknown(
-        crate::extensions::GROUP_METADATA_EXTENSION_TYPE,
-        UnknownExtension(metadata_bytes),
-    ));
+    // Require the metadata extension so that OpenMLS refuses to add anyone
+    // whose client cannot read it, instead of them joining and failing later
+    let group_metadata_ext = Extensions::from_vec(vec![
+        Extension::RequiredCapabilities(metadata_required_capabilities(None)),
+        Extension::Unknown(
+            crate::extensions::GROUP_METADATA_EXTENSION_TYPE,
+            UnknownExtension(metadata_bytes),
+        ),
+    ])
+    .map_err(|e| MlsError::OpenMls(e.to_string()))?;
 
     // Create group with metadata extension in GroupContext
     let group_config = MlsGroupCreateConfig::builder()
         .with_group_context_extensions(group_metadata_ext)
         .map_err(|e| MlsError::OpenMls(e.to_string()))?
+        .max_past_epochs(past_epochs)
+        .capabilities(capabilities_for(credential.credential.credential_type()))
         .build();
 
     let group = MlsGroup::new(provider, signer, &group_config, credential.clone())
@@ -78,6 +183,30 @@ pub fn create_group_with_config(
     Ok(group)
 }
 
+/// Required capabilities of a group, extended with the metadata extension
+///
+/// Keeps whatever `existing` already requires. Every member's leaf must then
+/// advertise the extension (see [`capabilities_for`]), which OpenMLS checks
+/// for each KeyPackage added to the group.
+pub fn metadata_required_capabilities(
+    existing: Option<&RequiredCapabilitiesExtension>,
+) -> RequiredCapabilitiesExtension {
+    let metadata_type = ExtensionType::Unknown(crate::extensions::GROUP_METADATA_EXTENSION_TYPE);
+    let mut extension_types = existing
+        .map(|r| r.extension_types().to_vec())
+        .unwrap_or_default();
+    if !extension_types.contains(&metadata_type) {
+        extension_types.push(metadata_type);
+    }
+    let proposal_types = existing
+        .map(|r| r.proposal_types().to_vec())
+        .unwrap_or_default();
+    let credential_types = existing
+        .map(|r| r.credential_types().to_vec())
+        .unwrap_or_default();
+    RequiredCapabilitiesExtension::new(&extension_types, &proposal_types, &credential_types)
+}
+
 /// Create an application message
 pub fn create_application_message(
     group: &mut MlsGroup,
@@ -110,6 +239,209 @@ pub fn process_message(
     Ok(processed_message)
 }
 
+/// Configuration for joining a group from a Welcome, keeping the message
+/// secrets of `past_epochs` earlier epochs
+pub fn join_config(past_epochs: usize) -> MlsGroupJoinConfig {
+    MlsGroupJoinConfig::builder()
+        .max_past_epochs(past_epochs)
+        .build()
+}
+
+/// Epoch an incoming protocol message was created in
+///
+/// `None` for messages that are not tied to an epoch (e.g. a Welcome).
+pub fn message_epoch(message: &MlsMessageIn) -> Option<u64> {
+    let protocol_message = message.clone().try_into_protocol_message().ok()?;
+    Some(protocol_message.epoch().as_u64())
+}
+
+/// Content of an incoming MLS message, reduced to what the client acts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessedContent {
+    /// Decrypted application data, the username of its sender and the
+    /// fingerprint of the sender's credential and key (see
+    /// `credential_fingerprint`)
+    Application {
+        sender_identity: String,
+        sender_fingerprint: Option<String>,
+        plaintext: Vec<u8>,
+    },
+    /// A commit that was merged into the group: its committer and the
+    /// usernames it added and removed
+    MembershipChange {
+        sender_identity: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// Proposals and anything else that needs no action from the caller
+    Other,
+}
+
+/// Process an incoming MLS message and classify its content
+///
+/// Staged commits are merged into `group` before returning, so the caller
+/// does not need to call `merge_staged_commit` itself. Removed members are
+/// looked up before the merge, while they are still in the tree. The sender
+/// and added members are identified with `member_identity`, so their X.509
+/// chains must be issued by one of `anchors`.
+pub fn process_envelope_content(
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    message: &MlsMessageIn,
+    anchors: &TrustAnchors,
+) -> Result<ProcessedContent> {
+    let processed = process_message(group, provider, message)?;
+    // The signer's leaf in the epoch the message was verified against
+    let sender_leaf = match processed.sender() {
+        Sender::Member(index) => group.members().find(|member| member.index == *index),
+        _ => None,
+    };
+    let sender_identity = match &sender_leaf {
+        Some(leaf) => member_identity(&leaf.credential, &leaf.signature_key, anchors),
+        None => credential_identity(processed.credential()),
+    }
+    .ok_or_else(|| MlsError::OpenMls("Sender credential has no trusted username".to_string()));
+    let sender_fingerprint = sender_leaf.and_then(|member| {
+        let credential = CredentialWithKey {
+            credential: member.credential,
+            signature_key: member.signature_key.into(),
+        };
+        credential_fingerprint(provider, &credential).ok()
+    });
+
+    match processed.into_content() {
+        ProcessedMessageContent::ApplicationMessage(app_msg) => {
+            let sender_identity = sender_identity?;
+            Ok(ProcessedContent::Application {
+                sender_identity,
+                sender_fingerprint,
+                plaintext: app_msg.into_bytes(),
+            })
+        }
+        ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
+            let sender_identity = sender_identity?;
+            let added = staged_commit
+                .add_proposals()
+                .filter_map(|add| {
+                    let leaf = add.add_proposal().key_package().leaf_node();
+                    member_identity(leaf.credential(), leaf.signature_key().as_slice(), anchors)
+                })
+                .collect();
+            let removed = staged_commit
+                .remove_proposals()
+                .filter_map(|remove| {
+                    group
+                        .member(remove.remove_proposal().removed())
+                        .and_then(credential_identity)
+                })
+                .collect();
+
+            group
+                .merge_staged_commit(provider, *staged_commit)
+                .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+
+            Ok(ProcessedContent::MembershipChange {
+                sender_identity,
+                added,
+                removed,
+            })
+        }
+        ProcessedMessageContent::ProposalMessage(_)
+        | ProcessedMessageContent::ExternalJoinProposalMessage(_) => Ok(ProcessedContent::Other),
+    }
+}
+
+/// Extract the username from a credential
+///
+/// Basic credentials carry it directly; X.509 credentials carry it as the
+/// subject CN of the leaf certificate.
+pub fn credential_identity(credential: &Credential) -> Option<String> {
+    match credential.credential_type() {
+        CredentialType::Basic => {
+            let basic = BasicCredential::try_from(credential.clone()).ok()?;
+            String::from_utf8(basic.identity().to_vec()).ok()
+        }
+        CredentialType::X509 => {
+            let chain = crate::x509::chain_from_credential(credential)?;
+            crate::x509::parse_certificate(&chain[0]).map(|info| info.common_name)
+        }
+        _ => None,
+    }
+}
+
+/// Fingerprint of an identity: SHA-256 over its TLS-serialized credential
+/// followed by its signature public key, as lowercase hex
+///
+/// Covers the key as well as the username, so it changes when the identity
+/// is recreated and can be compared out of band.
+pub fn credential_fingerprint(
+    provider: &impl OpenMlsProvider,
+    credential: &CredentialWithKey,
+) -> Result<String> {
+    use openmls_traits::crypto::OpenMlsCrypto;
+    use openmls_traits::types::HashType;
+    use tls_codec::Serialize;
+
+    let mut data = credential
+        .credential
+        .tls_serialize_detached()
+        .map_err(|e| MlsError::OpenMls(format!("Failed to serialize credential: {}", e)))?;
+    data.extend_from_slice(credential.signature_key.as_slice());
+    let digest = provider
+        .crypto()
+        .hash(HashType::Sha2_256, &data)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to hash credential: {:?}", e)))?;
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Number of SHA-256 bytes kept in a message id
+const MESSAGE_ID_LEN: usize = 16;
+
+/// Id of an application message: the first 16 bytes of SHA-256 over its
+/// TLS-serialized `MLSMessage`, as lowercase hex
+///
+/// Every member sees the same ciphertext, so sender and receivers derive the
+/// same id without coordinating; it reveals nothing about the plaintext.
+pub fn message_id(provider: &impl OpenMlsProvider, message_bytes: &[u8]) -> Result<String> {
+    use openmls_traits::crypto::OpenMlsCrypto;
+    use openmls_traits::types::HashType;
+
+    let digest = provider
+        .crypto()
+        .hash(HashType::Sha2_256, message_bytes)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to hash message: {:?}", e)))?;
+    Ok(digest[..MESSAGE_ID_LEN]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Extract the username of a leaf, checking it is bound to the leaf's key
+///
+/// For X.509 credentials the leaf certificate must certify `signature_key`,
+/// otherwise a member could present someone else's certificate, and the
+/// chain must be issued by one of `anchors` (any chain passes when none are
+/// configured).
+pub fn member_identity(
+    credential: &Credential,
+    signature_key: &[u8],
+    anchors: &TrustAnchors,
+) -> Option<String> {
+    if credential.credential_type() == CredentialType::X509 {
+        let chain = crate::x509::chain_from_credential(credential)?;
+        let info = crate::x509::parse_certificate(&chain[0])?;
+        if info.public_key != signature_key {
+            return None;
+        }
+        if let Err(e) = anchors.verify_chain(&chain) {
+            log::warn!("Rejected certificate for '{}': {}", info.common_name, e);
+            return None;
+        }
+        return Some(info.common_name);
+    }
+    credential_identity(credential)
+}
+
 /// Add members to the group
 /// Returns (commit_message_for_existing_members, welcome_message_for_new_members, group_info)
 pub fn add_members(
@@ -128,6 +460,62 @@ pub fn add_members(
     Ok((commit_message, welcome_message, group_info))
 }
 
+/// Remove members from the group
+/// Returns the commit message for the remaining members. Removed members can
+/// still process it, which is how they learn they were removed, but cannot
+/// decrypt anything sent in the epoch it starts.
+pub fn remove_members(
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    signer: &SignatureKeyPair,
+    members: &[LeafNodeIndex],
+) -> Result<MlsMessageOut> {
+    let (commit_message, _welcome, _group_info) = group
+        .remove_members(provider, signer, members)
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+    Ok(commit_message)
+}
+
+/// Create a Commit that replaces our own leaf with fresh key material
+///
+/// The Commit is left pending; merge it once the server accepted it.
+pub fn self_update(
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    signer: &SignatureKeyPair,
+) -> Result<MlsMessageOut> {
+    let bundle = group
+        .self_update(provider, signer, LeafNodeParameters::default())
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+    Ok(bundle.into_commit())
+}
+
+/// Create a Commit that replaces our own leaf under a new signature key
+///
+/// The leaf gets fresh key material and `credential_with_key`, and is signed
+/// with `old_signer` so members can verify the change. The Commit is left
+/// pending; merge it once the server accepted it.
+pub fn self_update_with_new_signer(
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    old_signer: &SignatureKeyPair,
+    new_signer: &SignatureKeyPair,
+    credential_with_key: CredentialWithKey,
+) -> Result<MlsMessageOut> {
+    let bundle = group
+        .self_update_with_new_signer(
+            provider,
+            old_signer,
+            NewSignerBundle {
+                signer: new_signer,
+                credential_with_key,
+            },
+            LeafNodeParameters::default(),
+        )
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+    Ok(bundle.into_commit())
+}
+
 /// Process a Welcome message to join a group (for new members)
 /// The welcome_message is the encrypted Welcome message received from the group organizer
 pub fn process_welcome_message(
@@ -136,22 +524,226 @@ pub fn process_welcome_message(
     welcome_message: &MlsMessageIn,
     ratchet_tree: Option<RatchetTreeIn>,
 ) -> Result<MlsGroup> {
+    let staged_join = stage_welcome_message(provider, config, welcome_message, ratchet_tree)?;
+
+    // Convert the staged join into a group
+    let group = staged_join
+        .into_group(provider)
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+
+    Ok(group)
+}
+
+/// Stage a Welcome message without joining yet, so the group it describes
+/// can be inspected first (see `validate_ratchet_tree`)
+pub fn stage_welcome_message(
+    provider: &impl OpenMlsProvider,
+    config: &MlsGroupJoinConfig,
+    welcome_message: &MlsMessageIn,
+    ratchet_tree: Option<RatchetTreeIn>,
+) -> Result<StagedWelcome> {
     // Extract Welcome from the incoming message
     let welcome = match welcome_message.clone().extract() {
         MlsMessageBodyIn::Welcome(w) => w,
         _ => return Err(MlsError::OpenMls("Expected Welcome message".to_string()).into()),
     };
 
-    // Create a staged join from the welcome message
-    let staged_join = StagedWelcome::new_from_welcome(provider, config, welcome, ratchet_tree)
-        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+    StagedWelcome::new_from_welcome(provider, config, welcome, ratchet_tree)
+        .map_err(|e| MlsError::OpenMls(e.to_string()).into())
+}
 
-    // Convert the staged join into a group
-    let group = staged_join
-        .into_group(provider)
-        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+/// What a Welcome says about its group, read without joining it
+#[derive(Debug, Clone)]
+pub struct WelcomePreview {
+    pub group_id: GroupId,
+    /// Group metadata extension, `None` if the group has none
+    pub metadata: Option<crate::extensions::GroupMetadata>,
+    /// Identities of the members in the ratchet tree, in leaf order
+    pub members: Vec<String>,
+}
 
-    Ok(group)
+/// Decrypt a Welcome far enough to describe its group, leaving it joinable
+///
+/// Staging a Welcome consumes the KeyPackage it was encrypted to. The
+/// KeyPackage is therefore read beforehand and stored again if staging
+/// removed it, so a later `process_welcome_message` still succeeds. No group
+/// state is written.
+///
+/// # Errors
+/// * `MlsError::OpenMls` if the Welcome cannot be decrypted, e.g. because
+///   its KeyPackage is no longer stored
+pub fn preview_welcome(
+    provider: &impl OpenMlsProvider,
+    welcome_message: &MlsMessageIn,
+    ratchet_tree: Option<RatchetTreeIn>,
+) -> Result<WelcomePreview> {
+    use openmls_traits::storage::StorageProvider as _;
+
+    let MlsMessageBodyIn::Welcome(welcome) = welcome_message.clone().extract() else {
+        return Err(MlsError::OpenMls("Expected Welcome message".to_string()).into());
+    };
+    let read = |hash_ref: &KeyPackageRef| -> Result<Option<KeyPackageBundle>> {
+        provider
+            .storage()
+            .key_package(hash_ref)
+            .map_err(|e| MlsError::OpenMls(format!("Failed to read KeyPackage: {:?}", e)).into())
+    };
+    let mut bundles = Vec::new();
+    for secrets in welcome.secrets() {
+        let hash_ref = secrets.new_member();
+        if let Some(bundle) = read(&hash_ref)? {
+            bundles.push((hash_ref, bundle));
+        }
+    }
+
+    let staged = StagedWelcome::new_from_welcome(
+        provider,
+        &MlsGroupJoinConfig::default(),
+        welcome,
+        ratchet_tree,
+    )
+    .map_err(|e| MlsError::OpenMls(e.to_string()));
+
+    for (hash_ref, bundle) in bundles {
+        if read(&hash_ref)?.is_none() {
+            provider
+                .storage()
+                .write_key_package(&hash_ref, &bundle)
+                .map_err(|e| MlsError::OpenMls(format!("Failed to restore KeyPackage: {:?}", e)))?;
+        }
+    }
+
+    let staged = staged?;
+    let context = staged.group_context();
+    let metadata = context
+        .extensions()
+        .unknown(crate::extensions::GROUP_METADATA_EXTENSION_TYPE)
+        .map(|ext| crate::extensions::GroupMetadata::from_bytes(&ext.0))
+        .transpose()
+        .map_err(|e| MlsError::OpenMls(format!("Failed to parse group metadata: {}", e)))?;
+    Ok(WelcomePreview {
+        group_id: context.group_id().clone(),
+        metadata,
+        members: staged
+            .members()
+            .filter_map(|member| credential_identity(&member.credential))
+            .collect(),
+    })
+}
+
+/// Ciphersuite of a serialized KeyPackage or Welcome
+///
+/// Accepts a Welcome or KeyPackage framed as an `MlsMessage`, as sent in
+/// envelopes, and a bare KeyPackage, as the server stores them. Only the
+/// structure is parsed: nothing is decrypted and no signature is checked,
+/// so this works on input this client could never join with.
+///
+/// # Errors
+/// * `MlsError::MalformedMessage` if `bytes` is neither a KeyPackage nor a
+///   Welcome, or uses a ciphersuite OpenMLS does not know
+pub fn inspect_ciphersuite(bytes: &[u8]) -> Result<Ciphersuite> {
+    use tls_codec::Deserialize as _;
+
+    // A KeyPackage starts with its protocol version (u16), then the suite;
+    // a Welcome starts with the suite
+    let suite_at = |offset: usize| -> Result<Ciphersuite> {
+        let raw = bytes
+            .get(offset..offset + 2)
+            .ok_or_else(|| MlsError::MalformedMessage("truncated message".to_string()))?;
+        let value = u16::from_be_bytes([raw[0], raw[1]]);
+        Ciphersuite::try_from(value).map_err(|_| {
+            MlsError::MalformedMessage(format!("unknown ciphersuite 0x{:04x}", value)).into()
+        })
+    };
+
+    if let Ok(message) = crate::envelope::decode_mls_message(bytes) {
+        return match message.extract() {
+            // After the MlsMessage version and wire format (u16 each)
+            MlsMessageBodyIn::Welcome(_) => suite_at(4),
+            MlsMessageBodyIn::KeyPackage(_) => suite_at(4 + 2),
+            _ => Err(MlsError::MalformedMessage(
+                "MLS message is neither a KeyPackage nor a Welcome".to_string(),
+            )
+            .into()),
+        };
+    }
+    openmls::key_packages::KeyPackageIn::tls_deserialize_exact(bytes).map_err(|e| {
+        MlsError::MalformedMessage(format!("neither a KeyPackage nor a Welcome: {}", e))
+    })?;
+    suite_at(2)
+}
+
+/// Hint for an error message when `bytes` (a KeyPackage or Welcome) uses a
+/// ciphersuite other than `ours`; empty if it matches or cannot be told
+pub fn ciphersuite_mismatch_hint(bytes: &[u8], ours: Ciphersuite) -> String {
+    match inspect_ciphersuite(bytes) {
+        Ok(theirs) if theirs != ours => format!(
+            " (it uses ciphersuite {:?}, this client uses {:?})",
+            theirs, ours
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Check the leaves of a ratchet tree against the members a joiner expects
+///
+/// Every leaf must carry a well-formed credential bound to its signature key
+/// and, for X.509, issued by one of `anchors` (see `member_identity`), no
+/// identity may hold more than one leaf, each of
+/// `expected_members` must hold one, and every other leaf must belong to
+/// someone in `roster`, the group's members as recorded outside the Welcome
+/// (the server records an invitee when its KeyPackage is reserved, before
+/// the Commit adding it is sent). OpenMLS already checks that the tree
+/// matches the Welcome's tree hash; this catches a phantom leaf the inviter
+/// put in both, whether under a new identity or as a second leaf claiming
+/// an existing member's identity with a key that member does not own.
+pub fn validate_ratchet_tree(
+    tree: &[Member],
+    expected_members: &[String],
+    roster: &[String],
+    anchors: &TrustAnchors,
+) -> Result<()> {
+    let mut identities = std::collections::BTreeSet::new();
+    for leaf in tree {
+        let identity =
+            member_identity(&leaf.credential, &leaf.signature_key, anchors).ok_or_else(|| {
+                MlsError::InvalidRatchetTree(format!(
+                    "leaf {} has a malformed or untrusted credential",
+                    leaf.index.u32()
+                ))
+            })?;
+        if !expected_members.contains(&identity) && !roster.contains(&identity) {
+            return Err(MlsError::InvalidRatchetTree(format!(
+                "'{}' (leaf {}) is not in the group's roster",
+                identity,
+                leaf.index.u32()
+            ))
+            .into());
+        }
+        if !identities.insert(identity.clone()) {
+            return Err(MlsError::InvalidRatchetTree(format!(
+                "'{}' holds more than one leaf (leaf {})",
+                identity,
+                leaf.index.u32()
+            ))
+            .into());
+        }
+    }
+
+    let missing: Vec<&str> = expected_members
+        .iter()
+        .filter(|member| !identities.contains(*member))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(MlsError::InvalidRatchetTree(format!(
+            "expected members missing: {}",
+            missing.join(", ")
+        ))
+        .into());
+    }
+
+    Ok(())
 }
 
 /// Merge pending commit after adding members
@@ -163,11 +755,189 @@ pub fn merge_pending_commit(group: &mut MlsGroup, provider: &impl OpenMlsProvide
     Ok(())
 }
 
+/// Discard the pending commit, e.g. after the server refused to sequence it
+pub fn clear_pending_commit(group: &mut MlsGroup, provider: &impl OpenMlsProvider) -> Result<()> {
+    group
+        .clear_pending_commit(provider.storage())
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+
+    Ok(())
+}
+
 /// Export ratchet tree for new members
 pub fn export_ratchet_tree(group: &MlsGroup) -> RatchetTreeIn {
     group.export_ratchet_tree().into()
 }
 
+/// Whether a GroupInfo of `group` would reveal only public group state
+///
+/// A published GroupInfo is signed but not encrypted, and it carries the group
+/// context extensions. Application-defined extensions (such as the
+/// `GroupMetadata` with the group name and admins) are for members only.
+pub fn group_info_is_public(group: &MlsGroup) -> bool {
+    group
+        .extensions()
+        .iter()
+        .all(|extension| !matches!(extension, Extension::Unknown(..)))
+}
+
+/// Export the current GroupInfo, with the ratchet tree, for external joiners
+///
+/// Returns `None` for groups whose GroupInfo is not public (see
+/// [`group_info_is_public`]).
+pub fn export_public_group_info(
+    group: &MlsGroup,
+    provider: &impl OpenMlsProvider,
+    signer: &SignatureKeyPair,
+) -> Result<Option<MlsMessageOut>> {
+    if !group_info_is_public(group) {
+        return Ok(None);
+    }
+    let group_info = group
+        .export_group_info(provider.crypto(), signer, true)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to export GroupInfo: {}", e)))?;
+    Ok(Some(group_info))
+}
+
+/// The GroupInfo carried by `message`, if it is a GroupInfo message
+///
+/// A Commit never contains one: OpenMLS returns the GroupInfo of the new epoch
+/// next to the Commit, and [`export_public_group_info`] builds it on demand.
+/// Commits and all other messages yield `None`.
+pub fn extract_group_info(message: &MlsMessageOut) -> Option<VerifiableGroupInfo> {
+    use tls_codec::{Deserialize, Serialize};
+
+    let bytes = message.tls_serialize_detached().ok()?;
+    match MlsMessageIn::tls_deserialize(&mut bytes.as_slice())
+        .ok()?
+        .extract()
+    {
+        MlsMessageBodyIn::GroupInfo(group_info) => Some(group_info),
+        _ => None,
+    }
+}
+
+/// Exporter label for attachment keys; the group id and the attachment's
+/// nonce go into the exporter context
+const ATTACHMENT_EXPORTER_LABEL: &str = "mls-chat attachment";
+
+/// Length of the random nonce drawn for each attachment
+const ATTACHMENT_NONCE_LEN: usize = 32;
+
+/// An attachment encrypted under a key exported from its group's epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedAttachment {
+    /// Epoch whose exporter secret the key was derived from
+    pub epoch: u64,
+    /// Random per-attachment nonce mixed into the key derivation
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Exporter context binding an attachment key to its group and nonce:
+/// the group id (u16 length-prefixed) followed by the nonce
+fn attachment_context(group: &MlsGroup, nonce: &[u8]) -> Vec<u8> {
+    let group_id = group.group_id().as_slice();
+    let mut context = Vec::with_capacity(2 + group_id.len() + nonce.len());
+    context.extend_from_slice(&(group_id.len() as u16).to_be_bytes());
+    context.extend_from_slice(group_id);
+    context.extend_from_slice(nonce);
+    context
+}
+
+/// Derive the AEAD key and nonce of one attachment with `export_secret`
+///
+/// The exporter secret changes every epoch, and the context holds the
+/// group id and attachment nonce, so every attachment gets its own key that
+/// no other group or epoch derives.
+fn attachment_key(
+    group: &MlsGroup,
+    provider: &impl OpenMlsProvider,
+    nonce: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let ciphersuite = group.ciphersuite();
+    let key_len = ciphersuite.aead_key_length();
+    let mut secret = group
+        .export_secret(
+            provider.crypto(),
+            ATTACHMENT_EXPORTER_LABEL,
+            &attachment_context(group, nonce),
+            key_len + ciphersuite.aead_nonce_length(),
+        )
+        .map_err(|e| MlsError::OpenMls(format!("Failed to export attachment key: {}", e)))?;
+    let aead_nonce = secret.split_off(key_len);
+    Ok((secret, aead_nonce))
+}
+
+/// Additional data authenticated with an attachment: its exporter context
+/// and epoch
+fn attachment_aad(group: &MlsGroup, nonce: &[u8], epoch: u64) -> Vec<u8> {
+    let mut aad = attachment_context(group, nonce);
+    aad.extend_from_slice(&epoch.to_be_bytes());
+    aad
+}
+
+/// Encrypt an attachment for the members of `group` at its current epoch
+pub fn encrypt_attachment(
+    group: &MlsGroup,
+    provider: &impl OpenMlsProvider,
+    data: &[u8],
+) -> Result<EncryptedAttachment> {
+    use openmls_traits::crypto::OpenMlsCrypto;
+    use openmls_traits::random::OpenMlsRand;
+
+    let nonce = provider
+        .rand()
+        .random_vec(ATTACHMENT_NONCE_LEN)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to draw attachment nonce: {:?}", e)))?;
+    let epoch = group.epoch().as_u64();
+    let (key, aead_nonce) = attachment_key(group, provider, &nonce)?;
+    let ciphertext = provider
+        .crypto()
+        .aead_encrypt(
+            group.ciphersuite().aead_algorithm(),
+            &key,
+            data,
+            &aead_nonce,
+            &attachment_aad(group, &nonce, epoch),
+        )
+        .map_err(|_| MlsError::EncryptionFailed)?;
+
+    Ok(EncryptedAttachment {
+        epoch,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Decrypt an attachment encrypted for `group`
+///
+/// Fails with `MlsError::DecryptionFailed` if the group has moved to
+/// another epoch since, or if the attachment belongs to another group.
+pub fn decrypt_attachment(
+    group: &MlsGroup,
+    provider: &impl OpenMlsProvider,
+    attachment: &EncryptedAttachment,
+) -> Result<Vec<u8>> {
+    use openmls_traits::crypto::OpenMlsCrypto;
+
+    if attachment.epoch != group.epoch().as_u64() {
+        return Err(MlsError::DecryptionFailed.into());
+    }
+    let (key, aead_nonce) = attachment_key(group, provider, &attachment.nonce)?;
+    let plaintext = provider
+        .crypto()
+        .aead_decrypt(
+            group.ciphersuite().aead_algorithm(),
+            &key,
+            &attachment.ciphertext,
+            &aead_nonce,
+            &attachment_aad(group, &attachment.nonce, attachment.epoch),
+        )
+        .map_err(|_| MlsError::DecryptionFailed)?;
+    Ok(plaintext)
+}
+
 /// Load an MLS group from storage by its group ID
 ///
 /// This retrieves a previously created and persisted MLS group from the storage provider.
@@ -206,6 +976,51 @@ pub fn extract_group_metadata(
     }
 }
 
+/// Replace the group metadata with a Commit (GroupContextExtensions proposal)
+///
+/// The Commit is left pending; the caller merges it and broadcasts it.
+///
+/// Every member's leaf must advertise the metadata extension (see
+/// [`capabilities_for`]); OpenMLS rejects the proposal otherwise.
+pub fn update_group_metadata(
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    signer: &SignatureKeyPair,
+    metadata: &crate::extensions::GroupMetadata,
+) -> Result<MlsMessageOut> {
+    let metadata_type = ExtensionType::Unknown(crate::extensions::GROUP_METADATA_EXTENSION_TYPE);
+    let metadata_bytes = metadata
+        .to_bytes()
+        .map_err(|e| MlsError::OpenMls(format!("Failed to serialize group metadata: {}", e)))?;
+
+    // The proposal replaces all group context extensions, so keep the others.
+    // Non-default extensions in a proposal must be required capabilities.
+    let mut extensions: Vec<Extension> = group
+        .extensions()
+        .iter()
+        .filter(|extension| {
+            let extension_type = extension.extension_type();
+            extension_type != ExtensionType::RequiredCapabilities && extension_type != metadata_type
+        })
+        .cloned()
+        .collect();
+    extensions.push(Extension::RequiredCapabilities(
+        metadata_required_capabilities(group.extensions().required_capabilities()),
+    ));
+    extensions.push(Extension::Unknown(
+        crate::extensions::GROUP_METADATA_EXTENSION_TYPE,
+        UnknownExtension(metadata_bytes),
+    ));
+    let extensions =
+        Extensions::from_vec(extensions).map_err(|e| MlsError::OpenMls(e.to_string()))?;
+
+    let (commit, _welcome, _group_info) = group
+        .update_group_context_extensions(provider, extensions, signer)
+        .map_err(|e| MlsError::OpenMls(e.to_string()))?;
+
+    Ok(commit)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,6 +1053,61 @@ mod tests {
         assert_eq!(metadata.unwrap().name, "testgroup");
     }
 
+    #[test]
+    fn test_past_epoch_secrets_are_kept_only_when_asked() {
+        // A message from the epoch before a Commit, delivered after it
+        let late_message_decrypts = |past_epochs: usize| {
+            let provider = &OpenMlsRustCrypto::default();
+            let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+            let mut alice_group =
+                create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+            let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+            let bob_key_package =
+                generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+            let (_commit, welcome, _group_info) = add_members(
+                &mut alice_group,
+                provider,
+                &alice_key,
+                &[bob_key_package.key_package()],
+            )
+            .unwrap();
+            merge_pending_commit(&mut alice_group, provider).unwrap();
+            let welcome_in = MlsMessageIn::tls_deserialize(
+                &mut welcome.tls_serialize_detached().unwrap().as_slice(),
+            )
+            .unwrap();
+            let mut bob_group = process_welcome_message(
+                provider,
+                &join_config(past_epochs),
+                &welcome_in,
+                Some(export_ratchet_tree(&alice_group)),
+            )
+            .unwrap();
+
+            let late = create_application_message(&mut alice_group, provider, &alice_key, b"late")
+                .unwrap();
+            let commit = self_update(&mut alice_group, provider, &alice_key).unwrap();
+            merge_pending_commit(&mut alice_group, provider).unwrap();
+            let transport = |message: MlsMessageOut| {
+                MlsMessageIn::tls_deserialize(
+                    &mut message.tls_serialize_detached().unwrap().as_slice(),
+                )
+                .unwrap()
+            };
+            let processed = process_message(&mut bob_group, provider, &transport(commit)).unwrap();
+            let ProcessedMessageContent::StagedCommitMessage(staged) = processed.into_content()
+            else {
+                panic!("Expected a Commit");
+            };
+            bob_group.merge_staged_commit(provider, *staged).unwrap();
+
+            process_message(&mut bob_group, provider, &transport(late)).is_ok()
+        };
+
+        assert!(!late_message_decrypts(0));
+        assert!(late_message_decrypts(STRICT_SENDERS_PAST_EPOCHS));
+    }
+
     #[test]
     fn test_create_and_process_application_message() {
         let provider = &OpenMlsRustCrypto::default();
@@ -251,85 +1121,513 @@ mod tests {
         let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
         let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
 
-        // Alice adds Bob to the group
-        let (_commit, welcome_message, _group_info) = add_members(
+        // Alice adds Bob to the group
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+
+        // Alice merges the pending commit
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        // Bob joins the group via Welcome
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let join_config = MlsGroupJoinConfig::default();
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let mut bob_group =
+            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
+
+        // Alice sends a message
+        let plaintext = b"Hello from Alice!";
+        let encrypted =
+            create_application_message(&mut alice_group, provider, &alice_key, plaintext).unwrap();
+
+        // Serialize the message for transport
+        let serialized = encrypted.tls_serialize_detached().unwrap();
+        let deserialized = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+
+        // Bob processes Alice's message
+        let processed = process_message(&mut bob_group, provider, &deserialized).unwrap();
+
+        // Verify Bob received an application message
+        match processed.content() {
+            ProcessedMessageContent::ApplicationMessage(_app_msg) => {
+                // ApplicationMessage received successfully by Bob
+            }
+            _ => panic!("Expected application message"),
+        }
+    }
+
+    #[test]
+    fn test_process_envelope_content_application_message() {
+        let provider = &OpenMlsRustCrypto::default();
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let mut bob_group = process_welcome_message(
+            provider,
+            &MlsGroupJoinConfig::default(),
+            &welcome_in,
+            ratchet_tree,
+        )
+        .unwrap();
+
+        // Alice sends a message, Bob classifies it
+        let encrypted =
+            create_application_message(&mut alice_group, provider, &alice_key, b"hi bob").unwrap();
+        let serialized = encrypted.tls_serialize_detached().unwrap();
+        let message_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+
+        let content = process_envelope_content(
+            &mut bob_group,
+            provider,
+            &message_in,
+            &TrustAnchors::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            content,
+            ProcessedContent::Application {
+                sender_identity: "alice".to_string(),
+                sender_fingerprint: Some(credential_fingerprint(provider, &alice_cred).unwrap()),
+                plaintext: b"hi bob".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_envelope_content_membership_changes() {
+        let provider = &OpenMlsRustCrypto::default();
+        let join_config = MlsGroupJoinConfig::default();
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+
+        // Bob joins so that he can observe later commits
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let mut bob_group =
+            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
+
+        // Alice adds Carol; Bob sees carol in the added list
+        let (carol_cred, carol_key) = generate_credential_with_key("carol").unwrap();
+        let carol_key_package =
+            generate_key_package_bundle(&carol_cred, &carol_key, provider).unwrap();
+        let (add_commit, _welcome, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[carol_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let serialized = add_commit.tls_serialize_detached().unwrap();
+        let commit_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let content = process_envelope_content(
+            &mut bob_group,
+            provider,
+            &commit_in,
+            &TrustAnchors::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            content,
+            ProcessedContent::MembershipChange {
+                sender_identity: "alice".to_string(),
+                added: vec!["carol".to_string()],
+                removed: vec![],
+            }
+        );
+        assert_eq!(bob_group.members().count(), 3);
+
+        // Alice removes Carol; Bob sees carol in the removed list
+        let carol_index = alice_group
+            .members()
+            .find(|m| credential_identity(&m.credential).as_deref() == Some("carol"))
+            .unwrap()
+            .index;
+        let (remove_commit, _welcome, _group_info) = alice_group
+            .remove_members(provider, &alice_key, &[carol_index])
+            .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let serialized = remove_commit.tls_serialize_detached().unwrap();
+        let commit_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let content = process_envelope_content(
+            &mut bob_group,
+            provider,
+            &commit_in,
+            &TrustAnchors::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            content,
+            ProcessedContent::MembershipChange {
+                sender_identity: "alice".to_string(),
+                added: vec![],
+                removed: vec!["carol".to_string()],
+            }
+        );
+        assert_eq!(bob_group.members().count(), 2);
+    }
+
+    #[test]
+    fn test_removed_member_cannot_decrypt_new_epoch() {
+        let alice_provider = &OpenMlsRustCrypto::default();
+        let carol_provider = &OpenMlsRustCrypto::default();
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, alice_provider, "testgroup").unwrap();
+
+        let (carol_cred, carol_key) = generate_credential_with_key("carol").unwrap();
+        let carol_key_package =
+            generate_key_package_bundle(&carol_cred, &carol_key, carol_provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            alice_provider,
+            &alice_key,
+            &[carol_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, alice_provider).unwrap();
+
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let mut carol_group = process_welcome_message(
+            carol_provider,
+            &MlsGroupJoinConfig::default(),
+            &welcome_in,
+            ratchet_tree,
+        )
+        .unwrap();
+
+        // Alice removes Carol; Carol processes the Commit and sees herself go
+        let carol_index = alice_group
+            .members()
+            .find(|m| credential_identity(&m.credential).as_deref() == Some("carol"))
+            .unwrap()
+            .index;
+        let remove_commit =
+            remove_members(&mut alice_group, alice_provider, &alice_key, &[carol_index]).unwrap();
+        merge_pending_commit(&mut alice_group, alice_provider).unwrap();
+
+        let serialized = remove_commit.tls_serialize_detached().unwrap();
+        let commit_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let content = process_envelope_content(
+            &mut carol_group,
+            carol_provider,
+            &commit_in,
+            &TrustAnchors::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            content,
+            ProcessedContent::MembershipChange {
+                sender_identity: "alice".to_string(),
+                added: vec![],
+                removed: vec!["carol".to_string()],
+            }
+        );
+        assert!(!carol_group.is_active());
+
+        // A message of the new epoch is out of Carol's reach
+        let message =
+            create_application_message(&mut alice_group, alice_provider, &alice_key, b"secret")
+                .unwrap();
+        let serialized = message.tls_serialize_detached().unwrap();
+        let message_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        assert!(process_message(&mut carol_group, carol_provider, &message_in).is_err());
+    }
+
+    #[test]
+    fn test_add_member_flow() {
+        let provider = &OpenMlsRustCrypto::default();
+
+        // Alice creates group
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+
+        // Bob generates key package
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+
+        // Alice adds Bob
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+
+        // Alice merges the pending commit
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        // Bob processes Welcome message (with ratchet tree from Alice's group)
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let join_config = MlsGroupJoinConfig::default();
+        // Serialize and deserialize the welcome message to convert MlsMessageOut to MlsMessageIn
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let bob_group =
+            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
+
+        assert_eq!(
+            bob_group.group_id().as_slice(),
+            alice_group.group_id().as_slice()
+        );
+    }
+
+    #[test]
+    fn test_inspect_ciphersuite_of_key_packages_and_welcomes() {
+        let provider = &OpenMlsRustCrypto::default();
+        let ours = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+
+        // Bare, as stored on the server, and framed as an MlsMessage
+        let key_package = bob_key_package.key_package().clone();
+        let bare = key_package.tls_serialize_detached().unwrap();
+        assert_eq!(inspect_ciphersuite(&bare).unwrap(), ours);
+        let framed = MlsMessageOut::from(key_package)
+            .tls_serialize_detached()
+            .unwrap();
+        assert_eq!(inspect_ciphersuite(&framed).unwrap(), ours);
+
+        let (commit, welcome, _group_info) = add_members(
             &mut alice_group,
             provider,
             &alice_key,
             &[bob_key_package.key_package()],
         )
         .unwrap();
+        let welcome = welcome.tls_serialize_detached().unwrap();
+        assert_eq!(inspect_ciphersuite(&welcome).unwrap(), ours);
+        assert_eq!(ciphersuite_mismatch_hint(&welcome, ours), "");
+        let other = Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+        assert!(ciphersuite_mismatch_hint(&welcome, other)
+            .contains("MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519"));
+
+        // A Commit is an MLS message, but not one that names a ciphersuite
+        let commit = commit.tls_serialize_detached().unwrap();
+        assert!(matches!(
+            inspect_ciphersuite(&commit),
+            Err(crate::error::ClientError::Mls(MlsError::MalformedMessage(
+                _
+            )))
+        ));
+        for garbage in [&[][..], &[0, 1][..], &bare[..bare.len() - 1], b"not mls"] {
+            assert!(matches!(
+                inspect_ciphersuite(garbage),
+                Err(crate::error::ClientError::Mls(MlsError::MalformedMessage(
+                    _
+                )))
+            ));
+        }
+        assert_eq!(ciphersuite_mismatch_hint(b"not mls", ours), "");
+    }
 
-        // Alice merges the pending commit
+    /// Alice's group with Bob and `also_added` added in one Commit, and
+    /// Bob's staged join of it from the real Welcome and tree
+    fn stage_welcome_to_bob(
+        provider: &OpenMlsRustCrypto,
+        also_added: &[&str],
+    ) -> (StagedWelcome, CredentialWithKey) {
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let mut key_packages =
+            vec![generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap()];
+        for name in also_added {
+            let (cred, key) = generate_credential_with_key(name).unwrap();
+            key_packages.push(generate_key_package_bundle(&cred, &key, provider).unwrap());
+        }
+        let key_packages: Vec<&KeyPackage> = key_packages
+            .iter()
+            .map(|bundle| bundle.key_package())
+            .collect();
+        let (_commit, welcome, _group_info) =
+            add_members(&mut alice_group, provider, &alice_key, &key_packages).unwrap();
         merge_pending_commit(&mut alice_group, provider).unwrap();
 
-        // Bob joins the group via Welcome
-        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
-        let join_config = MlsGroupJoinConfig::default();
-        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let serialized = welcome.tls_serialize_detached().unwrap();
         let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
-        let mut bob_group =
-            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
-
-        // Alice sends a message
-        let plaintext = b"Hello from Alice!";
-        let encrypted =
-            create_application_message(&mut alice_group, provider, &alice_key, plaintext).unwrap();
+        let staged = stage_welcome_message(
+            provider,
+            &MlsGroupJoinConfig::default(),
+            &welcome_in,
+            Some(export_ratchet_tree(&alice_group)),
+        )
+        .unwrap();
+        (staged, bob_cred)
+    }
 
-        // Serialize the message for transport
-        let serialized = encrypted.tls_serialize_detached().unwrap();
-        let deserialized = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
 
-        // Bob processes Alice's message
-        let processed = process_message(&mut bob_group, provider, &deserialized).unwrap();
+    #[test]
+    fn test_validate_ratchet_tree_accepts_expected_members() {
+        let provider = &OpenMlsRustCrypto::default();
+        let (staged, _) = stage_welcome_to_bob(provider, &[]);
+        let tree: Vec<Member> = staged.members().collect();
+        validate_ratchet_tree(
+            &tree,
+            &names(&["bob", "alice"]),
+            &names(&["alice"]),
+            &TrustAnchors::default(),
+        )
+        .unwrap();
 
-        // Verify Bob received an application message
-        match processed.content() {
-            ProcessedMessageContent::ApplicationMessage(_app_msg) => {
-                // ApplicationMessage received successfully by Bob
-            }
-            _ => panic!("Expected application message"),
-        }
+        // Another invitee of the same Commit is on the roster already
+        let (staged, _) = stage_welcome_to_bob(provider, &["carol"]);
+        let tree: Vec<Member> = staged.members().collect();
+        validate_ratchet_tree(
+            &tree,
+            &names(&["bob", "alice"]),
+            &names(&["alice", "bob", "carol"]),
+            &TrustAnchors::default(),
+        )
+        .unwrap();
     }
 
     #[test]
-    fn test_add_member_flow() {
+    fn test_validate_ratchet_tree_rejects_phantom_leaf() {
         let provider = &OpenMlsRustCrypto::default();
 
-        // Alice creates group
+        // The inviter slipped Mallory into the Commit that adds Bob, without
+        // reserving her KeyPackage, so the roster does not list her
+        let (staged, bob_cred) = stage_welcome_to_bob(provider, &["mallory"]);
+        let mut tree: Vec<Member> = staged.members().collect();
+        let result = validate_ratchet_tree(
+            &tree,
+            &names(&["bob", "alice"]),
+            &names(&["alice", "bob"]),
+            &TrustAnchors::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Mls(MlsError::InvalidRatchetTree(msg))) if msg.contains("mallory")
+        ));
+
+        // A second leaf claiming Bob's identity under a key Bob does not own
+        let (_, mallory_key) = generate_credential_with_key("mallory").unwrap();
+        tree.push(Member::new(
+            LeafNodeIndex::new(tree.len() as u32),
+            Vec::new(),
+            mallory_key.public().to_vec(),
+            bob_cred.credential,
+        ));
+        let result = validate_ratchet_tree(
+            &tree,
+            &names(&["bob", "alice"]),
+            &names(&["alice", "bob", "mallory"]),
+            &TrustAnchors::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Mls(MlsError::InvalidRatchetTree(msg))) if msg.contains("more than one leaf")
+        ));
+
+        // A member the joiner expects but the tree lacks is reported too
+        let (staged, _) = stage_welcome_to_bob(provider, &[]);
+        let tree: Vec<Member> = staged.members().collect();
+        let result = validate_ratchet_tree(
+            &tree,
+            &names(&["alice", "carol"]),
+            &names(&["alice", "bob", "carol"]),
+            &TrustAnchors::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Mls(MlsError::InvalidRatchetTree(msg))) if msg.contains("carol")
+        ));
+    }
+
+    #[test]
+    fn test_staging_rejects_a_tree_that_does_not_match_the_welcome() {
+        let provider = &OpenMlsRustCrypto::default();
         let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
         let mut alice_group =
             create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
-
-        // Bob generates key package
         let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
         let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
-
-        // Alice adds Bob
-        let (_commit, welcome_message, _group_info) = add_members(
+        let (_commit, welcome, _group_info) = add_members(
             &mut alice_group,
             provider,
             &alice_key,
             &[bob_key_package.key_package()],
         )
         .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
 
-        // Alice merges the pending commit
+        // The tree sent with Bob's Welcome has an extra leaf for Mallory
+        let (mallory_cred, mallory_key) = generate_credential_with_key("mallory").unwrap();
+        let mallory_key_package =
+            generate_key_package_bundle(&mallory_cred, &mallory_key, provider).unwrap();
+        add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[mallory_key_package.key_package()],
+        )
+        .unwrap();
         merge_pending_commit(&mut alice_group, provider).unwrap();
+        let tampered = export_ratchet_tree(&alice_group);
 
-        // Bob processes Welcome message (with ratchet tree from Alice's group)
-        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
-        let join_config = MlsGroupJoinConfig::default();
-        // Serialize and deserialize the welcome message to convert MlsMessageOut to MlsMessageIn
-        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let serialized = welcome.tls_serialize_detached().unwrap();
         let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
-        let bob_group =
-            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
-
-        assert_eq!(
-            bob_group.group_id().as_slice(),
-            alice_group.group_id().as_slice()
+        let result = stage_welcome_message(
+            provider,
+            &MlsGroupJoinConfig::default(),
+            &welcome_in,
+            Some(tampered),
         );
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Mls(MlsError::OpenMls(_)))
+        ));
     }
 
     #[test]
@@ -848,4 +2146,443 @@ mod tests {
             "Epoch should advance with member addition in session 2"
         );
     }
+
+    #[test]
+    fn test_x509_group_members_have_certificate_identities() {
+        let provider = &OpenMlsRustCrypto::default();
+        let join_config = MlsGroupJoinConfig::default();
+
+        let (alice_cred, alice_key) = generate_x509_credential_with_key("alice").unwrap();
+        assert_eq!(
+            alice_cred.credential.credential_type(),
+            CredentialType::X509
+        );
+        assert_eq!(
+            credential_identity(&alice_cred.credential).as_deref(),
+            Some("alice")
+        );
+
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "pkigroup").unwrap();
+
+        let (bob_cred, bob_key) = generate_x509_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let ratchet_tree = Some(export_ratchet_tree(&alice_group));
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let bob_group =
+            process_welcome_message(provider, &join_config, &welcome_in, ratchet_tree).unwrap();
+
+        let members: Vec<String> = bob_group
+            .members()
+            .filter_map(|m| {
+                member_identity(&m.credential, &m.signature_key, &TrustAnchors::default())
+            })
+            .collect();
+        assert_eq!(members, vec!["alice".to_string(), "bob".to_string()]);
+    }
+
+    fn issued_credential(
+        ca: &crate::x509::TestCa,
+        username: &str,
+    ) -> (CredentialWithKey, SignatureKeyPair) {
+        let (chain_pem, key_pem) = ca.issue(username);
+        let (chain, _, signer) =
+            crate::x509::load_issued_identity(chain_pem.as_bytes(), &key_pem).unwrap();
+        let credential = CredentialWithKey {
+            credential: crate::x509::credential_from_chain(&chain).unwrap(),
+            signature_key: signer.to_public_vec().into(),
+        };
+        (credential, signer)
+    }
+
+    #[test]
+    fn test_trust_anchors_reject_members_from_other_issuers() {
+        let ca = crate::x509::TestCa::new("Example CA");
+        let anchors = ca.anchors();
+        let alice_provider = &OpenMlsRustCrypto::default();
+        let bob_provider = &OpenMlsRustCrypto::default();
+        let mallory_provider = &OpenMlsRustCrypto::default();
+        let join_config = MlsGroupJoinConfig::default();
+
+        let (alice_cred, alice_key) = issued_credential(&ca, "alice");
+        let (bob_cred, bob_key) = issued_credential(&ca, "bob");
+        // Self-signed, so not issued by the configured CA
+        let (mallory_cred, mallory_key) = generate_x509_credential_with_key("mallory").unwrap();
+
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, alice_provider, "pkigroup").unwrap();
+        let bob_key_package =
+            generate_key_package_bundle(&bob_cred, &bob_key, bob_provider).unwrap();
+        let mallory_key_package =
+            generate_key_package_bundle(&mallory_cred, &mallory_key, mallory_provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            alice_provider,
+            &alice_key,
+            &[
+                bob_key_package.key_package(),
+                mallory_key_package.key_package(),
+            ],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, alice_provider).unwrap();
+
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let join = |provider: &OpenMlsRustCrypto| {
+            let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+            let tree = Some(export_ratchet_tree(&alice_group));
+            process_welcome_message(provider, &join_config, &welcome_in, tree).unwrap()
+        };
+        let mut bob_group = join(bob_provider);
+        let mut mallory_group = join(mallory_provider);
+
+        // Mallory's leaf has no identity under the anchors
+        let members: Vec<String> = bob_group
+            .members()
+            .filter_map(|m| member_identity(&m.credential, &m.signature_key, &anchors))
+            .collect();
+        assert_eq!(members, names(&["alice", "bob"]));
+
+        let tree: Vec<Member> = bob_group.members().collect();
+        let result = validate_ratchet_tree(
+            &tree,
+            &names(&["bob", "alice"]),
+            &names(&["alice", "bob", "mallory"]),
+            &anchors,
+        );
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Mls(MlsError::InvalidRatchetTree(msg))) if msg.contains("untrusted")
+        ));
+
+        // Messages from a trusted member are accepted, Mallory's are not
+        let from_alice =
+            create_application_message(&mut alice_group, alice_provider, &alice_key, b"hi")
+                .unwrap();
+        let message_in = MlsMessageIn::tls_deserialize(
+            &mut from_alice.tls_serialize_detached().unwrap().as_slice(),
+        )
+        .unwrap();
+        let content =
+            process_envelope_content(&mut bob_group, bob_provider, &message_in, &anchors).unwrap();
+        assert!(matches!(
+            content,
+            ProcessedContent::Application { sender_identity, .. } if sender_identity == "alice"
+        ));
+
+        let from_mallory =
+            create_application_message(&mut mallory_group, mallory_provider, &mallory_key, b"hi")
+                .unwrap();
+        let message_in = MlsMessageIn::tls_deserialize(
+            &mut from_mallory.tls_serialize_detached().unwrap().as_slice(),
+        )
+        .unwrap();
+        assert!(
+            process_envelope_content(&mut bob_group, bob_provider, &message_in, &anchors).is_err()
+        );
+    }
+
+    #[test]
+    fn test_x509_identity_requires_matching_signature_key() {
+        let (alice_cred, alice_key) = generate_x509_credential_with_key("alice").unwrap();
+        let (_other_cred, other_key) = generate_x509_credential_with_key("mallory").unwrap();
+
+        assert_eq!(
+            member_identity(
+                &alice_cred.credential,
+                alice_key.public(),
+                &TrustAnchors::default()
+            )
+            .as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            member_identity(
+                &alice_cred.credential,
+                other_key.public(),
+                &TrustAnchors::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_groups_reject_members_of_the_other_credential_type() {
+        let provider = &OpenMlsRustCrypto::default();
+
+        // Basic group rejects an X.509 member
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut basic_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "basicgroup").unwrap();
+        let (x509_cred, x509_key) = generate_x509_credential_with_key("bob").unwrap();
+        let x509_key_package =
+            generate_key_package_bundle(&x509_cred, &x509_key, provider).unwrap();
+        assert!(add_members(
+            &mut basic_group,
+            provider,
+            &alice_key,
+            &[x509_key_package.key_package()]
+        )
+        .is_err());
+
+        // X.509 group rejects a Basic member
+        let (carol_cred, carol_key) = generate_x509_credential_with_key("carol").unwrap();
+        let mut x509_group =
+            create_group_with_config(&carol_cred, &carol_key, provider, "pkigroup").unwrap();
+        let (basic_cred, basic_key) = generate_credential_with_key("dave").unwrap();
+        let basic_key_package =
+            generate_key_package_bundle(&basic_cred, &basic_key, provider).unwrap();
+        assert!(add_members(
+            &mut x509_group,
+            provider,
+            &carol_key,
+            &[basic_key_package.key_package()]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_group_requires_the_metadata_extension() {
+        let provider = &OpenMlsRustCrypto::default();
+        let metadata_type =
+            ExtensionType::Unknown(crate::extensions::GROUP_METADATA_EXTENSION_TYPE);
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+        let required = alice_group.extensions().required_capabilities().unwrap();
+        assert!(required.extension_types().contains(&metadata_type));
+
+        // A joiner whose leaf does not advertise the extension is refused
+        let (mallory_cred, mallory_key) = generate_credential_with_key("mallory").unwrap();
+        let incapable = KeyPackage::builder()
+            .leaf_node_capabilities(Capabilities::new(
+                None,
+                None,
+                None,
+                None,
+                Some(&[CredentialType::Basic]),
+            ))
+            .build(
+                Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+                provider,
+                &mallory_key,
+                mallory_cred,
+            )
+            .unwrap();
+        assert!(add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[incapable.key_package()]
+        )
+        .is_err());
+
+        // A capable joiner is added and sees the requirement in its context
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let bob_group = process_welcome_message(
+            provider,
+            &MlsGroupJoinConfig::default(),
+            &welcome_in,
+            Some(export_ratchet_tree(&alice_group)),
+        )
+        .unwrap();
+        let required = bob_group.extensions().required_capabilities().unwrap();
+        assert!(required.extension_types().contains(&metadata_type));
+        assert_eq!(
+            extract_group_metadata(&bob_group).unwrap().unwrap().name,
+            "testgroup"
+        );
+
+        // Updating the metadata keeps a single requirement entry
+        let metadata = extract_group_metadata(&alice_group).unwrap().unwrap();
+        update_group_metadata(&mut alice_group, provider, &alice_key, &metadata).unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+        let required = alice_group.extensions().required_capabilities().unwrap();
+        assert_eq!(
+            required
+                .extension_types()
+                .iter()
+                .filter(|t| **t == metadata_type)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_update_group_metadata_reaches_other_members() {
+        let provider = &OpenMlsRustCrypto::default();
+        let join_config = MlsGroupJoinConfig::default();
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let mut alice_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (_commit, welcome_message, _group_info) = add_members(
+            &mut alice_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+        let serialized = welcome_message.tls_serialize_detached().unwrap();
+        let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let mut bob_group = process_welcome_message(
+            provider,
+            &join_config,
+            &welcome_in,
+            Some(export_ratchet_tree(&alice_group)),
+        )
+        .unwrap();
+
+        let mut metadata = extract_group_metadata(&alice_group).unwrap().unwrap();
+        metadata.message_ttl_secs = Some(3600);
+        metadata.version += 1;
+        let commit =
+            update_group_metadata(&mut alice_group, provider, &alice_key, &metadata).unwrap();
+        merge_pending_commit(&mut alice_group, provider).unwrap();
+
+        let serialized = commit.tls_serialize_detached().unwrap();
+        let commit_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+        let content = process_envelope_content(
+            &mut bob_group,
+            provider,
+            &commit_in,
+            &TrustAnchors::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            content,
+            ProcessedContent::MembershipChange { ref added, ref removed, .. }
+                if added.is_empty() && removed.is_empty()
+        ));
+
+        let bob_metadata = extract_group_metadata(&bob_group).unwrap().unwrap();
+        assert_eq!(bob_metadata.name, "testgroup");
+        assert_eq!(bob_metadata.message_ttl_secs, Some(3600));
+        assert_eq!(bob_metadata.version, 2);
+    }
+
+    #[test]
+    fn test_extract_group_info_only_from_public_groups() {
+        let provider = &OpenMlsRustCrypto::default();
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+
+        // Groups created by this client carry the confidential GroupMetadata
+        let mut private_group =
+            create_group_with_config(&alice_cred, &alice_key, provider, "testgroup").unwrap();
+        assert!(!group_info_is_public(&private_group));
+        assert!(
+            export_public_group_info(&private_group, provider, &alice_key)
+                .unwrap()
+                .is_none()
+        );
+
+        // A Commit carries no GroupInfo of its own
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+        let bob_key_package = generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (commit, _welcome, _group_info) = add_members(
+            &mut private_group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        assert!(extract_group_info(&commit).is_none());
+
+        // Without application extensions the GroupInfo can be published
+        let public_group = MlsGroup::new(
+            provider,
+            &alice_key,
+            &MlsGroupCreateConfig::default(),
+            alice_cred.clone(),
+        )
+        .unwrap();
+        assert!(group_info_is_public(&public_group));
+        let message = export_public_group_info(&public_group, provider, &alice_key)
+            .unwrap()
+            .unwrap();
+        let group_info = extract_group_info(&message).unwrap();
+        assert_eq!(group_info.group_id(), public_group.group_id());
+    }
+
+    #[test]
+    fn test_attachment_keys_are_bound_to_their_group() {
+        let provider = &OpenMlsRustCrypto::default();
+        let join_config = MlsGroupJoinConfig::default();
+
+        let (alice_cred, alice_key) = generate_credential_with_key("alice").unwrap();
+        let (bob_cred, bob_key) = generate_credential_with_key("bob").unwrap();
+
+        // Alice and bob share two groups, both at epoch 1
+        let mut groups = Vec::new();
+        for name in ["group-a", "group-b"] {
+            let mut alice_group =
+                create_group_with_config(&alice_cred, &alice_key, provider, name).unwrap();
+            let bob_key_package =
+                generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+            let (_commit, welcome_message, _group_info) = add_members(
+                &mut alice_group,
+                provider,
+                &alice_key,
+                &[bob_key_package.key_package()],
+            )
+            .unwrap();
+            merge_pending_commit(&mut alice_group, provider).unwrap();
+            let serialized = welcome_message.tls_serialize_detached().unwrap();
+            let welcome_in = MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+            let bob_group = process_welcome_message(
+                provider,
+                &join_config,
+                &welcome_in,
+                Some(export_ratchet_tree(&alice_group)),
+            )
+            .unwrap();
+            groups.push((alice_group, bob_group));
+        }
+        let (alice_a, bob_a) = &groups[0];
+        let (alice_b, bob_b) = &groups[1];
+        assert_eq!(alice_a.epoch(), alice_b.epoch());
+
+        let attachment = encrypt_attachment(alice_a, provider, b"holiday.jpg").unwrap();
+        assert_eq!(
+            decrypt_attachment(bob_a, provider, &attachment).unwrap(),
+            b"holiday.jpg"
+        );
+
+        // The same nonce derives a different key in the other group
+        let key_a = attachment_key(alice_a, provider, &attachment.nonce).unwrap();
+        let key_b = attachment_key(alice_b, provider, &attachment.nonce).unwrap();
+        assert_ne!(key_a, key_b);
+        assert!(decrypt_attachment(alice_b, provider, &attachment).is_err());
+        assert!(decrypt_attachment(bob_b, provider, &attachment).is_err());
+
+        // Each attachment gets its own nonce and therefore its own key
+        let second = encrypt_attachment(alice_a, provider, b"holiday.jpg").unwrap();
+        assert_ne!(second.nonce, attachment.nonce);
+        assert_ne!(second.ciphertext, attachment.ciphertext);
+    }
 }
@@ -0,0 +1,293 @@
+//! Bounded decoding of untrusted envelopes
+//!
+//! Everything a client receives over the WebSocket (the JSON or binary envelope, the
+//! base64 blobs inside it and the TLS-encoded MLS messages they carry) comes
+//! from the network. This module is the only place that decodes it, and it
+//! must turn every malformed input into an `Err`:
+//! - inputs larger than `MAX_ENVELOPE_BYTES` are rejected before parsing
+//! - JSON nested deeper than `MAX_JSON_DEPTH` is rejected before serde sees it
+//! - base64 is decoded strictly (truncated or padded-wrong input fails)
+//! - TLS decoding must consume the whole blob, and a panic inside the
+//!   decoder is reported as an error (tls_codec `debug_assert`s on length
+//!   prefixes wider than MLS allows, which would otherwise abort the client)
+//!
+//! The fuzz targets under `fuzz/` exercise these functions directly.
+
+use crate::error::{MlsError, Result};
+use crate::models::MlsMessageEnvelope;
+use base64::{engine::general_purpose, Engine as _};
+use bincode::Options;
+use openmls::prelude::{MlsMessageIn, RatchetTreeIn};
+use serde::de::DeserializeOwned;
+use tls_codec::Deserialize as TlsDeserialize;
+
+/// Upper bound on an envelope or decoded blob, in bytes
+///
+/// Comfortably above a Welcome carrying the ratchet tree of a large group.
+pub const MAX_ENVELOPE_BYTES: usize = 8 * 1024 * 1024;
+
+/// Upper bound on JSON nesting (envelopes are flat; ratchet trees are shallow)
+pub const MAX_JSON_DEPTH: usize = 32;
+
+/// Parse a WebSocket text frame into an envelope
+pub fn parse_envelope(text: &str) -> Result<MlsMessageEnvelope> {
+    parse_json(text.as_bytes())
+}
+
+/// Parse a WebSocket binary frame into an envelope (see `crate::framing`)
+///
+/// bincode is bounded by `MAX_ENVELOPE_BYTES` as well, so a length prefix
+/// claiming more than that fails instead of allocating.
+pub fn parse_binary_envelope(bytes: &[u8]) -> Result<MlsMessageEnvelope> {
+    check_size(bytes.len())?;
+    let wire: crate::framing::BinaryEnvelope = crate::framing::bincode_options()
+        .deserialize(bytes)
+        .map_err(|e| malformed(format!("invalid binary envelope: {}", e)))?;
+    Ok(wire.into())
+}
+
+/// Parse bounded JSON from untrusted input
+pub fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    check_size(bytes.len())?;
+    check_json_depth(bytes)?;
+    serde_json::from_slice(bytes).map_err(|e| malformed(format!("invalid JSON: {}", e)))
+}
+
+/// Decode a base64 blob from an envelope
+pub fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    // Every 4 characters decode to at most 3 bytes
+    check_size(encoded.len() / 4 * 3)?;
+    general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| malformed(format!("invalid base64: {}", e)))
+}
+
+/// Decode a TLS-encoded MLS message, which must span all of `bytes`
+pub fn decode_mls_message(bytes: &[u8]) -> Result<MlsMessageIn> {
+    check_size(bytes.len())?;
+    let decoded = std::panic::catch_unwind(|| MlsMessageIn::tls_deserialize_exact(bytes))
+        .map_err(|_| malformed("MLS message decoder panicked".to_string()))?;
+    decoded.map_err(|e| malformed(format!("invalid MLS message: {}", e)))
+}
+
+/// Decode a base64 blob holding an MLS message (Welcome, Commit, application)
+pub fn decode_mls_blob(encoded: &str) -> Result<MlsMessageIn> {
+    decode_mls_message(&decode_base64(encoded)?)
+}
+
+/// Decode the base64 JSON ratchet tree sent alongside a Welcome
+pub fn decode_ratchet_tree(encoded: &str) -> Result<RatchetTreeIn> {
+    parse_json(&decode_base64(encoded)?)
+}
+
+fn check_size(len: usize) -> Result<()> {
+    if len > MAX_ENVELOPE_BYTES {
+        return Err(malformed(format!(
+            "{} bytes exceeds the {} byte limit",
+            len, MAX_ENVELOPE_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Reject JSON nested deeper than `MAX_JSON_DEPTH`, ignoring brackets in strings
+fn check_json_depth(bytes: &[u8]) -> Result<()> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_JSON_DEPTH {
+                    return Err(malformed(format!(
+                        "JSON nested deeper than {} levels",
+                        MAX_JSON_DEPTH
+                    )));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn malformed(reason: String) -> crate::error::ClientError {
+    MlsError::MalformedMessage(reason).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+    use tls_codec::Serialize;
+
+    fn commit_bytes() -> Vec<u8> {
+        let provider = &OpenMlsRustCrypto::default();
+        let (alice_cred, alice_key) = crypto::generate_credential_with_key("alice").unwrap();
+        let mut group =
+            crypto::create_group_with_config(&alice_cred, &alice_key, provider, "general").unwrap();
+        let (bob_cred, bob_key) = crypto::generate_credential_with_key("bob").unwrap();
+        let bob_key_package =
+            crypto::generate_key_package_bundle(&bob_cred, &bob_key, provider).unwrap();
+        let (commit, _welcome, _group_info) = crypto::add_members(
+            &mut group,
+            provider,
+            &alice_key,
+            &[bob_key_package.key_package()],
+        )
+        .unwrap();
+        commit.tls_serialize_detached().unwrap()
+    }
+
+    #[test]
+    fn test_valid_envelope_and_blob_decode() {
+        let bytes = commit_bytes();
+        let encoded = general_purpose::STANDARD.encode(&bytes);
+        let text = serde_json::json!({
+            "type": "commit",
+            "group_id": "Z3JvdXA=",
+            "sender": "alice",
+            "commit_blob": encoded,
+        })
+        .to_string();
+
+        let MlsMessageEnvelope::CommitMessage { commit_blob, .. } = parse_envelope(&text).unwrap()
+        else {
+            panic!("expected a commit envelope");
+        };
+        assert!(decode_mls_blob(&commit_blob).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_base64_is_an_error() {
+        let encoded = general_purpose::STANDARD.encode(commit_bytes());
+        for cut in [1, 2, 3, encoded.len() / 2] {
+            assert!(decode_base64(&encoded[..encoded.len() - cut]).is_err());
+        }
+        assert!(decode_mls_blob("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_truncated_or_padded_mls_message_is_an_error() {
+        let bytes = commit_bytes();
+        for len in [0, 1, 4, bytes.len() / 2, bytes.len() - 1] {
+            assert!(decode_mls_message(&bytes[..len]).is_err(), "length {}", len);
+        }
+
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(decode_mls_message(&padded).is_err());
+    }
+
+    /// Regression: an 8-byte QUIC length prefix (wider than MLS allows)
+    /// tripped a `debug_assert!` in tls_codec and panicked the client
+    #[test]
+    fn test_oversized_tls_length_prefix_is_an_error() {
+        // mls10, PrivateMessage, then a group_id claiming 2^40 bytes
+        let mut bytes = vec![0x00, 0x01, 0x00, 0x02];
+        bytes.extend_from_slice(&[0xc0, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0u8; 16]);
+        assert!(decode_mls_message(&bytes).is_err());
+
+        // A 4-byte prefix claiming far more than the input holds
+        let mut bytes = vec![0x00, 0x01, 0x00, 0x02, 0xbf, 0xff, 0xff, 0xff];
+        bytes.extend_from_slice(&[0u8; 16]);
+        assert!(decode_mls_message(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_deeply_nested_json_is_an_error() {
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(parse_envelope(&nested).is_err());
+        assert!(parse_json::<serde_json::Value>(nested.as_bytes()).is_err());
+
+        let shallow = format!(
+            "{}{}",
+            "[".repeat(MAX_JSON_DEPTH),
+            "]".repeat(MAX_JSON_DEPTH)
+        );
+        assert!(parse_json::<serde_json::Value>(shallow.as_bytes()).is_ok());
+
+        // Brackets inside strings do not count towards the depth
+        let text = serde_json::json!({
+            "type": "commit",
+            "group_id": "[".repeat(1000),
+            "sender": "{\"[",
+            "commit_blob": "",
+        })
+        .to_string();
+        assert!(parse_envelope(&text).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_inputs_are_rejected_before_decoding() {
+        let huge = "A".repeat(MAX_ENVELOPE_BYTES / 3 * 4 + 8);
+        assert!(decode_base64(&huge).is_err());
+        assert!(parse_envelope(&huge).is_err());
+    }
+
+    #[test]
+    fn test_malformed_envelopes_are_errors() {
+        for text in [
+            "",
+            "{",
+            "null",
+            r#"{"type":"commit"}"#,
+            r#"{"type":"unknown","sender":"a"}"#,
+            r#"{"type":"welcome","inviter":1,"invitee":"b","welcome_blob":"","ratchet_tree_blob":""}"#,
+        ] {
+            assert!(parse_envelope(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_malformed_binary_envelopes_are_errors() {
+        let envelope = MlsMessageEnvelope::CommitMessage {
+            group_id: "Z3JvdXA=".to_string(),
+            sender: "alice".to_string(),
+            commit_blob: general_purpose::STANDARD.encode(commit_bytes()),
+            device_id: None,
+        };
+        let bytes = crate::framing::encode_binary(&envelope).unwrap();
+        assert_eq!(parse_binary_envelope(&bytes).unwrap(), envelope);
+
+        for len in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+            assert!(
+                parse_binary_envelope(&bytes[..len]).is_err(),
+                "length {}",
+                len
+            );
+        }
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(parse_binary_envelope(&padded).is_err());
+
+        // Unknown variant, and a blob length far beyond the limit
+        assert!(parse_binary_envelope(&[7]).is_err());
+        assert!(
+            parse_binary_envelope(&[2, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_malformed_ratchet_tree_is_an_error() {
+        let encoded = general_purpose::STANDARD.encode(b"{\"not\": \"a tree\"}");
+        assert!(decode_ratchet_tree(&encoded).is_err());
+        assert!(decode_ratchet_tree("!!").is_err());
+    }
+}
@@ -40,6 +40,9 @@ pub enum ClientError {
 
     #[error("Database error: {0}")]
     Database(#[from] rusqlite::Error),
+
+    #[error("Invite error: {0}")]
+    Invite(#[from] InviteError),
 }
 
 /// Storage-related errors
@@ -59,6 +62,18 @@ pub enum StorageError {
 
     #[error("No group members found: {0}")]
     NoGroupMembers(String),
+
+    #[error("Outbound queue is full ({max_messages} messages waiting)")]
+    OutboundQueueFull { max_messages: usize },
+
+    #[error("Group settings are {size} bytes, more than the {max} allowed")]
+    GroupSettingsTooLarge { size: usize, max: usize },
+
+    #[error("Backup is not a usable database: {0}")]
+    InvalidBackup(String),
+
+    #[error("Keyring error: {0}")]
+    Keyring(String),
 }
 
 /// Network-related errors
@@ -83,7 +98,7 @@ pub enum NetworkError {
 /// KeyPackage pool operation errors
 #[derive(Error, Debug, Clone, PartialEq)]
 pub enum KeyPackageError {
-    #[error("No available KeyPackage for user '{username}'")]
+    #[error("No available KeyPackage for user '{username}' (ask them to come online so their client can publish new key packages)")]
     PoolExhausted { username: String },
 
     #[error("KeyPackage has expired (ref: {keypackage_ref:?})")]
@@ -117,6 +132,12 @@ pub enum MlsError {
     #[error("Invalid key package")]
     InvalidKeyPackage,
 
+    #[error("Ratchet tree rejected: {0}")]
+    InvalidRatchetTree(String),
+
+    #[error("Certificate rejected: {0}")]
+    InvalidCertificate(String),
+
     #[error("Group not found")]
     GroupNotFound,
 
@@ -129,8 +150,126 @@ pub enum MlsError {
     #[error("Decryption failed")]
     DecryptionFailed,
 
+    #[error("Malformed message: {0}")]
+    MalformedMessage(String),
+
     #[error("Key package pool capacity exceeded (needed {needed}, available {available})")]
     PoolCapacityExceeded { needed: usize, available: usize },
+
+    #[error("No Welcome was sent to '{username}' from this device")]
+    WelcomeNotFound { username: String },
+
+    #[error("The Welcome for '{username}' is for epoch {added_epoch} but the group has advanced to epoch {current_epoch}; they can no longer join with it and must be invited again")]
+    WelcomeStale {
+        username: String,
+        added_epoch: u64,
+        current_epoch: u64,
+    },
+
+    #[error("'{username}' cannot join another group: group limit reached ({limit} groups)")]
+    GroupLimitReached { username: String, limit: usize },
+
+    #[error("'{username}' is not an admin of this group")]
+    NotAdmin { username: String },
+
+    #[error("Group '{group_name}' maps to group id {group_id}, whose roster does not include '{username}'; the local group mapping is inconsistent")]
+    NotAMember {
+        username: String,
+        group_name: String,
+        group_id: String,
+    },
+
+    #[error("No message with id '{message_id}' in this group (it may have expired)")]
+    MessageNotFound { message_id: String },
+
+    #[error("Group '{name}' was already created from another device (group id {existing_group_id}); ask a member to invite you instead of creating it again")]
+    DuplicateGroup {
+        name: String,
+        existing_group_id: String,
+    },
+
+    #[error("A change to group '{group_name}' is still being committed; wait for the operation to complete, then send again")]
+    CommitInProgress { group_name: String },
+
+    #[error("Other members changed the group {attempts} times while this change was being made; try again")]
+    EpochConflict { attempts: u32 },
+
+    #[error("The KeyPackage reserved for '{username}' belongs to a credential they have since rotated; invite them again")]
+    SupersededKeyPackage { username: String },
+
+    #[error("Join token rejected: {reason}")]
+    JoinTokenRejected { reason: String },
+
+    #[error("Local state of group '{name}' (group id {group_id}) was lost; ask a member to remove and re-add you, or recreate the group to start a new conversation under this name")]
+    GroupStateLost { name: String, group_id: String },
+
+    #[error("No connected group is named '{name}'")]
+    UnknownGroupName { name: String },
+
+    #[error("{} connected groups are named '{name}' (group ids {}); address one by its group id", .group_ids.len(), .group_ids.join(", "))]
+    AmbiguousGroupName {
+        name: String,
+        group_ids: Vec<String>,
+    },
+
+    #[error("In-memory state of group '{group_name}' diverged from storage: {field} is {in_memory} in memory but {persisted} in storage")]
+    StateDiverged {
+        group_name: String,
+        field: &'static str,
+        in_memory: String,
+        persisted: String,
+    },
+}
+
+/// Stage of the invitation protocol (see `MlsMembership::invite_user`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteStage {
+    /// Reserving the invitee's KeyPackage on the server (unknown user or empty pool)
+    ReserveKeyPackage,
+    /// Deserializing and validating the reserved KeyPackage
+    ValidateKeyPackage,
+    /// Adding the invitee to the MLS group and merging the Commit locally
+    AddMember,
+    /// Getting the Commit accepted by the server for the current epoch
+    SequenceCommit,
+    /// Sending the Welcome message to the invitee
+    SendWelcome,
+    /// Broadcasting the Commit to existing members
+    BroadcastCommit,
+    /// Marking the KeyPackage as spent on the server and locally
+    SpendKeyPackage,
+}
+
+impl std::fmt::Display for InviteStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InviteStage::ReserveKeyPackage => "reserve key package",
+            InviteStage::ValidateKeyPackage => "validate key package",
+            InviteStage::AddMember => "add member",
+            InviteStage::SequenceCommit => "sequence commit",
+            InviteStage::SendWelcome => "send welcome",
+            InviteStage::BroadcastCommit => "broadcast commit",
+            InviteStage::SpendKeyPackage => "spend key package",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Failure of an invitation, with the stage that failed
+///
+/// `group_state_advanced` is true once the Commit adding the invitee has been
+/// merged locally: the local group is then at a new epoch that other members
+/// (or the invitee) may not have seen, and the caller must recover (e.g. resend
+/// the Welcome/Commit) rather than assume nothing happened.
+#[derive(Error, Debug)]
+#[error("inviting '{invitee}' failed at stage '{stage}'{}: {source}",
+    if *.group_state_advanced { " after the group state advanced" } else { "" })]
+pub struct InviteError {
+    pub invitee: String,
+    pub stage: InviteStage,
+    pub group_state_advanced: bool,
+    #[source]
+    pub source: Box<ClientError>,
 }
 
 /// Result type alias for the client
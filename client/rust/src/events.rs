@@ -0,0 +1,148 @@
+//! Observer API for group state changes
+//!
+//! UI layers register an observer and receive a `GroupEvent` whenever a
+//! member joins or leaves, the admins change, or a message is stored, instead
+//! of polling storage. Events go out over unbounded channels, so emitting
+//! never waits on a slow observer; an observer whose receiver was dropped is
+//! removed on the next event.
+
+use crate::models::ContentType;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// A change to the state of a group, as seen by this client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupEvent {
+    /// `username` was added to the group by `committer`
+    MemberAdded {
+        group_id: Vec<u8>,
+        username: String,
+        committer: String,
+    },
+    /// `username` was removed from the group by `committer`
+    MemberRemoved {
+        group_id: Vec<u8>,
+        username: String,
+        committer: String,
+    },
+    /// This client was removed from the group by `committer`; the
+    /// connection drops the group right after
+    RemovedFromGroup {
+        group_id: Vec<u8>,
+        committer: String,
+    },
+    /// The group's admins changed; `admins` is the new list
+    RolesChanged {
+        group_id: Vec<u8>,
+        admins: Vec<String>,
+        committer: String,
+    },
+    /// A chat message or announcement was stored in the local history
+    MessageStored {
+        group_id: Vec<u8>,
+        sender: String,
+        text: String,
+        content_type: ContentType,
+    },
+    /// The group's name was known locally but its MLS state was missing;
+    /// `recreated` is set if a new group took over the name (see
+    /// `LostGroupPolicy`)
+    GroupStateLost {
+        group_id: Vec<u8>,
+        group_name: String,
+        recreated: bool,
+    },
+}
+
+/// Handle returned by `GroupEvents::subscribe`, used to unsubscribe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+#[derive(Default)]
+struct Observers {
+    next_id: u64,
+    senders: Vec<(ObserverId, UnboundedSender<GroupEvent>)>,
+}
+
+/// Registry of group event observers
+///
+/// Clones share the same observers, so the connection and its memberships
+/// all emit to the observers registered on any of them.
+#[derive(Clone, Default)]
+pub struct GroupEvents {
+    observers: Arc<Mutex<Observers>>,
+}
+
+impl GroupEvents {
+    /// Register an observer; events emitted from now on arrive on the receiver
+    pub fn subscribe(&self) -> (ObserverId, UnboundedReceiver<GroupEvent>) {
+        let (sender, receiver) = unbounded_channel();
+        let mut observers = self.lock();
+        let id = ObserverId(observers.next_id);
+        observers.next_id += 1;
+        observers.senders.push((id, sender));
+        (id, receiver)
+    }
+
+    /// Remove an observer; returns whether it was registered
+    pub fn unsubscribe(&self, id: ObserverId) -> bool {
+        let mut observers = self.lock();
+        let before = observers.senders.len();
+        observers.senders.retain(|(observer, _)| *observer != id);
+        observers.senders.len() != before
+    }
+
+    /// Number of registered observers
+    pub fn observer_count(&self) -> usize {
+        self.lock().senders.len()
+    }
+
+    /// Send `event` to every observer without waiting on any of them
+    pub(crate) fn emit(&self, event: GroupEvent) {
+        self.lock()
+            .senders
+            .retain(|(_, sender)| sender.send(event.clone()).is_ok());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Observers> {
+        // The registry stays consistent even if an observer panicked mid-update
+        self.observers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(text: &str) -> GroupEvent {
+        GroupEvent::MessageStored {
+            group_id: b"group".to_vec(),
+            sender: "alice".to_string(),
+            text: text.to_string(),
+            content_type: ContentType::Text,
+        }
+    }
+
+    #[test]
+    fn test_unsubscribed_and_dropped_observers_stop_receiving() {
+        let events = GroupEvents::default();
+        let (first, mut first_rx) = events.subscribe();
+        let (_second, second_rx) = events.subscribe();
+        let (_third, mut third_rx) = events.subscribe();
+
+        events.emit(stored("one"));
+        assert_eq!(first_rx.try_recv().unwrap(), stored("one"));
+
+        assert!(events.unsubscribe(first));
+        assert!(!events.unsubscribe(first));
+        drop(second_rx);
+        events.emit(stored("two"));
+
+        assert!(first_rx.try_recv().is_err());
+        assert_eq!(third_rx.try_recv().unwrap(), stored("one"));
+        assert_eq!(third_rx.try_recv().unwrap(), stored("two"));
+        assert_eq!(events.observer_count(), 1);
+    }
+}
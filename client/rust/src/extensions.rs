@@ -18,6 +18,17 @@ pub struct GroupMetadata {
 
     /// Version for detecting changes/rollbacks
     pub version: u32,
+
+    /// Disappearing messages: seconds a message is kept after it was sent
+    /// (`None` keeps messages forever). Changed only by a Commit, so all
+    /// members agree on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_ttl_secs: Option<u64>,
+
+    /// Usernames with the Admin role (the creator, for new groups). Only
+    /// admins may post announcements; groups from older clients have none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admins: Vec<String>,
     // Future fields can be added here without breaking old clients
     // (clients will just ignore unknown fields during deserialization)
 }
@@ -32,9 +43,16 @@ impl GroupMetadata {
                 .unwrap()
                 .as_secs(),
             version: 1,
+            message_ttl_secs: None,
+            admins: Vec::new(),
         }
     }
 
+    /// Whether `username` has the Admin role in the group
+    pub fn is_admin(&self, username: &str) -> bool {
+        self.admins.iter().any(|admin| admin == username)
+    }
+
     /// Serialize to bytes for storage in UnknownExtension
     pub fn to_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
         serde_json::to_vec(self)
@@ -65,6 +83,8 @@ mod tests {
             name: "Complex Name".to_string(),
             created_at: 1234567890,
             version: 5,
+            message_ttl_secs: Some(3600),
+            admins: vec!["alice".to_string()],
         };
 
         let bytes = metadata.to_bytes().unwrap();
@@ -73,5 +93,16 @@ mod tests {
         assert_eq!(metadata.name, deserialized.name);
         assert_eq!(metadata.created_at, deserialized.created_at);
         assert_eq!(metadata.version, deserialized.version);
+        assert_eq!(metadata.message_ttl_secs, deserialized.message_ttl_secs);
+        assert!(deserialized.is_admin("alice"));
+        assert!(!deserialized.is_admin("bob"));
+    }
+
+    #[test]
+    fn test_group_metadata_without_ttl_from_older_clients() {
+        let bytes = br#"{"name":"Old Group","created_at":1234567890,"version":1}"#;
+        let metadata = GroupMetadata::from_bytes(bytes).unwrap();
+        assert_eq!(metadata.message_ttl_secs, None);
+        assert!(metadata.admins.is_empty());
     }
 }
@@ -0,0 +1,273 @@
+//! Framing of envelopes on the WebSocket
+//!
+//! The default JSON text frames carry every blob as base64, which inflates
+//! them by a third. Binary frames instead carry a bincode-encoded
+//! [`BinaryEnvelope`] with the blobs as raw bytes.
+//!
+//! Binary framing is negotiated when connecting: the client asks for it with
+//! `?framing=binary` and a server that supports it answers with the
+//! [`FRAMING_HEADER`] header set to `binary`. An older server ignores the
+//! query, and both sides keep JSON. Whatever was negotiated, incoming text
+//! frames are always accepted as JSON, because the server falls back to JSON
+//! for messages that it cannot encode in binary.
+//!
+//! The server (`server/src/handlers/framing.rs`) has its own copy of
+//! [`BinaryEnvelope`]; the two must stay identical.
+
+use crate::error::{MlsError, Result};
+use crate::models::{ContentType, MlsMessageEnvelope};
+use base64::{engine::general_purpose, Engine as _};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+/// Response header in which the server confirms binary framing
+pub const FRAMING_HEADER: &str = "x-mls-framing";
+
+/// How envelopes are sent on the WebSocket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// JSON text frames with base64 blobs, understood by every server
+    #[default]
+    Json,
+    /// Binary frames holding a bincode `BinaryEnvelope`, if the server
+    /// agrees to it
+    Binary,
+}
+
+impl Framing {
+    /// Value of the `framing` query parameter and of `FRAMING_HEADER`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Framing::Json => "json",
+            Framing::Binary => "binary",
+        }
+    }
+}
+
+/// Wire form of an `MlsMessageEnvelope` in a binary frame
+///
+/// Group ids and blobs are the bytes that JSON envelopes carry as base64.
+/// Variant and field order is part of the format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum BinaryEnvelope {
+    Application {
+        sender: String,
+        group_id: Vec<u8>,
+        encrypted_content: Vec<u8>,
+        content_type: ContentType,
+        seq: Option<i64>,
+        epoch: Option<u64>,
+    },
+    Welcome {
+        inviter: String,
+        invitee: String,
+        welcome: Vec<u8>,
+        ratchet_tree: Vec<u8>,
+    },
+    Commit {
+        group_id: Vec<u8>,
+        sender: String,
+        commit: Vec<u8>,
+        device_id: Option<String>,
+    },
+}
+
+/// bincode settings of binary frames: varint lengths, bounded input, and no
+/// trailing bytes
+pub(crate) fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_limit(crate::envelope::MAX_ENVELOPE_BYTES as u64)
+        .reject_trailing_bytes()
+}
+
+/// Encode an envelope for a binary frame
+///
+/// # Errors
+/// * `MlsError::MalformedMessage` if a group id or blob is not valid base64
+pub fn encode_binary(envelope: &MlsMessageEnvelope) -> Result<Vec<u8>> {
+    let wire = match envelope {
+        MlsMessageEnvelope::ApplicationMessage {
+            sender,
+            group_id,
+            encrypted_content,
+            content_type,
+            seq,
+            epoch,
+        } => BinaryEnvelope::Application {
+            sender: sender.clone(),
+            group_id: raw(group_id)?,
+            encrypted_content: raw(encrypted_content)?,
+            content_type: *content_type,
+            seq: *seq,
+            epoch: *epoch,
+        },
+        MlsMessageEnvelope::WelcomeMessage {
+            inviter,
+            invitee,
+            welcome_blob,
+            ratchet_tree_blob,
+        } => BinaryEnvelope::Welcome {
+            inviter: inviter.clone(),
+            invitee: invitee.clone(),
+            welcome: raw(welcome_blob)?,
+            ratchet_tree: raw(ratchet_tree_blob)?,
+        },
+        MlsMessageEnvelope::CommitMessage {
+            group_id,
+            sender,
+            commit_blob,
+            device_id,
+        } => BinaryEnvelope::Commit {
+            group_id: raw(group_id)?,
+            sender: sender.clone(),
+            commit: raw(commit_blob)?,
+            device_id: device_id.clone(),
+        },
+    };
+    bincode_options()
+        .serialize(&wire)
+        .map_err(|e| MlsError::MalformedMessage(format!("cannot encode envelope: {}", e)).into())
+}
+
+impl From<BinaryEnvelope> for MlsMessageEnvelope {
+    fn from(wire: BinaryEnvelope) -> Self {
+        let b64 = |bytes: Vec<u8>| general_purpose::STANDARD.encode(bytes);
+        match wire {
+            BinaryEnvelope::Application {
+                sender,
+                group_id,
+                encrypted_content,
+                content_type,
+                seq,
+                epoch,
+            } => MlsMessageEnvelope::ApplicationMessage {
+                sender,
+                group_id: b64(group_id),
+                encrypted_content: b64(encrypted_content),
+                content_type,
+                seq,
+                epoch,
+            },
+            BinaryEnvelope::Welcome {
+                inviter,
+                invitee,
+                welcome,
+                ratchet_tree,
+            } => MlsMessageEnvelope::WelcomeMessage {
+                inviter,
+                invitee,
+                welcome_blob: b64(welcome),
+                ratchet_tree_blob: b64(ratchet_tree),
+            },
+            BinaryEnvelope::Commit {
+                group_id,
+                sender,
+                commit,
+                device_id,
+            } => MlsMessageEnvelope::CommitMessage {
+                group_id: b64(group_id),
+                sender,
+                commit_blob: b64(commit),
+                device_id,
+            },
+        }
+    }
+}
+
+fn raw(encoded: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| MlsError::MalformedMessage(format!("invalid base64: {}", e)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{parse_binary_envelope, parse_envelope};
+
+    fn b64(len: usize, fill: u8) -> String {
+        general_purpose::STANDARD.encode(vec![fill; len])
+    }
+
+    /// One envelope of each variant with realistically sized blobs
+    fn all_variants() -> Vec<MlsMessageEnvelope> {
+        vec![
+            MlsMessageEnvelope::ApplicationMessage {
+                sender: "alice".to_string(),
+                group_id: b64(16, 1),
+                encrypted_content: b64(180, 2),
+                content_type: ContentType::Control,
+                seq: Some(42),
+                epoch: Some(3),
+            },
+            MlsMessageEnvelope::ApplicationMessage {
+                sender: "alice".to_string(),
+                group_id: b64(16, 1),
+                encrypted_content: b64(180, 2),
+                content_type: ContentType::Text,
+                seq: None,
+                epoch: None,
+            },
+            MlsMessageEnvelope::WelcomeMessage {
+                inviter: "alice".to_string(),
+                invitee: "bob".to_string(),
+                welcome_blob: b64(900, 3),
+                ratchet_tree_blob: b64(600, 4),
+            },
+            MlsMessageEnvelope::CommitMessage {
+                group_id: b64(16, 1),
+                sender: "alice".to_string(),
+                commit_blob: b64(700, 5),
+                device_id: None,
+            },
+            MlsMessageEnvelope::CommitMessage {
+                group_id: b64(16, 1),
+                sender: "alice".to_string(),
+                commit_blob: b64(700, 5),
+                device_id: Some("0f8c1e9a-2b4d-4c6e-8a1f-3d5b7c9e0a2b".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_every_variant_round_trips_in_both_framings() {
+        for envelope in all_variants() {
+            let json = serde_json::to_string(&envelope).unwrap();
+            assert_eq!(parse_envelope(&json).unwrap(), envelope);
+
+            let binary = encode_binary(&envelope).unwrap();
+            assert_eq!(parse_binary_envelope(&binary).unwrap(), envelope);
+        }
+    }
+
+    #[test]
+    fn test_binary_frames_are_smaller() {
+        let mut json_bytes = 0;
+        let mut binary_bytes = 0;
+        for envelope in all_variants() {
+            let json = serde_json::to_string(&envelope).unwrap().len();
+            let binary = encode_binary(&envelope).unwrap().len();
+            assert!(binary < json, "{} >= {} for {:?}", binary, json, envelope);
+            json_bytes += json;
+            binary_bytes += binary;
+        }
+        // Base64 alone costs a third; field names and quoting add more
+        assert!(
+            binary_bytes * 4 < json_bytes * 3,
+            "binary {} bytes vs JSON {} bytes",
+            binary_bytes,
+            json_bytes
+        );
+    }
+
+    #[test]
+    fn test_invalid_base64_cannot_be_sent_binary() {
+        let envelope = MlsMessageEnvelope::CommitMessage {
+            group_id: "not base64!".to_string(),
+            sender: "alice".to_string(),
+            commit_blob: b64(10, 5),
+            device_id: None,
+        };
+        assert!(encode_binary(&envelope).is_err());
+    }
+}
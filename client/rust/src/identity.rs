@@ -1,13 +1,18 @@
 //! Identity persistence and management
 //!
 //! Handles persistent storage and recovery of user identities (credentials and signature keys)
-//! using the OpenMLS storage provider. Each username maintains a unique cryptographic identity.
+//! using the OpenMLS storage provider, or the keyring configured on the metadata store (see
+//! `secret_store`). Each username maintains a unique cryptographic identity.
 
-use crate::error::{ClientError, Result};
+use crate::crypto::CredentialKind;
+use crate::error::{ClientError, MlsError, Result};
 use crate::provider::MlsProvider;
+use crate::secret_store::KeyringIdentity;
 use crate::storage::LocalStore;
+use crate::x509::TrustAnchors;
 use openmls::prelude::*;
 use openmls_basic_credential::SignatureKeyPair;
+use serde::{Deserialize, Serialize};
 
 /// Represents a stored user identity with all cryptographic material
 ///
@@ -20,6 +25,58 @@ pub struct StoredIdentity {
     pub signature_key: SignatureKeyPair,
 }
 
+/// Public half of an identity, as served by the server's user directory
+///
+/// Holds only what group members already see in the user's leaf node: the
+/// credential and the signature public key. The server derives it from the
+/// user's registered KeyPackage (`GET /users/{username}/identity`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicIdentityBundle {
+    pub username: String,
+    /// TLS-serialized `Credential`
+    pub credential: Vec<u8>,
+    /// Signature public key bound to the credential
+    pub signature_key: Vec<u8>,
+}
+
+impl PublicIdentityBundle {
+    /// Rebuild the credential and key this bundle describes
+    ///
+    /// # Errors
+    /// * `MlsError::InvalidCredential` if the credential does not decode
+    pub fn credential_with_key(&self) -> Result<CredentialWithKey> {
+        use tls_codec::Deserialize as _;
+
+        let credential = Credential::tls_deserialize_exact(&self.credential)
+            .map_err(|_| crate::error::MlsError::InvalidCredential)?;
+        Ok(CredentialWithKey {
+            credential,
+            signature_key: self.signature_key.clone().into(),
+        })
+    }
+
+    /// Fingerprint of the identity, comparable with
+    /// `IdentityInfo::credential_fingerprint` read out of band
+    ///
+    /// # Errors
+    /// * as for `credential_with_key`, and hashing errors
+    pub fn fingerprint(&self, provider: &impl OpenMlsProvider) -> Result<String> {
+        crate::crypto::credential_fingerprint(provider, &self.credential_with_key()?)
+    }
+
+    /// Whether `key_package` carries this credential and signature key
+    pub fn matches_key_package(&self, key_package: &KeyPackage) -> bool {
+        use tls_codec::Serialize as _;
+
+        let leaf = key_package.leaf_node();
+        leaf.signature_key().as_slice() == self.signature_key.as_slice()
+            && leaf
+                .credential()
+                .tls_serialize_detached()
+                .is_ok_and(|credential| credential == self.credential)
+    }
+}
+
 /// Identity manager for persistent credential and key storage
 pub struct IdentityManager;
 
@@ -47,41 +104,277 @@ impl IdentityManager {
         metadata_store: &LocalStore,
         username: &str,
     ) -> Result<StoredIdentity> {
+        Self::load_or_create_with_kind(provider, metadata_store, username, CredentialKind::Basic)
+    }
+
+    /// Load or create a user identity, creating new identities with `kind` credentials
+    ///
+    /// An existing identity keeps the credential type it was created with; a
+    /// mismatch with `kind` is logged rather than silently replacing the
+    /// user's keys.
+    pub fn load_or_create_with_kind(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+        kind: CredentialKind,
+    ) -> Result<StoredIdentity> {
+        if let Some(identity) = Self::load(provider, metadata_store, username)? {
+            let credential_type = identity.credential_with_key.credential.credential_type();
+            if credential_type != kind.credential_type() {
+                log::warn!(
+                    "Identity for {} uses {:?} credentials, not the requested {:?}",
+                    username,
+                    credential_type,
+                    kind
+                );
+            }
+            return Ok(identity);
+        }
+
+        if metadata_store.load_public_key(username)?.is_some() {
+            // Public key stored but not in OpenMLS storage - regenerate
+            log::warn!(
+                "Public key for {} found in metadata but not in OpenMLS storage. Regenerating.",
+                username
+            );
+        }
+        let (credential_with_key, signature_key) =
+            Self::create_new_identity(provider, metadata_store, username, kind)?;
+
+        Ok(StoredIdentity {
+            username: username.to_string(),
+            credential_with_key,
+            signature_key,
+        })
+    }
+
+    /// Load an existing identity without creating one
+    ///
+    /// Returns `None` if no identity is stored for `username`, or if its
+    /// signature key is missing from both the keyring and the OpenMLS
+    /// storage. With a keyring configured, an identity still in the OpenMLS
+    /// storage is moved to the keyring.
+    ///
+    /// # Errors
+    /// * Storage errors when reading credentials
+    pub fn load(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+    ) -> Result<Option<StoredIdentity>> {
         let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
 
-        // Try to load existing identity from metadata store
-        let (credential_with_key, signature_key) = match metadata_store.load_public_key(username)? {
-            Some(public_key_blob) => {
-                // We have the public key stored - try to load the signature key from OpenMLS storage
-                match SignatureKeyPair::read(
-                    provider.storage(),
-                    &public_key_blob,
-                    ciphersuite.signature_algorithm(),
-                ) {
-                    Some(sig_key) => {
-                        // Successfully loaded existing signature key
-                        let credential = BasicCredential::new(username.as_bytes().to_vec());
-                        let credential_with_key = CredentialWithKey {
-                            credential: credential.into(),
-                            signature_key: sig_key.to_public_vec().into(),
-                        };
-                        (credential_with_key, sig_key)
-                    }
-                    None => {
-                        // Public key stored but not in OpenMLS storage - regenerate
-                        log::warn!(
-                                "Public key for {} found in metadata but not in OpenMLS storage. Regenerating.",
-                                username
-                            );
-                        Self::create_new_identity(provider, metadata_store, username)?
-                    }
-                }
+        let Some(public_key_blob) = metadata_store.load_public_key(username)? else {
+            return Ok(None);
+        };
+        if let Some(identity) = Self::load_from_keyring(metadata_store, username, &public_key_blob)
+        {
+            return Ok(Some(identity));
+        }
+        let Some(signature_key) = SignatureKeyPair::read(
+            provider.storage(),
+            &public_key_blob,
+            ciphersuite.signature_algorithm(),
+        ) else {
+            return Ok(None);
+        };
+
+        // Only non-Basic credentials are stored; Basic ones are rebuilt from the username
+        let credential = match provider.load_credential(username)? {
+            Some(credential) => credential,
+            None => BasicCredential::new(username.as_bytes().to_vec()).into(),
+        };
+        if metadata_store.identity_keyring().is_some() {
+            Self::move_to_keyring(
+                provider,
+                metadata_store,
+                username,
+                &credential,
+                &signature_key,
+            );
+        }
+        let credential_with_key = CredentialWithKey {
+            credential,
+            signature_key: signature_key.to_public_vec().into(),
+        };
+
+        Ok(Some(StoredIdentity {
+            username: username.to_string(),
+            credential_with_key,
+            signature_key,
+        }))
+    }
+
+    /// The identity of `username` from the configured keyring, if it holds
+    /// the one whose public key is `public_key_blob`
+    ///
+    /// A keyring that fails is logged and treated as empty.
+    fn load_from_keyring(
+        metadata_store: &LocalStore,
+        username: &str,
+        public_key_blob: &[u8],
+    ) -> Option<StoredIdentity> {
+        let keyring = metadata_store.identity_keyring()?;
+        let secret = match keyring.get(username) {
+            Ok(secret) => secret?,
+            Err(e) => {
+                log::warn!(
+                    "Keyring unavailable ({}); looking for the identity of {} in the MLS database",
+                    e,
+                    username
+                );
+                return None;
             }
-            None => {
-                // No identity stored - create new one
-                Self::create_new_identity(provider, metadata_store, username)?
+        };
+        let stored = match KeyringIdentity::decode(&secret) {
+            Ok(stored) => stored,
+            Err(e) => {
+                log::warn!("Ignoring unreadable keyring entry of {}: {}", username, e);
+                return None;
             }
         };
+        if stored.signature_key.to_public_vec() != public_key_blob {
+            log::warn!(
+                "Keyring entry of {} holds a different key than the stored identity",
+                username
+            );
+            return None;
+        }
+        let credential = match stored.credential() {
+            Ok(credential) => credential,
+            Err(e) => {
+                log::warn!("Ignoring keyring entry of {}: {}", username, e);
+                return None;
+            }
+        };
+        let credential_with_key = CredentialWithKey {
+            credential,
+            signature_key: public_key_blob.to_vec().into(),
+        };
+        Some(StoredIdentity {
+            username: username.to_string(),
+            credential_with_key,
+            signature_key: stored.signature_key,
+        })
+    }
+
+    /// Move an identity loaded from the OpenMLS storage to the keyring
+    ///
+    /// The OpenMLS storage copy is only deleted once the keyring holds it;
+    /// on failure the identity stays where it is.
+    fn move_to_keyring(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+        credential: &Credential,
+        signature_key: &SignatureKeyPair,
+    ) {
+        let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+
+        if !Self::store_in_keyring(metadata_store, username, credential, signature_key) {
+            return;
+        }
+        let deleted = SignatureKeyPair::delete(
+            provider.storage(),
+            &signature_key.to_public_vec(),
+            ciphersuite.signature_algorithm(),
+        )
+        .map_err(|e| ClientError::Config(format!("Failed to delete signature key: {}", e)))
+        .and_then(|_| provider.delete_credential(username));
+        match deleted {
+            Ok(_) => log::info!("Moved the identity of {} to the keyring", username),
+            Err(e) => log::warn!(
+                "Identity of {} copied to the keyring but not removed from the MLS database: {}",
+                username,
+                e
+            ),
+        }
+    }
+
+    /// Store an identity in the configured keyring
+    ///
+    /// Returns `false`, after logging why, if no keyring is configured or it
+    /// cannot be used.
+    fn store_in_keyring(
+        metadata_store: &LocalStore,
+        username: &str,
+        credential: &Credential,
+        signature_key: &SignatureKeyPair,
+    ) -> bool {
+        let Some(keyring) = metadata_store.identity_keyring() else {
+            return false;
+        };
+        match KeyringIdentity::encode(signature_key, credential)
+            .and_then(|secret| keyring.set(username, &secret))
+        {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!(
+                    "Keyring unavailable ({}); storing the identity of {} in the MLS database",
+                    e,
+                    username
+                );
+                false
+            }
+        }
+    }
+
+    /// Store an identity's signature key and credential: in the keyring if
+    /// one is configured and usable, otherwise in the OpenMLS storage
+    fn store_secrets(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+        credential: &Credential,
+        signature_key: &SignatureKeyPair,
+    ) -> Result<()> {
+        if Self::store_in_keyring(metadata_store, username, credential, signature_key) {
+            return Ok(());
+        }
+        signature_key
+            .store(provider.storage())
+            .map_err(|e| ClientError::Config(format!("Failed to store signature key: {}", e)))?;
+        // Basic credentials are rebuilt from the username on load
+        if credential.credential_type() != CredentialType::Basic {
+            provider.save_credential(username, credential)?;
+        }
+        Ok(())
+    }
+
+    /// Replace `username`'s identity with a new signature key and credential
+    ///
+    /// The new identity keeps the credential type of the current one and is
+    /// what `load` returns from now on. The old signature key is deleted
+    /// from the provider storage; a caller that still has to sign with it,
+    /// e.g. to re-register with the server or update its group leaves, uses
+    /// the key pair it already holds.
+    ///
+    /// # Errors
+    /// * `StorageError::IdentityNotFound` if no identity is stored
+    /// * Storage and crypto errors while creating the new identity
+    pub fn rotate(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+    ) -> Result<StoredIdentity> {
+        let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+
+        let old = Self::load(provider, metadata_store, username)?
+            .ok_or_else(|| crate::error::StorageError::IdentityNotFound(username.to_string()))?;
+        let kind = CredentialKind::from_credential_type(
+            old.credential_with_key.credential.credential_type(),
+        )
+        .unwrap_or_default();
+
+        let (credential_with_key, signature_key) =
+            Self::create_new_identity(provider, metadata_store, username, kind)?;
+        SignatureKeyPair::delete(
+            provider.storage(),
+            &old.signature_key.to_public_vec(),
+            ciphersuite.signature_algorithm(),
+        )
+        .map_err(|e| ClientError::Config(format!("Failed to delete old signature key: {}", e)))?;
+        log::info!("Rotated the identity of {}", username);
 
         Ok(StoredIdentity {
             username: username.to_string(),
@@ -90,35 +383,169 @@ impl IdentityManager {
         })
     }
 
+    /// Make an identity the caller still holds the stored one again
+    ///
+    /// Undoes `rotate` when the rest of a rotation fails, e.g. because the
+    /// server refused the new registration.
+    ///
+    /// # Errors
+    /// * Storage errors when writing the key or credential
+    pub fn reinstate(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+        credential_with_key: &CredentialWithKey,
+        signature_key: &SignatureKeyPair,
+    ) -> Result<()> {
+        Self::store_secrets(
+            provider,
+            metadata_store,
+            username,
+            &credential_with_key.credential,
+            signature_key,
+        )?;
+        metadata_store.save_identity(username, &signature_key.to_public_vec())?;
+        Ok(())
+    }
+
+    /// Store a certificate chain and key issued by an external CA as
+    /// `username`'s identity
+    ///
+    /// `chain_pem` holds the leaf certificate first, optionally followed by
+    /// intermediates, and `key_pem` the leaf's Ed25519 key as PKCS#8 (see
+    /// `x509::load_issued_identity`); the chain must be issued by one of
+    /// `anchors` unless that is empty. Only a user without a stored identity
+    /// can import one; `load` returns it from then on.
+    ///
+    /// # Errors
+    /// * `MlsError::InvalidCertificate` if the files do not parse, the key
+    ///   does not match the leaf, the leaf's CN is not `username`, or the
+    ///   chain does not verify
+    /// * `ClientError::Config` if `username` already has an identity
+    /// * Storage errors when writing the key or credential
+    pub fn import_x509(
+        provider: &MlsProvider,
+        metadata_store: &LocalStore,
+        username: &str,
+        chain_pem: &[u8],
+        key_pem: &str,
+        anchors: &TrustAnchors,
+    ) -> Result<StoredIdentity> {
+        let (chain, info, signature_key) = crate::x509::load_issued_identity(chain_pem, key_pem)?;
+        if info.common_name != username {
+            return Err(MlsError::InvalidCertificate(format!(
+                "certificate is issued to '{}', not '{}'",
+                info.common_name, username
+            ))
+            .into());
+        }
+        anchors.verify_chain(&chain)?;
+        if Self::load(provider, metadata_store, username)?.is_some() {
+            return Err(ClientError::Config(format!(
+                "{} already has an identity; rotate it instead of importing",
+                username
+            )));
+        }
+
+        let credential = crate::x509::credential_from_chain(&chain)?;
+        Self::store_secrets(
+            provider,
+            metadata_store,
+            username,
+            &credential,
+            &signature_key,
+        )?;
+        metadata_store.save_identity(username, &signature_key.to_public_vec())?;
+        log::info!("Imported an X.509 identity for {}", username);
+
+        Ok(StoredIdentity {
+            username: username.to_string(),
+            credential_with_key: CredentialWithKey {
+                credential,
+                signature_key: signature_key.to_public_vec().into(),
+            },
+            signature_key,
+        })
+    }
+
+    /// The public material of `username`'s identity, for publishing or
+    /// comparison
+    ///
+    /// Takes only the credential and public key, so the signature private key
+    /// cannot end up in the bundle.
+    ///
+    /// # Errors
+    /// * Credential serialization errors
+    pub fn public_bundle(
+        username: &str,
+        credential_with_key: &CredentialWithKey,
+    ) -> Result<PublicIdentityBundle> {
+        use tls_codec::Serialize as _;
+
+        let credential = credential_with_key
+            .credential
+            .tls_serialize_detached()
+            .map_err(|e| {
+                crate::error::MlsError::OpenMls(format!("Failed to serialize credential: {}", e))
+            })?;
+        Ok(PublicIdentityBundle {
+            username: username.to_string(),
+            credential,
+            signature_key: credential_with_key.signature_key.as_slice().to_vec(),
+        })
+    }
+
     /// Create a new identity and store it in both provider and metadata store
     fn create_new_identity(
         provider: &MlsProvider,
         metadata_store: &LocalStore,
         username: &str,
+        kind: CredentialKind,
     ) -> Result<(CredentialWithKey, SignatureKeyPair)> {
         let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
 
-        // Generate new credential
-        let credential = BasicCredential::new(username.as_bytes().to_vec());
-
-        // Generate new signature key
-        let signature_keys = SignatureKeyPair::new(ciphersuite.signature_algorithm())
-            .map_err(|e| ClientError::Config(format!("Failed to generate signature key: {}", e)))?;
+        // Generate new signature key and credential; X.509 certificates are
+        // generated together with the key they certify
+        let (credential, signature_keys): (Credential, SignatureKeyPair) = match kind {
+            CredentialKind::Basic => {
+                let signature_keys = SignatureKeyPair::new(ciphersuite.signature_algorithm())
+                    .map_err(|e| {
+                        ClientError::Config(format!("Failed to generate signature key: {}", e))
+                    })?;
+                (
+                    BasicCredential::new(username.as_bytes().to_vec()).into(),
+                    signature_keys,
+                )
+            }
+            CredentialKind::X509 => {
+                let (certificate, signature_keys) = crate::x509::self_signed_identity(username)?;
+                (
+                    crate::x509::credential_from_chain(&[certificate])?,
+                    signature_keys,
+                )
+            }
+        };
 
-        // Store the signature key in the OpenMLS provider's storage
-        signature_keys
-            .store(provider.storage())
-            .map_err(|e| ClientError::Config(format!("Failed to store signature key: {}", e)))?;
+        // Store the signature key and credential in the keyring or the
+        // OpenMLS provider's storage
+        Self::store_secrets(
+            provider,
+            metadata_store,
+            username,
+            &credential,
+            &signature_keys,
+        )?;
 
         // Get public key to store in metadata
         let public_key_blob = signature_keys.to_public_vec();
 
         // Store identity in metadata store with public key
         // The public key is used to look up the signature key in OpenMLS provider storage
+        // or to check the one in the keyring
         metadata_store.save_identity(username, &public_key_blob)?;
 
         let credential_with_key = CredentialWithKey {
-            credential: credential.into(),
+            credential,
             signature_key: public_key_blob.into(),
         };
 
@@ -136,9 +563,18 @@ impl IdentityManager {
         let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
 
         // Check that public key is in metadata store
-        let public_key_in_metadata = metadata_store.load_public_key(&identity.username)?;
-        if public_key_in_metadata.is_none() {
+        let Some(public_key_in_metadata) = metadata_store.load_public_key(&identity.username)?
+        else {
             return Ok(false);
+        };
+
+        // Check the keyring, then the OpenMLS storage
+        if let Some(stored) =
+            Self::load_from_keyring(metadata_store, &identity.username, &public_key_in_metadata)
+        {
+            return Ok(
+                stored.signature_key.to_public_vec() == identity.signature_key.to_public_vec()
+            );
         }
 
         // Check that signature key is in OpenMLS storage
@@ -160,6 +596,7 @@ impl IdentityManager {
 mod tests {
     use super::*;
     use crate::storage::LocalStore;
+    use std::sync::Arc;
     use tempfile::tempdir;
     use tls_codec::Serialize;
 
@@ -348,4 +785,328 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_x509_identity_persists_certificate() {
+        let temp_dir = tempdir().unwrap();
+        let mls_db = temp_dir.path().join("mls.db");
+        let metadata_db = temp_dir.path().join("metadata.db");
+
+        let provider1 = MlsProvider::new(&mls_db).unwrap();
+        let metadata1 = LocalStore::new(&metadata_db).unwrap();
+        let identity1 = IdentityManager::load_or_create_with_kind(
+            &provider1,
+            &metadata1,
+            "carol",
+            CredentialKind::X509,
+        )
+        .unwrap();
+        assert_eq!(
+            identity1.credential_with_key.credential.credential_type(),
+            CredentialType::X509
+        );
+
+        // Reloading yields the same certificate, even if Basic is now requested
+        let provider2 = MlsProvider::new(&mls_db).unwrap();
+        let metadata2 = LocalStore::new(&metadata_db).unwrap();
+        let identity2 = IdentityManager::load_or_create(&provider2, &metadata2, "carol").unwrap();
+        assert_eq!(
+            identity1.credential_with_key.credential,
+            identity2.credential_with_key.credential
+        );
+        assert_eq!(
+            crate::crypto::credential_identity(&identity2.credential_with_key.credential)
+                .as_deref(),
+            Some("carol")
+        );
+    }
+
+    #[test]
+    fn test_import_x509_stores_an_issued_identity() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("mls.db");
+        let provider = MlsProvider::new(&db_path).unwrap();
+        let metadata_store = LocalStore::new(db_path.with_file_name("metadata.db")).unwrap();
+
+        let ca = crate::x509::TestCa::new("Example CA");
+        let (chain_pem, key_pem) = ca.issue("dave");
+
+        // Issued to someone else, or by an untrusted CA
+        let (other_pem, other_key) = ca.issue("erin");
+        assert!(IdentityManager::import_x509(
+            &provider,
+            &metadata_store,
+            "dave",
+            other_pem.as_bytes(),
+            &other_key,
+            &ca.anchors(),
+        )
+        .is_err());
+        let untrusted = crate::x509::TestCa::new("Other CA").anchors();
+        assert!(IdentityManager::import_x509(
+            &provider,
+            &metadata_store,
+            "dave",
+            chain_pem.as_bytes(),
+            &key_pem,
+            &untrusted,
+        )
+        .is_err());
+        assert!(IdentityManager::load(&provider, &metadata_store, "dave")
+            .unwrap()
+            .is_none());
+
+        let imported = IdentityManager::import_x509(
+            &provider,
+            &metadata_store,
+            "dave",
+            chain_pem.as_bytes(),
+            &key_pem,
+            &ca.anchors(),
+        )
+        .unwrap();
+        let loaded = IdentityManager::load(&provider, &metadata_store, "dave")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded.credential_with_key.credential,
+            imported.credential_with_key.credential
+        );
+        assert_eq!(
+            loaded.signature_key.public(),
+            imported.signature_key.public()
+        );
+
+        // An identity is never replaced by an import
+        assert!(IdentityManager::import_x509(
+            &provider,
+            &metadata_store,
+            "dave",
+            chain_pem.as_bytes(),
+            &key_pem,
+            &ca.anchors(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_rotate_replaces_the_stored_identity() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("mls.db");
+        let provider = MlsProvider::new(&db_path).unwrap();
+        let metadata_store = LocalStore::new(db_path.with_file_name("metadata.db")).unwrap();
+
+        assert!(IdentityManager::rotate(&provider, &metadata_store, "alice").is_err());
+
+        let old = IdentityManager::load_or_create(&provider, &metadata_store, "alice").unwrap();
+        let rotated = IdentityManager::rotate(&provider, &metadata_store, "alice").unwrap();
+        assert_ne!(
+            old.signature_key.to_public_vec(),
+            rotated.signature_key.to_public_vec()
+        );
+        assert_eq!(
+            rotated.credential_with_key.credential,
+            old.credential_with_key.credential
+        );
+
+        let loaded = IdentityManager::load(&provider, &metadata_store, "alice")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded.signature_key.to_public_vec(),
+            rotated.signature_key.to_public_vec()
+        );
+        assert!(SignatureKeyPair::read(
+            provider.storage(),
+            &old.signature_key.to_public_vec(),
+            Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519.signature_algorithm(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_public_bundle_matches_key_package_credential() {
+        let temp_dir = tempdir().unwrap();
+        let mls_db = temp_dir.path().join("mls.db");
+        let metadata_db = temp_dir.path().join("metadata.db");
+        let provider = MlsProvider::new(&mls_db).unwrap();
+        let metadata_store = LocalStore::new(&metadata_db).unwrap();
+
+        for (username, kind) in [
+            ("frank", CredentialKind::Basic),
+            ("grace", CredentialKind::X509),
+        ] {
+            let identity = IdentityManager::load_or_create_with_kind(
+                &provider,
+                &metadata_store,
+                username,
+                kind,
+            )
+            .unwrap();
+            let bundle =
+                IdentityManager::public_bundle(username, &identity.credential_with_key).unwrap();
+            assert_eq!(bundle.username, username);
+            assert_eq!(bundle.signature_key, identity.signature_key.to_public_vec());
+            assert_eq!(
+                bundle.credential_with_key().unwrap(),
+                identity.credential_with_key
+            );
+            assert_eq!(
+                bundle.fingerprint(&provider).unwrap(),
+                crate::crypto::credential_fingerprint(&provider, &identity.credential_with_key)
+                    .unwrap()
+            );
+
+            let key_package = crate::crypto::generate_key_package_bundle(
+                &identity.credential_with_key,
+                &identity.signature_key,
+                &provider,
+            )
+            .unwrap();
+            assert!(bundle.matches_key_package(key_package.key_package()));
+
+            // Serialized, it carries nothing beyond the public fields
+            let json = serde_json::to_value(&bundle).unwrap();
+            let mut fields: Vec<&str> = json
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            fields.sort();
+            assert_eq!(fields, vec!["credential", "signature_key", "username"]);
+        }
+
+        // Someone else's KeyPackage does not match
+        let frank = IdentityManager::load_or_create(&provider, &metadata_store, "frank").unwrap();
+        let other = IdentityManager::load_or_create(&provider, &metadata_store, "heidi").unwrap();
+        let other_key_package = crate::crypto::generate_key_package_bundle(
+            &other.credential_with_key,
+            &other.signature_key,
+            &provider,
+        )
+        .unwrap();
+        assert!(
+            !IdentityManager::public_bundle("frank", &frank.credential_with_key)
+                .unwrap()
+                .matches_key_package(other_key_package.key_package())
+        );
+    }
+
+    fn stored_in_provider(provider: &MlsProvider, identity: &StoredIdentity) -> bool {
+        SignatureKeyPair::read(
+            provider.storage(),
+            &identity.signature_key.to_public_vec(),
+            Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519.signature_algorithm(),
+        )
+        .is_some()
+    }
+
+    #[test]
+    fn test_identity_kept_in_keyring() {
+        let temp_dir = tempdir().unwrap();
+        let mls_db = temp_dir.path().join("mls.db");
+        let metadata_db = temp_dir.path().join("metadata.db");
+        let keyring = crate::secret_store::MemoryKeyring::default();
+
+        let provider = MlsProvider::new(&mls_db).unwrap();
+        let mut metadata_store = LocalStore::new(&metadata_db).unwrap();
+        metadata_store.set_identity_keyring(Some(Arc::new(keyring.clone())));
+        let created = IdentityManager::load_or_create_with_kind(
+            &provider,
+            &metadata_store,
+            "alice",
+            CredentialKind::X509,
+        )
+        .unwrap();
+        assert!(keyring.contains("alice"));
+        assert!(!stored_in_provider(&provider, &created));
+        assert!(provider.load_credential("alice").unwrap().is_none());
+        assert!(IdentityManager::verify_stored(&provider, &metadata_store, &created).unwrap());
+
+        // A new session with the same keyring loads the same identity
+        let provider = MlsProvider::new(&mls_db).unwrap();
+        let mut metadata_store = LocalStore::new(&metadata_db).unwrap();
+        metadata_store.set_identity_keyring(Some(Arc::new(keyring.clone())));
+        let loaded = IdentityManager::load(&provider, &metadata_store, "alice")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded.signature_key.to_public_vec(),
+            created.signature_key.to_public_vec()
+        );
+        assert_eq!(
+            loaded.credential_with_key.credential,
+            created.credential_with_key.credential
+        );
+
+        // Without the keyring the MLS database alone does not hold it
+        let metadata_store = LocalStore::new(&metadata_db).unwrap();
+        assert!(IdentityManager::load(&provider, &metadata_store, "alice")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_unavailable_keyring_falls_back_to_file() {
+        let temp_dir = tempdir().unwrap();
+        let mls_db = temp_dir.path().join("mls.db");
+        let metadata_db = temp_dir.path().join("metadata.db");
+
+        let provider = MlsProvider::new(&mls_db).unwrap();
+        let mut metadata_store = LocalStore::new(&metadata_db).unwrap();
+        metadata_store.set_identity_keyring(Some(Arc::new(
+            crate::secret_store::MemoryKeyring::unavailable(),
+        )));
+        let created = IdentityManager::load_or_create(&provider, &metadata_store, "bob").unwrap();
+        assert!(stored_in_provider(&provider, &created));
+
+        let loaded = IdentityManager::load(&provider, &metadata_store, "bob")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded.signature_key.to_public_vec(),
+            created.signature_key.to_public_vec()
+        );
+        assert!(IdentityManager::verify_stored(&provider, &metadata_store, &loaded).unwrap());
+    }
+
+    #[test]
+    fn test_file_identity_moves_to_keyring() {
+        let temp_dir = tempdir().unwrap();
+        let mls_db = temp_dir.path().join("mls.db");
+        let metadata_db = temp_dir.path().join("metadata.db");
+
+        let provider = MlsProvider::new(&mls_db).unwrap();
+        let mut metadata_store = LocalStore::new(&metadata_db).unwrap();
+        let created = IdentityManager::load_or_create_with_kind(
+            &provider,
+            &metadata_store,
+            "carol",
+            CredentialKind::X509,
+        )
+        .unwrap();
+        assert!(stored_in_provider(&provider, &created));
+
+        let keyring = crate::secret_store::MemoryKeyring::default();
+        metadata_store.set_identity_keyring(Some(Arc::new(keyring.clone())));
+        let moved = IdentityManager::load(&provider, &metadata_store, "carol")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            moved.credential_with_key.credential,
+            created.credential_with_key.credential
+        );
+        assert!(keyring.contains("carol"));
+        assert!(!stored_in_provider(&provider, &created));
+        assert!(provider.load_credential("carol").unwrap().is_none());
+
+        let loaded = IdentityManager::load(&provider, &metadata_store, "carol")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded.signature_key.to_public_vec(),
+            created.signature_key.to_public_vec()
+        );
+    }
 }
@@ -2,19 +2,36 @@
 //! Provides MLS group messaging functionality with OpenMLS
 
 pub mod api;
+pub mod archive;
 pub mod cli;
 pub mod client;
 pub mod crypto;
+pub mod envelope;
 pub mod error;
+pub mod events;
 pub mod extensions;
+pub mod framing;
 pub mod identity;
 pub mod message_processing;
+pub mod metrics;
 pub mod mls;
 pub mod models;
+pub mod payload;
+pub mod plaintext_log;
 pub mod provider;
+pub mod secret_store;
 pub mod storage;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod test_rng;
+pub mod transform;
 pub mod websocket;
+pub mod x509;
 
 pub use error::{ClientError, Result};
-pub use identity::{IdentityManager, StoredIdentity};
-pub use provider::MlsProvider;
+pub use identity::{IdentityManager, PublicIdentityBundle, StoredIdentity};
+pub use provider::{MlsProvider, StorageSpec};
+pub use secret_store::{IdentityStorage, MemoryKeyring, SecretStore};
+pub use storage::{
+    OutboundQueueConfig, QueueOverflowPolicy, QueuedMessage, StorageTuning, SynchronousLevel,
+};
+pub use transform::{MessageTransform, MessageTransforms, NoopTransform};
@@ -1,36 +1,15 @@
 /// MLS Chat Client - Main entry point
 ///
-/// A command-line client for MLS group messaging using OpenMLS
+/// A command-line client for MLS group messaging using OpenMLS.
+/// Each capability is a subcommand (see `cli::CliCommand`); the legacy
+/// `mls-client <GROUP_NAME> <USERNAME>` form runs the interactive loop.
 use clap::Parser;
-use log::info;
-use mls_chat_client::{cli, client::MlsClient, Result};
-
-#[derive(Parser)]
-#[command(name = "mls-client")]
-#[command(about = "MLS Chat Client - Secure group messaging")]
-struct Args {
-    /// Server URL (default: http://localhost:4000)
-    #[arg(long, default_value = "http://localhost:4000")]
-    server: String,
-
-    /// Config directory for state database (default: ~/.mlschat)
-    #[arg(long)]
-    config: Option<String>,
-
-    /// Group name to join or create
-    group_name: String,
-
-    /// Username for this client
-    username: String,
-
-    /// Enable verbose logging (DEBUG level)
-    #[arg(short, long)]
-    verbose: bool,
-}
+use log::{info, warn};
+use mls_chat_client::{cli, plaintext_log, Result};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = cli::Args::parse();
 
     // Initialize logger with appropriate level based on verbose flag
     let log_level = if args.verbose {
@@ -44,43 +23,22 @@ async fn main() -> Result<()> {
         .format_timestamp_millis()
         .init();
 
+    if args.unsafe_debug_plaintext {
+        plaintext_log::enable_unsafe_debug_plaintext()?;
+        warn!("--unsafe-debug-plaintext: decrypted message text will appear in logs");
+    }
+
     info!("Starting MLS Chat Client");
     info!("Server: {}", args.server);
-    info!("Group: {}", args.group_name);
-    info!("Username: {}", args.username);
 
     // Determine storage directory
-    let storage_dir = if let Some(config_path) = args.config {
-        std::path::PathBuf::from(config_path)
-    } else {
-        // Default to ~/.mlschat
-        use directories::BaseDirs;
-        let base_dirs = BaseDirs::new().ok_or_else(|| {
-            mls_chat_client::error::ClientError::Config("Failed to get home directory".to_string())
-        })?;
-        base_dirs.home_dir().join(".mlschat")
-    };
-
+    let storage_dir = args.storage_dir()?;
     info!("Config directory: {}", storage_dir.display());
 
-    // Create client
-    let mut client = MlsClient::new_with_storage_path(
-        &args.server,
-        &args.username,
-        &args.group_name,
-        &storage_dir,
-    )?;
-
-    // Initialize (load or create identity, register with server)
-    client.initialize().await?;
-
-    // Connect to group (create or load existing)
-    // Note: group_name is used to create the initial group if it doesn't exist
-    // After Welcome messages are processed, the client may switch to a different group
-    client.connect_to_group(&args.group_name).await?;
-
-    // Run the client control loop
-    cli::run_client_loop(&mut client).await?;
+    let server = args.server.clone();
+    let display = args.display_config();
+    let command = args.into_command()?;
+    info!("Command: {:?}", command.redacted());
 
-    Ok(())
+    cli::execute_command_with_display(command, &server, &storage_dir, display).await
 }
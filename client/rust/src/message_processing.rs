@@ -6,11 +6,12 @@
 //! - Support for all MLS message types
 //! - Detailed logging and debugging
 
+use crate::crypto::ProcessedContent;
 use crate::error::{ClientError, Result};
-use crate::models::IncomingMessage;
-use base64::{engine::general_purpose, Engine as _};
+use crate::models::{IncomingMessage, Quote};
+use crate::payload::TextFormat;
+use crate::x509::TrustAnchors;
 use openmls::prelude::*;
-use tls_codec::Deserialize;
 
 /// Process a single incoming message envelope
 ///
@@ -34,18 +35,10 @@ pub async fn process_incoming_message(
     group: &mut MlsGroup,
     provider: &impl OpenMlsProvider,
 ) -> Result<ProcessedMessage> {
-    // Decode base64-encoded MLS message
-    let encrypted_bytes = general_purpose::STANDARD
-        .decode(&envelope.encrypted_content)
-        .map_err(|e| {
-            log::error!("Failed to decode base64 message: {}", e);
-            ClientError::Mls(crate::error::MlsError::DecryptionFailed)
-        })?;
-
-    // Deserialize the MLS message
+    // Decode the base64, TLS-encoded MLS message
     let message_in =
-        MlsMessageIn::tls_deserialize(&mut encrypted_bytes.as_slice()).map_err(|e| {
-            log::error!("Failed to deserialize MLS message: {}", e);
+        crate::envelope::decode_mls_blob(&envelope.encrypted_content).map_err(|e| {
+            log::error!("Failed to decode MLS message: {}", e);
             ClientError::Mls(crate::error::MlsError::DecryptionFailed)
         })?;
 
@@ -78,13 +71,13 @@ pub fn handle_processed_message(
     match processed_msg.into_content() {
         ProcessedMessageContent::ApplicationMessage(app_msg) => {
             // Extract the actual plaintext from the application message
-            let plaintext = app_msg.into_bytes();
+            let plaintext = crate::payload::decode(&app_msg.into_bytes())?;
             let message_text = String::from_utf8_lossy(&plaintext).to_string();
 
             log::debug!(
                 "Successfully decrypted message from {}: {}",
                 envelope.sender,
-                message_text
+                crate::plaintext_log::redact(&message_text)
             );
             Ok(Some(message_text))
         }
@@ -109,6 +102,10 @@ pub fn handle_processed_message(
 
 /// Process an application message (encrypted plaintext)
 ///
+/// The decrypted plaintext is unframed with `payload::decode`, so
+/// compressed messages are returned decompressed. No trust anchors are
+/// applied to X.509 senders; use `decrypt_application_message` for that.
+///
 /// # Arguments
 /// * `sender` - Username of the message sender
 /// * `group_id` - ID of the group
@@ -118,7 +115,7 @@ pub fn handle_processed_message(
 ///
 /// # Returns
 /// * `Ok(Some(message_text))` if message was successfully decrypted
-/// * `Ok(None)` if message was not an application message
+/// * `Ok(None)` if message was not an application message (commits are merged)
 /// * `Err(...)` for processing errors
 pub async fn process_application_message(
     sender: &str,
@@ -127,36 +124,99 @@ pub async fn process_application_message(
     group: &mut MlsGroup,
     provider: &impl OpenMlsProvider,
 ) -> Result<Option<String>> {
-    // Decode base64-encoded MLS message
-    let encrypted_bytes = general_purpose::STANDARD
-        .decode(encrypted_content)
-        .map_err(|e| {
-            log::error!("Failed to decode base64 message: {}", e);
-            ClientError::Mls(crate::error::MlsError::DecryptionFailed)
-        })?;
+    let message = decrypt_application_message(
+        sender,
+        group_id,
+        encrypted_content,
+        group,
+        provider,
+        &TrustAnchors::default(),
+    )
+    .await?;
+    Ok(message.map(|message| message.text))
+}
 
-    // Deserialize the MLS message
-    let message_in =
-        MlsMessageIn::tls_deserialize(&mut encrypted_bytes.as_slice()).map_err(|e| {
-            log::error!("Failed to deserialize MLS message: {}", e);
-            ClientError::Mls(crate::error::MlsError::DecryptionFailed)
-        })?;
+/// An application message after decryption
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptedMessage {
+    /// Username from the credential that signed the message (not the
+    /// envelope's `sender`, which the server could change)
+    pub sender_identity: String,
+    /// Fingerprint of the signer's credential and key (see
+    /// `crypto::credential_fingerprint`)
+    pub sender_fingerprint: Option<String>,
+    /// Rendering requested by the sender
+    pub format: TextFormat,
+    pub text: String,
+    /// Snapshot of the message this one replies to
+    pub quote: Option<Quote>,
+}
+
+/// Process an application message, keeping what MLS authenticated about it
+///
+/// Like `process_application_message`; the sender identity and the format
+/// come from the decrypted (and therefore authenticated) message, and X.509
+/// senders must chain to one of `anchors`.
+pub async fn decrypt_application_message(
+    sender: &str,
+    group_id: &str,
+    encrypted_content: &str,
+    group: &mut MlsGroup,
+    provider: &impl OpenMlsProvider,
+    anchors: &TrustAnchors,
+) -> Result<Option<DecryptedMessage>> {
+    // Decode the base64, TLS-encoded MLS message
+    let message_in = crate::envelope::decode_mls_blob(encrypted_content).map_err(|e| {
+        log::error!("Failed to decode MLS message: {}", e);
+        ClientError::Mls(crate::error::MlsError::DecryptionFailed)
+    })?;
 
     // Process the message using the persistent group state
-    let processed_msg =
-        crate::crypto::process_message(group, provider, &message_in).map_err(|e| {
+    let content = crate::crypto::process_envelope_content(group, provider, &message_in, anchors)
+        .map_err(|e| {
             log::error!("Failed to process message: {}", e);
             e
         })?;
 
-    // Handle the processed message
-    let envelope = IncomingMessage {
-        sender: sender.to_string(),
-        group_id: group_id.to_string(),
-        encrypted_content: encrypted_content.to_string(),
-    };
-
-    handle_processed_message(&envelope, processed_msg)
+    match content {
+        ProcessedContent::Application {
+            sender_identity,
+            sender_fingerprint,
+            plaintext,
+        } => {
+            if sender_identity != sender {
+                log::warn!(
+                    "Message in group {} relayed as from {} but signed by {}",
+                    group_id,
+                    sender,
+                    sender_identity
+                );
+            }
+            let (format, message, quote) = crate::payload::decode_reply(&plaintext)?;
+            Ok(Some(DecryptedMessage {
+                sender_identity,
+                sender_fingerprint,
+                format,
+                text: String::from_utf8_lossy(&message).to_string(),
+                quote,
+            }))
+        }
+        ProcessedContent::MembershipChange {
+            sender_identity,
+            added,
+            removed,
+        } => {
+            log::info!(
+                "Merged commit from {} in group {} (added: {:?}, removed: {:?})",
+                sender_identity,
+                group_id,
+                added,
+                removed
+            );
+            Ok(None)
+        }
+        ProcessedContent::Other => Ok(None),
+    }
 }
 
 /// Format a message for display
@@ -173,7 +233,7 @@ pub async fn process_application_message(
 /// # Returns
 /// * Formatted message string in format: #groupname <username> message
 pub fn format_display_message(group_name: &str, sender: &str, message: &str) -> String {
-    format!("#{} <{}> {}", group_name, sender, message)
+    DisplayConfig::default().format_message(group_name, sender, message, 0, 0)
 }
 
 /// Format a control message for display
@@ -189,7 +249,262 @@ pub fn format_display_message(group_name: &str, sender: &str, message: &str) ->
 /// # Returns
 /// * Formatted control message string in format: #groupname action
 pub fn format_control_message(group_name: &str, action: &str) -> String {
-    format!("#{} {}", group_name, action)
+    DisplayConfig::default().format_control(group_name, action, 0, 0)
+}
+
+/// Format an admin announcement for display
+///
+/// Displays announcements in the format: #groupname [announcement] <admin> text
+pub fn format_announcement(group_name: &str, sender: &str, text: &str) -> String {
+    DisplayConfig::default().format_announcement(group_name, sender, text, 0, 0)
+}
+
+/// How the time of a displayed line is shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampStyle {
+    /// No timestamp
+    #[default]
+    Off,
+    /// Age of the line, e.g. `[5m ago]`
+    Relative,
+    /// UTC date and time, e.g. `[2026-10-16 09:30:00]`
+    Absolute,
+}
+
+/// Options for the lines the CLI prints for groups
+///
+/// The default reproduces the plain `#group <sender> text` format. The
+/// formatting methods are pure: the line's time and the current time are
+/// passed in as Unix seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayConfig {
+    pub timestamps: TimestampStyle,
+    /// Prefix lines with `#groupname`
+    pub show_group: bool,
+    /// Style the timestamp, group and sender with ANSI colors
+    pub color: bool,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            timestamps: TimestampStyle::Off,
+            show_group: true,
+            color: false,
+        }
+    }
+}
+
+const DIM: (&str, &str) = ("\x1b[2m", "\x1b[22m");
+const GROUP: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+const SENDER: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+
+impl DisplayConfig {
+    /// Format a chat message sent at `sent_at`
+    pub fn format_message(
+        &self,
+        group_name: &str,
+        sender: &str,
+        text: &str,
+        sent_at: i64,
+        now: i64,
+    ) -> String {
+        let body = format!("{} {}", self.sender(sender), text);
+        self.line(group_name, &body, sent_at, now)
+    }
+
+    /// Format a control message (membership change, status) that happened at `at`
+    pub fn format_control(&self, group_name: &str, action: &str, at: i64, now: i64) -> String {
+        self.line(group_name, action, at, now)
+    }
+
+    /// Format an admin announcement sent at `sent_at`
+    pub fn format_announcement(
+        &self,
+        group_name: &str,
+        sender: &str,
+        text: &str,
+        sent_at: i64,
+        now: i64,
+    ) -> String {
+        let body = format!("[announcement] {} {}", self.sender(sender), text);
+        self.line(group_name, &body, sent_at, now)
+    }
+
+    fn line(&self, group_name: &str, body: &str, at: i64, now: i64) -> String {
+        let mut line = String::new();
+        if let Some(timestamp) = format_timestamp(self.timestamps, at, now) {
+            line.push_str(&self.paint(DIM, &format!("[{}]", timestamp)));
+            line.push(' ');
+        }
+        if self.show_group {
+            line.push_str(&self.paint(GROUP, &format!("#{}", group_name)));
+            line.push(' ');
+        }
+        line.push_str(body);
+        line
+    }
+
+    fn sender(&self, sender: &str) -> String {
+        self.paint(SENDER, &format!("<{}>", sender))
+    }
+
+    fn paint(&self, (on, off): (&str, &str), text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", on, text, off)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Timestamp of a line at `at` seen at `now`, or `None` when timestamps are off
+pub(crate) fn format_timestamp(style: TimestampStyle, at: i64, now: i64) -> Option<String> {
+    match style {
+        TimestampStyle::Off => None,
+        TimestampStyle::Relative => {
+            let age = now.saturating_sub(at);
+            Some(match age {
+                i64::MIN..=59 => "just now".to_string(),
+                60..=3599 => format!("{}m ago", age / 60),
+                3600..=86399 => format!("{}h ago", age / 3600),
+                _ => format!("{}d ago", age / 86400),
+            })
+        }
+        TimestampStyle::Absolute => Some(
+            chrono::DateTime::from_timestamp(at, 0)
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| at.to_string()),
+        ),
+    }
+}
+
+/// Render a message body for the terminal according to its `TextFormat`
+///
+/// Markdown support is minimal: `**bold**`, `*italic*` and `` `code` `` are
+/// turned into ANSI styles, and anything else is shown as written.
+pub fn render_text(text: &str, format: TextFormat) -> String {
+    match format {
+        TextFormat::Plain => text.to_string(),
+        TextFormat::Markdown => render_markdown(text),
+    }
+}
+
+/// Append the quoted block of a reply below its rendered body
+///
+/// The block is one line, `  > author: text`; whitespace runs in the
+/// snapshot, line breaks included, become single spaces. Without a quote the
+/// body is returned as is.
+pub fn render_quote(body: &str, quote: Option<&Quote>) -> String {
+    match quote {
+        Some(quote) => format!(
+            "{}\n  > {}: {}",
+            body,
+            quote.author,
+            quote.text.split_whitespace().collect::<Vec<_>>().join(" ")
+        ),
+        None => body.to_string(),
+    }
+}
+
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const ITALIC: (&str, &str) = ("\x1b[3m", "\x1b[23m");
+const CODE: (&str, &str) = ("\x1b[7m", "\x1b[27m");
+
+fn render_markdown(text: &str) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let span = match c {
+            '`' => render_span(rest, "`", CODE, false),
+            '*' if rest.starts_with("**") => render_span(rest, "**", BOLD, true),
+            '*' => render_span(rest, "*", ITALIC, true),
+            _ => None,
+        };
+        match span {
+            Some((span, consumed)) => {
+                rendered.push_str(&span);
+                rest = &rest[consumed..];
+            }
+            None => {
+                rendered.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    rendered
+}
+
+/// Style the span `text` opens with `marker`, returning it and the bytes it
+/// used; `None` if it is not closed or its content is empty or padded with
+/// whitespace (so `2 * 3 * 4` stays as written)
+fn render_span(
+    text: &str,
+    marker: &str,
+    (on, off): (&str, &str),
+    nested: bool,
+) -> Option<(String, usize)> {
+    let body = &text[marker.len()..];
+    let end = body.find(marker)?;
+    let inner = &body[..end];
+    if inner.is_empty() || inner.trim() != inner {
+        return None;
+    }
+    let inner = if nested {
+        render_markdown(inner)
+    } else {
+        inner.to_string()
+    };
+    Some((format!("{}{}{}", on, inner, off), marker.len() * 2 + end))
+}
+
+/// Describe a merged commit's membership changes as system messages
+///
+/// All arguments should come from the authenticated staged commit
+/// (see `crypto::process_envelope_content`), not from the envelope.
+///
+/// # Returns
+/// * e.g. `["alice added bob, carol", "alice removed dave"]`; empty when the
+///   commit neither added nor removed anyone
+pub fn membership_change_notices(
+    committer: &str,
+    added: &[String],
+    removed: &[String],
+) -> Vec<String> {
+    let mut notices = Vec::new();
+    if !added.is_empty() {
+        notices.push(format!("{} added {}", committer, added.join(", ")));
+    }
+    if !removed.is_empty() {
+        notices.push(format!("{} removed {}", committer, removed.join(", ")));
+    }
+    notices
+}
+
+/// Build the system message for a change of the group's message TTL
+///
+/// # Returns
+/// * e.g. `"alice set messages to disappear after 1h"`, or
+///   `"alice turned off disappearing messages"` for `None`
+pub fn message_ttl_notice(committer: &str, ttl_secs: Option<u64>) -> String {
+    match ttl_secs {
+        Some(ttl) => format!(
+            "{} set messages to disappear after {}",
+            committer,
+            format_ttl(ttl)
+        ),
+        None => format!("{} turned off disappearing messages", committer),
+    }
+}
+
+/// Format a TTL with the largest unit that divides it exactly (e.g. `90s`, `2h`, `7d`)
+fn format_ttl(ttl_secs: u64) -> String {
+    match ttl_secs {
+        s if s >= 86_400 && s % 86_400 == 0 => format!("{}d", s / 86_400),
+        s if s >= 3_600 && s % 3_600 == 0 => format!("{}h", s / 3_600),
+        s if s >= 60 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
 }
 
 #[cfg(test)]
@@ -197,8 +512,9 @@ mod tests {
     use super::*;
     use crate::crypto;
     use crate::provider::MlsProvider;
+    use base64::{engine::general_purpose, Engine as _};
     use tempfile::tempdir;
-    use tls_codec::Serialize;
+    use tls_codec::{Deserialize, Serialize};
 
     #[test]
     fn test_extract_plaintext() {
@@ -220,6 +536,151 @@ mod tests {
         assert_eq!(formatted, "#mygroup <bob> How are you?");
     }
 
+    #[test]
+    fn test_format_announcement() {
+        assert_eq!(
+            format_announcement("team", "alice", "Offsite on Friday"),
+            "#team [announcement] <alice> Offsite on Friday"
+        );
+    }
+
+    #[test]
+    fn test_display_config_group_prefix_and_color() {
+        let no_group = DisplayConfig {
+            show_group: false,
+            ..DisplayConfig::default()
+        };
+        assert_eq!(
+            no_group.format_message("g", "alice", "hi", 0, 0),
+            "<alice> hi"
+        );
+        assert_eq!(
+            no_group.format_control("g", "bob joined", 0, 0),
+            "bob joined"
+        );
+
+        let color = DisplayConfig {
+            color: true,
+            ..DisplayConfig::default()
+        };
+        assert_eq!(
+            color.format_message("g", "alice", "hi", 0, 0),
+            "\x1b[36m#g\x1b[39m \x1b[1m<alice>\x1b[22m hi"
+        );
+        assert_eq!(
+            color.format_announcement("g", "alice", "hi", 0, 0),
+            "\x1b[36m#g\x1b[39m [announcement] \x1b[1m<alice>\x1b[22m hi"
+        );
+    }
+
+    #[test]
+    fn test_display_config_relative_timestamps() {
+        let config = DisplayConfig {
+            timestamps: TimestampStyle::Relative,
+            ..DisplayConfig::default()
+        };
+        let now = 1_000_000;
+        assert_eq!(
+            config.format_message("g", "alice", "hi", now - 30, now),
+            "[just now] #g <alice> hi"
+        );
+        assert_eq!(
+            config.format_message("g", "alice", "hi", now - 5 * 60, now),
+            "[5m ago] #g <alice> hi"
+        );
+        assert_eq!(
+            config.format_control("g", "bob joined", now - 3 * 3600, now),
+            "[3h ago] #g bob joined"
+        );
+        assert_eq!(
+            config.format_announcement("g", "alice", "hi", now - 2 * 86400, now),
+            "[2d ago] #g [announcement] <alice> hi"
+        );
+        // Clock skew puts the message in the future
+        assert_eq!(
+            config.format_message("g", "alice", "hi", now + 10, now),
+            "[just now] #g <alice> hi"
+        );
+    }
+
+    #[test]
+    fn test_display_config_absolute_timestamps() {
+        let config = DisplayConfig {
+            timestamps: TimestampStyle::Absolute,
+            show_group: true,
+            color: true,
+        };
+        assert_eq!(
+            config.format_message("g", "alice", "hi", 86_400 + 3661, 0),
+            "\x1b[2m[1970-01-02 01:01:01]\x1b[22m \x1b[36m#g\x1b[39m \x1b[1m<alice>\x1b[22m hi"
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        assert_eq!(
+            render_text("**hi** *there* `x*y*`", TextFormat::Markdown),
+            "\x1b[1mhi\x1b[22m \x1b[3mthere\x1b[23m \x1b[7mx*y*\x1b[27m"
+        );
+        assert_eq!(
+            render_text("**bold *and* italic**", TextFormat::Markdown),
+            "\x1b[1mbold \x1b[3mand\x1b[23m italic\x1b[22m"
+        );
+
+        // Unclosed or spaced-out markers are shown as written
+        for text in ["2 * 3 * 4", "**open", "`", "** **", "ünï*cödé"] {
+            assert_eq!(render_text(text, TextFormat::Markdown), text);
+        }
+
+        // Plain text is never styled
+        assert_eq!(render_text("**hi**", TextFormat::Plain), "**hi**");
+    }
+
+    #[test]
+    fn test_render_quote() {
+        let quote = Quote::new("alice", "lunch\nat noon?");
+        assert_eq!(
+            render_quote("yes", Some(&quote)),
+            "yes\n  > alice: lunch at noon?"
+        );
+        assert_eq!(render_quote("yes", None), "yes");
+    }
+
+    #[test]
+    fn test_message_ttl_notice() {
+        assert_eq!(
+            message_ttl_notice("alice", Some(3600)),
+            "alice set messages to disappear after 1h"
+        );
+        assert_eq!(
+            message_ttl_notice("bob", Some(90)),
+            "bob set messages to disappear after 90s"
+        );
+        assert_eq!(
+            message_ttl_notice("bob", Some(7 * 86_400)),
+            "bob set messages to disappear after 7d"
+        );
+        assert_eq!(
+            message_ttl_notice("carol", None),
+            "carol turned off disappearing messages"
+        );
+    }
+
+    #[test]
+    fn test_membership_change_notices() {
+        let added = vec!["bob".to_string(), "carol".to_string()];
+        let removed = vec!["dave".to_string()];
+        assert_eq!(
+            membership_change_notices("alice", &added, &removed),
+            vec!["alice added bob, carol", "alice removed dave"]
+        );
+        assert_eq!(
+            membership_change_notices("alice", &[], &removed),
+            vec!["alice removed dave"]
+        );
+        assert!(membership_change_notices("alice", &[], &[]).is_empty());
+    }
+
     #[tokio::test]
     async fn test_process_application_message_basic() {
         let temp_dir = tempdir().unwrap();
@@ -0,0 +1,210 @@
+//! Message counters for capacity planning
+//!
+//! Counts are only recorded in builds with the `metrics` feature; without it
+//! every `record_*` call is a no-op and snapshots stay at zero. Rates are
+//! left to whoever reads the counters, e.g. Prometheus `rate()` over a
+//! scraped `MetricsSnapshot::to_prometheus`.
+//!
+//! Only counts and sizes of encrypted messages are kept. Metric names and
+//! labels are fixed strings, so no plaintext, username, group id or key
+//! material can ever end up in a metric.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Upper bounds in bytes of the message size histogram buckets
+pub const SIZE_BUCKETS: [u64; 7] = [256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+/// Point-in-time copy of a connection's message counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Application messages encrypted and sent, control messages included
+    pub messages_sent: u64,
+    /// Application messages from other members taken up for decryption
+    pub messages_received: u64,
+    /// Total size of the sent MLS messages in bytes
+    pub bytes_sent: u64,
+    /// Total size of the received MLS messages in bytes
+    pub bytes_received: u64,
+    /// Received application messages that failed to decrypt
+    pub decryption_failures: u64,
+    /// Sent and received messages per size bucket (`SIZE_BUCKETS`, then
+    /// larger ones); not cumulative
+    pub size_buckets: [u64; SIZE_BUCKETS.len() + 1],
+}
+
+impl MetricsSnapshot {
+    /// Render the snapshot in the Prometheus text exposition format (0.0.4)
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "mls_chat_client_messages_sent_total",
+                "Application messages sent",
+                self.messages_sent,
+            ),
+            (
+                "mls_chat_client_messages_received_total",
+                "Application messages received from other members",
+                self.messages_received,
+            ),
+            (
+                "mls_chat_client_decryption_failures_total",
+                "Received application messages that failed to decrypt",
+                self.decryption_failures,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let name = "mls_chat_client_message_size_bytes";
+        let _ = writeln!(
+            out,
+            "# HELP {} Size of sent and received MLS messages in bytes",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (i, count) in self.size_buckets.iter().enumerate() {
+            cumulative += count;
+            let le = SIZE_BUCKETS
+                .get(i)
+                .map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.bytes_sent + self.bytes_received
+        );
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+        out
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    decryption_failures: AtomicU64,
+    size_buckets: [AtomicU64; SIZE_BUCKETS.len() + 1],
+}
+
+/// Message counters shared by a connection and its memberships
+///
+/// Clones share the same counters. Each update is a few relaxed atomic
+/// additions.
+#[derive(Clone, Default)]
+pub struct MessageMetrics {
+    counters: Arc<Counters>,
+}
+
+impl MessageMetrics {
+    /// Whether this build records metrics (the `metrics` feature)
+    pub const ENABLED: bool = cfg!(feature = "metrics");
+
+    /// Count a sent MLS message of `size` bytes
+    pub fn record_sent(&self, size: usize) {
+        if Self::ENABLED {
+            self.counters.messages_sent.fetch_add(1, Ordering::Relaxed);
+            self.counters
+                .bytes_sent
+                .fetch_add(size as u64, Ordering::Relaxed);
+            self.observe_size(size);
+        }
+    }
+
+    /// Count a received MLS message of `size` bytes
+    pub fn record_received(&self, size: usize) {
+        if Self::ENABLED {
+            self.counters
+                .messages_received
+                .fetch_add(1, Ordering::Relaxed);
+            self.counters
+                .bytes_received
+                .fetch_add(size as u64, Ordering::Relaxed);
+            self.observe_size(size);
+        }
+    }
+
+    /// Count a received message that failed to decrypt
+    pub fn record_decryption_failure(&self) {
+        if Self::ENABLED {
+            self.counters
+                .decryption_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Current values of all counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let counters = &self.counters;
+        MetricsSnapshot {
+            messages_sent: counters.messages_sent.load(Ordering::Relaxed),
+            messages_received: counters.messages_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            decryption_failures: counters.decryption_failures.load(Ordering::Relaxed),
+            size_buckets: std::array::from_fn(|i| counters.size_buckets[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn observe_size(&self, size: usize) {
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|bound| size as u64 <= *bound)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.counters.size_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_and_exposition() {
+        let metrics = MessageMetrics::default();
+        let shared = metrics.clone();
+        metrics.record_sent(300);
+        shared.record_received(100);
+        shared.record_received(2_000_000);
+        shared.record_decryption_failure();
+
+        let snapshot = metrics.snapshot();
+        let text = snapshot.to_prometheus();
+        if !MessageMetrics::ENABLED {
+            assert_eq!(snapshot, MetricsSnapshot::default());
+            return;
+        }
+        assert_eq!(snapshot.messages_sent, 1);
+        assert_eq!(snapshot.messages_received, 2);
+        assert_eq!(snapshot.bytes_received, 2_000_100);
+        assert_eq!(snapshot.decryption_failures, 1);
+        assert_eq!(snapshot.size_buckets, [1, 1, 0, 0, 0, 0, 0, 1]);
+
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "mls_chat_client_messages_sent_total 1",
+            "mls_chat_client_decryption_failures_total 1",
+            "mls_chat_client_message_size_bytes_bucket{le=\"1024\"} 2",
+            "mls_chat_client_message_size_bytes_bucket{le=\"+Inf\"} 3",
+            "mls_chat_client_message_size_bytes_count 3",
+        ] {
+            assert!(lines.contains(&line), "missing {}", line);
+        }
+        // Every sample is `name{labels} value` with a numeric value
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            value.parse::<f64>().unwrap();
+            assert!(series.starts_with("mls_chat_client_"));
+        }
+    }
+}
@@ -41,27 +41,56 @@
 //! # }
 //! ```
 
-use crate::api::{KeyPackageUpload, ServerApi};
-use crate::crypto;
-use crate::error::{ClientError, MlsError, Result};
-use crate::identity::IdentityManager;
+use crate::api::{JoinToken, KeyPackageUpload, ServerApi, ServerApiConfig};
+use crate::archive::GroupArchive;
+use crate::crypto::{self, CredentialKind};
+use crate::error::{ClientError, MlsError, Result, StorageError};
+use crate::events::{GroupEvent, GroupEvents, ObserverId};
+use crate::framing::Framing;
+use crate::identity::{IdentityManager, PublicIdentityBundle};
+use crate::message_processing::DisplayConfig;
+use crate::metrics::{MessageMetrics, MetricsSnapshot};
 use crate::mls::keypackage_pool::{KeyPackagePool, KeyPackagePoolConfig};
-use crate::mls::membership::MlsMembership;
+use crate::mls::membership::{MlsMembership, DEFAULT_MAX_HELD_ENVELOPES};
 use crate::mls::user::MlsUser;
-use crate::models::{Identity, MlsMessageEnvelope};
-use crate::provider::MlsProvider;
-use crate::storage::{KeyPackageMetadata, LocalStore};
-use crate::websocket::MessageHandler;
+use crate::models::{
+    sort_group_listings, CancelOutcome, ContentType, GroupListing, GroupMapping, GroupSort,
+    GroupStatus, GroupSummary, HistoryEntry, Identity, IdentityInfo, IntegrityReport,
+    LostGroupPolicy, MemberActivity, MessageSearch, MlsMessageEnvelope, PendingInvitation,
+    PendingInvite, PendingOp, PendingOpId, RosterComparison, SearchHit, SelfTestCheck,
+    SelfTestReport, SelfTestResult, SendConcurrency, StorageStats, WelcomePolicy,
+};
+use crate::payload::TextFormat;
+use crate::provider::{MlsProvider, StorageSpec};
+use crate::secret_store::{IdentityStorage, SecretStore};
+use crate::storage::{
+    KeyPackageMetadata, LocalStore, OutboundQueueConfig, QueuedMessage, StagedWelcome,
+    StorageTuning,
+};
+use crate::transform::{MessageTransform, MessageTransforms};
+use crate::websocket::{
+    ConnectionState, MessageHandler, ReconnectBackoff, SharedConnectionState, StateCallback,
+    StateCallbackId,
+};
+use crate::x509::TrustAnchors;
 use base64::{engine::general_purpose, Engine as _};
-use openmls::prelude::KeyPackageBundle;
+use openmls::prelude::{GroupId, KeyPackageBundle};
 use openmls_traits::storage::traits as storage_traits;
 use openmls_traits::storage::{self, StorageProvider};
 use openmls_traits::OpenMlsProvider;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
-use std::time::SystemTime;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tls_codec::Serialize as TlsSerialize;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Default cap on the groups a user can be in (matches the server default)
+pub const DEFAULT_MAX_GROUPS: usize = 100;
+
+/// How long the self-test waits for the WebSocket to open
+const SELF_TEST_WEBSOCKET_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 /// MLS Connection - Infrastructure and message routing
 ///
@@ -78,6 +107,7 @@ use tls_codec::Serialize as TlsSerialize;
 /// - `websocket`: WebSocket connection for real-time messaging
 /// - `user`: User identity (created during initialization)
 /// - `memberships`: Map of group_id (bytes) to MlsMembership instances
+/// - `events`: Observers of group changes (see `subscribe_events`)
 ///
 /// ## Ownership Model
 /// - MlsConnection owns all infrastructure (stores, provider, api, websocket)
@@ -101,20 +131,101 @@ pub struct MlsConnection {
     /// OpenMLS provider (crypto operations and group state persistence)
     mls_provider: MlsProvider,
 
+    /// Path of the per-user provider database (removed by `wipe()`)
+    mls_db_path: PathBuf,
+
     /// HTTP API client for server communication
     api: ServerApi,
 
     /// Configuration parameters for the KeyPackage pool
     keypackage_pool_config: KeyPackagePoolConfig,
 
+    /// Whether large outgoing messages are compressed (see `payload`)
+    compress_messages: bool,
+
+    /// Credential type used when a new identity is created
+    credential_kind: CredentialKind,
+
     /// WebSocket connection for real-time messaging
     websocket: Option<MessageHandler>,
 
+    /// WebSocket connection state, kept across reconnections so watchers
+    /// stay subscribed
+    connection_state: SharedConnectionState,
+
     /// User identity (initialized via initialize())
     user: Option<MlsUser>,
 
+    /// Server-assigned user id (known once registration succeeded)
+    server_user_id: Option<i64>,
+
+    /// Maximum number of groups this user may create or join
+    max_groups: usize,
+
+    /// Whether Welcomes without group metadata are accepted (off by default)
+    allow_missing_group_metadata: bool,
+
+    /// Whether public GroupInfos are published after our own Commits
+    publish_group_info: bool,
+
+    /// Whether Welcomes are also stored on the server for offline invitees
+    store_welcomes: bool,
+
+    /// Whether removing a member is followed by a self-update Commit
+    self_update_after_remove: bool,
+
+    /// Framing to ask the server for when connecting the WebSocket
+    websocket_framing: Framing,
+
+    /// Delays between reconnection attempts after the WebSocket is lost
+    reconnect_backoff: ReconnectBackoff,
+
+    /// Whether message senders are checked against the group roster
+    strict_senders: bool,
+
+    /// CA certificates that members' X.509 credentials must chain to
+    trust_anchors: TrustAnchors,
+
+    /// Whether sending is refused while a Commit of ours is pending
+    strict_epoch: bool,
+
+    /// Most envelopes from later epochs each group holds
+    max_held_envelopes: usize,
+
+    /// What connecting to a group whose MLS state was lost does
+    lost_group_policy: LostGroupPolicy,
+
+    /// Whether incoming Welcomes are joined right away or staged
+    welcome_policy: WelcomePolicy,
+
+    /// Whether `group_senders` gives each group its own send lock
+    send_concurrency: SendConcurrency,
+
+    /// Size limit and overflow policy of the queue of messages written offline
+    outbound_queue_config: OutboundQueueConfig,
+
+    /// Ids of queued messages sent since this connection was created, so a
+    /// late cancel can be reported as too late rather than unknown
+    sent_queued_ids: HashSet<i64>,
+
+    /// How memberships print messages and notices
+    display_config: DisplayConfig,
+
     /// Group memberships (keyed by group_id bytes)
     memberships: HashMap<Vec<u8>, MlsMembership<'static>>,
+
+    /// Groups this client was removed from since the connection was created;
+    /// Commits still forwarded for them are ignored
+    removed_groups: HashSet<Vec<u8>>,
+
+    /// Observers of group changes, shared with every membership
+    events: GroupEvents,
+
+    /// Transforms of chat text, shared with every membership
+    transforms: MessageTransforms,
+
+    /// Message counters, shared with every membership
+    metrics: MessageMetrics,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -156,6 +267,23 @@ impl MlsConnection {
         server_url: &str,
         username: &str,
         storage_dir: &Path,
+    ) -> Result<Self> {
+        Self::new_with_storage_tuning(server_url, username, storage_dir, &StorageTuning::default())
+    }
+
+    /// Create a new MLS connection whose databases use the given SQLite settings
+    ///
+    /// Same as `new_with_storage_path`, for clients with large local histories
+    /// that want a bigger cache or memory-mapped I/O.
+    ///
+    /// # Errors
+    /// * File system errors when creating storage directories
+    /// * Database initialization errors
+    pub fn new_with_storage_tuning(
+        server_url: &str,
+        username: &str,
+        storage_dir: &Path,
+        tuning: &StorageTuning,
     ) -> Result<Self> {
         log::info!("Creating MlsConnection for {} at {}", username, server_url);
 
@@ -164,12 +292,16 @@ impl MlsConnection {
 
         // Metadata storage (application-level metadata)
         let metadata_db_path = storage_dir.join("metadata.db");
-        let metadata_store = LocalStore::new(&metadata_db_path)?;
+        let metadata_store = LocalStore::new_with_tuning(&metadata_db_path, tuning)?;
 
         // MLS provider storage (handles all OpenMLS group state)
         // Use per-user database to isolate credentials and group state
         let mls_db_path = storage_dir.join(format!("mls-{}.db", username));
-        let mls_provider = MlsProvider::new(&mls_db_path)?;
+        let mls_provider =
+            MlsProvider::open_with_tuning(StorageSpec::File(mls_db_path.clone()), tuning)?;
+
+        // The databases hold private keys; keep them away from other local users
+        restrict_storage_permissions(storage_dir, &[&metadata_db_path, &mls_db_path]);
 
         let api = ServerApi::new(server_url);
 
@@ -178,11 +310,37 @@ impl MlsConnection {
             username: username.to_string(),
             metadata_store,
             mls_provider,
+            mls_db_path,
             api,
             keypackage_pool_config: KeyPackagePoolConfig::default(),
+            compress_messages: true,
+            credential_kind: CredentialKind::default(),
             websocket: None,
+            connection_state: SharedConnectionState::new(ConnectionState::Disconnected),
             user: None,
+            server_user_id: None,
+            max_groups: DEFAULT_MAX_GROUPS,
+            allow_missing_group_metadata: false,
+            publish_group_info: false,
+            store_welcomes: false,
+            self_update_after_remove: false,
+            websocket_framing: Framing::default(),
+            reconnect_backoff: ReconnectBackoff::default(),
+            strict_senders: false,
+            trust_anchors: TrustAnchors::default(),
+            strict_epoch: false,
+            max_held_envelopes: DEFAULT_MAX_HELD_ENVELOPES,
+            lost_group_policy: LostGroupPolicy::default(),
+            welcome_policy: WelcomePolicy::default(),
+            send_concurrency: SendConcurrency::default(),
+            outbound_queue_config: OutboundQueueConfig::default(),
+            sent_queued_ids: HashSet::new(),
+            display_config: DisplayConfig::default(),
             memberships: HashMap::new(),
+            removed_groups: HashSet::new(),
+            events: GroupEvents::default(),
+            transforms: MessageTransforms::default(),
+            metrics: MessageMetrics::default(),
         })
     }
 
@@ -222,14 +380,33 @@ impl MlsConnection {
         log::info!("Initializing MlsConnection for {}", self.username);
 
         // === Step 1: Load or create persistent identity ===
-        let stored_identity = IdentityManager::load_or_create(
+        let stored_identity = IdentityManager::load_or_create_with_kind(
             &self.mls_provider,
             &self.metadata_store,
             &self.username,
+            self.credential_kind,
         )?;
 
         let keypair_blob = stored_identity.signature_key.to_public_vec();
 
+        // Drop messages that disappeared while the client was closed
+        let pruned = self
+            .mls_provider
+            .prune_expired_history(chrono::Utc::now().timestamp())?;
+        if pruned > 0 {
+            log::info!("Removed {} expired messages", pruned);
+        }
+
+        // Groups that ended up under one name are left for the user to
+        // resolve (see `resolve_group_name_conflict`)
+        for conflict in self.mls_provider.group_name_conflicts()? {
+            log::warn!(
+                "Group name {} is mapped to {} groups with state; resolve it with the storage check",
+                conflict.group_name_key,
+                conflict.superseded.len() + 1
+            );
+        }
+
         // === Step 2: Create MlsUser with identity material ===
         let identity = Identity {
             username: self.username.clone(),
@@ -242,7 +419,8 @@ impl MlsConnection {
             identity,
             stored_identity.signature_key,
             stored_identity.credential_with_key.clone(),
-        );
+        )
+        .with_device_id(self.metadata_store.device_id(&self.username)?);
 
         log::info!(
             "Created MlsUser for {} with persistent signature key",
@@ -286,9 +464,11 @@ impl MlsConnection {
 
         // === Step 5: Register with server (idempotent) ===
         // This may fail in tests, but user is already stored locally
-        self.api
+        let registered = self
+            .api
             .register_user(&self.username, &key_package_bytes)
             .await?;
+        self.server_user_id = Some(registered.id);
 
         log::info!("MlsConnection initialized for {}", self.username);
         Ok(())
@@ -313,6 +493,7 @@ impl MlsConnection {
     /// #     "alice",
     /// #     Path::new("/tmp/storage"),
     /// # )?;
+    /// connection.initialize().await?;
     /// connection.connect_websocket().await?;
     /// assert!(connection.is_websocket_connected());
     /// # Ok(())
@@ -321,10 +502,45 @@ impl MlsConnection {
     pub async fn connect_websocket(&mut self) -> Result<()> {
         log::info!("Connecting WebSocket for {}", self.username);
 
-        let websocket = MessageHandler::connect(&self.server_url, &self.username).await?;
+        // A previous socket (even a dead one) means this is a reconnection.
+        // Drop it first so it cannot report on the shared state any more.
+        let previous = self.websocket.take();
+        self.connection_state.set(if previous.is_some() {
+            ConnectionState::Reconnecting
+        } else {
+            ConnectionState::Connecting
+        });
+        drop(previous);
+
+        let user = match self.user.as_ref() {
+            Some(user) => user,
+            None => {
+                self.connection_state.set(ConnectionState::Disconnected);
+                return Err(ClientError::Config(
+                    "User not initialized - call initialize() first".to_string(),
+                ));
+            }
+        };
+        let websocket = match MessageHandler::connect_with_framing(
+            &self.server_url,
+            &self.username,
+            user.get_signature_key(),
+            self.connection_state.clone(),
+            self.websocket_framing,
+        )
+        .await
+        {
+            Ok(websocket) => websocket,
+            Err(e) => {
+                self.connection_state.set(ConnectionState::Disconnected);
+                return Err(e);
+            }
+        };
 
-        // Subscribe to username for receiving direct messages (e.g., Welcome from inviter)
-        websocket.subscribe_to_group(&self.username).await?;
+        // Subscribe to our own channel for receiving Welcomes from inviters
+        websocket
+            .subscribe_to_group(&format!("user:{}", self.username))
+            .await?;
 
         self.websocket = Some(websocket);
 
@@ -332,6 +548,362 @@ impl MlsConnection {
         Ok(())
     }
 
+    /// Reconnect the WebSocket after the connection was lost
+    ///
+    /// Opens a new connection, re-subscribes to every group this connection
+    /// is a member of, then backfills the messages sent by others while
+    /// disconnected (see `backfill_missed_messages`).
+    ///
+    /// # Errors
+    /// * WebSocket connection errors (the state goes back to `Disconnected`)
+    pub async fn reconnect_websocket(&mut self) -> Result<()> {
+        self.connect_websocket().await?;
+
+        let group_ids: Vec<Vec<u8>> = self.memberships.keys().cloned().collect();
+        for group_id in group_ids {
+            self.subscribe_to_group(&group_id).await?;
+        }
+
+        // Subscribed first, so nothing falls between the backfill and live
+        // delivery; messages seen by both are only processed once
+        let backfilled = self.backfill_missed_messages().await?;
+
+        // Commits that were only broadcast cannot be backfilled; say so
+        // rather than wait for the first message that fails to decrypt
+        for membership in self.memberships.values() {
+            match membership.epoch_lag(&self.api).await {
+                Ok(Some(lag)) if lag > 0 => log::warn!(
+                    "{} is {} epoch(s) behind the server after reconnecting",
+                    membership.get_group_name(),
+                    lag
+                ),
+                Ok(_) => {}
+                Err(e) => log::debug!(
+                    "Could not check the epoch of {}: {}",
+                    membership.get_group_name(),
+                    e
+                ),
+            }
+        }
+
+        log::info!(
+            "WebSocket reconnected for {} ({} groups resubscribed, {} messages backfilled)",
+            self.username,
+            self.memberships.len(),
+            backfilled
+        );
+        Ok(())
+    }
+
+    /// Sync the groups whose next Commit has been missing for `stalled_for`
+    ///
+    /// Commits that arrive ahead of their epoch are held until the missing
+    /// one comes (see `MlsMembership::stalled_commit_epoch`); this fetches it
+    /// from the server instead of waiting forever. Groups that received an
+    /// application message from a later epoch are synced whatever
+    /// `stalled_for` is. A group whose sync fails is
+    /// logged and retried on a later call. Returns the number of groups synced.
+    pub async fn resolve_commit_gaps(&mut self, stalled_for: Duration) -> Result<usize> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let mut synced = 0;
+        for membership in self.memberships.values_mut() {
+            if membership.stalled_commit_epoch(stalled_for).is_none() {
+                continue;
+            }
+            match membership
+                .resolve_commit_gap(user, &self.mls_provider, &self.api)
+                .await
+            {
+                Ok(()) => synced += 1,
+                Err(e) => log::warn!(
+                    "Failed to sync missing Commits of {}: {}",
+                    membership.get_group_name(),
+                    e
+                ),
+            }
+        }
+        self.drop_removed_groups().await;
+        Ok(synced)
+    }
+
+    /// Drop the groups this client has been removed from
+    ///
+    /// Once a Commit removing this client is applied the membership can do
+    /// nothing more, so it is dropped: the group is unsubscribed, its name
+    /// mapping removed so it is not loaded again, and its MLS state deleted.
+    /// The local history is kept. Failures are only logged. Returns the ids
+    /// of the dropped groups.
+    async fn drop_removed_groups(&mut self) -> Vec<Vec<u8>> {
+        let removed: Vec<Vec<u8>> = self
+            .memberships
+            .iter()
+            .filter(|(_, membership)| !membership.is_active())
+            .map(|(group_id, _)| group_id.clone())
+            .collect();
+
+        for group_id in &removed {
+            let Some(mut membership) = self.memberships.remove(group_id) else {
+                continue;
+            };
+            self.removed_groups.insert(group_id.clone());
+            log::info!(
+                "Dropping group {}: this client was removed from it",
+                membership.get_group_name()
+            );
+            if let Some(websocket) = &self.websocket {
+                let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+                if let Err(e) = websocket.unsubscribe_from_group(&group_id_b64).await {
+                    log::warn!("Failed to unsubscribe from {}: {}", group_id_b64, e);
+                }
+            }
+            let group_name_key = format!("{}:{}", self.username, membership.get_group_name());
+            if let Err(e) = self.mls_provider.remove_group_name(&group_name_key) {
+                log::warn!("Failed to remove group mapping {}: {}", group_name_key, e);
+            }
+            if let Err(e) = membership.delete_group_state(&self.mls_provider) {
+                log::warn!(
+                    "Failed to delete the state of group {}: {}",
+                    membership.get_group_name(),
+                    e
+                );
+            }
+        }
+        removed
+    }
+
+    /// Remove every local trace of a group
+    ///
+    /// Deletes the group's queued outgoing messages together with
+    /// everything the provider stores for it (see
+    /// `MlsProvider::purge_group`), committing the queue deletion only once
+    /// the provider's purge succeeds, then drops the membership and
+    /// unsubscribes from the group. Meant for
+    /// groups this client left or was removed from; nothing is sent to the
+    /// group. Returns false if nothing was known about the group.
+    ///
+    /// # Errors
+    /// * Database errors; the queue is then left in place, and calling this
+    ///   again finishes a purge that stopped partway
+    pub async fn purge_group(&mut self, group_id: &[u8]) -> Result<bool> {
+        let (dropped, purged) =
+            self.metadata_store
+                .drop_queued_for_group(&self.username, group_id, || {
+                    self.mls_provider.purge_group(group_id)
+                })?;
+
+        let loaded = self.memberships.remove(group_id).is_some();
+        self.removed_groups.remove(group_id);
+        if loaded {
+            if let Some(websocket) = &self.websocket {
+                let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+                if let Err(e) = websocket.unsubscribe_from_group(&group_id_b64).await {
+                    log::warn!("Failed to unsubscribe from {}: {}", group_id_b64, e);
+                }
+            }
+        }
+
+        let found = loaded || purged || dropped > 0;
+        if found {
+            log::info!(
+                "Purged group {} ({} queued messages dropped)",
+                general_purpose::STANDARD.encode(group_id),
+                dropped
+            );
+        }
+        Ok(found)
+    }
+
+    /// Fetch and process the messages stored on the server that this
+    /// connection has not received yet, in every group
+    ///
+    /// See `MlsMembership::backfill_messages`. A group whose backfill fails is
+    /// logged and skipped so the others still catch up. Returns the number of
+    /// messages fetched.
+    ///
+    /// # Errors
+    /// * User not initialized
+    pub async fn backfill_missed_messages(&mut self) -> Result<usize> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        let mut fetched = 0;
+        for membership in self.memberships.values_mut() {
+            match membership
+                .backfill_messages(user, &self.mls_provider, &self.api)
+                .await
+            {
+                Ok(count) => fetched += count,
+                Err(e) => log::warn!(
+                    "Failed to backfill missed messages of {}: {}",
+                    membership.get_group_name(),
+                    e
+                ),
+            }
+        }
+        Ok(fetched)
+    }
+
+    /// How many epochs this connection's copy of a group is behind the server
+    ///
+    /// See `MlsMembership::epoch_lag`.
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * Network errors from the server
+    pub async fn epoch_lag(&self, group_id: &[u8]) -> Result<Option<u64>> {
+        let membership = self
+            .memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+        membership.epoch_lag(&self.api).await
+    }
+
+    /// Join the groups whose Welcomes the server kept for us while offline
+    ///
+    /// Each stored Welcome is processed like a live one (see
+    /// `process_incoming_envelope`) and deleted from the server once joined.
+    /// A Welcome that cannot be joined (e.g. its KeyPackage was already used
+    /// by the live copy) is deleted as well, except after a network error or
+    /// reaching the group limit: those are kept until they expire so the next
+    /// login retries them. Returns the joined group ids, oldest Welcome first.
+    ///
+    /// With `WelcomePolicy::Stage` the Welcomes are staged instead and kept
+    /// on the server until accepted or declined; nothing is joined.
+    ///
+    /// # Errors
+    /// * Network errors while fetching the stored Welcomes
+    pub async fn fetch_pending_welcomes(&mut self) -> Result<Vec<Vec<u8>>> {
+        let welcomes = self.api.pending_welcomes(&self.username).await?;
+
+        let mut joined = Vec::new();
+        for welcome in welcomes {
+            if self.welcome_policy == WelcomePolicy::Stage {
+                self.metadata_store.stage_welcome(
+                    &self.username,
+                    &welcome.inviter,
+                    &welcome.welcome_blob,
+                    &welcome.ratchet_tree_blob,
+                    Some(welcome.id),
+                )?;
+                continue;
+            }
+            let envelope = MlsMessageEnvelope::WelcomeMessage {
+                inviter: welcome.inviter.clone(),
+                invitee: self.username.clone(),
+                welcome_blob: welcome.welcome_blob,
+                ratchet_tree_blob: welcome.ratchet_tree_blob,
+            };
+            match self.process_incoming_envelope(envelope).await {
+                Ok(group_id) => joined.extend(group_id),
+                Err(
+                    e @ (ClientError::Mls(MlsError::GroupLimitReached { .. })
+                    | ClientError::Network(_)
+                    | ClientError::Http(_)),
+                ) => {
+                    log::warn!(
+                        "Failed to join from stored Welcome of {}, retrying on next login: {}",
+                        welcome.inviter,
+                        e
+                    );
+                    continue;
+                }
+                Err(e) => log::warn!("Dropping stored Welcome from {}: {}", welcome.inviter, e),
+            }
+
+            if let Err(e) = self.api.delete_welcome(&self.username, welcome.id).await {
+                log::warn!(
+                    "Failed to delete stored Welcome {} (it expires on its own): {}",
+                    welcome.id,
+                    e
+                );
+            }
+        }
+        Ok(joined)
+    }
+
+    /// Close the WebSocket connection, flushing queued outgoing messages
+    ///
+    /// Does nothing if no WebSocket is connected.
+    pub async fn disconnect_websocket(&mut self) {
+        if let Some(websocket) = self.websocket.take() {
+            websocket.close().await;
+            log::info!("WebSocket disconnected for {}", self.username);
+        }
+        self.connection_state.set(ConnectionState::Disconnected);
+    }
+
+    /// Current WebSocket connection state
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection_state.get()
+    }
+
+    /// Subscribe to WebSocket connection state changes
+    ///
+    /// The receiver stays valid across reconnections.
+    pub fn watch_connection_state(&self) -> tokio::sync::watch::Receiver<ConnectionState> {
+        self.connection_state.subscribe()
+    }
+
+    /// Call `callback` with the previous and new state on every WebSocket
+    /// state change
+    ///
+    /// Stays registered across reconnections. The callback runs once per
+    /// transition, on whichever task made it (possibly a WebSocket task),
+    /// and never while a lock is held; it should return quickly.
+    pub fn on_connection_state_change<F>(&self, callback: F) -> StateCallbackId
+    where
+        F: Fn(ConnectionState, ConnectionState) + Send + Sync + 'static,
+    {
+        let callback: StateCallback = std::sync::Arc::new(callback);
+        self.connection_state.on_change(callback)
+    }
+
+    /// Remove a callback added with `on_connection_state_change`
+    ///
+    /// Returns false if it was not registered.
+    pub fn remove_connection_state_callback(&self, id: StateCallbackId) -> bool {
+        self.connection_state.remove_callback(id)
+    }
+
+    /// Register an observer of group changes in every group of this connection
+    ///
+    /// The receiver gets a `GroupEvent` when members are added or removed,
+    /// admins change, or a message is stored. Emitting never blocks the
+    /// operation that caused the event; drop the receiver or call
+    /// `unsubscribe_events` to stop observing.
+    pub fn subscribe_events(&self) -> (ObserverId, UnboundedReceiver<GroupEvent>) {
+        self.events.subscribe()
+    }
+
+    /// Remove an observer registered with `subscribe_events`
+    ///
+    /// Returns whether the observer was still registered.
+    pub fn unsubscribe_events(&self, id: ObserverId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    /// Add a transform of chat text in every group of this connection
+    ///
+    /// Outgoing text passes through the transforms before it is framed and
+    /// encrypted, incoming text after it is decrypted (see
+    /// `crate::transform`). Applies to current and future groups.
+    pub fn add_message_transform(&self, transform: Arc<dyn MessageTransform>) {
+        self.transforms.add(transform);
+    }
+
+    /// Message counts, sizes and decryption failures across all groups
+    ///
+    /// All zero unless built with the `metrics` feature (see
+    /// `crate::metrics`).
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
     /// Receive next message envelope from WebSocket
     ///
     /// Waits for and returns the next message from the server, or None if connection closed.
@@ -388,6 +960,92 @@ impl MlsConnection {
         }
     }
 
+    /// The members the server recorded for the group a Welcome joins
+    ///
+    /// The group is read from the Welcome without joining it (see
+    /// `crypto::preview_welcome`). Every invite reserves the invitee's
+    /// KeyPackage, which records the invitee before the Commit adding it is
+    /// sent, so each leaf of an honest tree is on this list.
+    ///
+    /// # Errors
+    /// * The Welcome cannot be decoded or decrypted
+    /// * Network errors when fetching the server's list
+    async fn welcome_roster(
+        &self,
+        welcome_blob: &str,
+        ratchet_tree_blob: &str,
+    ) -> Result<Vec<String>> {
+        let message = crate::envelope::decode_mls_blob(welcome_blob)?;
+        let ratchet_tree = crate::envelope::decode_ratchet_tree(ratchet_tree_blob)?;
+        let preview = crypto::preview_welcome(&self.mls_provider, &message, Some(ratchet_tree))?;
+        Ok(self
+            .api
+            .get_group_members(preview.group_id.as_slice())
+            .await?
+            .into_iter()
+            .map(|member| member.username)
+            .collect())
+    }
+
+    /// Create a membership from a Welcome, record it on the server and
+    /// subscribe to the group
+    async fn join_from_welcome(
+        &mut self,
+        inviter: &str,
+        welcome_blob: &str,
+        ratchet_tree_blob: &str,
+    ) -> Result<Vec<u8>> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        self.ensure_group_capacity()?;
+        let roster = self.welcome_roster(welcome_blob, ratchet_tree_blob).await?;
+
+        let mut membership = MlsMembership::from_welcome_message_with_fallback(
+            inviter,
+            welcome_blob,
+            ratchet_tree_blob,
+            &roster,
+            &self.trust_anchors,
+            user,
+            &self.mls_provider,
+            &self.metadata_store,
+            self.allow_missing_group_metadata,
+            self.past_epochs(),
+        )?;
+
+        // The server enforces its own limit; drop the group if it refuses
+        let group_id = membership.get_group_id().to_vec();
+        if let Err(e) = self.record_group_membership(&group_id).await {
+            let group_name_key = format!("{}:{}", self.username, membership.get_group_name());
+            self.mls_provider.remove_group_name(&group_name_key)?;
+            return Err(e);
+        }
+
+        // Subscribe to group for receiving messages
+        self.subscribe_to_group(&group_id).await?;
+
+        log::info!(
+            "Created membership for group '{}' from Welcome message",
+            membership.get_group_name()
+        );
+
+        // Store membership in HashMap
+        membership.set_events(self.events.clone());
+        membership.set_message_transforms(self.transforms.clone());
+        membership.set_message_metrics(self.metrics.clone());
+        membership.set_display_config(self.display_config);
+        membership.set_strict_senders(self.strict_senders);
+        membership.set_trust_anchors(self.trust_anchors.clone());
+        membership.set_strict_epoch(self.strict_epoch);
+        membership.set_max_held_envelopes(self.max_held_envelopes);
+        self.removed_groups.remove(&group_id);
+        self.memberships.insert(group_id.clone(), membership);
+        Ok(group_id)
+    }
+
     /// Process an incoming message envelope
     ///
     /// Routes the message to the appropriate handler based on envelope type:
@@ -401,6 +1059,8 @@ impl MlsConnection {
     /// When a user is invited to a group, they receive a Welcome message.
     /// This creates a new MlsMembership and adds it to the memberships HashMap.
     /// Returns `Some(group_id)` so the caller can update selected group if needed.
+    /// With `WelcomePolicy::Stage` the Welcome is only staged (see
+    /// `staged_welcomes`) and `None` is returned.
     ///
     /// ### ApplicationMessage / CommitMessage
     /// These messages are group-specific and must be routed to the correct membership
@@ -450,40 +1110,32 @@ impl MlsConnection {
             } => {
                 log::info!("Received WelcomeMessage from {}", inviter);
 
-                // Create new membership from Welcome message
-                let user = self
-                    .user
-                    .as_ref()
-                    .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
-
-                let membership = MlsMembership::from_welcome_message(
-                    &inviter,
-                    &welcome_blob,
-                    &ratchet_tree_blob,
-                    user,
-                    &self.mls_provider,
-                    &self.metadata_store,
-                )?;
-
-                // Subscribe to group for receiving messages
-                let group_id = membership.get_group_id().to_vec();
-                self.subscribe_to_group(&group_id).await?;
-
-                log::info!(
-                    "Created membership for group '{}' from Welcome message",
-                    membership.get_group_name()
-                );
-
-                // Store membership in HashMap
-                self.memberships.insert(group_id.clone(), membership);
+                // Staged Welcomes create no group state until accepted
+                if self.welcome_policy == WelcomePolicy::Stage {
+                    let id = self.metadata_store.stage_welcome(
+                        &self.username,
+                        &inviter,
+                        &welcome_blob,
+                        &ratchet_tree_blob,
+                        None,
+                    )?;
+                    log::info!("Staged the invitation of {} as Welcome {}", inviter, id);
+                    return Ok(None);
+                }
 
                 // Return the group_id so caller can update selected group if needed
+                let group_id = self
+                    .join_from_welcome(&inviter, &welcome_blob, &ratchet_tree_blob)
+                    .await?;
                 Ok(Some(group_id))
             }
             MlsMessageEnvelope::ApplicationMessage {
                 sender,
                 group_id,
                 encrypted_content,
+                content_type,
+                seq,
+                epoch,
             } => {
                 log::debug!(
                     "Received ApplicationMessage from {} for group {}",
@@ -492,12 +1144,7 @@ impl MlsConnection {
                 );
 
                 // Decode group_id from base64
-                let group_id_bytes = general_purpose::STANDARD.decode(&group_id).map_err(|e| {
-                    ClientError::Mls(crate::error::MlsError::OpenMls(format!(
-                        "Failed to decode group_id: {}",
-                        e
-                    )))
-                })?;
+                let group_id_bytes = crate::envelope::decode_base64(&group_id)?;
 
                 // Find membership by group_id
                 let membership = self.memberships.get_mut(&group_id_bytes).ok_or_else(|| {
@@ -515,6 +1162,9 @@ impl MlsConnection {
                     sender,
                     group_id,
                     encrypted_content,
+                    content_type,
+                    seq,
+                    epoch,
                 };
 
                 // Delegate to membership
@@ -529,6 +1179,7 @@ impl MlsConnection {
                 group_id,
                 sender,
                 commit_blob,
+                device_id,
             } => {
                 log::info!(
                     "Received CommitMessage from {} for group {}",
@@ -537,12 +1188,17 @@ impl MlsConnection {
                 );
 
                 // Decode group_id from base64
-                let group_id_bytes = general_purpose::STANDARD.decode(&group_id).map_err(|e| {
-                    ClientError::Mls(crate::error::MlsError::OpenMls(format!(
-                        "Failed to decode group_id: {}",
-                        e
-                    )))
-                })?;
+                let group_id_bytes = crate::envelope::decode_base64(&group_id)?;
+
+                // Commits that follow our removal may arrive before the
+                // server processed our unsubscribe
+                if self.removed_groups.contains(&group_id_bytes) {
+                    log::debug!(
+                        "Ignoring Commit for group {} we were removed from",
+                        group_id
+                    );
+                    return Ok(None);
+                }
 
                 // Find membership by group_id
                 let membership = self.memberships.get_mut(&group_id_bytes).ok_or_else(|| {
@@ -560,12 +1216,14 @@ impl MlsConnection {
                     group_id,
                     sender,
                     commit_blob,
+                    device_id,
                 };
 
                 // Delegate to membership
                 membership
                     .process_incoming_message(envelope, user, &self.mls_provider)
                     .await?;
+                self.drop_removed_groups().await;
 
                 // CommitMessage doesn't affect group selection
                 Ok(None)
@@ -636,301 +1294,2370 @@ impl MlsConnection {
         Ok(())
     }
 
-    /// Get reference to user identity
+    /// Discard the whole KeyPackage pool and upload a fresh one
     ///
-    /// Returns None if initialize() has not been called yet.
-    pub fn get_user(&self) -> Option<&MlsUser> {
-        self.user.as_ref()
-    }
-
-    /// Get reference to MLS provider
-    pub fn get_provider(&self) -> &MlsProvider {
-        &self.mls_provider
-    }
+    /// Every unspent KeyPackage of this user is revoked on the server, marked
+    /// expired and deleted locally, then `target_pool_size` new KeyPackages
+    /// are generated and uploaded. An inviter holding a reservation on an old
+    /// KeyPackage fails to spend it and has to retry the invite, which
+    /// reserves a new one. Returns the number of KeyPackages uploaded.
+    ///
+    /// # Errors
+    /// * User not initialized
+    /// * Server errors revoking or uploading; if revoking fails the local
+    ///   pool is left untouched
+    pub async fn reset_key_package_pool(&mut self) -> Result<usize> {
+        let user = self.user.as_ref().ok_or_else(|| {
+            ClientError::Config("User not initialized - call initialize() first".to_string())
+        })?;
 
-    /// Override KeyPackage pool configuration (intended for testing hooks)
-    pub fn set_keypackage_pool_config(&mut self, config: KeyPackagePoolConfig) {
-        self.keypackage_pool_config = config;
-    }
+        let old_refs = self.own_pool_refs()?;
+        let revoked = self
+            .api
+            .revoke_key_packages(&self.username, &old_refs)
+            .await?;
 
-    /// Get reference to server API client
-    pub fn get_api(&self) -> &ServerApi {
-        &self.api
-    }
+        let pool = KeyPackagePool::new(
+            self.username.clone(),
+            self.keypackage_pool_config.clone(),
+            &self.metadata_store,
+        );
+        for keypackage_ref in &old_refs {
+            self.metadata_store.expire_pool_metadata(keypackage_ref)?;
+        }
+        let removed = pool.cleanup_expired(&self.mls_provider, SystemTime::now())?;
+        log::info!(
+            "Reset KeyPackage pool for {}: revoked {} on the server, removed {} locally",
+            self.username,
+            revoked,
+            removed
+        );
 
-    /// Get username for this connection
-    pub fn get_username(&self) -> &str {
-        &self.username
+        pool.generate_and_update_pool(
+            self.keypackage_pool_config.target_pool_size,
+            user.get_credential_with_key(),
+            user.get_signature_key(),
+            &self.mls_provider,
+        )
+        .await?;
+        self.upload_pending_keypackages().await
     }
 
-    /// Get reference to metadata store
-    pub fn get_metadata_store(&self) -> &LocalStore {
-        &self.metadata_store
+    /// Rotate this user's credential and signature key
+    ///
+    /// In order:
+    /// 1. A new identity replaces the stored one (`IdentityManager::rotate`)
+    /// 2. A KeyPackage under the new credential replaces the registered one
+    ///    on the server, signed with the old key; the server revokes every
+    ///    unspent KeyPackage, so invites can no longer reserve old ones
+    /// 3. Each loaded group gets a Commit moving our leaf to the new key
+    /// 4. The local KeyPackage pool is discarded and a fresh one uploaded
+    ///    under the new credential (see `reset_key_package_pool`)
+    ///
+    /// An inviter that reserved an old KeyPackage before step 2 fails the
+    /// invite at `InviteStage::ValidateKeyPackage` with
+    /// `MlsError::SupersededKeyPackage` and has to invite again.
+    ///
+    /// Returns the ids of groups whose leaf could not be moved; members there
+    /// cannot verify messages from this client until it is removed and
+    /// invited again.
+    ///
+    /// # Errors
+    /// * User not initialized or WebSocket not connected
+    /// * Network errors re-registering; the old identity is then stored
+    ///   again and nothing changed
+    /// * Errors resetting the KeyPackage pool
+    pub async fn rotate_identity(&mut self) -> Result<Vec<Vec<u8>>> {
+        let old_user = self.user.as_ref().ok_or_else(|| {
+            ClientError::Config("User not initialized - call initialize() first".to_string())
+        })?;
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        let stored_identity =
+            IdentityManager::rotate(&self.mls_provider, &self.metadata_store, &self.username)?;
+        let identity = Identity {
+            username: self.username.clone(),
+            keypair_blob: stored_identity.signature_key.to_public_vec(),
+            credential_blob: vec![],
+        };
+        let new_user = MlsUser::new(
+            self.username.clone(),
+            identity,
+            stored_identity.signature_key,
+            stored_identity.credential_with_key,
+        )
+        .with_device_id(self.metadata_store.device_id(&self.username)?);
+
+        let key_package_bundle = crypto::generate_key_package_bundle(
+            new_user.get_credential_with_key(),
+            new_user.get_signature_key(),
+            &self.mls_provider,
+        )?;
+        let key_package_bytes = key_package_bundle
+            .key_package()
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::OpenMls(format!("Failed to serialize key package: {}", e)))?;
+        let revoked = match self
+            .api
+            .rotate_registration(
+                &self.username,
+                &key_package_bytes,
+                old_user.get_signature_key(),
+            )
+            .await
+        {
+            Ok(revoked) => revoked,
+            Err(e) => {
+                // The server still knows us by the old key; keep using it
+                IdentityManager::reinstate(
+                    &self.mls_provider,
+                    &self.metadata_store,
+                    &self.username,
+                    old_user.get_credential_with_key(),
+                    old_user.get_signature_key(),
+                )?;
+                return Err(e);
+            }
+        };
+        log::info!(
+            "Re-registered {} under the rotated credential; the server revoked {} KeyPackages",
+            self.username,
+            revoked
+        );
+
+        let mut not_rotated = Vec::new();
+        for (group_id, membership) in self.memberships.iter_mut() {
+            if let Err(e) = membership
+                .rotate_own_leaf(
+                    old_user,
+                    &new_user,
+                    &self.mls_provider,
+                    &self.api,
+                    websocket,
+                )
+                .await
+            {
+                log::warn!(
+                    "Could not move our leaf in group {} to the rotated credential: {}",
+                    membership.get_group_name(),
+                    e
+                );
+                not_rotated.push(group_id.clone());
+            }
+        }
+
+        self.user = Some(new_user);
+        self.reset_key_package_pool().await?;
+        Ok(not_rotated)
     }
 
-    /// Get reference to a membership by group_id
+    /// Delete all local secrets of this user from the storage directory
     ///
-    /// # Arguments
-    /// * `group_id` - Group identifier (raw bytes)
+    /// Removes the per-user provider database (signature keys, KeyPackages and
+    /// group state), overwriting it before unlinking, this user's rows in the
+    /// shared metadata store and their keyring entry, if a keyring is
+    /// configured. Other users sharing the storage directory
+    /// are left untouched. Consumes the connection; a new one created on the
+    /// same directory starts from scratch.
     ///
-    /// # Returns
-    /// * `Some(&MlsMembership)` if membership exists
-    /// * `None` if no membership for this group
-    pub fn get_membership(&self, group_id: &[u8]) -> Option<&MlsMembership<'static>> {
-        self.memberships.get(group_id)
+    /// # Errors
+    /// * Database errors when deleting metadata
+    /// * I/O errors when overwriting or removing files
+    pub fn wipe(self) -> Result<()> {
+        log::info!("Wiping local state for {}", self.username);
+
+        let own_refs = self.own_pool_refs()?;
+        self.metadata_store
+            .delete_user_data(&self.username, &own_refs)?;
+        if let Some(keyring) = self.metadata_store.identity_keyring() {
+            if let Err(e) = keyring.delete(&self.username) {
+                log::warn!(
+                    "Failed to delete the keyring entry of {}: {}",
+                    self.username,
+                    e
+                );
+            }
+        }
+
+        // Close the provider database before removing it and its journals
+        let Self {
+            mls_provider,
+            mls_db_path,
+            ..
+        } = self;
+        drop(mls_provider);
+
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let mut path = mls_db_path.clone().into_os_string();
+            path.push(suffix);
+            crate::storage::secure_remove_file(Path::new(&path))?;
+        }
+
+        Ok(())
     }
 
-    /// Get mutable reference to a membership by group_id
+    /// Delete this user's account on the server (see `ServerApi::deregister`)
     ///
-    /// # Arguments
-    /// * `group_id` - Group identifier (raw bytes)
+    /// Signs with the stored identity, so the connection need not be
+    /// initialized. Local state is left alone; `wipe` removes it. Returns
+    /// false if the server did not know the user.
     ///
-    /// # Returns
-    /// * `Some(&mut MlsMembership)` if membership exists
-    /// * `None` if no membership for this group
-    pub fn get_membership_mut(&mut self, group_id: &[u8]) -> Option<&mut MlsMembership<'static>> {
-        self.memberships.get_mut(group_id)
+    /// # Errors
+    /// * `StorageError::IdentityNotFound` if no identity is stored locally
+    /// * Network errors, including the server refusing the signature
+    pub async fn deregister(&self) -> Result<bool> {
+        let deleted = match &self.user {
+            Some(user) => {
+                self.api
+                    .deregister(&self.username, user.get_signature_key())
+                    .await?
+            }
+            None => {
+                let identity = IdentityManager::load(
+                    &self.mls_provider,
+                    &self.metadata_store,
+                    &self.username,
+                )?
+                .ok_or_else(|| StorageError::IdentityNotFound(self.username.clone()))?;
+                self.api
+                    .deregister(&self.username, &identity.signature_key)
+                    .await?
+            }
+        };
+        if deleted {
+            log::info!("Deregistered {} from the server", self.username);
+        }
+        Ok(deleted)
     }
 
-    /// Get membership by group name
+    /// Replace `username`'s provider database in `storage_dir` with `image`
     ///
-    /// Searches through all memberships to find one matching the group name.
-    ///
-    /// # Arguments
-    /// * `group_name` - The human-readable group name to search for
+    /// `image` is a complete SQLite database file, as kept in a backup. It is
+    /// checked in a temporary file first, so a corrupt backup leaves the
+    /// current database in place. Works without opening the current
+    /// database, which may be the broken thing being restored; no connection
+    /// for `username` may be open. Metadata mappings to groups the backup
+    /// lacks show up in `check_storage_integrity`.
     ///
-    /// # Returns
-    /// * `Some((group_id, &MlsMembership))` if membership exists
-    /// * `None` if no membership for this group name
-    pub fn get_membership_by_name(
-        &self,
-        group_name: &str,
-    ) -> Option<(Vec<u8>, &MlsMembership<'static>)> {
-        self.memberships
-            .iter()
-            .find(|(_, membership)| membership.get_group_name() == group_name)
-            .map(|(group_id, membership)| (group_id.clone(), membership))
+    /// # Errors
+    /// * `StorageError::InvalidBackup` if `image` is not an intact database
+    /// * I/O errors when writing or replacing files
+    pub fn restore_provider_database(
+        storage_dir: &Path,
+        username: &str,
+        image: &[u8],
+    ) -> Result<()> {
+        const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+        if !image.starts_with(SQLITE_HEADER) {
+            return Err(StorageError::InvalidBackup("missing SQLite header".to_string()).into());
+        }
+
+        std::fs::create_dir_all(storage_dir)?;
+        let mls_db_path = storage_dir.join(format!("mls-{}.db", username));
+        let staged_path = storage_dir.join(format!("mls-{}.db.restore", username));
+        std::fs::write(&staged_path, image)?;
+        restrict_storage_permissions(storage_dir, &[&staged_path]);
+
+        let check = rusqlite::Connection::open(&staged_path).and_then(|conn| {
+            conn.query_row("PRAGMA integrity_check", [], |row| row.get::<_, String>(0))
+        });
+        match check {
+            Ok(result) if result == "ok" => {}
+            Ok(result) => {
+                crate::storage::secure_remove_file(&staged_path)?;
+                return Err(StorageError::InvalidBackup(result).into());
+            }
+            Err(e) => {
+                crate::storage::secure_remove_file(&staged_path)?;
+                return Err(StorageError::InvalidBackup(e.to_string()).into());
+            }
+        }
+
+        // Journals of the old database must not be replayed onto the new one
+        for suffix in ["-wal", "-shm", "-journal"] {
+            let mut path = mls_db_path.clone().into_os_string();
+            path.push(suffix);
+            crate::storage::secure_remove_file(Path::new(&path))?;
+        }
+        std::fs::rename(&staged_path, &mls_db_path)?;
+        log::info!("Restored the provider database of {}", username);
+        Ok(())
     }
 
-    /// Check if WebSocket is connected
-    pub fn is_websocket_connected(&self) -> bool {
-        self.websocket.is_some()
+    /// Get reference to user identity
+    ///
+    /// Returns None if initialize() has not been called yet.
+    pub fn get_user(&self) -> Option<&MlsUser> {
+        self.user.as_ref()
     }
 
-    /// Get reference to WebSocket (for operations that need it)
+    /// Server-assigned user id, for endpoints keyed on numeric ids
     ///
-    /// # Returns
-    /// * `Some(&MessageHandler)` if WebSocket is connected
-    /// * `None` if not connected
-    pub fn get_websocket(&self) -> Option<&MessageHandler> {
-        self.websocket.as_ref()
+    /// Returns None until `initialize()` has registered with the server.
+    pub fn get_server_user_id(&self) -> Option<i64> {
+        self.server_user_id
     }
 
-    /// Set the WebSocket connection (primarily for testing)
+    /// Describe the local identity without exposing any secret
     ///
-    /// Replaces the current WebSocket with the provided one. Used in tests to inject
-    /// mock implementations without requiring real network connections.
+    /// # Errors
+    /// * User not initialized
+    /// * Unsupported credential type or hashing errors
+    pub fn whoami(&self) -> Result<IdentityInfo> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let credential = user.get_credential_with_key();
+        let credential_kind =
+            CredentialKind::from_credential_type(credential.credential.credential_type())
+                .ok_or(MlsError::InvalidCredential)?;
+
+        Ok(IdentityInfo {
+            username: user.get_username().to_string(),
+            credential_kind,
+            credential_fingerprint: crypto::credential_fingerprint(&self.mls_provider, credential)?,
+            signature_public_key: credential.signature_key.as_slice().to_vec(),
+            server_user_id: self.server_user_id,
+        })
+    }
+
+    /// The public material of the local identity, as the server directory
+    /// should report it
     ///
-    /// # Arguments
-    /// * `websocket` - New WebSocket handler to use
-    #[cfg(test)]
-    pub fn set_websocket(&mut self, websocket: MessageHandler) {
-        self.websocket = Some(websocket);
+    /// # Errors
+    /// * User not initialized
+    /// * Credential serialization errors
+    pub fn public_bundle(&self) -> Result<PublicIdentityBundle> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        IdentityManager::public_bundle(user.get_username(), user.get_credential_with_key())
     }
 
-    /// Add a membership to the connection's HashMap
+    /// Look up another user's public identity in the server directory
     ///
-    /// Used when creating or loading a group outside of the Welcome message flow.
+    /// The bundle is checked against the KeyPackage the user registered with
+    /// before it is returned, so its `fingerprint` can be compared out of band
+    /// before inviting them. Returns `None` for an unknown user.
     ///
-    /// # Arguments
-    /// * `membership` - The membership to add
+    /// # Errors
+    /// * `MlsError::InvalidCredential` if the bundle does not match the
+    ///   registered KeyPackage
+    /// * Network errors, or an invalid KeyPackage
+    pub async fn lookup_identity(&self, username: &str) -> Result<Option<PublicIdentityBundle>> {
+        use tls_codec::Deserialize as _;
+
+        let Some(bundle) = self.api.get_user_identity(username).await? else {
+            return Ok(None);
+        };
+
+        let key_package_bytes = self.api.get_user_key(username).await?;
+        let key_package =
+            openmls::key_packages::KeyPackageIn::tls_deserialize_exact(&key_package_bytes)
+                .map_err(|e| {
+                    MlsError::OpenMls(format!("Failed to deserialize key package: {}", e))
+                })?
+                .validate(
+                    self.mls_provider.crypto(),
+                    openmls::prelude::ProtocolVersion::Mls10,
+                )
+                .map_err(|e| MlsError::OpenMls(format!("Invalid key package: {}", e)))?;
+
+        if bundle.username != username || !bundle.matches_key_package(&key_package) {
+            log::error!(
+                "SECURITY: Directory identity for {} does not match its KeyPackage",
+                username
+            );
+            return Err(MlsError::InvalidCredential.into());
+        }
+        Ok(Some(bundle))
+    }
+
+    /// Run read-only diagnostics on the identity, server, WebSocket,
+    /// KeyPackages and local databases
     ///
-    /// # Note
-    /// The membership's group_id is used as the key in the HashMap.
-    pub fn add_membership(&mut self, membership: MlsMembership<'static>) {
-        let group_id = membership.get_group_id().to_vec();
-        log::debug!(
-            "Adding membership for group_id: {}",
-            general_purpose::STANDARD.encode(&group_id)
-        );
-        self.memberships.insert(group_id, membership);
+    /// Every check runs even if an earlier one failed. Nothing is created,
+    /// uploaded or subscribed: the identity is loaded but never generated, the
+    /// probe WebSocket is closed straight away, and the database probe is
+    /// rolled back. Failed checks carry a remediation hint.
+    pub async fn self_test(&self) -> SelfTestReport {
+        let mut results = Vec::new();
+
+        let identity =
+            IdentityManager::load(&self.mls_provider, &self.metadata_store, &self.username);
+        results.push(match &identity {
+            Ok(Some(_)) => SelfTestResult::passed(
+                SelfTestCheck::Identity,
+                format!("signature key and credential for {} load", self.username),
+            ),
+            Ok(None) => SelfTestResult::failed(
+                SelfTestCheck::Identity,
+                format!("no usable identity stored for {}", self.username),
+                "run `mls-client register` to create one",
+            ),
+            Err(e) => SelfTestResult::failed(
+                SelfTestCheck::Identity,
+                format!("failed to load the identity: {}", e),
+                "run `mls-client check-storage` to inspect the local databases",
+            ),
+        });
+
+        results.push(match self.api.health_check().await {
+            Ok(()) => SelfTestResult::passed(
+                SelfTestCheck::Server,
+                format!("{} is healthy", self.server_url),
+            ),
+            Err(e) => SelfTestResult::failed(
+                SelfTestCheck::Server,
+                format!("{} is unreachable: {}", self.server_url, e),
+                "check the --server URL and that the server is running",
+            ),
+        });
+
+        // The server only accepts connections signed by a registered identity
+        let probe = match &identity {
+            Ok(Some(identity)) => Some(
+                tokio::time::timeout(
+                    SELF_TEST_WEBSOCKET_TIMEOUT,
+                    MessageHandler::connect(
+                        &self.server_url,
+                        &self.username,
+                        &identity.signature_key,
+                    ),
+                )
+                .await,
+            ),
+            _ => None,
+        };
+        results.push(match probe {
+            None => SelfTestResult::failed(
+                SelfTestCheck::WebSocket,
+                "no identity to sign the connection with",
+                "fix the identity check first",
+            ),
+            Some(Ok(Ok(handler))) => {
+                handler.close().await;
+                SelfTestResult::passed(SelfTestCheck::WebSocket, "connection opened")
+            }
+            Some(Ok(Err(e))) => SelfTestResult::failed(
+                SelfTestCheck::WebSocket,
+                format!("connection failed: {}", e),
+                "check that proxies and firewalls allow WebSocket upgrades",
+            ),
+            Some(Err(_)) => SelfTestResult::failed(
+                SelfTestCheck::WebSocket,
+                format!(
+                    "no connection within {}s",
+                    SELF_TEST_WEBSOCKET_TIMEOUT.as_secs()
+                ),
+                "check that proxies and firewalls allow WebSocket upgrades",
+            ),
+        });
+
+        results.push(match self.api.available_keypackages(&self.username).await {
+            Ok(0) => SelfTestResult::failed(
+                SelfTestCheck::KeyPackages,
+                "the server has no KeyPackages for this user, so nobody can invite them",
+                "run any command that connects (e.g. `mls-client register`) to refill the pool",
+            ),
+            Ok(available) => SelfTestResult::passed(
+                SelfTestCheck::KeyPackages,
+                format!("{} available on the server", available),
+            ),
+            Err(e) => SelfTestResult::failed(
+                SelfTestCheck::KeyPackages,
+                format!("failed to count KeyPackages: {}", e),
+                "fix the server check first",
+            ),
+        });
+
+        let round_trip = self
+            .metadata_store
+            .check_round_trip()
+            .and_then(|()| self.mls_provider.check_round_trip());
+        results.push(match round_trip {
+            Ok(()) => {
+                SelfTestResult::passed(SelfTestCheck::LocalStorage, "read/write round trip ok")
+            }
+            Err(e) => SelfTestResult::failed(
+                SelfTestCheck::LocalStorage,
+                format!("round trip failed: {}", e),
+                "check free disk space and permissions on the config directory",
+            ),
+        });
+
+        SelfTestReport { results }
     }
 
-    async fn upload_pending_keypackages(&self) -> Result<usize> {
-        let pending = self.metadata_store.get_metadata_by_status("created")?;
-        if pending.is_empty() {
-            return Ok(0);
+    /// Summarize this user's local storage usage (no server access needed)
+    pub fn storage_stats(&self) -> Result<StorageStats> {
+        self.mls_provider.stats()
+    }
+
+    /// Count this user's locally tracked KeyPackages per status (see `KeyPackagePool::stats`)
+    pub fn keypackage_pool_stats(&self) -> Result<BTreeMap<String, usize>> {
+        KeyPackagePool::new(
+            self.username.clone(),
+            self.keypackage_pool_config.clone(),
+            &self.metadata_store,
+        )
+        .stats()
+    }
+
+    /// Check local group mappings against stored MLS state (see `MlsProvider::check_integrity`)
+    pub fn check_storage_integrity(&self) -> Result<IntegrityReport> {
+        self.mls_provider.check_integrity()
+    }
+
+    /// Prune dangling group mappings, keeping any recoverable state (see `MlsProvider::repair`)
+    pub fn repair_storage(&self) -> Result<IntegrityReport> {
+        self.mls_provider.repair()
+    }
+
+    /// Every local group name mapping, current and superseded (see
+    /// `MlsProvider::list_group_mappings`)
+    pub fn list_group_mappings(&self) -> Result<Vec<GroupMapping>> {
+        self.mls_provider.list_group_mappings()
+    }
+
+    /// Map a conflicting group name to the group to keep (see
+    /// `MlsProvider::resolve_group_name_conflict`)
+    pub fn resolve_group_name_conflict(&self, group_name: &str, keep: &[u8]) -> Result<()> {
+        let group_name_key = format!("{}:{}", self.username, group_name);
+        self.mls_provider
+            .resolve_group_name_conflict(&group_name_key, keep)
+    }
+
+    /// Get reference to MLS provider
+    pub fn get_provider(&self) -> &MlsProvider {
+        &self.mls_provider
+    }
+
+    /// Override KeyPackage pool configuration (intended for testing hooks)
+    pub fn set_keypackage_pool_config(&mut self, config: KeyPackagePoolConfig) {
+        self.keypackage_pool_config = config;
+    }
+
+    /// Enable or disable compression of large outgoing messages (enabled by default)
+    pub fn set_message_compression(&mut self, enabled: bool) {
+        self.compress_messages = enabled;
+    }
+
+    /// Choose the credential type for a new identity (Basic by default)
+    ///
+    /// Must be called before `initialize()`; an existing identity keeps its type.
+    pub fn set_credential_kind(&mut self, kind: CredentialKind) {
+        self.credential_kind = kind;
+    }
+
+    /// Use a certificate and key issued by an external CA as this user's
+    /// identity
+    ///
+    /// Must be called before the first `initialize()`, which then loads the
+    /// imported identity instead of creating one, and after
+    /// `set_trust_anchors` if the chain should be checked against them. See
+    /// `IdentityManager::import_x509` for the expected PEM files.
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if the user is already initialized or has an
+    ///   identity
+    /// * `MlsError::InvalidCertificate` if the files are unusable, or the
+    ///   chain does not verify against the configured trust anchors
+    pub fn import_x509_identity(&mut self, chain_pem: &[u8], key_pem: &str) -> Result<()> {
+        if self.user.is_some() {
+            return Err(ClientError::Config(
+                "Import the identity before initialize()".to_string(),
+            ));
         }
+        IdentityManager::import_x509(
+            &self.mls_provider,
+            &self.metadata_store,
+            &self.username,
+            chain_pem,
+            key_pem,
+            &self.trust_anchors,
+        )?;
+        self.credential_kind = CredentialKind::X509;
+        Ok(())
+    }
 
-        let mut uploads = Vec::new();
-        for metadata in pending {
-            match self.load_keypackage_bytes(&metadata)? {
-                Some(bytes) => uploads.push(KeyPackageUpload {
-                    keypackage_ref: metadata.keypackage_ref.clone(),
-                    keypackage: bytes,
-                    not_after: metadata.not_after,
-                }),
-                None => {
+    /// Choose where the identity's signature key and credential are kept
+    /// (`IdentityStorage::File` by default)
+    ///
+    /// Must be called before `initialize()`. `IdentityStorage::Keyring` uses
+    /// the OS keyring; in builds without the `keyring` feature it logs a
+    /// warning and keeps file storage. An identity already in the MLS
+    /// database is moved to the keyring when next loaded.
+    pub fn set_identity_storage(&mut self, storage: IdentityStorage) {
+        let keyring = match storage {
+            IdentityStorage::File => None,
+            IdentityStorage::Keyring => {
+                let keyring = crate::secret_store::default_keyring();
+                if keyring.is_none() {
                     log::warn!(
-                        "Missing KeyPackage in provider storage for user {}",
+                        "Built without the keyring feature; keeping the identity of {} in the MLS database",
                         self.username
                     );
-                    self.metadata_store
-                        .update_pool_metadata_status(&metadata.keypackage_ref, "failed")?;
                 }
+                keyring
             }
-        }
+        };
+        self.metadata_store.set_identity_keyring(keyring);
+    }
 
-        if uploads.is_empty() {
-            return Ok(0);
+    /// Keep the identity's secrets in `keyring` instead of the OS keyring
+    ///
+    /// Must be called before `initialize()`.
+    pub fn set_identity_keyring(&mut self, keyring: Arc<dyn SecretStore>) {
+        self.metadata_store.set_identity_keyring(Some(keyring));
+    }
+
+    /// Set the maximum number of groups this user may create or join
+    ///
+    /// Checked locally before creating a group or accepting a Welcome; the
+    /// server applies its own limit as well.
+    pub fn set_max_groups(&mut self, max_groups: usize) {
+        self.max_groups = max_groups;
+    }
+
+    /// Accept Welcomes for groups without the GroupMetadata extension
+    ///
+    /// Off by default: such Welcomes are refused. When enabled, the group is
+    /// joined under a placeholder name derived from its id (see
+    /// `MlsMembership::from_welcome_message_with_fallback`).
+    pub fn set_allow_missing_group_metadata(&mut self, allow: bool) {
+        self.allow_missing_group_metadata = allow;
+    }
+
+    /// Set how messages and notices of all groups are printed
+    pub fn set_display_config(&mut self, display: DisplayConfig) {
+        self.display_config = display;
+        for membership in self.memberships.values_mut() {
+            membership.set_display_config(display);
         }
+    }
 
-        let response = self
-            .api
-            .upload_key_packages(&self.username, &uploads)
-            .await?;
+    /// Current display options (see `set_display_config`)
+    pub fn display_config(&self) -> DisplayConfig {
+        self.display_config
+    }
 
-        let mut rejected_refs: HashSet<Vec<u8>> = HashSet::new();
-        for rejected in response.rejected {
-            match general_purpose::STANDARD.decode(&rejected) {
-                Ok(bytes) => {
-                    self.metadata_store
-                        .update_pool_metadata_status(&bytes, "failed")?;
-                    rejected_refs.insert(bytes);
-                }
-                Err(err) => {
+    /// Publish the GroupInfo after inviting members or updating a group
+    ///
+    /// Off by default. Only GroupInfos without confidential extensions are
+    /// published (see `MlsMembership::publish_group_info`), so external
+    /// joiners can fetch the latest one from the server.
+    pub fn set_publish_group_info(&mut self, publish: bool) {
+        self.publish_group_info = publish;
+    }
+
+    /// Also store each Welcome on the server when inviting
+    ///
+    /// Off by default. Welcomes are always sent to the invitee's channel,
+    /// which only reaches them while online; a stored copy is picked up by
+    /// `fetch_pending_welcomes` on their next login.
+    pub fn set_store_welcomes(&mut self, store: bool) {
+        self.store_welcomes = store;
+    }
+
+    /// Follow every member removal with a self-update Commit
+    ///
+    /// Off by default, since it costs a second Commit per removal. The
+    /// removal alone already locks the removed member out of later epochs;
+    /// the self-update also replaces the remover's own leaf secrets, which
+    /// the removed member's old state was derived alongside (see
+    /// `MlsMembership::update_own_leaf`). Other members apply both Commits in
+    /// epoch order.
+    pub fn set_self_update_after_remove(&mut self, enabled: bool) {
+        self.self_update_after_remove = enabled;
+    }
+
+    /// Ask the server for binary WebSocket frames on the next connection
+    ///
+    /// JSON by default. Servers that do not support binary frames keep
+    /// JSON; `MessageHandler::framing` on `get_websocket()` tells which one
+    /// was negotiated.
+    pub fn set_websocket_framing(&mut self, framing: Framing) {
+        self.websocket_framing = framing;
+    }
+
+    /// Set the delays between reconnection attempts
+    ///
+    /// The connection does not reconnect by itself; whoever drives
+    /// `reconnect_websocket` follows these delays through a
+    /// `ReconnectSchedule`, as the interactive client does.
+    pub fn set_reconnect_backoff(&mut self, backoff: ReconnectBackoff) {
+        self.reconnect_backoff = backoff;
+    }
+
+    /// Delays between reconnection attempts (see `set_reconnect_backoff`)
+    pub fn reconnect_backoff(&self) -> ReconnectBackoff {
+        self.reconnect_backoff
+    }
+
+    /// Reject messages from senders who are not in the group
+    ///
+    /// Off by default. Applies to every current and future membership; see
+    /// `MlsMembership::set_strict_senders` for what is checked. Only groups
+    /// created or joined while it is on keep the previous epoch's secrets
+    /// (see `past_epochs`), so turn it on before connecting to groups.
+    pub fn set_strict_senders(&mut self, strict: bool) {
+        self.strict_senders = strict;
+        for membership in self.memberships.values_mut() {
+            membership.set_strict_senders(strict);
+        }
+    }
+
+    /// Require members' X.509 credentials to chain to one of `anchors`
+    ///
+    /// Empty by default, which accepts any certificate bound to its leaf's
+    /// key. Applies to every current and future membership, including the
+    /// ratchet tree check when joining from a Welcome; see
+    /// `MlsMembership::set_trust_anchors`.
+    pub fn set_trust_anchors(&mut self, anchors: TrustAnchors) {
+        for membership in self.memberships.values_mut() {
+            membership.set_trust_anchors(anchors.clone());
+        }
+        self.trust_anchors = anchors;
+    }
+
+    /// Trust anchors for members' X.509 credentials (see `set_trust_anchors`)
+    pub fn trust_anchors(&self) -> &TrustAnchors {
+        &self.trust_anchors
+    }
+
+    /// Earlier epochs whose message secrets groups created or joined from
+    /// now on keep
+    ///
+    /// None, unless strict sender checks are on
+    /// (`crypto::STRICT_SENDERS_PAST_EPOCHS`).
+    pub fn past_epochs(&self) -> usize {
+        if self.strict_senders {
+            crypto::STRICT_SENDERS_PAST_EPOCHS
+        } else {
+            0
+        }
+    }
+
+    /// Refuse to send while a Commit of ours is pending in the group
+    ///
+    /// Off by default, leaving it to OpenMLS to reject the message. When on,
+    /// sends fail early with `MlsError::CommitInProgress`, which asks the
+    /// user to wait for the operation to complete. Applies to every current
+    /// and future membership.
+    pub fn set_strict_epoch(&mut self, strict: bool) {
+        self.strict_epoch = strict;
+        for membership in self.memberships.values_mut() {
+            membership.set_strict_epoch(strict);
+        }
+    }
+
+    /// Bound the Commits and messages from later epochs each group holds
+    /// while the Commits before them are missing
+    ///
+    /// Defaults to `DEFAULT_MAX_HELD_ENVELOPES`. When a group exceeds it,
+    /// the oldest held envelope is dropped and the group syncs fully at the
+    /// next `resolve_commit_gaps`, fetching what was dropped from the
+    /// server. Applies to every current and future membership.
+    pub fn set_max_held_envelopes(&mut self, max: usize) {
+        self.max_held_envelopes = max;
+        for membership in self.memberships.values_mut() {
+            membership.set_max_held_envelopes(max);
+        }
+    }
+
+    /// Choose what connecting to a group whose MLS state was lost does
+    ///
+    /// Defaults to `LostGroupPolicy::Rejoin`.
+    pub fn set_lost_group_policy(&mut self, policy: LostGroupPolicy) {
+        self.lost_group_policy = policy;
+    }
+
+    /// What connecting to a group whose MLS state was lost does
+    pub fn lost_group_policy(&self) -> LostGroupPolicy {
+        self.lost_group_policy
+    }
+
+    /// Choose whether the senders from `group_senders` lock per group or
+    /// share one lock
+    ///
+    /// Defaults to `SendConcurrency::PerGroup`. Senders already taken keep
+    /// their locks.
+    pub fn set_send_concurrency(&mut self, concurrency: SendConcurrency) {
+        self.send_concurrency = concurrency;
+    }
+
+    /// Whether the senders from `group_senders` lock per group or share one lock
+    pub fn send_concurrency(&self) -> SendConcurrency {
+        self.send_concurrency
+    }
+
+    /// Choose whether incoming Welcomes are joined right away or staged for
+    /// `accept_welcome` / `decline_welcome`
+    ///
+    /// Defaults to `WelcomePolicy::AutoAccept`. Welcomes staged earlier stay
+    /// staged when switching back.
+    pub fn set_welcome_policy(&mut self, policy: WelcomePolicy) {
+        self.welcome_policy = policy;
+    }
+
+    /// Whether incoming Welcomes are joined right away or staged
+    pub fn welcome_policy(&self) -> WelcomePolicy {
+        self.welcome_policy
+    }
+
+    /// Welcomes waiting for this user to accept or decline, oldest first
+    pub fn staged_welcomes(&self) -> Result<Vec<StagedWelcome>> {
+        self.metadata_store.staged_welcomes(&self.username)
+    }
+
+    /// Every group this user is invited to but has not joined yet, oldest
+    /// invitation first
+    ///
+    /// One entry per staged Welcome (see `WelcomePolicy::Stage`), with the
+    /// group read from the Welcome without joining it. Pass an entry's `id`
+    /// to `accept_welcome` or `decline_welcome`; the entry is gone from the
+    /// list afterwards. A Welcome that can no longer be decrypted, e.g.
+    /// because its KeyPackage expired, is listed without a group and can
+    /// only be declined.
+    ///
+    /// # Errors
+    /// * Database errors when reading the staged Welcomes
+    pub fn pending_invitations_for_me(&self) -> Result<Vec<PendingInvitation>> {
+        let mut invitations = Vec::new();
+        for welcome in self.staged_welcomes()? {
+            let preview =
+                crate::envelope::decode_mls_blob(&welcome.welcome_blob).and_then(|message| {
+                    let ratchet_tree =
+                        crate::envelope::decode_ratchet_tree(&welcome.ratchet_tree_blob)?;
+                    crypto::preview_welcome(&self.mls_provider, &message, Some(ratchet_tree))
+                });
+            let preview = match preview {
+                Ok(preview) => Some(preview),
+                Err(e) => {
                     log::warn!(
-                        "Invalid rejected keypackage ref from server for user {}: {}",
-                        self.username,
-                        err
+                        "Cannot read the group of the invitation from {}: {}",
+                        welcome.inviter,
+                        e
                     );
+                    None
                 }
+            };
+            invitations.push(PendingInvitation {
+                id: welcome.id,
+                inviter: welcome.inviter,
+                group_id: preview
+                    .as_ref()
+                    .map(|preview| preview.group_id.as_slice().to_vec()),
+                group_name: preview
+                    .as_ref()
+                    .and_then(|preview| preview.metadata.as_ref())
+                    .map(|metadata| metadata.name.clone()),
+                members: preview.map(|preview| preview.members).unwrap_or_default(),
+                received_at: welcome.received_at,
+            });
+        }
+        Ok(invitations)
+    }
+
+    /// Join the group of a staged Welcome and return its id
+    ///
+    /// The group is created, recorded on the server and subscribed to only
+    /// now. The staged Welcome and the server's stored copy are deleted once
+    /// joined; if joining fails it stays staged.
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if no Welcome with that id is staged
+    /// * as for a Welcome received live (see `process_incoming_envelope`)
+    pub async fn accept_welcome(&mut self, id: i64) -> Result<Vec<u8>> {
+        let welcome = self
+            .metadata_store
+            .staged_welcome(&self.username, id)?
+            .ok_or_else(|| ClientError::Config(format!("No staged Welcome {}", id)))?;
+        let group_id = self
+            .join_from_welcome(
+                &welcome.inviter,
+                &welcome.welcome_blob,
+                &welcome.ratchet_tree_blob,
+            )
+            .await?;
+        self.forget_staged_welcome(&welcome).await?;
+        Ok(group_id)
+    }
+
+    /// Discard a staged Welcome without joining its group
+    ///
+    /// Returns false if no Welcome with that id is staged.
+    pub async fn decline_welcome(&mut self, id: i64) -> Result<bool> {
+        let Some(welcome) = self.metadata_store.staged_welcome(&self.username, id)? else {
+            return Ok(false);
+        };
+        log::info!("Declined the invitation of {}", welcome.inviter);
+        self.forget_staged_welcome(&welcome).await?;
+        Ok(true)
+    }
+
+    /// Remove a staged Welcome and the server's stored copy of it
+    async fn forget_staged_welcome(&self, welcome: &StagedWelcome) -> Result<()> {
+        self.metadata_store
+            .remove_staged_welcome(&self.username, welcome.id)?;
+        if let Some(server_id) = welcome.server_id {
+            if let Err(e) = self.api.delete_welcome(&self.username, server_id).await {
+                log::warn!(
+                    "Failed to delete stored Welcome {} (it expires on its own): {}",
+                    server_id,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Limit the queue of messages written while offline
+    ///
+    /// Defaults to 500 messages, dropping the oldest one on overflow.
+    pub fn set_outbound_queue_config(&mut self, config: OutboundQueueConfig) {
+        self.outbound_queue_config = config;
+    }
+
+    /// Tell the user that the state of `group_name` was lost
+    ///
+    /// Emits `GroupEvent::GroupStateLost` and leaves a notice in the history
+    /// the user will see next: the lost group's while waiting to rejoin it,
+    /// the new group's once `recreated`.
+    pub(crate) fn report_lost_group(
+        &self,
+        group_name: &str,
+        lost_group_id: &[u8],
+        history_group_id: &[u8],
+        recreated: bool,
+    ) {
+        let notice = if recreated {
+            "Previous state of this group was lost; started a new conversation under the same name"
+        } else {
+            "Local state of this group was lost; waiting for a member to re-add you"
+        };
+        if let Err(e) = self.mls_provider.append_history(
+            history_group_id,
+            &self.username,
+            notice,
+            ContentType::System,
+            None,
+        ) {
+            log::warn!("Failed to record lost state of {}: {}", group_name, e);
+        }
+        self.events.emit(GroupEvent::GroupStateLost {
+            group_id: lost_group_id.to_vec(),
+            group_name: group_name.to_string(),
+            recreated,
+        });
+    }
+
+    /// Fail with `MlsError::GroupLimitReached` if no further group can be joined
+    pub fn ensure_group_capacity(&self) -> Result<()> {
+        if self.mls_provider.group_count()? >= self.max_groups {
+            return Err(MlsError::GroupLimitReached {
+                username: self.username.clone(),
+                limit: self.max_groups,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Record on the server that this user is now a member of `group_id`
+    ///
+    /// The request is signed with this user's signature key. Only
+    /// `MlsError::GroupLimitReached` is returned as an error; other failures
+    /// are logged, since MLS membership does not depend on the server's list.
+    pub async fn record_group_membership(&self, group_id: &[u8]) -> Result<()> {
+        let user = self.user.as_ref().ok_or_else(|| {
+            ClientError::Config("User not initialized - call initialize() first".to_string())
+        })?;
+        match self
+            .api
+            .join_group(&self.username, group_id, user.get_signature_key())
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e @ ClientError::Mls(MlsError::GroupLimitReached { .. })) => Err(e),
+            Err(e) => {
+                log::warn!(
+                    "Failed to record membership of group {} on the server: {}",
+                    general_purpose::STANDARD.encode(group_id),
+                    e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Export a group's local history as a compliance archive
+    ///
+    /// Each entry carries the sender MLS authenticated when the message was
+    /// sent or received, and entries are hash-chained (see
+    /// `archive::GroupArchive`).
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if the group is not loaded
+    /// * Storage errors reading the history
+    pub fn export_archive(&self, group_id: &[u8]) -> Result<GroupArchive> {
+        let membership = self
+            .memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+        let history = self.mls_provider.load_history(group_id)?;
+        let proofs = self.mls_provider.message_proofs(group_id)?;
+        GroupArchive::build(
+            &self.mls_provider,
+            group_id,
+            membership.get_group_name(),
+            &self.username,
+            history,
+            &proofs,
+        )
+    }
+
+    /// Compare a group's MLS roster with the members the server has recorded
+    ///
+    /// The roster is the authoritative list; the server's is shown as its
+    /// unverified view. Differences are logged and reported, never applied:
+    /// the server does not see removals, and it may list members added by
+    /// Commits this client has not processed yet.
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if the group is not loaded
+    /// * Network errors when fetching the server's list
+    pub async fn compare_rosters(&self, group_id: &[u8]) -> Result<RosterComparison> {
+        let membership = self
+            .memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+        let mut verified = membership.list_members();
+        verified.sort();
+        verified.dedup();
+        let server_view: Vec<String> = self
+            .api
+            .get_group_members(group_id)
+            .await?
+            .into_iter()
+            .map(|member| member.username)
+            .collect();
+
+        let comparison = RosterComparison {
+            only_on_server: server_view
+                .iter()
+                .filter(|username| !verified.contains(username))
+                .cloned()
+                .collect(),
+            only_in_roster: verified
+                .iter()
+                .filter(|username| !server_view.contains(username))
+                .cloned()
+                .collect(),
+            verified,
+            server_view,
+        };
+        if !comparison.is_consistent() {
+            log::warn!(
+                "Server view of {} differs from the MLS roster: only on the server {:?}, only in the roster {:?}",
+                membership.get_group_name(),
+                comparison.only_on_server,
+                comparison.only_in_roster
+            );
+        }
+        Ok(comparison)
+    }
+
+    /// Rebuild the server API client with different timeouts (e.g. for slow links)
+    pub fn set_server_api_config(&mut self, config: ServerApiConfig) {
+        self.api = ServerApi::with_config(&self.server_url, config);
+    }
+
+    /// Get reference to server API client
+    pub fn get_api(&self) -> &ServerApi {
+        &self.api
+    }
+
+    /// Get username for this connection
+    pub fn get_username(&self) -> &str {
+        &self.username
+    }
+
+    /// Get reference to metadata store
+    pub fn get_metadata_store(&self) -> &LocalStore {
+        &self.metadata_store
+    }
+
+    /// Get reference to a membership by group_id
+    ///
+    /// # Arguments
+    /// * `group_id` - Group identifier (raw bytes)
+    ///
+    /// # Returns
+    /// * `Some(&MlsMembership)` if membership exists
+    /// * `None` if no membership for this group
+    pub fn get_membership(&self, group_id: &[u8]) -> Option<&MlsMembership<'static>> {
+        self.memberships.get(group_id)
+    }
+
+    /// Get mutable reference to a membership by group_id
+    ///
+    /// # Arguments
+    /// * `group_id` - Group identifier (raw bytes)
+    ///
+    /// # Returns
+    /// * `Some(&mut MlsMembership)` if membership exists
+    /// * `None` if no membership for this group
+    pub fn get_membership_mut(&mut self, group_id: &[u8]) -> Option<&mut MlsMembership<'static>> {
+        self.memberships.get_mut(group_id)
+    }
+
+    /// Get membership by group name
+    ///
+    /// Searches through all memberships to find one matching the group name.
+    ///
+    /// # Arguments
+    /// * `group_name` - The human-readable group name to search for
+    ///
+    /// # Returns
+    /// * `Some((group_id, &MlsMembership))` if membership exists
+    /// * `None` if no membership for this group name
+    pub fn get_membership_by_name(
+        &self,
+        group_name: &str,
+    ) -> Option<(Vec<u8>, &MlsMembership<'static>)> {
+        self.memberships
+            .iter()
+            .find(|(_, membership)| membership.get_group_name() == group_name)
+            .map(|(group_id, membership)| (group_id.clone(), membership))
+    }
+
+    /// Check if WebSocket is connected
+    pub fn is_websocket_connected(&self) -> bool {
+        self.websocket.is_some()
+    }
+
+    /// Get reference to WebSocket (for operations that need it)
+    ///
+    /// # Returns
+    /// * `Some(&MessageHandler)` if WebSocket is connected
+    /// * `None` if not connected
+    pub fn get_websocket(&self) -> Option<&MessageHandler> {
+        self.websocket.as_ref()
+    }
+
+    /// Set the WebSocket connection (primarily for testing)
+    ///
+    /// Replaces the current WebSocket with the provided one. Used in tests to inject
+    /// mock implementations without requiring real network connections.
+    ///
+    /// # Arguments
+    /// * `websocket` - New WebSocket handler to use
+    #[cfg(test)]
+    pub fn set_websocket(&mut self, websocket: MessageHandler) {
+        self.connection_state.set(websocket.state());
+        self.websocket = Some(websocket);
+    }
+
+    /// Add a membership to the connection's HashMap
+    ///
+    /// Used when creating or loading a group outside of the Welcome message flow.
+    ///
+    /// # Arguments
+    /// * `membership` - The membership to add
+    ///
+    /// # Note
+    /// The membership's group_id is used as the key in the HashMap.
+    pub fn add_membership(&mut self, mut membership: MlsMembership<'static>) {
+        membership.set_events(self.events.clone());
+        membership.set_message_transforms(self.transforms.clone());
+        membership.set_message_metrics(self.metrics.clone());
+        membership.set_display_config(self.display_config);
+        membership.set_strict_epoch(self.strict_epoch);
+        membership.set_max_held_envelopes(self.max_held_envelopes);
+        membership.set_strict_senders(self.strict_senders);
+        membership.set_trust_anchors(self.trust_anchors.clone());
+        let group_id = membership.get_group_id().to_vec();
+        log::debug!(
+            "Adding membership for group_id: {}",
+            general_purpose::STANDARD.encode(&group_id)
+        );
+        self.removed_groups.remove(&group_id);
+        self.memberships.insert(group_id, membership);
+    }
+
+    /// Refs of this user's entries in the pool metadata
+    ///
+    /// The pool metadata table is shared by every user of the directory;
+    /// this user's entries are the ones whose KeyPackage is in its provider.
+    fn own_pool_refs(&self) -> Result<Vec<Vec<u8>>> {
+        let mut own_refs = Vec::new();
+        for keypackage_ref in self.metadata_store.get_all_pool_refs()? {
+            let reference = StoredKeyPackageRef(keypackage_ref.clone());
+            let owned = self
+                .mls_provider
+                .storage()
+                .key_package::<_, KeyPackageBundle>(&reference)
+                .map_err(|err| ClientError::Mls(MlsError::OpenMls(err.to_string())))?
+                .is_some();
+            if owned {
+                own_refs.push(keypackage_ref);
+            }
+        }
+        Ok(own_refs)
+    }
+
+    async fn upload_pending_keypackages(&self) -> Result<usize> {
+        let pending = self.metadata_store.get_metadata_by_status("created")?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut uploads = Vec::new();
+        for metadata in pending {
+            match self.load_keypackage_bytes(&metadata)? {
+                Some(bytes) => uploads.push(KeyPackageUpload {
+                    keypackage_ref: metadata.keypackage_ref.clone(),
+                    keypackage: bytes,
+                    not_after: metadata.not_after,
+                }),
+                None => {
+                    log::warn!(
+                        "Missing KeyPackage in provider storage for user {}",
+                        self.username
+                    );
+                    self.metadata_store
+                        .update_pool_metadata_status(&metadata.keypackage_ref, "failed")?;
+                }
+            }
+        }
+
+        if uploads.is_empty() {
+            return Ok(0);
+        }
+
+        let response = self
+            .api
+            .upload_key_packages(&self.username, &uploads)
+            .await?;
+
+        let mut rejected_refs: HashSet<Vec<u8>> = HashSet::new();
+        for rejected in response.rejected {
+            match general_purpose::STANDARD.decode(&rejected) {
+                Ok(bytes) => {
+                    self.metadata_store
+                        .update_pool_metadata_status(&bytes, "failed")?;
+                    rejected_refs.insert(bytes);
+                }
+                Err(err) => {
+                    log::warn!(
+                        "Invalid rejected keypackage ref from server for user {}: {}",
+                        self.username,
+                        err
+                    );
+                }
+            }
+        }
+
+        let mut promoted = 0usize;
+        for upload in uploads {
+            if rejected_refs.contains(&upload.keypackage_ref) {
+                continue;
+            }
+
+            self.metadata_store
+                .update_pool_metadata_status(&upload.keypackage_ref, "uploaded")?;
+            self.metadata_store
+                .update_pool_metadata_status(&upload.keypackage_ref, "available")?;
+            promoted += 1;
+        }
+
+        Ok(promoted)
+    }
+
+    fn load_keypackage_bytes(&self, metadata: &KeyPackageMetadata) -> Result<Option<Vec<u8>>> {
+        let reference = StoredKeyPackageRef(metadata.keypackage_ref.clone());
+        let maybe_bundle = self
+            .mls_provider
+            .storage()
+            .key_package::<_, KeyPackageBundle>(&reference)
+            .map_err(|err| ClientError::Mls(MlsError::OpenMls(err.to_string())))?;
+
+        if let Some(bundle) = maybe_bundle {
+            let serialized = bundle
+                .key_package()
+                .tls_serialize_detached()
+                .map_err(|err| ClientError::Mls(MlsError::OpenMls(err.to_string())))?;
+            Ok(Some(serialized))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Send a message to a specific group
+    ///
+    /// Helper method that handles the borrow-checking complexity of accessing
+    /// both the membership and the services it needs.
+    ///
+    /// Sends are strictly ordered per group: each message is encrypted and
+    /// queued on the WebSocket in one step without yielding, so the ratchet
+    /// generations leave in the order they were used. To send from several
+    /// tasks at once, or to several groups side by side, take senders with
+    /// `group_senders` instead.
+    ///
+    /// # Arguments
+    /// * `group_id` - Group to send message to
+    /// * `text` - Message text
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * User not initialized
+    /// * WebSocket not connected
+    /// * MLS encryption errors
+    pub async fn send_message_to_group(&mut self, group_id: &[u8], text: &str) -> Result<()> {
+        self.send_formatted_message_to_group(group_id, text, TextFormat::Plain)
+            .await
+    }
+
+    /// Take a `GroupSender` for each of `group_ids`, for concurrent sends
+    ///
+    /// Each sender owns its group's state until it is dropped and guards it
+    /// with a lock held from encryption until the envelope is queued, so
+    /// any number of tasks can send through a shared `&GroupSender` and the
+    /// group's messages still leave in the order they were encrypted. With
+    /// `SendConcurrency::PerGroup` (the default) sends to different groups
+    /// proceed side by side; with `SendConcurrency::Serial` all senders
+    /// share one lock.
+    ///
+    /// # Errors
+    /// * User not initialized
+    /// * WebSocket not connected
+    /// * A group is not found, or appears twice in `group_ids`
+    pub fn group_senders(&mut self, group_ids: &[&[u8]]) -> Result<Vec<GroupSender<'_>>> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        let shared_lock = match self.send_concurrency {
+            SendConcurrency::PerGroup => None,
+            SendConcurrency::Serial => Some(Arc::new(tokio::sync::Mutex::new(()))),
+        };
+        let mut memberships: HashMap<&[u8], &mut MlsMembership<'static>> = self
+            .memberships
+            .iter_mut()
+            .map(|(group_id, membership)| (group_id.as_slice(), membership))
+            .collect();
+        group_ids
+            .iter()
+            .map(|group_id| {
+                let membership = memberships.remove(group_id).ok_or_else(|| {
+                    ClientError::Config("Group not found or requested twice".to_string())
+                })?;
+                Ok(GroupSender {
+                    group_id: group_id.to_vec(),
+                    membership: tokio::sync::Mutex::new(membership),
+                    shared_lock: shared_lock.clone(),
+                    user,
+                    provider: &self.mls_provider,
+                    websocket,
+                    compress: self.compress_messages,
+                })
+            })
+            .collect()
+    }
+
+    /// Send a message to the group called `group_name`
+    ///
+    /// The name is resolved with `resolve_group_name`; see there for what
+    /// happens when it is unknown or shared by several groups.
+    ///
+    /// # Errors
+    /// * as for `resolve_group_name` and `send_message_to_group`
+    pub async fn send_message_by_name(&mut self, group_name: &str, text: &str) -> Result<()> {
+        let group_id = self.resolve_group_name(group_name)?;
+        self.send_message_to_group(&group_id, text).await
+    }
+
+    /// Find the id of the connected group called `group_name`
+    ///
+    /// Group names are not unique: a user invited to a second group with the
+    /// same name holds two memberships under it. Rather than guess which one
+    /// is meant, an ambiguous name is an error that lists the candidates'
+    /// ids. A name that is mapped in storage but whose group is not
+    /// connected is reported as unknown, with a hint in the log.
+    ///
+    /// # Errors
+    /// * `MlsError::UnknownGroupName` if no connected group has this name
+    /// * `MlsError::AmbiguousGroupName` if several do
+    pub fn resolve_group_name(&self, group_name: &str) -> Result<Vec<u8>> {
+        let mut candidates: Vec<&Vec<u8>> = self
+            .memberships
+            .iter()
+            .filter(|(_, membership)| membership.get_group_name() == group_name)
+            .map(|(group_id, _)| group_id)
+            .collect();
+        match candidates.len() {
+            0 => {
+                let group_name_key = format!("{}:{}", self.username, group_name);
+                if self
+                    .mls_provider
+                    .load_group_by_name(&group_name_key)?
+                    .is_some()
+                {
+                    log::warn!(
+                        "Group {} is known but not connected; connect to it first",
+                        group_name
+                    );
+                }
+                Err(MlsError::UnknownGroupName {
+                    name: group_name.to_string(),
+                }
+                .into())
+            }
+            1 => Ok(candidates[0].clone()),
+            _ => {
+                candidates.sort();
+                Err(MlsError::AmbiguousGroupName {
+                    name: group_name.to_string(),
+                    group_ids: candidates
+                        .into_iter()
+                        .map(|group_id| general_purpose::STANDARD.encode(group_id))
+                        .collect(),
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Post an announcement to a group, if this user is one of its admins
+    ///
+    /// # Errors
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    /// * as for `send_message_to_group`
+    pub async fn send_announcement_to_group(&mut self, group_id: &[u8], text: &str) -> Result<()> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        membership
+            .send_announcement(text, user, &self.mls_provider, websocket)
+            .await
+    }
+
+    /// Pin a message of a group, or unpin it, if this user is an admin
+    ///
+    /// # Errors
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    /// * `MlsError::MessageNotFound` when pinning an unknown or expired message
+    /// * as for `send_message_to_group`
+    pub async fn pin_message_in_group(
+        &mut self,
+        group_id: &[u8],
+        message_id: &str,
+        unpin: bool,
+    ) -> Result<()> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        membership
+            .pin_message(message_id, unpin, user, &self.mls_provider, websocket)
+            .await
+    }
+
+    /// Keep a message for `group_id` until the connection is back
+    ///
+    /// The queue survives restarts. Returns the messages the overflow policy
+    /// evicted to make room, which are lost and should be reported.
+    ///
+    /// # Errors
+    /// * `StorageError::OutboundQueueFull` if the queue is full and the
+    ///   policy is `QueueOverflowPolicy::RejectNew`
+    pub fn queue_offline_message(&self, group_id: &[u8], text: &str) -> Result<Vec<QueuedMessage>> {
+        let dropped = self.metadata_store.enqueue_outbound(
+            &self.username,
+            group_id,
+            text,
+            &self.outbound_queue_config,
+        )?;
+        for message in &dropped {
+            log::warn!(
+                "Outbound queue full: dropped message queued at {} for {}",
+                message.queued_at,
+                general_purpose::STANDARD.encode(&message.group_id)
+            );
+        }
+        Ok(dropped)
+    }
+
+    /// Send the messages queued while offline, oldest first
+    ///
+    /// Stops at the first failed send, leaving it and the messages after it
+    /// queued for the next attempt. Messages for groups we are no longer a
+    /// member of cannot be sent and are returned as undeliverable.
+    ///
+    /// # Returns
+    /// The number of messages sent and the undeliverable ones
+    pub async fn flush_offline_queue(&mut self) -> Result<(usize, Vec<QueuedMessage>)> {
+        let mut sent = 0;
+        let mut undeliverable = Vec::new();
+        for message in self.metadata_store.outbound_queue(&self.username)? {
+            if !self.memberships.contains_key(&message.group_id) {
+                log::warn!(
+                    "Dropping queued message for {}: not a member any more",
+                    general_purpose::STANDARD.encode(&message.group_id)
+                );
+                self.metadata_store.remove_outbound(message.id)?;
+                undeliverable.push(message);
+                continue;
+            }
+            self.send_message_to_group(&message.group_id, &message.text)
+                .await?;
+            self.sent_queued_ids.insert(message.id);
+            self.metadata_store.remove_outbound(message.id)?;
+            sent += 1;
+        }
+        Ok((sent, undeliverable))
+    }
+
+    /// Outbound operations that have not completed
+    ///
+    /// Lists the messages in the outbound queue (oldest first), then the
+    /// pending invites of every group (see `pending_invites`), by group id
+    /// and invitee. Messages sent while connected go straight to the
+    /// WebSocket and are never pending.
+    pub fn pending_operations(&self) -> Result<Vec<PendingOp>> {
+        let mut operations: Vec<PendingOp> = self
+            .metadata_store
+            .outbound_queue(&self.username)?
+            .into_iter()
+            .map(|message| PendingOp {
+                id: PendingOpId::QueuedMessage(message.id),
+                group_id: message.group_id,
+                description: message.text,
+            })
+            .collect();
+
+        let mut group_ids: Vec<&Vec<u8>> = self.memberships.keys().collect();
+        group_ids.sort();
+        for group_id in group_ids {
+            for invite in self.pending_invites(group_id)? {
+                operations.push(PendingOp {
+                    id: PendingOpId::Invite {
+                        group_id: group_id.clone(),
+                        invitee: invite.invitee.clone(),
+                    },
+                    group_id: group_id.clone(),
+                    description: invite.invitee,
+                });
+            }
+        }
+        Ok(operations)
+    }
+
+    /// Cancel an operation listed by `pending_operations`
+    ///
+    /// A queued message that is still queued is withdrawn. One that was
+    /// already flushed is reported as `CancelOutcome::AlreadySent`, as is an
+    /// invite: its Commit and Welcome went out when it was made, so
+    /// cancelling only stops tracking (and resending) it; the invitee stays
+    /// in the group until removed. Unknown ids are a no-op reported as
+    /// `CancelOutcome::NotFound`.
+    pub fn cancel_operation(&mut self, id: &PendingOpId) -> Result<CancelOutcome> {
+        let outcome = match id {
+            PendingOpId::QueuedMessage(queue_id) => {
+                if self
+                    .metadata_store
+                    .cancel_outbound(&self.username, *queue_id)?
+                {
+                    CancelOutcome::Cancelled
+                } else if self.sent_queued_ids.contains(queue_id) {
+                    CancelOutcome::AlreadySent
+                } else {
+                    CancelOutcome::NotFound
+                }
+            }
+            PendingOpId::Invite { group_id, invitee } => {
+                if self.memberships.contains_key(group_id)
+                    && self.mls_provider.delete_sent_welcome(group_id, invitee)?
+                {
+                    CancelOutcome::AlreadySent
+                } else {
+                    CancelOutcome::NotFound
+                }
+            }
+        };
+        log::info!("Cancelling {}: {:?}", id, outcome);
+        Ok(outcome)
+    }
+
+    /// Search the local history of every group, newest match first
+    ///
+    /// The filters of `search` all apply (see `MessageSearch`). Returns at
+    /// most `limit` entries.
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if `search.group` names no local group
+    /// * Database errors
+    pub fn search_messages(&self, search: &MessageSearch, limit: usize) -> Result<Vec<SearchHit>> {
+        let names: HashMap<Vec<u8>, String> = self
+            .mls_provider
+            .group_listings_by_id(&self.username)?
+            .into_iter()
+            .map(|(group_id, listing)| (group_id, listing.name))
+            .collect();
+        let group_id = match &search.group {
+            Some(name) => Some(
+                names
+                    .iter()
+                    .find(|(_, group_name)| *group_name == name)
+                    .map(|(group_id, _)| group_id.clone())
+                    .ok_or_else(|| ClientError::Config(format!("No group named {}", name)))?,
+            ),
+            None => None,
+        };
+        let hits = self
+            .mls_provider
+            .search_messages(search, group_id.as_deref(), limit)?
+            .into_iter()
+            .map(|(group_id, entry)| SearchHit {
+                group_name: names.get(&group_id).cloned(),
+                group_id,
+                entry,
+            })
+            .collect();
+        Ok(hits)
+    }
+
+    /// The pinned messages of a group, oldest first
+    pub fn get_pinned_messages(&self, group_id: &[u8]) -> Result<Vec<HistoryEntry>> {
+        self.mls_provider.pinned_messages(group_id)
+    }
+
+    /// Store the application's local settings for a group (see
+    /// `MlsProvider::save_group_settings`)
+    pub fn set_group_settings(&self, group_id: &[u8], settings: &serde_json::Value) -> Result<()> {
+        self.mls_provider.save_group_settings(group_id, settings)
+    }
+
+    /// The application's local settings for a group, if any were stored
+    pub fn get_group_settings(&self, group_id: &[u8]) -> Result<Option<serde_json::Value>> {
+        self.mls_provider.load_group_settings(group_id)
+    }
+
+    /// Send a message that receivers should render in `format`
+    ///
+    /// # Errors
+    /// * as for `send_message_to_group`
+    pub async fn send_formatted_message_to_group(
+        &mut self,
+        group_id: &[u8],
+        text: &str,
+        format: TextFormat,
+    ) -> Result<()> {
+        // Get user first (immutable borrow)
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        // Get websocket (immutable borrow)
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        // Get membership (mutable borrow - but we've finished with user/websocket immutable borrows above)
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        // Call membership method
+        membership
+            .send_formatted_message(
+                text,
+                format,
+                self.compress_messages,
+                user,
+                &self.mls_provider,
+                websocket,
+            )
+            .await
+    }
+
+    /// Reply to a message of a group, quoting it (see `MlsMembership::send_reply`)
+    ///
+    /// # Errors
+    /// * `MlsError::MessageNotFound` if the quoted message is unknown or expired
+    /// * as for `send_message_to_group`
+    pub async fn send_reply_to_group(
+        &mut self,
+        group_id: &[u8],
+        quoted_message_id: &str,
+        text: &str,
+    ) -> Result<()> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        membership
+            .send_reply(
+                text,
+                quoted_message_id,
+                self.compress_messages,
+                user,
+                &self.mls_provider,
+                websocket,
+            )
+            .await
+    }
+
+    /// Invite a user to a specific group
+    ///
+    /// Helper method that handles the borrow-checking complexity.
+    ///
+    /// # Arguments
+    /// * `group_id` - Group to invite user to
+    /// * `invitee_username` - Username to invite
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * User not initialized
+    /// * Server errors
+    pub async fn invite_user_to_group(
+        &mut self,
+        group_id: &[u8],
+        invitee_username: &str,
+    ) -> Result<()> {
+        // Get user first
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        // Get websocket
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        // Get membership
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        // Call membership method
+        membership
+            .invite_user(
+                invitee_username,
+                user,
+                &self.mls_provider,
+                &self.api,
+                &self.metadata_store,
+                websocket,
+            )
+            .await?;
+
+        if self.publish_group_info {
+            publish_group_info_or_warn(membership, user, &self.mls_provider, &self.api).await;
+        }
+
+        if self.store_welcomes {
+            // The invitee is already added; if the upload fails they can
+            // still join from the live Welcome or a resend
+            let stored = match self
+                .mls_provider
+                .load_sent_welcome(group_id, invitee_username)?
+            {
+                Some(welcome) => {
+                    self.api
+                        .store_welcome(
+                            &self.username,
+                            invitee_username,
+                            &welcome.welcome_blob,
+                            &welcome.ratchet_tree_blob,
+                        )
+                        .await
+                }
+                None => Err(MlsError::WelcomeNotFound {
+                    username: invitee_username.to_string(),
+                }
+                .into()),
+            };
+            if let Err(e) = stored {
+                log::warn!(
+                    "Failed to store the Welcome for {} on the server: {}",
+                    invitee_username,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a single-use join token for a group (admins only)
+    ///
+    /// Whoever redeems the token with `redeem_join_token` is added by the
+    /// next `admit_join_requests_in_group`, so the admin does not need to
+    /// know their username in advance. The token expires after `ttl_secs`
+    /// (the server's default, one day, when `None`) and can be revoked
+    /// until redeemed.
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    pub async fn create_join_token_in_group(
+        &self,
+        group_id: &[u8],
+        ttl_secs: Option<u64>,
+    ) -> Result<JoinToken> {
+        let membership = self
+            .memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+        if !membership.is_admin(&self.username) {
+            return Err(MlsError::NotAdmin {
+                username: self.username.clone(),
+            }
+            .into());
+        }
+
+        self.api
+            .create_join_token(group_id, &self.username, ttl_secs)
+            .await
+    }
+
+    /// Revoke an unredeemed join token of one of our groups
+    ///
+    /// Returns false if the token is unknown or was already redeemed.
+    pub async fn revoke_join_token(&self, token: &str) -> Result<bool> {
+        self.api.revoke_join_token(token, &self.username).await
+    }
+
+    /// Redeem a join token, asking to be added to its group
+    ///
+    /// Our KeyPackages are uploaded first, so the admin completing the add
+    /// can reserve one. We join once the admin's Welcome arrives. Returns
+    /// the id of the group the token is for.
+    ///
+    /// # Errors
+    /// * `MlsError::JoinTokenRejected` if the token is unknown, expired,
+    ///   revoked or already redeemed
+    pub async fn redeem_join_token(&mut self, token: &str) -> Result<Vec<u8>> {
+        self.refresh_key_packages().await?;
+        let group_id = self.api.redeem_join_token(token, &self.username).await?;
+        log::info!(
+            "Redeemed join token for group {}; waiting to be added",
+            general_purpose::STANDARD.encode(&group_id)
+        );
+        Ok(group_id)
+    }
+
+    /// Add everyone who redeemed a join token of a group (admins only)
+    ///
+    /// Each redeemer is invited as by `invite_user_to_group` and their
+    /// request marked done. Redeemers who are already members are only
+    /// marked done. Stops at the first failed invite, leaving it and the
+    /// requests after it for the next call. Returns the admitted usernames.
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * `MlsError::NotAdmin` if this user is not an admin of the group
+    /// * As for `invite_user_to_group`
+    pub async fn admit_join_requests_in_group(&mut self, group_id: &[u8]) -> Result<Vec<String>> {
+        let members = {
+            let membership = self
+                .memberships
+                .get(group_id)
+                .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+            if !membership.is_admin(&self.username) {
+                return Err(MlsError::NotAdmin {
+                    username: self.username.clone(),
+                }
+                .into());
+            }
+            membership.list_members()
+        };
+
+        let mut admitted = Vec::new();
+        for request in self.api.join_requests(group_id, &self.username).await? {
+            if !members.contains(&request.username) {
+                self.invite_user_to_group(group_id, &request.username)
+                    .await?;
+            }
+            if !self
+                .api
+                .complete_join_request(&request.token, &self.username)
+                .await?
+            {
+                log::warn!(
+                    "Join request of {} was already completed by another member",
+                    request.username
+                );
+            }
+            admitted.push(request.username);
+        }
+        Ok(admitted)
+    }
+
+    /// Remove a member from a specific group (admins only)
+    ///
+    /// See `MlsMembership::remove_member`. Returns the epoch the group moved
+    /// into, from which on the removed member can decrypt nothing. With
+    /// `set_self_update_after_remove` the removal is followed by a
+    /// self-update and the epoch after it is returned; if only the
+    /// self-update fails, the removal stands and its epoch is returned.
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * User not initialized
+    /// * WebSocket not connected
+    /// * as for `MlsMembership::remove_member`
+    pub async fn remove_member_from_group(
+        &mut self,
+        group_id: &[u8],
+        username: &str,
+    ) -> Result<u64> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        let mut epoch = membership
+            .remove_member(username, user, &self.mls_provider, &self.api, websocket)
+            .await?;
+        if self.self_update_after_remove {
+            match membership
+                .update_own_leaf(user, &self.mls_provider, &self.api, websocket)
+                .await
+            {
+                Ok(updated) => epoch = updated,
+                Err(e) => log::warn!(
+                    "Removed {} but the follow-up self-update failed: {}",
+                    username,
+                    e
+                ),
             }
         }
 
-        let mut promoted = 0usize;
-        for upload in uploads {
-            if rejected_refs.contains(&upload.keypackage_ref) {
-                continue;
-            }
+        if self.publish_group_info {
+            publish_group_info_or_warn(membership, user, &self.mls_provider, &self.api).await;
+        }
+        Ok(epoch)
+    }
 
-            self.metadata_store
-                .update_pool_metadata_status(&upload.keypackage_ref, "uploaded")?;
-            self.metadata_store
-                .update_pool_metadata_status(&upload.keypackage_ref, "available")?;
-            promoted += 1;
+    /// Set the disappearing-message TTL of a specific group
+    ///
+    /// See `MlsMembership::set_message_ttl`.
+    ///
+    /// # Errors
+    /// * Group not found
+    /// * User not initialized
+    /// * WebSocket not connected
+    /// * MLS errors while committing the change
+    pub async fn set_message_ttl_in_group(
+        &mut self,
+        group_id: &[u8],
+        ttl_secs: Option<u64>,
+    ) -> Result<()> {
+        let user = self
+            .user
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+
+        let websocket = self
+            .websocket
+            .as_ref()
+            .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
+
+        let membership = self
+            .memberships
+            .get_mut(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
+
+        membership
+            .set_message_ttl(ttl_secs, user, &self.mls_provider, &self.api, websocket)
+            .await?;
+
+        if self.publish_group_info {
+            publish_group_info_or_warn(membership, user, &self.mls_provider, &self.api).await;
         }
+        Ok(())
+    }
 
-        Ok(promoted)
+    /// Number of messages from other members after the group's read watermark
+    ///
+    /// The watermark is per group and persisted in the user's database.
+    pub fn unread_count(&self, group_id: &[u8]) -> Result<usize> {
+        self.mls_provider.unread_count(group_id, &self.username)
     }
 
-    fn load_keypackage_bytes(&self, metadata: &KeyPackageMetadata) -> Result<Option<Vec<u8>>> {
-        let reference = StoredKeyPackageRef(metadata.keypackage_ref.clone());
-        let maybe_bundle = self
-            .mls_provider
-            .storage()
-            .key_package::<_, KeyPackageBundle>(&reference)
-            .map_err(|err| ClientError::Mls(MlsError::OpenMls(err.to_string())))?;
+    /// Unread message counts keyed by group name
+    pub fn unread_counts(&self) -> Result<BTreeMap<String, usize>> {
+        self.mls_provider.unread_counts(&self.username)
+    }
 
-        if let Some(bundle) = maybe_bundle {
-            let serialized = bundle
-                .key_package()
-                .tls_serialize_detached()
-                .map_err(|err| ClientError::Mls(MlsError::OpenMls(err.to_string())))?;
-            Ok(Some(serialized))
-        } else {
-            Ok(None)
-        }
+    /// Local groups with their last activity and unread counts, in `order`
+    pub fn list_groups(&self, order: GroupSort) -> Result<Vec<GroupListing>> {
+        let mut groups = self.mls_provider.group_listings(&self.username)?;
+        sort_group_listings(&mut groups, order);
+        Ok(groups)
     }
 
-    /// Send a message to a specific group
+    /// Every local group with its roster size, epoch, unread count, last
+    /// activity and status, by name
     ///
-    /// Helper method that handles the borrow-checking complexity of accessing
-    /// both the membership and the services it needs.
+    /// Names, activity and unread counts come from one metadata query; loaded
+    /// groups answer the rest from memory. A group that is not loaded has its
+    /// MLS state read from storage, and is listed as
+    /// `GroupStatus::Unavailable` with the error if that fails, rather than
+    /// left out.
+    pub fn list_groups_detailed(&self) -> Result<Vec<GroupSummary>> {
+        let connected =
+            self.websocket.is_some() && self.connection_state.get() == ConnectionState::Connected;
+        let listings = self.mls_provider.group_listings_by_id(&self.username)?;
+        Ok(listings
+            .into_iter()
+            .map(|(group_id, listing)| {
+                let (member_count, epoch, status) = match self.memberships.get(&group_id) {
+                    Some(membership) => (
+                        Some(membership.member_count()),
+                        Some(membership.get_epoch()),
+                        if connected {
+                            GroupStatus::Subscribed
+                        } else {
+                            GroupStatus::Disconnected
+                        },
+                    ),
+                    None => {
+                        let stored = crypto::load_group_from_storage(
+                            &self.mls_provider,
+                            &GroupId::from_slice(&group_id),
+                        );
+                        match stored {
+                            // Counted like `MlsMembership::member_count`
+                            Ok(Some(group)) => (
+                                Some(
+                                    group
+                                        .members()
+                                        .filter_map(|member| {
+                                            crypto::member_identity(
+                                                &member.credential,
+                                                &member.signature_key,
+                                                &self.trust_anchors,
+                                            )
+                                        })
+                                        .count(),
+                                ),
+                                Some(group.epoch().as_u64()),
+                                GroupStatus::NotLoaded,
+                            ),
+                            Ok(None) => (
+                                None,
+                                None,
+                                GroupStatus::Unavailable {
+                                    error: "MLS state not found".to_string(),
+                                },
+                            ),
+                            Err(e) => (
+                                None,
+                                None,
+                                GroupStatus::Unavailable {
+                                    error: e.to_string(),
+                                },
+                            ),
+                        }
+                    }
+                };
+                GroupSummary {
+                    name: listing.name,
+                    group_id,
+                    member_count,
+                    epoch,
+                    unread: listing.unread,
+                    last_activity: listing.last_activity,
+                    status,
+                }
+            })
+            .collect())
+    }
+
+    /// Mark every message of a group as read and send a read receipt
     ///
-    /// # Arguments
-    /// * `group_id` - Group to send message to
-    /// * `text` - Message text
+    /// Advances the group's read watermark to its latest history entry. The
+    /// receipt is only sent when the watermark actually moved.
     ///
     /// # Errors
     /// * Group not found
     /// * User not initialized
     /// * WebSocket not connected
-    /// * MLS encryption errors
-    pub async fn send_message_to_group(&mut self, group_id: &[u8], text: &str) -> Result<()> {
-        // Get user first (immutable borrow)
+    pub async fn mark_all_read(&mut self, group_id: &[u8]) -> Result<()> {
         let user = self
             .user
             .as_ref()
             .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
 
-        // Get websocket (immutable borrow)
         let websocket = self
             .websocket
             .as_ref()
             .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
 
-        // Get membership (mutable borrow - but we've finished with user/websocket immutable borrows above)
         let membership = self
             .memberships
             .get_mut(group_id)
             .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
 
-        // Call membership method
-        membership
-            .send_message(text, user, &self.mls_provider, &self.api, websocket)
-            .await
+        if self.mls_provider.mark_all_read(group_id)? {
+            membership
+                .send_read_receipt(user, &self.mls_provider, websocket)
+                .await?;
+        }
+        Ok(())
     }
 
-    /// Invite a user to a specific group
-    ///
-    /// Helper method that handles the borrow-checking complexity.
+    /// Resend the stored Welcome to a member of a specific group
     ///
-    /// # Arguments
-    /// * `group_id` - Group to invite user to
-    /// * `invitee_username` - Username to invite
+    /// See `MlsMembership::resend_welcome`.
     ///
     /// # Errors
     /// * Group not found
     /// * User not initialized
-    /// * Server errors
-    pub async fn invite_user_to_group(
-        &mut self,
-        group_id: &[u8],
-        invitee_username: &str,
-    ) -> Result<()> {
-        // Get user first
+    /// * WebSocket not connected
+    /// * Member not found, or the stored Welcome is missing or stale
+    pub async fn resend_welcome_in_group(&self, group_id: &[u8], username: &str) -> Result<()> {
         let user = self
             .user
             .as_ref()
             .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
 
-        // Get websocket
         let websocket = self
             .websocket
             .as_ref()
             .ok_or_else(|| ClientError::Config("WebSocket not connected".to_string()))?;
 
-        // Get membership
         let membership = self
             .memberships
-            .get_mut(group_id)
+            .get(group_id)
             .ok_or_else(|| ClientError::Config("Group not found".to_string()))?;
 
-        // Call membership method
         membership
-            .invite_user(
-                invitee_username,
-                user,
-                &self.mls_provider,
-                &self.api,
-                &self.metadata_store,
-                websocket,
+            .resend_welcome(username, user, &self.mls_provider, websocket)
+            .await
+    }
+
+    /// Join time and last activity of a group's members
+    ///
+    /// See `MlsMembership::member_activity`.
+    ///
+    /// # Errors
+    /// * Group not found
+    pub fn member_activity(&self, group_id: &[u8]) -> Result<Vec<MemberActivity>> {
+        self.memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?
+            .member_activity(&self.mls_provider)
+    }
+
+    /// Invites of a group still waiting for the invitee to show up
+    ///
+    /// See `MlsMembership::pending_invites`.
+    ///
+    /// # Errors
+    /// * Group not found
+    pub fn pending_invites(&self, group_id: &[u8]) -> Result<Vec<PendingInvite>> {
+        self.memberships
+            .get(group_id)
+            .ok_or_else(|| ClientError::Config("Group not found".to_string()))?
+            .pending_invites(&self.mls_provider)
+    }
+
+    /// Resend the Welcome of every pending invite of a group that still can be
+    ///
+    /// Returns the invitees whose Welcome was resent. Invites made before the
+    /// group's current epoch cannot be resent (`MlsError::WelcomeStale`); they
+    /// are logged and stay pending until the invitee is removed and invited
+    /// again.
+    ///
+    /// # Errors
+    /// * As for `resend_welcome_in_group`, except stale Welcomes
+    pub async fn resend_pending_invites(&self, group_id: &[u8]) -> Result<Vec<String>> {
+        let mut resent = Vec::new();
+        for invite in self.pending_invites(group_id)? {
+            match self
+                .resend_welcome_in_group(group_id, &invite.invitee)
+                .await
+            {
+                Ok(()) => resent.push(invite.invitee),
+                Err(ClientError::Mls(MlsError::WelcomeStale { .. })) => {
+                    log::warn!(
+                        "Welcome to {} is from epoch {} and can no longer be resent",
+                        invite.invitee,
+                        invite.epoch
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(resent)
+    }
+}
+
+/// Sends to one group, borrowed from its connection
+///
+/// Obtained from `MlsConnection::group_senders`. The sender owns the group's
+/// state while it lives and locks it for the whole of each send, from
+/// encryption until the envelope is queued, so concurrent sends through one
+/// sender leave in the order they were encrypted.
+pub struct GroupSender<'c> {
+    group_id: Vec<u8>,
+    membership: tokio::sync::Mutex<&'c mut MlsMembership<'static>>,
+    /// Lock shared with the other senders under `SendConcurrency::Serial`
+    shared_lock: Option<Arc<tokio::sync::Mutex<()>>>,
+    user: &'c MlsUser,
+    provider: &'c MlsProvider,
+    websocket: &'c MessageHandler,
+    compress: bool,
+}
+
+impl GroupSender<'_> {
+    /// The group this sender sends to
+    pub fn group_id(&self) -> &[u8] {
+        &self.group_id
+    }
+
+    /// Send a message to the group (see `MlsConnection::send_message_to_group`)
+    ///
+    /// Waits for any other send through this sender (or, under
+    /// `SendConcurrency::Serial`, through any sender of the same
+    /// `group_senders` call) to finish first.
+    ///
+    /// # Errors
+    /// * as for `MlsConnection::send_message_to_group`
+    pub async fn send_message(&self, text: &str) -> Result<()> {
+        let _serial = match &self.shared_lock {
+            Some(lock) => Some(lock.lock().await),
+            None => None,
+        };
+        let mut membership = self.membership.lock().await;
+        membership
+            .send_formatted_message(
+                text,
+                TextFormat::Plain,
+                self.compress,
+                self.user,
+                self.provider,
+                self.websocket,
             )
             .await
     }
 }
 
+/// Publish a membership's GroupInfo after our own Commit, logging failures
+///
+/// The Commit is already merged and sent, so a failed publish only means
+/// external joiners see an older GroupInfo until the next one.
+async fn publish_group_info_or_warn(
+    membership: &MlsMembership<'static>,
+    user: &MlsUser,
+    provider: &MlsProvider,
+    api: &ServerApi,
+) {
+    if let Err(e) = membership.publish_group_info(user, provider, api).await {
+        log::warn!(
+            "Failed to publish GroupInfo of {}: {}",
+            membership.get_group_name(),
+            e
+        );
+    }
+}
+
+/// Restrict the storage directory to its owner (0700) and the databases to 0600
+///
+/// Best effort: a path that cannot be tightened (e.g. owned by another user)
+/// only logs a warning. SQLite creates `-wal`/`-shm`/`-journal` files with the
+/// mode of their database, so existing ones are tightened too.
+#[cfg(unix)]
+fn restrict_storage_permissions(storage_dir: &Path, db_paths: &[&Path]) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let set_mode = |path: &Path, mode: u32| {
+        if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)) {
+            log::warn!(
+                "Could not restrict permissions of {} to {:o}: {}",
+                path.display(),
+                mode,
+                e
+            );
+        }
+    };
+
+    set_mode(storage_dir, 0o700);
+    for db_path in db_paths {
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let mut path = db_path.as_os_str().to_owned();
+            path.push(suffix);
+            let path = PathBuf::from(path);
+            if path.exists() {
+                set_mode(&path, 0o600);
+            }
+        }
+    }
+}
+
+/// Permissions are left to the platform's ACLs off Unix
+#[cfg(not(unix))]
+fn restrict_storage_permissions(_storage_dir: &Path, _db_paths: &[&Path]) {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -986,6 +3713,28 @@ mod tests {
         assert_eq!(connection.get_user().unwrap().get_username(), "alice");
     }
 
+    /// The server-assigned id is learned on initialize and is the same when
+    /// the user opens their storage again
+    #[tokio::test]
+    async fn test_initialize_records_stable_server_user_id() {
+        let (server, addr) = mls_chat_server::server::create_test_http_server().unwrap();
+        tokio::spawn(server);
+        let server_url = format!("http://{}", addr);
+        let temp_dir = tempdir().unwrap();
+
+        let mut first =
+            MlsConnection::new_with_storage_path(&server_url, "alice", temp_dir.path()).unwrap();
+        assert_eq!(first.get_server_user_id(), None);
+        first.initialize().await.unwrap();
+        let id = first.get_server_user_id().expect("id after registration");
+        drop(first);
+
+        let mut second =
+            MlsConnection::new_with_storage_path(&server_url, "alice", temp_dir.path()).unwrap();
+        second.initialize().await.unwrap();
+        assert_eq!(second.get_server_user_id(), Some(id));
+    }
+
     /// Test that accessors return correct values
     ///
     /// Verifies:
@@ -1065,11 +3814,13 @@ mod tests {
         let bob_storage = temp_dir.path().join("bob");
         std::fs::create_dir_all(&bob_storage).unwrap();
 
+        let (server, addr) = mls_chat_server::server::create_test_http_server().unwrap();
+        tokio::spawn(server);
         let mut bob_connection =
-            MlsConnection::new_with_storage_path("http://localhost:4000", "bob", &bob_storage)
+            MlsConnection::new_with_storage_path(&format!("http://{}", addr), "bob", &bob_storage)
                 .unwrap();
 
-        // Initialize Bob's user (server registration will fail, but user is created locally)
+        // Initialize Bob's user; the server only supplies the group roster here
         let _ = bob_connection.initialize().await;
 
         // Inject mock WebSocket for testing
@@ -1137,6 +3888,495 @@ mod tests {
         assert_eq!(membership.list_members().len(), 2);
     }
 
+    /// A Welcome that would take the user beyond their group limit is refused
+    #[tokio::test]
+    async fn test_welcome_beyond_group_limit_is_rejected() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        harness.connection_mut("bob").unwrap().set_max_groups(0);
+
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        let err = harness
+            .invite("alice", &group_id, "bob")
+            .await
+            .expect_err("bob cannot join any group");
+        assert!(matches!(
+            err,
+            ClientError::Mls(MlsError::GroupLimitReached { limit: 0, .. })
+        ));
+
+        let bob = harness.connection("bob").unwrap();
+        assert!(bob.get_membership(&group_id).is_none());
+        assert_eq!(bob.get_provider().group_count().unwrap(), 0);
+    }
+
+    /// The detailed group list matches each group's state, and a group
+    /// whose MLS state is missing is listed with an error
+    #[tokio::test]
+    async fn test_list_groups_detailed_matches_group_state() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness
+            .send_message("alice", &group_id, "one")
+            .await
+            .unwrap();
+        harness
+            .send_message("alice", &group_id, "two")
+            .await
+            .unwrap();
+
+        let bob = harness.connection("bob").unwrap();
+        bob.get_provider()
+            .save_group_name("bob:ghost", b"no-such-group")
+            .unwrap();
+
+        let groups = bob.list_groups_detailed().unwrap();
+        assert_eq!(groups.len(), 2);
+        let ghost = &groups[0];
+        assert_eq!(ghost.name, "ghost");
+        assert_eq!(ghost.member_count, None);
+        assert_eq!(ghost.epoch, None);
+        assert!(matches!(ghost.status, GroupStatus::Unavailable { .. }));
+
+        let team = &groups[1];
+        let membership = bob.get_membership(&group_id).unwrap();
+        assert_eq!(team.name, "team");
+        assert_eq!(team.group_id, group_id);
+        assert_eq!(team.member_count, Some(membership.member_count()));
+        assert_eq!(team.member_count, Some(2));
+        assert_eq!(team.epoch, harness.epoch("bob", &group_id));
+        assert_eq!(team.unread, bob.unread_count(&group_id).unwrap());
+        assert_eq!(team.unread, 2);
+        let listing = bob
+            .list_groups(GroupSort::Name)
+            .unwrap()
+            .into_iter()
+            .find(|listing| listing.name == "team")
+            .unwrap();
+        assert_eq!(team.last_activity, listing.last_activity);
+        assert!(team.last_activity.is_some());
+        assert_eq!(team.status, GroupStatus::Subscribed);
+    }
+
+    /// Unread counts exclude own messages and clear with a read receipt
+    #[tokio::test]
+    async fn test_unread_count_and_mark_all_read() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        harness
+            .send_message("alice", &group_id, "one")
+            .await
+            .unwrap();
+        harness
+            .send_message("alice", &group_id, "two")
+            .await
+            .unwrap();
+        harness
+            .send_message("bob", &group_id, "three")
+            .await
+            .unwrap();
+
+        let unread = |harness: &ConvergenceHarness, member: &str| {
+            harness
+                .connection(member)
+                .unwrap()
+                .unread_count(&group_id)
+                .unwrap()
+        };
+        assert_eq!(unread(&harness, "bob"), 2);
+        assert_eq!(unread(&harness, "alice"), 1);
+
+        harness
+            .connection_mut("bob")
+            .unwrap()
+            .mark_all_read(&group_id)
+            .await
+            .unwrap();
+        harness.pump().await.unwrap();
+
+        assert_eq!(unread(&harness, "bob"), 0);
+        assert_eq!(unread(&harness, "alice"), 1);
+
+        // The receipt is not shown to alice as a chat message
+        let alice = harness.connection("alice").unwrap();
+        let history = alice.get_provider().load_history(&group_id).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .filter(|entry| entry.content_type == ContentType::Text)
+                .count(),
+            3
+        );
+        assert_eq!(alice.unread_counts().unwrap()["team"], 1);
+
+        // New messages after the watermark are unread again
+        harness
+            .send_message("alice", &group_id, "four")
+            .await
+            .unwrap();
+        assert_eq!(unread(&harness, "bob"), 1);
+    }
+
+    /// Sending by name reaches the named group; unknown and shared names are
+    /// errors instead of a guess
+    #[tokio::test]
+    async fn test_send_message_by_name() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let team = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &team, "bob").await.unwrap();
+
+        harness
+            .connection_mut("alice")
+            .unwrap()
+            .send_message_by_name("team", "hello team")
+            .await
+            .unwrap();
+        harness.pump().await.unwrap();
+        assert_eq!(
+            harness
+                .connection("bob")
+                .unwrap()
+                .unread_count(&team)
+                .unwrap(),
+            1
+        );
+
+        let err = harness
+            .connection_mut("alice")
+            .unwrap()
+            .send_message_by_name("nonexistent", "hello")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::Mls(MlsError::UnknownGroupName { ref name }) if name == "nonexistent"
+        ));
+
+        // Carol invites alice to another group called "team"; bob could not
+        // create one, as his name already maps to alice's
+        harness.add_member("carol").await.unwrap();
+        let other = harness.create_group("carol", "team").await.unwrap();
+        harness.invite("carol", &other, "alice").await.unwrap();
+        let err = harness
+            .connection_mut("alice")
+            .unwrap()
+            .send_message_by_name("team", "which one?")
+            .await
+            .unwrap_err();
+        match err {
+            ClientError::Mls(MlsError::AmbiguousGroupName { ref group_ids, .. }) => {
+                assert_eq!(group_ids.len(), 2);
+                assert!(group_ids.contains(&general_purpose::STANDARD.encode(&team)));
+                assert!(group_ids.contains(&general_purpose::STANDARD.encode(&other)));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        // Nothing was sent to either group
+        harness.pump().await.unwrap();
+        assert_eq!(
+            harness
+                .connection("bob")
+                .unwrap()
+                .unread_count(&team)
+                .unwrap(),
+            1
+        );
+    }
+
+    /// The creator's announcements reach members as system entries; a member
+    /// who is not an admin cannot send one, and a forged one is ignored
+    #[tokio::test]
+    async fn test_announcements_only_from_admins() {
+        use crate::mls::test_harness::ConvergenceHarness;
+        use crate::models::ControlMessage;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        let announcements = |harness: &ConvergenceHarness, member: &str| -> Vec<(String, String)> {
+            let provider = harness.connection(member).unwrap().get_provider();
+            provider
+                .load_history(&group_id)
+                .unwrap()
+                .into_iter()
+                .filter(|entry| entry.content_type == ContentType::System)
+                .filter(|entry| !entry.content.contains("added"))
+                .map(|entry| (entry.sender, entry.content))
+                .collect()
+        };
+
+        // Admin sends: accepted and stored as a system entry on both sides
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(membership.admins(), vec!["alice".to_string()]);
+        harness
+            .connection_mut("alice")
+            .unwrap()
+            .send_announcement_to_group(&group_id, "Offsite on Friday")
+            .await
+            .unwrap();
+        harness.pump().await.unwrap();
+        let expected = vec![("alice".to_string(), "Offsite on Friday".to_string())];
+        assert_eq!(announcements(&harness, "alice"), expected);
+        assert_eq!(announcements(&harness, "bob"), expected);
+
+        // Non-admin: refused locally
+        let err = harness
+            .connection_mut("bob")
+            .unwrap()
+            .send_announcement_to_group(&group_id, "Free pizza")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::Mls(MlsError::NotAdmin { ref username }) if username == "bob"
+        ));
+
+        // Non-admin bypassing the local check: alice ignores it
+        let bob = harness.connection_mut("bob").unwrap();
+        let forged = ControlMessage::Announcement {
+            text: "Free pizza".to_string(),
+        };
+        bob.memberships
+            .get_mut(&group_id)
+            .unwrap()
+            .send_control(
+                &forged,
+                ContentType::System,
+                bob.user.as_ref().unwrap(),
+                &bob.mls_provider,
+                bob.websocket.as_ref().unwrap(),
+            )
+            .await
+            .unwrap();
+        harness.pump().await.unwrap();
+        assert_eq!(announcements(&harness, "alice"), expected);
+
+        // The forged message still advanced alice's ratchet: chat keeps working
+        harness
+            .send_message("bob", &group_id, "hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            harness
+                .connection("alice")
+                .unwrap()
+                .unread_count(&group_id)
+                .unwrap(),
+            1
+        );
+    }
+
+    /// An admin's pin reaches every member, unpinning twice is harmless, and
+    /// pins of unknown messages or from non-admins are refused
+    /// Uppercases on send, lowercases on receive
+    struct Shout;
+
+    impl MessageTransform for Shout {
+        fn outgoing(&self, text: &str) -> Result<String> {
+            Ok(text.to_uppercase())
+        }
+
+        fn incoming(&self, text: &str) -> Result<String> {
+            Ok(text.to_lowercase())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_transforms_apply_on_send_and_receive() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        for member in ["alice", "bob", "carol"] {
+            harness.add_member(member).await.unwrap();
+        }
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+
+        // Carol has no transform and sees the text as it was encrypted
+        for member in ["alice", "bob"] {
+            harness
+                .connection(member)
+                .unwrap()
+                .add_message_transform(Arc::new(Shout));
+        }
+        harness
+            .send_message("alice", &group_id, "Quiet please")
+            .await
+            .unwrap();
+
+        let last_text = |harness: &ConvergenceHarness, member: &str| -> String {
+            harness
+                .connection(member)
+                .unwrap()
+                .get_provider()
+                .load_history(&group_id)
+                .unwrap()
+                .into_iter()
+                .rev()
+                .find(|entry| entry.content_type == ContentType::Text)
+                .unwrap()
+                .content
+        };
+        assert_eq!(last_text(&harness, "alice"), "QUIET PLEASE");
+        assert_eq!(last_text(&harness, "carol"), "QUIET PLEASE");
+        assert_eq!(last_text(&harness, "bob"), "quiet please");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_sent_and_received_messages() {
+        use crate::metrics::{MessageMetrics, MetricsSnapshot};
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        let metrics = |harness: &ConvergenceHarness, member: &str| {
+            harness.connection(member).unwrap().metrics()
+        };
+        let alice_before = metrics(&harness, "alice");
+        let bob_before = metrics(&harness, "bob");
+        harness
+            .send_message("alice", &group_id, "secret plans")
+            .await
+            .unwrap();
+        let alice = metrics(&harness, "alice");
+        let bob = metrics(&harness, "bob");
+
+        if !MessageMetrics::ENABLED {
+            assert_eq!(alice, MetricsSnapshot::default());
+            assert_eq!(bob, MetricsSnapshot::default());
+            return;
+        }
+        assert_eq!(alice.messages_sent, alice_before.messages_sent + 1);
+        assert_eq!(bob.messages_received, bob_before.messages_received + 1);
+        assert_eq!(bob.decryption_failures, 0);
+        // Both see the same encrypted message size
+        assert_eq!(
+            alice.bytes_sent - alice_before.bytes_sent,
+            bob.bytes_received - bob_before.bytes_received
+        );
+        assert!(!alice.to_prometheus().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_pins_only_from_admins() {
+        use crate::mls::test_harness::ConvergenceHarness;
+
+        let mut harness = ConvergenceHarness::start().await;
+        harness.add_member("alice").await.unwrap();
+        harness.add_member("bob").await.unwrap();
+        let group_id = harness.create_group("alice", "team").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        harness
+            .send_message("alice", &group_id, "Agenda is in the doc")
+            .await
+            .unwrap();
+        let pinned = |harness: &ConvergenceHarness, member: &str| -> Vec<String> {
+            harness
+                .connection(member)
+                .unwrap()
+                .get_pinned_messages(&group_id)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.content)
+                .collect()
+        };
+        let message_id = |harness: &ConvergenceHarness, member: &str| -> String {
+            harness
+                .connection(member)
+                .unwrap()
+                .get_provider()
+                .load_history(&group_id)
+                .unwrap()
+                .into_iter()
+                .find(|entry| entry.content == "Agenda is in the doc")
+                .and_then(|entry| entry.message_id)
+                .unwrap()
+        };
+        // Sender and receiver derive the same id for the message
+        let id = message_id(&harness, "alice");
+        assert_eq!(message_id(&harness, "bob"), id);
+
+        // Admin pins: both sides see it
+        harness
+            .connection_mut("alice")
+            .unwrap()
+            .pin_message_in_group(&group_id, &id, false)
+            .await
+            .unwrap();
+        harness.pump().await.unwrap();
+        let expected = vec!["Agenda is in the doc".to_string()];
+        assert_eq!(pinned(&harness, "alice"), expected);
+        assert_eq!(pinned(&harness, "bob"), expected);
+
+        // Non-admin: refused locally
+        let err = harness
+            .connection_mut("bob")
+            .unwrap()
+            .pin_message_in_group(&group_id, &id, true)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::Mls(MlsError::NotAdmin { ref username }) if username == "bob"
+        ));
+
+        // Unknown message: refused
+        let err = harness
+            .connection_mut("alice")
+            .unwrap()
+            .pin_message_in_group(&group_id, "00000000000000000000000000000000", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::Mls(MlsError::MessageNotFound { .. })
+        ));
+
+        // Unpin is idempotent
+        for _ in 0..2 {
+            harness
+                .connection_mut("alice")
+                .unwrap()
+                .pin_message_in_group(&group_id, &id, true)
+                .await
+                .unwrap();
+            harness.pump().await.unwrap();
+            assert!(pinned(&harness, "alice").is_empty());
+            assert!(pinned(&harness, "bob").is_empty());
+        }
+    }
+
     /// Test that ApplicationMessage routing works
     ///
     /// Verifies:
@@ -1159,8 +4399,10 @@ mod tests {
         // Bob setup
         let bob_storage = temp_dir.path().join("bob");
         std::fs::create_dir_all(&bob_storage).unwrap();
+        let (server, addr) = mls_chat_server::server::create_test_http_server().unwrap();
+        tokio::spawn(server);
         let mut bob_connection =
-            MlsConnection::new_with_storage_path("http://localhost:4000", "bob", &bob_storage)
+            MlsConnection::new_with_storage_path(&format!("http://{}", addr), "bob", &bob_storage)
                 .unwrap();
         let _ = bob_connection.initialize().await;
 
@@ -1230,6 +4472,9 @@ mod tests {
             sender: "alice".to_string(),
             group_id: group_id_b64,
             encrypted_content: encrypted_b64,
+            content_type: ContentType::Text,
+            seq: None,
+            epoch: None,
         };
 
         // === Bob processes the message ===
@@ -1262,8 +4507,10 @@ mod tests {
         // Bob setup and join
         let bob_storage = temp_dir.path().join("bob");
         std::fs::create_dir_all(&bob_storage).unwrap();
+        let (server, addr) = mls_chat_server::server::create_test_http_server().unwrap();
+        tokio::spawn(server);
         let mut bob_connection =
-            MlsConnection::new_with_storage_path("http://localhost:4000", "bob", &bob_storage)
+            MlsConnection::new_with_storage_path(&format!("http://{}", addr), "bob", &bob_storage)
                 .unwrap();
         let _ = bob_connection.initialize().await;
 
@@ -1338,6 +4585,7 @@ mod tests {
             group_id: group_id_b64,
             sender: "alice".to_string(),
             commit_blob: commit2_b64,
+            device_id: None,
         };
 
         // Process commit
@@ -1350,4 +4598,28 @@ mod tests {
         let bob_membership = bob_connection.get_membership(&group_id).unwrap();
         assert_eq!(bob_membership.list_members().len(), 3);
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_storage_permissions_are_restricted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let storage_dir = temp_dir.path().join("storage");
+        std::fs::create_dir(&storage_dir).unwrap();
+        std::fs::set_permissions(&storage_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // A database left world-readable by an older client is tightened too
+        let metadata_db = storage_dir.join("metadata.db");
+        std::fs::write(&metadata_db, b"").unwrap();
+        std::fs::set_permissions(&metadata_db, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let _connection =
+            MlsConnection::new_with_storage_path("http://localhost:4000", "alice", &storage_dir)
+                .unwrap();
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&storage_dir), 0o700);
+        assert_eq!(mode(&metadata_db), 0o600);
+        assert_eq!(mode(&storage_dir.join("mls-alice.db")), 0o600);
+    }
 }
@@ -4,6 +4,7 @@
 //! tracking, and cleaning up KeyPackages using the OpenMLS storage
 //! provider together with LocalStore metadata.
 
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::{debug, warn};
@@ -84,6 +85,9 @@ impl<'a> KeyPackagePool<'a> {
 
         for _ in 0..count {
             let bundle = KeyPackage::builder()
+                .leaf_node_capabilities(crate::crypto::capabilities_for(
+                    credential.credential.credential_type(),
+                ))
                 .build(ciphersuite, provider, signer, credential.clone())
                 .map_err(|e| MlsError::OpenMls(e.to_string()))?;
 
@@ -116,6 +120,11 @@ impl<'a> KeyPackagePool<'a> {
         self.store.count_by_status("available")
     }
 
+    /// Count the locally tracked KeyPackages per lifecycle status.
+    pub fn stats(&self) -> Result<BTreeMap<String, usize>> {
+        self.store.count_all_by_status()
+    }
+
     /// Count KeyPackages that remain locally and are not yet consumed.
     fn get_unspent_count(&self) -> Result<usize> {
         let available = self.get_available_count()?;
@@ -4,14 +4,17 @@
 //! - `user`: User identity and credential management
 //! - `membership`: Group session state and operations
 //! - `connection`: Infrastructure and message routing
+//! - `test_harness`: In-process transport for convergence tests (tests only)
 
 pub mod connection;
 pub mod keypackage_pool;
 pub mod membership;
+#[cfg(test)]
+pub(crate) mod test_harness;
 pub mod user;
 
 // Re-export for convenience
-pub use connection::MlsConnection;
+pub use connection::{GroupSender, MlsConnection};
 pub use keypackage_pool::{KeyPackagePool, KeyPackagePoolConfig};
 pub use membership::MlsMembership;
 pub use user::MlsUser;
@@ -0,0 +1,1507 @@
+//! Test harness for membership convergence
+//!
+//! Drives several `MlsConnection`s in-process and plays the WebSocket server
+//! for them: every envelope a connection sends is captured and routed to the
+//! other connections by the harness (Welcomes to the invitee, Commits and
+//! application messages to the group's subscribers). HTTP calls (KeyPackage
+//! upload and reservation) go to an in-process test server.
+//!
+//! Commits can be dropped or delayed per recipient to provoke the epoch
+//! desyncs described in `docs/membership-learn.md`. Every routed Commit is
+//! also kept in a log, and `sync` replays the ones a member missed, which is
+//! how a lagging member catches up. `assert_converged` then checks that all
+//! members agree on the epoch and roster.
+
+use crate::error::{ClientError, Result};
+use crate::mls::connection::MlsConnection;
+use crate::mls::membership::MlsMembership;
+use crate::models::MlsMessageEnvelope;
+use crate::websocket::MessageHandler;
+use base64::{engine::general_purpose, Engine as _};
+use futures::channel::mpsc::UnboundedReceiver;
+use openmls::prelude::MlsMessageIn;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use tempfile::TempDir;
+use tls_codec::Deserialize;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Fault applied to the next Commit routed to a given member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CommitFault {
+    /// The Commit is never delivered (it stays in the log for `sync`)
+    Drop,
+    /// The Commit is held back until `release_held`, so later messages overtake it
+    Delay,
+}
+
+/// A Commit as the server saw it, kept for replay by `sync`
+struct LoggedCommit {
+    group_id: String,
+    sender: String,
+    epoch: u64,
+    envelope: MlsMessageEnvelope,
+}
+
+/// One connection driven by the harness
+struct HarnessMember {
+    connection: MlsConnection,
+    outbox: UnboundedReceiver<Message>,
+    subscriptions: HashSet<String>,
+}
+
+/// In-process transport connecting several `MlsConnection`s
+pub(crate) struct ConvergenceHarness {
+    server_url: String,
+    storage: TempDir,
+    members: BTreeMap<String, HarnessMember>,
+    /// Envelopes routed but not yet delivered, as (recipient, envelope)
+    in_flight: VecDeque<(String, MlsMessageEnvelope)>,
+    held: HashMap<String, Vec<MlsMessageEnvelope>>,
+    faults: HashMap<String, VecDeque<CommitFault>>,
+    commit_log: Vec<LoggedCommit>,
+    /// Latest application message routed in each group, by base64 group id
+    latest_messages: HashMap<String, MlsMessageEnvelope>,
+}
+
+impl ConvergenceHarness {
+    /// Start a test server and an empty harness
+    pub(crate) async fn start() -> Self {
+        let (server, addr) = mls_chat_server::server::create_test_http_server()
+            .expect("Failed to create test server");
+        tokio::spawn(server);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        Self {
+            server_url: format!("http://{}", addr),
+            storage: tempfile::tempdir().expect("Failed to create temp dir"),
+            members: BTreeMap::new(),
+            in_flight: VecDeque::new(),
+            held: HashMap::new(),
+            faults: HashMap::new(),
+            commit_log: Vec::new(),
+            latest_messages: HashMap::new(),
+        }
+    }
+
+    /// Create, initialize and attach a connection for `username`
+    ///
+    /// The member's KeyPackages are uploaded so others can invite them.
+    pub(crate) async fn add_member(&mut self, username: &str) -> Result<()> {
+        let storage_dir = self.storage.path().join(username);
+        let mut connection =
+            MlsConnection::new_with_storage_path(&self.server_url, username, &storage_dir)?;
+        connection.initialize().await?;
+        connection.refresh_key_packages().await?;
+
+        let (websocket, outbox) = MessageHandler::new_capturing_mock();
+        connection.set_websocket(websocket);
+
+        self.members.insert(
+            username.to_string(),
+            HarnessMember {
+                connection,
+                outbox,
+                subscriptions: HashSet::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Create a group owned by `creator` and return its id
+    pub(crate) async fn create_group(
+        &mut self,
+        creator: &str,
+        group_name: &str,
+    ) -> Result<Vec<u8>> {
+        let connection = &mut self.member_mut(creator)?.connection;
+        let user = connection
+            .get_user()
+            .ok_or_else(|| ClientError::Config("User not initialized".to_string()))?;
+        let membership =
+            MlsMembership::create_new_group(group_name, user, connection.get_provider())?;
+        let group_id = membership.get_group_id().to_vec();
+
+        connection.add_membership(membership);
+        connection.record_group_membership(&group_id).await?;
+        connection.subscribe_to_group(&group_id).await?;
+        self.pump().await?;
+        Ok(group_id)
+    }
+
+    /// Have `inviter` invite `invitee`, then deliver the resulting messages
+    pub(crate) async fn invite(
+        &mut self,
+        inviter: &str,
+        group_id: &[u8],
+        invitee: &str,
+    ) -> Result<()> {
+        self.member_mut(inviter)?
+            .connection
+            .invite_user_to_group(group_id, invitee)
+            .await?;
+        self.pump().await
+    }
+
+    /// Run several invites before delivering any of their messages
+    ///
+    /// Each `(inviter, invitee)` pair is invited in order without pumping in
+    /// between, so every inviter builds its Commit without having received
+    /// the Commits of the others.
+    pub(crate) async fn invite_concurrently(
+        &mut self,
+        group_id: &[u8],
+        invites: &[(&str, &str)],
+    ) -> Result<()> {
+        for (inviter, invitee) in invites {
+            self.member_mut(inviter)?
+                .connection
+                .invite_user_to_group(group_id, invitee)
+                .await?;
+        }
+        self.pump().await
+    }
+
+    /// Have `sender` send an application message, then deliver it
+    pub(crate) async fn send_message(
+        &mut self,
+        sender: &str,
+        group_id: &[u8],
+        text: &str,
+    ) -> Result<()> {
+        self.member_mut(sender)?
+            .connection
+            .send_message_to_group(group_id, text)
+            .await?;
+        self.pump().await
+    }
+
+    /// Have `sender` reply to the message `quoted_message_id`, then deliver it
+    pub(crate) async fn send_reply(
+        &mut self,
+        sender: &str,
+        group_id: &[u8],
+        quoted_message_id: &str,
+        text: &str,
+    ) -> Result<()> {
+        self.member_mut(sender)?
+            .connection
+            .send_reply_to_group(group_id, quoted_message_id, text)
+            .await?;
+        self.pump().await
+    }
+
+    /// Have `admin` remove `member`, then deliver the Commit
+    ///
+    /// Returns the epoch the group moved into.
+    pub(crate) async fn remove_member(
+        &mut self,
+        admin: &str,
+        group_id: &[u8],
+        member: &str,
+    ) -> Result<u64> {
+        let epoch = self
+            .member_mut(admin)?
+            .connection
+            .remove_member_from_group(group_id, member)
+            .await?;
+        self.pump().await?;
+        Ok(epoch)
+    }
+
+    /// Deliver the group's latest application message to `recipient` again,
+    /// whether or not they are subscribed
+    ///
+    /// Plays a server that still forwards the group's traffic to a removed
+    /// member. Returns the result of processing it.
+    pub(crate) async fn redeliver_latest_message(
+        &mut self,
+        recipient: &str,
+        group_id: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let envelope = self
+            .latest_messages
+            .get(&general_purpose::STANDARD.encode(group_id))
+            .cloned()
+            .ok_or_else(|| ClientError::Config("No message sent in the group".to_string()))?;
+        self.member_mut(recipient)?
+            .connection
+            .process_incoming_envelope(envelope)
+            .await
+    }
+
+    /// Have `member` change the group's message TTL, then deliver the Commit
+    pub(crate) async fn set_message_ttl(
+        &mut self,
+        member: &str,
+        group_id: &[u8],
+        ttl_secs: Option<u64>,
+    ) -> Result<()> {
+        self.member_mut(member)?
+            .connection
+            .set_message_ttl_in_group(group_id, ttl_secs)
+            .await?;
+        self.pump().await
+    }
+
+    /// Apply `fault` to the next Commit routed to `recipient`
+    ///
+    /// Faults queue up, one per Commit, in the order they were injected.
+    pub(crate) fn inject(&mut self, recipient: &str, fault: CommitFault) {
+        self.faults
+            .entry(recipient.to_string())
+            .or_default()
+            .push_back(fault);
+    }
+
+    /// Deliver the Commits held back from `recipient`, oldest first
+    pub(crate) async fn release_held(&mut self, recipient: &str) -> Result<()> {
+        for envelope in self.held.remove(recipient).unwrap_or_default() {
+            self.in_flight.push_back((recipient.to_string(), envelope));
+        }
+        self.pump().await
+    }
+
+    /// Replay the logged Commits `member` is missing, in epoch order
+    pub(crate) async fn sync(&mut self, member: &str, group_id: &[u8]) -> Result<()> {
+        let group_id_b64 = general_purpose::STANDARD.encode(group_id);
+        loop {
+            let epoch = self
+                .epoch(member, group_id)
+                .ok_or_else(|| ClientError::Config(format!("{} is not in the group", member)))?;
+            let Some(commit) = self.commit_log.iter().find(|commit| {
+                commit.group_id == group_id_b64 && commit.epoch == epoch && commit.sender != member
+            }) else {
+                break;
+            };
+
+            let envelope = commit.envelope.clone();
+            self.member_mut(member)?
+                .connection
+                .process_incoming_envelope(envelope)
+                .await?;
+            if self.epoch(member, group_id) == Some(epoch) {
+                // The Commit could not be applied; replaying it again won't help
+                break;
+            }
+        }
+        self.pump().await
+    }
+
+    /// Route and deliver messages until every outbox is empty
+    pub(crate) async fn pump(&mut self) -> Result<()> {
+        loop {
+            self.route_outgoing()?;
+            let Some((recipient, envelope)) = self.in_flight.pop_front() else {
+                return Ok(());
+            };
+            self.member_mut(&recipient)?
+                .connection
+                .process_incoming_envelope(envelope)
+                .await?;
+        }
+    }
+
+    /// Mutable connection of `member`, for changing their settings
+    pub(crate) fn connection_mut(&mut self, member: &str) -> Option<&mut MlsConnection> {
+        self.members
+            .get_mut(member)
+            .map(|member| &mut member.connection)
+    }
+
+    /// Current epoch of `member` in the group, if they are in it
+    pub(crate) fn epoch(&self, member: &str, group_id: &[u8]) -> Option<u64> {
+        self.members
+            .get(member)?
+            .connection
+            .get_membership(group_id)
+            .map(|membership| membership.get_epoch())
+    }
+
+    /// Connection of `member`, for inspecting their local state
+    pub(crate) fn connection(&self, member: &str) -> Option<&MlsConnection> {
+        self.members.get(member).map(|member| &member.connection)
+    }
+
+    /// Sorted roster as seen by `member`, if they are in the group
+    pub(crate) fn roster(&self, member: &str, group_id: &[u8]) -> Option<Vec<String>> {
+        let mut roster = self
+            .members
+            .get(member)?
+            .connection
+            .get_membership(group_id)?
+            .list_members();
+        roster.sort();
+        Some(roster)
+    }
+
+    /// Assert that every member in `expected` is in the group at the same
+    /// epoch and sees exactly `expected` as the roster
+    pub(crate) fn assert_converged(&self, group_id: &[u8], expected: &[&str]) {
+        let mut expected: Vec<String> = expected.iter().map(|name| name.to_string()).collect();
+        expected.sort();
+
+        let epochs: BTreeMap<&str, Option<u64>> = expected
+            .iter()
+            .map(|member| (member.as_str(), self.epoch(member, group_id)))
+            .collect();
+        let first_epoch = epochs.values().next().copied().flatten();
+        assert!(
+            first_epoch.is_some() && epochs.values().all(|epoch| *epoch == first_epoch),
+            "members are at different epochs: {:?}",
+            epochs
+        );
+
+        for member in &expected {
+            assert_eq!(
+                self.roster(member, group_id).as_ref(),
+                Some(&expected),
+                "{} sees a different roster",
+                member
+            );
+        }
+    }
+
+    fn member_mut(&mut self, username: &str) -> Result<&mut HarnessMember> {
+        self.members
+            .get_mut(username)
+            .ok_or_else(|| ClientError::Config(format!("No harness member {}", username)))
+    }
+
+    /// Drain every outbox, routing each message to its recipients
+    ///
+    /// Recipients are chosen when the message is drained, like a server
+    /// broadcasting to the subscribers it has at that moment.
+    fn route_outgoing(&mut self) -> Result<()> {
+        let senders: Vec<String> = self.members.keys().cloned().collect();
+        for sender in senders {
+            while let Ok(Some(message)) = self.member_mut(&sender)?.outbox.try_next() {
+                let Message::Text(text) = message else {
+                    continue;
+                };
+                let value: serde_json::Value = serde_json::from_str(&text)?;
+                match value.get("action").and_then(|a| a.as_str()) {
+                    Some("subscribe") => {
+                        if let Some(group_id) = value.get("group_id").and_then(|g| g.as_str()) {
+                            self.member_mut(&sender)?
+                                .subscriptions
+                                .insert(group_id.to_string());
+                        }
+                        continue;
+                    }
+                    Some("unsubscribe") => {
+                        if let Some(group_id) = value.get("group_id").and_then(|g| g.as_str()) {
+                            self.member_mut(&sender)?.subscriptions.remove(group_id);
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+                let envelope: MlsMessageEnvelope = serde_json::from_value(value)?;
+                self.route(&sender, envelope)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn route(&mut self, sender: &str, envelope: MlsMessageEnvelope) -> Result<()> {
+        let group_id = match &envelope {
+            MlsMessageEnvelope::WelcomeMessage { invitee, .. } => {
+                self.in_flight.push_back((invitee.clone(), envelope));
+                return Ok(());
+            }
+            MlsMessageEnvelope::ApplicationMessage { group_id, .. } => {
+                self.latest_messages
+                    .insert(group_id.clone(), envelope.clone());
+                group_id.clone()
+            }
+            MlsMessageEnvelope::CommitMessage {
+                group_id,
+                commit_blob,
+                ..
+            } => {
+                self.commit_log.push(LoggedCommit {
+                    group_id: group_id.clone(),
+                    sender: sender.to_string(),
+                    epoch: commit_epoch(commit_blob)?,
+                    envelope: envelope.clone(),
+                });
+                group_id.clone()
+            }
+        };
+        let is_commit = matches!(envelope, MlsMessageEnvelope::CommitMessage { .. });
+
+        let recipients: Vec<String> = self
+            .members
+            .iter()
+            .filter(|(name, member)| {
+                name.as_str() != sender && member.subscriptions.contains(&group_id)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for recipient in recipients {
+            let fault = if is_commit {
+                self.faults
+                    .get_mut(&recipient)
+                    .and_then(|faults| faults.pop_front())
+            } else {
+                None
+            };
+            match fault {
+                Some(CommitFault::Drop) => {}
+                Some(CommitFault::Delay) => self
+                    .held
+                    .entry(recipient)
+                    .or_default()
+                    .push(envelope.clone()),
+                None => self.in_flight.push_back((recipient, envelope.clone())),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Epoch in which a base64-encoded Commit was created
+fn commit_epoch(commit_blob: &str) -> Result<u64> {
+    let invalid = |e: String| ClientError::Config(format!("Invalid Commit in transport: {}", e));
+    let bytes = general_purpose::STANDARD
+        .decode(commit_blob)
+        .map_err(|e| invalid(e.to_string()))?;
+    let message =
+        MlsMessageIn::tls_deserialize(&mut bytes.as_slice()).map_err(|e| invalid(e.to_string()))?;
+    let protocol = message
+        .try_into_protocol_message()
+        .map_err(|e| invalid(e.to_string()))?;
+    Ok(protocol.epoch().as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::GroupEvent;
+    use crate::message_processing::render_quote;
+    use crate::models::{
+        CancelOutcome, PendingInvite, PendingOp, PendingOpId, Quote, WelcomePolicy,
+    };
+
+    /// Start a harness with alice, bob, carol and dave registered
+    async fn four_members() -> ConvergenceHarness {
+        let mut harness = ConvergenceHarness::start().await;
+        for name in ["alice", "bob", "carol", "dave"] {
+            harness.add_member(name).await.unwrap();
+        }
+        harness
+    }
+
+    #[tokio::test]
+    async fn test_four_member_group_converges() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+
+        for invitee in ["bob", "carol", "dave"] {
+            harness.invite("alice", &group_id, invitee).await.unwrap();
+        }
+
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+    }
+
+    /// Bob misses the Commit adding dave: he is stuck one epoch behind and
+    /// cannot read messages from the new epoch until sync replays the Commit
+    #[tokio::test]
+    async fn test_dropped_commit_recovers_via_sync() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+
+        harness.inject("bob", CommitFault::Drop);
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+        harness
+            .send_message("dave", &group_id, "hi all")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            harness.roster("bob", &group_id).unwrap(),
+            vec!["alice", "bob", "carol"]
+        );
+        assert_eq!(
+            harness.epoch("bob", &group_id).unwrap() + 1,
+            harness.epoch("alice", &group_id).unwrap()
+        );
+
+        harness.sync("bob", &group_id).await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+    }
+
+    /// Alice and bob invite at the same epoch: bob's Commit is superseded by
+    /// alice's, so his client applies hers and re-adds dave in the next epoch
+    #[tokio::test]
+    async fn test_concurrent_invites_both_join() {
+        let mut harness = ConvergenceHarness::start().await;
+        for name in ["alice", "bob", "carol", "dave"] {
+            harness.add_member(name).await.unwrap();
+        }
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        let epoch = harness.epoch("alice", &group_id).unwrap();
+
+        harness
+            .invite_concurrently(&group_id, &[("alice", "carol"), ("bob", "dave")])
+            .await
+            .unwrap();
+        assert_eq!(harness.epoch("alice", &group_id), Some(epoch + 2));
+        assert_eq!(harness.epoch("bob", &group_id), Some(epoch + 2));
+
+        // Carol joined from alice's Welcome after bob's Commit was routed
+        harness.sync("carol", &group_id).await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+    }
+
+    /// Alice tracks bob's invite until bob's first message shows he joined
+    #[tokio::test]
+    async fn test_pending_invite_cleared_once_invitee_is_seen() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        let epoch = harness.epoch("alice", &group_id).unwrap();
+
+        // Processing the Welcome is invisible to the inviter
+        let alice = harness.connection("alice").unwrap();
+        assert_eq!(
+            alice.pending_invites(&group_id).unwrap(),
+            vec![PendingInvite {
+                invitee: "bob".to_string(),
+                epoch,
+            }]
+        );
+
+        harness
+            .send_message("bob", &group_id, "thanks for the invite")
+            .await
+            .unwrap();
+        let alice = harness.connection("alice").unwrap();
+        assert!(alice.pending_invites(&group_id).unwrap().is_empty());
+    }
+
+    /// Queued messages can be withdrawn until flushed; an invite can only
+    /// stop being tracked, and unknown ids change nothing
+    #[tokio::test]
+    async fn test_pending_operations_list_and_cancel() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        let alice = harness.connection_mut("alice").unwrap();
+        for text in ["first", "second", "third"] {
+            alice.queue_offline_message(&group_id, text).unwrap();
+        }
+        let operations = alice.pending_operations().unwrap();
+        let queued: Vec<PendingOpId> = operations
+            .iter()
+            .filter(|op| matches!(op.id, PendingOpId::QueuedMessage(_)))
+            .map(|op| op.id.clone())
+            .collect();
+        let invite = PendingOpId::Invite {
+            group_id: group_id.clone(),
+            invitee: "bob".to_string(),
+        };
+        assert_eq!(queued.len(), 3);
+        assert_eq!(
+            operations.last(),
+            Some(&PendingOp {
+                id: invite.clone(),
+                group_id: group_id.clone(),
+                description: "bob".to_string(),
+            })
+        );
+
+        // Withdraw the second message; cancelling it again is a no-op
+        assert_eq!(
+            alice.cancel_operation(&queued[1]).unwrap(),
+            CancelOutcome::Cancelled
+        );
+        assert_eq!(
+            alice.cancel_operation(&queued[1]).unwrap(),
+            CancelOutcome::NotFound
+        );
+        assert_eq!(
+            alice
+                .cancel_operation(&PendingOpId::QueuedMessage(9999))
+                .unwrap(),
+            CancelOutcome::NotFound
+        );
+
+        // The Welcome already went out, so the invite is only forgotten
+        assert_eq!(
+            alice.cancel_operation(&invite).unwrap(),
+            CancelOutcome::AlreadySent
+        );
+        assert!(alice.pending_invites(&group_id).unwrap().is_empty());
+        assert_eq!(
+            alice.cancel_operation(&invite).unwrap(),
+            CancelOutcome::NotFound
+        );
+
+        // Flushing sends what is left; cancelling those is too late
+        assert_eq!(alice.flush_offline_queue().await.unwrap().0, 2);
+        assert_eq!(
+            alice.cancel_operation(&queued[0]).unwrap(),
+            CancelOutcome::AlreadySent
+        );
+        assert!(alice.pending_operations().unwrap().is_empty());
+        harness.pump().await.unwrap();
+
+        let bob_history = harness
+            .connection("bob")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        let texts: Vec<&str> = bob_history
+            .iter()
+            .map(|entry| entry.content.as_str())
+            .collect();
+        assert!(texts.contains(&"first") && texts.contains(&"third"));
+        assert!(!texts.contains(&"second"));
+    }
+
+    /// A redeemed token lets the admin add a user they never named; expired,
+    /// reused and revoked tokens are refused
+    #[tokio::test]
+    async fn test_join_tokens_add_redeemer_once() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+
+        let token = harness
+            .connection("alice")
+            .unwrap()
+            .create_join_token_in_group(&group_id, Some(600))
+            .await
+            .unwrap();
+        let redeemed = harness
+            .connection_mut("bob")
+            .unwrap()
+            .redeem_join_token(&token.token)
+            .await
+            .unwrap();
+        assert_eq!(redeemed, group_id);
+
+        let admitted = harness
+            .connection_mut("alice")
+            .unwrap()
+            .admit_join_requests_in_group(&group_id)
+            .await
+            .unwrap();
+        assert_eq!(admitted, vec!["bob".to_string()]);
+        harness.pump().await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob"]);
+
+        // Nothing left to admit, and the token is used up
+        let alice = harness.connection_mut("alice").unwrap();
+        assert!(alice
+            .admit_join_requests_in_group(&group_id)
+            .await
+            .unwrap()
+            .is_empty());
+        let rejected = |result: Result<Vec<u8>>, expected: &str| match result {
+            Err(ClientError::Mls(crate::error::MlsError::JoinTokenRejected { reason })) => {
+                assert_eq!(reason, expected)
+            }
+            other => panic!("expected the token to be rejected, got {:?}", other),
+        };
+        let carol = harness.connection_mut("carol").unwrap();
+        rejected(
+            carol.redeem_join_token(&token.token).await,
+            "already redeemed",
+        );
+
+        // Expired
+        let alice = harness.connection("alice").unwrap();
+        let expiring = alice
+            .create_join_token_in_group(&group_id, Some(1))
+            .await
+            .unwrap();
+        let revoked = alice
+            .create_join_token_in_group(&group_id, None)
+            .await
+            .unwrap();
+        assert!(alice.revoke_join_token(&revoked.token).await.unwrap());
+        tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+        let carol = harness.connection_mut("carol").unwrap();
+        rejected(carol.redeem_join_token(&expiring.token).await, "expired");
+        rejected(carol.redeem_join_token(&revoked.token).await, "revoked");
+        rejected(carol.redeem_join_token("no-such-token").await, "not found");
+
+        // Only admins create tokens
+        let bob = harness.connection("bob").unwrap();
+        assert!(matches!(
+            bob.create_join_token_in_group(&group_id, None).await,
+            Err(ClientError::Mls(crate::error::MlsError::NotAdmin { .. }))
+        ));
+    }
+
+    /// Bob's join time comes from the Commit adding him, and his first
+    /// message marks him as seen
+    #[tokio::test]
+    async fn test_member_activity_records_join_and_last_seen() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        let before_invite = chrono::Utc::now().timestamp();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        let bob_activity = |harness: &ConvergenceHarness, viewer: &str| {
+            harness
+                .connection(viewer)
+                .unwrap()
+                .member_activity(&group_id)
+                .unwrap()
+                .into_iter()
+                .find(|member| member.username == "bob")
+                .unwrap()
+        };
+        for viewer in ["alice", "bob"] {
+            let bob = bob_activity(&harness, viewer);
+            assert!(bob.joined_at.is_some_and(|at| at >= before_invite));
+            assert_eq!(bob.last_seen, None);
+        }
+
+        let before_message = chrono::Utc::now().timestamp();
+        harness
+            .send_message("bob", &group_id, "hello")
+            .await
+            .unwrap();
+        let bob = bob_activity(&harness, "alice");
+        assert!(bob.last_seen.is_some_and(|at| at >= before_message));
+    }
+
+    /// A delayed Commit is overtaken by the next one; the later Commit is
+    /// held until the delayed one arrives, then both apply in epoch order
+    #[tokio::test]
+    async fn test_reordered_commits_apply_in_epoch_order() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        let epoch = harness.epoch("bob", &group_id).unwrap();
+
+        harness.inject("bob", CommitFault::Delay);
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+
+        // The Commit adding dave (epoch N+1) waits for the one adding carol
+        assert_eq!(harness.epoch("bob", &group_id), Some(epoch));
+        assert_eq!(
+            harness.roster("bob", &group_id).unwrap(),
+            vec!["alice", "bob"]
+        );
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::ZERO),
+            Some(epoch)
+        );
+
+        // The held Commit (adding carol) now arrives after the one adding dave
+        harness.release_held("bob").await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::ZERO),
+            None
+        );
+    }
+
+    /// A Commit that never arrives is detected from the later one held
+    /// behind it and fetched from the server
+    #[tokio::test]
+    async fn test_missing_commit_is_synced_from_server() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        harness.inject("bob", CommitFault::Drop);
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+
+        // Not stalled long enough yet
+        let synced = harness
+            .connection_mut("bob")
+            .unwrap()
+            .resolve_commit_gaps(std::time::Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(synced, 0);
+
+        let synced = harness
+            .connection_mut("bob")
+            .unwrap()
+            .resolve_commit_gaps(std::time::Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(synced, 1);
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+    }
+
+    /// A message from an epoch the member has not reached is recognized and
+    /// held, and the sync it triggers fetches the missed Commit so the
+    /// message can be read, instead of failing to decrypt
+    #[tokio::test]
+    async fn test_future_epoch_message_is_routed_to_sync() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        let epoch = harness.epoch("bob", &group_id).unwrap();
+
+        harness.inject("bob", CommitFault::Drop);
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness
+            .send_message("alice", &group_id, "welcome carol")
+            .await
+            .unwrap();
+
+        let bob_history = |harness: &ConvergenceHarness| {
+            harness
+                .connection("bob")
+                .unwrap()
+                .get_provider()
+                .load_history(&group_id)
+                .unwrap()
+        };
+        assert_eq!(harness.epoch("bob", &group_id), Some(epoch));
+        assert!(bob_history(&harness)
+            .iter()
+            .all(|entry| entry.content != "welcome carol"));
+        // No timeout to wait out: the message shows Commits are missing
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::from_secs(3600)),
+            Some(epoch)
+        );
+
+        let synced = harness
+            .connection_mut("bob")
+            .unwrap()
+            .resolve_commit_gaps(std::time::Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(synced, 1);
+        harness.assert_converged(&group_id, &["alice", "bob", "carol"]);
+        assert!(bob_history(&harness)
+            .iter()
+            .any(|entry| entry.content == "welcome carol"));
+    }
+
+    /// The envelope's epoch is only a hint: a forged one neither holds back
+    /// a current message nor makes it readable
+    #[tokio::test]
+    async fn test_forged_epoch_hint_is_ignored() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        harness
+            .member_mut("alice")
+            .unwrap()
+            .connection
+            .send_message_to_group(&group_id, "hint says 999")
+            .await
+            .unwrap();
+        harness.route_outgoing().unwrap();
+        let sent_epoch = harness.epoch("alice", &group_id);
+        for (_, envelope) in harness.in_flight.iter_mut() {
+            if let MlsMessageEnvelope::ApplicationMessage { epoch, .. } = envelope {
+                assert_eq!(*epoch, sent_epoch);
+                *epoch = Some(999);
+            }
+        }
+        harness.pump().await.unwrap();
+
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::from_secs(3600)),
+            None
+        );
+        let history = harness
+            .connection("bob")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        assert!(history.iter().any(|entry| entry.content == "hint says 999"));
+    }
+
+    /// The removed member's client notices the removal and drops the group;
+    /// what is sent afterwards is out of its reach
+    #[tokio::test]
+    async fn test_removed_member_drops_group_and_cannot_decrypt() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        let (_, mut carol_events) = harness.connection("carol").unwrap().subscribe_events();
+
+        // Only admins may remove
+        assert!(harness
+            .remove_member("bob", &group_id, "carol")
+            .await
+            .is_err());
+
+        let epoch = harness
+            .remove_member("alice", &group_id, "carol")
+            .await
+            .unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob"]);
+        assert_eq!(harness.epoch("alice", &group_id), Some(epoch));
+
+        // Carol's client saw its own removal and cleaned up
+        let carol = harness.connection("carol").unwrap();
+        assert!(carol.get_membership(&group_id).is_none());
+        assert!(!carol.get_provider().group_exists("carol:general").unwrap());
+        assert!(crate::crypto::load_group_from_storage(
+            carol.get_provider(),
+            &openmls::prelude::GroupId::from_slice(&group_id)
+        )
+        .unwrap()
+        .is_none());
+        let mut removed_event = None;
+        while let Ok(event) = carol_events.try_recv() {
+            if let GroupEvent::RemovedFromGroup { .. } = event {
+                removed_event = Some(event);
+            }
+        }
+        assert_eq!(
+            removed_event,
+            Some(GroupEvent::RemovedFromGroup {
+                group_id: group_id.clone(),
+                committer: "alice".to_string(),
+            })
+        );
+
+        // The next message reaches bob but not carol, even if delivered to her
+        harness
+            .send_message("alice", &group_id, "after carol left")
+            .await
+            .unwrap();
+        let bob_history = harness
+            .connection("bob")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        assert!(bob_history
+            .iter()
+            .any(|entry| entry.content == "after carol left"));
+
+        assert!(harness
+            .redeliver_latest_message("carol", &group_id)
+            .await
+            .is_err());
+        let carol_history = harness
+            .connection("carol")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        assert!(carol_history
+            .iter()
+            .any(|entry| entry.content == "alice removed carol"));
+        assert!(carol_history
+            .iter()
+            .all(|entry| entry.content != "after carol left"));
+    }
+
+    /// With self-update after remove, a removal costs two Commits; members
+    /// apply them in epoch order even when the second arrives first
+    #[tokio::test]
+    async fn test_self_update_after_remove_advances_epoch_twice() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+        harness
+            .connection_mut("alice")
+            .unwrap()
+            .set_self_update_after_remove(true);
+        let before = harness.epoch("alice", &group_id).unwrap();
+
+        // The Remove Commit reaches bob only after the self-update
+        harness.inject("bob", CommitFault::Delay);
+        let epoch = harness
+            .remove_member("alice", &group_id, "carol")
+            .await
+            .unwrap();
+        assert_eq!(epoch, before + 2);
+        assert_eq!(harness.epoch("bob", &group_id), Some(before));
+        assert_eq!(harness.epoch("dave", &group_id), Some(epoch));
+
+        harness.release_held("bob").await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "dave"]);
+        assert_eq!(harness.epoch("bob", &group_id), Some(epoch));
+
+        // Carol ignored the self-update sent after her removal
+        assert!(harness
+            .connection("carol")
+            .unwrap()
+            .get_membership(&group_id)
+            .is_none());
+
+        harness
+            .send_message("bob", &group_id, "after the rotation")
+            .await
+            .unwrap();
+        let dave_history = harness
+            .connection("dave")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        assert!(dave_history
+            .iter()
+            .any(|entry| entry.content == "after the rotation"));
+    }
+
+    /// After a credential rotation the member's leaf follows the new key,
+    /// and invites use KeyPackages of the new credential only
+    #[tokio::test]
+    async fn test_rotated_credential_is_used_in_groups_and_new_invites() {
+        use openmls_traits::OpenMlsProvider as _;
+
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+
+        let carol = harness.connection_mut("carol").unwrap();
+        let old_key = carol.public_bundle().unwrap().signature_key;
+        assert!(carol.rotate_identity().await.unwrap().is_empty());
+        let new_key = carol.public_bundle().unwrap().signature_key;
+        assert_ne!(old_key, new_key);
+        harness.pump().await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "carol"]);
+        let leaf_key = |harness: &ConvergenceHarness, member: &str| {
+            harness
+                .connection("alice")
+                .unwrap()
+                .get_membership(&group_id)
+                .unwrap()
+                .member_signature_key(member)
+        };
+        assert_eq!(leaf_key(&harness, "carol"), Some(new_key));
+
+        harness
+            .send_message("carol", &group_id, "signed with the new key")
+            .await
+            .unwrap();
+        let bob_history = harness
+            .connection("bob")
+            .unwrap()
+            .get_provider()
+            .load_history(&group_id)
+            .unwrap();
+        assert!(bob_history
+            .iter()
+            .any(|entry| entry.content == "signed with the new key"));
+
+        // A KeyPackage reserved before dave rotates no longer matches him
+        let alice = harness.connection("alice").unwrap();
+        let stale = alice
+            .get_api()
+            .reserve_key_package(
+                "dave",
+                &group_id,
+                "alice",
+                alice.get_user().unwrap().get_signature_key(),
+            )
+            .await
+            .unwrap();
+        let dave = harness.connection_mut("dave").unwrap();
+        dave.rotate_identity().await.unwrap();
+        let dave_key = dave.public_bundle().unwrap().signature_key;
+        let directory = dave.lookup_identity("dave").await.unwrap().unwrap();
+        assert_eq!(directory.signature_key, dave_key);
+        let stale_key_package =
+            openmls::prelude::KeyPackageIn::tls_deserialize_exact(&stale.keypackage)
+                .unwrap()
+                .validate(
+                    dave.get_provider().crypto(),
+                    openmls::prelude::ProtocolVersion::Mls10,
+                )
+                .unwrap();
+        assert!(!directory.matches_key_package(&stale_key_package));
+
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+        harness.assert_converged(&group_id, &["alice", "bob", "carol", "dave"]);
+        assert_eq!(leaf_key(&harness, "dave"), Some(dave_key));
+    }
+
+    /// A reply carries a snapshot of the message it quotes, which every
+    /// member still shows after the original has expired
+    #[tokio::test]
+    async fn test_quoted_reply_survives_the_original() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+
+        harness
+            .set_message_ttl("alice", &group_id, Some(60))
+            .await
+            .unwrap();
+        harness
+            .send_message("alice", &group_id, "lunch at noon?")
+            .await
+            .unwrap();
+        harness
+            .set_message_ttl("alice", &group_id, None)
+            .await
+            .unwrap();
+
+        let history = |harness: &ConvergenceHarness, member: &str| {
+            harness
+                .connection(member)
+                .unwrap()
+                .get_provider()
+                .load_history(&group_id)
+                .unwrap()
+        };
+        let original = history(&harness, "bob")
+            .into_iter()
+            .find(|entry| entry.content == "lunch at noon?")
+            .unwrap();
+        let original_id = original.message_id.unwrap();
+
+        // Only known messages can be quoted
+        assert!(harness
+            .send_reply("bob", &group_id, "no-such-message", "yes")
+            .await
+            .is_err());
+        harness
+            .send_reply("bob", &group_id, &original_id, "count me in")
+            .await
+            .unwrap();
+
+        let expected = Some(Quote::new("alice", "lunch at noon?"));
+        for member in ["alice", "bob", "carol"] {
+            let reply = history(&harness, member).pop().unwrap();
+            assert_eq!(reply.sender, "bob");
+            assert_eq!(reply.content, "count me in");
+            assert_eq!(reply.quote, expected, "quote seen by {}", member);
+        }
+
+        // The original expires; the reply, sent without a TTL, keeps its quote
+        let carol = harness.connection("carol").unwrap().get_provider();
+        carol
+            .prune_expired_history(chrono::Utc::now().timestamp() + 120)
+            .unwrap();
+        let carol_history = history(&harness, "carol");
+        assert!(carol_history
+            .iter()
+            .all(|entry| entry.content != "lunch at noon?"));
+        let reply = carol_history.last().unwrap();
+        assert_eq!(
+            render_quote(&reply.content, reply.quote.as_ref()),
+            "count me in\n  > alice: lunch at noon?"
+        );
+    }
+
+    /// The server never hears about removals, so its member list keeps carol
+    /// after she is removed; the comparison reports it rather than trusting it
+    #[tokio::test]
+    async fn test_server_member_view_is_compared_with_roster() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+
+        let comparison = harness
+            .connection("bob")
+            .unwrap()
+            .compare_rosters(&group_id)
+            .await
+            .unwrap();
+        assert!(comparison.is_consistent(), "{:?}", comparison);
+        assert_eq!(comparison.verified, vec!["alice", "bob", "carol"]);
+        assert_eq!(comparison.server_view, comparison.verified);
+
+        harness
+            .remove_member("alice", &group_id, "carol")
+            .await
+            .unwrap();
+        let comparison = harness
+            .connection("alice")
+            .unwrap()
+            .compare_rosters(&group_id)
+            .await
+            .unwrap();
+        assert!(!comparison.is_consistent());
+        assert_eq!(comparison.verified, vec!["alice", "bob"]);
+        assert_eq!(comparison.server_view, vec!["alice", "bob", "carol"]);
+        assert_eq!(comparison.only_on_server, vec!["carol"]);
+        assert!(comparison.only_in_roster.is_empty());
+    }
+
+    /// Carol joins on receipt (the default); bob's Welcome is staged, and
+    /// nothing of the group exists for him until he accepts it
+    #[tokio::test]
+    async fn test_staged_welcome_joins_only_on_accept() {
+        let mut harness = four_members().await;
+        harness
+            .connection_mut("bob")
+            .unwrap()
+            .set_welcome_policy(WelcomePolicy::Stage);
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        assert!(harness.epoch("carol", &group_id).is_some());
+        let bob = harness.connection("bob").unwrap();
+        assert!(bob.get_membership(&group_id).is_none());
+        assert!(!bob.get_provider().group_exists("bob:general").unwrap());
+        let staged = bob.staged_welcomes().unwrap();
+        assert_eq!(staged.len(), 1);
+        assert_eq!(staged[0].inviter, "alice");
+        // Neither invitee has sent anything to the group yet
+        let alice = harness.connection("alice").unwrap();
+        let pending: Vec<String> = alice
+            .pending_invites(&group_id)
+            .unwrap()
+            .into_iter()
+            .map(|invite| invite.invitee)
+            .collect();
+        assert_eq!(pending.len(), 2);
+        assert!(pending.contains(&"bob".to_string()));
+
+        let joined = harness
+            .connection_mut("bob")
+            .unwrap()
+            .accept_welcome(staged[0].id)
+            .await
+            .unwrap();
+        assert_eq!(joined, group_id);
+        harness.pump().await.unwrap();
+        let bob = harness.connection("bob").unwrap();
+        assert!(bob.staged_welcomes().unwrap().is_empty());
+        harness.assert_converged(&group_id, &["alice", "bob", "carol"]);
+
+        harness
+            .send_message("bob", &group_id, "glad to be here")
+            .await
+            .unwrap();
+        let alice = harness.connection("alice").unwrap();
+        let pending = alice.pending_invites(&group_id).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].invitee, "carol");
+    }
+
+    /// A declined Welcome leaves no group behind and the invite pending
+    #[tokio::test]
+    async fn test_declined_welcome_is_discarded() {
+        let mut harness = four_members().await;
+        harness
+            .connection_mut("bob")
+            .unwrap()
+            .set_welcome_policy(WelcomePolicy::Stage);
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+
+        let bob = harness.connection_mut("bob").unwrap();
+        let id = bob.staged_welcomes().unwrap()[0].id;
+        assert!(bob.decline_welcome(id).await.unwrap());
+        assert!(!bob.decline_welcome(id).await.unwrap());
+        assert!(bob.accept_welcome(id).await.is_err());
+        assert!(bob.staged_welcomes().unwrap().is_empty());
+        assert!(bob.get_membership(&group_id).is_none());
+        assert!(!bob.get_provider().group_exists("bob:general").unwrap());
+
+        let alice = harness.connection("alice").unwrap();
+        assert_eq!(alice.pending_invites(&group_id).unwrap().len(), 1);
+    }
+
+    /// Bob's invitations to two groups are listed with their groups, and
+    /// accepting or declining one acts on that group and drops it from the list
+    #[tokio::test]
+    async fn test_pending_invitations_span_groups() {
+        let mut harness = four_members().await;
+        harness
+            .connection_mut("bob")
+            .unwrap()
+            .set_welcome_policy(WelcomePolicy::Stage);
+        let general = harness.create_group("alice", "general").await.unwrap();
+        let random = harness.create_group("carol", "random").await.unwrap();
+        harness.invite("alice", &general, "bob").await.unwrap();
+        harness.invite("carol", &random, "bob").await.unwrap();
+
+        let bob = harness.connection("bob").unwrap();
+        let invitations = bob.pending_invitations_for_me().unwrap();
+        let listed: Vec<_> = invitations
+            .iter()
+            .map(|i| {
+                (
+                    i.inviter.as_str(),
+                    i.group_name.as_deref(),
+                    i.group_id.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("alice", Some("general"), Some(general.clone())),
+                ("carol", Some("random"), Some(random.clone())),
+            ]
+        );
+        assert!(invitations[1].members.contains(&"carol".to_string()));
+        // Listing joins nothing
+        assert!(bob.get_membership(&general).is_none());
+        assert!(bob.get_membership(&random).is_none());
+
+        let joined = harness
+            .connection_mut("bob")
+            .unwrap()
+            .accept_welcome(invitations[1].id)
+            .await
+            .unwrap();
+        assert_eq!(joined, random);
+        harness.pump().await.unwrap();
+        harness.assert_converged(&random, &["bob", "carol"]);
+
+        let bob = harness.connection_mut("bob").unwrap();
+        let remaining = bob.pending_invitations_for_me().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].group_id.as_deref(), Some(general.as_slice()));
+        assert!(bob.decline_welcome(remaining[0].id).await.unwrap());
+        assert!(bob.pending_invitations_for_me().unwrap().is_empty());
+        assert!(bob.get_membership(&general).is_none());
+    }
+
+    /// Every chat message in bob's archive names its signer and the
+    /// fingerprint of the signer's credential; notices are marked unverified
+    #[tokio::test]
+    async fn test_archive_entries_carry_authenticated_senders() {
+        use crate::archive::SenderAuthentication;
+
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        harness
+            .send_message("alice", &group_id, "welcome bob")
+            .await
+            .unwrap();
+        harness.send_message("bob", &group_id, "hi!").await.unwrap();
+        harness
+            .connection("bob")
+            .unwrap()
+            .get_provider()
+            .append_history(
+                &group_id,
+                "bob",
+                "bob set a topic",
+                crate::models::ContentType::System,
+                None,
+            )
+            .unwrap();
+
+        let fingerprint = |member: &str| {
+            let connection = harness.connection(member).unwrap();
+            crate::crypto::credential_fingerprint(
+                connection.get_provider(),
+                connection.get_user().unwrap().get_credential_with_key(),
+            )
+            .unwrap()
+        };
+        let bob = harness.connection("bob").unwrap();
+        let archive = bob.export_archive(&group_id).unwrap();
+        assert_eq!(archive.exported_by, "bob");
+        assert_eq!(archive.verify_chain(bob.get_provider()).unwrap(), None);
+
+        let messages: Vec<_> = archive
+            .entries
+            .iter()
+            .filter(|entry| entry.message_id.is_some())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        for entry in messages {
+            assert_eq!(entry.authentication, SenderAuthentication::Verified);
+            assert_eq!(
+                entry.authenticated_sender.as_deref(),
+                Some(entry.sender.as_str())
+            );
+            assert_eq!(entry.sender_fingerprint, Some(fingerprint(&entry.sender)));
+            assert!(entry.epoch.is_some());
+        }
+        let notice = archive.entries.last().unwrap();
+        assert_eq!(notice.content, "bob set a topic");
+        assert_eq!(notice.authentication, SenderAuthentication::Unverified);
+        assert_eq!(notice.authenticated_sender, None);
+    }
+
+    /// Overflowing the buffer of Commits from later epochs drops the oldest
+    /// and syncs at once, recovering the dropped Commit from the server
+    #[tokio::test]
+    async fn test_held_envelope_overflow_triggers_full_sync() {
+        let mut harness = four_members().await;
+        let group_id = harness.create_group("alice", "general").await.unwrap();
+        harness.invite("alice", &group_id, "bob").await.unwrap();
+        let epoch = harness.epoch("bob", &group_id).unwrap();
+        harness
+            .connection_mut("bob")
+            .unwrap()
+            .set_max_held_envelopes(1);
+
+        // The Commit adding carol never arrives, so the next two are held
+        harness.inject("bob", CommitFault::Drop);
+        harness.invite("alice", &group_id, "carol").await.unwrap();
+        harness.invite("alice", &group_id, "dave").await.unwrap();
+        harness
+            .remove_member("alice", &group_id, "dave")
+            .await
+            .unwrap();
+
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(membership.held_envelopes(), 1);
+        // No timeout to wait out: the overflow asks for a sync right away
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::from_secs(3600)),
+            Some(epoch)
+        );
+
+        let synced = harness
+            .connection_mut("bob")
+            .unwrap()
+            .resolve_commit_gaps(std::time::Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(synced, 1);
+        harness.assert_converged(&group_id, &["alice", "bob", "carol"]);
+        let membership = harness
+            .connection("bob")
+            .unwrap()
+            .get_membership(&group_id)
+            .unwrap();
+        assert_eq!(membership.held_envelopes(), 0);
+        assert_eq!(
+            membership.stalled_commit_epoch(std::time::Duration::ZERO),
+            None
+        );
+    }
+}
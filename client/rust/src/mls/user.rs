@@ -72,6 +72,12 @@ pub struct MlsUser {
     /// This is reused across all groups the user joins. The credential
     /// binds the username to the public key material from signature_key.
     credential_with_key: openmls::prelude::CredentialWithKey,
+
+    /// Id of this device among the user's devices, if known
+    ///
+    /// Sent along with our Commits so that the user's other devices can
+    /// tell them from their own.
+    device_id: Option<String>,
 }
 
 impl MlsUser {
@@ -116,9 +122,16 @@ impl MlsUser {
             identity,
             signature_key,
             credential_with_key,
+            device_id: None,
         }
     }
 
+    /// Set the id of the device this user is on
+    pub fn with_device_id(mut self, device_id: String) -> Self {
+        self.device_id = Some(device_id);
+        self
+    }
+
     /// Get the username
     ///
     /// Returns a reference to avoid copying. The username is owned by MlsUser
@@ -160,6 +173,11 @@ impl MlsUser {
     pub fn get_credential_with_key(&self) -> &openmls::prelude::CredentialWithKey {
         &self.credential_with_key
     }
+
+    /// Get the id of this device, if one was set
+    pub fn get_device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
 }
 
 #[cfg(test)]
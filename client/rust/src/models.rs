@@ -1,6 +1,8 @@
 //! Data models and DTOs for the MLS client
 
+use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// User identity information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +20,598 @@ pub struct GroupState {
     pub members: Vec<String>,
 }
 
+/// Kind of payload carried by an application message
+///
+/// Travels in the clear next to the ciphertext so the server and clients can
+/// filter messages without decrypting them. Envelopes from older clients carry
+/// no content type and are treated as `Text`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Chat message typed by a user
+    #[default]
+    Text,
+    /// Protocol/control payload not meant for display
+    Control,
+    /// Notice generated by a client (joins, leaves, ...)
+    System,
+}
+
+impl ContentType {
+    /// Value stored in the local `group_history.content_type` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Control => "control",
+            ContentType::System => "system",
+        }
+    }
+
+    /// Parse a stored value, `None` if unknown
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(ContentType::Text),
+            "control" => Some(ContentType::Control),
+            "system" => Some(ContentType::System),
+            _ => None,
+        }
+    }
+}
+
+/// Payload of a `ContentType::Control` application message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    /// The sender has read the group's messages up to `read_at` (Unix seconds)
+    ReadReceipt { read_at: i64 },
+    /// Pinned announcement; honored only from an admin of the group. Sent
+    /// with `ContentType::System`, unlike other control messages.
+    Announcement { text: String },
+    /// Pin (or, with `unpin`, unpin) the message with id `target_message_id`;
+    /// honored only from an admin of the group
+    Pin {
+        target_message_id: String,
+        #[serde(default)]
+        unpin: bool,
+    },
+}
+
+/// Longest quote snapshot kept, in characters (see `Quote::new`)
+pub const MAX_QUOTE_CHARS: usize = 280;
+
+/// Snapshot of a quoted message, carried inside the reply quoting it
+///
+/// The reply is self-contained: the snapshot still shows after the quoted
+/// message expired or was never received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    /// Sender of the quoted message
+    pub author: String,
+    /// Text of the quoted message, at most `MAX_QUOTE_CHARS` characters
+    pub text: String,
+}
+
+impl Quote {
+    /// Snapshot a message, cutting longer text (and author) to
+    /// `MAX_QUOTE_CHARS` characters, the last of which becomes `…`
+    pub fn new(author: &str, text: &str) -> Self {
+        fn bounded(value: &str) -> String {
+            if value.chars().count() <= MAX_QUOTE_CHARS {
+                return value.to_string();
+            }
+            let mut cut: String = value.chars().take(MAX_QUOTE_CHARS - 1).collect();
+            cut.push('…');
+            cut
+        }
+        Self {
+            author: bounded(author),
+            text: bounded(text),
+        }
+    }
+}
+
+/// Entry of a group's local history (see `MlsProvider::append_history`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub sender: String,
+    pub content: String,
+    pub content_type: ContentType,
+    /// Unix timestamp (seconds) when the entry was recorded
+    pub recorded_at: i64,
+    /// Unix timestamp (seconds) after which the entry disappears, if any
+    pub expires_at: Option<i64>,
+    /// Id members know a chat message by (see `crypto::message_id`); `None`
+    /// for notices and entries recorded before ids existed
+    pub message_id: Option<String>,
+    /// Pinned by a group admin
+    pub pinned: bool,
+    /// Snapshot of the message this one replies to
+    pub quote: Option<Quote>,
+}
+
+/// What MLS authenticated about a chat message when it was sent or received
+/// (see `MlsProvider::message_proofs`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageProof {
+    /// Username from the credential that signed the message
+    pub sender_identity: String,
+    /// `crypto::credential_fingerprint` of the signer's credential and key,
+    /// if its leaf could be read
+    pub sender_fingerprint: Option<String>,
+    /// Epoch the message was sent in
+    pub epoch: u64,
+}
+
+/// One page of a group's local history (see `MlsProvider::load_history_page`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryPage {
+    /// Entries of the page, oldest first
+    pub entries: Vec<HistoryEntry>,
+    /// Cursor for the page before this one; `None` at the start of history
+    pub older: Option<i64>,
+}
+
+/// A history entry to insert with `MlsProvider::append_messages`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewHistoryEntry {
+    /// Id members know a chat message by; `None` for notices
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub content: String,
+    pub content_type: ContentType,
+    /// Unix timestamp (seconds) after which the entry disappears, if any
+    pub expires_at: Option<i64>,
+    /// Snapshot of the message this one replies to
+    pub quote: Option<Quote>,
+}
+
+/// Local storage usage of one user (see `MlsProvider::stats`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Number of groups with a stored name mapping
+    pub groups: usize,
+    /// Number of entries in the local group history
+    pub messages: usize,
+    /// On-disk size of the user's database, including SQLite journal files
+    pub total_bytes: u64,
+    /// History entries per group name
+    pub per_group_message_counts: BTreeMap<String, usize>,
+}
+
+/// Consistency of a user's group name mappings and MLS group state
+/// (see `MlsProvider::check_integrity`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Name keys whose group has no stored state at all; `repair` prunes these
+    pub dangling_mappings: Vec<String>,
+    /// Name keys whose group has stored state that does not load (kept by `repair`)
+    pub unloadable_mappings: Vec<String>,
+    /// Group ids with stored state but no name mapping (kept by `repair`)
+    pub unreferenced_groups: Vec<Vec<u8>>,
+    /// Name keys mapped to more than one group that still has state
+    pub name_conflicts: Vec<GroupNameConflict>,
+}
+
+impl IntegrityReport {
+    /// True when every mapping loads and every stored group is mapped
+    pub fn is_clean(&self) -> bool {
+        self.dangling_mappings.is_empty()
+            && self.unloadable_mappings.is_empty()
+            && self.unreferenced_groups.is_empty()
+            && self.name_conflicts.is_empty()
+    }
+}
+
+/// A group's MLS roster next to the server's view of its members (see
+/// `MlsConnection::compare_rosters`)
+///
+/// Only `verified` is authoritative. The server's list is recorded when
+/// users create, join or are added to a group and never sees removals, so
+/// differences are reported rather than merged into the roster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RosterComparison {
+    /// Members in our MLS group state, whose credentials the group verified
+    pub verified: Vec<String>,
+    /// Members the server has recorded for the group (unverified)
+    pub server_view: Vec<String>,
+    /// Listed by the server but not in our roster: removed since, or added
+    /// by Commits we have not processed yet
+    pub only_on_server: Vec<String>,
+    /// In our roster but not listed by the server
+    pub only_in_roster: Vec<String>,
+}
+
+impl RosterComparison {
+    /// True when the server lists exactly the members of our roster
+    pub fn is_consistent(&self) -> bool {
+        self.only_on_server.is_empty() && self.only_in_roster.is_empty()
+    }
+}
+
+/// A group name mapping (see `MlsProvider::list_group_mappings`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMapping {
+    /// `<username>:<group name>`
+    pub group_name_key: String,
+    /// Group the name pointed to
+    pub group_id: Vec<u8>,
+    /// When the mapping was saved (RFC 3339)
+    pub created_at: String,
+    /// False for a mapping that a later group of the same name replaced
+    pub current: bool,
+}
+
+/// Groups that were saved under the same name key and all still have
+/// state; resolved with `MlsProvider::resolve_group_name_conflict`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupNameConflict {
+    /// `<username>:<group name>`
+    pub group_name_key: String,
+    /// Group the name maps to now
+    pub current: Vec<u8>,
+    /// Earlier groups of that name, oldest first
+    pub superseded: Vec<Vec<u8>>,
+}
+
+/// A check run by `MlsClient::self_test`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestCheck {
+    /// The stored identity (signature key and credential) loads
+    Identity,
+    /// The server answers its health endpoint
+    Server,
+    /// A WebSocket connection to the server opens
+    WebSocket,
+    /// The server holds at least one KeyPackage for this user
+    KeyPackages,
+    /// The local databases accept a write and return it (rolled back)
+    LocalStorage,
+}
+
+impl SelfTestCheck {
+    /// Short name shown by the `doctor` command
+    pub fn name(self) -> &'static str {
+        match self {
+            SelfTestCheck::Identity => "identity",
+            SelfTestCheck::Server => "server",
+            SelfTestCheck::WebSocket => "websocket",
+            SelfTestCheck::KeyPackages => "keypackages",
+            SelfTestCheck::LocalStorage => "local storage",
+        }
+    }
+}
+
+/// Outcome of one self-test check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestResult {
+    pub check: SelfTestCheck,
+    pub passed: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+    /// How to fix a failed check
+    pub hint: Option<String>,
+}
+
+impl SelfTestResult {
+    pub(crate) fn passed(check: SelfTestCheck, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            passed: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub(crate) fn failed(
+        check: SelfTestCheck,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            check,
+            passed: false,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Results of `MlsClient::self_test`, one per check in a fixed order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    /// True when every check passed
+    pub fn all_passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    /// The result of `check`, if it was run
+    pub fn result(&self, check: SelfTestCheck) -> Option<&SelfTestResult> {
+        self.results.iter().find(|result| result.check == check)
+    }
+}
+
+/// Welcome sent to an invitee, kept so it can be resent if delivery is lost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentWelcome {
+    /// Epoch the group entered when the invitee was added
+    pub epoch: u64,
+    /// TLS-serialized Welcome message (base64)
+    pub welcome_blob: String,
+    /// Exported ratchet tree at that epoch (base64)
+    pub ratchet_tree_blob: String,
+}
+
+/// An invite whose Welcome this device sent but the invitee has not been
+/// seen to use yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingInvite {
+    pub invitee: String,
+    /// Epoch the group entered when the invitee was added; the Welcome can
+    /// only be resent while the group is still at this epoch
+    pub epoch: u64,
+}
+
+/// Identifies an outbound operation (see `MlsConnection::pending_operations`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PendingOpId {
+    /// Message in the outbound queue, by its `QueuedMessage::id`
+    QueuedMessage(i64),
+    /// Invite this device sent that the invitee has not been seen to use
+    Invite { group_id: Vec<u8>, invitee: String },
+}
+
+impl PendingOpId {
+    /// Parse the form produced by `Display`, `None` if malformed
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(id) = value.strip_prefix("message:") {
+            return id.parse().ok().map(PendingOpId::QueuedMessage);
+        }
+        let (group_id, invitee) = value.strip_prefix("invite:")?.split_once(':')?;
+        if invitee.is_empty() {
+            return None;
+        }
+        Some(PendingOpId::Invite {
+            group_id: general_purpose::STANDARD.decode(group_id).ok()?,
+            invitee: invitee.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for PendingOpId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PendingOpId::QueuedMessage(id) => write!(f, "message:{}", id),
+            PendingOpId::Invite { group_id, invitee } => write!(
+                f,
+                "invite:{}:{}",
+                general_purpose::STANDARD.encode(group_id),
+                invitee
+            ),
+        }
+    }
+}
+
+/// An outbound operation that has not completed and may still be cancelled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingOp {
+    pub id: PendingOpId,
+    /// Group the operation is for
+    pub group_id: Vec<u8>,
+    /// The queued text, or the invitee
+    pub description: String,
+}
+
+/// What `MlsConnection::cancel_operation` managed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The operation was withdrawn before anything was sent
+    Cancelled,
+    /// Too late: the operation already reached the server. Local tracking
+    /// stopped, but members may already have received it.
+    AlreadySent,
+    /// No such operation; nothing was changed
+    NotFound,
+}
+
+/// When a group member joined and was last active, as seen by this client
+///
+/// Times are Unix seconds, taken when this client processed the
+/// authenticated Commit adding the member or a message signed by them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberActivity {
+    pub username: String,
+    /// `None` if they were already in the group when this client joined
+    pub joined_at: Option<i64>,
+    /// `None` until a message from them is received
+    pub last_seen: Option<i64>,
+}
+
+/// Order of the group list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupSort {
+    /// Alphabetically by group name
+    #[default]
+    Name,
+    /// Most recent message first; groups without messages last
+    Activity,
+    /// Most unread messages first
+    Unread,
+}
+
+impl GroupSort {
+    /// Parse a `/groups` argument: `name`, `activity` or `unread`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "name" => Some(Self::Name),
+            "activity" => Some(Self::Activity),
+            "unread" => Some(Self::Unread),
+            _ => None,
+        }
+    }
+}
+
+/// A local group as shown in the group list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupListing {
+    pub name: String,
+    /// Unix seconds of the newest unexpired history entry; `None` if the
+    /// group has no messages
+    pub last_activity: Option<i64>,
+    /// Unread messages from other members (see `MlsProvider::unread_count`)
+    pub unread: usize,
+}
+
+/// Whether a group in a `GroupSummary` is loaded and receiving messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupStatus {
+    /// Loaded, and subscribed on a connected WebSocket
+    Subscribed,
+    /// Loaded, but the WebSocket is not connected
+    Disconnected,
+    /// Its MLS state is stored but the group is not loaded in this session
+    NotLoaded,
+    /// Known by name, but its MLS state could not be loaded
+    Unavailable { error: String },
+}
+
+/// A local group with its details, as listed by
+/// `MlsConnection::list_groups_detailed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSummary {
+    pub name: String,
+    pub group_id: Vec<u8>,
+    /// Members in the MLS roster; `None` if the group is unavailable
+    pub member_count: Option<usize>,
+    /// Current MLS epoch; `None` if the group is unavailable
+    pub epoch: Option<u64>,
+    /// Unread messages from other members (see `MlsProvider::unread_count`)
+    pub unread: usize,
+    /// Unix seconds of the newest unexpired history entry; `None` if the
+    /// group has no messages
+    pub last_activity: Option<i64>,
+    pub status: GroupStatus,
+}
+
+/// Search of the local message history
+///
+/// Every set field must match (AND). An empty `text` with no other field
+/// matches every entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageSearch {
+    /// Text the content contains, ignoring ASCII case; empty matches all
+    pub text: String,
+    /// Exact sender username
+    pub sender: Option<String>,
+    /// Unix seconds; only entries recorded at or after it
+    pub since: Option<i64>,
+    /// Unix seconds; only entries recorded before it
+    pub until: Option<i64>,
+    pub content_type: Option<ContentType>,
+    /// Name of the group to search, as `/groups` shows it; all groups if unset
+    pub group: Option<String>,
+}
+
+impl MessageSearch {
+    /// Parse the argument of `/search`
+    ///
+    /// `from:<user>`, `since:<YYYY-MM-DD>`, `until:<YYYY-MM-DD>`,
+    /// `type:<text|system|control>` and `in:<group>` set filters; the other
+    /// words are the text. Dates are UTC days and `until` includes its day.
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let day = |value: &str| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp())
+                .map_err(|_| format!("Invalid date {} (expected YYYY-MM-DD)", value))
+        };
+        let mut search = MessageSearch::default();
+        let mut words = Vec::new();
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some(("from", user)) if !user.is_empty() => search.sender = Some(user.to_string()),
+                Some(("since", date)) => search.since = Some(day(date)?),
+                Some(("until", date)) => search.until = Some(day(date)? + 24 * 60 * 60),
+                Some(("type", value)) => {
+                    search.content_type = Some(
+                        ContentType::parse(value)
+                            .ok_or_else(|| format!("Unknown message type {}", value))?,
+                    )
+                }
+                Some(("in", group)) if !group.is_empty() => search.group = Some(group.to_string()),
+                _ => words.push(word),
+            }
+        }
+        search.text = words.join(" ");
+        Ok(search)
+    }
+}
+
+/// A history entry found by `MlsConnection::search_messages`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub group_id: Vec<u8>,
+    /// Group name, `None` if the group has no name mapping any more
+    pub group_name: Option<String>,
+    pub entry: HistoryEntry,
+}
+
+/// A staged Welcome with the group it invites to, as listed by
+/// `MlsConnection::pending_invitations_for_me`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingInvitation {
+    /// Id of the staged Welcome, for `accept_welcome` or `decline_welcome`
+    pub id: i64,
+    pub inviter: String,
+    /// Group id; `None` if the Welcome can no longer be decrypted
+    pub group_id: Option<Vec<u8>>,
+    /// Group name from the group metadata, if the Welcome carries it
+    pub group_name: Option<String>,
+    /// Members of the group when the Welcome was sent
+    pub members: Vec<String>,
+    /// Unix timestamp when the Welcome was staged
+    pub received_at: i64,
+}
+
+/// Sort a group list
+///
+/// Ties are broken by more recent activity and then by name, so the order
+/// never depends on the order groups were loaded in. Groups without
+/// messages sort after every group with one.
+pub fn sort_group_listings(groups: &mut [GroupListing], order: GroupSort) {
+    let by_activity = |a: &GroupListing, b: &GroupListing| b.last_activity.cmp(&a.last_activity);
+    let by_name = |a: &GroupListing, b: &GroupListing| a.name.cmp(&b.name);
+    match order {
+        GroupSort::Name => groups.sort_by(by_name),
+        GroupSort::Activity => groups.sort_by(|a, b| by_activity(a, b).then_with(|| by_name(a, b))),
+        GroupSort::Unread => groups.sort_by(|a, b| {
+            b.unread
+                .cmp(&a.unread)
+                .then_with(|| by_activity(a, b))
+                .then_with(|| by_name(a, b))
+        }),
+    }
+}
+
+/// Public facts about the local identity, as reported by `MlsClient::whoami`
+///
+/// Holds nothing secret: the signature private key never leaves `MlsUser`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityInfo {
+    pub username: String,
+    pub credential_kind: crate::crypto::CredentialKind,
+    /// SHA-256 of the credential and signature public key, in hex (see
+    /// `crypto::credential_fingerprint`)
+    pub credential_fingerprint: String,
+    /// Signature public key that members see in this user's leaves
+    pub signature_public_key: Vec<u8>,
+    /// Id the server assigned at registration; `None` until `initialize()`
+    /// has registered
+    pub server_user_id: Option<i64>,
+}
+
 /// Envelope discriminator for WebSocket message types
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type")]
@@ -28,6 +622,17 @@ pub enum MlsMessageEnvelope {
         sender: String,
         group_id: String,
         encrypted_content: String,
+        #[serde(default)]
+        content_type: ContentType,
+        /// Position in the group's history assigned by the server when it
+        /// stored the message; absent on messages we send
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<i64>,
+        /// Epoch the sender encrypted in, copied from the MLS message for
+        /// routers that do not parse MLS. Advisory: receivers go by the
+        /// epoch in the MLS framing, which decryption authenticates
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        epoch: Option<u64>,
     },
     /// Welcome message: new member joining the group
     /// Includes the Welcome message and ratchet tree in one envelope
@@ -45,6 +650,10 @@ pub enum MlsMessageEnvelope {
         group_id: String,
         sender: String,
         commit_blob: String, // TLS-serialized Commit message (base64)
+        /// Device of `sender` that sent the Commit (see
+        /// `LocalStore::device_id`); absent from older clients
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
     },
 }
 
@@ -56,15 +665,110 @@ pub struct IncomingMessage {
     pub encrypted_content: String,
 }
 
+/// What `MlsClient::connect_to_group` does when a group's name is known
+/// locally but its MLS state is missing from storage
+///
+/// Either way the user is told (`GroupEvent::GroupStateLost` and a notice in
+/// the group's history) rather than silently starting fresh.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LostGroupPolicy {
+    /// Keep the name pointing at the lost group and fail with
+    /// `MlsError::GroupStateLost`; a Welcome back into the same group, fetched
+    /// from the server on every connect, restores it
+    #[default]
+    Rejoin,
+    /// Start a new group under the name; the old conversation stays on the
+    /// server but is no longer reachable from this device
+    Recreate,
+}
+
+/// What the connection does with an incoming Welcome
+///
+/// Staged Welcomes create no group state, are not recorded on the server
+/// and subscribe to nothing until accepted, so the inviter cannot see the
+/// invitee in the group before they chose to join.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WelcomePolicy {
+    /// Join the group right away
+    #[default]
+    AutoAccept,
+    /// Keep the Welcome until the user accepts or declines it (see
+    /// `MlsConnection::staged_welcomes`)
+    Stage,
+}
+
+/// How the `GroupSender`s of one `MlsConnection::group_senders` call share
+/// their send locks
+///
+/// Sends through one sender are always one at a time, each holding the
+/// group's lock from encryption until the envelope is queued.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SendConcurrency {
+    /// Each group has its own lock, so sends to different groups proceed
+    /// side by side
+    #[default]
+    PerGroup,
+    /// All senders share one lock, so only one send is in progress at a time
+    Serial,
+}
+
 /// Command types for CLI
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Invite(String),
+    /// Remove a member from the group (group admins only)
+    Remove(String),
     List,
+    /// List members with their join time and last activity, most recently
+    /// active first when `by_activity` is set
+    Members {
+        by_activity: bool,
+    },
+    /// Set the disappearing-message TTL in seconds (`None` turns it off)
+    SetMessageTtl(Option<u64>),
+    /// Mark the selected group as read
+    MarkRead,
+    /// Show every local group with its unread count and last activity
+    Groups(GroupSort),
     Message(String),
+    /// Send a message rendered as markdown by receivers
+    Markdown(String),
+    /// Post an announcement (group admins only)
+    Announce(String),
+    /// Pin a message by its id (group admins only)
+    Pin(String),
+    /// Unpin a message by its id (group admins only)
+    Unpin(String),
+    /// List the group's pinned messages
+    Pins,
+    /// Show the page of history before the messages already shown
+    More,
+    /// List queued messages and pending invites
+    Pending,
+    /// Cancel a pending operation by the id `/pending` shows
+    Cancel(String),
+    /// Create a join token for the group, optionally expiring after the
+    /// given seconds (group admins only)
+    JoinToken(Option<u64>),
+    /// Redeem a join token to ask to be added to its group
+    Redeem(String),
+    /// Add everyone who redeemed one of the group's join tokens
+    Admit,
+    /// Reply to a message by its id, quoting it
+    Quote {
+        message_id: String,
+        text: String,
+    },
+    /// Search the local history of every group (see `MessageSearch::parse`)
+    Search(MessageSearch),
+    /// Show the local identity
+    Whoami,
     Quit,
 }
 
+const SEARCH_USAGE: &str =
+    "Usage: /search [from:<user>] [since:<YYYY-MM-DD>] [until:<YYYY-MM-DD>] [type:<type>] [in:<group>] [text]";
+
 impl Command {
     /// Parse a command string
     pub fn parse(input: &str) -> Result<Self, String> {
@@ -78,6 +782,47 @@ impl Command {
             return Ok(Command::List);
         }
 
+        if input == "/members" {
+            return Ok(Command::Members { by_activity: false });
+        }
+
+        if let Some(order) = input.strip_prefix("/members ") {
+            return match order.trim() {
+                "active" => Ok(Command::Members { by_activity: true }),
+                _ => Err("Usage: /members [active]".to_string()),
+            };
+        }
+
+        if input == "/read" {
+            return Ok(Command::MarkRead);
+        }
+
+        if input == "/groups" {
+            return Ok(Command::Groups(GroupSort::Name));
+        }
+
+        if let Some(order) = input.strip_prefix("/groups ") {
+            return GroupSort::parse(order.trim())
+                .map(Command::Groups)
+                .ok_or_else(|| "Usage: /groups [name|activity|unread]".to_string());
+        }
+
+        if input == "/whoami" {
+            return Ok(Command::Whoami);
+        }
+
+        if input == "/search" {
+            return Err(SEARCH_USAGE.to_string());
+        }
+
+        if let Some(query) = input.strip_prefix("/search ") {
+            let search = MessageSearch::parse(query)?;
+            if search == MessageSearch::default() {
+                return Err(SEARCH_USAGE.to_string());
+            }
+            return Ok(Command::Search(search));
+        }
+
         if let Some(invitee) = input.strip_prefix("/invite ") {
             if invitee.is_empty() {
                 return Err("Usage: /invite <username>".to_string());
@@ -85,6 +830,112 @@ impl Command {
             return Ok(Command::Invite(invitee.to_string()));
         }
 
+        if let Some(username) = input.strip_prefix("/remove ") {
+            return match username.trim() {
+                "" => Err("Usage: /remove <username>".to_string()),
+                username => Ok(Command::Remove(username.to_string())),
+            };
+        }
+
+        if input == "/announce" {
+            return Err("Usage: /announce <message>".to_string());
+        }
+
+        if let Some(text) = input.strip_prefix("/announce ") {
+            return Ok(Command::Announce(text.trim_start().to_string()));
+        }
+
+        if input == "/pins" {
+            return Ok(Command::Pins);
+        }
+
+        if input == "/more" {
+            return Ok(Command::More);
+        }
+
+        if input == "/pending" {
+            return Ok(Command::Pending);
+        }
+
+        if input == "/admit" {
+            return Ok(Command::Admit);
+        }
+
+        if input == "/token" {
+            return Ok(Command::JoinToken(None));
+        }
+
+        if let Some(ttl) = input.strip_prefix("/token ") {
+            return ttl
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|ttl| *ttl > 0)
+                .map(|ttl| Command::JoinToken(Some(ttl)))
+                .ok_or_else(|| "Usage: /token [seconds]".to_string());
+        }
+
+        if input == "/redeem" {
+            return Err("Usage: /redeem <token>".to_string());
+        }
+
+        if let Some(token) = input.strip_prefix("/redeem ") {
+            return Ok(Command::Redeem(token.trim().to_string()));
+        }
+
+        if input == "/cancel" {
+            return Err("Usage: /cancel <operation id>".to_string());
+        }
+
+        if let Some(id) = input.strip_prefix("/cancel ") {
+            return match id.trim() {
+                "" => Err("Usage: /cancel <operation id>".to_string()),
+                id => Ok(Command::Cancel(id.to_string())),
+            };
+        }
+
+        if let Some(id) = input.strip_prefix("/pin ") {
+            return match id.trim() {
+                "" => Err("Usage: /pin <message id>".to_string()),
+                id => Ok(Command::Pin(id.to_string())),
+            };
+        }
+
+        if let Some(id) = input.strip_prefix("/unpin ") {
+            return match id.trim() {
+                "" => Err("Usage: /unpin <message id>".to_string()),
+                id => Ok(Command::Unpin(id.to_string())),
+            };
+        }
+
+        if let Some(rest) = input.strip_prefix("/quote ") {
+            return match rest.trim_start().split_once(' ') {
+                Some((id, text)) if !text.trim().is_empty() => Ok(Command::Quote {
+                    message_id: id.to_string(),
+                    text: text.trim_start().to_string(),
+                }),
+                _ => Err("Usage: /quote <message id> <message>".to_string()),
+            };
+        }
+
+        if input == "/md" {
+            return Err("Usage: /md <message>".to_string());
+        }
+
+        if let Some(text) = input.strip_prefix("/md ") {
+            return Ok(Command::Markdown(text.trim_start().to_string()));
+        }
+
+        if let Some(ttl) = input.strip_prefix("/ttl ") {
+            return match ttl.trim() {
+                "off" => Ok(Command::SetMessageTtl(None)),
+                secs => match secs.parse::<u64>() {
+                    Ok(secs) if secs > 0 => Ok(Command::SetMessageTtl(Some(secs))),
+                    _ => Err("Usage: /ttl <seconds>|off".to_string()),
+                },
+            };
+        }
+
         if input.starts_with('/') {
             return Err(format!("Unknown command: {}", input));
         }
@@ -103,7 +954,22 @@ mod tests {
             Command::parse("/invite alice"),
             Ok(Command::Invite("alice".to_string()))
         );
+        assert_eq!(
+            Command::parse("/remove carol"),
+            Ok(Command::Remove("carol".to_string()))
+        );
         assert_eq!(Command::parse("/list"), Ok(Command::List));
+        assert_eq!(Command::parse("/read"), Ok(Command::MarkRead));
+        assert_eq!(
+            Command::parse("/groups"),
+            Ok(Command::Groups(GroupSort::Name))
+        );
+        assert_eq!(
+            Command::parse("/groups unread"),
+            Ok(Command::Groups(GroupSort::Unread))
+        );
+        assert!(Command::parse("/groups newest").is_err());
+        assert_eq!(Command::parse("/whoami"), Ok(Command::Whoami));
         assert_eq!(
             Command::parse("Hello world"),
             Ok(Command::Message("Hello world".to_string()))
@@ -113,6 +979,195 @@ mod tests {
 
         assert!(Command::parse("/unknown").is_err());
         assert!(Command::parse("/invite").is_err());
+        assert!(Command::parse("/remove ").is_err());
+    }
+
+    #[test]
+    fn test_markdown_command_parsing() {
+        assert_eq!(
+            Command::parse("/md **hello** world"),
+            Ok(Command::Markdown("**hello** world".to_string()))
+        );
+        assert!(Command::parse("/md").is_err());
+        assert!(Command::parse("/md   ").is_err());
+        assert!(Command::parse("/mdx").is_err());
+    }
+
+    #[test]
+    fn test_announce_command_parsing() {
+        assert_eq!(
+            Command::parse("/announce Offsite on Friday"),
+            Ok(Command::Announce("Offsite on Friday".to_string()))
+        );
+        assert!(Command::parse("/announce").is_err());
+    }
+
+    #[test]
+    fn test_pending_op_ids_roundtrip() {
+        let ids = [
+            PendingOpId::QueuedMessage(12),
+            PendingOpId::Invite {
+                group_id: vec![0xfb, 0xff, 0x00],
+                invitee: "bob".to_string(),
+            },
+        ];
+        for id in ids {
+            assert_eq!(PendingOpId::parse(&id.to_string()), Some(id));
+        }
+        assert_eq!(PendingOpId::QueuedMessage(3).to_string(), "message:3");
+        for malformed in [
+            "message:x",
+            "invite:AAA",
+            "invite:!!:bob",
+            "invite:AAA:",
+            "3",
+        ] {
+            assert_eq!(PendingOpId::parse(malformed), None, "{}", malformed);
+        }
+
+        assert_eq!(Command::parse("/pending"), Ok(Command::Pending));
+        assert_eq!(Command::parse("/token"), Ok(Command::JoinToken(None)));
+        assert_eq!(
+            Command::parse("/token 3600"),
+            Ok(Command::JoinToken(Some(3600)))
+        );
+        assert!(Command::parse("/token 0").is_err());
+        assert!(Command::parse("/token soon").is_err());
+        assert_eq!(
+            Command::parse("/redeem  abc-123 "),
+            Ok(Command::Redeem("abc-123".to_string()))
+        );
+        assert!(Command::parse("/redeem").is_err());
+        assert_eq!(Command::parse("/admit"), Ok(Command::Admit));
+        assert_eq!(
+            Command::parse("/cancel message:3"),
+            Ok(Command::Cancel("message:3".to_string()))
+        );
+        assert!(Command::parse("/cancel").is_err());
+    }
+
+    #[test]
+    fn test_search_command_parsing() {
+        assert_eq!(
+            Command::parse("/search from:alice since:2024-01-01 lunch plans"),
+            Ok(Command::Search(MessageSearch {
+                text: "lunch plans".to_string(),
+                sender: Some("alice".to_string()),
+                since: Some(1_704_067_200),
+                ..MessageSearch::default()
+            }))
+        );
+        // Filters alone, with until covering its whole day
+        assert_eq!(
+            Command::parse("/search until:2024-01-01 type:system in:general"),
+            Ok(Command::Search(MessageSearch {
+                until: Some(1_704_153_600),
+                content_type: Some(ContentType::System),
+                group: Some("general".to_string()),
+                ..MessageSearch::default()
+            }))
+        );
+        // Words with an unknown prefix are text
+        assert_eq!(MessageSearch::parse("re: 10:30").unwrap().text, "re: 10:30");
+        assert!(Command::parse("/search").is_err());
+        assert!(Command::parse("/search   ").is_err());
+        assert!(Command::parse("/search since:yesterday").is_err());
+        assert!(Command::parse("/search type:image").is_err());
+    }
+
+    #[test]
+    fn test_pin_command_parsing() {
+        assert_eq!(Command::parse("/pins"), Ok(Command::Pins));
+        assert_eq!(Command::parse("/more"), Ok(Command::More));
+        assert_eq!(
+            Command::parse("/pin 0a1b2c"),
+            Ok(Command::Pin("0a1b2c".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/unpin  0a1b2c "),
+            Ok(Command::Unpin("0a1b2c".to_string()))
+        );
+        assert!(Command::parse("/pin").is_err());
+        assert!(Command::parse("/unpin").is_err());
+        assert_eq!(
+            Command::parse("/quote 0a1b2c  see you there"),
+            Ok(Command::Quote {
+                message_id: "0a1b2c".to_string(),
+                text: "see you there".to_string(),
+            })
+        );
+        assert!(Command::parse("/quote 0a1b2c").is_err());
+        assert!(Command::parse("/quote 0a1b2c   ").is_err());
+    }
+
+    #[test]
+    fn test_members_command_parsing() {
+        assert_eq!(
+            Command::parse("/members"),
+            Ok(Command::Members { by_activity: false })
+        );
+        assert_eq!(
+            Command::parse("/members active"),
+            Ok(Command::Members { by_activity: true })
+        );
+        assert!(Command::parse("/members oldest").is_err());
+    }
+
+    #[test]
+    fn test_group_list_sort_orders() {
+        let listing = |name: &str, last_activity: Option<i64>, unread: usize| GroupListing {
+            name: name.to_string(),
+            last_activity,
+            unread,
+        };
+        let groups = vec![
+            listing("zeta", Some(100), 0),
+            listing("empty-b", None, 0),
+            listing("alpha", Some(300), 2),
+            listing("empty-a", None, 0),
+            listing("beta", Some(300), 5),
+            listing("gamma", Some(200), 2),
+        ];
+        let sorted = |order| {
+            let mut groups = groups.clone();
+            sort_group_listings(&mut groups, order);
+            groups.into_iter().map(|g| g.name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(GroupSort::Name),
+            ["alpha", "beta", "empty-a", "empty-b", "gamma", "zeta"]
+        );
+        // Equal activity falls back to name; groups without messages go last
+        assert_eq!(
+            sorted(GroupSort::Activity),
+            ["alpha", "beta", "gamma", "zeta", "empty-a", "empty-b"]
+        );
+        // Equal unread counts fall back to activity, then name
+        assert_eq!(
+            sorted(GroupSort::Unread),
+            ["beta", "alpha", "gamma", "zeta", "empty-a", "empty-b"]
+        );
+
+        // The input order does not matter
+        let mut reversed = groups.clone();
+        reversed.reverse();
+        sort_group_listings(&mut reversed, GroupSort::Unread);
+        assert_eq!(
+            reversed.into_iter().map(|g| g.name).collect::<Vec<_>>(),
+            sorted(GroupSort::Unread)
+        );
+    }
+
+    #[test]
+    fn test_ttl_command_parsing() {
+        assert_eq!(
+            Command::parse("/ttl 3600"),
+            Ok(Command::SetMessageTtl(Some(3600)))
+        );
+        assert_eq!(Command::parse("/ttl off"), Ok(Command::SetMessageTtl(None)));
+        assert!(Command::parse("/ttl 0").is_err());
+        assert!(Command::parse("/ttl soon").is_err());
     }
 
     #[test]
@@ -137,14 +1192,52 @@ mod tests {
             sender: "alice".to_string(),
             group_id: "testgroup".to_string(),
             encrypted_content: "base64encrypteddata".to_string(),
+            content_type: ContentType::Text,
+            seq: None,
+            epoch: None,
         };
 
         let json = serde_json::to_string(&envelope).unwrap();
         assert!(json.contains("\"type\":\"application\""));
+        assert!(json.contains("\"content_type\":\"text\""));
         assert!(json.contains("\"sender\":\"alice\""));
+        assert!(!json.contains("seq"));
+        assert!(!json.contains("epoch"));
 
         let deserialized: MlsMessageEnvelope = serde_json::from_str(&json).unwrap();
         assert_eq!(envelope, deserialized);
+
+        // The server adds the sequence number it stored the message under
+        let json = r#"{"type":"application","sender":"alice","group_id":"g","encrypted_content":"x","seq":42}"#;
+        let envelope: MlsMessageEnvelope = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            envelope,
+            MlsMessageEnvelope::ApplicationMessage { seq: Some(42), .. }
+        ));
+    }
+
+    #[test]
+    fn test_application_message_without_content_type_is_text() {
+        let json =
+            r#"{"type":"application","sender":"alice","group_id":"g","encrypted_content":"x"}"#;
+        let envelope: MlsMessageEnvelope = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            envelope,
+            MlsMessageEnvelope::ApplicationMessage {
+                content_type: ContentType::Text,
+                ..
+            }
+        ));
+
+        let json = r#"{"type":"application","sender":"alice","group_id":"g","encrypted_content":"x","content_type":"system"}"#;
+        let envelope: MlsMessageEnvelope = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            envelope,
+            MlsMessageEnvelope::ApplicationMessage {
+                content_type: ContentType::System,
+                ..
+            }
+        ));
     }
 
     #[test]
@@ -173,6 +1266,7 @@ mod tests {
             group_id: "testgroup".to_string(),
             sender: "alice".to_string(),
             commit_blob: "base64commitblob".to_string(),
+            device_id: None,
         };
 
         let json = serde_json::to_string(&envelope).unwrap();
@@ -0,0 +1,351 @@
+//! Application message payload encoding
+//!
+//! Every application message plaintext starts with a one-byte format
+//! descriptor, followed by the message body:
+//! - `0x00`: body is the raw message
+//! - `0x01`: body is the message compressed with zstd
+//! - `0x02`: a one-byte `TextFormat`, then a payload with one of the
+//!   descriptors above
+//! - `0x03`: a quote snapshot (the quoted message's author and text, each a
+//!   big-endian `u16` length and UTF-8 bytes), then a payload with one of
+//!   the descriptors above
+//!
+//! Plain text is sent without the `0x02` wrapper, so it stays readable by
+//! clients that predate it. An unknown `TextFormat` is rendered as plain.
+//! A quote is carried in the reply itself, so it still shows once the quoted
+//! message is gone; it is cut to `MAX_QUOTE_CHARS` on both ends.
+//!
+//! The descriptor is part of the plaintext handed to MLS, so it is encrypted
+//! and authenticated together with the body. Messages below
+//! `COMPRESSION_THRESHOLD` are never compressed, since the zstd frame
+//! overhead outweighs any saving.
+//!
+//! Payloads whose first byte is not a known descriptor are treated as raw
+//! messages from clients that predate the descriptor.
+
+use crate::error::{MlsError, Result};
+use crate::models::Quote;
+
+/// Messages shorter than this (in bytes) are sent uncompressed
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Upper bound on the size of a decompressed message
+pub const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
+const FORMAT_RAW: u8 = 0x00;
+const FORMAT_ZSTD: u8 = 0x01;
+const FORMAT_TEXT: u8 = 0x02;
+const FORMAT_QUOTE: u8 = 0x03;
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the sender wants a text message rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextFormat {
+    #[default]
+    Plain,
+    /// Minimal markdown: `**bold**`, `*italic*` and `` `code` ``
+    Markdown,
+}
+
+impl TextFormat {
+    fn as_byte(self) -> u8 {
+        match self {
+            TextFormat::Plain => 0x00,
+            TextFormat::Markdown => 0x01,
+        }
+    }
+
+    /// Parse a format byte, falling back to `Plain` for unknown formats
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0x01 => TextFormat::Markdown,
+            0x00 => TextFormat::Plain,
+            other => {
+                log::debug!("Unknown text format {:#04x}, rendering as plain", other);
+                TextFormat::Plain
+            }
+        }
+    }
+}
+
+/// Encode a message for encryption
+///
+/// Compresses when `compress` is set, the message reaches
+/// `COMPRESSION_THRESHOLD` and compression actually makes it smaller.
+pub fn encode(message: &[u8], compress: bool) -> Result<Vec<u8>> {
+    if compress && message.len() >= COMPRESSION_THRESHOLD {
+        let compressed = zstd::bulk::compress(message, ZSTD_LEVEL)
+            .map_err(|e| MlsError::OpenMls(format!("Failed to compress message: {}", e)))?;
+        if compressed.len() < message.len() {
+            let mut payload = Vec::with_capacity(compressed.len() + 1);
+            payload.push(FORMAT_ZSTD);
+            payload.extend_from_slice(&compressed);
+            return Ok(payload);
+        }
+    }
+
+    let mut payload = Vec::with_capacity(message.len() + 1);
+    payload.push(FORMAT_RAW);
+    payload.extend_from_slice(message);
+    Ok(payload)
+}
+
+/// Encode a text message in the given format for encryption
+///
+/// Plain text is encoded exactly as by `encode`.
+pub fn encode_text(message: &[u8], format: TextFormat, compress: bool) -> Result<Vec<u8>> {
+    let body = encode(message, compress)?;
+    if format == TextFormat::Plain {
+        return Ok(body);
+    }
+
+    let mut payload = Vec::with_capacity(body.len() + 2);
+    payload.push(FORMAT_TEXT);
+    payload.push(format.as_byte());
+    payload.extend_from_slice(&body);
+    Ok(payload)
+}
+
+/// Encode a reply quoting another message for encryption
+///
+/// The quote snapshot precedes the text, framed as by `encode_text`. The
+/// snapshot is cut to `MAX_QUOTE_CHARS` if needed.
+pub fn encode_reply(
+    message: &[u8],
+    format: TextFormat,
+    quote: &Quote,
+    compress: bool,
+) -> Result<Vec<u8>> {
+    let quote = Quote::new(&quote.author, &quote.text);
+    let body = encode_text(message, format, compress)?;
+
+    let mut payload = Vec::with_capacity(body.len() + quote.author.len() + quote.text.len() + 5);
+    payload.push(FORMAT_QUOTE);
+    for field in [&quote.author, &quote.text] {
+        // Bounded fields are at most 4 * MAX_QUOTE_CHARS bytes long
+        payload.extend_from_slice(&(field.len() as u16).to_be_bytes());
+        payload.extend_from_slice(field.as_bytes());
+    }
+    payload.extend_from_slice(&body);
+    Ok(payload)
+}
+
+/// Decode a decrypted payload back into the message
+///
+/// Any text format or quote is dropped; use `decode_text` or `decode_reply`
+/// to keep them.
+///
+/// # Errors
+/// * `MlsError::DecryptionFailed` if a compressed body is corrupt or would
+///   exceed `MAX_DECOMPRESSED_SIZE`
+pub fn decode(payload: &[u8]) -> Result<Vec<u8>> {
+    decode_text(payload).map(|(_, message)| message)
+}
+
+/// Decode a decrypted payload into the message and its text format
+///
+/// Any quote is dropped; use `decode_reply` to keep it.
+///
+/// # Errors
+/// * as for `decode`
+pub fn decode_text(payload: &[u8]) -> Result<(TextFormat, Vec<u8>)> {
+    decode_reply(payload).map(|(format, message, _)| (format, message))
+}
+
+/// Decode a decrypted payload into the message, its text format and the
+/// quote it carries, if any
+///
+/// # Errors
+/// * as for `decode`
+/// * `MlsError::DecryptionFailed` if a quote snapshot is truncated or not UTF-8
+pub fn decode_reply(payload: &[u8]) -> Result<(TextFormat, Vec<u8>, Option<Quote>)> {
+    match payload {
+        [FORMAT_QUOTE, rest @ ..] => {
+            let (author, rest) = split_quote_field(rest)?;
+            let (text, rest) = split_quote_field(rest)?;
+            let (format, message) = decode_formatted(rest)?;
+            Ok((format, message, Some(Quote::new(&author, &text))))
+        }
+        _ => {
+            let (format, message) = decode_formatted(payload)?;
+            Ok((format, message, None))
+        }
+    }
+}
+
+/// Split a length-prefixed UTF-8 field off the front of a quote snapshot
+fn split_quote_field(payload: &[u8]) -> Result<(String, &[u8])> {
+    let malformed = || {
+        log::error!("Malformed quote in message payload");
+        MlsError::DecryptionFailed.into()
+    };
+    let (len, rest) = match payload {
+        [high, low, rest @ ..] => (u16::from_be_bytes([*high, *low]) as usize, rest),
+        _ => return Err(malformed()),
+    };
+    if rest.len() < len {
+        return Err(malformed());
+    }
+    let (field, rest) = rest.split_at(len);
+    let field = String::from_utf8(field.to_vec()).map_err(|_| malformed())?;
+    Ok((field, rest))
+}
+
+fn decode_formatted(payload: &[u8]) -> Result<(TextFormat, Vec<u8>)> {
+    match payload {
+        [FORMAT_TEXT, format, body @ ..] => {
+            Ok((TextFormat::from_byte(*format), decode_body(body)?))
+        }
+        _ => Ok((TextFormat::Plain, decode_body(payload)?)),
+    }
+}
+
+fn decode_body(payload: &[u8]) -> Result<Vec<u8>> {
+    match payload.split_first() {
+        Some((&FORMAT_RAW, body)) => Ok(body.to_vec()),
+        Some((&FORMAT_ZSTD, body)) => {
+            zstd::bulk::decompress(body, MAX_DECOMPRESSED_SIZE).map_err(|e| {
+                log::error!("Failed to decompress message: {}", e);
+                MlsError::DecryptionFailed.into()
+            })
+        }
+        _ => Ok(payload.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MAX_QUOTE_CHARS;
+
+    #[test]
+    fn test_small_message_is_not_compressed() {
+        let payload = encode(b"hello", true).unwrap();
+        assert_eq!(payload, b"\x00hello");
+        assert_eq!(decode(&payload).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_large_message_roundtrips_compressed() {
+        let message = "all work and no play ".repeat(500).into_bytes();
+        let payload = encode(&message, true).unwrap();
+        assert_eq!(payload[0], FORMAT_ZSTD);
+        assert!(payload.len() < message.len());
+        assert_eq!(decode(&payload).unwrap(), message);
+
+        let uncompressed = encode(&message, false).unwrap();
+        assert_eq!(uncompressed[0], FORMAT_RAW);
+        assert_eq!(decode(&uncompressed).unwrap(), message);
+    }
+
+    #[test]
+    fn test_decode_legacy_and_corrupt_payloads() {
+        // Payloads without a descriptor pass through unchanged
+        assert_eq!(decode(b"Hello from Alice!").unwrap(), b"Hello from Alice!");
+        assert_eq!(decode(b"").unwrap(), b"");
+
+        assert!(decode(b"\x01not zstd").is_err());
+    }
+
+    #[test]
+    fn test_text_format_roundtrips() {
+        // Plain text is framed exactly as before
+        assert_eq!(
+            encode_text(b"hi", TextFormat::Plain, true).unwrap(),
+            encode(b"hi", true).unwrap()
+        );
+
+        let payload = encode_text(b"**hi**", TextFormat::Markdown, true).unwrap();
+        assert_eq!(payload, b"\x02\x01\x00**hi**");
+        assert_eq!(
+            decode_text(&payload).unwrap(),
+            (TextFormat::Markdown, b"**hi**".to_vec())
+        );
+        assert_eq!(decode(&payload).unwrap(), b"**hi**");
+
+        // The format survives compression
+        let message = "**all work** and no play ".repeat(500).into_bytes();
+        let payload = encode_text(&message, TextFormat::Markdown, true).unwrap();
+        assert_eq!(payload[2], FORMAT_ZSTD);
+        assert_eq!(
+            decode_text(&payload).unwrap(),
+            (TextFormat::Markdown, message)
+        );
+    }
+
+    #[test]
+    fn test_reply_roundtrips_with_quote() {
+        let quote = Quote::new("alice", "lunch at noon?");
+        let payload = encode_reply(b"**yes**", TextFormat::Markdown, &quote, true).unwrap();
+        assert_eq!(payload[0], FORMAT_QUOTE);
+        assert_eq!(
+            decode_reply(&payload).unwrap(),
+            (
+                TextFormat::Markdown,
+                b"**yes**".to_vec(),
+                Some(quote.clone())
+            )
+        );
+        // Readers that do not care about quotes get the reply text
+        assert_eq!(decode(&payload).unwrap(), b"**yes**");
+
+        // The reply itself can still be compressed
+        let message = "all work and no play ".repeat(500).into_bytes();
+        let payload = encode_reply(&message, TextFormat::Plain, &quote, true).unwrap();
+        assert!(payload.len() < message.len());
+        assert_eq!(
+            decode_reply(&payload).unwrap(),
+            (TextFormat::Plain, message, Some(quote))
+        );
+
+        assert_eq!(
+            decode_reply(b"\x00hi").unwrap(),
+            (TextFormat::Plain, b"hi".to_vec(), None)
+        );
+    }
+
+    #[test]
+    fn test_quote_snapshot_is_bounded() {
+        let long = "é".repeat(MAX_QUOTE_CHARS * 2);
+        let payload = encode_reply(
+            b"tl;dr",
+            TextFormat::Plain,
+            &Quote::new("bob", &long),
+            false,
+        )
+        .unwrap();
+        let (_, _, quote) = decode_reply(&payload).unwrap();
+        let quote = quote.unwrap();
+        assert_eq!(quote.text.chars().count(), MAX_QUOTE_CHARS);
+        assert!(quote.text.ends_with('…'));
+
+        // A sender that skips the bound is cut on receipt
+        let mut payload = vec![FORMAT_QUOTE, 0, 3];
+        payload.extend_from_slice(b"bob");
+        payload.extend_from_slice(&(long.len() as u16).to_be_bytes());
+        payload.extend_from_slice(long.as_bytes());
+        payload.extend_from_slice(b"\x00tl;dr");
+        let (_, message, quote) = decode_reply(&payload).unwrap();
+        assert_eq!(message, b"tl;dr");
+        assert_eq!(quote.unwrap().text.chars().count(), MAX_QUOTE_CHARS);
+    }
+
+    #[test]
+    fn test_malformed_quote_is_rejected() {
+        assert!(decode_reply(b"\x03").is_err());
+        assert!(decode_reply(b"\x03\x00\x09bob").is_err());
+        assert!(decode_reply(b"\x03\x00\x01\xff\x00\x00\x00hi").is_err());
+    }
+
+    #[test]
+    fn test_unknown_text_format_is_plain() {
+        assert_eq!(
+            decode_text(b"\x02\x7f\x00*hi*").unwrap(),
+            (TextFormat::Plain, b"*hi*".to_vec())
+        );
+        assert_eq!(
+            decode_text(b"hello").unwrap(),
+            (TextFormat::Plain, b"hello".to_vec())
+        );
+    }
+}
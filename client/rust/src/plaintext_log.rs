@@ -0,0 +1,96 @@
+//! Guard against logging decrypted message content
+//!
+//! Code that wants to log message text wraps it with `redact`, which prints
+//! only the length unless `--unsafe-debug-plaintext` was given at startup.
+//! The flag is refused in release builds, so a deployed client never writes
+//! plaintext to its logs.
+
+use crate::error::{ClientError, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static UNSAFE_DEBUG_PLAINTEXT: AtomicBool = AtomicBool::new(false);
+
+/// Let `redact` show message text in logs (`--unsafe-debug-plaintext`)
+///
+/// # Errors
+/// * Called in a release build
+pub fn enable_unsafe_debug_plaintext() -> Result<()> {
+    check_build(cfg!(debug_assertions))?;
+    UNSAFE_DEBUG_PLAINTEXT.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether message text may appear in logs
+pub fn plaintext_logging_enabled() -> bool {
+    UNSAFE_DEBUG_PLAINTEXT.load(Ordering::Relaxed)
+}
+
+fn check_build(debug_build: bool) -> Result<()> {
+    if debug_build {
+        Ok(())
+    } else {
+        Err(ClientError::Config(
+            "--unsafe-debug-plaintext is only available in debug builds".to_string(),
+        ))
+    }
+}
+
+/// Message text as it may appear in a log line
+pub struct Redacted<'a> {
+    text: &'a str,
+    reveal: bool,
+}
+
+/// Wrap `text` for logging; it is shown only under `--unsafe-debug-plaintext`
+pub fn redact(text: &str) -> Redacted<'_> {
+    Redacted {
+        text,
+        reveal: plaintext_logging_enabled(),
+    }
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reveal {
+            f.write_str(self.text)
+        } else {
+            write!(f, "[{} bytes redacted]", self.text.len())
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_text_shows_only_its_length() {
+        let hidden = Redacted {
+            text: "attack at dawn",
+            reveal: false,
+        };
+        assert_eq!(hidden.to_string(), "[14 bytes redacted]");
+        assert_eq!(format!("{:?}", hidden), "[14 bytes redacted]");
+
+        let shown = Redacted {
+            text: "attack at dawn",
+            reveal: true,
+        };
+        assert_eq!(shown.to_string(), "attack at dawn");
+    }
+
+    #[test]
+    fn test_release_builds_refuse_plaintext_logging() {
+        assert!(check_build(true).is_ok());
+        assert!(matches!(check_build(false), Err(ClientError::Config(_))));
+        // Nothing in the test suite turns the flag on
+        assert!(!plaintext_logging_enabled());
+    }
+}
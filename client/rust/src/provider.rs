@@ -5,13 +5,20 @@
 //! - SqliteStorageProvider for persistent group state
 //! - Automatic serialization/deserialization of MLS state
 
-use crate::error::{ClientError, Result};
+use crate::error::{ClientError, Result, StorageError};
+use crate::models::{
+    ContentType, GroupListing, GroupMapping, GroupNameConflict, HistoryEntry, HistoryPage,
+    IntegrityReport, MemberActivity, MessageProof, MessageSearch, NewHistoryEntry, PendingInvite,
+    Quote, SentWelcome, StorageStats,
+};
+use crate::storage::StorageTuning;
 use openmls::prelude::*;
 use openmls_rust_crypto::RustCrypto;
 use openmls_sqlite_storage::SqliteStorageProvider;
 use rusqlite::{Connection, OptionalExtension};
 use serde::Serialize;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
 /// Binary codec for efficient serialization
 #[derive(Default)]
@@ -31,26 +38,106 @@ impl openmls_sqlite_storage::Codec for BincodeCodec {
     }
 }
 
+/// Metadata tables with rows keyed by a group's id (see `purge_group`)
+const GROUP_TABLES: [&str; 9] = [
+    "group_names",
+    "superseded_group_names",
+    "group_history",
+    "sent_welcomes",
+    "read_watermarks",
+    "received_messages",
+    "member_activity",
+    "group_settings",
+    "message_proofs",
+];
+
+/// Largest serialized settings blob kept per group (see `save_group_settings`)
+pub const MAX_GROUP_SETTINGS_BYTES: usize = 64 * 1024;
+
+/// Insert one `group_history` row; a message id already recorded for the
+/// group is ignored
+const INSERT_HISTORY: &str = "INSERT OR IGNORE INTO group_history (group_id, sender, content, content_type, recorded_at, expires_at, message_id, quote_author, quote_text) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+
+/// Columns of `group_history` read by `history_entry`, in order
+const HISTORY_COLUMNS: &str =
+    "sender, content, content_type, recorded_at, expires_at, message_id, pinned, quote_author, quote_text";
+
+/// Build a `HistoryEntry` from a row selecting `HISTORY_COLUMNS`
+fn history_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<HistoryEntry> {
+    let content_type: String = row.get(2)?;
+    let quote_author: Option<String> = row.get(7)?;
+    let quote_text: Option<String> = row.get(8)?;
+    Ok(HistoryEntry {
+        sender: row.get(0)?,
+        content: row.get(1)?,
+        content_type: ContentType::parse(&content_type).unwrap_or_default(),
+        recorded_at: row.get(3)?,
+        expires_at: row.get(4)?,
+        message_id: row.get(5)?,
+        pinned: row.get(6)?,
+        quote: quote_author
+            .zip(quote_text)
+            .map(|(author, text)| Quote { author, text }),
+    })
+}
+
 /// OpenMLS provider combining cryptography, randomness, and storage
 pub struct MlsProvider {
     crypto: RustCrypto,
+    /// Randomness that tests can seed (see `set_rng_seed`)
+    #[cfg(any(test, feature = "test_utils"))]
+    rand: crate::test_rng::SeededRand,
     storage: SqliteStorageProvider<BincodeCodec, Connection>,
     conn: Connection,
 }
 
-impl MlsProvider {
-    /// Create a new provider with file-based SQLite storage
+/// Where an `MlsProvider` keeps its SQLite databases
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageSpec {
+    /// Private in-memory databases, discarded when the provider is dropped
+    InMemory,
+    /// Database file at the given path
+    File(PathBuf),
+    /// In-memory database shared by every provider opened with the same name
+    /// in this process; it is discarded once the last of them is dropped
     ///
-    /// # Arguments
-    /// * `db_path` - Path to the SQLite database file
+    /// The name is used in an SQLite URI and must not contain `?` or `#`.
+    SharedMemory(String),
+}
+
+impl StorageSpec {
+    fn open_connection(&self, tuning: &StorageTuning) -> Result<Connection> {
+        let conn = match self {
+            StorageSpec::InMemory => Connection::open_in_memory()?,
+            StorageSpec::File(path) => Connection::open(path)?,
+            // rusqlite opens with SQLITE_OPEN_URI, so this names a shared-cache memory DB
+            StorageSpec::SharedMemory(name) => {
+                Connection::open(format!("file:{}?mode=memory&cache=shared", name))?
+            }
+        };
+        tuning.apply(&conn)?;
+        Ok(conn)
+    }
+}
+
+impl MlsProvider {
+    /// Create a provider on the storage described by `spec`
     ///
     /// # Errors
     /// * Database connection errors
     /// * Migration errors during initialization
-    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
-        let path_buf = db_path.as_ref().to_path_buf();
+    pub fn open(spec: StorageSpec) -> Result<Self> {
+        Self::open_with_tuning(spec, &StorageTuning::default())
+    }
 
-        let connection = Connection::open(&path_buf)?;
+    /// Create a provider on the storage described by `spec`, with the given
+    /// SQLite settings on both of its connections
+    ///
+    /// # Errors
+    /// * Database connection errors
+    /// * Migration errors during initialization
+    pub fn open_with_tuning(spec: StorageSpec, tuning: &StorageTuning) -> Result<Self> {
+        let connection = spec.open_connection(tuning)?;
         let mut storage = SqliteStorageProvider::<BincodeCodec, Connection>::new(connection);
 
         // Run migrations to initialize schema
@@ -59,40 +146,53 @@ impl MlsProvider {
             .map_err(|e| ClientError::Config(format!("Migration error: {}", e)))?;
 
         // Initialize metadata tables for group name mapping
-        let conn = Connection::open(&path_buf)?;
+        let conn = spec.open_connection(tuning)?;
         Self::initialize_metadata_tables(&conn)?;
 
         Ok(Self {
             crypto: RustCrypto::default(),
+            #[cfg(any(test, feature = "test_utils"))]
+            rand: crate::test_rng::SeededRand::default(),
             storage,
             conn,
         })
     }
 
+    /// Create a new provider with file-based SQLite storage
+    ///
+    /// # Arguments
+    /// * `db_path` - Path to the SQLite database file
+    ///
+    /// # Errors
+    /// * Database connection errors
+    /// * Migration errors during initialization
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        Self::open(StorageSpec::File(db_path.as_ref().to_path_buf()))
+    }
+
     /// Create a new provider with in-memory SQLite storage (testing only)
     ///
     /// # Errors
     /// * Migration errors during initialization
     pub fn new_in_memory() -> Result<Self> {
-        let connection = Connection::open_in_memory()?;
-        let mut storage = SqliteStorageProvider::<BincodeCodec, Connection>::new(connection);
-
-        // Run migrations to initialize schema
-        storage
-            .run_migrations()
-            .map_err(|e| ClientError::Config(format!("Migration error: {}", e)))?;
-
-        let conn = Connection::open_in_memory()?;
-        Self::initialize_metadata_tables(&conn)?;
+        Self::open(StorageSpec::InMemory)
+    }
 
-        Ok(Self {
-            crypto: RustCrypto::default(),
-            storage,
-            conn,
-        })
+    /// Make the provider's randomness deterministic (tests only)
+    ///
+    /// Everything OpenMLS draws through `OpenMlsProvider::rand()` after this
+    /// call (group ids, key package and leaf HPKE keys, epoch secrets) is
+    /// the same for the same seed. Signature keys are generated outside the
+    /// provider and stay random. Only available in the crate's tests and
+    /// with the `test_utils` feature.
+    #[cfg(any(test, feature = "test_utils"))]
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rand.seed(seed);
     }
 
-    /// Initialize metadata tables for group name mappings
+    /// Initialize metadata tables for group name mappings, group history, sent Welcomes,
+    /// received message sequence numbers, member activity, non-Basic identity credentials,
+    /// group settings and message proofs
     fn initialize_metadata_tables(conn: &Connection) -> Result<()> {
         conn.execute_batch(
             r#"
@@ -101,17 +201,272 @@ impl MlsProvider {
                 group_id BLOB NOT NULL,
                 created_at TEXT NOT NULL
             );
+
+            CREATE TABLE IF NOT EXISTS superseded_group_names (
+                group_name_key TEXT NOT NULL,
+                group_id BLOB NOT NULL,
+                created_at TEXT NOT NULL,
+                superseded_at TEXT NOT NULL,
+                PRIMARY KEY (group_name_key, group_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS group_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                group_id BLOB NOT NULL,
+                sender TEXT NOT NULL,
+                content TEXT NOT NULL,
+                content_type TEXT NOT NULL,
+                recorded_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                message_id TEXT,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                quote_author TEXT,
+                quote_text TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_group_history_group
+                ON group_history(group_id, id);
+
+            CREATE TABLE IF NOT EXISTS sent_welcomes (
+                group_id BLOB NOT NULL,
+                invitee TEXT NOT NULL,
+                epoch INTEGER NOT NULL,
+                welcome_blob TEXT NOT NULL,
+                ratchet_tree_blob TEXT NOT NULL,
+                PRIMARY KEY (group_id, invitee)
+            );
+
+            CREATE TABLE IF NOT EXISTS read_watermarks (
+                group_id BLOB PRIMARY KEY,
+                last_read_id INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS received_messages (
+                group_id BLOB NOT NULL,
+                seq INTEGER NOT NULL,
+                PRIMARY KEY (group_id, seq)
+            );
+
+            CREATE TABLE IF NOT EXISTS member_activity (
+                group_id BLOB NOT NULL,
+                username TEXT NOT NULL,
+                joined_at INTEGER,
+                last_seen INTEGER,
+                PRIMARY KEY (group_id, username)
+            );
+
+            CREATE TABLE IF NOT EXISTS identity_credentials (
+                username TEXT PRIMARY KEY,
+                credential_blob BLOB NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS group_settings (
+                group_id BLOB PRIMARY KEY,
+                settings TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS message_proofs (
+                group_id BLOB NOT NULL,
+                message_id TEXT NOT NULL,
+                sender_identity TEXT NOT NULL,
+                sender_fingerprint TEXT,
+                epoch INTEGER NOT NULL,
+                PRIMARY KEY (group_id, message_id)
+            );
             "#,
         )?;
+
+        // Databases created before disappearing messages lack the expiry column
+        let has_expires_at: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('group_history') WHERE name = 'expires_at'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_expires_at {
+            conn.execute(
+                "ALTER TABLE group_history ADD COLUMN expires_at INTEGER",
+                [],
+            )?;
+        }
+
+        // ... and those created before pinning lack message ids and the pin flag
+        let has_message_id: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('group_history') WHERE name = 'message_id'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_message_id {
+            conn.execute_batch(
+                "ALTER TABLE group_history ADD COLUMN message_id TEXT;
+                 ALTER TABLE group_history ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+        // ... and those created before quoting lack the quote snapshot
+        let has_quote: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('group_history') WHERE name = 'quote_author'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_quote {
+            conn.execute_batch(
+                "ALTER TABLE group_history ADD COLUMN quote_author TEXT;
+                 ALTER TABLE group_history ADD COLUMN quote_text TEXT;",
+            )?;
+        }
+        // A message is recorded once per group, however often it is delivered
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_group_history_message ON group_history(group_id, message_id) WHERE message_id IS NOT NULL",
+            [],
+        )?;
         Ok(())
     }
 
     /// Save a mapping from group name key to group ID
+    ///
+    /// A mapping of the key to another group (e.g. one whose state was lost
+    /// before a group of the same name was created) is not dropped: it is
+    /// kept as superseded, so that `check_integrity` can report both groups
+    /// if the earlier one still has state. Saving the current mapping again
+    /// changes nothing.
     pub fn save_group_name(&self, group_name_key: &str, group_id: &[u8]) -> Result<()> {
-        let created_at = chrono::Utc::now().to_rfc3339();
-        self.conn.execute(
+        let now = chrono::Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        let existing = tx
+            .query_row(
+                "SELECT group_id, created_at FROM group_names WHERE group_name_key = ?1",
+                (group_name_key,),
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+        match existing {
+            Some((existing_id, _)) if existing_id == group_id => return Ok(()),
+            Some((existing_id, created_at)) => {
+                log::warn!(
+                    "Group name {} now maps to a new group; keeping the earlier mapping as superseded",
+                    group_name_key
+                );
+                tx.execute(
+                    "INSERT OR REPLACE INTO superseded_group_names (group_name_key, group_id, created_at, superseded_at) VALUES (?1, ?2, ?3, ?4)",
+                    (group_name_key, existing_id, created_at, &now),
+                )?;
+            }
+            None => {}
+        }
+        // A group mapped again is current, not superseded
+        tx.execute(
+            "DELETE FROM superseded_group_names WHERE group_name_key = ?1 AND group_id = ?2",
+            (group_name_key, group_id),
+        )?;
+        tx.execute(
             "INSERT OR REPLACE INTO group_names (group_name_key, group_id, created_at) VALUES (?1, ?2, ?3)",
-            (group_name_key, group_id, created_at),
+            (group_name_key, group_id, &now),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Every group name mapping, current and superseded
+    ///
+    /// Sorted by name key; for each key the current mapping comes first,
+    /// then the superseded ones from oldest to newest.
+    pub fn list_group_mappings(&self) -> Result<Vec<GroupMapping>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT group_name_key, group_id, created_at, 1 FROM group_names
+             UNION ALL
+             SELECT group_name_key, group_id, created_at, 0 FROM superseded_group_names
+             ORDER BY 1, 4 DESC, 3",
+        )?;
+        let mappings = stmt
+            .query_map([], |row| {
+                Ok(GroupMapping {
+                    group_name_key: row.get(0)?,
+                    group_id: row.get(1)?,
+                    created_at: row.get(2)?,
+                    current: row.get(3)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(mappings)
+    }
+
+    /// Name keys mapped to more than one group that still has state
+    ///
+    /// Superseded mappings whose group has no stored state are not
+    /// conflicts; `repair` prunes them. A conflict whose current mapping
+    /// was removed has an empty `current`. Cheap enough to run at startup,
+    /// since no group is loaded. Always empty for in-memory providers,
+    /// whose group state is not visible here (see `check_integrity`).
+    pub fn group_name_conflicts(&self) -> Result<Vec<GroupNameConflict>> {
+        let Some(stored) = self.stored_group_ids()? else {
+            return Ok(Vec::new());
+        };
+        let mut conflicts: Vec<GroupNameConflict> = Vec::new();
+        for mapping in self.list_group_mappings()? {
+            if mapping.current {
+                conflicts.push(GroupNameConflict {
+                    group_name_key: mapping.group_name_key,
+                    current: mapping.group_id,
+                    superseded: Vec::new(),
+                });
+            } else if stored.contains(&mapping.group_id) {
+                match conflicts.last_mut() {
+                    Some(conflict) if conflict.group_name_key == mapping.group_name_key => {
+                        conflict.superseded.push(mapping.group_id)
+                    }
+                    // The key's current mapping was removed since
+                    _ => conflicts.push(GroupNameConflict {
+                        group_name_key: mapping.group_name_key,
+                        current: Vec::new(),
+                        superseded: vec![mapping.group_id],
+                    }),
+                }
+            }
+        }
+        // Two groups with state are needed for a conflict
+        conflicts.retain(|conflict| {
+            conflict.superseded.len() + usize::from(stored.contains(&conflict.current)) > 1
+        });
+        Ok(conflicts)
+    }
+
+    /// Resolve a name conflict by mapping `group_name_key` to `keep`
+    ///
+    /// `keep` must be the current or a superseded group of that key. All
+    /// superseded mappings of the key are dropped; the state of the groups
+    /// not kept stays in storage and is reported as unreferenced until it is
+    /// mapped under another name with `save_group_name`.
+    ///
+    /// # Errors
+    /// * `ClientError::Config` if `keep` was never mapped to the key
+    pub fn resolve_group_name_conflict(&self, group_name_key: &str, keep: &[u8]) -> Result<()> {
+        let known = self
+            .list_group_mappings()?
+            .into_iter()
+            .any(|mapping| mapping.group_name_key == group_name_key && mapping.group_id == keep);
+        if !known {
+            return Err(ClientError::Config(format!(
+                "{} was never mapped to that group",
+                group_name_key
+            )));
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM superseded_group_names WHERE group_name_key = ?1",
+            (group_name_key,),
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO group_names (group_name_key, group_id, created_at) VALUES (?1, ?2, ?3)",
+            (group_name_key, keep, chrono::Utc::now().to_rfc3339()),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Remove a group name mapping (the group state itself is left untouched)
+    pub fn remove_group_name(&self, group_name_key: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM group_names WHERE group_name_key = ?1",
+            (group_name_key,),
         )?;
         Ok(())
     }
@@ -126,6 +481,16 @@ impl MlsProvider {
         Ok(exists)
     }
 
+    /// Number of distinct groups this user has created or joined
+    pub fn group_count(&self) -> Result<usize> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(DISTINCT group_id) FROM group_names",
+            [],
+            |row| row.get::<_, i64>(0),
+        )?;
+        Ok(count as usize)
+    }
+
     /// Load a group by its name key
     /// Note: This just checks if a group ID mapping exists; the actual group state
     /// is managed by the OpenMLS provider's storage
@@ -140,11 +505,853 @@ impl MlsProvider {
 
         Ok(group_id_opt)
     }
+
+    /// Append an entry to a group's local history
+    ///
+    /// History lives in the per-user database, next to the group state, so it
+    /// is removed together with the user's keys by `MlsConnection::wipe`.
+    /// Entries with an `expires_at` (Unix seconds) disappear once it passes.
+    pub fn append_history(
+        &self,
+        group_id: &[u8],
+        sender: &str,
+        content: &str,
+        content_type: ContentType,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        self.append_message(group_id, None, sender, content, content_type, expires_at)
+    }
+
+    /// Append an entry with the id members know the message by
+    ///
+    /// `message_id` is the `crypto::message_id` of the encrypted message, the
+    /// same for its sender and every receiver; it is how pins refer to it. A
+    /// message already recorded under its id is not recorded again.
+    pub fn append_message(
+        &self,
+        group_id: &[u8],
+        message_id: Option<&str>,
+        sender: &str,
+        content: &str,
+        content_type: ContentType,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        let recorded_at = chrono::Utc::now().timestamp();
+        self.conn.execute(
+            INSERT_HISTORY,
+            (
+                group_id,
+                sender,
+                content,
+                content_type.as_str(),
+                recorded_at,
+                expires_at,
+                message_id,
+                None::<&str>,
+                None::<&str>,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Append many entries to a group's history in one transaction
+    ///
+    /// Used when backfilling, where one transaction per entry dominates the
+    /// cost. Entries keep their order. An entry whose `message_id` is already
+    /// recorded for the group is skipped without failing the rest of the
+    /// batch. Returns how many entries were inserted.
+    pub fn append_messages(&self, group_id: &[u8], entries: &[NewHistoryEntry]) -> Result<usize> {
+        let recorded_at = chrono::Utc::now().timestamp();
+        let tx = self.conn.unchecked_transaction()?;
+        let mut inserted = 0;
+        {
+            let mut stmt = tx.prepare_cached(INSERT_HISTORY)?;
+            for entry in entries {
+                inserted += stmt.execute((
+                    group_id,
+                    &entry.sender,
+                    &entry.content,
+                    entry.content_type.as_str(),
+                    recorded_at,
+                    entry.expires_at,
+                    entry.message_id.as_deref(),
+                    entry.quote.as_ref().map(|quote| quote.author.as_str()),
+                    entry.quote.as_ref().map(|quote| quote.text.as_str()),
+                ))?;
+            }
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    /// Find an unexpired history entry by its message id
+    pub fn find_message(&self, group_id: &[u8], message_id: &str) -> Result<Option<HistoryEntry>> {
+        let now = chrono::Utc::now().timestamp();
+        let entry = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM group_history WHERE group_id = ?1 AND message_id = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+                    HISTORY_COLUMNS
+                ),
+                (group_id, message_id, now),
+                history_entry,
+            )
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// Set or clear the pin flag of an unexpired message
+    ///
+    /// Returns false if the group has no such message (never received, or
+    /// expired). Setting the flag it already has is not an error.
+    pub fn set_pinned(&self, group_id: &[u8], message_id: &str, pinned: bool) -> Result<bool> {
+        let now = chrono::Utc::now().timestamp();
+        let matched = self.conn.execute(
+            "UPDATE group_history SET pinned = ?3 WHERE group_id = ?1 AND message_id = ?2 AND (expires_at IS NULL OR expires_at > ?4)",
+            (group_id, message_id, pinned, now),
+        )?;
+        Ok(matched > 0)
+    }
+
+    /// A group's unexpired pinned messages, oldest first
+    pub fn pinned_messages(&self, group_id: &[u8]) -> Result<Vec<HistoryEntry>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM group_history WHERE group_id = ?1 AND pinned = 1 AND (expires_at IS NULL OR expires_at > ?2) ORDER BY id",
+            HISTORY_COLUMNS
+        ))?;
+        let entries = stmt
+            .query_map((group_id, now), history_entry)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    /// Load a group's unexpired local history, oldest first
+    pub fn load_history(&self, group_id: &[u8]) -> Result<Vec<HistoryEntry>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM group_history WHERE group_id = ?1 AND (expires_at IS NULL OR expires_at > ?2) ORDER BY id",
+            HISTORY_COLUMNS
+        ))?;
+
+        let entries = stmt
+            .query_map((group_id, now), history_entry)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    /// Unexpired history entries matching `search`, newest first, with the
+    /// id of their group
+    ///
+    /// Searches the group `group_id` only if given, every group otherwise;
+    /// `search.group` is ignored here. Returns at most `limit` entries.
+    pub fn search_messages(
+        &self,
+        search: &MessageSearch,
+        group_id: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, HistoryEntry)>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, group_id FROM group_history \
+             WHERE (expires_at IS NULL OR expires_at > ?1) \
+             AND (?2 = '' OR instr(lower(content), lower(?2)) > 0) \
+             AND (?3 IS NULL OR sender = ?3) \
+             AND (?4 IS NULL OR recorded_at >= ?4) \
+             AND (?5 IS NULL OR recorded_at < ?5) \
+             AND (?6 IS NULL OR content_type = ?6) \
+             AND (?7 IS NULL OR group_id = ?7) \
+             ORDER BY id DESC LIMIT ?8",
+            HISTORY_COLUMNS
+        ))?;
+        let hits = stmt
+            .query_map(
+                rusqlite::params![
+                    now,
+                    search.text,
+                    search.sender,
+                    search.since,
+                    search.until,
+                    search
+                        .content_type
+                        .map(|content_type| content_type.as_str()),
+                    group_id,
+                    limit.min(i64::MAX as usize) as i64,
+                ],
+                |row| Ok((row.get::<_, Vec<u8>>(9)?, history_entry(row)?)),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+
+    /// Load one page of a group's unexpired local history, oldest first
+    ///
+    /// The page holds the newest `limit` entries recorded before the `before`
+    /// cursor, or the newest `limit` entries overall when `before` is `None`.
+    /// Pass the returned `HistoryPage::older` as `before` to fetch the page
+    /// preceding this one; it is `None` once the start of history is reached.
+    pub fn load_history_page(
+        &self,
+        group_id: &[u8],
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<HistoryPage> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, id FROM group_history WHERE group_id = ?1 AND (expires_at IS NULL OR expires_at > ?2) AND id < ?3 ORDER BY id DESC LIMIT ?4",
+            HISTORY_COLUMNS
+        ))?;
+
+        // Fetch one extra row to learn whether an older page exists
+        let mut rows = stmt
+            .query_map(
+                (
+                    group_id,
+                    now,
+                    before.unwrap_or(i64::MAX),
+                    limit.saturating_add(1) as i64,
+                ),
+                |row| Ok((history_entry(row)?, row.get::<_, i64>(9)?)),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let has_older = rows.len() > limit;
+        rows.truncate(limit);
+        rows.reverse();
+        let older = if has_older {
+            rows.first().map(|(_, id)| *id)
+        } else {
+            None
+        };
+
+        Ok(HistoryPage {
+            entries: rows.into_iter().map(|(entry, _)| entry).collect(),
+            older,
+        })
+    }
+
+    /// Count chat messages in a group after its read watermark
+    ///
+    /// Only unexpired `ContentType::Text` entries from senders other than
+    /// `own_username` count; notices and the user's own messages never do.
+    pub fn unread_count(&self, group_id: &[u8], own_username: &str) -> Result<usize> {
+        let now = chrono::Utc::now().timestamp();
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM group_history h \
+             WHERE h.group_id = ?1 AND h.content_type = ?2 AND h.sender != ?3 \
+             AND (h.expires_at IS NULL OR h.expires_at > ?4) \
+             AND h.id > COALESCE((SELECT last_read_id FROM read_watermarks WHERE group_id = ?1), 0)",
+            (group_id, ContentType::Text.as_str(), own_username, now),
+            |row| row.get::<_, i64>(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Unread counts per group name (see `unread_count`)
+    pub fn unread_counts(&self, own_username: &str) -> Result<BTreeMap<String, usize>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT group_name_key, group_id FROM group_names")?;
+        let groups = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Group name keys are "<username>:<group name>"
+        groups
+            .into_iter()
+            .map(|(key, group_id)| {
+                let name = key.split_once(':').map_or(key.as_str(), |(_, name)| name);
+                Ok((
+                    name.to_string(),
+                    self.unread_count(&group_id, own_username)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Every local group with its last activity and unread count, by name
+    ///
+    /// Last activity is the time of the newest unexpired history entry of
+    /// any kind, notices included.
+    pub fn group_listings(&self, own_username: &str) -> Result<Vec<GroupListing>> {
+        Ok(self
+            .group_listings_by_id(own_username)?
+            .into_iter()
+            .map(|(_, listing)| listing)
+            .collect())
+    }
+
+    /// `group_listings` with each group's id, in a single query
+    ///
+    /// Unread counts follow `unread_count`.
+    pub fn group_listings_by_id(&self, own_username: &str) -> Result<Vec<(Vec<u8>, GroupListing)>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stmt = self.conn.prepare(
+            "SELECT n.group_name_key, n.group_id, \
+             (SELECT MAX(h.recorded_at) FROM group_history h \
+              WHERE h.group_id = n.group_id AND (h.expires_at IS NULL OR h.expires_at > ?1)), \
+             (SELECT COUNT(*) FROM group_history h \
+              WHERE h.group_id = n.group_id AND h.content_type = ?2 AND h.sender != ?3 \
+              AND (h.expires_at IS NULL OR h.expires_at > ?1) \
+              AND h.id > COALESCE((SELECT last_read_id FROM read_watermarks w WHERE w.group_id = n.group_id), 0)) \
+             FROM group_names n ORDER BY n.group_name_key",
+        )?;
+        let groups = stmt
+            .query_map((now, ContentType::Text.as_str(), own_username), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Group name keys are "<username>:<group name>"
+        Ok(groups
+            .into_iter()
+            .map(|(key, group_id, last_activity, unread)| {
+                let name = key.split_once(':').map_or(key.as_str(), |(_, name)| name);
+                let listing = GroupListing {
+                    name: name.to_string(),
+                    last_activity,
+                    unread: unread as usize,
+                };
+                (group_id, listing)
+            })
+            .collect())
+    }
+
+    /// Move a group's read watermark to its latest history entry
+    ///
+    /// Returns `true` if the watermark advanced. The watermark never moves
+    /// backwards.
+    pub fn mark_all_read(&self, group_id: &[u8]) -> Result<bool> {
+        let changed = self.conn.execute(
+            "INSERT INTO read_watermarks (group_id, last_read_id) \
+             SELECT ?1, MAX(id) FROM group_history WHERE group_id = ?1 HAVING MAX(id) IS NOT NULL \
+             ON CONFLICT(group_id) DO UPDATE SET last_read_id = excluded.last_read_id \
+             WHERE excluded.last_read_id > read_watermarks.last_read_id",
+            (group_id,),
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Delete history entries whose expiry is at or before `now` (Unix seconds)
+    ///
+    /// Returns the number of entries removed.
+    pub fn prune_expired_history(&self, now: i64) -> Result<usize> {
+        let removed = self.conn.execute(
+            "DELETE FROM group_history WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            (now,),
+        )?;
+        Ok(removed)
+    }
+
+    /// Record that the message with server sequence number `seq` was received
+    ///
+    /// Returns `false` if it had been received before, so a message delivered
+    /// both live and by a backfill is only processed once.
+    pub fn mark_message_received(&self, group_id: &[u8], seq: i64) -> Result<bool> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO received_messages (group_id, seq) VALUES (?1, ?2)",
+            (group_id, seq),
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Highest server sequence number received in a group, if any
+    pub fn last_received_seq(&self, group_id: &[u8]) -> Result<Option<i64>> {
+        let seq = self.conn.query_row(
+            "SELECT MAX(seq) FROM received_messages WHERE group_id = ?1",
+            (group_id,),
+            |row| row.get(0),
+        )?;
+        Ok(seq)
+    }
+
+    /// Record what MLS authenticated about the message `message_id`
+    ///
+    /// The first proof recorded for a message is kept.
+    pub fn record_message_proof(
+        &self,
+        group_id: &[u8],
+        message_id: &str,
+        proof: &MessageProof,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO message_proofs (group_id, message_id, sender_identity, sender_fingerprint, epoch) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                group_id,
+                message_id,
+                &proof.sender_identity,
+                &proof.sender_fingerprint,
+                proof.epoch as i64,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Proofs recorded for a group's messages, by message id
+    pub fn message_proofs(&self, group_id: &[u8]) -> Result<HashMap<String, MessageProof>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT message_id, sender_identity, sender_fingerprint, epoch FROM message_proofs WHERE group_id = ?1",
+        )?;
+        let proofs = stmt
+            .query_map((group_id,), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    MessageProof {
+                        sender_identity: row.get(1)?,
+                        sender_fingerprint: row.get(2)?,
+                        epoch: row.get::<_, i64>(3)? as u64,
+                    },
+                ))
+            })?
+            .collect::<std::result::Result<HashMap<_, _>, _>>()?;
+        Ok(proofs)
+    }
+
+    /// Record that `username` joined a group at `joined_at` (Unix seconds)
+    pub fn record_member_joined(
+        &self,
+        group_id: &[u8],
+        username: &str,
+        joined_at: i64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO member_activity (group_id, username, joined_at) VALUES (?1, ?2, ?3) \
+             ON CONFLICT(group_id, username) DO UPDATE SET joined_at = excluded.joined_at",
+            (group_id, username, joined_at),
+        )?;
+        Ok(())
+    }
+
+    /// Record activity of `username` in a group at `seen_at` (Unix seconds)
+    ///
+    /// `last_seen` never moves backwards.
+    pub fn record_member_seen(&self, group_id: &[u8], username: &str, seen_at: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO member_activity (group_id, username, last_seen) VALUES (?1, ?2, ?3) \
+             ON CONFLICT(group_id, username) DO UPDATE SET last_seen = MAX(COALESCE(last_seen, 0), excluded.last_seen)",
+            (group_id, username, seen_at),
+        )?;
+        Ok(())
+    }
+
+    /// Forget the activity of a member who left a group
+    pub fn forget_member(&self, group_id: &[u8], username: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM member_activity WHERE group_id = ?1 AND username = ?2",
+            (group_id, username),
+        )?;
+        Ok(())
+    }
+
+    /// Recorded activity of a group's members, by username
+    ///
+    /// Members nothing was recorded for are not listed.
+    pub fn member_activity(&self, group_id: &[u8]) -> Result<Vec<MemberActivity>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT username, joined_at, last_seen FROM member_activity WHERE group_id = ?1 ORDER BY username",
+        )?;
+        let members = stmt
+            .query_map((group_id,), |row| {
+                Ok(MemberActivity {
+                    username: row.get(0)?,
+                    joined_at: row.get(1)?,
+                    last_seen: row.get(2)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(members)
+    }
+
+    /// Remember the Welcome sent to `invitee`, replacing any earlier one
+    pub fn save_sent_welcome(
+        &self,
+        group_id: &[u8],
+        invitee: &str,
+        welcome: &SentWelcome,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sent_welcomes (group_id, invitee, epoch, welcome_blob, ratchet_tree_blob) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                group_id,
+                invitee,
+                welcome.epoch as i64,
+                &welcome.welcome_blob,
+                &welcome.ratchet_tree_blob,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Load the last Welcome sent to `invitee` in this group
+    pub fn load_sent_welcome(&self, group_id: &[u8], invitee: &str) -> Result<Option<SentWelcome>> {
+        let welcome = self
+            .conn
+            .query_row(
+                "SELECT epoch, welcome_blob, ratchet_tree_blob FROM sent_welcomes WHERE group_id = ?1 AND invitee = ?2",
+                (group_id, invitee),
+                |row| {
+                    Ok(SentWelcome {
+                        epoch: row.get::<_, i64>(0)? as u64,
+                        welcome_blob: row.get(1)?,
+                        ratchet_tree_blob: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(welcome)
+    }
+
+    /// Invites of this group whose Welcome is still stored, by invitee
+    pub fn pending_invites(&self, group_id: &[u8]) -> Result<Vec<PendingInvite>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT invitee, epoch FROM sent_welcomes WHERE group_id = ?1 ORDER BY invitee",
+        )?;
+        let invites = stmt
+            .query_map((group_id,), |row| {
+                Ok(PendingInvite {
+                    invitee: row.get(0)?,
+                    epoch: row.get::<_, i64>(1)? as u64,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(invites)
+    }
+
+    /// Forget the Welcome sent to `invitee`; returns whether one was stored
+    pub fn delete_sent_welcome(&self, group_id: &[u8], invitee: &str) -> Result<bool> {
+        let deleted = self.conn.execute(
+            "DELETE FROM sent_welcomes WHERE group_id = ?1 AND invitee = ?2",
+            (group_id, invitee),
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Persist the credential of an identity that cannot be rebuilt from its username
+    ///
+    /// Basic credentials are derived from the username on load; X.509
+    /// certificates are not reproducible and must be stored.
+    pub fn save_credential(&self, username: &str, credential: &Credential) -> Result<()> {
+        use tls_codec::Serialize as TlsSerialize;
+        let credential_blob = credential
+            .tls_serialize_detached()
+            .map_err(|e| ClientError::Config(format!("Failed to serialize credential: {}", e)))?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO identity_credentials (username, credential_blob) VALUES (?1, ?2)",
+            (username, credential_blob),
+        )?;
+        Ok(())
+    }
+
+    /// Delete a credential stored with `save_credential`
+    ///
+    /// Returns `true` if one was stored.
+    pub fn delete_credential(&self, username: &str) -> Result<bool> {
+        let deleted = self.conn.execute(
+            "DELETE FROM identity_credentials WHERE username = ?1",
+            (username,),
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Load a credential stored with `save_credential`
+    pub fn load_credential(&self, username: &str) -> Result<Option<Credential>> {
+        use tls_codec::Deserialize as TlsDeserialize;
+        let blob = self
+            .conn
+            .query_row(
+                "SELECT credential_blob FROM identity_credentials WHERE username = ?1",
+                (username,),
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()?;
+
+        blob.map(|blob| {
+            Credential::tls_deserialize_exact(blob)
+                .map_err(|e| ClientError::Config(format!("Corrupt stored credential: {}", e)))
+        })
+        .transpose()
+    }
+
+    /// Store the application's settings for a group, replacing earlier ones
+    ///
+    /// The settings are opaque to the client and never leave this device.
+    /// `Value::Null` removes them.
+    ///
+    /// # Errors
+    /// * `StorageError::GroupSettingsTooLarge` if their JSON exceeds
+    ///   `MAX_GROUP_SETTINGS_BYTES`
+    /// * Serialization errors if they are nested too deeply to be loaded again
+    pub fn save_group_settings(&self, group_id: &[u8], settings: &serde_json::Value) -> Result<()> {
+        if settings.is_null() {
+            self.conn.execute(
+                "DELETE FROM group_settings WHERE group_id = ?1",
+                (group_id,),
+            )?;
+            return Ok(());
+        }
+        let json = serde_json::to_string(settings)?;
+        if json.len() > MAX_GROUP_SETTINGS_BYTES {
+            return Err(StorageError::GroupSettingsTooLarge {
+                size: json.len(),
+                max: MAX_GROUP_SETTINGS_BYTES,
+            }
+            .into());
+        }
+        // serde_json limits nesting when parsing but not when writing; refuse
+        // what `load_group_settings` could not read back
+        serde_json::from_str::<serde_json::Value>(&json)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO group_settings (group_id, settings) VALUES (?1, ?2)",
+            (group_id, json),
+        )?;
+        Ok(())
+    }
+
+    /// Load the settings stored with `save_group_settings`
+    pub fn load_group_settings(&self, group_id: &[u8]) -> Result<Option<serde_json::Value>> {
+        let json = self
+            .conn
+            .query_row(
+                "SELECT settings FROM group_settings WHERE group_id = ?1",
+                (group_id,),
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    /// Check that the metadata database accepts a write and returns it,
+    /// leaving it unchanged
+    pub fn check_round_trip(&self) -> Result<()> {
+        crate::storage::check_round_trip(&self.conn)
+    }
+
+    /// Summarize local storage usage: groups, history entries and on-disk size
+    ///
+    /// The size covers the database file and its `-wal`/`-shm`/`-journal`
+    /// companions, which hold all group state, keys and history. An in-memory
+    /// provider reports zero bytes.
+    pub fn stats(&self) -> Result<StorageStats> {
+        let groups = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM group_names", [], |row| {
+                row.get::<_, i64>(0)
+            })? as usize;
+        let messages = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM group_history", [], |row| {
+                row.get::<_, i64>(0)
+            })? as usize;
+
+        // Group name keys are "<username>:<group name>"
+        let mut stmt = self.conn.prepare(
+            "SELECT n.group_name_key, COUNT(h.id) FROM group_names n LEFT JOIN group_history h ON h.group_id = n.group_id GROUP BY n.group_name_key",
+        )?;
+        let per_group_message_counts = stmt
+            .query_map([], |row| {
+                let key: String = row.get(0)?;
+                let count: i64 = row.get(1)?;
+                let name = key.split_once(':').map_or(key.as_str(), |(_, name)| name);
+                Ok((name.to_string(), count as usize))
+            })?
+            .collect::<std::result::Result<_, _>>()?;
+
+        let db_path: String = self.conn.query_row(
+            "SELECT file FROM pragma_database_list WHERE name = 'main'",
+            [],
+            |row| row.get(0),
+        )?;
+        let total_bytes = if db_path.is_empty() {
+            0
+        } else {
+            ["", "-wal", "-shm", "-journal"]
+                .iter()
+                .filter_map(|suffix| std::fs::metadata(format!("{}{}", db_path, suffix)).ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        };
+
+        Ok(StorageStats {
+            groups,
+            messages,
+            total_bytes,
+            per_group_message_counts,
+        })
+    }
+}
+
+impl MlsProvider {
+    /// Check that every group name mapping refers to a group that loads
+    ///
+    /// A crash part-way through writing group state can leave mappings whose
+    /// group no longer loads (the "metadata exists but group not found" case
+    /// when connecting). Mappings are classified as:
+    /// - dangling: no MLS state is stored for the group id at all
+    /// - unloadable: some state is stored, but the group does not load
+    ///
+    /// Stored groups that no mapping refers to are reported as unreferenced,
+    /// and groups sharing a name key as name conflicts (see
+    /// `group_name_conflicts`). An in-memory provider keeps MLS state on a separate connection, so it
+    /// never reports unreferenced groups and only relies on loading.
+    pub fn check_integrity(&self) -> Result<IntegrityReport> {
+        let stored_groups = self.stored_group_ids()?;
+        let mut report = IntegrityReport::default();
+        let mut mapped_groups = BTreeSet::new();
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT group_name_key, group_id FROM group_names ORDER BY group_name_key")?;
+        let mappings = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        for (group_name_key, group_id) in mappings {
+            let loaded = MlsGroup::load(&self.storage, &GroupId::from_slice(&group_id));
+            let has_state = stored_groups
+                .as_ref()
+                .is_some_and(|stored| stored.contains(&group_id));
+            match loaded {
+                Ok(Some(_)) => {}
+                Ok(None) if !has_state => report.dangling_mappings.push(group_name_key),
+                Ok(None) => report.unloadable_mappings.push(group_name_key),
+                Err(e) => {
+                    log::warn!("Group for mapping {} failed to load: {}", group_name_key, e);
+                    report.unloadable_mappings.push(group_name_key);
+                }
+            }
+            mapped_groups.insert(group_id);
+        }
+
+        report.name_conflicts = self.group_name_conflicts()?;
+        for conflict in &report.name_conflicts {
+            mapped_groups.extend(conflict.superseded.iter().cloned());
+        }
+
+        report.unreferenced_groups = stored_groups
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|group_id| !mapped_groups.contains(group_id))
+            .collect();
+        Ok(report)
+    }
+
+    /// Prune dangling group name mappings found by `check_integrity`
+    ///
+    /// Only mappings without any stored group state are removed, along with
+    /// superseded mappings of groups without state. Unloadable and
+    /// unreferenced group state, name conflicts, and all history are left in
+    /// place since they may still be recovered. Returns the report from
+    /// before the repair.
+    pub fn repair(&self) -> Result<IntegrityReport> {
+        let report = self.check_integrity()?;
+        for group_name_key in &report.dangling_mappings {
+            log::warn!("Pruning dangling group mapping {}", group_name_key);
+            self.remove_group_name(group_name_key)?;
+        }
+        if let Some(stored) = self.stored_group_ids()? {
+            for mapping in self.list_group_mappings()? {
+                if !mapping.current && !stored.contains(&mapping.group_id) {
+                    self.conn.execute(
+                        "DELETE FROM superseded_group_names WHERE group_name_key = ?1 AND group_id = ?2",
+                        (&mapping.group_name_key, &mapping.group_id),
+                    )?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Delete everything stored locally for a group
+    ///
+    /// Removes its name mappings, history, read state, received message
+    /// numbers, member activity, sent Welcomes and settings in one
+    /// transaction, then deletes its OpenMLS state through
+    /// `MlsGroup::delete`. Returns false if nothing was stored for the
+    /// group, which is not an error.
+    ///
+    /// The OpenMLS state lives on the storage's own connection, so the two
+    /// steps cannot share a transaction. The metadata goes first: if the
+    /// OpenMLS deletion then fails, the group is left as unreferenced state
+    /// (reported by `check_integrity`) and never as a name that points at a
+    /// lost group, and calling `purge_group` again finishes the job.
+    ///
+    /// # Errors
+    /// * Database errors; a failure in the metadata step changes nothing
+    pub fn purge_group(&self, group_id: &[u8]) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut deleted = 0;
+        for table in GROUP_TABLES {
+            deleted += tx.execute(
+                &format!("DELETE FROM {} WHERE group_id = ?1", table),
+                (group_id,),
+            )?;
+        }
+        tx.commit()?;
+
+        let group_id = GroupId::from_slice(group_id);
+        let loaded = MlsGroup::load(&self.storage, &group_id)
+            .map_err(|e| ClientError::Config(format!("Failed to load group: {}", e)))?;
+        if let Some(mut group) = loaded {
+            group
+                .delete(&self.storage)
+                .map_err(|e| ClientError::Config(format!("Failed to delete group: {}", e)))?;
+            deleted += 1;
+        }
+        Ok(deleted > 0)
+    }
+
+    /// Group ids with rows in the OpenMLS group data table
+    ///
+    /// Returns None when that table is not visible on the metadata connection
+    /// (in-memory providers).
+    fn stored_group_ids(&self) -> Result<Option<BTreeSet<Vec<u8>>>> {
+        let visible: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'openmls_group_data'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !visible {
+            return Ok(None);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT group_id FROM openmls_group_data")?;
+        let keys = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // The storage keys rows by the codec-encoded GroupId
+        let group_ids = keys
+            .iter()
+            .filter_map(|key| {
+                <BincodeCodec as openmls_sqlite_storage::Codec>::from_slice::<GroupId>(key)
+                    .map_err(|e| log::warn!("Skipping undecodable group id in storage: {}", e))
+                    .ok()
+            })
+            .map(|group_id| group_id.as_slice().to_vec())
+            .collect();
+        Ok(Some(group_ids))
+    }
 }
 
 impl OpenMlsProvider for MlsProvider {
     type CryptoProvider = RustCrypto;
+    #[cfg(not(any(test, feature = "test_utils")))]
     type RandProvider = RustCrypto;
+    #[cfg(any(test, feature = "test_utils"))]
+    type RandProvider = crate::test_rng::SeededRand;
     type StorageProvider = SqliteStorageProvider<BincodeCodec, Connection>;
 
     fn storage(&self) -> &Self::StorageProvider {
@@ -155,9 +1362,15 @@ impl OpenMlsProvider for MlsProvider {
         &self.crypto
     }
 
+    #[cfg(not(any(test, feature = "test_utils")))]
     fn rand(&self) -> &Self::RandProvider {
         &self.crypto
     }
+
+    #[cfg(any(test, feature = "test_utils"))]
+    fn rand(&self) -> &Self::RandProvider {
+        &self.rand
+    }
 }
 
 #[cfg(test)]
@@ -178,4 +1391,960 @@ mod tests {
         let _provider = MlsProvider::new(&db_path).unwrap();
         // Provider created successfully with file-based storage
     }
+
+    #[test]
+    fn test_seeded_providers_create_identical_group_ids() {
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let temp_dir = tempdir().unwrap();
+        let create_group = |name: &str, seed: Option<u64>| {
+            let mut provider = MlsProvider::new(temp_dir.path().join(name)).unwrap();
+            if let Some(seed) = seed {
+                provider.set_rng_seed(seed);
+            }
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, "team")
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec()
+        };
+
+        let first = create_group("run-1.db", Some(42));
+        let second = create_group("run-2.db", Some(42));
+        assert_eq!(first, second);
+
+        assert_ne!(first, create_group("run-3.db", Some(43)));
+        assert_ne!(
+            create_group("run-4.db", None),
+            create_group("run-5.db", None)
+        );
+    }
+
+    #[test]
+    fn test_shared_memory_providers_see_the_same_data() {
+        let spec = StorageSpec::SharedMemory("provider-shared-test".to_string());
+        let first = MlsProvider::open(spec.clone()).unwrap();
+        let second = MlsProvider::open(spec).unwrap();
+
+        first.save_group_name("alice:general", b"group-1").unwrap();
+        assert_eq!(
+            second.load_group_by_name("alice:general").unwrap(),
+            Some(b"group-1".to_vec())
+        );
+
+        // A different name is a different database
+        let other = MlsProvider::open(StorageSpec::SharedMemory("provider-other-test".to_string()))
+            .unwrap();
+        assert!(!other.group_exists("alice:general").unwrap());
+    }
+
+    #[test]
+    fn test_group_settings_are_bounded_and_survive_restart() {
+        use serde_json::json;
+
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("settings.db");
+        {
+            let provider = MlsProvider::new(&db_path).unwrap();
+            assert_eq!(provider.load_group_settings(b"group-a").unwrap(), None);
+
+            provider
+                .save_group_settings(b"group-a", &json!({"sound": "chime", "theme": "dark"}))
+                .unwrap();
+            // Overwriting replaces the whole blob
+            provider
+                .save_group_settings(b"group-a", &json!({"theme": "light"}))
+                .unwrap();
+            provider
+                .save_group_settings(b"group-b", &json!({"muted": true}))
+                .unwrap();
+
+            let huge = json!({ "note": "x".repeat(MAX_GROUP_SETTINGS_BYTES) });
+            match provider.save_group_settings(b"group-a", &huge) {
+                Err(ClientError::Storage(StorageError::GroupSettingsTooLarge { max, .. })) => {
+                    assert_eq!(max, MAX_GROUP_SETTINGS_BYTES)
+                }
+                other => panic!("expected GroupSettingsTooLarge, got {:?}", other),
+            }
+            // Small but too deep to be parsed back
+            let mut deep = json!(0);
+            for _ in 0..200 {
+                deep = json!([deep]);
+            }
+            assert!(provider.save_group_settings(b"group-a", &deep).is_err());
+        }
+
+        // Rejected writes left the stored settings as they were
+        let provider = MlsProvider::new(&db_path).unwrap();
+        assert_eq!(
+            provider.load_group_settings(b"group-a").unwrap(),
+            Some(json!({"theme": "light"}))
+        );
+        assert_eq!(
+            provider.load_group_settings(b"group-b").unwrap(),
+            Some(json!({"muted": true}))
+        );
+
+        provider
+            .save_group_settings(b"group-b", &serde_json::Value::Null)
+            .unwrap();
+        assert_eq!(provider.load_group_settings(b"group-b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_plain_in_memory_providers_are_isolated() {
+        let first = MlsProvider::open(StorageSpec::InMemory).unwrap();
+        let second = MlsProvider::open(StorageSpec::InMemory).unwrap();
+
+        first.save_group_name("alice:general", b"group-1").unwrap();
+        assert!(first.group_exists("alice:general").unwrap());
+        assert!(!second.group_exists("alice:general").unwrap());
+    }
+
+    #[test]
+    fn test_history_inserts_with_and_without_tuning() {
+        use crate::storage::SynchronousLevel;
+
+        const MESSAGES: usize = 500;
+        let temp_dir = tempdir().unwrap();
+        // SQLite's own defaults: 2000 KiB of cache and a full sync per commit
+        let untuned = StorageTuning {
+            cache_size_kib: 2000,
+            mmap_size: 0,
+            synchronous: SynchronousLevel::Full,
+        };
+        let tuned = StorageTuning {
+            cache_size_kib: 64 * 1024,
+            mmap_size: 64 * 1024 * 1024,
+            synchronous: SynchronousLevel::Normal,
+        };
+
+        for (name, tuning) in [("untuned", untuned), ("tuned", tuned)] {
+            let spec = StorageSpec::File(temp_dir.path().join(format!("{}.db", name)));
+            let provider = MlsProvider::open_with_tuning(spec, &tuning).unwrap();
+            let pragma = |name: &str| -> i64 {
+                provider
+                    .conn
+                    .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+                    .unwrap()
+            };
+            assert_eq!(pragma("synchronous"), tuning.synchronous.pragma_value());
+            assert_eq!(pragma("cache_size"), -i64::from(tuning.cache_size_kib));
+            assert_eq!(pragma("mmap_size"), tuning.mmap_size as i64);
+            let journal_mode: String = provider
+                .conn
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(journal_mode, "wal");
+
+            for i in 0..MESSAGES {
+                provider
+                    .append_history(
+                        b"group",
+                        "alice",
+                        &format!("message {}", i),
+                        ContentType::Text,
+                        None,
+                    )
+                    .unwrap();
+            }
+            assert_eq!(provider.load_history(b"group").unwrap().len(), MESSAGES);
+        }
+    }
+
+    #[test]
+    fn test_group_history_is_per_group_and_ordered() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+
+        provider
+            .append_history(
+                b"group-a",
+                "alice",
+                "alice added bob",
+                ContentType::System,
+                None,
+            )
+            .unwrap();
+        provider
+            .append_history(b"group-b", "carol", "hello", ContentType::Text, None)
+            .unwrap();
+        provider
+            .append_history(
+                b"group-a",
+                "alice",
+                "alice removed bob",
+                ContentType::System,
+                None,
+            )
+            .unwrap();
+
+        let history = provider.load_history(b"group-a").unwrap();
+        let contents: Vec<&str> = history.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(contents, vec!["alice added bob", "alice removed bob"]);
+        assert!(history
+            .iter()
+            .all(|e| e.content_type == ContentType::System && e.sender == "alice"));
+
+        assert!(provider.load_history(b"group-c").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expired_history_is_hidden_and_pruned() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        let now = chrono::Utc::now().timestamp();
+
+        provider
+            .append_history(b"group-a", "alice", "kept", ContentType::Text, None)
+            .unwrap();
+        provider
+            .append_history(b"group-a", "alice", "old", ContentType::Text, Some(now - 1))
+            .unwrap();
+        provider
+            .append_history(
+                b"group-a",
+                "bob",
+                "fresh",
+                ContentType::Text,
+                Some(now + 60),
+            )
+            .unwrap();
+
+        // Already-expired entries are never shown, even before pruning
+        let history = provider.load_history(b"group-a").unwrap();
+        let contents: Vec<&str> = history.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(contents, vec!["kept", "fresh"]);
+        assert_eq!(history[1].expires_at, Some(now + 60));
+
+        assert_eq!(provider.prune_expired_history(now).unwrap(), 1);
+
+        // Advance the clock past the remaining expiry
+        assert_eq!(provider.prune_expired_history(now + 60).unwrap(), 1);
+        assert_eq!(provider.stats().unwrap().messages, 1);
+        assert_eq!(provider.prune_expired_history(now + 3600).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_search_filters_combine() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let seed = [
+            (
+                b"group-a",
+                "alice",
+                "Lunch at noon?",
+                ContentType::Text,
+                1000,
+            ),
+            (
+                b"group-a",
+                "bob",
+                "lunch sounds good",
+                ContentType::Text,
+                2000,
+            ),
+            (
+                b"group-a",
+                "alice",
+                "alice added carol",
+                ContentType::System,
+                3000,
+            ),
+            (
+                b"group-b",
+                "alice",
+                "no lunch today",
+                ContentType::Text,
+                4000,
+            ),
+            (
+                b"group-b",
+                "carol",
+                "meeting moved",
+                ContentType::Text,
+                5000,
+            ),
+        ];
+        for (group_id, sender, content, content_type, recorded_at) in seed {
+            provider
+                .append_history(group_id, sender, content, content_type, None)
+                .unwrap();
+            provider
+                .conn
+                .execute(
+                    "UPDATE group_history SET recorded_at = ?2 WHERE content = ?1",
+                    (content, recorded_at),
+                )
+                .unwrap();
+        }
+        provider
+            .append_history(
+                b"group-a",
+                "bob",
+                "lunch expired",
+                ContentType::Text,
+                Some(now - 1),
+            )
+            .unwrap();
+
+        let found = |search: MessageSearch, group_id: Option<&[u8]>| -> Vec<String> {
+            provider
+                .search_messages(&search, group_id, 10)
+                .unwrap()
+                .into_iter()
+                .map(|(_, entry)| entry.content)
+                .collect()
+        };
+        let text = |text: &str| MessageSearch {
+            text: text.to_string(),
+            ..MessageSearch::default()
+        };
+
+        // Each filter alone, newest first; the text ignores case
+        assert_eq!(
+            found(text("LUNCH"), None),
+            ["no lunch today", "lunch sounds good", "Lunch at noon?"]
+        );
+        let from_alice = MessageSearch {
+            sender: Some("alice".to_string()),
+            ..MessageSearch::default()
+        };
+        assert_eq!(
+            found(from_alice.clone(), None),
+            ["no lunch today", "alice added carol", "Lunch at noon?"]
+        );
+        let window = MessageSearch {
+            since: Some(2000),
+            until: Some(4000),
+            ..MessageSearch::default()
+        };
+        assert_eq!(
+            found(window, None),
+            ["alice added carol", "lunch sounds good"]
+        );
+        let notices = MessageSearch {
+            content_type: Some(ContentType::System),
+            ..MessageSearch::default()
+        };
+        assert_eq!(found(notices, None), ["alice added carol"]);
+        assert_eq!(
+            found(MessageSearch::default(), Some(&b"group-b"[..])),
+            ["meeting moved", "no lunch today"]
+        );
+
+        // Combined filters must all match
+        let alice_lunch = MessageSearch {
+            text: "lunch".to_string(),
+            ..from_alice
+        };
+        assert_eq!(
+            found(alice_lunch.clone(), None),
+            ["no lunch today", "Lunch at noon?"]
+        );
+        assert_eq!(
+            found(alice_lunch, Some(&b"group-a"[..])),
+            ["Lunch at noon?"]
+        );
+        let recent_lunch = MessageSearch {
+            since: Some(2000),
+            ..text("lunch")
+        };
+        assert_eq!(
+            found(recent_lunch, None),
+            ["no lunch today", "lunch sounds good"]
+        );
+        assert!(found(text("dinner"), None).is_empty());
+
+        // No text and no filters matches every unexpired entry
+        assert_eq!(found(MessageSearch::default(), None).len(), 5);
+        let hits = provider
+            .search_messages(&MessageSearch::default(), None, 2)
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].0, b"group-b".to_vec());
+    }
+
+    #[test]
+    fn test_history_pages_walk_back_without_gaps() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("mls-alice.db")).unwrap();
+        let batch: Vec<NewHistoryEntry> = (0..25)
+            .map(|i| NewHistoryEntry {
+                message_id: Some(format!("m{}", i)),
+                sender: "bob".to_string(),
+                content: format!("message {}", i),
+                content_type: ContentType::Text,
+                expires_at: None,
+                quote: None,
+            })
+            .collect();
+        provider.append_messages(b"group-a", &batch).unwrap();
+        provider
+            .append_message(b"group-b", None, "bob", "other", ContentType::Text, None)
+            .unwrap();
+
+        // Newest page first, each page oldest first
+        let mut pages = Vec::new();
+        let mut before = None;
+        loop {
+            let page = provider.load_history_page(b"group-a", before, 10).unwrap();
+            assert!(page.entries.len() <= 10);
+            pages.push(page.entries);
+            match page.older {
+                Some(cursor) => before = Some(cursor),
+                None => break,
+            }
+        }
+        let sizes: Vec<usize> = pages.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![10, 10, 5]);
+        assert_eq!(pages[0].last().unwrap().content, "message 24");
+
+        let walked: Vec<HistoryEntry> = pages.into_iter().rev().flatten().collect();
+        assert_eq!(walked, provider.load_history(b"group-a").unwrap());
+
+        // A window that covers all history has nothing older
+        let page = provider.load_history_page(b"group-a", None, 25).unwrap();
+        assert_eq!(page.entries.len(), 25);
+        assert_eq!(page.older, None);
+        assert!(provider
+            .load_history_page(b"group-c", None, 10)
+            .unwrap()
+            .entries
+            .is_empty());
+    }
+
+    #[test]
+    fn test_bulk_history_insert_keeps_order_and_skips_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("mls-alice.db")).unwrap();
+        let entry = |i: usize| NewHistoryEntry {
+            message_id: Some(format!("m{}", i)),
+            sender: "bob".to_string(),
+            content: format!("message {}", i),
+            content_type: ContentType::Text,
+            expires_at: None,
+            quote: None,
+        };
+
+        let batch: Vec<NewHistoryEntry> = (0..1000).map(entry).collect();
+        assert_eq!(provider.append_messages(b"group-b", &batch).unwrap(), 1000);
+
+        let history = provider.load_history(b"group-b").unwrap();
+        assert_eq!(history.len(), 1000);
+        assert!(history
+            .iter()
+            .enumerate()
+            .all(|(i, e)| e.content == format!("message {}", i)));
+
+        // Already-recorded ids are skipped; the rest of the batch goes in
+        let mut notice = entry(0);
+        notice.message_id = None;
+        notice.content = "bob joined".to_string();
+        notice.content_type = ContentType::System;
+        let mixed = vec![entry(998), entry(1000), notice.clone(), notice, entry(1000)];
+        assert_eq!(provider.append_messages(b"group-b", &mixed).unwrap(), 3);
+        let tail: Vec<String> = provider.load_history(b"group-b").unwrap()[1000..]
+            .iter()
+            .map(|e| e.content.clone())
+            .collect();
+        assert_eq!(tail, vec!["message 1000", "bob joined", "bob joined"]);
+
+        // Ids are unique per group only
+        assert_eq!(
+            provider.append_messages(b"group-c", &mixed[..1]).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_pins_follow_live_messages() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        let now = chrono::Utc::now().timestamp();
+
+        for (id, content, expires_at) in [
+            ("m1", "first", None),
+            ("m2", "gone", Some(now - 1)),
+            ("m3", "third", None),
+        ] {
+            provider
+                .append_message(
+                    b"group-a",
+                    Some(id),
+                    "alice",
+                    content,
+                    ContentType::Text,
+                    expires_at,
+                )
+                .unwrap();
+        }
+
+        assert!(provider.find_message(b"group-a", "m1").unwrap().is_some());
+        assert!(provider.find_message(b"group-a", "m2").unwrap().is_none());
+        assert!(provider.find_message(b"group-b", "m1").unwrap().is_none());
+
+        // Unknown and expired messages cannot be pinned
+        assert!(!provider.set_pinned(b"group-a", "m2", true).unwrap());
+        assert!(!provider.set_pinned(b"group-a", "nope", true).unwrap());
+
+        assert!(provider.set_pinned(b"group-a", "m3", true).unwrap());
+        assert!(provider.set_pinned(b"group-a", "m1", true).unwrap());
+        assert!(provider.set_pinned(b"group-a", "m1", true).unwrap());
+        let pinned: Vec<String> = provider
+            .pinned_messages(b"group-a")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.content)
+            .collect();
+        assert_eq!(pinned, vec!["first", "third"]);
+        assert!(provider.pinned_messages(b"group-b").unwrap().is_empty());
+
+        assert!(provider.set_pinned(b"group-a", "m1", false).unwrap());
+        assert!(provider.set_pinned(b"group-a", "m1", false).unwrap());
+        let pinned = provider.pinned_messages(b"group-a").unwrap();
+        assert_eq!(pinned.len(), 1);
+        assert_eq!(pinned[0].message_id.as_deref(), Some("m3"));
+        assert!(pinned[0].pinned);
+    }
+
+    #[test]
+    fn test_history_expiry_column_is_added_to_existing_databases() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("mls-alice.db");
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE group_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    group_id BLOB NOT NULL,
+                    sender TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    content_type TEXT NOT NULL,
+                    recorded_at INTEGER NOT NULL
+                );
+                INSERT INTO group_history (group_id, sender, content, content_type, recorded_at)
+                    VALUES (x'01', 'alice', 'alice added bob', 'system', 0);",
+            )
+            .unwrap();
+        }
+
+        let provider = MlsProvider::new(&db_path).unwrap();
+        let history = provider.load_history(&[1]).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].expires_at, None);
+    }
+
+    #[test]
+    fn test_unread_count_follows_the_read_watermark() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("mls-alice.db");
+        let provider = MlsProvider::new(&db_path).unwrap();
+        provider.save_group_name("alice:team", b"group-a").unwrap();
+        provider.save_group_name("alice:quiet", b"group-b").unwrap();
+
+        // Nothing to mark read in an empty group
+        assert!(!provider.mark_all_read(b"group-a").unwrap());
+
+        for (sender, content_type) in [
+            ("bob", ContentType::Text),
+            ("alice", ContentType::Text),
+            ("bob", ContentType::System),
+            ("carol", ContentType::Text),
+        ] {
+            provider
+                .append_history(b"group-a", sender, "hi", content_type, None)
+                .unwrap();
+        }
+        provider
+            .append_history(b"group-b", "bob", "hi", ContentType::Text, None)
+            .unwrap();
+
+        // Own messages and notices are never unread
+        assert_eq!(provider.unread_count(b"group-a", "alice").unwrap(), 2);
+        assert_eq!(provider.unread_count(b"group-b", "alice").unwrap(), 1);
+
+        assert!(provider.mark_all_read(b"group-a").unwrap());
+        assert!(!provider.mark_all_read(b"group-a").unwrap());
+        assert_eq!(provider.unread_count(b"group-a", "alice").unwrap(), 0);
+        assert_eq!(provider.unread_count(b"group-b", "alice").unwrap(), 1);
+
+        provider
+            .append_history(b"group-a", "bob", "again", ContentType::Text, None)
+            .unwrap();
+
+        // The watermark is persisted per group
+        drop(provider);
+        let provider = MlsProvider::new(&db_path).unwrap();
+        let counts = provider.unread_counts("alice").unwrap();
+        assert_eq!(counts["team"], 1);
+        assert_eq!(counts["quiet"], 1);
+    }
+
+    #[test]
+    fn test_group_listings_report_activity_and_unread() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        provider.save_group_name("alice:team", b"group-a").unwrap();
+        provider.save_group_name("alice:quiet", b"group-b").unwrap();
+        provider
+            .append_history(b"group-a", "bob", "hi", ContentType::Text, None)
+            .unwrap();
+        provider
+            .append_history(b"group-a", "alice", "hello", ContentType::Text, None)
+            .unwrap();
+        // Expired entries are not activity
+        provider
+            .append_history(b"group-b", "bob", "gone", ContentType::Text, Some(1))
+            .unwrap();
+
+        let listings = provider.group_listings("alice").unwrap();
+        assert_eq!(listings.len(), 2);
+        assert_eq!(listings[0].name, "quiet");
+        assert_eq!(listings[0].last_activity, None);
+        assert_eq!(listings[0].unread, 0);
+        assert_eq!(listings[1].name, "team");
+        assert!(listings[1].last_activity.is_some());
+        assert_eq!(listings[1].unread, 1);
+
+        // Read messages no longer count
+        provider.mark_all_read(b"group-a").unwrap();
+        let by_id = provider.group_listings_by_id("alice").unwrap();
+        assert_eq!(by_id[1].0, b"group-a");
+        assert_eq!(by_id[1].1.unread, 0);
+        assert_eq!(by_id[1].1.last_activity, listings[1].last_activity);
+    }
+
+    #[test]
+    fn test_received_messages_are_deduplicated_per_group() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        assert_eq!(provider.last_received_seq(b"group-a").unwrap(), None);
+
+        assert!(provider.mark_message_received(b"group-a", 7).unwrap());
+        assert!(provider.mark_message_received(b"group-a", 3).unwrap());
+        assert!(!provider.mark_message_received(b"group-a", 7).unwrap());
+        assert!(provider.mark_message_received(b"group-b", 7).unwrap());
+
+        assert_eq!(provider.last_received_seq(b"group-a").unwrap(), Some(7));
+        assert_eq!(provider.last_received_seq(b"group-c").unwrap(), None);
+    }
+
+    #[test]
+    fn test_stats_counts_groups_history_and_file_size() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("mls-alice.db");
+        let provider = MlsProvider::new(&db_path).unwrap();
+
+        provider
+            .save_group_name("alice:general", b"group-a")
+            .unwrap();
+        provider.save_group_name("alice:quiet", b"group-b").unwrap();
+        for notice in ["alice added bob", "alice added carol", "bob removed carol"] {
+            provider
+                .append_history(b"group-a", "alice", notice, ContentType::System, None)
+                .unwrap();
+        }
+
+        let stats = provider.stats().unwrap();
+        assert_eq!(stats.groups, 2);
+        assert_eq!(stats.messages, 3);
+        assert_eq!(stats.per_group_message_counts["general"], 3);
+        assert_eq!(stats.per_group_message_counts["quiet"], 0);
+
+        let on_disk: u64 = ["", "-wal", "-shm", "-journal"]
+            .iter()
+            .filter_map(|suffix| std::fs::metadata(format!("{}{}", db_path.display(), suffix)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        assert!(stats.total_bytes > 0);
+        assert_eq!(stats.total_bytes, on_disk);
+
+        let in_memory = MlsProvider::new_in_memory().unwrap().stats().unwrap();
+        assert_eq!(in_memory, StorageStats::default());
+    }
+
+    #[test]
+    fn test_reused_group_name_is_kept_and_reported_as_conflict() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("conflicts.db")).unwrap();
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let new_group = |name: &str| {
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, name)
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec()
+        };
+        let first = new_group("team");
+        let second = new_group("team");
+
+        provider.save_group_name("alice:team", &first).unwrap();
+        // Saving the same mapping again is not a conflict
+        provider.save_group_name("alice:team", &first).unwrap();
+        assert!(provider.group_name_conflicts().unwrap().is_empty());
+
+        // A new group under the same name does not clobber the first one
+        provider.save_group_name("alice:team", &second).unwrap();
+        assert_eq!(
+            provider.load_group_by_name("alice:team").unwrap(),
+            Some(second.clone())
+        );
+        let mappings = provider.list_group_mappings().unwrap();
+        let listed: Vec<(&[u8], bool)> = mappings
+            .iter()
+            .map(|mapping| (mapping.group_id.as_slice(), mapping.current))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![(second.as_slice(), true), (first.as_slice(), false)]
+        );
+
+        let expected = GroupNameConflict {
+            group_name_key: "alice:team".to_string(),
+            current: second.clone(),
+            superseded: vec![first.clone()],
+        };
+        assert_eq!(
+            provider.group_name_conflicts().unwrap(),
+            vec![expected.clone()]
+        );
+        let report = provider.check_integrity().unwrap();
+        assert_eq!(report.name_conflicts, vec![expected]);
+        assert!(report.unreferenced_groups.is_empty());
+        // Repair leaves conflicts for the user
+        provider.repair().unwrap();
+        assert_eq!(provider.group_name_conflicts().unwrap().len(), 1);
+
+        // A superseded group without state is no conflict, and is pruned
+        provider.save_group_name("alice:lost", b"gone").unwrap();
+        provider
+            .save_group_name("alice:lost", &new_group("lost"))
+            .unwrap();
+        assert_eq!(provider.group_name_conflicts().unwrap().len(), 1);
+        provider.repair().unwrap();
+        assert!(!provider
+            .list_group_mappings()
+            .unwrap()
+            .iter()
+            .any(|mapping| mapping.group_id == b"gone"));
+
+        // Keeping the first group resolves the conflict; the second stays
+        // stored, unmapped
+        assert!(provider
+            .resolve_group_name_conflict("alice:team", b"unrelated")
+            .is_err());
+        provider
+            .resolve_group_name_conflict("alice:team", &first)
+            .unwrap();
+        assert_eq!(
+            provider.load_group_by_name("alice:team").unwrap(),
+            Some(first)
+        );
+        let report = provider.check_integrity().unwrap();
+        assert!(report.name_conflicts.is_empty());
+        assert_eq!(report.unreferenced_groups, vec![second]);
+    }
+
+    #[test]
+    fn test_integrity_check_and_repair_prune_only_dangling_mappings() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("integrity.db")).unwrap();
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let new_group = |name: &str| {
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, name)
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec()
+        };
+        let healthy = new_group("healthy");
+        let partial = new_group("partial");
+        let unmapped = new_group("unmapped");
+
+        provider.save_group_name("alice:healthy", &healthy).unwrap();
+        provider.save_group_name("alice:partial", &partial).unwrap();
+        provider.save_group_name("alice:ghost", b"missing").unwrap();
+        assert!(provider
+            .check_integrity()
+            .unwrap()
+            .unloadable_mappings
+            .is_empty());
+
+        // Simulate a crash that lost part of a group's state
+        let key =
+            <BincodeCodec as openmls_sqlite_storage::Codec>::to_vec(&GroupId::from_slice(&partial))
+                .unwrap();
+        provider
+            .conn
+            .execute(
+                "DELETE FROM openmls_group_data WHERE group_id = ?1 AND data_type = 'tree'",
+                (key,),
+            )
+            .unwrap();
+
+        let report = provider.check_integrity().unwrap();
+        assert_eq!(report.dangling_mappings, vec!["alice:ghost".to_string()]);
+        assert_eq!(
+            report.unloadable_mappings,
+            vec!["alice:partial".to_string()]
+        );
+        assert_eq!(report.unreferenced_groups, vec![unmapped.clone()]);
+        assert!(!report.is_clean());
+
+        assert_eq!(provider.repair().unwrap(), report);
+        assert!(!provider.group_exists("alice:ghost").unwrap());
+        assert!(provider.group_exists("alice:partial").unwrap());
+        assert!(provider.group_exists("alice:healthy").unwrap());
+
+        let after = provider.check_integrity().unwrap();
+        assert!(after.dangling_mappings.is_empty());
+        assert_eq!(after.unloadable_mappings, report.unloadable_mappings);
+        assert!(
+            crate::crypto::load_group_from_storage(&provider, &GroupId::from_slice(&unmapped))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_purge_group_removes_every_trace() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("purge.db")).unwrap();
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let new_group = |name: &str| {
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, name)
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec()
+        };
+        let doomed = new_group("doomed");
+        let kept = new_group("kept");
+
+        // The doomed group is both superseded under one name and current
+        // under another
+        provider.save_group_name("alice:old", &doomed).unwrap();
+        provider.save_group_name("alice:old", &kept).unwrap();
+        provider.save_group_name("alice:doomed", &doomed).unwrap();
+        for group_id in [&doomed, &kept] {
+            provider
+                .append_history(group_id, "bob", "hi", ContentType::Text, None)
+                .unwrap();
+            provider.mark_all_read(group_id).unwrap();
+            provider.mark_message_received(group_id, 1).unwrap();
+            provider.record_member_joined(group_id, "bob", 100).unwrap();
+            provider
+                .save_sent_welcome(
+                    group_id,
+                    "bob",
+                    &SentWelcome {
+                        epoch: 1,
+                        welcome_blob: "d2VsY29tZQ==".to_string(),
+                        ratchet_tree_blob: "dHJlZQ==".to_string(),
+                    },
+                )
+                .unwrap();
+            provider
+                .save_group_settings(group_id, &serde_json::json!({"muted": true}))
+                .unwrap();
+            provider
+                .record_message_proof(
+                    group_id,
+                    "0123456789abcdef",
+                    &MessageProof {
+                        sender_identity: "bob".to_string(),
+                        sender_fingerprint: None,
+                        epoch: 1,
+                    },
+                )
+                .unwrap();
+        }
+        let rows = |table: &str, group_id: &[u8]| -> i64 {
+            provider
+                .conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE group_id = ?1", table),
+                    (group_id,),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        for table in GROUP_TABLES {
+            assert!(rows(table, &doomed) > 0, "{} has no rows to purge", table);
+        }
+
+        assert!(provider.purge_group(&doomed).unwrap());
+
+        for table in GROUP_TABLES {
+            assert_eq!(rows(table, &doomed), 0, "{} still has rows", table);
+        }
+        let storage_key =
+            <BincodeCodec as openmls_sqlite_storage::Codec>::to_vec(&GroupId::from_slice(&doomed))
+                .unwrap();
+        assert_eq!(rows("openmls_group_data", &storage_key), 0);
+        assert!(
+            crate::crypto::load_group_from_storage(&provider, &GroupId::from_slice(&doomed))
+                .unwrap()
+                .is_none()
+        );
+
+        // The other group is untouched
+        assert_eq!(rows("group_names", &kept), 1);
+        assert_eq!(rows("group_history", &kept), 1);
+        assert_eq!(rows("group_settings", &kept), 1);
+        assert!(
+            crate::crypto::load_group_from_storage(&provider, &GroupId::from_slice(&kept))
+                .unwrap()
+                .is_some()
+        );
+        assert!(provider.check_integrity().unwrap().is_clean());
+
+        // Purging again, or a group that never existed, is a no-op
+        assert!(!provider.purge_group(&doomed).unwrap());
+        assert!(!provider.purge_group(b"unknown").unwrap());
+    }
+
+    #[test]
+    fn test_purge_group_finishes_a_group_left_unreferenced() {
+        let temp_dir = tempdir().unwrap();
+        let provider = MlsProvider::new(temp_dir.path().join("purge.db")).unwrap();
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let group_id =
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, "team")
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec();
+
+        // What an interrupted purge leaves: the metadata is gone and the
+        // OpenMLS state is still stored
+        assert_eq!(
+            provider.check_integrity().unwrap().unreferenced_groups,
+            vec![group_id.clone()]
+        );
+
+        assert!(provider.purge_group(&group_id).unwrap());
+        assert!(provider.check_integrity().unwrap().is_clean());
+        assert!(
+            crate::crypto::load_group_from_storage(&provider, &GroupId::from_slice(&group_id))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_purge_group_in_memory_deletes_openmls_state() {
+        let provider = MlsProvider::new_in_memory().unwrap();
+        let (credential, signer) = crate::crypto::generate_credential_with_key("alice").unwrap();
+        let group_id =
+            crate::crypto::create_group_with_config(&credential, &signer, &provider, "team")
+                .unwrap()
+                .group_id()
+                .as_slice()
+                .to_vec();
+        provider.save_group_name("alice:team", &group_id).unwrap();
+
+        assert!(provider.purge_group(&group_id).unwrap());
+        assert!(!provider.group_exists("alice:team").unwrap());
+        assert!(
+            crate::crypto::load_group_from_storage(&provider, &GroupId::from_slice(&group_id))
+                .unwrap()
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,209 @@
+//! Storage of identity secrets outside the MLS databases
+//!
+//! By default (`IdentityStorage::File`) an identity's signature key is kept
+//! in the per-user OpenMLS provider database, next to the group state, and a
+//! non-Basic credential in the same database. With
+//! `IdentityStorage::Keyring` both are kept in a [`SecretStore`] instead,
+//! normally the OS keyring ([`OsKeyring`], with the `keyring` feature), and
+//! the provider database holds only group state. The metadata store keeps
+//! the public key in either case.
+//!
+//! A keyring that cannot be used is never fatal: `IdentityManager` logs a
+//! warning and keeps the identity in the provider database, as in file mode.
+
+use crate::error::{Result, StorageError};
+use openmls::prelude::Credential;
+use openmls_basic_credential::SignatureKeyPair;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Keyring service name under which identities are stored
+pub const KEYRING_SERVICE: &str = "mls-chat-client";
+
+/// Where an identity's signature key and credential are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdentityStorage {
+    /// In the per-user OpenMLS provider database
+    #[default]
+    File,
+    /// In the OS keyring, or the store given to
+    /// `MlsConnection::set_identity_keyring`
+    Keyring,
+}
+
+/// Backend holding one secret per account, such as the OS keyring
+///
+/// Errors mean the backend is unavailable; callers fall back to file
+/// storage rather than fail.
+pub trait SecretStore: Send + Sync {
+    /// The secret stored for `account`, `None` if there is none
+    fn get(&self, account: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store `secret` for `account`, replacing any previous one
+    fn set(&self, account: &str, secret: &[u8]) -> Result<()>;
+
+    /// Remove the secret of `account`; removing a missing one is not an error
+    fn delete(&self, account: &str) -> Result<()>;
+}
+
+/// The OS keyring: macOS Keychain, Windows Credential Manager or the Secret
+/// Service on Linux
+#[cfg(feature = "keyring")]
+pub struct OsKeyring {
+    service: String,
+}
+
+#[cfg(feature = "keyring")]
+impl OsKeyring {
+    /// Keyring entries under `service`
+    pub fn new(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+        }
+    }
+
+    fn entry(&self, account: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(&self.service, account)
+            .map_err(|e| StorageError::Keyring(e.to_string()).into())
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl SecretStore for OsKeyring {
+    fn get(&self, account: &str) -> Result<Option<Vec<u8>>> {
+        match self.entry(account)?.get_secret() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(StorageError::Keyring(e.to_string()).into()),
+        }
+    }
+
+    fn set(&self, account: &str, secret: &[u8]) -> Result<()> {
+        self.entry(account)?
+            .set_secret(secret)
+            .map_err(|e| StorageError::Keyring(e.to_string()).into())
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        match self.entry(account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(StorageError::Keyring(e.to_string()).into()),
+        }
+    }
+}
+
+/// The keyring `IdentityStorage::Keyring` uses by default
+///
+/// `None` in builds without the `keyring` feature.
+pub fn default_keyring() -> Option<Arc<dyn SecretStore>> {
+    #[cfg(feature = "keyring")]
+    {
+        Some(Arc::new(OsKeyring::new(KEYRING_SERVICE)))
+    }
+    #[cfg(not(feature = "keyring"))]
+    {
+        None
+    }
+}
+
+/// Secret store kept in memory, for tests and embedders with their own
+/// protected storage
+///
+/// Clones share the same secrets. `MemoryKeyring::unavailable` fails every
+/// call, like a locked or missing OS keyring.
+#[derive(Clone, Default)]
+pub struct MemoryKeyring {
+    secrets: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    unavailable: bool,
+}
+
+impl MemoryKeyring {
+    /// A store whose every call fails
+    pub fn unavailable() -> Self {
+        Self {
+            unavailable: true,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a secret is stored for `account`
+    pub fn contains(&self, account: &str) -> bool {
+        self.lock().contains_key(account)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.secrets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn check_available(&self) -> Result<()> {
+        if self.unavailable {
+            return Err(StorageError::Keyring("keyring is unavailable".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+impl SecretStore for MemoryKeyring {
+    fn get(&self, account: &str) -> Result<Option<Vec<u8>>> {
+        self.check_available()?;
+        Ok(self.lock().get(account).cloned())
+    }
+
+    fn set(&self, account: &str, secret: &[u8]) -> Result<()> {
+        self.check_available()?;
+        self.lock().insert(account.to_string(), secret.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        self.check_available()?;
+        self.lock().remove(account);
+        Ok(())
+    }
+}
+
+/// An identity's secrets as kept in a `SecretStore`, as JSON
+#[derive(Deserialize)]
+pub(crate) struct KeyringIdentity {
+    pub(crate) signature_key: SignatureKeyPair,
+    /// TLS-serialized `Credential`
+    credential: Vec<u8>,
+}
+
+/// Borrowed form of `KeyringIdentity` for encoding
+#[derive(Serialize)]
+struct KeyringIdentityRef<'a> {
+    signature_key: &'a SignatureKeyPair,
+    credential: Vec<u8>,
+}
+
+impl KeyringIdentity {
+    /// The secret stored for an identity
+    pub(crate) fn encode(
+        signature_key: &SignatureKeyPair,
+        credential: &Credential,
+    ) -> Result<Vec<u8>> {
+        use tls_codec::Serialize as _;
+
+        let credential = credential.tls_serialize_detached().map_err(|e| {
+            crate::error::MlsError::OpenMls(format!("Failed to serialize credential: {}", e))
+        })?;
+        Ok(serde_json::to_vec(&KeyringIdentityRef {
+            signature_key,
+            credential,
+        })?)
+    }
+
+    /// Parse a secret written by `encode`
+    pub(crate) fn decode(secret: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(secret)?)
+    }
+
+    pub(crate) fn credential(&self) -> Result<Credential> {
+        use tls_codec::Deserialize as _;
+
+        Credential::tls_deserialize_exact(&self.credential)
+            .map_err(|_| crate::error::MlsError::InvalidCredential.into())
+    }
+}
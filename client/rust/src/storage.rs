@@ -7,9 +7,74 @@
 //! The KeyPackage pool metadata tracks lifecycle state and expiry information
 //! for KeyPackages whose actual cryptographic material is stored by OpenMLS.
 
-use crate::error::Result;
+use crate::error::{Result, StorageError};
+use crate::secret_store::SecretStore;
 use rusqlite::Connection;
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
+
+/// SQLite `synchronous` level: how often writes are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynchronousLevel {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl SynchronousLevel {
+    /// Value of `PRAGMA synchronous` for this level
+    pub fn pragma_value(self) -> i64 {
+        match self {
+            SynchronousLevel::Off => 0,
+            SynchronousLevel::Normal => 1,
+            SynchronousLevel::Full => 2,
+            SynchronousLevel::Extra => 3,
+        }
+    }
+}
+
+/// Performance settings for the client's SQLite databases
+///
+/// Applied to every connection when it is opened. File databases always use
+/// WAL journaling, which keeps `SynchronousLevel::Normal` safe against
+/// application crashes; only a power loss may drop the last transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageTuning {
+    /// Page cache size per connection, in KiB (`PRAGMA cache_size`)
+    pub cache_size_kib: u32,
+    /// Bytes of the database file mapped into memory (`PRAGMA mmap_size`);
+    /// 0 disables memory mapping
+    pub mmap_size: u64,
+    /// `PRAGMA synchronous`
+    pub synchronous: SynchronousLevel,
+}
+
+impl Default for StorageTuning {
+    fn default() -> Self {
+        Self {
+            cache_size_kib: 8 * 1024,
+            mmap_size: 0,
+            synchronous: SynchronousLevel::Normal,
+        }
+    }
+}
+
+impl StorageTuning {
+    /// Apply these settings to `conn`
+    ///
+    /// In-memory databases keep their `memory` journal mode.
+    pub(crate) fn apply(&self, conn: &Connection) -> Result<()> {
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        // A negative cache_size is in KiB rather than pages
+        conn.pragma_update(None, "cache_size", -i64::from(self.cache_size_kib))?;
+        // Answers with a row only where memory mapping is supported
+        conn.pragma_update(None, "mmap_size", self.mmap_size as i64)?;
+        conn.pragma_update(None, "synchronous", self.synchronous.pragma_value())?;
+        Ok(())
+    }
+}
 
 /// Metadata for a KeyPackage in the pool
 ///
@@ -43,12 +108,79 @@ pub struct KeyPackageMetadata {
     pub spent_by: Option<String>,
 }
 
+/// What queueing a message does when the outbound queue is full
+///
+/// The choice is applied the same way every time, and any message that does
+/// not end up in the queue is handed back to the caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueOverflowPolicy {
+    /// Evict the oldest queued message to make room for the new one
+    #[default]
+    DropOldest,
+    /// Evict the most recently queued message to make room for the new one
+    DropNewest,
+    /// Refuse the new message and keep the queue as it is
+    RejectNew,
+}
+
+/// Limits of the queue holding messages written while offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundQueueConfig {
+    /// Most messages kept per user; 0 refuses every message
+    pub max_messages: usize,
+    /// What happens to a message queued while `max_messages` are waiting
+    pub overflow: QueueOverflowPolicy,
+}
+
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 500,
+            overflow: QueueOverflowPolicy::default(),
+        }
+    }
+}
+
+/// A message waiting in the outbound queue until the client reconnects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage {
+    /// Position in the queue (increases with every queued message)
+    pub id: i64,
+    /// Group the message is for
+    pub group_id: Vec<u8>,
+    /// Message text
+    pub text: String,
+    /// Unix timestamp when the message was queued
+    pub queued_at: i64,
+}
+
+/// A Welcome kept for the user to accept or decline (see `WelcomePolicy`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagedWelcome {
+    /// Id to pass to `accept_welcome` or `decline_welcome`
+    pub id: i64,
+    /// Member who sent the invitation
+    pub inviter: String,
+    /// Base64 TLS-serialized Welcome, as received
+    pub welcome_blob: String,
+    /// Base64 exported ratchet tree
+    pub ratchet_tree_blob: String,
+    /// Id of the server's stored copy, deleted once the Welcome is accepted
+    /// or declined
+    pub server_id: Option<i64>,
+    /// Unix timestamp when the Welcome was staged
+    pub received_at: i64,
+}
+
 /// Local storage manager for SQLite database
 ///
 /// Stores only application metadata (identities).
 /// MLS group state is persisted transparently by the OpenMlsProvider.
+/// Identity secrets go to the OpenMlsProvider too, or to the keyring set
+/// with `set_identity_keyring`.
 pub struct LocalStore {
     conn: Connection,
+    identity_keyring: Option<Arc<dyn SecretStore>>,
 }
 
 impl LocalStore {
@@ -67,9 +199,18 @@ impl LocalStore {
 
     /// Create a new local store with the given database path
     pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        Self::new_with_tuning(db_path, &StorageTuning::default())
+    }
+
+    /// Create a new local store with the given database path and SQLite settings
+    pub fn new_with_tuning<P: AsRef<Path>>(db_path: P, tuning: &StorageTuning) -> Result<Self> {
         let conn = Connection::open(db_path)?;
+        tuning.apply(&conn)?;
         Self::initialize(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            identity_keyring: None,
+        })
     }
 
     /// Initialize the database schema for application metadata
@@ -103,11 +244,49 @@ impl LocalStore {
                 ON keypackage_pool_metadata(not_after);
             CREATE INDEX IF NOT EXISTS idx_created
                 ON keypackage_pool_metadata(created_at);
+
+            CREATE TABLE IF NOT EXISTS outbound_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                group_id BLOB NOT NULL,
+                text TEXT NOT NULL,
+                queued_at INTEGER NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_outbound_queue_user
+                ON outbound_queue(username, id);
+
+            CREATE TABLE IF NOT EXISTS devices (
+                username TEXT PRIMARY KEY,
+                device_id TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS staged_welcomes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                inviter TEXT NOT NULL,
+                welcome_blob TEXT NOT NULL,
+                ratchet_tree_blob TEXT NOT NULL,
+                server_id INTEGER,
+                received_at INTEGER NOT NULL,
+                UNIQUE (username, welcome_blob)
+            );
             "#,
         )?;
         Ok(())
     }
 
+    /// Keep identity secrets in `keyring` rather than the provider database
+    /// (see `secret_store`); `None` restores the default file storage
+    pub fn set_identity_keyring(&mut self, keyring: Option<Arc<dyn SecretStore>>) {
+        self.identity_keyring = keyring;
+    }
+
+    /// The keyring holding identity secrets, if one is configured
+    pub fn identity_keyring(&self) -> Option<&dyn SecretStore> {
+        self.identity_keyring.as_deref()
+    }
+
     /// Save identity for a username with their public key
     ///
     /// The public key is used to look up the actual signature key in the OpenMLS provider storage.
@@ -136,6 +315,23 @@ impl LocalStore {
         Ok(result)
     }
 
+    /// Id of this device for `username`, created on first use
+    ///
+    /// The id is random and stays the same for as long as this store
+    /// exists, so each device a user runs the client on has its own.
+    pub fn device_id(&self, username: &str) -> Result<String> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO devices (username, device_id) VALUES (?1, ?2)",
+            (username, uuid::Uuid::new_v4().to_string()),
+        )?;
+        let device_id = self.conn.query_row(
+            "SELECT device_id FROM devices WHERE username = ?1",
+            (username,),
+            |row| row.get(0),
+        )?;
+        Ok(device_id)
+    }
+
     // ===== KeyPackage Pool Metadata Methods =====
 
     /// Create a new metadata entry for a KeyPackage
@@ -187,6 +383,21 @@ impl LocalStore {
         Ok(count)
     }
 
+    /// Count KeyPackages per status, for every status present
+    pub fn count_all_by_status(&self) -> Result<BTreeMap<String, usize>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT status, COUNT(*) FROM keypackage_pool_metadata GROUP BY status")?;
+
+        let counts = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+            })?
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(counts)
+    }
+
     /// Get references to expired KeyPackages
     ///
     /// Returns KeyPackage refs where not_after < current_time
@@ -243,6 +454,66 @@ impl LocalStore {
         Ok(())
     }
 
+    /// Mark an unspent KeyPackage as expired right away
+    ///
+    /// Its expiry is moved to the epoch, so the next expiry cleanup deletes
+    /// it. Spent entries are left as they are.
+    pub fn expire_pool_metadata(&self, keypackage_ref: &[u8]) -> Result<()> {
+        self.conn.execute(
+            "UPDATE keypackage_pool_metadata SET status = 'expired', not_after = 0
+             WHERE keypackage_ref = ?1 AND status != 'spent'",
+            (keypackage_ref,),
+        )?;
+        Ok(())
+    }
+
+    /// Get the refs of every KeyPackage tracked in the pool, whatever its status
+    pub fn get_all_pool_refs(&self) -> Result<Vec<Vec<u8>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT keypackage_ref FROM keypackage_pool_metadata")?;
+
+        let refs = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(refs)
+    }
+
+    /// Check that the database accepts a write and returns it, leaving it unchanged
+    pub fn check_round_trip(&self) -> Result<()> {
+        check_round_trip(&self.conn)
+    }
+
+    /// Delete the identity of `username` and the given pool entries
+    ///
+    /// Other users sharing this store are left untouched. Deleted rows are
+    /// overwritten on disk (`secure_delete`) rather than left in free pages.
+    pub fn delete_user_data(&self, username: &str, keypackage_refs: &[Vec<u8>]) -> Result<()> {
+        self.conn.pragma_update(None, "secure_delete", true)?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM identities WHERE username = ?1", (username,))?;
+        tx.execute("DELETE FROM devices WHERE username = ?1", (username,))?;
+        tx.execute(
+            "DELETE FROM outbound_queue WHERE username = ?1",
+            (username,),
+        )?;
+        tx.execute(
+            "DELETE FROM staged_welcomes WHERE username = ?1",
+            (username,),
+        )?;
+        for keypackage_ref in keypackage_refs {
+            tx.execute(
+                "DELETE FROM keypackage_pool_metadata WHERE keypackage_ref = ?1",
+                (keypackage_ref,),
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
     /// Update reservation information for a KeyPackage
     ///
     /// Called when server confirms a reservation
@@ -287,6 +558,273 @@ impl LocalStore {
 
         Ok(())
     }
+
+    /// Queue a message for `group_id` until it can be sent
+    ///
+    /// When `config.max_messages` messages are already waiting, the overflow
+    /// policy decides which message gives way. Returns the messages evicted
+    /// to make room (at most one), so they can be reported to the user.
+    ///
+    /// # Errors
+    /// * `StorageError::OutboundQueueFull` if the queue is full and the
+    ///   policy is `RejectNew`, or if `config.max_messages` is 0
+    pub fn enqueue_outbound(
+        &self,
+        username: &str,
+        group_id: &[u8],
+        text: &str,
+        config: &OutboundQueueConfig,
+    ) -> Result<Vec<QueuedMessage>> {
+        let now = Self::current_timestamp()?;
+        let full = || StorageError::OutboundQueueFull {
+            max_messages: config.max_messages,
+        };
+        if config.max_messages == 0 {
+            return Err(full().into());
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        let queued: usize = tx.query_row(
+            "SELECT COUNT(*) FROM outbound_queue WHERE username = ?1",
+            (username,),
+            |row| row.get(0),
+        )?;
+
+        let mut dropped = Vec::new();
+        if queued >= config.max_messages {
+            let order = match config.overflow {
+                QueueOverflowPolicy::RejectNew => return Err(full().into()),
+                QueueOverflowPolicy::DropOldest => "ASC",
+                QueueOverflowPolicy::DropNewest => "DESC",
+            };
+            // Shrink to one below the limit, in case it was lowered since
+            let excess = queued + 1 - config.max_messages;
+            let mut stmt = tx.prepare(&format!(
+                "SELECT id, group_id, text, queued_at FROM outbound_queue
+                 WHERE username = ?1 ORDER BY id {} LIMIT ?2",
+                order
+            ))?;
+            dropped = stmt
+                .query_map((username, excess as i64), Self::queued_message)?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            drop(stmt);
+            for message in &dropped {
+                tx.execute("DELETE FROM outbound_queue WHERE id = ?1", (message.id,))?;
+            }
+            dropped.sort_by_key(|message| message.id);
+        }
+
+        tx.execute(
+            "INSERT INTO outbound_queue (username, group_id, text, queued_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            (username, group_id, text, now),
+        )?;
+        tx.commit()?;
+
+        Ok(dropped)
+    }
+
+    /// Messages waiting in the outbound queue, oldest first
+    pub fn outbound_queue(&self, username: &str) -> Result<Vec<QueuedMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, group_id, text, queued_at FROM outbound_queue
+             WHERE username = ?1 ORDER BY id",
+        )?;
+        let messages = stmt
+            .query_map((username,), Self::queued_message)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(messages)
+    }
+
+    /// Remove a message from the outbound queue once it was sent
+    pub fn remove_outbound(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM outbound_queue WHERE id = ?1", (id,))?;
+        Ok(())
+    }
+
+    /// Withdraw one of `username`'s queued messages before it is sent
+    ///
+    /// Returns false if the user has no queued message with that id.
+    pub fn cancel_outbound(&self, username: &str, id: i64) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM outbound_queue WHERE id = ?1 AND username = ?2",
+            (id, username),
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Drop `username`'s queued messages for a group along with another change
+    ///
+    /// The messages are deleted in a transaction committed only once `also`
+    /// succeeded, so if it fails the queue is left as it was. Returns the
+    /// number of messages dropped and the result of `also`.
+    pub fn drop_queued_for_group<T>(
+        &self,
+        username: &str,
+        group_id: &[u8],
+        also: impl FnOnce() -> Result<T>,
+    ) -> Result<(usize, T)> {
+        let tx = self.conn.unchecked_transaction()?;
+        let dropped = tx.execute(
+            "DELETE FROM outbound_queue WHERE username = ?1 AND group_id = ?2",
+            (username, group_id),
+        )?;
+        let result = also()?;
+        tx.commit()?;
+        Ok((dropped, result))
+    }
+
+    // ===== Staged Welcomes =====
+
+    /// Keep a Welcome for `username` until they accept or decline it
+    ///
+    /// The same Welcome may arrive live and again from the server's store;
+    /// it is staged once, remembering the stored copy's `server_id`. Returns
+    /// the staged Welcome's id.
+    pub fn stage_welcome(
+        &self,
+        username: &str,
+        inviter: &str,
+        welcome_blob: &str,
+        ratchet_tree_blob: &str,
+        server_id: Option<i64>,
+    ) -> Result<i64> {
+        let now = Self::current_timestamp()?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO staged_welcomes
+             (username, inviter, welcome_blob, ratchet_tree_blob, server_id, received_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                username,
+                inviter,
+                welcome_blob,
+                ratchet_tree_blob,
+                server_id,
+                now,
+            ),
+        )?;
+        tx.execute(
+            "UPDATE staged_welcomes SET server_id = COALESCE(server_id, ?3)
+             WHERE username = ?1 AND welcome_blob = ?2",
+            (username, welcome_blob, server_id),
+        )?;
+        let id = tx.query_row(
+            "SELECT id FROM staged_welcomes WHERE username = ?1 AND welcome_blob = ?2",
+            (username, welcome_blob),
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Welcomes staged for `username`, oldest first
+    pub fn staged_welcomes(&self, username: &str) -> Result<Vec<StagedWelcome>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, inviter, welcome_blob, ratchet_tree_blob, server_id, received_at
+             FROM staged_welcomes WHERE username = ?1 ORDER BY id",
+        )?;
+        let welcomes = stmt
+            .query_map((username,), Self::staged_welcome_from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(welcomes)
+    }
+
+    /// One of `username`'s staged Welcomes, `None` if there is no such id
+    pub fn staged_welcome(&self, username: &str, id: i64) -> Result<Option<StagedWelcome>> {
+        use rusqlite::OptionalExtension;
+        let welcome = self
+            .conn
+            .query_row(
+                "SELECT id, inviter, welcome_blob, ratchet_tree_blob, server_id, received_at
+                 FROM staged_welcomes WHERE id = ?1 AND username = ?2",
+                (id, username),
+                Self::staged_welcome_from_row,
+            )
+            .optional()?;
+        Ok(welcome)
+    }
+
+    /// Forget one of `username`'s staged Welcomes
+    ///
+    /// Returns false if the user has no staged Welcome with that id.
+    pub fn remove_staged_welcome(&self, username: &str, id: i64) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM staged_welcomes WHERE id = ?1 AND username = ?2",
+            (id, username),
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn staged_welcome_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<StagedWelcome> {
+        Ok(StagedWelcome {
+            id: row.get(0)?,
+            inviter: row.get(1)?,
+            welcome_blob: row.get(2)?,
+            ratchet_tree_blob: row.get(3)?,
+            server_id: row.get(4)?,
+            received_at: row.get(5)?,
+        })
+    }
+
+    fn queued_message(row: &rusqlite::Row<'_>) -> rusqlite::Result<QueuedMessage> {
+        Ok(QueuedMessage {
+            id: row.get(0)?,
+            group_id: row.get(1)?,
+            text: row.get(2)?,
+            queued_at: row.get(3)?,
+        })
+    }
+}
+
+/// Overwrite a file with zeros, then unlink it
+///
+/// Best effort against recovery from the file system: copy-on-write and
+/// journaling file systems or SSD wear levelling may still keep old blocks.
+/// A missing file is not an error.
+pub(crate) fn secure_remove_file(path: &Path) -> Result<()> {
+    use std::io::Write;
+
+    let mut file = match std::fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let zeros = [0u8; 8192];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Write a probe row inside a transaction, read it back and roll it back
+///
+/// Used by self-tests: confirms the database is writable without leaving
+/// anything behind.
+pub(crate) fn check_round_trip(conn: &Connection) -> Result<()> {
+    const PROBE: &[u8] = b"mls-chat self-test";
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch("CREATE TABLE self_test_probe (value BLOB NOT NULL)")?;
+    tx.execute("INSERT INTO self_test_probe (value) VALUES (?1)", (PROBE,))?;
+    let read: Vec<u8> = tx.query_row("SELECT value FROM self_test_probe", [], |row| row.get(0))?;
+    tx.rollback()?;
+
+    if read != PROBE {
+        return Err(crate::error::ClientError::Config(
+            "database returned a different value than was written".to_string(),
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -355,4 +893,275 @@ mod tests {
         assert_eq!(alice, b"alice_pubkey");
         assert_eq!(bob, b"bob_pubkey");
     }
+
+    #[test]
+    fn test_delete_user_data_keeps_other_users() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        store.save_identity("alice", b"alice_key").unwrap();
+        store.save_identity("bob", b"bob_key").unwrap();
+        store.create_pool_metadata(b"alice_ref", i64::MAX).unwrap();
+        store.create_pool_metadata(b"bob_ref", i64::MAX).unwrap();
+
+        store
+            .delete_user_data("alice", &[b"alice_ref".to_vec()])
+            .unwrap();
+
+        assert!(store.load_public_key("alice").unwrap().is_none());
+        assert!(store.load_public_key("bob").unwrap().is_some());
+        assert_eq!(
+            store.get_all_pool_refs().unwrap(),
+            vec![b"bob_ref".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_device_id_is_stable_per_store_and_user() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let store = LocalStore::new(&db_path).unwrap();
+        let alice = store.device_id("alice").unwrap();
+        assert_eq!(store.device_id("alice").unwrap(), alice);
+        assert_ne!(store.device_id("bob").unwrap(), alice);
+        drop(store);
+
+        let reopened = LocalStore::new(&db_path).unwrap();
+        assert_eq!(reopened.device_id("alice").unwrap(), alice);
+
+        let other_device = LocalStore::new(temp_dir.path().join("other.db")).unwrap();
+        assert_ne!(other_device.device_id("alice").unwrap(), alice);
+    }
+
+    #[test]
+    fn test_welcome_is_staged_once() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+
+        let live = store
+            .stage_welcome("bob", "alice", "d2VsY29tZQ==", "dHJlZQ==", None)
+            .unwrap();
+        // The server's stored copy of the same Welcome adds its id
+        let stored = store
+            .stage_welcome("bob", "alice", "d2VsY29tZQ==", "dHJlZQ==", Some(7))
+            .unwrap();
+        assert_eq!(stored, live);
+        let staged = store.staged_welcomes("bob").unwrap();
+        assert_eq!(staged.len(), 1);
+        assert_eq!(staged[0].inviter, "alice");
+        assert_eq!(staged[0].server_id, Some(7));
+        assert!(store.staged_welcomes("carol").unwrap().is_empty());
+
+        assert!(store.staged_welcome("carol", live).unwrap().is_none());
+        assert!(!store.remove_staged_welcome("carol", live).unwrap());
+        assert!(store.remove_staged_welcome("bob", live).unwrap());
+        assert!(store.staged_welcome("bob", live).unwrap().is_none());
+    }
+
+    fn fill_queue(store: &LocalStore, config: &OutboundQueueConfig) {
+        for text in ["one", "two", "three"] {
+            assert!(store
+                .enqueue_outbound("alice", b"group", text, config)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    fn queued_texts(store: &LocalStore) -> Vec<String> {
+        store
+            .outbound_queue("alice")
+            .unwrap()
+            .into_iter()
+            .map(|message| message.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_outbound_queue_drop_oldest_at_limit() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig {
+            max_messages: 3,
+            overflow: QueueOverflowPolicy::DropOldest,
+        };
+        fill_queue(&store, &config);
+
+        let dropped = store
+            .enqueue_outbound("alice", b"group", "four", &config)
+            .unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].text, "one");
+        assert_eq!(queued_texts(&store), vec!["two", "three", "four"]);
+    }
+
+    #[test]
+    fn test_outbound_queue_drop_newest_at_limit() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig {
+            max_messages: 3,
+            overflow: QueueOverflowPolicy::DropNewest,
+        };
+        fill_queue(&store, &config);
+
+        let dropped = store
+            .enqueue_outbound("alice", b"group", "four", &config)
+            .unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].text, "three");
+        assert_eq!(queued_texts(&store), vec!["one", "two", "four"]);
+    }
+
+    #[test]
+    fn test_outbound_queue_reject_new_at_limit() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig {
+            max_messages: 3,
+            overflow: QueueOverflowPolicy::RejectNew,
+        };
+        fill_queue(&store, &config);
+
+        let result = store.enqueue_outbound("alice", b"group", "four", &config);
+
+        assert!(matches!(
+            result,
+            Err(crate::error::ClientError::Storage(
+                StorageError::OutboundQueueFull { max_messages: 3 }
+            ))
+        ));
+        assert_eq!(queued_texts(&store), vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_outbound_queue_is_per_user_and_drains_in_order() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig {
+            max_messages: 3,
+            overflow: QueueOverflowPolicy::RejectNew,
+        };
+        fill_queue(&store, &config);
+        store
+            .enqueue_outbound("bob", b"group", "bob's", &config)
+            .unwrap();
+
+        let first = store.outbound_queue("alice").unwrap().remove(0);
+        store.remove_outbound(first.id).unwrap();
+
+        assert_eq!(queued_texts(&store), vec!["two", "three"]);
+        assert_eq!(store.outbound_queue("bob").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_outbound_only_removes_own_messages() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig::default();
+        fill_queue(&store, &config);
+        store
+            .enqueue_outbound("bob", b"group", "bob's", &config)
+            .unwrap();
+        let two = store.outbound_queue("alice").unwrap()[1].id;
+        let bobs = store.outbound_queue("bob").unwrap()[0].id;
+
+        assert!(store.cancel_outbound("alice", two).unwrap());
+        assert!(!store.cancel_outbound("alice", two).unwrap());
+        assert!(!store.cancel_outbound("alice", bobs).unwrap());
+
+        assert_eq!(queued_texts(&store), vec!["one", "three"]);
+        assert_eq!(store.outbound_queue("bob").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dropping_a_groups_queue_waits_for_the_other_change() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+        let config = OutboundQueueConfig::default();
+        fill_queue(&store, &config);
+        store
+            .enqueue_outbound("alice", b"other", "elsewhere", &config)
+            .unwrap();
+        store
+            .enqueue_outbound("bob", b"group", "bob's", &config)
+            .unwrap();
+
+        let failed = store.drop_queued_for_group("alice", b"group", || -> Result<()> {
+            Err(StorageError::NoGroupMembers("purge failed".to_string()).into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(store.outbound_queue("alice").unwrap().len(), 4);
+
+        let (dropped, ()) = store
+            .drop_queued_for_group("alice", b"group", || Ok(()))
+            .unwrap();
+        assert_eq!(dropped, 3);
+        assert_eq!(queued_texts(&store), vec!["elsewhere"]);
+        assert_eq!(store.outbound_queue("bob").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_storage_tuning_is_applied() {
+        let temp_dir = tempdir().unwrap();
+        let journal_mode = |store: &LocalStore| -> String {
+            store
+                .conn
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap()
+        };
+        let pragma = |store: &LocalStore, name: &str| -> i64 {
+            store
+                .conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let store = LocalStore::new(temp_dir.path().join("default.db")).unwrap();
+        assert_eq!(journal_mode(&store), "wal");
+        assert_eq!(pragma(&store, "synchronous"), 1);
+        assert_eq!(pragma(&store, "cache_size"), -8192);
+
+        let tuning = StorageTuning {
+            cache_size_kib: 64 * 1024,
+            mmap_size: 1 << 20,
+            synchronous: SynchronousLevel::Full,
+        };
+        let store = LocalStore::new_with_tuning(temp_dir.path().join("tuned.db"), &tuning).unwrap();
+        assert_eq!(journal_mode(&store), "wal");
+        assert_eq!(pragma(&store, "synchronous"), 2);
+        assert_eq!(pragma(&store, "cache_size"), -65536);
+        assert_eq!(pragma(&store, "mmap_size"), 1 << 20);
+    }
+
+    #[test]
+    fn test_round_trip_check_leaves_no_trace() {
+        let temp_dir = tempdir().unwrap();
+        let store = LocalStore::new(temp_dir.path().join("test.db")).unwrap();
+
+        store.check_round_trip().unwrap();
+        store.check_round_trip().unwrap();
+
+        let probes: i64 = store
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'self_test_probe'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(probes, 0);
+    }
+
+    #[test]
+    fn test_secure_remove_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("secret.db");
+        std::fs::write(&path, vec![0xAB; 20_000]).unwrap();
+
+        secure_remove_file(&path).unwrap();
+        assert!(!path.exists());
+        // Removing a missing file is not an error
+        secure_remove_file(&path).unwrap();
+    }
 }
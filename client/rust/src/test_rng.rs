@@ -0,0 +1,94 @@
+//! Deterministic randomness for reproducible tests
+//!
+//! Only compiled for the crate's own tests or with the `test_utils` feature.
+//! Release builds never contain this module, so an `MlsProvider` there
+//! always draws from the OS-seeded `RustCrypto` generator.
+
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::random::OpenMlsRand;
+use std::sync::Mutex;
+
+/// Randomness source of an `MlsProvider` in test builds
+///
+/// Delegates to the OS-seeded `RustCrypto` generator until a seed is set
+/// with `MlsProvider::set_rng_seed`; from then on it yields the same bytes
+/// for the same seed. The seeded stream is SplitMix64 and is NOT
+/// cryptographically secure.
+#[derive(Default)]
+pub struct SeededRand {
+    os: RustCrypto,
+    state: Mutex<Option<u64>>,
+}
+
+impl SeededRand {
+    /// Switch to the deterministic stream starting at `seed`
+    pub fn seed(&self, seed: u64) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = Some(seed);
+    }
+
+    /// Fill `out` from the seeded stream; returns false if no seed is set
+    fn fill_seeded(&self, out: &mut [u8]) -> bool {
+        let mut guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(state) = guard.as_mut() else {
+            return false;
+        };
+        for chunk in out.chunks_mut(8) {
+            *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = *state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+        true
+    }
+}
+
+impl OpenMlsRand for SeededRand {
+    type Error = <RustCrypto as OpenMlsRand>::Error;
+
+    fn random_array<const N: usize>(&self) -> std::result::Result<[u8; N], Self::Error> {
+        let mut out = [0u8; N];
+        if self.fill_seeded(&mut out) {
+            Ok(out)
+        } else {
+            self.os.random_array()
+        }
+    }
+
+    fn random_vec(&self, len: usize) -> std::result::Result<Vec<u8>, Self::Error> {
+        let mut out = vec![0u8; len];
+        if self.fill_seeded(&mut out) {
+            Ok(out)
+        } else {
+            self.os.random_vec(len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_gives_same_bytes() {
+        let first = SeededRand::default();
+        let second = SeededRand::default();
+        first.seed(7);
+        second.seed(7);
+        let a: [u8; 13] = first.random_array().unwrap();
+        let b: [u8; 13] = second.random_array().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(
+            first.random_vec(40).unwrap(),
+            second.random_vec(40).unwrap()
+        );
+
+        // The stream advances, and another seed gives other bytes
+        let next: [u8; 13] = first.random_array().unwrap();
+        assert_ne!(a, next);
+        let other = SeededRand::default();
+        other.seed(8);
+        assert_ne!(a, other.random_array::<13>().unwrap());
+    }
+}
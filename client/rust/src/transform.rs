@@ -0,0 +1,164 @@
+//! Transforms applied to chat text around encryption
+//!
+//! Embedders register a `MessageTransform` to rewrite the text of outgoing
+//! messages before they are encrypted (e.g. to strip tracking parameters from
+//! links or redact patterns) and of incoming messages after they are
+//! decrypted. Transforms only ever see the text: payload framing
+//! (`payload::encode_text`) is applied after the outgoing transforms and
+//! removed before the incoming ones, so no transform can change the format
+//! descriptor or compression of what is encrypted. Control messages
+//! (receipts, announcements, pins) are not transformed.
+
+use crate::error::Result;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// A rewrite of message text on its way out and on its way in
+///
+/// Both directions default to leaving the text unchanged.
+pub trait MessageTransform: Send + Sync {
+    /// Rewrite the text of a message about to be sent
+    ///
+    /// An error aborts the send: nothing is encrypted or recorded.
+    fn outgoing(&self, text: &str) -> Result<String> {
+        Ok(text.to_string())
+    }
+
+    /// Rewrite the text of a message just decrypted
+    ///
+    /// On error the message is shown and stored as received.
+    fn incoming(&self, text: &str) -> Result<String> {
+        Ok(text.to_string())
+    }
+}
+
+/// The transform that changes nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopTransform;
+
+impl MessageTransform for NoopTransform {}
+
+/// Pipeline of transforms shared by a connection and its memberships
+///
+/// Cloning gives another handle to the same pipeline, so a transform added
+/// later applies to every group. Outgoing text goes through the transforms
+/// in the order they were added, incoming text in reverse order, so a
+/// transform and its inverse compose.
+#[derive(Clone, Default)]
+pub struct MessageTransforms {
+    transforms: Arc<RwLock<Vec<Arc<dyn MessageTransform>>>>,
+}
+
+impl MessageTransforms {
+    /// Append `transform` to the pipeline
+    pub fn add(&self, transform: Arc<dyn MessageTransform>) {
+        self.transforms
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(transform);
+    }
+
+    /// Number of registered transforms
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Whether no transform is registered
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Run outgoing text through every transform
+    ///
+    /// # Errors
+    /// * The first transform error; the message must not be sent
+    pub(crate) fn apply_outgoing(&self, text: &str) -> Result<String> {
+        let mut text = text.to_string();
+        for transform in self.read().iter() {
+            text = transform.outgoing(&text)?;
+        }
+        Ok(text)
+    }
+
+    /// Run incoming text through every transform, last added first
+    ///
+    /// If a transform fails, the text as received is returned so the
+    /// message is still shown.
+    pub(crate) fn apply_incoming(&self, text: &str) -> String {
+        let mut transformed = text.to_string();
+        for transform in self.read().iter().rev() {
+            match transform.incoming(&transformed) {
+                Ok(next) => transformed = next,
+                Err(e) => {
+                    log::warn!("Message transform failed, showing the raw text: {}", e);
+                    return text.to_string();
+                }
+            }
+        }
+        transformed
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Arc<dyn MessageTransform>>> {
+        self.transforms
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ClientError;
+
+    /// Uppercases on send, lowercases on receive
+    struct Shout;
+
+    impl MessageTransform for Shout {
+        fn outgoing(&self, text: &str) -> Result<String> {
+            Ok(text.to_uppercase())
+        }
+
+        fn incoming(&self, text: &str) -> Result<String> {
+            Ok(text.to_lowercase())
+        }
+    }
+
+    /// Appends a marker on receive, or fails on text containing "bad"
+    struct Tag;
+
+    impl MessageTransform for Tag {
+        fn incoming(&self, text: &str) -> Result<String> {
+            if text.contains("bad") {
+                return Err(ClientError::Config("cannot tag".to_string()));
+            }
+            Ok(format!("{} [tagged]", text))
+        }
+    }
+
+    #[test]
+    fn test_empty_pipeline_changes_nothing() {
+        let transforms = MessageTransforms::default();
+        transforms.add(Arc::new(NoopTransform));
+        assert_eq!(transforms.apply_outgoing("Hi there").unwrap(), "Hi there");
+        assert_eq!(transforms.apply_incoming("Hi there"), "Hi there");
+    }
+
+    #[test]
+    fn test_incoming_runs_in_reverse_order() {
+        let transforms = MessageTransforms::default();
+        transforms.add(Arc::new(Shout));
+        transforms.add(Arc::new(Tag));
+        assert_eq!(transforms.apply_outgoing("hi").unwrap(), "HI");
+        // Tag runs first, then Shout lowercases its marker as well
+        assert_eq!(transforms.apply_incoming("HI"), "hi [tagged]");
+    }
+
+    #[test]
+    fn test_failed_incoming_transform_shows_raw_text() {
+        let transforms = MessageTransforms::default();
+        transforms.add(Arc::new(Shout));
+        transforms.add(Arc::new(Tag));
+        // Tag runs first and fails: the text is kept as received
+        assert_eq!(transforms.apply_incoming("Not a bad day"), "Not a bad day");
+        assert_eq!(transforms.apply_incoming("FINE"), "fine [tagged]");
+    }
+}
@@ -1,11 +1,225 @@
 //! WebSocket message handler for real-time communication
 
-use crate::error::Result;
+use crate::error::{MlsError, Result};
+use crate::framing::{Framing, FRAMING_HEADER};
 use crate::models::MlsMessageEnvelope;
+use base64::{engine::general_purpose, Engine as _};
 use futures::{SinkExt, StreamExt};
+use openmls_traits::signatures::Signer;
 use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
+/// State of the WebSocket connection to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// First connection attempt in progress
+    Connecting,
+    /// Connected: messages can be sent and received
+    Connected,
+    /// Connection was lost and a new attempt is in progress
+    Reconnecting,
+    /// Not connected
+    Disconnected,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Disconnected => "disconnected",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Callback told about a state change, with the previous and the new state
+pub type StateCallback = Arc<dyn Fn(ConnectionState, ConnectionState) + Send + Sync>;
+
+/// Handle of a registered `StateCallback`, used to remove it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateCallbackId(u64);
+
+#[derive(Default)]
+struct StateCallbacks {
+    next_id: u64,
+    entries: Vec<(StateCallbackId, StateCallback)>,
+}
+
+/// Connection state shared by a connection and its WebSocket tasks
+///
+/// Clones share the same state. Every change is published to watchers
+/// (`subscribe`) and passed to the registered callbacks. A callback runs
+/// exactly once per actual transition, on the task that made it, and never
+/// while a lock is held, so it may itself read the state or register
+/// callbacks. Setting the state it already has is not a transition.
+#[derive(Clone)]
+pub struct SharedConnectionState {
+    state: watch::Sender<ConnectionState>,
+    callbacks: Arc<Mutex<StateCallbacks>>,
+}
+
+impl SharedConnectionState {
+    /// Create a state starting at `initial`, without callbacks
+    pub fn new(initial: ConnectionState) -> Self {
+        Self {
+            state: watch::Sender::new(initial),
+            callbacks: Arc::default(),
+        }
+    }
+
+    /// Current state
+    pub fn get(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Subscribe to state changes
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Change the state, then run the callbacks if it actually changed
+    pub fn set(&self, state: ConnectionState) {
+        // The swap is atomic, so of two tasks setting the same state only
+        // one sees a transition
+        let previous = self.state.send_replace(state);
+        if previous == state {
+            return;
+        }
+        let callbacks: Vec<StateCallback> = self
+            .callbacks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .iter()
+            .map(|(_, callback)| callback.clone())
+            .collect();
+        for callback in callbacks {
+            callback(previous, state);
+        }
+    }
+
+    /// Register a callback for every later state change
+    pub fn on_change(&self, callback: StateCallback) -> StateCallbackId {
+        let mut callbacks = self.callbacks.lock().unwrap_or_else(|e| e.into_inner());
+        let id = StateCallbackId(callbacks.next_id);
+        callbacks.next_id += 1;
+        callbacks.entries.push((id, callback));
+        id
+    }
+
+    /// Remove a callback; returns false if it was not registered
+    ///
+    /// A change already being reported may still reach it.
+    pub fn remove_callback(&self, id: StateCallbackId) -> bool {
+        let mut callbacks = self.callbacks.lock().unwrap_or_else(|e| e.into_inner());
+        let before = callbacks.entries.len();
+        callbacks.entries.retain(|(entry, _)| *entry != id);
+        callbacks.entries.len() != before
+    }
+}
+
+/// Delays between reconnection attempts after the connection is lost
+///
+/// The first attempt waits `initial_delay`; each failed attempt multiplies
+/// the delay by `multiplier`, up to `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectBackoff {
+    /// Delay before the first attempt
+    pub initial_delay: Duration,
+    /// Longest delay between attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt; 1 keeps it
+    /// constant
+    pub multiplier: u32,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Delay before the next attempt after `failures` failed attempts
+    pub fn delay_after(&self, failures: u32) -> Duration {
+        let factor = self.multiplier.max(1).saturating_pow(failures);
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+/// When to try reconnecting, following a `ReconnectBackoff`
+///
+/// Holds no timer: every method takes the current time, so the caller owns
+/// the clock (and tests can step it without sleeping).
+#[derive(Debug, Clone)]
+pub struct ReconnectSchedule {
+    backoff: ReconnectBackoff,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl ReconnectSchedule {
+    /// Create a schedule with no attempt planned
+    pub fn new(backoff: ReconnectBackoff) -> Self {
+        Self {
+            backoff,
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+
+    /// Follow a connection state change observed at `now`
+    ///
+    /// Connecting resets the backoff; losing the connection plans an attempt
+    /// unless one is already planned.
+    pub fn on_state(&mut self, state: ConnectionState, now: Instant) {
+        match state {
+            ConnectionState::Connected => {
+                self.failures = 0;
+                self.next_attempt = None;
+            }
+            ConnectionState::Disconnected if self.next_attempt.is_none() => {
+                self.next_attempt = Some(now + self.backoff.delay_after(self.failures));
+            }
+            _ => {}
+        }
+    }
+
+    /// Time of the planned attempt, if any
+    pub fn next_attempt(&self) -> Option<Instant> {
+        self.next_attempt
+    }
+
+    /// Whether the planned attempt is due at `now`
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_attempt.is_some_and(|at| at <= now)
+    }
+
+    /// Record that the planned attempt is being made
+    pub fn attempt_started(&mut self) {
+        self.next_attempt = None;
+    }
+
+    /// Record that an attempt failed at `now` and plan the next one
+    pub fn attempt_failed(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        self.next_attempt = Some(now + self.backoff.delay_after(self.failures));
+    }
+}
+
 #[derive(Serialize)]
 struct SubscribeMessage {
     action: String,
@@ -16,11 +230,62 @@ struct SubscribeMessage {
 pub struct MessageHandler {
     sender: futures::channel::mpsc::UnboundedSender<Message>,
     receiver: futures::channel::mpsc::UnboundedReceiver<Message>,
+    /// Task writing outgoing messages to the socket (None for mocks)
+    writer: Option<tokio::task::JoinHandle<()>>,
+    /// Task reading incoming messages (None for mocks), aborted on drop so a
+    /// replaced connection cannot report on the shared state any more
+    reader: Option<tokio::task::JoinHandle<()>>,
+    /// Connection state, set to `Disconnected` when the socket goes away
+    state: SharedConnectionState,
+    /// Framing of outgoing envelopes, as negotiated with the server
+    framing: Framing,
+    /// Sends accepted before the mock acts as closed (see `closing_after`)
+    #[cfg(test)]
+    sends_left: Option<std::sync::atomic::AtomicUsize>,
 }
 
 impl MessageHandler {
     /// Connect to the server WebSocket
-    pub async fn connect(server_url: &str, username: &str) -> Result<Self> {
+    ///
+    /// The connection request is signed with `signer`, the signature key of
+    /// the KeyPackage `username` registered with; the server refuses unsigned
+    /// connections.
+    pub async fn connect(server_url: &str, username: &str, signer: &impl Signer) -> Result<Self> {
+        Self::connect_with_state(
+            server_url,
+            username,
+            signer,
+            SharedConnectionState::new(ConnectionState::Connecting),
+        )
+        .await
+    }
+
+    /// Connect to the server WebSocket, reporting state changes on `state`
+    ///
+    /// `state` is set to `Connected` once the socket is open, and to
+    /// `Disconnected` as soon as the server closes it or a send fails, so
+    /// watchers learn about a lost connection without polling.
+    pub async fn connect_with_state(
+        server_url: &str,
+        username: &str,
+        signer: &impl Signer,
+        state: SharedConnectionState,
+    ) -> Result<Self> {
+        Self::connect_with_framing(server_url, username, signer, state, Framing::Json).await
+    }
+
+    /// Connect to the server WebSocket, asking for `framing`
+    ///
+    /// Binary framing is only used if the server confirms it in its
+    /// handshake response; otherwise envelopes are sent as JSON. See
+    /// `framing()` for what was negotiated.
+    pub async fn connect_with_framing(
+        server_url: &str,
+        username: &str,
+        signer: &impl Signer,
+        state: SharedConnectionState,
+        framing: Framing,
+    ) -> Result<Self> {
         // Extract host and port from HTTP URL
         let url = if let Some(stripped) = server_url.strip_prefix("http://") {
             format!("ws://{}/ws/{}", stripped, username)
@@ -30,24 +295,49 @@ impl MessageHandler {
             format!("ws://{}/ws/{}", server_url, username)
         };
 
-        let (ws_stream, _) = connect_async(&url).await?;
+        let timestamp = chrono::Utc::now().timestamp();
+        let payload = format!("mls-chat-ws-connect:{}:{}", username, timestamp);
+        let signature = signer
+            .sign(payload.as_bytes())
+            .map_err(|e| MlsError::OpenMls(format!("Failed to sign connection: {:?}", e)))?;
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("timestamp", &timestamp.to_string())
+            .append_pair("signature", &general_purpose::STANDARD.encode(signature));
+        if framing != Framing::Json {
+            query.append_pair("framing", framing.as_str());
+        }
+        let url = format!("{}?{}", url, query.finish());
+
+        let (ws_stream, response) = connect_async(&url).await?;
+        let framing = match response.headers().get(FRAMING_HEADER) {
+            Some(value) if value.as_bytes() == Framing::Binary.as_str().as_bytes() => {
+                Framing::Binary
+            }
+            _ => Framing::Json,
+        };
         let (mut write, read) = ws_stream.split();
 
         let (tx, mut rx) = futures::channel::mpsc::unbounded::<Message>();
         let (tx_out, rx_out) = futures::channel::mpsc::unbounded::<Message>();
 
         // Spawn task to handle outgoing messages
-        tokio::spawn(async move {
+        let writer_state = state.clone();
+        let writer = tokio::spawn(async move {
             while let Some(msg) = rx.next().await {
                 if let Err(e) = write.send(msg).await {
                     log::error!("Failed to send WebSocket message: {}", e);
-                    break;
+                    writer_state.set(ConnectionState::Disconnected);
+                    return;
                 }
             }
+            // Sender closed: finish the WebSocket close handshake
+            let _ = write.close().await;
         });
 
         // Spawn task to handle incoming messages
-        tokio::spawn(async move {
+        let reader_state = state.clone();
+        let reader = tokio::spawn(async move {
             let mut read = read;
             while let Some(msg) = read.next().await {
                 if let Ok(msg) = msg {
@@ -57,11 +347,22 @@ impl MessageHandler {
                     }
                 }
             }
+            // Report the loss before `tx_out` is dropped, so readers seeing
+            // the end of the stream also see the new state
+            reader_state.set(ConnectionState::Disconnected);
         });
 
+        state.set(ConnectionState::Connected);
+
         Ok(Self {
             sender: tx,
             receiver: rx_out,
+            writer: Some(writer),
+            reader: Some(reader),
+            state,
+            framing,
+            #[cfg(test)]
+            sends_left: None,
         })
     }
 
@@ -87,9 +388,83 @@ impl MessageHandler {
         Self {
             sender: tx,
             receiver: rx_out,
+            writer: None,
+            reader: None,
+            state: SharedConnectionState::new(ConnectionState::Connected),
+            framing: Framing::Json,
+            #[cfg(test)]
+            sends_left: None,
+        }
+    }
+
+    /// Create a mock WebSocket handler that hands sent messages to the test
+    ///
+    /// Returns the handler and the receiving end of everything sent through
+    /// it, so a test can play the server and route messages itself. Nothing
+    /// is ever received through the handler.
+    #[cfg(test)]
+    pub fn new_capturing_mock() -> (Self, futures::channel::mpsc::UnboundedReceiver<Message>) {
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Message>();
+        let (_tx_out, rx_out) = futures::channel::mpsc::unbounded::<Message>();
+
+        let handler = Self {
+            sender: tx,
+            receiver: rx_out,
+            writer: None,
+            reader: None,
+            state: SharedConnectionState::new(ConnectionState::Connected),
+            framing: Framing::Json,
+            #[cfg(test)]
+            sends_left: None,
+        };
+        (handler, rx)
+    }
+
+    /// Close the mock's connection once `sends` more envelopes were queued
+    ///
+    /// Lets a test fail a send part-way through a sequence of them.
+    #[cfg(test)]
+    pub fn closing_after(mut self, sends: usize) -> Self {
+        self.sends_left = Some(std::sync::atomic::AtomicUsize::new(sends));
+        self
+    }
+
+    /// Create a mock WebSocket handler whose connection is already closed
+    ///
+    /// Every send fails, which lets unit tests exercise the error paths that
+    /// follow a dropped connection.
+    #[cfg(test)]
+    pub fn new_closed_mock() -> Self {
+        let (tx, _rx) = futures::channel::mpsc::unbounded::<Message>();
+        let (_tx_out, rx_out) = futures::channel::mpsc::unbounded::<Message>();
+
+        Self {
+            sender: tx,
+            receiver: rx_out,
+            writer: None,
+            reader: None,
+            state: SharedConnectionState::new(ConnectionState::Disconnected),
+            framing: Framing::Json,
+            #[cfg(test)]
+            sends_left: None,
         }
     }
 
+    /// Current connection state
+    pub fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    /// Framing used for outgoing envelopes
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Subscribe to connection state changes
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
     /// Subscribe to a group
     pub async fn subscribe_to_group(&self, group_id: &str) -> Result<()> {
         let message = SubscribeMessage {
@@ -104,30 +479,195 @@ impl MessageHandler {
         Ok(())
     }
 
+    /// Unsubscribe from a group
+    pub async fn unsubscribe_from_group(&self, group_id: &str) -> Result<()> {
+        let message = SubscribeMessage {
+            action: "unsubscribe".to_string(),
+            group_id: group_id.to_string(),
+        };
+
+        let json = serde_json::to_string(&message)?;
+        let ws_message = Message::Text(json.into());
+
+        self.sender.unbounded_send(ws_message)?;
+        Ok(())
+    }
+
     /// Send an MLS message envelope (application, welcome, or commit)
     pub async fn send_envelope(&self, envelope: &MlsMessageEnvelope) -> Result<()> {
-        let json = serde_json::to_string(envelope)?;
-        let ws_message = Message::Text(json.into());
+        self.queue_envelope(envelope)
+    }
+
+    /// Queue an envelope for sending without yielding
+    ///
+    /// Envelopes leave in the order they were queued. Callers that advance
+    /// MLS state for an envelope queue it in the same synchronous step, so no
+    /// other send can run in between (see `MlsMembership::send_application`).
+    pub fn queue_envelope(&self, envelope: &MlsMessageEnvelope) -> Result<()> {
+        let ws_message = match self.framing {
+            Framing::Json => Message::Text(serde_json::to_string(envelope)?.into()),
+            Framing::Binary => Message::Binary(crate::framing::encode_binary(envelope)?.into()),
+        };
+        #[cfg(test)]
+        if let Some(sends_left) = &self.sends_left {
+            use std::sync::atomic::Ordering;
+            if sends_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_err()
+            {
+                self.sender.close_channel();
+            }
+        }
         self.sender.unbounded_send(ws_message)?;
         Ok(())
     }
 
+    /// Close the connection after flushing queued outgoing messages
+    ///
+    /// Used by one-shot commands that must not exit before their envelopes
+    /// (e.g. Welcome and Commit) reach the server.
+    pub async fn close(mut self) {
+        self.sender.close_channel();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.await;
+        }
+        self.state.set(ConnectionState::Disconnected);
+    }
+
     /// Get the next incoming message envelope (supports discriminated MLS messages)
     ///
     /// Returns type-safe MLS message envelopes that can be pattern-matched to determine
     /// message type (ApplicationMessage, WelcomeMessage, or CommitMessage).
+    ///
+    /// Text frames are parsed as JSON and binary frames as bincode, whatever
+    /// framing was negotiated. Returns `Ok(None)` once the connection is
+    /// closed; ping/pong frames are skipped.
     pub async fn next_envelope(&mut self) -> Result<Option<MlsMessageEnvelope>> {
-        if let Some(msg) = self.receiver.next().await {
+        while let Some(msg) = self.receiver.next().await {
             match msg {
                 Message::Text(text) => {
-                    let incoming: MlsMessageEnvelope = serde_json::from_str(&text)?;
-                    Ok(Some(incoming))
+                    let incoming = crate::envelope::parse_envelope(&text)?;
+                    return Ok(Some(incoming));
+                }
+                Message::Binary(bytes) => {
+                    let incoming = crate::envelope::parse_binary_envelope(&bytes)?;
+                    return Ok(Some(incoming));
                 }
-                Message::Close(_) => Ok(None),
-                _ => Ok(None),
+                Message::Close(_) => return Ok(None),
+                _ => continue,
             }
-        } else {
-            Ok(None)
         }
+        Ok(None)
+    }
+}
+
+impl Drop for MessageHandler {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_backoff_delays_grow_and_are_capped() {
+        let backoff = ReconnectBackoff {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+            multiplier: 3,
+        };
+        let delays: Vec<u64> = (0..5)
+            .map(|failures| backoff.delay_after(failures).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![500, 1500, 3000, 3000, 3000]);
+        // No overflow however many attempts failed
+        assert_eq!(backoff.delay_after(u32::MAX), Duration::from_secs(3));
+
+        let constant = ReconnectBackoff {
+            multiplier: 0,
+            ..ReconnectBackoff::default()
+        };
+        assert_eq!(constant.delay_after(4), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_schedule_follows_an_injected_clock() {
+        let mut schedule = ReconnectSchedule::new(ReconnectBackoff::default());
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        schedule.on_state(ConnectionState::Connected, at(0));
+        assert_eq!(schedule.next_attempt(), None);
+
+        // Lost at t=10: first attempt after 1s
+        schedule.on_state(ConnectionState::Disconnected, at(10));
+        assert_eq!(schedule.next_attempt(), Some(at(11)));
+        assert!(!schedule.is_due(at(10)));
+        assert!(schedule.is_due(at(11)));
+
+        // Two failed attempts double the delay each time; the Disconnected
+        // reported by a failed attempt does not move the planned one
+        schedule.attempt_started();
+        schedule.on_state(ConnectionState::Reconnecting, at(11));
+        schedule.attempt_failed(at(11));
+        schedule.on_state(ConnectionState::Disconnected, at(11));
+        assert_eq!(schedule.next_attempt(), Some(at(13)));
+        schedule.attempt_started();
+        schedule.attempt_failed(at(13));
+        assert_eq!(schedule.next_attempt(), Some(at(17)));
+
+        // Connecting resets the backoff for the next loss
+        schedule.attempt_started();
+        schedule.on_state(ConnectionState::Connected, at(17));
+        assert_eq!(schedule.next_attempt(), None);
+        schedule.on_state(ConnectionState::Disconnected, at(100));
+        assert_eq!(schedule.next_attempt(), Some(at(101)));
+    }
+
+    #[test]
+    fn test_callbacks_run_once_per_transition_outside_the_lock() {
+        let state = SharedConnectionState::new(ConnectionState::Disconnected);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let observed = state.clone();
+        state.on_change(Arc::new(move |previous, current| {
+            // Reading the state or registering from a callback must not
+            // deadlock
+            assert_eq!(observed.get(), current);
+            observed.on_change(Arc::new(|_, _| {}));
+            recorder.lock().unwrap().push((previous, current));
+        }));
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let id = state.on_change(Arc::new(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        state.set(ConnectionState::Connecting);
+        state.set(ConnectionState::Connected);
+        // Setting the current state again is not a transition
+        state.set(ConnectionState::Connected);
+        state.clone().set(ConnectionState::Disconnected);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (ConnectionState::Disconnected, ConnectionState::Connecting),
+                (ConnectionState::Connecting, ConnectionState::Connected),
+                (ConnectionState::Connected, ConnectionState::Disconnected),
+            ]
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        assert!(state.remove_callback(id));
+        assert!(!state.remove_callback(id));
+        state.set(ConnectionState::Connecting);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
     }
 }
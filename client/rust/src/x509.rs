@@ -0,0 +1,380 @@
+//! X.509 support for MLS credentials
+//!
+//! MLS treats X.509 credentials as an opaque certificate chain; OpenMLS does
+//! not parse them. This module covers what the client needs on top:
+//! - building a self-signed Ed25519 certificate whose subject CN is the username
+//! - loading a certificate chain and key issued by an external CA
+//! - reading the subject CN and public key back out of a certificate
+//! - validating a chain against configured trust anchors
+//! - packing a chain into (and out of) an OpenMLS `Credential`
+//!
+//! Certificates are built with `rcgen`, parsed with `x509-cert` and validated
+//! with `rustls-webpki`.
+
+use crate::error::{MlsError, Result};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use openmls::prelude::{Credential, CredentialType, SignatureScheme};
+use openmls_basic_credential::SignatureKeyPair;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, TrustAnchor, UnixTime};
+use std::sync::Arc;
+use tls_codec::{Deserialize as TlsDeserialize, Serialize as TlsSerialize, VLBytes};
+use x509_cert::der::asn1::{Ia5StringRef, PrintableStringRef, Utf8StringRef};
+use x509_cert::der::{Decode, Tag, Tagged};
+
+/// id-at-commonName (2.5.4.3)
+const OID_COMMON_NAME: x509_cert::der::asn1::ObjectIdentifier =
+    x509_cert::der::asn1::ObjectIdentifier::new_unwrap("2.5.4.3");
+
+/// The fields of a certificate the client cares about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// Subject common name, used as the MLS username
+    pub common_name: String,
+    /// Raw subject public key (the MLS signature key for leaf certificates)
+    pub public_key: Vec<u8>,
+}
+
+/// CA certificates that X.509 member credentials must chain to
+///
+/// An empty set accepts any well-formed certificate, which keeps
+/// self-signed credentials usable when no CA is configured.
+#[derive(Debug, Clone, Default)]
+pub struct TrustAnchors {
+    anchors: Arc<Vec<TrustAnchor<'static>>>,
+}
+
+impl TrustAnchors {
+    /// Trust anchors from DER-encoded CA certificates
+    pub fn from_der(certificates: &[Vec<u8>]) -> Result<Self> {
+        let mut anchors = Vec::with_capacity(certificates.len());
+        for certificate in certificates {
+            let der = CertificateDer::from(certificate.as_slice());
+            let anchor = webpki::anchor_from_trusted_cert(&der).map_err(|e| {
+                MlsError::InvalidCertificate(format!("Invalid trust anchor: {}", e))
+            })?;
+            anchors.push(anchor.to_owned());
+        }
+        Ok(Self {
+            anchors: Arc::new(anchors),
+        })
+    }
+
+    /// Trust anchors from a PEM bundle of CA certificates
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        Self::from_der(&certificates_from_pem(pem)?)
+    }
+
+    /// Whether no anchors are configured
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Check that a chain (leaf first) is issued by one of the anchors
+    ///
+    /// Every chain passes when no anchors are configured.
+    pub fn verify_chain(&self, chain: &[Vec<u8>]) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let (leaf, intermediates) = chain
+            .split_first()
+            .ok_or_else(|| MlsError::InvalidCertificate("Empty certificate chain".to_string()))?;
+
+        let leaf = CertificateDer::from(leaf.as_slice());
+        let end_entity = webpki::EndEntityCert::try_from(&leaf).map_err(|e| {
+            MlsError::InvalidCertificate(format!("Invalid leaf certificate: {}", e))
+        })?;
+        let intermediates: Vec<CertificateDer<'_>> = intermediates
+            .iter()
+            .map(|certificate| CertificateDer::from(certificate.as_slice()))
+            .collect();
+
+        end_entity
+            .verify_for_usage(
+                &[webpki::ring::ED25519],
+                &self.anchors,
+                &intermediates,
+                UnixTime::now(),
+                webpki::KeyUsage::client_auth(),
+                None,
+                None,
+            )
+            .map_err(|e| MlsError::InvalidCertificate(format!("Untrusted chain: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Generate an Ed25519 signature key and a self-signed certificate binding
+/// `common_name` to it
+///
+/// The key is generated here because `SignatureKeyPair` does not expose its
+/// private half for signing with another library.
+pub fn self_signed_identity(common_name: &str) -> Result<(Vec<u8>, SignatureKeyPair)> {
+    let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to generate key: {}", e)))?;
+    let signing_key = ed25519_dalek::SigningKey::from_pkcs8_der(&key_pair.serialize_der())
+        .map_err(|e| MlsError::OpenMls(format!("Failed to load generated key: {}", e)))?;
+
+    let mut params = rcgen::CertificateParams::default();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, common_name);
+    let certificate = params
+        .self_signed(&key_pair)
+        .map_err(|e| MlsError::OpenMls(format!("Failed to sign certificate: {}", e)))?;
+
+    Ok((
+        certificate.der().to_vec(),
+        signer_from_signing_key(&signing_key),
+    ))
+}
+
+/// Load a certificate chain and its private key issued by an external CA
+///
+/// `chain_pem` holds the leaf certificate first, optionally followed by
+/// intermediates; `key_pem` holds the leaf's Ed25519 key as PKCS#8. Fails
+/// unless the leaf certifies that key and carries a subject CN.
+pub fn load_issued_identity(
+    chain_pem: &[u8],
+    key_pem: &str,
+) -> Result<(Vec<Vec<u8>>, CertificateInfo, SignatureKeyPair)> {
+    let chain = certificates_from_pem(chain_pem)?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| MlsError::InvalidCertificate("No certificate in chain".to_string()))?;
+    let info = parse_certificate(leaf).ok_or_else(|| {
+        MlsError::InvalidCertificate("Leaf certificate has no usable subject CN".to_string())
+    })?;
+
+    let signing_key = ed25519_dalek::SigningKey::from_pkcs8_pem(key_pem)
+        .map_err(|e| MlsError::InvalidCertificate(format!("Invalid private key: {}", e)))?;
+    let public_key = signing_key.verifying_key().to_bytes().to_vec();
+    if public_key != info.public_key {
+        return Err(MlsError::InvalidCertificate(
+            "Private key does not match the leaf certificate".to_string(),
+        )
+        .into());
+    }
+
+    Ok((chain, info, signer_from_signing_key(&signing_key)))
+}
+
+/// Read the subject CN and public key from a DER certificate
+///
+/// Returns `None` if the certificate is malformed or has no string CN.
+pub fn parse_certificate(der: &[u8]) -> Option<CertificateInfo> {
+    let certificate = x509_cert::Certificate::from_der(der).ok()?;
+    let tbs = &certificate.tbs_certificate;
+
+    let common_name = tbs
+        .subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == OID_COMMON_NAME)
+        .and_then(|attribute| {
+            let value = &attribute.value;
+            match value.tag() {
+                Tag::Utf8String => Utf8StringRef::try_from(value).ok().map(|s| s.to_string()),
+                Tag::PrintableString => PrintableStringRef::try_from(value)
+                    .ok()
+                    .map(|s| s.to_string()),
+                Tag::Ia5String => Ia5StringRef::try_from(value).ok().map(|s| s.to_string()),
+                _ => None,
+            }
+        })?;
+
+    let public_key = tbs
+        .subject_public_key_info
+        .subject_public_key
+        .as_bytes()?
+        .to_vec();
+
+    Some(CertificateInfo {
+        common_name,
+        public_key,
+    })
+}
+
+/// Wrap a certificate chain (leaf first) in an MLS X.509 credential
+pub fn credential_from_chain(chain: &[Vec<u8>]) -> Result<Credential> {
+    let mut content = Vec::new();
+    for certificate in chain {
+        let encoded = VLBytes::from(certificate.clone())
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::OpenMls(format!("Failed to encode certificate: {}", e)))?;
+        content.extend_from_slice(&encoded);
+    }
+    Ok(Credential::new(CredentialType::X509, content))
+}
+
+/// Unpack the certificate chain of an X.509 credential (leaf first)
+pub fn chain_from_credential(credential: &Credential) -> Option<Vec<Vec<u8>>> {
+    if credential.credential_type() != CredentialType::X509 {
+        return None;
+    }
+
+    let mut remaining = credential.serialized_content();
+    let mut chain = Vec::new();
+    while !remaining.is_empty() {
+        let certificate = VLBytes::tls_deserialize(&mut remaining).ok()?;
+        chain.push(certificate.as_slice().to_vec());
+    }
+
+    if chain.is_empty() {
+        None
+    } else {
+        Some(chain)
+    }
+}
+
+/// Decode every CERTIFICATE block of a PEM bundle
+fn certificates_from_pem(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    CertificateDer::pem_slice_iter(pem)
+        .map(|certificate| {
+            certificate
+                .map(|der| der.to_vec())
+                .map_err(|e| MlsError::InvalidCertificate(format!("Invalid PEM: {}", e)).into())
+        })
+        .collect()
+}
+
+/// The MLS signer for an Ed25519 key
+fn signer_from_signing_key(signing_key: &ed25519_dalek::SigningKey) -> SignatureKeyPair {
+    SignatureKeyPair::from_raw(
+        SignatureScheme::ED25519,
+        signing_key.to_bytes().to_vec(),
+        signing_key.verifying_key().to_bytes().to_vec(),
+    )
+}
+
+/// A certificate authority for tests, issuing Ed25519 leaf certificates
+#[cfg(any(test, feature = "test_utils"))]
+pub struct TestCa {
+    key: rcgen::KeyPair,
+    certificate: rcgen::Certificate,
+}
+
+#[cfg(any(test, feature = "test_utils"))]
+impl TestCa {
+    /// A self-signed CA whose subject CN is `common_name`
+    pub fn new(common_name: &str) -> Self {
+        let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let certificate = params.self_signed(&key).unwrap();
+        Self { key, certificate }
+    }
+
+    /// Trust anchors holding only this CA
+    pub fn anchors(&self) -> TrustAnchors {
+        TrustAnchors::from_der(&[self.certificate.der().to_vec()]).unwrap()
+    }
+
+    /// A leaf certificate for `common_name` as PEM, with its PKCS#8 key as PEM
+    pub fn issue(&self, common_name: &str) -> (String, String) {
+        let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let certificate = params
+            .signed_by(&key, &self.certificate, &self.key)
+            .unwrap();
+        (certificate.pem(), key.serialize_pem())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(common_name: &str) -> Vec<u8> {
+        self_signed_identity(common_name).unwrap().0
+    }
+
+    #[test]
+    fn test_self_signed_certificate_roundtrip() {
+        let (certificate, signer) = self_signed_identity("alice").unwrap();
+
+        let info = parse_certificate(&certificate).unwrap();
+        assert_eq!(info.common_name, "alice");
+        assert_eq!(info.public_key, signer.public());
+    }
+
+    #[test]
+    fn test_long_common_name_roundtrip() {
+        let name = "a".repeat(300);
+        let certificate = certificate(&name);
+
+        assert_eq!(parse_certificate(&certificate).unwrap().common_name, name);
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage_and_truncation() {
+        let certificate = certificate("alice");
+
+        assert!(parse_certificate(b"not a certificate").is_none());
+        assert!(parse_certificate(&certificate[..certificate.len() - 10]).is_none());
+    }
+
+    #[test]
+    fn test_credential_chain_roundtrip() {
+        let leaf = certificate("alice");
+        let issuer = certificate("Example CA");
+
+        let credential = credential_from_chain(&[leaf.clone(), issuer.clone()]).unwrap();
+        assert_eq!(credential.credential_type(), CredentialType::X509);
+        assert_eq!(
+            chain_from_credential(&credential).unwrap(),
+            vec![leaf, issuer]
+        );
+    }
+
+    #[test]
+    fn test_issued_identity_loads_and_chains_to_its_ca() {
+        let ca = TestCa::new("Example CA");
+        let (chain_pem, key_pem) = ca.issue("alice");
+        let (chain, info, signer) = load_issued_identity(chain_pem.as_bytes(), &key_pem).unwrap();
+
+        assert_eq!(info.common_name, "alice");
+        assert_eq!(info.public_key, signer.public());
+        ca.anchors().verify_chain(&chain).unwrap();
+    }
+
+    #[test]
+    fn test_anchors_reject_other_issuers() {
+        let ca = TestCa::new("Example CA");
+        let (other_chain_pem, other_key_pem) = TestCa::new("Other CA").issue("mallory");
+        let (other_chain, _, _) =
+            load_issued_identity(other_chain_pem.as_bytes(), &other_key_pem).unwrap();
+        let self_signed = certificate("mallory");
+
+        let anchors = ca.anchors();
+        assert!(anchors.verify_chain(&other_chain).is_err());
+        assert!(anchors
+            .verify_chain(std::slice::from_ref(&self_signed))
+            .is_err());
+
+        // Without anchors any chain is accepted
+        TrustAnchors::default()
+            .verify_chain(&[self_signed])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_issued_identity_requires_the_matching_key() {
+        let ca = TestCa::new("Example CA");
+        let (chain_pem, _) = ca.issue("alice");
+        let (_, other_key_pem) = ca.issue("alice");
+
+        assert!(load_issued_identity(chain_pem.as_bytes(), &other_key_pem).is_err());
+        assert!(load_issued_identity(b"", &other_key_pem).is_err());
+    }
+}
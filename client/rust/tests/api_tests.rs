@@ -2,11 +2,12 @@
 ///
 /// Tests cover user registration, key retrieval, and health checks
 /// using actual HTTP server endpoints via the ServerApi client.
-use mls_chat_client::api::{KeyPackageUpload, ServerApi};
+use mls_chat_client::api::{KeyPackageUpload, ServerApi, ServerApiConfig};
 use mls_chat_client::crypto;
 use tls_codec::Serialize;
 
 use mls_chat_server::db::{self, DbPool};
+use openmls_basic_credential::SignatureKeyPair;
 use openmls_traits::OpenMlsProvider;
 use rusqlite::params;
 
@@ -41,6 +42,32 @@ fn generate_keypackage_upload(username: &str) -> KeyPackageUpload {
     }
 }
 
+/// Register `username` and record them as the creator of `group_id`,
+/// returning the key that signs their requests
+async fn register_group_creator(
+    api: &ServerApi,
+    username: &str,
+    group_id: &[u8],
+) -> SignatureKeyPair {
+    let provider =
+        mls_chat_client::provider::MlsProvider::new_in_memory().expect("Failed to create provider");
+    let (credential, sig_key) =
+        crypto::generate_credential_with_key(username).expect("Failed to generate credential");
+    let bundle = crypto::generate_key_package_bundle(&credential, &sig_key, &provider)
+        .expect("Failed to generate key package bundle");
+    let key_package = bundle
+        .key_package()
+        .tls_serialize_detached()
+        .expect("Failed to serialize key package");
+    api.register_user(username, &key_package)
+        .await
+        .expect("Registration should succeed");
+    api.join_group(username, group_id, &sig_key)
+        .await
+        .expect("Creating the group should succeed");
+    sig_key
+}
+
 async fn spawn_server_with_pool() -> (String, DbPool) {
     use actix_web::web;
 
@@ -93,8 +120,12 @@ async fn test_register_new_user() {
     let alice_key_package = generate_test_key_package("alice");
 
     // Register a new user via HTTP
-    let result = api.register_user("alice", &alice_key_package).await;
-    assert!(result.is_ok(), "User registration should succeed");
+    let registered = api
+        .register_user("alice", &alice_key_package)
+        .await
+        .expect("User registration should succeed");
+    assert_eq!(registered.username, "alice");
+    assert!(registered.id > 0, "Server should assign a user id");
 
     // Verify by retrieving the user's key
     let retrieved_key = api
@@ -136,6 +167,68 @@ async fn test_register_duplicate_user() {
     );
 }
 
+#[tokio::test]
+async fn test_join_group_reports_group_limit() {
+    let (server, addr) = mls_chat_server::server::create_test_http_server_with_config(
+        mls_chat_server::handlers::ServerConfig {
+            max_groups_per_user: 1,
+            ..Default::default()
+        },
+    )
+    .expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let api = ServerApi::new(&format!("http://{}", addr));
+    let alice = register_group_creator(&api, "alice", b"group-1").await;
+    api.join_group("alice", b"group-1", &alice)
+        .await
+        .expect("Joining the same group again should succeed");
+
+    let err = api
+        .join_group("alice", b"group-2", &alice)
+        .await
+        .expect_err("Joining beyond the limit should fail");
+    assert!(matches!(
+        err,
+        mls_chat_client::error::ClientError::Mls(
+            mls_chat_client::error::MlsError::GroupLimitReached { limit: 1, .. }
+        )
+    ));
+    assert!(err.to_string().contains("group limit reached"));
+}
+
+#[tokio::test]
+async fn test_reregistration_returns_same_id() {
+    let (server, addr) =
+        mls_chat_server::server::create_test_http_server().expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let api = ServerApi::new(&format!("http://{}", addr));
+    let alice_key_package = generate_test_key_package("alice");
+    let bob_key_package = generate_test_key_package("bob");
+
+    let first = api
+        .register_user("alice", &alice_key_package)
+        .await
+        .expect("First registration should succeed");
+    let bob = api
+        .register_user("bob", &bob_key_package)
+        .await
+        .expect("Bob's registration should succeed");
+    let again = api
+        .register_user("alice", &alice_key_package)
+        .await
+        .expect("Re-registration with the same key package should succeed");
+
+    assert_eq!(
+        again, first,
+        "Re-registration should return the existing user"
+    );
+    assert_ne!(bob.id, first.id);
+}
+
 #[tokio::test]
 async fn test_get_user_key() {
     // Spawn a test HTTP server and run it in background
@@ -283,8 +376,9 @@ async fn test_reserve_and_spend_keypackage_flow() {
         .expect("Upload should succeed");
 
     let group_id = vec![0x10, 0x20, 0x30, 0x40];
+    let inviter = register_group_creator(&api, "inviter", &group_id).await;
     let reservation = api
-        .reserve_key_package("invitee", &group_id, "inviter")
+        .reserve_key_package("invitee", &group_id, "inviter", &inviter)
         .await
         .expect("Reservation should succeed");
 
@@ -309,6 +403,99 @@ async fn test_reserve_and_spend_keypackage_flow() {
     assert_eq!(status_after_spend.spent, 1);
 }
 
+#[tokio::test]
+async fn test_reserve_then_release_vs_reserve_then_spend() {
+    let (addr, _pool) = spawn_server_with_pool().await;
+    let api = ServerApi::new(&format!("http://{}", addr));
+
+    let uploads: Vec<KeyPackageUpload> = (0..2)
+        .map(|_| generate_keypackage_upload("release-user"))
+        .collect();
+    api.upload_key_packages("release-user", &uploads)
+        .await
+        .expect("Upload should succeed");
+    let group_id = vec![0x0a, 0x0b];
+    let inviter = register_group_creator(&api, "inviter", &group_id).await;
+
+    // Reserve then release: the KeyPackage is reservable again
+    let released = api
+        .reserve_key_package("release-user", &group_id, "inviter", &inviter)
+        .await
+        .expect("Reservation should succeed");
+    assert_eq!(api.available_keypackages("release-user").await.unwrap(), 1);
+    assert!(api
+        .release_reservation(&released.reservation_id)
+        .await
+        .expect("Release should succeed"));
+    assert_eq!(api.available_keypackages("release-user").await.unwrap(), 2);
+
+    // Releasing twice is a no-op
+    assert!(!api
+        .release_reservation(&released.reservation_id)
+        .await
+        .expect("Second release should succeed"));
+
+    // Reserve then spend: a later release is a no-op and the key stays spent
+    let spent = api
+        .reserve_key_package("release-user", &group_id, "inviter", &inviter)
+        .await
+        .expect("Reservation should succeed");
+    api.spend_reservation(&spent, &group_id, "inviter")
+        .await
+        .expect("Spending should succeed");
+    assert!(!api
+        .release_reservation(&spent.reservation_id)
+        .await
+        .expect("Releasing a spent reservation should succeed"));
+
+    let status = api
+        .get_key_package_status("release-user")
+        .await
+        .expect("Status should be queryable");
+    assert_eq!(status.spent, 1);
+    assert_eq!(status.reserved, 0);
+    assert_eq!(status.available, 1);
+}
+
+#[tokio::test]
+async fn test_list_keypackages_reports_statuses() {
+    let (addr, _pool) = spawn_server_with_pool().await;
+    let api = ServerApi::new(&format!("http://{}", addr));
+
+    assert!(api.list_keypackages("list-user").await.unwrap().is_empty());
+
+    let uploads: Vec<KeyPackageUpload> = (0..3)
+        .map(|_| generate_keypackage_upload("list-user"))
+        .collect();
+    api.upload_key_packages("list-user", &uploads)
+        .await
+        .expect("Upload should succeed");
+    let inviter = register_group_creator(&api, "inviter", &[0x0c]).await;
+    let reserved = api
+        .reserve_key_package("list-user", &[0x0c], "inviter", &inviter)
+        .await
+        .expect("Reservation should succeed");
+
+    let listed = api
+        .list_keypackages("list-user")
+        .await
+        .expect("Listing should succeed");
+    assert_eq!(listed.len(), 3);
+    for upload in &uploads {
+        let summary = listed
+            .iter()
+            .find(|summary| summary.keypackage_ref == upload.keypackage_ref)
+            .expect("Every uploaded ref is listed");
+        let expected = if upload.keypackage_ref == reserved.keypackage_ref {
+            "reserved"
+        } else {
+            "available"
+        };
+        assert_eq!(summary.status, expected);
+        assert_eq!(summary.not_after, upload.not_after);
+    }
+}
+
 #[tokio::test]
 async fn test_spend_prevents_double_spend() {
     let (addr, _pool) = spawn_server_with_pool().await;
@@ -320,8 +507,9 @@ async fn test_spend_prevents_double_spend() {
         .expect("Upload should succeed");
 
     let group_id = vec![0x01, 0x02];
+    let inviter = register_group_creator(&api, "inviter", &group_id).await;
     let reservation = api
-        .reserve_key_package("double-spend", &group_id, "inviter")
+        .reserve_key_package("double-spend", &group_id, "inviter", &inviter)
         .await
         .expect("Reservation should succeed");
 
@@ -342,8 +530,9 @@ async fn test_reserve_on_empty_pool_returns_not_found() {
     let (addr, _pool) = spawn_server_with_pool().await;
     let api = ServerApi::new(&format!("http://{}", addr));
 
+    let inviter = register_group_creator(&api, "inviter", &[0x01, 0x02]).await;
     let result = api
-        .reserve_key_package("no-keys", &[0x01, 0x02], "inviter")
+        .reserve_key_package("no-keys", &[0x01, 0x02], "inviter", &inviter)
         .await;
 
     assert!(result.is_err(), "Reserve should fail when pool empty");
@@ -354,6 +543,28 @@ async fn test_reserve_on_empty_pool_returns_not_found() {
         .contains("No available KeyPackage"));
 }
 
+#[tokio::test]
+async fn test_available_keypackages_counts_only_reservable() {
+    let (addr, _pool) = spawn_server_with_pool().await;
+    let api = ServerApi::new(&format!("http://{}", addr));
+
+    assert_eq!(api.available_keypackages("count-user").await.unwrap(), 0);
+
+    let uploads: Vec<KeyPackageUpload> = (0..2)
+        .map(|_| generate_keypackage_upload("count-user"))
+        .collect();
+    api.upload_key_packages("count-user", &uploads)
+        .await
+        .expect("Upload should succeed");
+    assert_eq!(api.available_keypackages("count-user").await.unwrap(), 2);
+
+    let inviter = register_group_creator(&api, "inviter", &[0x01]).await;
+    api.reserve_key_package("count-user", &[0x01], "inviter", &inviter)
+        .await
+        .expect("Reserve should succeed");
+    assert_eq!(api.available_keypackages("count-user").await.unwrap(), 1);
+}
+
 #[tokio::test]
 async fn test_expired_key_rejected() {
     let (addr, pool) = spawn_server_with_pool().await;
@@ -374,8 +585,9 @@ async fn test_expired_key_rejected() {
         .expect("Failed to update expiration");
     }
 
+    let inviter = register_group_creator(&api, "inviter", &[0x01, 0x02, 0x03]).await;
     let result = api
-        .reserve_key_package("expiry-test", &[0x01, 0x02, 0x03], "inviter")
+        .reserve_key_package("expiry-test", &[0x01, 0x02, 0x03], "inviter", &inviter)
         .await;
 
     assert!(result.is_err(), "Expired key should not be reserved");
@@ -392,8 +604,9 @@ async fn test_reservation_timeout_releases_key() {
         .expect("Upload should succeed");
 
     let group_id = vec![0xaa, 0xbb];
+    let inviter = register_group_creator(&api, "inviter", &group_id).await;
     let reservation = api
-        .reserve_key_package("ttl-user", &group_id, "inviter")
+        .reserve_key_package("ttl-user", &group_id, "inviter", &inviter)
         .await
         .expect("Reservation should succeed");
 
@@ -408,7 +621,7 @@ async fn test_reservation_timeout_releases_key() {
 
     // Next reserve should succeed because server releases expired reservations
     let second = api
-        .reserve_key_package("ttl-user", &group_id, "inviter")
+        .reserve_key_package("ttl-user", &group_id, "inviter", &inviter)
         .await
         .expect("Reservation should be released and succeed");
 
@@ -434,22 +647,26 @@ async fn test_concurrent_multi_inviter() {
     let group_id2 = vec![0x03, 0x04];
     let group_id3 = vec![0x05, 0x06];
 
+    let inviter1 = register_group_creator(&api, "inviter1", &group_id1).await;
+    let inviter2 = register_group_creator(&api, "inviter2", &group_id2).await;
+    let inviter3 = register_group_creator(&api, "inviter3", &group_id3).await;
+
     let api1 = api.clone();
     let api2 = api.clone();
     let api3 = api.clone();
 
     let handle1 = tokio::spawn(async move {
-        api1.reserve_key_package("target-user", &group_id1, "inviter1")
+        api1.reserve_key_package("target-user", &group_id1, "inviter1", &inviter1)
             .await
     });
 
     let handle2 = tokio::spawn(async move {
-        api2.reserve_key_package("target-user", &group_id2, "inviter2")
+        api2.reserve_key_package("target-user", &group_id2, "inviter2", &inviter2)
             .await
     });
 
     let handle3 = tokio::spawn(async move {
-        api3.reserve_key_package("target-user", &group_id3, "inviter3")
+        api3.reserve_key_package("target-user", &group_id3, "inviter3", &inviter3)
             .await
     });
 
@@ -505,9 +722,11 @@ async fn test_structured_error_types() {
     let (addr, _pool) = spawn_server_with_pool().await;
     let api = ServerApi::new(&format!("http://{}", addr));
 
+    let inviter = register_group_creator(&api, "inviter", &[0x01, 0x02]).await;
+
     // Test PoolExhausted error
     let result = api
-        .reserve_key_package("no-keys", &[0x01, 0x02], "inviter")
+        .reserve_key_package("no-keys", &[0x01, 0x02], "inviter", &inviter)
         .await;
     assert!(result.is_err());
     match result.err().unwrap() {
@@ -527,7 +746,7 @@ async fn test_structured_error_types() {
 
     let group_id = vec![0x01, 0x02];
     let reservation = api
-        .reserve_key_package("error-test", &group_id, "inviter")
+        .reserve_key_package("error-test", &group_id, "inviter", &inviter)
         .await
         .expect("Reservation should succeed");
 
@@ -562,3 +781,197 @@ async fn test_structured_error_types() {
         other => panic!("Expected InvalidKeyPackageRef error, got: {:?}", other),
     }
 }
+
+/// A server that accepts connections but never answers must fail with a
+/// timeout error within the configured bound
+#[tokio::test]
+async fn test_request_times_out_against_unresponsive_server() {
+    use mls_chat_client::error::{ClientError, NetworkError};
+    use std::time::{Duration, Instant};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        // Hold accepted sockets open without ever writing a response
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            held.push(socket);
+        }
+    });
+
+    let config = ServerApiConfig {
+        request_timeout: Duration::from_millis(500),
+        connect_timeout: Duration::from_millis(500),
+        ..ServerApiConfig::default()
+    };
+    let api = ServerApi::with_config(&format!("http://{}", addr), config);
+    assert_eq!(api.config().request_timeout, Duration::from_millis(500));
+
+    let started = Instant::now();
+    let result = api.health_check().await;
+    let elapsed = started.elapsed();
+
+    match result {
+        Err(ClientError::Network(NetworkError::Timeout)) => {}
+        other => panic!("Expected timeout error, got: {:?}", other),
+    }
+    assert!(
+        elapsed < Duration::from_secs(3),
+        "request took {:?}, expected to time out within the bound",
+        elapsed
+    );
+}
+
+/// A 503 with `Retry-After` is retried after the indicated delay, and the
+/// busy state is visible while waiting
+#[tokio::test]
+async fn test_retry_after_is_honored_before_retrying() {
+    use mls_chat_client::api::ServerStatus;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Answers the first request with 503 + Retry-After: 1, then 200
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let request_times = Arc::new(std::sync::Mutex::new(Vec::new()));
+    {
+        let requests = requests.clone();
+        let request_times = request_times.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                request_times.lock().unwrap().push(Instant::now());
+                let response = if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+    }
+
+    let api = ServerApi::new(&format!("http://{}", addr));
+    let mut status_rx = api.watch_status();
+    let busy_seen = tokio::spawn(async move {
+        while status_rx.changed().await.is_ok() {
+            if let ServerStatus::Busy { retry_after } = *status_rx.borrow_and_update() {
+                return Some(retry_after);
+            }
+        }
+        None
+    });
+
+    api.health_check()
+        .await
+        .expect("retried request should succeed");
+
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    let times = request_times.lock().unwrap().clone();
+    assert!(
+        times[1] - times[0] >= Duration::from_secs(1),
+        "retry came after {:?}, expected at least the Retry-After delay",
+        times[1] - times[0]
+    );
+    assert_eq!(api.status(), ServerStatus::Available);
+    assert_eq!(busy_seen.await.unwrap(), Some(Duration::from_secs(1)));
+}
+
+/// Once the retry budget is spent the busy response is reported as an error
+#[tokio::test]
+async fn test_retry_after_gives_up_after_max_retries() {
+    use mls_chat_client::api::ServerStatus;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    {
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                requests.fetch_add(1, Ordering::SeqCst);
+                let _ = socket
+                    .write_all(b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await;
+            }
+        });
+    }
+
+    let config = ServerApiConfig {
+        max_busy_retries: 2,
+        max_retry_after: Duration::from_secs(1),
+        ..ServerApiConfig::default()
+    };
+    let api = ServerApi::with_config(&format!("http://{}", addr), config);
+
+    assert!(api.health_check().await.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(api.status(), ServerStatus::Available);
+}
+
+#[tokio::test]
+async fn test_group_info_round_trips_through_the_server() {
+    use openmls::prelude::{MlsGroup, MlsGroupCreateConfig, MlsMessageBodyIn, MlsMessageIn};
+    use tls_codec::Deserialize;
+
+    let (addr, _pool) = spawn_server_with_pool().await;
+    let api = ServerApi::new(&format!("http://{}", addr));
+    let provider =
+        mls_chat_client::provider::MlsProvider::new_in_memory().expect("Failed to create provider");
+
+    // A group without application extensions has a public GroupInfo
+    let (credential, sig_key) =
+        crypto::generate_credential_with_key("alice").expect("Failed to generate credential");
+    let group = MlsGroup::new(
+        &provider,
+        &sig_key,
+        &MlsGroupCreateConfig::default(),
+        credential,
+    )
+    .expect("Failed to create group");
+    let message = crypto::export_public_group_info(&group, &provider, &sig_key)
+        .expect("Failed to export GroupInfo")
+        .expect("GroupInfo should be public");
+    let group_info_bytes = message
+        .tls_serialize_detached()
+        .expect("Failed to serialize GroupInfo");
+    let group_id = group.group_id().as_slice().to_vec();
+
+    assert!(api.get_group_info(&group_id).await.unwrap().is_none());
+    assert!(api
+        .publish_group_info(&group_id, 1, &group_info_bytes)
+        .await
+        .expect("Publish should succeed"));
+    // An older epoch does not replace it
+    assert!(!api
+        .publish_group_info(&group_id, 0, b"stale")
+        .await
+        .expect("Stale publish is reported, not failed"));
+
+    let published = api
+        .get_group_info(&group_id)
+        .await
+        .expect("Fetch should succeed")
+        .expect("GroupInfo should be published");
+    assert_eq!(published.epoch, 1);
+    assert_eq!(published.group_info, group_info_bytes);
+
+    let fetched = MlsMessageIn::tls_deserialize(&mut published.group_info.as_slice())
+        .expect("Published bytes should be an MLS message");
+    match fetched.extract() {
+        MlsMessageBodyIn::GroupInfo(group_info) => {
+            assert_eq!(group_info.group_id(), group.group_id())
+        }
+        _ => panic!("Expected a GroupInfo message"),
+    }
+}
@@ -0,0 +1,364 @@
+/// Integration tests for the command-line subcommands
+///
+/// Tests cover argument parsing (including the legacy positional form) and
+/// running one-shot subcommands end-to-end against a test server.
+use base64::{engine::general_purpose, Engine as _};
+use clap::Parser;
+use mls_chat_client::api::ServerApi;
+use mls_chat_client::cli::{execute_command, Args, CliCommand};
+use mls_chat_client::message_processing::{DisplayConfig, TimestampStyle};
+use std::time::Duration;
+use tempfile::tempdir;
+
+/// Test helper: Spawn a test server and return its URL
+async fn spawn_test_server() -> String {
+    let (server, addr) =
+        mls_chat_server::server::create_test_http_server().expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    format!("http://{}", addr)
+}
+
+#[test]
+fn test_legacy_positional_form_maps_to_run() {
+    let args = Args::try_parse_from(["mls-client", "--server", "http://x:1", "mygroup", "alice"])
+        .expect("Legacy form should parse");
+    assert_eq!(args.server, "http://x:1");
+
+    assert_eq!(
+        args.into_command().unwrap(),
+        CliCommand::Run {
+            group_name: "mygroup".to_string(),
+            username: "alice".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_subcommands_have_their_own_args() {
+    let args = Args::try_parse_from(["mls-client", "send", "mygroup", "alice", "hello world"])
+        .expect("send should parse");
+    assert_eq!(
+        args.into_command().unwrap(),
+        CliCommand::Send {
+            group_name: "mygroup".to_string(),
+            username: "alice".to_string(),
+            message: "hello world".to_string(),
+        }
+    );
+
+    let args = Args::try_parse_from(["mls-client", "join", "bob", "--timeout", "5"])
+        .expect("join should parse");
+    assert_eq!(
+        args.into_command().unwrap(),
+        CliCommand::Join {
+            username: "bob".to_string(),
+            timeout: 5,
+        }
+    );
+
+    // Missing subcommand arguments are rejected
+    assert!(Args::try_parse_from(["mls-client", "invite", "mygroup", "alice"]).is_err());
+}
+
+#[test]
+fn test_display_flags_build_display_config() {
+    let args = Args::try_parse_from(["mls-client", "mygroup", "alice"]).unwrap();
+    assert_eq!(args.display_config(), DisplayConfig::default());
+
+    let args = Args::try_parse_from([
+        "mls-client",
+        "--timestamps",
+        "relative",
+        "--no-group-prefix",
+        "--color",
+        "mygroup",
+        "alice",
+    ])
+    .expect("display flags should parse");
+    assert_eq!(
+        args.display_config(),
+        DisplayConfig {
+            timestamps: TimestampStyle::Relative,
+            show_group: false,
+            color: true,
+        }
+    );
+
+    assert!(Args::try_parse_from(["mls-client", "--timestamps", "sometimes", "g", "a"]).is_err());
+}
+
+#[test]
+fn test_missing_command_is_rejected() {
+    let args = Args::try_parse_from(["mls-client", "mygroup"]).expect("Parsing succeeds");
+    assert!(args.into_command().is_err());
+}
+
+#[tokio::test]
+async fn test_register_create_group_and_invite_subcommands() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+    let bob_dir = tempdir().expect("Failed to create temp dir");
+
+    execute_command(
+        CliCommand::Register {
+            username: "bob".to_string(),
+        },
+        &server_url,
+        bob_dir.path(),
+    )
+    .await
+    .expect("register should succeed");
+
+    execute_command(
+        CliCommand::CreateGroup {
+            group_name: "cli-group".to_string(),
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("create-group should succeed");
+
+    // A separate invocation reloads the group from storage
+    execute_command(
+        CliCommand::Invite {
+            group_name: "cli-group".to_string(),
+            username: "alice".to_string(),
+            invitee: "bob".to_string(),
+            store_welcome: false,
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("invite should succeed");
+
+    let mut alice = mls_chat_client::client::MlsClient::new_with_storage_path(
+        &server_url,
+        "alice",
+        "cli-group",
+        alice_dir.path(),
+    )
+    .expect("Failed to create client");
+    alice.initialize().await.expect("Failed to initialize");
+    alice
+        .connect_to_group("cli-group")
+        .await
+        .expect("Failed to connect");
+    let members = alice.list_members();
+    assert!(members.contains(&"alice".to_string()));
+    assert!(members.contains(&"bob".to_string()));
+}
+
+#[tokio::test]
+async fn test_send_subcommand() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+
+    execute_command(
+        CliCommand::Send {
+            group_name: "cli-group".to_string(),
+            username: "alice".to_string(),
+            message: "hello".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("send should succeed");
+}
+
+#[tokio::test]
+async fn test_wipe_requires_confirm_flag() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+
+    execute_command(
+        CliCommand::Register {
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("register should succeed");
+    let provider_db = alice_dir.path().join("mls-alice.db");
+    assert!(provider_db.exists());
+
+    let args = Args::try_parse_from(["mls-client", "wipe", "alice"]).expect("wipe should parse");
+    let err = execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect_err("wipe without --confirm must be refused");
+    assert!(err.to_string().contains("--confirm"));
+    assert!(provider_db.exists(), "nothing is deleted without --confirm");
+
+    let args = Args::try_parse_from(["mls-client", "wipe", "alice", "--confirm"])
+        .expect("wipe should parse");
+    execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect("confirmed wipe should succeed");
+    assert!(!provider_db.exists());
+}
+
+#[tokio::test]
+async fn test_wipe_can_deregister_from_the_server() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+    let api = ServerApi::new(&server_url);
+
+    execute_command(
+        CliCommand::Register {
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("register should succeed");
+    api.store_backup("alice", "state").await.unwrap();
+    assert!(api.available_keypackages("alice").await.unwrap() > 0);
+
+    let args = Args::try_parse_from(["mls-client", "wipe", "alice", "--confirm", "--deregister"])
+        .expect("wipe should parse");
+    execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect("wipe with deregistration should succeed");
+
+    assert!(!alice_dir.path().join("mls-alice.db").exists());
+    assert!(api.get_user_key("alice").await.is_err());
+    assert_eq!(api.available_keypackages("alice").await.unwrap(), 0);
+    assert!(api.list_backups("alice").await.unwrap().is_empty());
+
+    // The name is free again and a new identity can take it
+    execute_command(
+        CliCommand::Register {
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("registering the freed name should succeed");
+}
+
+#[tokio::test]
+async fn test_backups_are_listed_and_a_version_restored() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+    let provider_db = alice_dir.path().join("mls-alice.db");
+
+    execute_command(
+        CliCommand::Register {
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("register should succeed");
+    let registered = std::fs::read(&provider_db).expect("provider database exists");
+
+    // Version 1 is the database right after registering, version 2 garbage
+    let api = ServerApi::new(&server_url);
+    let good = general_purpose::STANDARD.encode(&registered);
+    assert_eq!(api.store_backup("alice", &good).await.unwrap(), 1);
+    assert_eq!(
+        api.store_backup("alice", "bm90IGEgZGF0YWJhc2U=")
+            .await
+            .unwrap(),
+        2
+    );
+
+    let backups = api.list_backups("alice").await.unwrap();
+    let versions: Vec<i64> = backups.iter().map(|b| b.version).collect();
+    assert_eq!(versions, vec![2, 1]);
+    assert_eq!(backups[1].size, Some(good.len() as u64));
+
+    let args =
+        Args::try_parse_from(["mls-client", "backups", "alice"]).expect("backups should parse");
+    execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect("listing backups should succeed");
+
+    execute_command(
+        CliCommand::CreateGroup {
+            group_name: "general".to_string(),
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("create-group should succeed");
+    let with_group = std::fs::read(&provider_db).unwrap();
+    assert_ne!(with_group, registered);
+
+    let restore = |version: &str, confirm: bool| {
+        let mut argv = vec!["mls-client", "backups", "alice", "restore", "--version"];
+        argv.push(version);
+        if confirm {
+            argv.push("--confirm");
+        }
+        Args::try_parse_from(argv)
+            .expect("restore should parse")
+            .into_command()
+            .unwrap()
+    };
+
+    let err = execute_command(restore("1", false), &server_url, alice_dir.path())
+        .await
+        .expect_err("restore without --confirm must be refused");
+    assert!(err.to_string().contains("--confirm"));
+    assert_eq!(std::fs::read(&provider_db).unwrap(), with_group);
+
+    let err = execute_command(restore("7", true), &server_url, alice_dir.path())
+        .await
+        .expect_err("an unknown version must be refused");
+    assert!(err.to_string().contains("no backup version 7"), "{}", err);
+    assert!(err.to_string().contains("available: 2, 1"), "{}", err);
+
+    execute_command(restore("2", true), &server_url, alice_dir.path())
+        .await
+        .expect_err("a corrupt version must be refused");
+    assert_eq!(
+        std::fs::read(&provider_db).unwrap(),
+        with_group,
+        "a corrupt backup leaves the current state in place"
+    );
+
+    execute_command(restore("1", true), &server_url, alice_dir.path())
+        .await
+        .expect("restoring version 1 should succeed");
+    assert_eq!(std::fs::read(&provider_db).unwrap(), registered);
+}
+
+#[tokio::test]
+async fn test_stats_reports_local_storage() {
+    let server_url = spawn_test_server().await;
+    let alice_dir = tempdir().expect("Failed to create temp dir");
+
+    let args = Args::try_parse_from(["mls-client", "stats", "alice"]).expect("stats should parse");
+    let err = execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect_err("stats for an unknown user must fail");
+    assert!(err.to_string().contains("no local data"));
+    assert!(!alice_dir.path().join("mls-alice.db").exists());
+
+    execute_command(
+        CliCommand::CreateGroup {
+            group_name: "general".to_string(),
+            username: "alice".to_string(),
+        },
+        &server_url,
+        alice_dir.path(),
+    )
+    .await
+    .expect("create-group should succeed");
+
+    let args = Args::try_parse_from(["mls-client", "stats", "alice"]).expect("stats should parse");
+    execute_command(args.into_command().unwrap(), &server_url, alice_dir.path())
+        .await
+        .expect("stats should succeed");
+}
@@ -3,7 +3,9 @@
 /// Tests cover group creation, persistence, and state management
 /// Note: Tests that require server interaction (registration) are marked with skip
 use actix_web::web;
+use base64::{engine::general_purpose, Engine as _};
 use mls_chat_client::client::MlsClient;
+use mls_chat_client::error::{ClientError, MlsError};
 use mls_chat_client::mls::KeyPackagePoolConfig;
 use mls_chat_server::db::keypackage_store::{KeyPackageStatus, KeyPackageStore};
 use std::time::Duration;
@@ -406,6 +408,1119 @@ async fn test_client_server_integration_complete_workflow() {
     server_handle.abort();
 }
 
+/// Creating groups beyond the client's configured limit fails with
+/// "group limit reached"; groups under it, and reconnecting, still work
+#[tokio::test]
+async fn test_group_limit_blocks_creating_beyond_local_cap() {
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "first");
+    alice.set_max_groups(2);
+    alice.initialize().await.expect("initialize");
+
+    alice.connect_to_group("first").await.expect("first group");
+    alice
+        .connect_to_group("second")
+        .await
+        .expect("second group");
+
+    let err = alice
+        .connect_to_group("third")
+        .await
+        .expect_err("third group exceeds the limit");
+    assert!(err.to_string().contains("group limit reached"), "{err}");
+    assert!(matches!(
+        err,
+        ClientError::Mls(MlsError::GroupLimitReached { limit: 2, .. })
+    ));
+    assert!(!alice.get_provider().group_exists("alice:third").unwrap());
+
+    // Groups the user is already in are not counted twice
+    alice
+        .connect_to_group("first")
+        .await
+        .expect("reconnecting to an existing group at the limit");
+
+    server_handle.abort();
+}
+
+/// Selecting a group loads only the recent window of a long history; older
+/// pages are loaded on demand
+#[tokio::test]
+async fn test_select_group_loads_recent_history_window() {
+    use mls_chat_client::models::{ContentType, NewHistoryEntry};
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "chat");
+    alice.initialize().await.expect("initialize");
+    alice.connect_to_group("chat").await.expect("create group");
+    let group_id = alice.get_group_id().expect("group id");
+
+    let messages: Vec<NewHistoryEntry> = (0..500)
+        .map(|i| NewHistoryEntry {
+            message_id: Some(format!("m{}", i)),
+            sender: "bob".to_string(),
+            content: format!("message {}", i),
+            content_type: ContentType::Text,
+            expires_at: None,
+            quote: None,
+        })
+        .collect();
+    alice
+        .get_provider()
+        .append_messages(&group_id, &messages)
+        .expect("append history");
+
+    // Only the newest 50 entries are loaded on select
+    alice.connect_to_group("chat").await.expect("select group");
+    let window = alice.loaded_history();
+    assert_eq!(window.len(), 50);
+    assert_eq!(window.first().unwrap().content, "message 450");
+    assert_eq!(window.last().unwrap().content, "message 499");
+    assert!(alice.has_older_history());
+
+    // Scrollback fetches the preceding page and prepends it
+    let page = alice.load_older_history().expect("older page");
+    assert_eq!(page.len(), 50);
+    assert_eq!(page.first().unwrap().content, "message 400");
+    assert_eq!(page.last().unwrap().content, "message 449");
+    assert_eq!(alice.loaded_history().len(), 100);
+    assert_eq!(alice.loaded_history()[0].content, "message 400");
+
+    // Walking back reaches the start of history without gaps
+    while alice.has_older_history() {
+        assert!(!alice.load_older_history().expect("older page").is_empty());
+    }
+    let loaded: Vec<&str> = alice
+        .loaded_history()
+        .iter()
+        .map(|entry| entry.content.as_str())
+        .collect();
+    let expected: Vec<String> = (0..500).map(|i| format!("message {}", i)).collect();
+    assert_eq!(loaded, expected);
+    assert!(alice.load_older_history().expect("no more").is_empty());
+
+    // A smaller limit shrinks the window and the pages
+    alice.set_initial_history_limit(10);
+    alice.connect_to_group("chat").await.expect("select group");
+    assert_eq!(alice.loaded_history().len(), 10);
+    assert_eq!(alice.load_older_history().expect("older page").len(), 10);
+
+    server_handle.abort();
+}
+
+/// The server's limit applies to both creating a group and being invited
+#[tokio::test]
+async fn test_server_group_limit_blocks_creating_and_inviting() {
+    let (server, addr) = mls_chat_server::server::create_test_http_server_with_config(
+        mls_chat_server::handlers::ServerConfig {
+            max_groups_per_user: 1,
+            ..Default::default()
+        },
+    )
+    .expect("Failed to create test server");
+    let server_handle = tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let server_url = format!("http://{}", addr);
+
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "team");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "bobs");
+    alice.initialize().await.expect("alice initialize");
+    bob.initialize().await.expect("bob initialize");
+
+    // Under the limit
+    alice.connect_to_group("team").await.expect("alice's group");
+    bob.connect_to_group("bobs").await.expect("bob's group");
+
+    // Creating a second group is refused by the server
+    let err = alice
+        .connect_to_group("other")
+        .await
+        .expect_err("second group exceeds the server limit");
+    assert!(matches!(
+        err,
+        ClientError::Mls(MlsError::GroupLimitReached { limit: 1, .. })
+    ));
+    assert!(!alice.get_provider().group_exists("alice:other").unwrap());
+
+    // Inviting bob, who is already in one group, is refused as well
+    alice.connect_to_group("team").await.expect("reselect team");
+    let err = alice
+        .invite_user("bob")
+        .await
+        .expect_err("bob is at the group limit");
+    let ClientError::Invite(invite) = err else {
+        panic!("expected an invite error, got {err:?}");
+    };
+    assert!(matches!(
+        *invite.source,
+        ClientError::Mls(MlsError::GroupLimitReached { ref username, limit: 1 }) if username == "bob"
+    ));
+    assert!(invite.to_string().contains("group limit reached"));
+
+    server_handle.abort();
+}
+
+/// The same user creating a group of the same name from a second device gets
+/// an explicit conflict instead of a second, hidden group
+#[tokio::test]
+async fn test_duplicate_group_creation_across_devices() {
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+
+    // The server only accepts connections signed by the registered identity,
+    // so the phone is set up from a copy of the laptop's storage, taken
+    // before the group exists
+    let (mut laptop, laptop_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    laptop.initialize().await.expect("laptop initialize");
+    laptop.shutdown().await;
+    drop(laptop);
+    let phone_dir = tempdir().expect("Failed to create temp dir");
+    for entry in std::fs::read_dir(laptop_dir.path()).expect("read laptop storage") {
+        let entry = entry.expect("storage entry");
+        std::fs::copy(entry.path(), phone_dir.path().join(entry.file_name()))
+            .expect("copy laptop storage");
+    }
+    let mut phone =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", phone_dir.path())
+            .expect("open phone storage");
+    phone.initialize().await.expect("phone initialize");
+
+    let mut laptop =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", laptop_dir.path())
+            .expect("reopen laptop storage");
+    laptop.initialize().await.expect("laptop initialize");
+    laptop
+        .connect_to_group("testgroup")
+        .await
+        .expect("first device creates the group");
+    let group_id = laptop.get_group_id().expect("group id");
+
+    let err = phone
+        .connect_to_group("testgroup")
+        .await
+        .expect_err("second device must not create a second group");
+    match err {
+        ClientError::Mls(MlsError::DuplicateGroup {
+            name,
+            existing_group_id,
+        }) => {
+            assert_eq!(name, "alice:testgroup");
+            assert_eq!(
+                existing_group_id,
+                general_purpose::STANDARD.encode(&group_id)
+            );
+        }
+        other => panic!("expected DuplicateGroup, got {other:?}"),
+    }
+    assert!(!phone.is_group_connected());
+    assert!(
+        !phone
+            .get_provider()
+            .group_exists("alice:testgroup")
+            .unwrap(),
+        "the rejected group must not stay mapped to the name"
+    );
+    assert!(
+        phone
+            .get_provider()
+            .check_integrity()
+            .unwrap()
+            .unreferenced_groups
+            .is_empty(),
+        "the rejected group's MLS state must be deleted"
+    );
+
+    // The first device keeps reconnecting to the same group
+    let mut laptop_again =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", laptop_dir.path())
+            .expect("reopen laptop storage");
+    laptop_again
+        .initialize()
+        .await
+        .expect("laptop re-initialize");
+    laptop_again
+        .connect_to_group("testgroup")
+        .await
+        .expect("owner device reconnects");
+    assert_eq!(laptop_again.get_group_id(), Some(group_id));
+
+    server_handle.abort();
+}
+
+/// A failure to claim the group name, other than a conflict, does not stop
+/// the group from being created and connected
+#[tokio::test]
+async fn test_group_name_registration_failure_is_not_fatal() {
+    let pool = web::Data::new(mls_chat_server::db::create_test_pool());
+    let (server, addr) = mls_chat_server::server::create_test_http_server_with_pool(pool.clone())
+        .expect("Failed to create test server");
+    let server_handle = tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut alice, _alice_dir) =
+        create_client_with_server(&format!("http://{}", addr), "alice", "testgroup");
+    alice.initialize().await.expect("initialize");
+
+    // Every name registration now fails with a server error
+    pool.lock()
+        .await
+        .execute("DROP TABLE group_names", [])
+        .expect("drop group_names");
+
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("the group is connected without its name claimed");
+    assert!(alice.is_group_connected());
+    assert!(alice
+        .get_provider()
+        .group_exists("alice:testgroup")
+        .unwrap());
+
+    server_handle.abort();
+}
+
+/// Wiping a user removes its provider database and identity, leaves other
+/// users of the same storage directory alone, and allows a fresh start
+#[tokio::test]
+async fn test_wipe_removes_local_state_and_allows_reinitialize() {
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let shared_dir = tempdir().expect("Failed to create temp dir");
+
+    let mut alice =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", shared_dir.path())
+            .expect("create alice");
+    let mut bob =
+        MlsClient::new_with_storage_path(&server_url, "bob", "testgroup", shared_dir.path())
+            .expect("create bob");
+    // Bob first: the pool metadata table is shared by the storage directory,
+    // so the KeyPackages counted below are bob's
+    bob.initialize().await.expect("bob initialize");
+    alice.initialize().await.expect("alice initialize");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("alice creates group");
+    let old_key = alice.get_identity().unwrap().keypair_blob.clone();
+    let bob_key = bob.get_identity().unwrap().keypair_blob.clone();
+    let bob_available = bob
+        .get_metadata_store()
+        .count_by_status("available")
+        .unwrap();
+    assert!(bob_available > 0);
+
+    alice.wipe().await.expect("wipe should succeed");
+
+    let alice_db = shared_dir.path().join("mls-alice.db");
+    assert!(!alice_db.exists(), "provider DB must be removed");
+    assert!(shared_dir.path().join("mls-bob.db").exists());
+
+    // Bob's identity and pool metadata survive in the shared metadata store
+    let metadata = mls_chat_client::storage::LocalStore::new(shared_dir.path().join("metadata.db"))
+        .expect("open metadata");
+    assert!(metadata.load_public_key("alice").unwrap().is_none());
+    assert_eq!(metadata.load_public_key("bob").unwrap(), Some(bob_key));
+    assert_eq!(
+        metadata.count_by_status("available").unwrap(),
+        bob_available
+    );
+
+    // A fresh client for alice starts over with a new identity
+    let mut alice =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", shared_dir.path())
+            .expect("recreate alice");
+    alice.initialize().await.expect("fresh initialize");
+    assert!(alice_db.exists());
+    assert_ne!(alice.get_identity().unwrap().keypair_blob, old_key);
+
+    server_handle.abort();
+}
+
+/// Connection state follows disconnects and reconnects, and groups are
+/// resubscribed after reconnecting
+#[tokio::test]
+async fn test_reconnect_websocket_updates_state_and_resubscribes() {
+    use mls_chat_client::websocket::ConnectionState;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let (mut client, _temp_dir) =
+        create_client_with_server(&format!("http://{}", addr), "alice", "testgroup");
+
+    let state = client.get_connection().watch_connection_state();
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+
+    client.initialize().await.expect("initialize");
+    client
+        .connect_to_group("testgroup")
+        .await
+        .expect("connect to group");
+    assert_eq!(*state.borrow(), ConnectionState::Connected);
+
+    client.get_connection_mut().disconnect_websocket().await;
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+
+    client
+        .get_connection_mut()
+        .reconnect_websocket()
+        .await
+        .expect("reconnect");
+    assert_eq!(*state.borrow(), ConnectionState::Connected);
+
+    // The group subscription was restored: our own message is echoed back
+    client.send_message("after reconnect").await.expect("send");
+    let envelope = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.get_connection_mut().next_envelope(),
+    )
+    .await
+    .expect("echo should arrive")
+    .expect("receive")
+    .expect("connection open");
+    assert!(matches!(
+        envelope,
+        mls_chat_client::models::MlsMessageEnvelope::ApplicationMessage { .. }
+    ));
+
+    server_handle.abort();
+}
+
+/// Test helper: Wait up to 5 seconds for the next envelope of `client`
+async fn next_envelope_within(
+    client: &mut MlsClient,
+) -> mls_chat_client::models::MlsMessageEnvelope {
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        client.get_connection_mut().next_envelope(),
+    )
+    .await
+    .expect("envelope should arrive")
+    .expect("receive")
+    .expect("connection open")
+}
+
+/// Messages sent while a member is disconnected are backfilled in order when
+/// it reconnects, without repeating the ones it received live
+#[tokio::test]
+async fn test_reconnect_backfills_messages_missed_while_disconnected() {
+    use mls_chat_client::models::ContentType;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "testgroup");
+
+    alice.initialize().await.expect("initialize alice");
+    bob.initialize().await.expect("initialize bob");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("connect alice");
+    bob.get_connection_mut()
+        .connect_websocket()
+        .await
+        .expect("connect bob");
+
+    alice.invite_user("bob").await.expect("invite bob");
+    let welcome = next_envelope_within(&mut bob).await;
+    let group_id = bob
+        .get_connection_mut()
+        .process_incoming_envelope(welcome)
+        .await
+        .expect("process Welcome")
+        .expect("Welcome creates a membership");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The first message is delivered live
+    alice.send_message("before the drop").await.expect("send");
+    let live = next_envelope_within(&mut bob).await;
+    bob.get_connection_mut()
+        .process_incoming_envelope(live)
+        .await
+        .expect("process live message");
+
+    // Bob's connection drops while alice keeps sending
+    bob.get_connection_mut().disconnect_websocket().await;
+    alice.send_message("missed 1").await.expect("send");
+    alice.send_message("missed 2").await.expect("send");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    bob.get_connection_mut()
+        .reconnect_websocket()
+        .await
+        .expect("reconnect");
+
+    let texts = |client: &MlsClient| -> Vec<String> {
+        client
+            .get_connection()
+            .get_provider()
+            .load_history(&group_id)
+            .expect("load history")
+            .into_iter()
+            .filter(|entry| entry.content_type == ContentType::Text)
+            .map(|entry| entry.content)
+            .collect()
+    };
+    assert_eq!(texts(&bob), vec!["before the drop", "missed 1", "missed 2"]);
+
+    // Nothing is left to backfill, and nothing was stored twice
+    assert_eq!(
+        bob.get_connection_mut()
+            .backfill_missed_messages()
+            .await
+            .expect("backfill"),
+        0
+    );
+    assert_eq!(texts(&bob).len(), 3);
+
+    server_handle.abort();
+}
+
+/// Many sends racing through one group's sender all decrypt on the
+/// receiver, in the order the sender encrypted them
+#[tokio::test]
+async fn test_concurrent_sends_decrypt_in_order() {
+    use mls_chat_client::models::ContentType;
+
+    const SENDS: usize = 50;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "testgroup");
+
+    alice.initialize().await.expect("initialize alice");
+    bob.initialize().await.expect("initialize bob");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("connect alice");
+    bob.get_connection_mut()
+        .connect_websocket()
+        .await
+        .expect("connect bob");
+
+    alice.invite_user("bob").await.expect("invite bob");
+    let welcome = next_envelope_within(&mut bob).await;
+    let group_id = bob
+        .get_connection_mut()
+        .process_incoming_envelope(welcome)
+        .await
+        .expect("process Welcome")
+        .expect("Welcome creates a membership");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Every send contends for the group's lock; the order in which they got
+    // it is the order their messages were encrypted
+    let sender = alice
+        .get_connection_mut()
+        .group_senders(&[&group_id])
+        .expect("group sender")
+        .pop()
+        .expect("sender");
+    let sent_order = std::sync::Mutex::new(Vec::new());
+    let sends = (0..SENDS).map(|i| {
+        let (sender, sent_order) = (&sender, &sent_order);
+        async move {
+            tokio::task::yield_now().await;
+            let text = format!("message {}", i);
+            sender.send_message(&text).await.expect("send");
+            sent_order.lock().unwrap().push(text);
+        }
+    });
+    futures::future::join_all(sends).await;
+    drop(sender);
+
+    for _ in 0..SENDS {
+        let envelope = next_envelope_within(&mut bob).await;
+        bob.get_connection_mut()
+            .process_incoming_envelope(envelope)
+            .await
+            .expect("every message decrypts");
+    }
+
+    let received: Vec<String> = bob
+        .get_connection()
+        .get_provider()
+        .load_history(&group_id)
+        .expect("load history")
+        .into_iter()
+        .filter(|entry| entry.content_type == ContentType::Text)
+        .map(|entry| entry.content)
+        .collect();
+    assert_eq!(received, sent_order.into_inner().unwrap());
+
+    server_handle.abort();
+}
+
+/// Test helper: Send `count` numbered messages through `sender`, yielding
+/// after each so concurrent senders interleave
+async fn send_numbered(sender: &mls_chat_client::mls::GroupSender<'_>, label: &str, count: usize) {
+    for i in 0..count {
+        sender
+            .send_message(&format!("{} {}", label, i))
+            .await
+            .expect("send");
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Sends to two groups running concurrently, with no lock around the
+/// client, interleave on the wire and still decrypt in order within each group
+#[tokio::test]
+async fn test_concurrent_sends_to_two_groups_decrypt_in_order() {
+    use mls_chat_client::models::{ContentType, MlsMessageEnvelope};
+
+    const SENDS: usize = 25;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "red");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "red");
+
+    alice.initialize().await.expect("initialize alice");
+    bob.initialize().await.expect("initialize bob");
+    bob.get_connection_mut()
+        .connect_websocket()
+        .await
+        .expect("connect bob");
+
+    let mut group_ids = Vec::new();
+    for group_name in ["red", "blue"] {
+        alice
+            .connect_to_group(group_name)
+            .await
+            .expect("connect alice");
+        alice.invite_user("bob").await.expect("invite bob");
+        let welcome = next_envelope_within(&mut bob).await;
+        let group_id = bob
+            .get_connection_mut()
+            .process_incoming_envelope(welcome)
+            .await
+            .expect("process Welcome")
+            .expect("Welcome creates a membership");
+        group_ids.push(group_id);
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (red, blue) = (group_ids[0].clone(), group_ids[1].clone());
+    let mut senders = alice
+        .get_connection_mut()
+        .group_senders(&[&red, &blue])
+        .expect("group senders");
+    let blue_sender = senders.pop().expect("blue sender");
+    let red_sender = senders.pop().expect("red sender");
+    assert_eq!(red_sender.group_id(), red.as_slice());
+    tokio::join!(
+        send_numbered(&red_sender, "red", SENDS),
+        send_numbered(&blue_sender, "blue", SENDS)
+    );
+
+    let mut arrivals = Vec::new();
+    for _ in 0..2 * SENDS {
+        let envelope = next_envelope_within(&mut bob).await;
+        if let MlsMessageEnvelope::ApplicationMessage { group_id, .. } = &envelope {
+            arrivals.push(group_id.clone());
+        }
+        bob.get_connection_mut()
+            .process_incoming_envelope(envelope)
+            .await
+            .expect("every message decrypts");
+    }
+    // The two groups' messages were on the wire at the same time
+    let switches = arrivals
+        .windows(2)
+        .filter(|pair| pair[0] != pair[1])
+        .count();
+    assert!(switches > 1, "sends did not interleave: {:?}", arrivals);
+
+    for (group_id, label) in [(&red, "red"), (&blue, "blue")] {
+        let received: Vec<String> = bob
+            .get_connection()
+            .get_provider()
+            .load_history(group_id)
+            .expect("load history")
+            .into_iter()
+            .filter(|entry| entry.content_type == ContentType::Text)
+            .map(|entry| entry.content)
+            .collect();
+        let sent: Vec<String> = (0..SENDS).map(|i| format!("{} {}", label, i)).collect();
+        assert_eq!(received, sent);
+    }
+
+    server_handle.abort();
+}
+
+/// The server reports the epoch a broadcast Commit moved the group into, so
+/// a member that missed it can tell it is behind
+#[tokio::test]
+async fn test_server_reports_epoch_advanced_by_broadcast_commit() {
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "testgroup");
+
+    alice.initialize().await.expect("initialize alice");
+    bob.initialize().await.expect("initialize bob");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("connect alice");
+    let group_id = alice.get_group_id().expect("group id");
+    assert_eq!(
+        alice.get_api().group_epoch(&group_id).await.expect("epoch"),
+        None,
+        "no Commit seen for a new group"
+    );
+
+    bob.get_connection_mut()
+        .connect_websocket()
+        .await
+        .expect("connect bob");
+    alice.invite_user("bob").await.expect("invite bob");
+    let welcome = next_envelope_within(&mut bob).await;
+    bob.get_connection_mut()
+        .process_incoming_envelope(welcome)
+        .await
+        .expect("process Welcome");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(
+        alice.get_api().group_epoch(&group_id).await.expect("epoch"),
+        Some(1)
+    );
+    assert_eq!(
+        bob.get_connection()
+            .epoch_lag(&group_id)
+            .await
+            .expect("lag"),
+        Some(0)
+    );
+
+    // Bob misses a Commit that is only broadcast, not sequenced
+    bob.get_connection_mut().disconnect_websocket().await;
+    alice
+        .set_message_ttl(Some(3600))
+        .await
+        .expect("set message ttl");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(
+        alice.get_api().group_epoch(&group_id).await.expect("epoch"),
+        Some(2)
+    );
+    assert_eq!(
+        alice
+            .get_connection()
+            .epoch_lag(&group_id)
+            .await
+            .expect("lag"),
+        Some(0)
+    );
+    assert_eq!(
+        bob.get_connection()
+            .epoch_lag(&group_id)
+            .await
+            .expect("lag"),
+        Some(1)
+    );
+
+    server_handle.abort();
+}
+
+/// A Welcome stored on the server while the invitee is offline is picked up
+/// when they next connect, and deleted from the server once they joined
+#[tokio::test]
+async fn test_stored_welcome_is_delivered_on_next_connect() {
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "testgroup");
+
+    // Bob registers, then goes offline before the invitation
+    alice.initialize().await.expect("initialize alice");
+    bob.initialize().await.expect("initialize bob");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("connect alice");
+
+    alice.set_store_welcomes(true);
+    alice.invite_user("bob").await.expect("invite bob");
+
+    let stored = alice
+        .get_api()
+        .pending_welcomes("bob")
+        .await
+        .expect("list stored Welcomes");
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].inviter, "alice");
+
+    // On connecting, bob joins the group from the stored Welcome
+    bob.connect_to_group("testgroup")
+        .await
+        .expect("connect bob");
+    assert_eq!(bob.get_group_id(), alice.get_group_id());
+    assert!(bob.list_members().contains(&"alice".to_string()));
+    assert!(
+        bob.get_api()
+            .pending_welcomes("bob")
+            .await
+            .expect("list stored Welcomes")
+            .is_empty(),
+        "the Welcome must be deleted once joined"
+    );
+
+    // Bob is a working member of alice's group
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    alice.send_message("welcome back").await.expect("send");
+    let envelope = next_envelope_within(&mut bob).await;
+    bob.get_connection_mut()
+        .process_incoming_envelope(envelope)
+        .await
+        .expect("bob decrypts alice's message");
+
+    server_handle.abort();
+}
+
+/// whoami reports the initialized identity, and the same one after reloading
+/// it from storage
+#[tokio::test]
+async fn test_whoami_reflects_initialized_identity() {
+    use mls_chat_client::crypto::CredentialKind;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+
+    assert!(alice.whoami().is_err(), "no identity before initialize");
+    alice.initialize().await.expect("initialize alice");
+
+    let info = alice.whoami().expect("whoami");
+    let user = alice.get_connection().get_user().expect("user");
+    assert_eq!(info.username, "alice");
+    assert_eq!(info.credential_kind, CredentialKind::Basic);
+    assert_eq!(
+        info.signature_public_key,
+        user.get_signature_key().to_public_vec()
+    );
+    assert_eq!(info.credential_fingerprint.len(), 64);
+    assert!(info
+        .credential_fingerprint
+        .chars()
+        .all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(
+        info.server_user_id,
+        alice.get_connection().get_server_user_id()
+    );
+    assert!(info.server_user_id.is_some());
+
+    // The identity loaded from storage is the same one
+    let mut reloaded =
+        MlsClient::new_with_storage_path(&server_url, "alice", "testgroup", alice_dir.path())
+            .expect("reload alice");
+    reloaded
+        .initialize()
+        .await
+        .expect("initialize reloaded alice");
+    assert_eq!(reloaded.whoami().expect("whoami"), info);
+
+    // Another user has another fingerprint
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "testgroup");
+    bob.initialize().await.expect("initialize bob");
+    assert_ne!(
+        bob.whoami().expect("whoami").credential_fingerprint,
+        info.credential_fingerprint
+    );
+
+    server_handle.abort();
+}
+
+/// The server directory publishes exactly the identity bound in the user's
+/// registered KeyPackage, and its fingerprint matches the user's own
+#[tokio::test]
+async fn test_published_identity_matches_key_package_credential() {
+    use mls_chat_client::crypto::CredentialKind;
+    use openmls::key_packages::KeyPackageIn;
+    use openmls::prelude::ProtocolVersion;
+    use openmls_traits::OpenMlsProvider;
+    use tls_codec::Deserialize;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+
+    for (username, kind) in [
+        ("alice", CredentialKind::Basic),
+        ("carol", CredentialKind::X509),
+    ] {
+        let temp_dir = tempdir().expect("temp dir");
+        let mut client =
+            MlsClient::new_with_storage_path(&server_url, username, "testgroup", temp_dir.path())
+                .expect("create client");
+        client.set_credential_kind(kind);
+        client.initialize().await.expect("initialize");
+        let connection = client.get_connection();
+
+        let published = connection
+            .get_api()
+            .get_user_identity(username)
+            .await
+            .expect("fetch identity")
+            .expect("identity is published");
+        assert_eq!(published, connection.public_bundle().expect("local bundle"));
+
+        // It is the credential and key of the registered KeyPackage
+        let key_package_bytes = connection
+            .get_api()
+            .get_user_key(username)
+            .await
+            .expect("fetch key package");
+        let key_package = KeyPackageIn::tls_deserialize_exact(&key_package_bytes)
+            .expect("decode key package")
+            .validate(connection.get_provider().crypto(), ProtocolVersion::Mls10)
+            .expect("valid key package");
+        assert!(published.matches_key_package(&key_package));
+
+        let looked_up = connection
+            .lookup_identity(username)
+            .await
+            .expect("lookup")
+            .expect("known user");
+        assert_eq!(
+            looked_up
+                .fingerprint(connection.get_provider())
+                .expect("fingerprint"),
+            client.whoami().expect("whoami").credential_fingerprint
+        );
+    }
+
+    let (alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    assert_eq!(
+        alice
+            .get_connection()
+            .lookup_identity("nobody")
+            .await
+            .expect("lookup"),
+        None
+    );
+
+    server_handle.abort();
+}
+
+/// A group whose name is known but whose state is missing is reported, not
+/// silently recreated; recreating it takes an explicit policy
+#[tokio::test]
+async fn test_lost_group_state_is_reported_before_recreating() {
+    use mls_chat_client::events::GroupEvent;
+    use mls_chat_client::models::{ContentType, LostGroupPolicy};
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "team");
+    alice.initialize().await.expect("initialize alice");
+    let (_observer, mut events) = alice.subscribe_events();
+
+    // The name mapping survived, the MLS state did not
+    let lost_group_id = b"lost-group-id".to_vec();
+    alice
+        .get_connection()
+        .get_provider()
+        .save_group_name("alice:team", &lost_group_id)
+        .expect("save mapping");
+
+    // Default: fail and wait to be re-added, keeping the name on the old group
+    let err = alice.connect_to_group("team").await.unwrap_err();
+    assert!(
+        matches!(err, ClientError::Mls(MlsError::GroupStateLost { ref name, .. }) if name == "team"),
+        "unexpected error: {}",
+        err
+    );
+    assert_eq!(
+        events.try_recv().expect("lost group event"),
+        GroupEvent::GroupStateLost {
+            group_id: lost_group_id.clone(),
+            group_name: "team".to_string(),
+            recreated: false,
+        }
+    );
+    let provider = alice.get_connection().get_provider();
+    assert_eq!(
+        provider.load_group_by_name("alice:team").expect("mapping"),
+        Some(lost_group_id.clone())
+    );
+    let history = provider.load_history(&lost_group_id).expect("history");
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content_type, ContentType::System);
+    assert!(history[0].content.contains("was lost"));
+
+    // Recreating is the caller's explicit choice
+    alice.set_lost_group_policy(LostGroupPolicy::Recreate);
+    alice
+        .connect_to_group("team")
+        .await
+        .expect("recreate the group");
+    let group_id = alice
+        .get_connection()
+        .get_provider()
+        .load_group_by_name("alice:team")
+        .expect("mapping")
+        .expect("new mapping");
+    assert_ne!(group_id, lost_group_id);
+    assert_eq!(
+        events.try_recv().expect("recreated event"),
+        GroupEvent::GroupStateLost {
+            group_id: lost_group_id,
+            group_name: "team".to_string(),
+            recreated: true,
+        }
+    );
+    let history = alice
+        .get_connection()
+        .get_provider()
+        .load_history(&group_id)
+        .expect("history");
+    assert!(history
+        .iter()
+        .any(|entry| entry.content_type == ContentType::System
+            && entry.content.contains("new conversation")));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_self_test_passes_against_live_server() {
+    use mls_chat_client::models::SelfTestCheck;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+
+    // Before initialize there is no identity and nothing on the server
+    let report = alice.self_test().await;
+    assert!(!report.all_passed());
+    let identity = report
+        .result(SelfTestCheck::Identity)
+        .expect("identity check");
+    assert!(!identity.passed);
+    assert!(identity.hint.is_some());
+    assert!(alice
+        .get_metadata_store()
+        .load_public_key("alice")
+        .unwrap()
+        .is_none());
+
+    alice.initialize().await.expect("initialize alice");
+    let pool_before = alice.keypackage_pool_stats().expect("pool stats");
+    let available_before = alice
+        .get_api()
+        .available_keypackages("alice")
+        .await
+        .expect("available keypackages");
+
+    let report = alice.self_test().await;
+    assert!(report.all_passed(), "self-test failed: {:?}", report);
+    assert_eq!(report.results.len(), 5);
+    assert!(report.results.iter().all(|result| result.hint.is_none()));
+
+    // Read-only: the pool and server inventory are untouched
+    assert_eq!(
+        alice.keypackage_pool_stats().expect("pool stats"),
+        pool_before
+    );
+    assert_eq!(
+        alice
+            .get_api()
+            .available_keypackages("alice")
+            .await
+            .expect("available keypackages"),
+        available_before
+    );
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_reset_key_package_pool_replaces_server_refs() {
+    use std::collections::HashSet;
+
+    let (server, addr) = create_test_server().await;
+    let server_handle = tokio::spawn(server);
+    let server_url = format!("http://{}", addr);
+    let (mut alice, _alice_dir) = create_client_with_server(&server_url, "alice", "testgroup");
+    alice.initialize().await.expect("initialize alice");
+
+    let server_refs = |summaries: Vec<mls_chat_client::api::KeyPackageSummary>| {
+        summaries
+            .into_iter()
+            .map(|summary| summary.keypackage_ref)
+            .collect::<HashSet<_>>()
+    };
+    let api = alice.get_api().clone();
+    let old_refs = server_refs(api.list_keypackages("alice").await.expect("list"));
+    assert!(!old_refs.is_empty());
+
+    // An inviter holds a reservation on an old KeyPackage across the reset
+    let (mut bob, _bob_dir) = create_client_with_server(&server_url, "bob", "reset-group");
+    bob.initialize().await.expect("initialize bob");
+    bob.connect_to_group("reset-group")
+        .await
+        .expect("bob creates the group");
+    let group_id = bob.get_group_id().expect("bob's group");
+    let bob_key = bob
+        .get_connection()
+        .get_user()
+        .expect("bob's identity")
+        .get_signature_key();
+    let reservation = api
+        .reserve_key_package("alice", &group_id, "bob", bob_key)
+        .await
+        .expect("reserve");
+
+    let uploaded = alice.reset_key_package_pool().await.expect("reset pool");
+
+    let new_refs = server_refs(api.list_keypackages("alice").await.expect("list"));
+    assert_eq!(new_refs.len(), uploaded);
+    assert!(new_refs.is_disjoint(&old_refs));
+    assert_eq!(
+        alice
+            .keypackage_pool_stats()
+            .expect("pool stats")
+            .get("available"),
+        Some(&uploaded)
+    );
+
+    // The old reservation can no longer be spent; a retry reserves a new one
+    assert!(api
+        .spend_reservation(&reservation, &group_id, "bob")
+        .await
+        .is_err());
+    let retry = api
+        .reserve_key_package("alice", &group_id, "bob", bob_key)
+        .await
+        .expect("reserve again");
+    assert!(new_refs.contains(&retry.keypackage_ref));
+
+    server_handle.abort();
+}
+
 /// Integration Test 2: Multiple clients with same server
 #[tokio::test]
 async fn test_multiple_clients_same_server() {
@@ -942,3 +2057,88 @@ async fn test_refresh_time_tracking_survives_updates() {
     client.update_refresh_time();
     assert!(!client.should_refresh());
 }
+
+/// Test: scheduled refresh replenishes the pool after packages are consumed
+#[tokio::test]
+async fn test_scheduled_refresh_replenishes_consumed_pool() {
+    let pool = web::Data::new(mls_chat_server::db::create_test_pool());
+    let (server, addr) = mls_chat_server::server::create_test_http_server_with_pool(pool.clone())
+        .expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let mut client = MlsClient::new_with_storage_path(
+        &format!("http://{}", addr),
+        "scheduled_user",
+        "scheduled-group",
+        temp_dir.path(),
+    )
+    .expect("Failed to create client");
+    client.set_keypackage_pool_config(KeyPackagePoolConfig {
+        target_pool_size: 4,
+        low_watermark: 2,
+        hard_cap: 8,
+    });
+    client.set_refresh_period(Duration::from_millis(200));
+    client
+        .initialize()
+        .await
+        .expect("Initialization should succeed");
+
+    // Consume three packages (as invitations by other users would)
+    let available = client
+        .get_metadata_store()
+        .get_metadata_by_status("available")
+        .expect("Metadata query should succeed");
+    for metadata in available.iter().take(3) {
+        client
+            .get_metadata_store()
+            .update_pool_metadata_status(&metadata.keypackage_ref, "spent")
+            .expect("Status update should succeed");
+    }
+
+    // Not due yet: the scheduler leaves the pool alone
+    assert!(!client.refresh_if_due().await.expect("No-op should succeed"));
+
+    // Drive the scheduler until it has run once
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        tokio::time::sleep(client.next_refresh_delay()).await;
+        if client
+            .refresh_if_due()
+            .await
+            .expect("Refresh should succeed")
+        {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "Refresh never ran");
+    }
+
+    let local_available = client
+        .get_metadata_store()
+        .count_by_status("available")
+        .expect("Metadata count should succeed");
+    assert_eq!(local_available, 4, "Pool should be back at target size");
+    assert_eq!(client.get_refresh_failures(), 0);
+}
+
+/// Test: failed scheduled refreshes back off instead of waiting a full period
+#[tokio::test]
+async fn test_scheduled_refresh_backs_off_on_failure() {
+    let (mut client, _temp_dir) =
+        create_test_client_no_init("http://localhost:4000", "alice", "group");
+
+    // Without initialize() the refresh fails
+    assert!(client.refresh_if_due().await.is_err());
+    assert_eq!(client.get_refresh_failures(), 1);
+
+    // The retry is scheduled well before the 1 hour period
+    let delay = client.next_refresh_delay();
+    assert!(delay > Duration::ZERO);
+    assert!(delay <= Duration::from_secs(5));
+
+    // A short refresh period caps the backoff
+    client.set_refresh_period(Duration::from_millis(100));
+    assert!(client.next_refresh_delay() <= Duration::from_millis(100));
+}
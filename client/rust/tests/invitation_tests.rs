@@ -5,8 +5,11 @@
 ///
 /// Note: These tests spawn a test server via mls-chat-server to verify
 /// complete client-server integration for the invitation protocol.
+use mls_chat_client::api::{KeyPackageUpload, ServerApi};
 use mls_chat_client::client::MlsClient;
-use mls_chat_client::models::MlsMessageEnvelope;
+use mls_chat_client::crypto;
+use mls_chat_client::error::{ClientError, InviteStage};
+use mls_chat_client::models::{ContentType, MlsMessageEnvelope};
 use std::time::Duration;
 use tempfile::tempdir;
 use tls_codec::{Deserialize, Serialize};
@@ -144,6 +147,7 @@ fn test_commit_message_envelope_structure() {
         group_id: "testgroup".to_string(),
         sender: "alice".to_string(),
         commit_blob: "base64commitblob".to_string(),
+        device_id: None,
     };
 
     let json = serde_json::to_string(&commit_envelope).expect("Failed to serialize");
@@ -226,6 +230,9 @@ fn test_application_message_envelope_structure() {
         sender: "alice".to_string(),
         group_id: "testgroup".to_string(),
         encrypted_content: "base64encryptedtext".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     let json = serde_json::to_string(&app_envelope).expect("Failed to serialize");
@@ -354,6 +361,126 @@ async fn test_invitation_to_nonexistent_user_fails() {
 
     // Should fail because user doesn't exist on server
     assert!(result.is_err(), "Should fail to invite non-existent user");
+
+    // The failure is reported at the reservation stage, before any group change,
+    // with a hint that the invitee needs to come online
+    match result.unwrap_err() {
+        ClientError::Invite(err) => {
+            assert_eq!(err.stage, InviteStage::ReserveKeyPackage);
+            assert!(!err.group_state_advanced);
+            assert!(err.to_string().contains("ask them to come online"));
+        }
+        other => panic!("Expected invite error, got {:?}", other),
+    }
+    assert_eq!(alice.list_members(), vec!["alice".to_string()]);
+}
+
+/// Test 8b: Invitee KeyPackage that fails validation
+///
+/// Verifies that a malformed KeyPackage on the server is reported at the
+/// validation stage and leaves the group untouched
+#[tokio::test]
+async fn test_invitation_with_invalid_keypackage_reports_validate_stage() {
+    let (_server_handle, server_addr) = spawn_test_server().await;
+    let temp_dir_alice = tempdir().expect("Failed to create temp dir");
+
+    let mut alice =
+        MlsClient::new_with_storage_path(&server_addr, "alice", "testgroup", temp_dir_alice.path())
+            .expect("Failed to create Alice");
+    alice.initialize().await.expect("Failed to init");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("Failed to connect");
+
+    // Upload a KeyPackage that is not valid TLS for mallory
+    ServerApi::new(&server_addr)
+        .upload_key_packages(
+            "mallory",
+            &[KeyPackageUpload {
+                keypackage_ref: vec![0x01, 0x02],
+                keypackage: vec![0xde, 0xad, 0xbe, 0xef],
+                not_after: 9999999999,
+            }],
+        )
+        .await
+        .expect("Upload should succeed");
+
+    match alice.invite_user("mallory").await.unwrap_err() {
+        ClientError::Invite(err) => {
+            assert_eq!(err.invitee, "mallory");
+            assert_eq!(err.stage, InviteStage::ValidateKeyPackage);
+            assert!(!err.group_state_advanced);
+        }
+        other => panic!("Expected invite error, got {:?}", other),
+    }
+    assert_eq!(alice.list_members(), vec!["alice".to_string()]);
+}
+
+/// Test 8c: Invitee KeyPackage rejected when adding to the group
+///
+/// Uploads a valid KeyPackage bound to Alice's own signature key under another
+/// username; MLS refuses to add a duplicate signature key, so the failure is
+/// reported at the add-member stage without advancing the group, and the
+/// reservation is released
+#[tokio::test]
+async fn test_invitation_with_duplicate_member_key_reports_add_member_stage() {
+    let (_server_handle, server_addr) = spawn_test_server().await;
+    let temp_dir_alice = tempdir().expect("Failed to create temp dir");
+
+    let mut alice =
+        MlsClient::new_with_storage_path(&server_addr, "alice", "testgroup", temp_dir_alice.path())
+            .expect("Failed to create Alice");
+    alice.initialize().await.expect("Failed to init");
+    alice
+        .connect_to_group("testgroup")
+        .await
+        .expect("Failed to connect");
+
+    let alice_user = alice
+        .get_connection()
+        .get_user()
+        .expect("Alice initialized");
+    let key_package = crypto::generate_key_package_bundle(
+        alice_user.get_credential_with_key(),
+        alice_user.get_signature_key(),
+        alice.get_provider(),
+    )
+    .expect("Failed to generate key package");
+    let key_package_bytes = key_package
+        .key_package()
+        .tls_serialize_detached()
+        .expect("Failed to serialize key package");
+
+    ServerApi::new(&server_addr)
+        .upload_key_packages(
+            "mallory",
+            &[KeyPackageUpload {
+                keypackage_ref: vec![0x03, 0x04],
+                keypackage: key_package_bytes,
+                not_after: 9999999999,
+            }],
+        )
+        .await
+        .expect("Upload should succeed");
+
+    match alice.invite_user("mallory").await.unwrap_err() {
+        ClientError::Invite(err) => {
+            assert_eq!(err.stage, InviteStage::AddMember);
+            assert!(!err.group_state_advanced);
+        }
+        other => panic!("Expected invite error, got {:?}", other),
+    }
+    assert_eq!(alice.list_members(), vec!["alice".to_string()]);
+
+    // The aborted invite released its reservation
+    assert_eq!(
+        ServerApi::new(&server_addr)
+            .available_keypackages("mallory")
+            .await
+            .expect("Count should be available"),
+        1
+    );
 }
 
 /// Test 9: Welcome message includes all necessary information
@@ -394,6 +521,7 @@ fn test_commit_message_broadcast() {
         group_id: "mygroup".to_string(),
         sender: "alice".to_string(),
         commit_blob: "CommitAddingBob".to_string(),
+        device_id: None,
     };
 
     match commit {
@@ -401,6 +529,7 @@ fn test_commit_message_broadcast() {
             group_id,
             sender,
             commit_blob,
+            ..
         } => {
             // Fields should be valid
             assert_eq!(group_id, "mygroup");
@@ -439,6 +568,8 @@ fn test_list_members_no_group() {
         vec![] as Vec<String>,
         "Should return empty list when no group connected"
     );
+    assert_eq!(client.member_count(), 0);
+    assert!(client.list_members_paged(0, 10).is_empty());
 }
 
 /// Test 12: list_members() returns single member (creator)
@@ -154,6 +154,29 @@ fn mark_as_spent_updates_status() {
     assert_eq!(store.count_by_status("spent").unwrap(), 1);
 }
 
+#[test]
+fn stats_counts_each_status() {
+    let (store, _temp) = setup_store();
+    let pool = KeyPackagePool::new("alice", KeyPackagePoolConfig::default(), &store);
+    assert!(pool.stats().unwrap().is_empty());
+
+    for (name, status) in [("a", "available"), ("b", "available"), ("c", "spent")] {
+        store
+            .create_pool_metadata(name.as_bytes(), 2_000_000)
+            .unwrap();
+        store
+            .update_pool_metadata_status(name.as_bytes(), status)
+            .unwrap();
+    }
+    store.create_pool_metadata(b"d", 2_000_000).unwrap();
+
+    let stats = pool.stats().unwrap();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats["available"], 2);
+    assert_eq!(stats["spent"], 1);
+    assert_eq!(stats["created"], 1);
+}
+
 #[tokio::test]
 async fn cleanup_expired_removes_entries() {
     let (store, _temp) = setup_store();
@@ -9,6 +9,7 @@ use mls_chat_client::crypto;
 /// - Integration with MLS group state
 use mls_chat_client::message_processing::*;
 use mls_chat_client::provider::MlsProvider;
+use mls_chat_client::x509::TrustAnchors;
 use tempfile::tempdir;
 use tls_codec::{Deserialize, Serialize};
 
@@ -500,3 +501,131 @@ async fn test_message_processing_performance() {
         avg_time.as_millis()
     );
 }
+
+/// Test: large compressible messages roundtrip and are smaller on the wire
+#[tokio::test]
+async fn test_compressed_message_roundtrip_is_smaller_on_wire() {
+    use mls_chat_client::payload;
+
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let provider = MlsProvider::new(&db_path).unwrap();
+
+    let (alice_cred, alice_key) = crypto::generate_credential_with_key("alice").unwrap();
+    let mut alice_group =
+        crypto::create_group_with_config(&alice_cred, &alice_key, &provider, "testgroup").unwrap();
+
+    let (bob_cred, bob_key) = crypto::generate_credential_with_key("bob").unwrap();
+    let bob_key_package =
+        crypto::generate_key_package_bundle(&bob_cred, &bob_key, &provider).unwrap();
+    let (_commit, welcome, _group_info) = crypto::add_members(
+        &mut alice_group,
+        &provider,
+        &alice_key,
+        &[bob_key_package.key_package()],
+    )
+    .unwrap();
+    crypto::merge_pending_commit(&mut alice_group, &provider).unwrap();
+
+    let ratchet_tree = Some(crypto::export_ratchet_tree(&alice_group));
+    let join_config = openmls::prelude::MlsGroupJoinConfig::default();
+    let serialized = welcome.tls_serialize_detached().unwrap();
+    let welcome_in =
+        openmls::prelude::MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+    let mut bob_group =
+        crypto::process_welcome_message(&provider, &join_config, &welcome_in, ratchet_tree)
+            .unwrap();
+
+    // ~10KB of repetitive text
+    let large_message = "The quick brown fox jumps over the lazy dog. ".repeat(230);
+    assert!(large_message.len() > 10_000);
+
+    let mut wire_sizes = Vec::new();
+    for compress in [false, true] {
+        let plaintext = payload::encode(large_message.as_bytes(), compress).unwrap();
+        let encrypted =
+            crypto::create_application_message(&mut alice_group, &provider, &alice_key, &plaintext)
+                .unwrap();
+        let encrypted_b64 =
+            general_purpose::STANDARD.encode(encrypted.tls_serialize_detached().unwrap());
+        wire_sizes.push(encrypted_b64.len());
+
+        let result = process_application_message(
+            "alice",
+            "testgroup",
+            &encrypted_b64,
+            &mut bob_group,
+            &provider,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.as_deref(), Some(large_message.as_str()));
+    }
+
+    assert!(
+        wire_sizes[1] < wire_sizes[0],
+        "compressed message ({} bytes) should be smaller than uncompressed ({} bytes)",
+        wire_sizes[1],
+        wire_sizes[0]
+    );
+}
+
+/// Test: the markdown flag is encrypted with the message and survives decryption
+#[tokio::test]
+async fn test_markdown_message_roundtrip_keeps_format() {
+    use mls_chat_client::payload::{self, TextFormat};
+
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let provider = MlsProvider::new(&db_path).unwrap();
+
+    let (alice_cred, alice_key) = crypto::generate_credential_with_key("alice").unwrap();
+    let mut alice_group =
+        crypto::create_group_with_config(&alice_cred, &alice_key, &provider, "testgroup").unwrap();
+
+    let (bob_cred, bob_key) = crypto::generate_credential_with_key("bob").unwrap();
+    let bob_key_package =
+        crypto::generate_key_package_bundle(&bob_cred, &bob_key, &provider).unwrap();
+    let (_commit, welcome, _group_info) = crypto::add_members(
+        &mut alice_group,
+        &provider,
+        &alice_key,
+        &[bob_key_package.key_package()],
+    )
+    .unwrap();
+    crypto::merge_pending_commit(&mut alice_group, &provider).unwrap();
+
+    let ratchet_tree = Some(crypto::export_ratchet_tree(&alice_group));
+    let join_config = openmls::prelude::MlsGroupJoinConfig::default();
+    let serialized = welcome.tls_serialize_detached().unwrap();
+    let welcome_in =
+        openmls::prelude::MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+    let mut bob_group =
+        crypto::process_welcome_message(&provider, &join_config, &welcome_in, ratchet_tree)
+            .unwrap();
+
+    let text = "**ship it** after `cargo test`";
+    for format in [TextFormat::Markdown, TextFormat::Plain] {
+        let plaintext = payload::encode_text(text.as_bytes(), format, true).unwrap();
+        let encrypted =
+            crypto::create_application_message(&mut alice_group, &provider, &alice_key, &plaintext)
+                .unwrap();
+        let encrypted_b64 =
+            general_purpose::STANDARD.encode(encrypted.tls_serialize_detached().unwrap());
+
+        let result = decrypt_application_message(
+            "alice",
+            "testgroup",
+            &encrypted_b64,
+            &mut bob_group,
+            &provider,
+            &TrustAnchors::default(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.format, format);
+        assert_eq!(result.text, text);
+        assert_eq!(result.sender_identity, "alice");
+    }
+}
@@ -0,0 +1,104 @@
+/// Tests that decrypted message text stays out of the logs by default
+///
+/// This file installs its own logger, so it runs as a separate test binary.
+use clap::Parser;
+use mls_chat_client::cli::Args;
+use mls_chat_client::crypto;
+use mls_chat_client::message_processing::handle_processed_message;
+use mls_chat_client::models::IncomingMessage;
+use mls_chat_client::provider::MlsProvider;
+use std::sync::Mutex;
+use tempfile::tempdir;
+use tls_codec::{Deserialize, Serialize};
+
+const SECRET: &str = "the launch code is 0000";
+
+/// Logger that keeps every formatted record in memory
+struct CaptureLogger {
+    lines: Mutex<Vec<String>>,
+}
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.lines.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger {
+    lines: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn test_plaintext_not_in_logs_by_default() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let temp_dir = tempdir().unwrap();
+    let provider = MlsProvider::new(temp_dir.path().join("test.db")).unwrap();
+
+    // Alice creates a group and adds Bob
+    let (alice_cred, alice_key) = crypto::generate_credential_with_key("alice").unwrap();
+    let mut alice_group =
+        crypto::create_group_with_config(&alice_cred, &alice_key, &provider, "testgroup").unwrap();
+    let (bob_cred, bob_key) = crypto::generate_credential_with_key("bob").unwrap();
+    let bob_key_package =
+        crypto::generate_key_package_bundle(&bob_cred, &bob_key, &provider).unwrap();
+    let (_commit, welcome, _group_info) = crypto::add_members(
+        &mut alice_group,
+        &provider,
+        &alice_key,
+        &[bob_key_package.key_package()],
+    )
+    .unwrap();
+    crypto::merge_pending_commit(&mut alice_group, &provider).unwrap();
+
+    let ratchet_tree = Some(crypto::export_ratchet_tree(&alice_group));
+    let join_config = openmls::prelude::MlsGroupJoinConfig::default();
+    let serialized = welcome.tls_serialize_detached().unwrap();
+    let welcome_in =
+        openmls::prelude::MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+    let mut bob_group =
+        crypto::process_welcome_message(&provider, &join_config, &welcome_in, ratchet_tree)
+            .unwrap();
+
+    // Bob decrypts a message from Alice with debug logging on
+    let encrypted = crypto::create_application_message(
+        &mut alice_group,
+        &provider,
+        &alice_key,
+        SECRET.as_bytes(),
+    )
+    .unwrap();
+    let serialized = encrypted.tls_serialize_detached().unwrap();
+    let message_in =
+        openmls::prelude::MlsMessageIn::tls_deserialize(&mut serialized.as_slice()).unwrap();
+    let processed = crypto::process_message(&mut bob_group, &provider, &message_in).unwrap();
+    let envelope = IncomingMessage {
+        sender: "alice".to_string(),
+        group_id: "testgroup".to_string(),
+        encrypted_content: String::new(),
+    };
+    let text = handle_processed_message(&envelope, processed).unwrap();
+    assert_eq!(text.as_deref(), Some(SECRET));
+
+    // The command line of `send` is logged at startup
+    let command = Args::try_parse_from(["mls-client", "send", "testgroup", "alice", SECRET])
+        .unwrap()
+        .into_command()
+        .unwrap();
+    log::info!("Command: {:?}", command.redacted());
+
+    let lines = LOGGER.lines.lock().unwrap();
+    assert!(lines.iter().any(|line| line.contains("bytes redacted")));
+    assert!(
+        lines.iter().all(|line| !line.contains(SECRET)),
+        "plaintext found in logs: {:?}",
+        *lines
+    );
+}
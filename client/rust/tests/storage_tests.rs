@@ -271,6 +271,32 @@ fn test_delete_pool_metadata_nonexistent() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_expire_pool_metadata_skips_spent() {
+    let (store, _temp) = setup_store();
+
+    store
+        .create_pool_metadata(b"available", 1900000000)
+        .unwrap();
+    store
+        .update_pool_metadata_status(b"available", "available")
+        .unwrap();
+    store.create_pool_metadata(b"spent", 1900000000).unwrap();
+    store
+        .update_pool_metadata_status(b"spent", "spent")
+        .unwrap();
+
+    store.expire_pool_metadata(b"available").unwrap();
+    store.expire_pool_metadata(b"spent").unwrap();
+
+    assert_eq!(store.count_by_status("expired").unwrap(), 1);
+    assert_eq!(store.count_by_status("spent").unwrap(), 1);
+    assert_eq!(
+        store.get_expired_refs(1700000000).unwrap(),
+        vec![b"available".to_vec()]
+    );
+}
+
 #[test]
 fn test_update_reservation_info() {
     let (store, _temp) = setup_store();
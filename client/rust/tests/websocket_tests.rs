@@ -4,13 +4,15 @@
 /// subscriptions, message sending/receiving, and persistence.
 use mls_chat_client::api::ServerApi;
 use mls_chat_client::crypto;
-use mls_chat_client::models::MlsMessageEnvelope;
-use mls_chat_client::websocket::MessageHandler;
+use mls_chat_client::models::{ContentType, MlsMessageEnvelope};
+use mls_chat_client::websocket::{ConnectionState, MessageHandler};
+use openmls_basic_credential::SignatureKeyPair;
 use std::time::Duration;
 use tls_codec::Serialize;
 
-/// Helper function to generate a valid KeyPackage for testing
-fn generate_test_key_package(username: &str) -> Vec<u8> {
+/// Helper function to generate a valid KeyPackage for testing, with the
+/// signature key that signs the user's connections
+fn generate_test_key_package(username: &str) -> (Vec<u8>, SignatureKeyPair) {
     // Create a temporary provider for key package generation
     use tempfile::tempdir;
 
@@ -28,10 +30,11 @@ fn generate_test_key_package(username: &str) -> Vec<u8> {
         .expect("Failed to generate key package");
 
     // Serialize using TLS codec
-    key_package_bundle
+    let key_package = key_package_bundle
         .key_package()
         .tls_serialize_detached()
-        .expect("Failed to serialize key package")
+        .expect("Failed to serialize key package");
+    (key_package, sig_key)
 }
 
 #[tokio::test]
@@ -46,15 +49,22 @@ async fn test_websocket_connect() {
 
     // Register a user via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("User registration should succeed");
 
     // Connect to WebSocket as alice
-    let _handler = MessageHandler::connect(&addr, "alice")
+    let _handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Should connect to WebSocket");
+
+    // A key other than the registered one is refused
+    let (_, other_key) =
+        crypto::generate_credential_with_key("alice").expect("Failed to generate credential");
+    assert!(MessageHandler::connect(&addr, "alice", &other_key)
+        .await
+        .is_err());
 }
 
 #[tokio::test]
@@ -69,13 +79,13 @@ async fn test_subscribe_to_group() {
 
     // Register a user via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("User registration should succeed");
 
     // Connect to WebSocket and subscribe to group
-    let handler = MessageHandler::connect(&addr, "alice")
+    let handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Should connect to WebSocket");
 
@@ -97,13 +107,13 @@ async fn test_send_message_via_websocket() {
 
     // Register a user via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("User registration should succeed");
 
     // Connect to WebSocket, subscribe, and send envelope
-    let handler = MessageHandler::connect(&addr, "alice")
+    let handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Should connect to WebSocket");
 
@@ -117,6 +127,9 @@ async fn test_send_message_via_websocket() {
         sender: "alice".to_string(),
         group_id: "testgroup_base64_encoded_id".to_string(),
         encrypted_content: "encrypted_message_content".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     handler
@@ -138,8 +151,8 @@ async fn test_two_clients_exchange_messages() {
 
     // Register two users via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
-    let bob_key_package = generate_test_key_package("bob");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
+    let (bob_key_package, bob_key) = generate_test_key_package("bob");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("Alice registration should succeed");
@@ -147,8 +160,15 @@ async fn test_two_clients_exchange_messages() {
         .await
         .expect("Bob registration should succeed");
 
+    // The server only honors subscriptions from group members
+    for username in ["alice", "bob"] {
+        mls_chat_server::db::Database::add_group_member(&pool, username, "testgroup")
+            .await
+            .expect("Membership should be recorded");
+    }
+
     // Alice connects and subscribes to testgroup
-    let mut alice_handler = MessageHandler::connect(&addr, "alice")
+    let mut alice_handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Alice should connect to WebSocket");
 
@@ -158,7 +178,7 @@ async fn test_two_clients_exchange_messages() {
         .expect("Alice should subscribe to testgroup");
 
     // Bob connects and subscribes to testgroup
-    let bob_handler = MessageHandler::connect(&addr, "bob")
+    let bob_handler = MessageHandler::connect(&addr, "bob", &bob_key)
         .await
         .expect("Bob should connect to WebSocket");
 
@@ -172,6 +192,9 @@ async fn test_two_clients_exchange_messages() {
         sender: "bob".to_string(),
         group_id: "testgroup".to_string(),
         encrypted_content: "hello_from_bob".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     bob_handler
@@ -196,8 +219,16 @@ async fn test_two_clients_exchange_messages() {
             sender,
             group_id,
             encrypted_content,
+            content_type,
+            seq,
+            epoch: _,
         } => {
             assert_eq!(sender, "bob", "Sender should be bob");
+            assert_eq!(content_type, ContentType::Text);
+            assert!(
+                seq.is_some(),
+                "Server should add the stored sequence number"
+            );
             assert_eq!(group_id, "testgroup", "Group should be testgroup");
             assert_eq!(encrypted_content, "hello_from_bob", "Content should match");
         }
@@ -243,13 +274,13 @@ async fn test_multiple_groups_isolation() {
 
     // Register user via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("User registration should succeed");
 
     // Alice connects and subscribes to two groups
-    let handler = MessageHandler::connect(&addr, "alice")
+    let handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Should connect to WebSocket");
 
@@ -268,12 +299,18 @@ async fn test_multiple_groups_isolation() {
         sender: "alice".to_string(),
         group_id: "group1".to_string(),
         encrypted_content: "message_for_group1".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     let group2_envelope = MlsMessageEnvelope::ApplicationMessage {
         sender: "alice".to_string(),
         group_id: "group2".to_string(),
         encrypted_content: "message_for_group2".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     handler
@@ -357,13 +394,13 @@ async fn test_message_persistence() {
 
     // Register user via HTTP
     let api = ServerApi::new(&format!("http://{}", addr));
-    let alice_key_package = generate_test_key_package("alice");
+    let (alice_key_package, alice_key) = generate_test_key_package("alice");
     api.register_user("alice", &alice_key_package)
         .await
         .expect("User registration should succeed");
 
     // Connect to WebSocket, subscribe, and send envelope
-    let handler = MessageHandler::connect(&addr, "alice")
+    let handler = MessageHandler::connect(&addr, "alice", &alice_key)
         .await
         .expect("Should connect to WebSocket");
 
@@ -378,6 +415,9 @@ async fn test_message_persistence() {
         sender: "alice".to_string(),
         group_id: "persistent_group".to_string(), // Must match the group we subscribed to
         encrypted_content: "message_to_persist".to_string(),
+        content_type: ContentType::Text,
+        seq: None,
+        epoch: None,
     };
 
     handler
@@ -410,3 +450,136 @@ async fn test_message_persistence() {
     );
     assert_eq!(messages[0].sender_id, user.id, "Sender ID should match");
 }
+
+#[tokio::test]
+async fn test_server_close_reports_disconnected_state() {
+    // Minimal WebSocket server that accepts one client and closes it on demand
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().unwrap();
+    let (close_tx, close_rx) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _ = close_rx.await;
+        let _ = ws.close(None).await;
+    });
+
+    let (_, alice_key) =
+        crypto::generate_credential_with_key("alice").expect("Failed to generate credential");
+    let mut handler = MessageHandler::connect(&addr.to_string(), "alice", &alice_key)
+        .await
+        .expect("Should connect");
+    let mut state = handler.watch_state();
+    assert_eq!(*state.borrow_and_update(), ConnectionState::Connected);
+
+    close_tx.send(()).unwrap();
+
+    // The change is pushed to watchers, no polling of the handler needed
+    tokio::time::timeout(Duration::from_secs(5), state.changed())
+        .await
+        .expect("state change should be delivered")
+        .expect("state sender alive");
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+    assert!(handler.next_envelope().await.unwrap().is_none());
+}
+
+/// Test that state callbacks see every transition of a dropped and
+/// reconnected connection, with the reconnection timed by the backoff
+#[tokio::test]
+async fn test_state_callbacks_follow_drop_and_reconnect() {
+    use mls_chat_client::mls::connection::MlsConnection;
+    use mls_chat_client::websocket::{ReconnectBackoff, ReconnectSchedule};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::Instant;
+
+    // WebSocket server that closes the first client on demand and keeps
+    // later ones open
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().unwrap();
+    let (drop_tx, drop_rx) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        // Plain HTTP requests (from `initialize`) are not upgrades; skip them
+        let mut first = loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                break ws;
+            }
+        };
+        tokio::spawn(async move {
+            let _ = drop_rx.await;
+            let _ = first.close(None).await;
+        });
+        let mut kept = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                kept.push(ws);
+            }
+        }
+    });
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut connection =
+        MlsConnection::new_with_storage_path(&format!("http://{}", addr), "alice", temp_dir.path())
+            .unwrap();
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let recorder = transitions.clone();
+    connection.on_connection_state_change(move |previous, current| {
+        recorder.lock().unwrap().push((previous, current));
+    });
+    let mut state = connection.watch_connection_state();
+    let mut schedule = ReconnectSchedule::new(ReconnectBackoff {
+        initial_delay: Duration::from_secs(2),
+        ..ReconnectBackoff::default()
+    });
+
+    // There is no server to register with, but the identity that signs the
+    // connection is kept
+    assert!(connection.initialize().await.is_err());
+    assert!(connection.get_user().is_some());
+
+    connection
+        .connect_websocket()
+        .await
+        .expect("Should connect");
+    schedule.on_state(connection.connection_state(), Instant::now());
+
+    // Simulated drop: the server closes the socket
+    drop_tx.send(()).unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        state.wait_for(|state| *state == ConnectionState::Disconnected),
+    )
+    .await
+    .expect("drop should be reported")
+    .expect("state sender alive");
+
+    // The clock is stepped instead of slept: the attempt is due after the
+    // initial delay, not before
+    let lost_at = Instant::now();
+    schedule.on_state(ConnectionState::Disconnected, lost_at);
+    assert!(!schedule.is_due(lost_at + Duration::from_secs(1)));
+    assert!(schedule.is_due(lost_at + Duration::from_secs(2)));
+    schedule.attempt_started();
+    connection
+        .connect_websocket()
+        .await
+        .expect("Should reconnect");
+    schedule.on_state(connection.connection_state(), Instant::now());
+    assert_eq!(schedule.next_attempt(), None);
+
+    use ConnectionState::*;
+    assert_eq!(
+        *transitions.lock().unwrap(),
+        vec![
+            (Disconnected, Connecting),
+            (Connecting, Connected),
+            (Connected, Disconnected),
+            (Disconnected, Reconnecting),
+            (Reconnecting, Connected),
+        ]
+    );
+}
@@ -11,9 +11,10 @@ pub struct Config {
     #[arg(long, default_value = "4000")]
     pub port: u16,
 
-    /// SQLite database file path (default: chatserver.db)
+    /// SQLite database to use: a file path or `sqlite://` URL
+    /// (default: chatserver.db)
     #[arg(long, default_value = "chatserver.db")]
-    pub database: PathBuf,
+    pub database: String,
 
     /// PID file path (optional) - write server PID to this file on startup
     #[arg(long)]
@@ -22,6 +23,41 @@ pub struct Config {
     /// KeyPackage reservation timeout in seconds (default: 60)
     #[arg(long, default_value = "60")]
     pub reservation_timeout_seconds: i64,
+
+    /// Maximum unspent KeyPackages stored per user (default: 128)
+    #[arg(long, default_value = "128")]
+    pub max_keypackages_per_user: usize,
+
+    /// Maximum groups a user can be a member of (default: 100)
+    #[arg(long, default_value = "100")]
+    pub max_groups_per_user: usize,
+
+    /// How often expired messages are pruned, in seconds (default: 60)
+    #[arg(long, default_value = "60")]
+    pub message_prune_interval_seconds: u64,
+
+    /// Backup versions kept per user (default: 10)
+    #[arg(long, default_value = "10")]
+    pub max_backup_versions: usize,
+
+    /// How long a stored Welcome waits for its invitee, in seconds (default: 7 days)
+    #[arg(long, default_value = "604800")]
+    pub welcome_ttl_seconds: i64,
+
+    /// Concurrent WebSocket connections allowed per user (default: 5)
+    #[arg(long, default_value = "5")]
+    pub max_connections_per_user: usize,
+
+    /// Broadcast each group message as it comes instead of through the
+    /// group's ordered stream; subscribers may then see concurrent messages
+    /// in different orders
+    #[arg(long)]
+    pub unordered_fan_out: bool,
+
+    /// User allowed to list a group's messages by sender for moderation
+    /// (repeat for several; default: none)
+    #[arg(long = "moderator", value_name = "USERNAME")]
+    pub moderators: Vec<String>,
 }
 
 impl Config {
@@ -29,6 +65,28 @@ impl Config {
     pub fn from_args() -> Self {
         Config::parse()
     }
+
+    /// SQLite file named by `--database`
+    ///
+    /// Anything without a `scheme://` prefix is a file path, so existing
+    /// `--database file.db` invocations keep working. SQLite is the only
+    /// backend: any scheme but `sqlite://` is an error.
+    pub fn database_path(&self) -> Result<PathBuf, String> {
+        let Some((scheme, rest)) = self.database.split_once("://") else {
+            if self.database.is_empty() {
+                return Err("Database path is empty".to_string());
+            }
+            return Ok(PathBuf::from(&self.database));
+        };
+        match scheme {
+            "sqlite" if rest.is_empty() => Err("sqlite:// URL has no file path".to_string()),
+            "sqlite" => Ok(PathBuf::from(rest)),
+            _ => Err(format!(
+                "Unsupported database scheme '{}' (only SQLite is supported: a path or sqlite:// URL)",
+                scheme
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -39,12 +97,20 @@ mod tests {
     fn test_default_config() {
         let config = Config {
             port: 4000,
-            database: PathBuf::from("chatserver.db"),
+            database: "chatserver.db".to_string(),
             pidfile: None,
             reservation_timeout_seconds: 60,
+            max_keypackages_per_user: 128,
+            max_groups_per_user: 100,
+            message_prune_interval_seconds: 60,
+            max_backup_versions: 10,
+            welcome_ttl_seconds: 604800,
+            max_connections_per_user: 5,
+            unordered_fan_out: false,
+            moderators: Vec::new(),
         };
         assert_eq!(config.port, 4000);
-        assert_eq!(config.database.to_str().unwrap(), "chatserver.db");
+        assert_eq!(config.database, "chatserver.db");
         assert_eq!(config.reservation_timeout_seconds, 60);
     }
 
@@ -52,9 +118,17 @@ mod tests {
     fn test_custom_port() {
         let config = Config {
             port: 8080,
-            database: PathBuf::from("chatserver.db"),
+            database: "chatserver.db".to_string(),
             pidfile: None,
             reservation_timeout_seconds: 60,
+            max_keypackages_per_user: 128,
+            max_groups_per_user: 100,
+            message_prune_interval_seconds: 60,
+            max_backup_versions: 10,
+            welcome_ttl_seconds: 604800,
+            max_connections_per_user: 5,
+            unordered_fan_out: false,
+            moderators: Vec::new(),
         };
         assert_eq!(config.port, 8080);
     }
@@ -63,10 +137,52 @@ mod tests {
     fn test_custom_database() {
         let config = Config {
             port: 4000,
-            database: PathBuf::from("/tmp/custom.db"),
+            database: "/tmp/custom.db".to_string(),
             pidfile: None,
             reservation_timeout_seconds: 60,
+            max_keypackages_per_user: 128,
+            max_groups_per_user: 100,
+            message_prune_interval_seconds: 60,
+            max_backup_versions: 10,
+            welcome_ttl_seconds: 604800,
+            max_connections_per_user: 5,
+            unordered_fan_out: false,
+            moderators: Vec::new(),
         };
-        assert_eq!(config.database.to_str().unwrap(), "/tmp/custom.db");
+        assert_eq!(config.database, "/tmp/custom.db");
+    }
+
+    #[test]
+    fn test_database_path_from_database_argument() {
+        let with_database = |database: &str| Config {
+            port: 4000,
+            database: database.to_string(),
+            pidfile: None,
+            reservation_timeout_seconds: 60,
+            max_keypackages_per_user: 128,
+            max_groups_per_user: 100,
+            message_prune_interval_seconds: 60,
+            max_backup_versions: 10,
+            welcome_ttl_seconds: 604800,
+            max_connections_per_user: 5,
+            unordered_fan_out: false,
+            moderators: Vec::new(),
+        };
+
+        assert_eq!(
+            with_database("chatserver.db").database_path(),
+            Ok(PathBuf::from("chatserver.db"))
+        );
+        assert_eq!(
+            with_database("sqlite:///var/lib/chat.db").database_path(),
+            Ok(PathBuf::from("/var/lib/chat.db"))
+        );
+
+        assert!(with_database("").database_path().is_err());
+        assert!(with_database("sqlite://").database_path().is_err());
+        assert!(with_database("postgres://chat@db.internal/chat")
+            .database_path()
+            .unwrap_err()
+            .contains("postgres"));
     }
 }
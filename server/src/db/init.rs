@@ -10,6 +10,61 @@ pub fn initialize_database(conn: &Connection) -> SqliteResult<()> {
 
     // Create tables
     create_schema(conn)?;
+    migrate_schema(conn)?;
+
+    Ok(())
+}
+
+/// Bring databases created by older versions up to the current schema
+fn migrate_schema(conn: &Connection) -> SqliteResult<()> {
+    // messages.content_type: legacy rows were all chat messages
+    let has_content_type = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'content_type'")?
+        .exists([])?;
+    if !has_content_type {
+        conn.execute(
+            "ALTER TABLE messages ADD COLUMN content_type TEXT NOT NULL DEFAULT 'text'",
+            [],
+        )?;
+    }
+
+    // groups.message_ttl_secs / messages.expires_at: NULL means kept forever
+    let has_message_ttl = conn
+        .prepare("SELECT 1 FROM pragma_table_info('groups') WHERE name = 'message_ttl_secs'")?
+        .exists([])?;
+    if !has_message_ttl {
+        conn.execute("ALTER TABLE groups ADD COLUMN message_ttl_secs INTEGER", [])?;
+    }
+    let has_expires_at = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'expires_at'")?
+        .exists([])?;
+    if !has_expires_at {
+        conn.execute("ALTER TABLE messages ADD COLUMN expires_at INTEGER", [])?;
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages(expires_at)",
+        [],
+    )?;
+
+    // backups.version: number legacy backups of each user in insertion order
+    let has_backup_version = conn
+        .prepare("SELECT 1 FROM pragma_table_info('backups') WHERE name = 'version'")?
+        .exists([])?;
+    if !has_backup_version {
+        conn.execute(
+            "ALTER TABLE backups ADD COLUMN version INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+        conn.execute(
+            "UPDATE backups SET version = (SELECT COUNT(*) FROM backups AS older
+                 WHERE older.username = backups.username AND older.id <= backups.id)",
+            [],
+        )?;
+    }
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_backups_username_version ON backups(username, version)",
+        [],
+    )?;
 
     Ok(())
 }
@@ -29,15 +84,63 @@ fn create_schema(conn: &Connection) -> SqliteResult<()> {
             id INTEGER PRIMARY KEY,
             group_id TEXT UNIQUE NOT NULL,
             name TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            message_ttl_secs INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS group_names (
+            name TEXT PRIMARY KEY,
+            group_id TEXT NOT NULL,
             created_at TEXT NOT NULL
         );
 
+        CREATE TABLE IF NOT EXISTS group_members (
+            username TEXT NOT NULL,
+            group_id TEXT NOT NULL,
+            joined_at TEXT NOT NULL,
+            PRIMARY KEY (username, group_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS group_commits (
+            group_id TEXT NOT NULL,
+            epoch INTEGER NOT NULL,
+            sender TEXT NOT NULL,
+            commit_blob TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (group_id, epoch)
+        );
+
+        CREATE TABLE IF NOT EXISTS group_infos (
+            group_id TEXT PRIMARY KEY,
+            epoch INTEGER NOT NULL,
+            group_info_blob TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS group_epochs (
+            group_id TEXT PRIMARY KEY,
+            epoch INTEGER NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS pending_welcomes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            invitee TEXT NOT NULL,
+            inviter TEXT NOT NULL,
+            welcome_blob TEXT NOT NULL,
+            ratchet_tree_blob TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+
         CREATE TABLE IF NOT EXISTS messages (
             id INTEGER PRIMARY KEY,
             group_id INTEGER NOT NULL,
             sender_id INTEGER NOT NULL,
             encrypted_content TEXT NOT NULL,
+            content_type TEXT NOT NULL DEFAULT 'text',
             timestamp TEXT NOT NULL,
+            expires_at INTEGER,
             FOREIGN KEY(group_id) REFERENCES groups(id),
             FOREIGN KEY(sender_id) REFERENCES users(id)
         );
@@ -45,15 +148,40 @@ fn create_schema(conn: &Connection) -> SqliteResult<()> {
         CREATE TABLE IF NOT EXISTS backups (
             id INTEGER PRIMARY KEY,
             username TEXT NOT NULL,
+            version INTEGER NOT NULL,
             encrypted_state TEXT NOT NULL,
             timestamp TEXT NOT NULL,
-            UNIQUE(username, timestamp),
+            UNIQUE(username, version),
             FOREIGN KEY(username) REFERENCES users(username)
         );
 
+        CREATE TABLE IF NOT EXISTS deferred_messages (
+            username TEXT NOT NULL,
+            group_id TEXT NOT NULL,
+            message_id INTEGER NOT NULL,
+            deferred_at TEXT NOT NULL,
+            PRIMARY KEY (username, group_id, message_id)
+        );
+
         CREATE INDEX IF NOT EXISTS idx_messages_group ON messages(group_id);
         CREATE INDEX IF NOT EXISTS idx_messages_sender ON messages(sender_id);
+        CREATE INDEX IF NOT EXISTS idx_messages_group_sender ON messages(group_id, sender_id);
         CREATE INDEX IF NOT EXISTS idx_backups_username ON backups(username);
+        CREATE INDEX IF NOT EXISTS idx_pending_welcomes_invitee ON pending_welcomes(invitee);
+
+        CREATE TABLE IF NOT EXISTS join_tokens (
+            token TEXT PRIMARY KEY,
+            group_id TEXT NOT NULL,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            revoked INTEGER NOT NULL DEFAULT 0,
+            redeemed_by TEXT,
+            redeemed_at INTEGER,
+            completed INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_join_tokens_group ON join_tokens(group_id);
 
         CREATE TABLE IF NOT EXISTS keypackages (
             keypackage_ref BLOB NOT NULL,
@@ -145,4 +273,36 @@ mod tests {
         // In-memory databases don't support WAL, but query should not fail
         assert!(!journal_mode.is_empty());
     }
+
+    #[test]
+    fn test_migration_adds_content_type_to_legacy_messages() {
+        let conn = Connection::open_in_memory().expect("Failed to open in-memory DB");
+        conn.execute_batch(
+            r#"
+            CREATE TABLE messages (
+                id INTEGER PRIMARY KEY,
+                group_id INTEGER NOT NULL,
+                sender_id INTEGER NOT NULL,
+                encrypted_content TEXT NOT NULL,
+                timestamp TEXT NOT NULL
+            );
+            INSERT INTO messages (group_id, sender_id, encrypted_content, timestamp)
+                VALUES (1, 1, 'legacy', '2025-10-20T10:00:00Z');
+            "#,
+        )
+        .expect("Failed to create legacy schema");
+
+        initialize_database(&conn).expect("Failed to migrate DB");
+        // Running again is a no-op
+        initialize_database(&conn).expect("Failed to re-run migration");
+
+        let content_type: String = conn
+            .query_row(
+                "SELECT content_type FROM messages WHERE encrypted_content = 'legacy'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Query failed");
+        assert_eq!(content_type, "text");
+    }
 }
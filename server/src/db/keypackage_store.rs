@@ -82,6 +82,22 @@ pub struct ReservedKeyPackage {
     pub not_after: i64,
 }
 
+/// The invite a released reservation was held for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleasedReservation {
+    /// Owner of the KeyPackage, i.e. the invitee
+    pub username: String,
+    pub group_id: Vec<u8>,
+}
+
+/// Lifecycle metadata of one stored KeyPackage (never its bytes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyPackageSummary {
+    pub keypackage_ref: Vec<u8>,
+    pub status: KeyPackageStatus,
+    pub not_after: i64,
+}
+
 /// KeyPackage pool storage operations
 pub struct KeyPackageStore;
 
@@ -194,34 +210,37 @@ impl KeyPackageStore {
     }
 
     /// Spend a KeyPackage (mark as used)
+    /// Returns the username of the KeyPackage's owner (the invitee)
     /// Returns error if key is already spent or doesn't exist
     pub async fn spend_key_package(
         pool: &DbPool,
         keypackage_ref: &[u8],
         group_id: &[u8],
         spent_by: &str,
-    ) -> SqliteResult<()> {
+    ) -> SqliteResult<String> {
         let conn = pool.lock().await;
 
         // First check current status
-        let mut stmt = conn.prepare("SELECT status FROM keypackages WHERE keypackage_ref = ?1")?;
+        let mut stmt =
+            conn.prepare("SELECT status, username FROM keypackages WHERE keypackage_ref = ?1")?;
 
-        let current_status: Option<String> = stmt
-            .query_row(params![keypackage_ref], |row| row.get(0))
+        let current: Option<(String, String)> = stmt
+            .query_row(params![keypackage_ref], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
             .optional()?;
 
-        match current_status {
+        let owner = match current {
             None => {
                 return Err(rusqlite::Error::QueryReturnedNoRows);
             }
-            Some(status) if status == KeyPackageStatus::Spent.as_str() => {
+            Some((status, _)) if status == KeyPackageStatus::Spent.as_str() => {
                 // Already spent - this is an error (double-spend attempt)
                 return Err(rusqlite::Error::ExecuteReturnedResults);
             }
-            _ => {
-                // Available or Reserved - OK to spend
-            }
-        }
+            // Available or Reserved - OK to spend
+            Some((_, owner)) => owner,
+        };
 
         let spent_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -241,7 +260,46 @@ impl KeyPackageStore {
             ],
         )?;
 
-        Ok(())
+        Ok(owner)
+    }
+
+    /// Release a reservation so the KeyPackage can be reserved again
+    ///
+    /// Returns the invitee and group of the released reservation, or `None`
+    /// if nothing was released. Releasing a reservation that was already
+    /// spent, released or expired is a no-op: a spent KeyPackage is never
+    /// made available again.
+    pub async fn release_reservation(
+        pool: &DbPool,
+        reservation_id: &str,
+    ) -> SqliteResult<Option<ReleasedReservation>> {
+        let conn = pool.lock().await;
+        let reserved = conn
+            .query_row(
+                "SELECT username, group_id FROM keypackages
+                 WHERE reservation_id = ?1 AND status = ?2",
+                params![reservation_id, KeyPackageStatus::Reserved.as_str()],
+                |row| {
+                    Ok(ReleasedReservation {
+                        username: row.get(0)?,
+                        group_id: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        if reserved.is_some() {
+            conn.execute(
+                "UPDATE keypackages
+                 SET status = ?1, reservation_id = NULL, reservation_expires_at = NULL, reserved_by = NULL, group_id = NULL
+                 WHERE reservation_id = ?2 AND status = ?3",
+                params![
+                    KeyPackageStatus::Available.as_str(),
+                    reservation_id,
+                    KeyPackageStatus::Reserved.as_str(),
+                ],
+            )?;
+        }
+        Ok(reserved)
     }
 
     /// Internal helper to release expired reservations (synchronous, optionally filtered by username)
@@ -296,6 +354,115 @@ impl KeyPackageStore {
         let count: i64 = stmt.query_row(params![username, status.as_str()], |row| row.get(0))?;
         Ok(count as usize)
     }
+
+    /// Count a user's KeyPackages that can currently be reserved
+    /// Releases the user's expired reservations first and ignores expired packages.
+    pub async fn count_available(pool: &DbPool, username: &str) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        Self::release_expired_reservations_sync(&conn, Some(username))?;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = ?2 AND not_after > ?3",
+            params![username, KeyPackageStatus::Available.as_str(), now],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Count the KeyPackages a user currently holds (available or reserved)
+    /// Spent KeyPackages are kept for double-spend detection and do not count.
+    pub async fn count_unspent(pool: &DbPool, username: &str) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status != ?2",
+            params![username, KeyPackageStatus::Spent.as_str()],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Delete a user's expired, unspent KeyPackages (based on not_after timestamp)
+    /// Returns the number of keys removed
+    pub async fn sweep_expired_for_user(pool: &DbPool, username: &str) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let deleted = conn.execute(
+            "DELETE FROM keypackages WHERE username = ?1 AND status != ?2 AND not_after <= ?3",
+            params![username, KeyPackageStatus::Spent.as_str(), now],
+        )?;
+
+        Ok(deleted)
+    }
+
+    /// Delete the given unspent KeyPackages of a user (available or reserved)
+    /// Refs owned by another user, unknown or already spent are left alone;
+    /// a revoked reservation can no longer be spent.
+    /// Returns the number of keys removed
+    pub async fn revoke_for_user(
+        pool: &DbPool,
+        username: &str,
+        keypackage_refs: &[Vec<u8>],
+    ) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+
+        let mut stmt = conn.prepare(
+            "DELETE FROM keypackages WHERE keypackage_ref = ?1 AND username = ?2 AND status != ?3",
+        )?;
+
+        let mut deleted = 0;
+        for keypackage_ref in keypackage_refs {
+            deleted += stmt.execute(params![
+                keypackage_ref,
+                username,
+                KeyPackageStatus::Spent.as_str()
+            ])?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// List the refs, statuses and expiry of all of a user's KeyPackages
+    /// Releases the user's expired reservations first so statuses are current.
+    /// Ordered by upload time, then ref.
+    pub async fn list_for_user(
+        pool: &DbPool,
+        username: &str,
+    ) -> SqliteResult<Vec<KeyPackageSummary>> {
+        let conn = pool.lock().await;
+
+        Self::release_expired_reservations_sync(&conn, Some(username))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT keypackage_ref, status, not_after
+             FROM keypackages
+             WHERE username = ?1
+             ORDER BY uploaded_at ASC, keypackage_ref ASC",
+        )?;
+
+        let rows = stmt.query_map(params![username], |row| {
+            let status_str: String = row.get(1)?;
+            let status =
+                KeyPackageStatus::from_str(&status_str).unwrap_or(KeyPackageStatus::Available);
+
+            Ok(KeyPackageSummary {
+                keypackage_ref: row.get(0)?,
+                status,
+                not_after: row.get(2)?,
+            })
+        })?;
+
+        rows.collect()
+    }
 }
 
 #[cfg(test)]
@@ -510,6 +677,85 @@ mod tests {
         assert_eq!(data.status, KeyPackageStatus::Spent);
     }
 
+    #[tokio::test]
+    async fn test_release_reservation_vs_spend() {
+        let pool = create_test_pool();
+        KeyPackageStore::initialize_schema(&pool).await.unwrap();
+        for keypackage_ref in [[0x01], [0x02]] {
+            KeyPackageStore::save_key_package(
+                &pool,
+                "bob",
+                &keypackage_ref,
+                &[0x10],
+                9999999999,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+
+        // Reserve then release: the KeyPackage is available again
+        let released =
+            KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[0xaa], "alice", 60)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            KeyPackageStore::release_reservation(&pool, &released.reservation_id)
+                .await
+                .unwrap(),
+            Some(ReleasedReservation {
+                username: "bob".to_string(),
+                group_id: vec![0xaa],
+            })
+        );
+        assert!(
+            KeyPackageStore::release_reservation(&pool, &released.reservation_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "bob")
+                .await
+                .unwrap(),
+            2
+        );
+
+        // Reserve then spend: releasing afterwards is a no-op
+        let spent =
+            KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[0xaa], "alice", 60)
+                .await
+                .unwrap()
+                .unwrap();
+        KeyPackageStore::spend_key_package(&pool, &spent.keypackage_ref, &[0xaa], "alice")
+            .await
+            .unwrap();
+        assert!(
+            KeyPackageStore::release_reservation(&pool, &spent.reservation_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let data = KeyPackageStore::get_key_package(&pool, &spent.keypackage_ref)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.status, KeyPackageStatus::Spent);
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "bob")
+                .await
+                .unwrap(),
+            1
+        );
+
+        assert!(KeyPackageStore::release_reservation(&pool, "unknown")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
     #[tokio::test]
     async fn test_reservation_ttl_enforcement() {
         let pool = create_test_pool();
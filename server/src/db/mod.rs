@@ -5,7 +5,11 @@ pub mod keypackage_store;
 pub mod models;
 
 use chrono::Utc;
-use models::{Backup, Group, Message, User};
+use keypackage_store::KeyPackageStatus;
+use models::{
+    Backup, BackupVersion, ContentType, Group, GroupMember, JoinRequest, Message, PendingWelcome,
+    PublishedGroupInfo, SequencedCommit, StoredMessage, User,
+};
 use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -29,6 +33,31 @@ pub fn create_test_pool() -> DbPool {
     Arc::new(Mutex::new(conn))
 }
 
+/// Result of redeeming a join token (see `Database::redeem_join_token`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinTokenRedemption {
+    /// The token is now used up; a member of `group_id` will add the redeemer
+    Redeemed {
+        group_id: String,
+    },
+    NotFound,
+    Expired,
+    Revoked,
+    /// The token was redeemed before (by anyone)
+    AlreadyRedeemed,
+}
+
+/// Result of recording a user in a group (see `Database::claim_group_membership`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupClaim {
+    /// The user is now (or already was) recorded as a member
+    Joined,
+    /// The user is already in the maximum number of other groups
+    GroupLimitReached,
+    /// The group has members and the user is not one of them
+    NotInvited,
+}
+
 /// Database operations
 pub struct Database;
 
@@ -63,6 +92,81 @@ impl Database {
         Ok(user)
     }
 
+    /// Delete a user and everything the server keeps for them
+    ///
+    /// Removes, in one transaction, the user's KeyPackages, backups, stored
+    /// Welcomes, deferred deliveries, group memberships and stored messages,
+    /// then the user.
+    /// Group state is left alone: other members remove the user's leaf
+    /// with a Commit. Returns false if the user did not exist.
+    pub async fn delete_user(pool: &DbPool, username: &str) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let tx = conn.unchecked_transaction()?;
+        let user_id: Option<i64> = tx
+            .query_row(
+                "SELECT id FROM users WHERE username = ?1",
+                params![username],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(user_id) = user_id else {
+            return Ok(false);
+        };
+
+        tx.execute(
+            "DELETE FROM keypackages WHERE username = ?1",
+            params![username],
+        )?;
+        tx.execute("DELETE FROM backups WHERE username = ?1", params![username])?;
+        tx.execute(
+            "DELETE FROM pending_welcomes WHERE invitee = ?1",
+            params![username],
+        )?;
+        tx.execute(
+            "DELETE FROM deferred_messages WHERE username = ?1",
+            params![username],
+        )?;
+        tx.execute(
+            "DELETE FROM group_members WHERE username = ?1",
+            params![username],
+        )?;
+        tx.execute(
+            "DELETE FROM messages WHERE sender_id = ?1",
+            params![user_id],
+        )?;
+        tx.execute("DELETE FROM users WHERE id = ?1", params![user_id])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Replace a user's registered KeyPackage after a credential rotation
+    ///
+    /// In the same transaction every unspent KeyPackage of the user is
+    /// deleted, reservations included: they are bound to the old credential,
+    /// and an inviter still holding one can no longer spend it. Returns the
+    /// number of KeyPackages deleted, or None if the user does not exist.
+    pub async fn replace_user_key_package(
+        pool: &DbPool,
+        username: &str,
+        key_package: &[u8],
+    ) -> SqliteResult<Option<usize>> {
+        let conn = pool.lock().await;
+        let tx = conn.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE users SET key_package = ?1 WHERE username = ?2",
+            params![key_package, username],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        let revoked = tx.execute(
+            "DELETE FROM keypackages WHERE username = ?1 AND status != ?2",
+            params![username, KeyPackageStatus::Spent.as_str()],
+        )?;
+        tx.commit()?;
+        Ok(Some(revoked))
+    }
+
     /// Get user by username
     pub async fn get_user(pool: &DbPool, username: &str) -> SqliteResult<Option<User>> {
         let conn = pool.lock().await;
@@ -130,37 +234,702 @@ impl Database {
         Ok(group)
     }
 
+    /// Claim a group name for `group_id`
+    ///
+    /// Names are per-user `user:group` keys; several names may point at the
+    /// same group (one per member). The first name registered for a group is
+    /// also stored as its name in `groups`. Returns the group id that owns `name` after
+    /// the call: if another group claimed it first, that group's id is
+    /// returned and nothing changes, so callers detect a conflict by comparing.
+    pub async fn claim_group_name(
+        pool: &DbPool,
+        group_id: &str,
+        name: &str,
+    ) -> SqliteResult<String> {
+        let conn = pool.lock().await;
+        let created_at = Utc::now().to_rfc3339();
+
+        conn.execute(
+            "INSERT OR IGNORE INTO group_names (name, group_id, created_at) VALUES (?1, ?2, ?3)",
+            params![name, group_id, &created_at],
+        )?;
+
+        let owner: String = conn.query_row(
+            "SELECT group_id FROM group_names WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+
+        // The first registered name becomes the group's name, replacing the
+        // placeholder (its id) used when a message created the row
+        if owner == group_id {
+            conn.execute(
+                "INSERT OR IGNORE INTO groups (group_id, name, created_at) VALUES (?1, ?2, ?3)",
+                params![group_id, name, &created_at],
+            )?;
+            conn.execute(
+                "UPDATE groups SET name = ?2 WHERE group_id = ?1 AND name = group_id",
+                params![group_id, name],
+            )?;
+        }
+        Ok(owner)
+    }
+
+    /// Record `username` as a member of `group_id` on their own request
+    ///
+    /// Only a group nobody is recorded in yet (the user is creating it) or
+    /// one the user is already in can be claimed; invitees are recorded by
+    /// `add_group_member` when an existing member reserves their KeyPackage.
+    /// The check and the insert happen under one lock, so two users cannot
+    /// both claim a new group.
+    pub async fn claim_group_membership(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+        max_groups: usize,
+    ) -> SqliteResult<GroupClaim> {
+        let conn = pool.lock().await;
+        let (members, is_member): (i64, bool) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(username = ?2), 0) > 0 FROM group_members WHERE group_id = ?1",
+            params![group_id, username],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if members > 0 && !is_member {
+            return Ok(GroupClaim::NotInvited);
+        }
+        if !Self::can_join_group_locked(&conn, username, group_id, max_groups)? {
+            return Ok(GroupClaim::GroupLimitReached);
+        }
+
+        conn.execute(
+            "INSERT OR IGNORE INTO group_members (username, group_id, joined_at) VALUES (?1, ?2, ?3)",
+            params![username, group_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(GroupClaim::Joined)
+    }
+
+    /// Whether `username` could join `group_id` without exceeding `max_groups`
+    pub async fn can_join_group(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+        max_groups: usize,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        Self::can_join_group_locked(&conn, username, group_id, max_groups)
+    }
+
+    fn can_join_group_locked(
+        conn: &Connection,
+        username: &str,
+        group_id: &str,
+        max_groups: usize,
+    ) -> SqliteResult<bool> {
+        let (groups, already_member): (i64, bool) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(group_id = ?2), 0) > 0 FROM group_members WHERE username = ?1",
+            params![username, group_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(already_member || (groups as usize) < max_groups)
+    }
+
+    /// Record that `username` was added to `group_id`, regardless of the limit
+    ///
+    /// Used when an inviter reserves the invitee's KeyPackage, once the quota
+    /// was checked: the invitee must be a member before the Welcome reaches it
+    /// and it subscribes.
+    pub async fn add_group_member(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+    ) -> SqliteResult<()> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO group_members (username, group_id, joined_at) VALUES (?1, ?2, ?3)",
+            params![username, group_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Forget that `username` was added to `group_id`
+    ///
+    /// Used when the reservation that recorded an invitee is released
+    /// because the invite was aborted. Returns whether a membership was
+    /// removed.
+    pub async fn remove_group_member(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let removed = conn.execute(
+            "DELETE FROM group_members WHERE username = ?1 AND group_id = ?2",
+            params![username, group_id],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Whether `username` is recorded as a member of `group_id`
+    pub async fn is_group_member(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM group_members WHERE username = ?1 AND group_id = ?2)",
+            params![username, group_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// List the users recorded as members of `group_id`, by username
+    pub async fn list_group_members(
+        pool: &DbPool,
+        group_id: &str,
+    ) -> SqliteResult<Vec<GroupMember>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT username, joined_at FROM group_members WHERE group_id = ?1 ORDER BY username",
+        )?;
+        let members = stmt
+            .query_map(params![group_id], |row| {
+                Ok(GroupMember {
+                    username: row.get(0)?,
+                    joined_at: row.get(1)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(members)
+    }
+
+    /// List the ids of the groups `username` has joined, oldest first
+    pub async fn list_user_groups(pool: &DbPool, username: &str) -> SqliteResult<Vec<String>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT group_id FROM group_members WHERE username = ?1 ORDER BY joined_at, group_id",
+        )?;
+        let groups = stmt
+            .query_map(params![username], |row| row.get(0))?
+            .collect::<SqliteResult<Vec<String>>>()?;
+        Ok(groups)
+    }
+
+    /// Accept `commit_blob` as the Commit moving `group_id` out of `epoch`
+    ///
+    /// Only the first Commit per epoch is accepted; returns false if another
+    /// one already holds it. This serializes concurrent group changes: the
+    /// losing member must apply the accepted Commit and retry in the next epoch.
+    pub async fn sequence_commit(
+        pool: &DbPool,
+        group_id: &str,
+        epoch: i64,
+        sender: &str,
+        commit_blob: &str,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO group_commits (group_id, epoch, sender, commit_blob, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![group_id, epoch, sender, commit_blob, Utc::now().to_rfc3339()],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    /// Accepted Commits of `group_id` from `epoch` onward, in epoch order
+    pub async fn commits_since(
+        pool: &DbPool,
+        group_id: &str,
+        epoch: i64,
+    ) -> SqliteResult<Vec<SequencedCommit>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT epoch, sender, commit_blob FROM group_commits WHERE group_id = ?1 AND epoch >= ?2 ORDER BY epoch",
+        )?;
+        let commits = stmt
+            .query_map(params![group_id, epoch], |row| {
+                Ok(SequencedCommit {
+                    epoch: row.get(0)?,
+                    sender: row.get(1)?,
+                    commit_blob: row.get(2)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(commits)
+    }
+
+    /// Note that `group_id` has reached `epoch`, keeping the highest one seen
+    ///
+    /// Advisory only: the server learns epochs from Commit headers and never
+    /// checks them against group state.
+    pub async fn record_group_epoch(pool: &DbPool, group_id: &str, epoch: i64) -> SqliteResult<()> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "INSERT INTO group_epochs (group_id, epoch, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(group_id) DO UPDATE SET epoch = excluded.epoch, updated_at = excluded.updated_at
+             WHERE excluded.epoch > group_epochs.epoch",
+            params![group_id, epoch, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// The highest epoch recorded for `group_id`, if the server saw any Commit
+    pub async fn group_epoch(pool: &DbPool, group_id: &str) -> SqliteResult<Option<i64>> {
+        let conn = pool.lock().await;
+        conn.query_row(
+            "SELECT epoch FROM group_epochs WHERE group_id = ?1",
+            params![group_id],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Store the GroupInfo of `info.group_id`, replacing an older epoch's
+    ///
+    /// Returns false, leaving the stored one, if a GroupInfo for a later
+    /// epoch is already stored.
+    pub async fn publish_group_info(
+        pool: &DbPool,
+        info: &PublishedGroupInfo,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let changed = conn.execute(
+            "INSERT INTO group_infos (group_id, epoch, group_info_blob, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(group_id) DO UPDATE SET epoch = excluded.epoch, group_info_blob = excluded.group_info_blob, updated_at = excluded.updated_at
+             WHERE excluded.epoch >= group_infos.epoch",
+            params![
+                info.group_id,
+                info.epoch,
+                info.group_info_blob,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(changed == 1)
+    }
+
+    /// The latest GroupInfo published for `group_id`
+    pub async fn get_group_info(
+        pool: &DbPool,
+        group_id: &str,
+    ) -> SqliteResult<Option<PublishedGroupInfo>> {
+        let conn = pool.lock().await;
+        conn.query_row(
+            "SELECT group_id, epoch, group_info_blob FROM group_infos WHERE group_id = ?1",
+            params![group_id],
+            |row| {
+                Ok(PublishedGroupInfo {
+                    group_id: row.get(0)?,
+                    epoch: row.get(1)?,
+                    group_info_blob: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Keep a Welcome for `invitee` until they fetch it or `expires_at` passes
+    ///
+    /// Returns the id the invitee uses to delete it once joined.
+    pub async fn store_welcome(
+        pool: &DbPool,
+        invitee: &str,
+        inviter: &str,
+        welcome_blob: &str,
+        ratchet_tree_blob: &str,
+        expires_at: i64,
+    ) -> SqliteResult<i64> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "INSERT INTO pending_welcomes (invitee, inviter, welcome_blob, ratchet_tree_blob, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                invitee,
+                inviter,
+                welcome_blob,
+                ratchet_tree_blob,
+                Utc::now().to_rfc3339(),
+                expires_at
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Welcomes waiting for `invitee` that have not expired at `now`, oldest first
+    pub async fn pending_welcomes(
+        pool: &DbPool,
+        invitee: &str,
+        now: i64,
+    ) -> SqliteResult<Vec<PendingWelcome>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, inviter, welcome_blob, ratchet_tree_blob, expires_at FROM pending_welcomes
+             WHERE invitee = ?1 AND expires_at > ?2 ORDER BY id",
+        )?;
+        let welcomes = stmt
+            .query_map(params![invitee, now], |row| {
+                Ok(PendingWelcome {
+                    id: row.get(0)?,
+                    inviter: row.get(1)?,
+                    welcome_blob: row.get(2)?,
+                    ratchet_tree_blob: row.get(3)?,
+                    expires_at: row.get(4)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(welcomes)
+    }
+
+    /// Delete one of `invitee`'s stored Welcomes
+    ///
+    /// Returns false if no such Welcome is stored for them.
+    pub async fn delete_welcome(pool: &DbPool, invitee: &str, id: i64) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let deleted = conn.execute(
+            "DELETE FROM pending_welcomes WHERE id = ?1 AND invitee = ?2",
+            params![id, invitee],
+        )?;
+        Ok(deleted == 1)
+    }
+
+    /// Delete Welcomes whose expiry is at or before `now` (Unix seconds)
+    ///
+    /// Returns the number of Welcomes deleted.
+    pub async fn prune_expired_welcomes(pool: &DbPool, now: i64) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "DELETE FROM pending_welcomes WHERE expires_at <= ?1",
+            params![now],
+        )
+    }
+
+    /// Store a new single-use join token for `group_id`, valid until `expires_at`
+    ///
+    /// The token is a random UUID; it is the only secret a redeemer needs.
+    pub async fn create_join_token(
+        pool: &DbPool,
+        group_id: &str,
+        created_by: &str,
+        expires_at: i64,
+    ) -> SqliteResult<String> {
+        let token = uuid::Uuid::new_v4().to_string();
+        let conn = pool.lock().await;
+        conn.execute(
+            "INSERT INTO join_tokens (token, group_id, created_by, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![token, group_id, created_by, Utc::now().to_rfc3339(), expires_at],
+        )?;
+        Ok(token)
+    }
+
+    /// Redeem a join token for `username` at `now` (Unix seconds)
+    ///
+    /// A token can be redeemed once, before it expires and unless it was
+    /// revoked. The check and the update run under one lock, so two
+    /// concurrent redemptions cannot both succeed.
+    pub async fn redeem_join_token(
+        pool: &DbPool,
+        token: &str,
+        username: &str,
+        now: i64,
+    ) -> SqliteResult<JoinTokenRedemption> {
+        let conn = pool.lock().await;
+        let row = conn
+            .query_row(
+                "SELECT group_id, expires_at, revoked, redeemed_by FROM join_tokens WHERE token = ?1",
+                params![token],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let Some((group_id, expires_at, revoked, redeemed_by)) = row else {
+            return Ok(JoinTokenRedemption::NotFound);
+        };
+        if revoked {
+            return Ok(JoinTokenRedemption::Revoked);
+        }
+        if redeemed_by.is_some() {
+            return Ok(JoinTokenRedemption::AlreadyRedeemed);
+        }
+        if expires_at <= now {
+            return Ok(JoinTokenRedemption::Expired);
+        }
+
+        conn.execute(
+            "UPDATE join_tokens SET redeemed_by = ?2, redeemed_at = ?3 WHERE token = ?1",
+            params![token, username, now],
+        )?;
+        Ok(JoinTokenRedemption::Redeemed { group_id })
+    }
+
+    /// Revoke an unredeemed join token of a group `username` is a member of
+    ///
+    /// Returns false if there is no such token, it was already redeemed, or
+    /// `username` is not a member of its group.
+    pub async fn revoke_join_token(
+        pool: &DbPool,
+        token: &str,
+        username: &str,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let revoked = conn.execute(
+            "UPDATE join_tokens SET revoked = 1 WHERE token = ?1 AND redeemed_by IS NULL \
+             AND group_id IN (SELECT group_id FROM group_members WHERE username = ?2)",
+            params![token, username],
+        )?;
+        Ok(revoked == 1)
+    }
+
+    /// Redeemed tokens of a group whose redeemer has not been added yet, oldest first
+    pub async fn join_requests(pool: &DbPool, group_id: &str) -> SqliteResult<Vec<JoinRequest>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT token, redeemed_by, redeemed_at FROM join_tokens \
+             WHERE group_id = ?1 AND redeemed_by IS NOT NULL AND completed = 0 ORDER BY redeemed_at, token",
+        )?;
+        let requests = stmt
+            .query_map(params![group_id], |row| {
+                Ok(JoinRequest {
+                    token: row.get(0)?,
+                    username: row.get(1)?,
+                    redeemed_at: row.get(2)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(requests)
+    }
+
+    /// Mark the join request of a redeemed token as done once its redeemer was added
+    ///
+    /// Only members of the token's group can complete it. Returns false if
+    /// there is no such pending request.
+    pub async fn complete_join_request(
+        pool: &DbPool,
+        token: &str,
+        username: &str,
+    ) -> SqliteResult<bool> {
+        let conn = pool.lock().await;
+        let completed = conn.execute(
+            "UPDATE join_tokens SET completed = 1 WHERE token = ?1 AND redeemed_by IS NOT NULL AND completed = 0 \
+             AND group_id IN (SELECT group_id FROM group_members WHERE username = ?2)",
+            params![token, username],
+        )?;
+        Ok(completed == 1)
+    }
+
+    /// Set how long new messages of a group are kept (`None` keeps them forever)
+    ///
+    /// The TTL itself is agreed by the members in the encrypted group state;
+    /// this is the copy the server needs to prune its store. The group row is
+    /// created if no message has been stored for it yet.
+    pub async fn set_message_ttl(
+        pool: &DbPool,
+        group_id: &str,
+        ttl_secs: Option<i64>,
+    ) -> SqliteResult<()> {
+        let conn = pool.lock().await;
+        let created_at = Utc::now().to_rfc3339();
+
+        conn.execute(
+            "INSERT OR IGNORE INTO groups (group_id, name, created_at) VALUES (?1, ?1, ?2)",
+            params![group_id, &created_at],
+        )?;
+        conn.execute(
+            "UPDATE groups SET message_ttl_secs = ?2 WHERE group_id = ?1",
+            params![group_id, ttl_secs],
+        )?;
+        Ok(())
+    }
+
+    /// Delete messages whose expiry is at or before `now` (Unix seconds)
+    ///
+    /// Returns the number of messages deleted.
+    pub async fn prune_expired_messages(pool: &DbPool, now: i64) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "DELETE FROM messages WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            params![now],
+        )
+    }
+
     /// Store an encrypted message
     pub async fn store_message(
         pool: &DbPool,
         group_id: i64,
         sender_id: i64,
         encrypted_content: &str,
+        content_type: ContentType,
     ) -> SqliteResult<Message> {
         let conn = pool.lock().await;
-        let timestamp = Utc::now().to_rfc3339();
+        let now = Utc::now();
+        let timestamp = now.to_rfc3339();
 
+        // The group's TTL at send time fixes the expiry, so TTL changes only
+        // apply to messages sent afterwards
         conn.execute(
-            "INSERT INTO messages (group_id, sender_id, encrypted_content, timestamp) VALUES (?1, ?2, ?3, ?4)",
-            params![group_id, sender_id, encrypted_content, &timestamp],
+            "INSERT INTO messages (group_id, sender_id, encrypted_content, content_type, timestamp, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, (SELECT ?6 + message_ttl_secs FROM groups WHERE id = ?1))",
+            params![group_id, sender_id, encrypted_content, content_type.as_str(), &timestamp, now.timestamp()],
         )?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, group_id, sender_id, encrypted_content, timestamp FROM messages ORDER BY id DESC LIMIT 1",
+            "SELECT id, group_id, sender_id, encrypted_content, content_type, timestamp FROM messages ORDER BY id DESC LIMIT 1",
         )?;
-        let message = stmt.query_row([], |row| {
-            Ok(Message {
-                id: row.get(0)?,
-                group_id: row.get(1)?,
-                sender_id: row.get(2)?,
-                encrypted_content: row.get(3)?,
-                timestamp: row.get(4)?,
-            })
-        })?;
+        let message = stmt.query_row([], Self::message_from_row)?;
 
         Ok(message)
     }
 
+    /// Messages of `group_id` stored after `after_seq`, oldest first
+    ///
+    /// Expired messages are left out even if they have not been pruned yet.
+    pub async fn messages_since(
+        pool: &DbPool,
+        group_id: &str,
+        after_seq: i64,
+        limit: i64,
+    ) -> SqliteResult<Vec<StoredMessage>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT m.id, u.username, m.encrypted_content, m.content_type, m.timestamp FROM messages m
+             JOIN groups g ON g.id = m.group_id
+             JOIN users u ON u.id = m.sender_id
+             WHERE g.group_id = ?1 AND m.id > ?2 AND (m.expires_at IS NULL OR m.expires_at > ?3)
+             ORDER BY m.id LIMIT ?4",
+        )?;
+        let messages = stmt
+            .query_map(
+                params![group_id, after_seq, Utc::now().timestamp(), limit],
+                |row| {
+                    let content_type: String = row.get(3)?;
+                    Ok(StoredMessage {
+                        seq: row.get(0)?,
+                        sender: row.get(1)?,
+                        encrypted_content: row.get(2)?,
+                        content_type: ContentType::parse(&content_type).unwrap_or_default(),
+                        timestamp: row.get(4)?,
+                    })
+                },
+            )?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(messages)
+    }
+
+    /// Record that message `seq` of `group_id` still has to be delivered to
+    /// each of `recipients`
+    ///
+    /// Used for members who are offline as the message is sent. Recording a
+    /// message twice for a member is a no-op. Returns the number of
+    /// deliveries newly deferred.
+    pub async fn defer_message(
+        pool: &DbPool,
+        group_id: &str,
+        seq: i64,
+        recipients: &[String],
+    ) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        let tx = conn.unchecked_transaction()?;
+        let deferred_at = Utc::now().to_rfc3339();
+        let mut deferred = 0;
+        for username in recipients {
+            deferred += tx.execute(
+                "INSERT OR IGNORE INTO deferred_messages (username, group_id, message_id, deferred_at) VALUES (?1, ?2, ?3, ?4)",
+                params![username, group_id, seq, &deferred_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(deferred)
+    }
+
+    /// The messages of `group_id` deferred for `username`, oldest first
+    ///
+    /// The deferrals stay recorded until `clear_deferred_messages` is called
+    /// for what was actually delivered. Messages that expired in the
+    /// meantime are left out.
+    pub async fn deferred_messages(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+    ) -> SqliteResult<Vec<StoredMessage>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT m.id, u.username, m.encrypted_content, m.content_type, m.timestamp FROM deferred_messages d
+             JOIN messages m ON m.id = d.message_id
+             JOIN users u ON u.id = m.sender_id
+             WHERE d.username = ?1 AND d.group_id = ?2 AND (m.expires_at IS NULL OR m.expires_at > ?3)
+             ORDER BY m.id",
+        )?;
+        let messages = stmt
+            .query_map(params![username, group_id, Utc::now().timestamp()], |row| {
+                let content_type: String = row.get(3)?;
+                Ok(StoredMessage {
+                    seq: row.get(0)?,
+                    sender: row.get(1)?,
+                    encrypted_content: row.get(2)?,
+                    content_type: ContentType::parse(&content_type).unwrap_or_default(),
+                    timestamp: row.get(4)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(messages)
+    }
+
+    /// Forget the deferrals of `group_id` for `username` up to message
+    /// `seq`, once those messages were delivered
+    ///
+    /// Deferrals of expired messages before `seq` go too. Returns the number
+    /// of deferrals removed.
+    pub async fn clear_deferred_messages(
+        pool: &DbPool,
+        username: &str,
+        group_id: &str,
+        seq: i64,
+    ) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "DELETE FROM deferred_messages WHERE username = ?1 AND group_id = ?2 AND message_id <= ?3",
+            params![username, group_id, seq],
+        )
+    }
+
+    /// The latest `limit` messages `sender` stored in `group_id`, newest first
+    ///
+    /// Served by the `(group_id, sender_id)` index. Expired messages are
+    /// left out even if they have not been pruned yet.
+    pub async fn get_messages_by_sender(
+        pool: &DbPool,
+        group_id: &str,
+        sender: &str,
+        limit: i64,
+    ) -> SqliteResult<Vec<StoredMessage>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT m.id, u.username, m.encrypted_content, m.content_type, m.timestamp FROM messages m
+             JOIN groups g ON g.id = m.group_id
+             JOIN users u ON u.id = m.sender_id
+             WHERE g.group_id = ?1 AND u.username = ?2 AND (m.expires_at IS NULL OR m.expires_at > ?3)
+             ORDER BY m.id DESC LIMIT ?4",
+        )?;
+        let messages = stmt
+            .query_map(
+                params![group_id, sender, Utc::now().timestamp(), limit],
+                |row| {
+                    let content_type: String = row.get(3)?;
+                    Ok(StoredMessage {
+                        seq: row.get(0)?,
+                        sender: row.get(1)?,
+                        encrypted_content: row.get(2)?,
+                        content_type: ContentType::parse(&content_type).unwrap_or_default(),
+                        timestamp: row.get(4)?,
+                    })
+                },
+            )?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(messages)
+    }
+
     /// Get messages for a group.
     /// Used by both server internal tests and client integration tests.
     /// Available during testing or with `test_utils` feature flag enabled.
@@ -173,25 +942,67 @@ impl Database {
         let conn = pool.lock().await;
 
         let mut stmt = conn.prepare(
-            "SELECT id, group_id, sender_id, encrypted_content, timestamp FROM messages WHERE group_id = ?1 ORDER BY timestamp DESC LIMIT ?2",
+            "SELECT id, group_id, sender_id, encrypted_content, content_type, timestamp FROM messages WHERE group_id = ?1 ORDER BY timestamp DESC LIMIT ?2",
         )?;
 
         let messages = stmt
-            .query_map(params![group_id, limit], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    group_id: row.get(1)?,
-                    sender_id: row.get(2)?,
-                    encrypted_content: row.get(3)?,
-                    timestamp: row.get(4)?,
-                })
-            })?
+            .query_map(params![group_id, limit], Self::message_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(messages)
     }
 
-    /// Store encrypted state backup
+    /// Get messages for a group restricted to the given content types
+    /// (e.g. only user-visible messages for history).
+    #[cfg(test)]
+    pub async fn get_group_messages_by_type(
+        pool: &DbPool,
+        group_id: i64,
+        content_types: &[ContentType],
+        limit: i64,
+    ) -> SqliteResult<Vec<Message>> {
+        use rusqlite::{params_from_iter, types::Value};
+
+        let conn = pool.lock().await;
+
+        let placeholders = vec!["?"; content_types.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, group_id, sender_id, encrypted_content, content_type, timestamp FROM messages WHERE group_id = ? AND content_type IN ({}) ORDER BY timestamp DESC LIMIT ?",
+            placeholders
+        ))?;
+
+        let mut values = vec![Value::Integer(group_id)];
+        values.extend(
+            content_types
+                .iter()
+                .map(|content_type| Value::Text(content_type.as_str().to_string())),
+        );
+        values.push(Value::Integer(limit));
+
+        let messages = stmt
+            .query_map(params_from_iter(values), Self::message_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(messages)
+    }
+
+    fn message_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<Message> {
+        let content_type: String = row.get(4)?;
+        Ok(Message {
+            id: row.get(0)?,
+            group_id: row.get(1)?,
+            sender_id: row.get(2)?,
+            encrypted_content: row.get(3)?,
+            // Unknown values (e.g. written by a newer server) fall back to text
+            content_type: ContentType::parse(&content_type).unwrap_or_default(),
+            timestamp: row.get(5)?,
+        })
+    }
+
+    /// Store encrypted state backup as the user's next version
+    ///
+    /// Earlier versions are kept so a corrupt backup does not overwrite the
+    /// last good one; `prune_backup_versions` bounds how many remain.
     pub async fn store_backup(
         pool: &DbPool,
         username: &str,
@@ -201,21 +1012,15 @@ impl Database {
         let timestamp = Utc::now().to_rfc3339();
 
         conn.execute(
-            "INSERT OR REPLACE INTO backups (username, encrypted_state, timestamp) VALUES (?1, ?2, ?3)",
+            "INSERT INTO backups (username, version, encrypted_state, timestamp)
+             SELECT ?1, COALESCE(MAX(version), 0) + 1, ?2, ?3 FROM backups WHERE username = ?1",
             params![username, encrypted_state, &timestamp],
         )?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, username, encrypted_state, timestamp FROM backups WHERE username = ?1 ORDER BY timestamp DESC LIMIT 1",
+            "SELECT id, username, version, encrypted_state, timestamp FROM backups WHERE id = ?1",
         )?;
-        let backup = stmt.query_row(params![username], |row| {
-            Ok(Backup {
-                id: row.get(0)?,
-                username: row.get(1)?,
-                encrypted_state: row.get(2)?,
-                timestamp: row.get(3)?,
-            })
-        })?;
+        let backup = stmt.query_row(params![conn.last_insert_rowid()], Self::backup_from_row)?;
 
         Ok(backup)
     }
@@ -225,22 +1030,82 @@ impl Database {
         let conn = pool.lock().await;
 
         let mut stmt = conn.prepare(
-            "SELECT id, username, encrypted_state, timestamp FROM backups WHERE username = ?1 ORDER BY timestamp DESC LIMIT 1",
+            "SELECT id, username, version, encrypted_state, timestamp FROM backups WHERE username = ?1 ORDER BY version DESC LIMIT 1",
         )?;
 
         let backup = stmt
-            .query_row(params![username], |row| {
-                Ok(Backup {
-                    id: row.get(0)?,
-                    username: row.get(1)?,
-                    encrypted_state: row.get(2)?,
-                    timestamp: row.get(3)?,
-                })
-            })
+            .query_row(params![username], Self::backup_from_row)
             .optional()?;
 
         Ok(backup)
     }
+
+    /// Get one backup version of a user
+    pub async fn get_backup_version(
+        pool: &DbPool,
+        username: &str,
+        version: i64,
+    ) -> SqliteResult<Option<Backup>> {
+        let conn = pool.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, username, version, encrypted_state, timestamp FROM backups WHERE username = ?1 AND version = ?2",
+        )?;
+
+        let backup = stmt
+            .query_row(params![username, version], Self::backup_from_row)
+            .optional()?;
+
+        Ok(backup)
+    }
+
+    /// List the backup versions kept for a user, newest first
+    pub async fn list_backup_versions(
+        pool: &DbPool,
+        username: &str,
+    ) -> SqliteResult<Vec<BackupVersion>> {
+        let conn = pool.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT version, timestamp, LENGTH(encrypted_state) FROM backups WHERE username = ?1 ORDER BY version DESC",
+        )?;
+        let versions = stmt
+            .query_map(params![username], |row| {
+                Ok(BackupVersion {
+                    version: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    size: row.get(2)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        Ok(versions)
+    }
+
+    /// Delete all but the `keep` newest backup versions of a user
+    ///
+    /// Returns the number of versions deleted.
+    pub async fn prune_backup_versions(
+        pool: &DbPool,
+        username: &str,
+        keep: usize,
+    ) -> SqliteResult<usize> {
+        let conn = pool.lock().await;
+        conn.execute(
+            "DELETE FROM backups WHERE username = ?1 AND version NOT IN (
+                 SELECT version FROM backups WHERE username = ?1 ORDER BY version DESC LIMIT ?2
+             )",
+            params![username, keep as i64],
+        )
+    }
+
+    fn backup_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<Backup> {
+        Ok(Backup {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            version: row.get(2)?,
+            encrypted_state: row.get(3)?,
+            timestamp: row.get(4)?,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -287,6 +1152,110 @@ mod tests {
         assert!(user.is_none());
     }
 
+    #[tokio::test]
+    async fn test_claim_group_name() {
+        let pool = create_test_pool();
+        let owner = Database::claim_group_name(&pool, "group_001", "alice:team")
+            .await
+            .expect("Failed to claim group name");
+        assert_eq!(owner, "group_001");
+
+        // Claiming again for the same group is idempotent
+        let owner = Database::claim_group_name(&pool, "group_001", "alice:team")
+            .await
+            .expect("Failed to claim group name");
+        assert_eq!(owner, "group_001");
+
+        // A different group cannot take over the name
+        let owner = Database::claim_group_name(&pool, "group_002", "alice:team")
+            .await
+            .expect("Failed to claim group name");
+        assert_eq!(owner, "group_001");
+
+        // Other members name the same group under their own key
+        let owner = Database::claim_group_name(&pool, "group_001", "bob:team")
+            .await
+            .expect("Failed to claim group name");
+        assert_eq!(owner, "group_001");
+    }
+
+    #[tokio::test]
+    async fn test_claim_group_membership_up_to_limit() {
+        let pool = create_test_pool();
+        for group_id in ["group_001", "group_002"] {
+            assert_eq!(
+                Database::claim_group_membership(&pool, "alice", group_id, 2)
+                    .await
+                    .unwrap(),
+                GroupClaim::Joined
+            );
+        }
+
+        // A third group is over the limit
+        assert_eq!(
+            Database::claim_group_membership(&pool, "alice", "group_003", 2)
+                .await
+                .unwrap(),
+            GroupClaim::GroupLimitReached
+        );
+        assert!(!Database::can_join_group(&pool, "alice", "group_003", 2)
+            .await
+            .unwrap());
+
+        // Rejoining a group already counted is fine, and other users are unaffected
+        assert_eq!(
+            Database::claim_group_membership(&pool, "alice", "group_001", 2)
+                .await
+                .unwrap(),
+            GroupClaim::Joined
+        );
+        assert_eq!(
+            Database::claim_group_membership(&pool, "bob", "group_003", 2)
+                .await
+                .unwrap(),
+            GroupClaim::Joined
+        );
+
+        let groups = Database::list_user_groups(&pool, "alice").await.unwrap();
+        assert_eq!(groups, vec!["group_001", "group_002"]);
+    }
+
+    #[tokio::test]
+    async fn test_claim_group_membership_needs_an_invite() {
+        let pool = create_test_pool();
+        Database::add_group_member(&pool, "alice", "group_001")
+            .await
+            .unwrap();
+
+        // Bob cannot add himself to a group that already has members
+        assert_eq!(
+            Database::claim_group_membership(&pool, "bob", "group_001", 10)
+                .await
+                .unwrap(),
+            GroupClaim::NotInvited
+        );
+        assert!(!Database::is_group_member(&pool, "bob", "group_001")
+            .await
+            .unwrap());
+
+        // Once recorded by an existing member he can; removing him undoes it
+        Database::add_group_member(&pool, "bob", "group_001")
+            .await
+            .unwrap();
+        assert_eq!(
+            Database::claim_group_membership(&pool, "bob", "group_001", 10)
+                .await
+                .unwrap(),
+            GroupClaim::Joined
+        );
+        assert!(Database::remove_group_member(&pool, "bob", "group_001")
+            .await
+            .unwrap());
+        assert!(!Database::remove_group_member(&pool, "bob", "group_001")
+            .await
+            .unwrap());
+    }
+
     #[tokio::test]
     async fn test_create_group() {
         let pool = create_test_pool();
@@ -310,13 +1279,20 @@ mod tests {
             .await
             .expect("Failed to create group");
 
-        let message = Database::store_message(&pool, group.id, user.id, "encrypted_content")
-            .await
-            .expect("Failed to store message");
+        let message = Database::store_message(
+            &pool,
+            group.id,
+            user.id,
+            "encrypted_content",
+            ContentType::Text,
+        )
+        .await
+        .expect("Failed to store message");
 
         assert_eq!(message.group_id, group.id);
         assert_eq!(message.sender_id, user.id);
         assert_eq!(message.encrypted_content, "encrypted_content");
+        assert_eq!(message.content_type, ContentType::Text);
     }
 
     #[tokio::test]
@@ -330,10 +1306,10 @@ mod tests {
             .await
             .expect("Failed to create group");
 
-        Database::store_message(&pool, group.id, user.id, "msg1")
+        Database::store_message(&pool, group.id, user.id, "msg1", ContentType::Text)
             .await
             .expect("Failed to store");
-        Database::store_message(&pool, group.id, user.id, "msg2")
+        Database::store_message(&pool, group.id, user.id, "msg2", ContentType::Text)
             .await
             .expect("Failed to store");
 
@@ -344,6 +1320,280 @@ mod tests {
         assert_eq!(messages.len(), 2);
     }
 
+    #[tokio::test]
+    async fn test_messages_since_returns_later_messages_in_order() {
+        let pool = create_test_pool();
+        let alice = Database::register_user(&pool, "alice", &[0x01])
+            .await
+            .expect("Failed to register user");
+        let group = Database::create_group(&pool, "group_001", "test")
+            .await
+            .expect("Failed to create group");
+        let other = Database::create_group(&pool, "group_002", "other")
+            .await
+            .expect("Failed to create group");
+
+        let first = Database::store_message(&pool, group.id, alice.id, "msg1", ContentType::Text)
+            .await
+            .expect("Failed to store");
+        Database::store_message(&pool, other.id, alice.id, "elsewhere", ContentType::Text)
+            .await
+            .expect("Failed to store");
+        Database::store_message(&pool, group.id, alice.id, "msg2", ContentType::System)
+            .await
+            .expect("Failed to store");
+        Database::store_message(&pool, group.id, alice.id, "msg3", ContentType::Text)
+            .await
+            .expect("Failed to store");
+
+        let all = Database::messages_since(&pool, "group_001", 0, 10)
+            .await
+            .expect("Failed to list messages");
+        let contents: Vec<_> = all.iter().map(|m| m.encrypted_content.as_str()).collect();
+        assert_eq!(contents, vec!["msg1", "msg2", "msg3"]);
+        assert!(all.iter().all(|m| m.sender == "alice"));
+        assert_eq!(all[1].content_type, ContentType::System);
+
+        let later = Database::messages_since(&pool, "group_001", first.id, 1)
+            .await
+            .expect("Failed to list messages");
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].encrypted_content, "msg2");
+    }
+
+    #[tokio::test]
+    async fn test_messages_by_sender_uses_the_index() {
+        let pool = create_test_pool();
+        let alice = Database::register_user(&pool, "alice", &[0x01])
+            .await
+            .expect("Failed to register user");
+        let bob = Database::register_user(&pool, "bob", &[0x02])
+            .await
+            .expect("Failed to register user");
+        let group = Database::create_group(&pool, "group_001", "test")
+            .await
+            .expect("Failed to create group");
+        let other = Database::create_group(&pool, "group_002", "other")
+            .await
+            .expect("Failed to create group");
+
+        for (group_id, sender_id, content) in [
+            (group.id, bob.id, "bob1"),
+            (group.id, alice.id, "alice1"),
+            (other.id, bob.id, "elsewhere"),
+            (group.id, bob.id, "bob2"),
+            (group.id, bob.id, "bob3"),
+        ] {
+            Database::store_message(&pool, group_id, sender_id, content, ContentType::Text)
+                .await
+                .expect("Failed to store");
+        }
+
+        let messages = Database::get_messages_by_sender(&pool, "group_001", "bob", 10)
+            .await
+            .expect("Failed to list messages");
+        let contents: Vec<_> = messages
+            .iter()
+            .map(|m| m.encrypted_content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["bob3", "bob2", "bob1"]);
+
+        let latest = Database::get_messages_by_sender(&pool, "group_001", "bob", 1)
+            .await
+            .expect("Failed to list messages");
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].encrypted_content, "bob3");
+        assert!(
+            Database::get_messages_by_sender(&pool, "group_001", "carol", 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let conn = pool.lock().await;
+        let plan: String = conn
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT id FROM messages WHERE group_id = ?1 AND sender_id = ?2",
+                params![group.id, bob.id],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("idx_messages_group_sender"), "plan: {}", plan);
+    }
+
+    #[tokio::test]
+    async fn test_group_epoch_only_moves_forward() {
+        let pool = create_test_pool();
+        assert_eq!(Database::group_epoch(&pool, "group").await.unwrap(), None);
+
+        Database::record_group_epoch(&pool, "group", 2)
+            .await
+            .unwrap();
+        Database::record_group_epoch(&pool, "group", 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            Database::group_epoch(&pool, "group").await.unwrap(),
+            Some(2)
+        );
+
+        Database::record_group_epoch(&pool, "group", 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            Database::group_epoch(&pool, "group").await.unwrap(),
+            Some(3)
+        );
+        assert_eq!(Database::group_epoch(&pool, "other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_pending_welcomes_expire_and_are_deleted() {
+        let pool = create_test_pool();
+        let kept = Database::store_welcome(&pool, "bob", "alice", "welcome1", "tree1", 200)
+            .await
+            .expect("Failed to store welcome");
+        Database::store_welcome(&pool, "bob", "carol", "welcome2", "tree2", 100)
+            .await
+            .expect("Failed to store welcome");
+        Database::store_welcome(&pool, "dave", "alice", "welcome3", "tree3", 200)
+            .await
+            .expect("Failed to store welcome");
+
+        let pending = Database::pending_welcomes(&pool, "bob", 100)
+            .await
+            .expect("Failed to list welcomes");
+        assert_eq!(pending.len(), 1, "expired Welcome must not be delivered");
+        assert_eq!(pending[0].id, kept);
+        assert_eq!(pending[0].inviter, "alice");
+        assert_eq!(pending[0].welcome_blob, "welcome1");
+
+        let pruned = Database::prune_expired_welcomes(&pool, 100)
+            .await
+            .expect("Failed to prune welcomes");
+        assert_eq!(pruned, 1);
+
+        assert!(!Database::delete_welcome(&pool, "dave", kept)
+            .await
+            .expect("Failed to delete welcome"));
+        assert!(Database::delete_welcome(&pool, "bob", kept)
+            .await
+            .expect("Failed to delete welcome"));
+        assert!(Database::pending_welcomes(&pool, "bob", 0)
+            .await
+            .expect("Failed to list welcomes")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_group_messages_by_type() {
+        let pool = create_test_pool();
+        let user = Database::register_user(&pool, "alice", &[0x01])
+            .await
+            .expect("Failed to register user");
+        let group = Database::create_group(&pool, "group_001", "test")
+            .await
+            .expect("Failed to create group");
+
+        for (content, content_type) in [
+            ("chat", ContentType::Text),
+            ("control", ContentType::Control),
+            ("joined", ContentType::System),
+        ] {
+            Database::store_message(&pool, group.id, user.id, content, content_type)
+                .await
+                .expect("Failed to store");
+        }
+
+        let visible = Database::get_group_messages_by_type(
+            &pool,
+            group.id,
+            &[ContentType::Text, ContentType::System],
+            10,
+        )
+        .await
+        .expect("Failed to get messages");
+        assert_eq!(visible.len(), 2);
+        assert!(visible
+            .iter()
+            .all(|m| m.content_type != ContentType::Control));
+
+        let control =
+            Database::get_group_messages_by_type(&pool, group.id, &[ContentType::Control], 10)
+                .await
+                .expect("Failed to get messages");
+        assert_eq!(control.len(), 1);
+        assert_eq!(control[0].encrypted_content, "control");
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired_messages_honors_ttl_going_forward() {
+        let pool = create_test_pool();
+        let user = Database::register_user(&pool, "alice", &[0x01])
+            .await
+            .expect("Failed to register user");
+        let ephemeral = Database::create_group(&pool, "group_001", "ephemeral")
+            .await
+            .expect("Failed to create group");
+        let kept = Database::create_group(&pool, "group_002", "kept")
+            .await
+            .expect("Failed to create group");
+
+        // Sent before the TTL was set: never expires
+        Database::store_message(&pool, ephemeral.id, user.id, "before", ContentType::Text)
+            .await
+            .expect("Failed to store message");
+
+        Database::set_message_ttl(&pool, "group_001", Some(60))
+            .await
+            .expect("Failed to set TTL");
+        let sent_at = Utc::now().timestamp();
+        Database::store_message(&pool, ephemeral.id, user.id, "after", ContentType::Text)
+            .await
+            .expect("Failed to store message");
+        Database::store_message(&pool, kept.id, user.id, "other group", ContentType::Text)
+            .await
+            .expect("Failed to store message");
+
+        let pruned = Database::prune_expired_messages(&pool, sent_at + 30)
+            .await
+            .expect("Failed to prune");
+        assert_eq!(pruned, 0, "nothing has expired yet");
+
+        // Allow for the clock ticking between `sent_at` and the insert
+        let pruned = Database::prune_expired_messages(&pool, sent_at + 62)
+            .await
+            .expect("Failed to prune");
+        assert_eq!(pruned, 1);
+
+        let remaining: Vec<String> = Database::get_group_messages(&pool, ephemeral.id, 10)
+            .await
+            .expect("Failed to get messages")
+            .into_iter()
+            .map(|m| m.encrypted_content)
+            .collect();
+        assert_eq!(remaining, vec!["before"]);
+        assert_eq!(
+            Database::get_group_messages(&pool, kept.id, 10)
+                .await
+                .expect("Failed to get messages")
+                .len(),
+            1
+        );
+
+        // Clearing the TTL stops new messages from expiring
+        Database::set_message_ttl(&pool, "group_001", None)
+            .await
+            .expect("Failed to clear TTL");
+        Database::store_message(&pool, ephemeral.id, user.id, "later", ContentType::Text)
+            .await
+            .expect("Failed to store message");
+        let pruned = Database::prune_expired_messages(&pool, sent_at + 1_000_000)
+            .await
+            .expect("Failed to prune");
+        assert_eq!(pruned, 0);
+    }
+
     #[tokio::test]
     async fn test_store_and_get_backup() {
         let pool = create_test_pool();
@@ -391,4 +1641,198 @@ mod tests {
         assert_eq!(retrieved.encrypted_state, "state2");
         assert_eq!(retrieved.id, backup2.id);
     }
+
+    #[tokio::test]
+    async fn test_backup_versions_are_kept_and_pruned() {
+        let pool = create_test_pool();
+        Database::register_user(&pool, "alice", b"kp")
+            .await
+            .expect("Failed to register user");
+
+        for state in ["state1", "state2", "state3"] {
+            Database::store_backup(&pool, "alice", state)
+                .await
+                .expect("Failed to store backup");
+        }
+
+        let versions = Database::list_backup_versions(&pool, "alice")
+            .await
+            .expect("Failed to list versions");
+        let numbers: Vec<i64> = versions.iter().map(|v| v.version).collect();
+        assert_eq!(numbers, vec![3, 2, 1]);
+        assert!(versions.iter().all(|v| v.size == 6));
+
+        let older = Database::get_backup_version(&pool, "alice", 1)
+            .await
+            .expect("Failed to get backup")
+            .expect("Version 1 not found");
+        assert_eq!(older.encrypted_state, "state1");
+
+        let pruned = Database::prune_backup_versions(&pool, "alice", 2)
+            .await
+            .expect("Failed to prune");
+        assert_eq!(pruned, 1);
+        assert!(Database::get_backup_version(&pool, "alice", 1)
+            .await
+            .expect("Failed to get backup")
+            .is_none());
+
+        // Versions keep increasing after a prune
+        let next = Database::store_backup(&pool, "alice", "state4")
+            .await
+            .expect("Failed to store backup");
+        assert_eq!(next.version, 4);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_commit_accepts_one_commit_per_epoch() {
+        let pool = create_test_pool();
+
+        assert!(Database::sequence_commit(&pool, "g", 3, "alice", "c-alice")
+            .await
+            .unwrap());
+        assert!(!Database::sequence_commit(&pool, "g", 3, "bob", "c-bob")
+            .await
+            .unwrap());
+        assert!(
+            Database::sequence_commit(&pool, "g", 4, "bob", "c-bob-retry")
+                .await
+                .unwrap()
+        );
+        // Epochs are per group
+        assert!(
+            Database::sequence_commit(&pool, "other", 3, "bob", "c-other")
+                .await
+                .unwrap()
+        );
+
+        let commits = Database::commits_since(&pool, "g", 3).await.unwrap();
+        let senders: Vec<_> = commits
+            .iter()
+            .map(|c| (c.epoch, c.sender.as_str()))
+            .collect();
+        assert_eq!(senders, vec![(3, "alice"), (4, "bob")]);
+        assert_eq!(commits[0].commit_blob, "c-alice");
+        assert_eq!(
+            Database::commits_since(&pool, "g", 4).await.unwrap().len(),
+            1
+        );
+        assert!(Database::commits_since(&pool, "g", 5)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_join_tokens_are_single_use_expiring_and_revocable() {
+        let pool = create_test_pool();
+        let now = Utc::now().timestamp();
+        Database::add_group_member(&pool, "alice", "g")
+            .await
+            .unwrap();
+
+        // Redeemed once; the second redemption is refused
+        let token = Database::create_join_token(&pool, "g", "alice", now + 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            Database::redeem_join_token(&pool, &token, "bob", now)
+                .await
+                .unwrap(),
+            JoinTokenRedemption::Redeemed {
+                group_id: "g".to_string()
+            }
+        );
+        assert_eq!(
+            Database::redeem_join_token(&pool, &token, "carol", now)
+                .await
+                .unwrap(),
+            JoinTokenRedemption::AlreadyRedeemed
+        );
+        let requests = Database::join_requests(&pool, "g").await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].username, "bob");
+        assert!(!Database::revoke_join_token(&pool, &token, "alice")
+            .await
+            .unwrap());
+
+        // Only members complete requests, once
+        assert!(!Database::complete_join_request(&pool, &token, "mallory")
+            .await
+            .unwrap());
+        assert!(Database::complete_join_request(&pool, &token, "alice")
+            .await
+            .unwrap());
+        assert!(!Database::complete_join_request(&pool, &token, "alice")
+            .await
+            .unwrap());
+        assert!(Database::join_requests(&pool, "g")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Expired at its expiry time
+        let token = Database::create_join_token(&pool, "g", "alice", now + 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            Database::redeem_join_token(&pool, &token, "bob", now + 60)
+                .await
+                .unwrap(),
+            JoinTokenRedemption::Expired
+        );
+
+        // Revoked by a member before use
+        let token = Database::create_join_token(&pool, "g", "alice", now + 60)
+            .await
+            .unwrap();
+        assert!(!Database::revoke_join_token(&pool, &token, "mallory")
+            .await
+            .unwrap());
+        assert!(Database::revoke_join_token(&pool, &token, "alice")
+            .await
+            .unwrap());
+        assert_eq!(
+            Database::redeem_join_token(&pool, &token, "bob", now)
+                .await
+                .unwrap(),
+            JoinTokenRedemption::Revoked
+        );
+        assert_eq!(
+            Database::redeem_join_token(&pool, "no-such-token", "bob", now)
+                .await
+                .unwrap(),
+            JoinTokenRedemption::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_info_keeps_the_latest_epoch() {
+        let pool = create_test_pool();
+        let info = |epoch: i64, blob: &str| PublishedGroupInfo {
+            group_id: "g".to_string(),
+            epoch,
+            group_info_blob: blob.to_string(),
+        };
+
+        assert!(Database::get_group_info(&pool, "g")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(Database::publish_group_info(&pool, &info(2, "gi-2"))
+            .await
+            .unwrap());
+        assert!(Database::publish_group_info(&pool, &info(3, "gi-3"))
+            .await
+            .unwrap());
+        // A late upload from an older epoch does not replace the newer one
+        assert!(!Database::publish_group_info(&pool, &info(2, "gi-2-late"))
+            .await
+            .unwrap());
+
+        assert_eq!(
+            Database::get_group_info(&pool, "g").await.unwrap(),
+            Some(info(3, "gi-3"))
+        );
+    }
 }
@@ -18,12 +18,63 @@ pub struct Group {
     pub created_at: String,
 }
 
+/// Kind of payload carried by a stored message
+///
+/// Set by the sender next to the ciphertext, so history can be filtered
+/// without decrypting. Rows stored before the column existed are `Text`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Chat message typed by a user
+    #[default]
+    Text,
+    /// Protocol/control payload not meant for display
+    Control,
+    /// Notice generated by a client (joins, leaves, ...)
+    System,
+}
+
+impl ContentType {
+    /// Value stored in the `messages.content_type` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Control => "control",
+            ContentType::System => "system",
+        }
+    }
+
+    /// Parse a stored or received value, `None` if unknown
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(ContentType::Text),
+            "control" => Some(ContentType::Control),
+            "system" => Some(ContentType::System),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: i64,
     pub group_id: i64,
     pub sender_id: i64,
     pub encrypted_content: String,
+    pub content_type: ContentType,
+    pub timestamp: String,
+}
+
+/// A stored message as served to members catching up on missed messages
+///
+/// `seq` is the message's id: it increases with every stored message, so a
+/// client can ask for everything after the last `seq` it has seen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub seq: i64,
+    pub sender: String,
+    pub encrypted_content: String,
+    pub content_type: ContentType,
     pub timestamp: String,
 }
 
@@ -31,10 +82,32 @@ pub struct Message {
 pub struct Backup {
     pub id: i64,
     pub username: String,
+    /// Per-user version, starting at 1 and increasing with every stored backup
+    pub version: i64,
     pub encrypted_state: String,
     pub timestamp: String,
 }
 
+/// A user the server has recorded as a member of a group
+///
+/// Best effort: recorded when the user creates, joins or is added to the
+/// group, and not verified against the group's MLS roster.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub username: String,
+    /// When the server recorded the membership (RFC 3339)
+    pub joined_at: String,
+}
+
+/// A stored backup version, without its contents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupVersion {
+    pub version: i64,
+    pub timestamp: String,
+    /// Length of the stored state in bytes
+    pub size: i64,
+}
+
 // Request/Response DTOs
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterUserRequest {
@@ -42,6 +115,49 @@ pub struct RegisterUserRequest {
     pub key_package: Vec<u8>,
 }
 
+/// Proof that a deregistration comes from the user being deleted
+///
+/// `signature` is the base64 Ed25519 signature, by the signature key of the
+/// user's registered KeyPackage, of `deregister_payload(username, timestamp)`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeregisterUserRequest {
+    /// Unix seconds when the request was signed
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+/// Bytes a user signs to delete their account
+pub fn deregister_payload(username: &str, timestamp: i64) -> Vec<u8> {
+    format!("mls-chat-deregister:{}:{}", username, timestamp).into_bytes()
+}
+
+/// Bytes a user signs to open a WebSocket connection
+///
+/// Sent as the `timestamp` and `signature` query parameters of `/ws/{username}`.
+pub fn ws_connect_payload(username: &str, timestamp: i64) -> Vec<u8> {
+    format!("mls-chat-ws-connect:{}:{}", username, timestamp).into_bytes()
+}
+
+/// Re-registration of a user under a rotated credential
+///
+/// `key_package` is bound to the new credential; `signature` is the base64
+/// Ed25519 signature, by the signature key of the currently registered
+/// KeyPackage, of `rotate_key_payload(username, timestamp, key_package)`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateUserKeyRequest {
+    pub key_package: Vec<u8>,
+    /// Unix seconds when the request was signed
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+/// Bytes a user signs to replace their registered KeyPackage
+pub fn rotate_key_payload(username: &str, timestamp: i64, key_package: &[u8]) -> Vec<u8> {
+    let mut payload = format!("mls-chat-rotate:{}:{}:", username, timestamp).into_bytes();
+    payload.extend_from_slice(key_package);
+    payload
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterUserResponse {
     pub id: i64,
@@ -49,12 +165,218 @@ pub struct RegisterUserResponse {
     pub created_at: String,
 }
 
+impl From<User> for RegisterUserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            created_at: user.created_at,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserKeyResponse {
     pub username: String,
     pub key_package: Vec<u8>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterGroupRequest {
+    pub group_id: String,
+    pub name: String,
+}
+
+/// Record that a user has created or joined a group
+///
+/// `signature` is the base64 Ed25519 signature, by the signature key of the
+/// user's registered KeyPackage, of
+/// `join_group_payload(username, group_id, timestamp)`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinGroupRequest {
+    pub username: String,
+    pub group_id: String,
+    /// Unix seconds when the request was signed
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+/// Bytes a user signs to record that they created or joined a group
+pub fn join_group_payload(username: &str, group_id: &str, timestamp: i64) -> Vec<u8> {
+    format!(
+        "mls-chat-join-group:{}:{}:{}",
+        username, group_id, timestamp
+    )
+    .into_bytes()
+}
+
+/// Bytes a member signs to reserve another user's KeyPackage for an invite
+pub fn reserve_key_package_payload(
+    reserved_by: &str,
+    target_username: &str,
+    group_id: &str,
+    timestamp: i64,
+) -> Vec<u8> {
+    format!(
+        "mls-chat-reserve:{}:{}:{}:{}",
+        reserved_by, target_username, group_id, timestamp
+    )
+    .into_bytes()
+}
+
+/// A Commit submitted for sequencing: it moves `group_id` out of `epoch`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SequenceCommitRequest {
+    pub group_id: String,
+    pub epoch: i64,
+    pub sender: String,
+    pub commit_blob: String,
+}
+
+/// The Commit the server accepted for one epoch of a group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequencedCommit {
+    pub epoch: i64,
+    pub sender: String,
+    pub commit_blob: String,
+}
+
+/// A group's public GroupInfo at `epoch`, published for external joiners
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublishedGroupInfo {
+    pub group_id: String,
+    pub epoch: i64,
+    pub group_info_blob: String,
+}
+
+/// Query for `GET /groups/info`
+#[derive(Debug, Deserialize)]
+pub struct GroupInfoQuery {
+    pub group_id: String,
+}
+
+/// Query for `GET /groups/messages`: messages of `group_id` after `after`
+#[derive(Debug, Deserialize)]
+pub struct MessagesSinceQuery {
+    pub group_id: String,
+    /// Member asking; only members may read a group's messages
+    pub username: String,
+    #[serde(default)]
+    pub after: i64,
+    pub limit: Option<i64>,
+}
+
+/// Query for `GET /groups/messages/by-sender`: a sender's messages in a group
+///
+/// `signature` is the base64 Ed25519 signature, by the signature key of the
+/// moderator's registered KeyPackage, of
+/// `messages_by_sender_payload(moderator, group_id, sender, timestamp)`.
+#[derive(Debug, Deserialize)]
+pub struct MessagesBySenderQuery {
+    pub group_id: String,
+    pub sender: String,
+    /// Moderator asking; must be configured as one and a member of the group
+    pub moderator: String,
+    pub limit: Option<i64>,
+    /// Unix seconds when the request was signed
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+/// Bytes a moderator signs to list a sender's messages in a group
+pub fn messages_by_sender_payload(
+    moderator: &str,
+    group_id: &str,
+    sender: &str,
+    timestamp: i64,
+) -> Vec<u8> {
+    format!(
+        "mls-chat-messages-by-sender:{}:{}:{}:{}",
+        moderator, group_id, sender, timestamp
+    )
+    .into_bytes()
+}
+
+/// Query for `GET /groups/commits`: accepted Commits from `epoch` onward
+#[derive(Debug, Deserialize)]
+pub struct CommitsSinceQuery {
+    pub group_id: String,
+    pub epoch: i64,
+}
+
+/// A Welcome kept on the server until the invitee picks it up
+///
+/// The blobs are stored as the inviter sent them; the server cannot read
+/// group secrets from a Welcome.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingWelcome {
+    pub id: i64,
+    pub inviter: String,
+    pub welcome_blob: String,
+    pub ratchet_tree_blob: String,
+    /// Unix seconds after which the Welcome is no longer delivered
+    pub expires_at: i64,
+}
+
+/// Body of `POST /welcomes`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreWelcomeRequest {
+    pub inviter: String,
+    pub invitee: String,
+    pub welcome_blob: String,
+    pub ratchet_tree_blob: String,
+}
+
+/// Body of `POST /groups/tokens`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateJoinTokenRequest {
+    pub group_id: String,
+    /// Member creating the token
+    pub created_by: String,
+    /// Seconds until the token expires; the server default when omitted
+    pub ttl_seconds: Option<i64>,
+}
+
+/// Body of `POST /groups/tokens/redeem`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemJoinTokenRequest {
+    pub token: String,
+    /// User asking to be added to the token's group
+    pub username: String,
+}
+
+/// Body of `POST /groups/tokens/revoke` and `POST /groups/tokens/complete`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinTokenActionRequest {
+    pub token: String,
+    /// Group member revoking the token or completing the add
+    pub username: String,
+}
+
+/// Query of `GET /groups/{group_id}/join-requests`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinRequestsQuery {
+    /// Member asking; only members may list a group's join requests
+    pub username: String,
+}
+
+/// A redeemed join token waiting for a member to add its redeemer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub token: String,
+    /// User who redeemed the token
+    pub username: String,
+    /// Unix seconds when the token was redeemed
+    pub redeemed_at: i64,
+}
+
+/// Message retention for a group; `None` keeps messages forever
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetMessageTtlRequest {
+    pub group_id: String,
+    pub message_ttl_secs: Option<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StoreBackupRequest {
     pub encrypted_state: String,
@@ -63,10 +385,17 @@ pub struct StoreBackupRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BackupResponse {
     pub username: String,
+    pub version: i64,
     pub encrypted_state: String,
     pub timestamp: String,
 }
 
+/// Which backup version to fetch; the latest when omitted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupVersionQuery {
+    pub version: Option<i64>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,4 +430,17 @@ mod tests {
         assert_eq!(deserialized.username, "bob");
         assert_eq!(deserialized.key_package, key_package);
     }
+
+    #[test]
+    fn test_content_type_roundtrip() {
+        for content_type in [ContentType::Text, ContentType::Control, ContentType::System] {
+            assert_eq!(
+                ContentType::parse(content_type.as_str()),
+                Some(content_type)
+            );
+            let json = serde_json::to_string(&content_type).expect("Serialization failed");
+            assert_eq!(json, format!("\"{}\"", content_type.as_str()));
+        }
+        assert_eq!(ContentType::parse("video"), None);
+    }
 }
@@ -0,0 +1,289 @@
+/// Binary WebSocket framing.
+///
+/// A client that connects with `?framing=binary` and gets `FRAMING_HEADER`
+/// back sends and receives envelopes as bincode-encoded `BinaryEnvelope`s,
+/// with the blobs as raw bytes instead of base64. The actor converts them to
+/// and from the JSON it handles anyway, so routing, persistence and metrics
+/// do not depend on the framing. Messages that are not envelopes (errors,
+/// acknowledgements) are always sent as JSON text.
+///
+/// `BinaryEnvelope` must stay identical to the client's copy in
+/// `client/rust/src/framing.rs`.
+use crate::db::models::ContentType;
+use base64::{engine::general_purpose, Engine as _};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+/// Response header confirming binary framing to the client
+pub const FRAMING_HEADER: &str = "x-mls-framing";
+
+/// Largest binary frame accepted, matching the client's envelope limit
+pub const MAX_BINARY_FRAME_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Wire form of an envelope in a binary frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum BinaryEnvelope {
+    Application {
+        sender: String,
+        group_id: Vec<u8>,
+        encrypted_content: Vec<u8>,
+        content_type: ContentType,
+        seq: Option<i64>,
+        epoch: Option<u64>,
+    },
+    Welcome {
+        inviter: String,
+        invitee: String,
+        welcome: Vec<u8>,
+        ratchet_tree: Vec<u8>,
+    },
+    Commit {
+        group_id: Vec<u8>,
+        sender: String,
+        commit: Vec<u8>,
+        device_id: Option<String>,
+    },
+}
+
+/// JSON form of the same envelopes, as sent in text frames
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonEnvelope {
+    Application {
+        sender: String,
+        group_id: String,
+        encrypted_content: String,
+        #[serde(default)]
+        content_type: ContentType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        epoch: Option<u64>,
+    },
+    Welcome {
+        inviter: String,
+        invitee: String,
+        welcome_blob: String,
+        ratchet_tree_blob: String,
+    },
+    Commit {
+        group_id: String,
+        sender: String,
+        commit_blob: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
+    },
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_limit(MAX_BINARY_FRAME_BYTES)
+        .reject_trailing_bytes()
+}
+
+/// Whether the `framing` query parameter asks for binary frames
+pub fn wants_binary(framing: Option<&str>) -> bool {
+    framing == Some("binary")
+}
+
+/// Convert a binary frame from a client to the JSON text of its envelope
+pub fn binary_to_json(bytes: &[u8]) -> Result<String, String> {
+    let wire: BinaryEnvelope = bincode_options()
+        .deserialize(bytes)
+        .map_err(|e| format!("invalid binary envelope: {}", e))?;
+    let b64 = |bytes: Vec<u8>| general_purpose::STANDARD.encode(bytes);
+    let envelope = match wire {
+        BinaryEnvelope::Application {
+            sender,
+            group_id,
+            encrypted_content,
+            content_type,
+            seq,
+            epoch,
+        } => JsonEnvelope::Application {
+            sender,
+            group_id: b64(group_id),
+            encrypted_content: b64(encrypted_content),
+            content_type,
+            seq,
+            epoch,
+        },
+        BinaryEnvelope::Welcome {
+            inviter,
+            invitee,
+            welcome,
+            ratchet_tree,
+        } => JsonEnvelope::Welcome {
+            inviter,
+            invitee,
+            welcome_blob: b64(welcome),
+            ratchet_tree_blob: b64(ratchet_tree),
+        },
+        BinaryEnvelope::Commit {
+            group_id,
+            sender,
+            commit,
+            device_id,
+        } => JsonEnvelope::Commit {
+            group_id: b64(group_id),
+            sender,
+            commit_blob: b64(commit),
+            device_id,
+        },
+    };
+    serde_json::to_string(&envelope).map_err(|e| e.to_string())
+}
+
+/// Convert an outgoing JSON message to a binary frame
+///
+/// Returns `None` if the message is not an envelope or one of its blobs is
+/// not base64; the caller then sends the JSON text as it is.
+pub fn json_to_binary(text: &str) -> Option<Vec<u8>> {
+    let raw = |encoded: String| general_purpose::STANDARD.decode(encoded).ok();
+    let wire = match serde_json::from_str::<JsonEnvelope>(text).ok()? {
+        JsonEnvelope::Application {
+            sender,
+            group_id,
+            encrypted_content,
+            content_type,
+            seq,
+            epoch,
+        } => BinaryEnvelope::Application {
+            sender,
+            group_id: raw(group_id)?,
+            encrypted_content: raw(encrypted_content)?,
+            content_type,
+            seq,
+            epoch,
+        },
+        JsonEnvelope::Welcome {
+            inviter,
+            invitee,
+            welcome_blob,
+            ratchet_tree_blob,
+        } => BinaryEnvelope::Welcome {
+            inviter,
+            invitee,
+            welcome: raw(welcome_blob)?,
+            ratchet_tree: raw(ratchet_tree_blob)?,
+        },
+        JsonEnvelope::Commit {
+            group_id,
+            sender,
+            commit_blob,
+            device_id,
+        } => BinaryEnvelope::Commit {
+            group_id: raw(group_id)?,
+            sender,
+            commit: raw(commit_blob)?,
+            device_id,
+        },
+    };
+    bincode_options().serialize(&wire).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn b64(len: usize, fill: u8) -> String {
+        general_purpose::STANDARD.encode(vec![fill; len])
+    }
+
+    #[test]
+    fn test_envelopes_round_trip_through_binary() {
+        let messages = [
+            json!({
+                "type": "application",
+                "sender": "alice",
+                "group_id": b64(16, 1),
+                "encrypted_content": b64(180, 2),
+                "content_type": "control",
+                "seq": 42,
+                "epoch": 3
+            }),
+            json!({
+                "type": "welcome",
+                "inviter": "alice",
+                "invitee": "bob",
+                "welcome_blob": b64(900, 3),
+                "ratchet_tree_blob": b64(600, 4)
+            }),
+            json!({
+                "type": "commit",
+                "group_id": b64(16, 1),
+                "sender": "alice",
+                "commit_blob": b64(700, 5)
+            }),
+            json!({
+                "type": "commit",
+                "group_id": b64(16, 1),
+                "sender": "alice",
+                "commit_blob": b64(700, 5),
+                "device_id": "0f8c1e9a-2b4d-4c6e-8a1f-3d5b7c9e0a2b"
+            }),
+        ];
+        for message in messages {
+            let text = message.to_string();
+            let binary = json_to_binary(&text).expect("envelope converts to binary");
+            assert!(binary.len() < text.len());
+            let back: serde_json::Value =
+                serde_json::from_str(&binary_to_json(&binary).unwrap()).unwrap();
+            assert_eq!(back, message);
+        }
+    }
+
+    #[test]
+    fn test_application_without_seq_converts() {
+        // Clients send application messages without a seq
+        let text = json!({
+            "type": "application",
+            "sender": "alice",
+            "group_id": b64(16, 1),
+            "encrypted_content": b64(10, 2),
+            "content_type": "text"
+        })
+        .to_string();
+        let binary = json_to_binary(&text).unwrap();
+        let back: serde_json::Value =
+            serde_json::from_str(&binary_to_json(&binary).unwrap()).unwrap();
+        assert!(back.get("seq").is_none());
+    }
+
+    #[test]
+    fn test_other_messages_stay_json() {
+        assert!(json_to_binary(&json!({"error": "Invalid message format"}).to_string()).is_none());
+        let bad_blob = json!({
+            "type": "commit",
+            "group_id": "not base64!",
+            "sender": "alice",
+            "commit_blob": b64(10, 5)
+        });
+        assert!(json_to_binary(&bad_blob.to_string()).is_none());
+    }
+
+    #[test]
+    fn test_malformed_binary_frames_are_errors() {
+        assert!(binary_to_json(&[]).is_err());
+        assert!(binary_to_json(&[9]).is_err());
+        let text = json!({
+            "type": "commit",
+            "group_id": b64(4, 1),
+            "sender": "alice",
+            "commit_blob": b64(10, 5)
+        })
+        .to_string();
+        let mut binary = json_to_binary(&text).unwrap();
+        assert!(binary_to_json(&binary[..binary.len() - 1]).is_err());
+        binary.push(0);
+        assert!(binary_to_json(&binary).is_err());
+    }
+
+    #[test]
+    fn test_only_binary_is_negotiated() {
+        assert!(wants_binary(Some("binary")));
+        assert!(!wants_binary(Some("json")));
+        assert!(!wants_binary(None));
+    }
+}
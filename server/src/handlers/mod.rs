@@ -1,11 +1,18 @@
 /// HTTP handlers module
 /// Provides REST and WebSocket endpoints
+pub mod framing;
 pub mod rest;
 pub mod websocket;
 
 pub use rest::{
-    get_backup, get_keypackage_status, get_user_key, health, register_user, reserve_key_package,
-    spend_key_package, store_backup, upload_key_packages,
+    complete_join_request, create_join_token, delete_welcome, deregister_user,
+    get_available_keypackage_count, get_backup, get_backup_version, get_commits_since, get_group,
+    get_group_epoch, get_group_info, get_group_members, get_join_requests, get_keypackage_status,
+    get_messages_by_sender, get_messages_since, get_metrics, get_pending_welcomes, get_user_groups,
+    get_user_identity, get_user_key, health, join_group, list_backup_versions, list_keypackages,
+    publish_group_info, redeem_join_token, register_group, register_user, release_reservation,
+    reserve_key_package, revoke_join_token, revoke_key_packages, rotate_user_key, sequence_commit,
+    set_group_message_ttl, spend_key_package, store_backup, store_welcome, upload_key_packages,
 };
 pub use websocket::{ws_connect, WsServer};
 
@@ -13,12 +20,31 @@ pub use websocket::{ws_connect, WsServer};
 #[derive(Clone, Debug)]
 pub struct ServerConfig {
     pub reservation_timeout_seconds: i64,
+    /// Maximum number of unspent KeyPackages stored per user
+    pub max_keypackages_per_user: usize,
+    /// Maximum number of groups a user can be a member of
+    pub max_groups_per_user: usize,
+    /// Number of backup versions kept per user; older ones are deleted
+    pub max_backup_versions: usize,
+    /// How long a stored Welcome waits for its invitee before it is dropped
+    pub welcome_ttl_seconds: i64,
+    /// Maximum number of WebSocket connections open at once per user
+    pub max_connections_per_user: usize,
+    /// Users allowed to list a group's messages by sender (see
+    /// `rest::get_messages_by_sender`)
+    pub moderators: Vec<String>,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             reservation_timeout_seconds: 60,
+            max_keypackages_per_user: 128,
+            max_groups_per_user: 100,
+            max_backup_versions: 10,
+            welcome_ttl_seconds: 7 * 24 * 60 * 60,
+            max_connections_per_user: 5,
+            moderators: Vec::new(),
         }
     }
 }
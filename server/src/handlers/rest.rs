@@ -1,9 +1,11 @@
 /// REST API handlers for HTTP endpoints.
-/// Handles user registration, key retrieval, and backup management.
+/// Handles user registration, key retrieval, group names, and backup management.
 use crate::db::{
     keypackage_store::KeyPackageStatus, keypackage_store::KeyPackageStore, models::*, Database,
-    DbPool,
+    DbPool, GroupClaim, JoinTokenRedemption,
 };
+use crate::handlers::WsServer;
+use crate::metrics::Metrics;
 use actix_web::{web, HttpResponse, Result as ActixResult};
 use base64::{engine::general_purpose, Engine as _};
 use serde_json::json;
@@ -26,13 +28,57 @@ struct UploadKeyPackagesResponse {
     accepted: usize,
     rejected: Vec<String>,
     pool_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
 }
 
+/// Seconds a client is told to wait (`Retry-After`) when the database is busy or full
+const BUSY_RETRY_AFTER_SECS: u64 = 5;
+
+/// Most messages returned by one `GET /groups/messages` request
+const MAX_MESSAGES_PER_PAGE: i64 = 500;
+
+/// Map a database error to an HTTP response
+///
+/// Contention (`SQLITE_BUSY`/`SQLITE_LOCKED`) and a full disk are transient:
+/// they become 503 Service Unavailable with a `Retry-After` header so clients
+/// back off instead of hammering the server. Anything else is a 500 with
+/// `message`.
+fn internal_error(err: &rusqlite::Error, message: &str) -> HttpResponse {
+    let transient = matches!(
+        err.sqlite_error_code(),
+        Some(
+            rusqlite::ErrorCode::DatabaseBusy
+                | rusqlite::ErrorCode::DatabaseLocked
+                | rusqlite::ErrorCode::DiskFull
+        )
+    );
+    if transient {
+        HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", BUSY_RETRY_AFTER_SECS.to_string()))
+            .json(json!({
+                "error": "Server busy, retry later",
+                "retry_after": BUSY_RETRY_AFTER_SECS
+            }))
+    } else {
+        HttpResponse::InternalServerError().json(json!({ "error": message }))
+    }
+}
+
+/// Error code reported when an upload would exceed the per-user KeyPackage quota
+const KEYPACKAGE_QUOTA_EXCEEDED: &str = "keypackage_quota_exceeded";
+
+/// `signature` is the base64 Ed25519 signature, by the signature key of
+/// `reserved_by`'s registered KeyPackage, of
+/// `reserve_key_package_payload(reserved_by, target_username, group_id, timestamp)`.
 #[derive(Debug, serde::Deserialize)]
 pub struct ReserveKeyPackageRequest {
     target_username: String,
     reserved_by: String,
     group_id: String,
+    /// Unix seconds when the request was signed
+    timestamp: i64,
+    signature: String,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -56,6 +102,46 @@ struct SpendKeyPackageResponse {
     spent: bool,
 }
 
+#[derive(Debug, serde::Deserialize)]
+pub struct ReleaseReservationRequest {
+    reservation_id: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ReleaseReservationResponse {
+    released: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RevokeKeyPackagesRequest {
+    username: String,
+    keypackage_refs: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RevokeKeyPackagesResponse {
+    revoked: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct KeyPackageCountResponse {
+    username: String,
+    available: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct KeyPackageListEntry {
+    keypackage_ref: String,
+    status: &'static str,
+    not_after: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct KeyPackageListResponse {
+    username: String,
+    keypackages: Vec<KeyPackageListEntry>,
+}
+
 #[derive(Debug, serde::Serialize)]
 struct KeyPackageStatusResponse {
     username: String,
@@ -72,30 +158,33 @@ struct KeyPackageStatusResponse {
 
 /// Register a new user with their key package
 /// POST /users
+///
+/// Re-registering with the same key package succeeds and returns the existing
+/// user, so clients can always learn their id. A different key package under
+/// a taken username returns 409.
 pub async fn register_user(
     pool: web::Data<DbPool>,
     req: web::Json<RegisterUserRequest>,
 ) -> ActixResult<HttpResponse> {
     match Database::register_user(&pool, &req.username, &req.key_package).await {
-        Ok(user) => {
-            let response = RegisterUserResponse {
-                id: user.id,
-                username: user.username,
-                created_at: user.created_at,
-            };
-            Ok(HttpResponse::Created().json(response))
+        Ok(user) => Ok(HttpResponse::Created().json(RegisterUserResponse::from(user))),
+        Err(e) if e.to_string().contains("UNIQUE constraint failed") => {
+            match Database::get_user(&pool, &req.username).await {
+                Ok(Some(user)) if user.key_package == req.key_package => {
+                    Ok(HttpResponse::Ok().json(RegisterUserResponse::from(user)))
+                }
+                Ok(_) => Ok(HttpResponse::Conflict().json(json!({
+                    "error": "Username already exists"
+                }))),
+                Err(e) => {
+                    log::error!("Database error: {}", e);
+                    Ok(internal_error(&e, "Failed to register user"))
+                }
+            }
         }
         Err(e) => {
             log::error!("Failed to register user: {}", e);
-            if e.to_string().contains("UNIQUE constraint failed") {
-                Ok(HttpResponse::Conflict().json(json!({
-                    "error": "Username already exists"
-                })))
-            } else {
-                Ok(HttpResponse::InternalServerError().json(json!({
-                    "error": "Failed to register user"
-                })))
-            }
+            Ok(internal_error(&e, "Failed to register user"))
         }
     }
 }
@@ -119,23 +208,30 @@ pub async fn get_user_key(
         }))),
         Err(e) => {
             log::error!("Database error: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to retrieve user"
-            })))
+            Ok(internal_error(&e, "Failed to retrieve user"))
         }
     }
 }
 
-/// Store encrypted state backup
-/// POST /backup/:username
-pub async fn store_backup(
+/// How far a deregistration's timestamp may be from the server clock
+pub const DEREGISTER_MAX_SKEW_SECS: u64 = 300;
+
+/// Delete a user account with its KeyPackages, backups and memberships
+/// DELETE /users/{username}
+///
+/// The request must be signed with the signature key of the KeyPackage the
+/// user registered with (see `DeregisterUserRequest`), within
+/// `DEREGISTER_MAX_SKEW_SECS` of now. Groups the user is in keep their leaf
+/// until a member removes it; the server no longer knows the user, so they
+/// can neither be invited nor fetch anything. The username becomes free,
+/// and a new registration under it has a different signature key.
+pub async fn deregister_user(
     pool: web::Data<DbPool>,
     username: web::Path<String>,
-    req: web::Json<StoreBackupRequest>,
+    req: web::Json<DeregisterUserRequest>,
 ) -> ActixResult<HttpResponse> {
-    // Verify user exists
-    match Database::get_user(&pool, &username).await {
-        Ok(Some(_)) => {}
+    let user = match Database::get_user(&pool, &username).await {
+        Ok(Some(user)) => user,
         Ok(None) => {
             return Ok(HttpResponse::NotFound().json(json!({
                 "error": "User not found"
@@ -143,341 +239,3108 @@ pub async fn store_backup(
         }
         Err(e) => {
             log::error!("Database error: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to check user"
-            })));
+            return Ok(internal_error(&e, "Failed to retrieve user"));
         }
+    };
+
+    if chrono::Utc::now().timestamp().abs_diff(req.timestamp) > DEREGISTER_MAX_SKEW_SECS {
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Deregistration request expired"
+        })));
+    }
+    let verified = keypackage_identity(&user.key_package).is_some_and(|(_, signature_key)| {
+        verify_ed25519(
+            &signature_key,
+            &deregister_payload(&username, req.timestamp),
+            &req.signature,
+        )
+    });
+    if !verified {
+        log::warn!("Refused deregistration of {}: bad signature", username);
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Invalid signature"
+        })));
     }
 
-    match Database::store_backup(&pool, &username, &req.encrypted_state).await {
-        Ok(backup) => {
-            let response = BackupResponse {
-                username: backup.username,
-                encrypted_state: backup.encrypted_state,
-                timestamp: backup.timestamp,
-            };
-            Ok(HttpResponse::Ok().json(response))
+    match Database::delete_user(&pool, &username).await {
+        Ok(true) => {
+            log::info!("Deregistered user {}", username);
+            Ok(HttpResponse::NoContent().finish())
         }
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        }))),
         Err(e) => {
-            log::error!("Failed to store backup: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to store backup"
-            })))
+            log::error!("Failed to deregister {}: {}", username, e);
+            Ok(internal_error(&e, "Failed to deregister user"))
         }
     }
 }
 
-/// Retrieve encrypted state backup
-/// GET /backup/:username
-pub async fn get_backup(
+/// Replace a user's registered KeyPackage after a credential rotation
+/// PUT /users/{username}
+///
+/// The request must be signed with the signature key of the currently
+/// registered KeyPackage (see `RotateUserKeyRequest`), within
+/// `DEREGISTER_MAX_SKEW_SECS` of now, and the new KeyPackage must carry a
+/// different signature key. All unspent KeyPackages of the user are
+/// revoked; the user uploads fresh ones under the new credential. Returns
+/// the number revoked.
+pub async fn rotate_user_key(
     pool: web::Data<DbPool>,
     username: web::Path<String>,
+    req: web::Json<RotateUserKeyRequest>,
 ) -> ActixResult<HttpResponse> {
-    match Database::get_backup(&pool, &username).await {
-        Ok(Some(backup)) => {
-            let response = BackupResponse {
-                username: backup.username,
-                encrypted_state: backup.encrypted_state,
-                timestamp: backup.timestamp,
-            };
-            Ok(HttpResponse::Ok().json(response))
+    let user = match Database::get_user(&pool, &username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "User not found"
+            })))
         }
-        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
-            "error": "No backup found"
-        }))),
         Err(e) => {
             log::error!("Database error: {}", e);
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to retrieve backup"
+            return Ok(internal_error(&e, "Failed to retrieve user"));
+        }
+    };
+
+    if chrono::Utc::now().timestamp().abs_diff(req.timestamp) > DEREGISTER_MAX_SKEW_SECS {
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Rotation request expired"
+        })));
+    }
+    let Some((_, old_signature_key)) = keypackage_identity(&user.key_package) else {
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Registered KeyPackage cannot be parsed"
+        })));
+    };
+    let verified = verify_ed25519(
+        &old_signature_key,
+        &rotate_key_payload(&username, req.timestamp, &req.key_package),
+        &req.signature,
+    );
+    if !verified {
+        log::warn!("Refused key rotation of {}: bad signature", username);
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Invalid signature"
+        })));
+    }
+    match keypackage_identity(&req.key_package) {
+        Some((_, signature_key)) if signature_key != old_signature_key => {}
+        Some(_) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": "New KeyPackage uses the registered signature key"
+            })))
+        }
+        None => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": "Invalid KeyPackage"
             })))
         }
     }
-}
 
-/// Health check endpoint
-/// GET /health
-pub async fn health() -> ActixResult<HttpResponse> {
-    Ok(HttpResponse::Ok().json(json!({
-        "status": "ok"
-    })))
+    match Database::replace_user_key_package(&pool, &username, &req.key_package).await {
+        Ok(Some(revoked)) => {
+            log::info!(
+                "Rotated registered key of {}; revoked {} KeyPackages",
+                username,
+                revoked
+            );
+            Ok(HttpResponse::Ok().json(json!({ "revoked": revoked })))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to rotate key of {}: {}", username, e);
+            Ok(internal_error(&e, "Failed to rotate key"))
+        }
+    }
 }
 
-/// Upload a batch of KeyPackages for a user
-/// POST /keypackages/upload
-pub async fn upload_key_packages(
-    pool: web::Data<DbPool>,
-    req: web::Json<UploadKeyPackagesRequest>,
-) -> ActixResult<HttpResponse> {
-    if req.keypackages.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(json!({
-            "error": "No keypackages provided"
-        })));
-    }
+/// Whether `signature_b64` is a valid Ed25519 signature of `payload` by
+/// the raw public key `public_key`
+fn verify_ed25519(public_key: &[u8], payload: &[u8], signature_b64: &str) -> bool {
+    use ed25519_dalek::{Signature, VerifyingKey};
 
-    let mut accepted = 0usize;
-    let mut rejected = Vec::new();
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(public_key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = general_purpose::STANDARD
+        .decode(signature_b64)
+        .map_err(|_| ())
+        .and_then(|bytes| Signature::from_slice(&bytes).map_err(|_| ()))
+    else {
+        return false;
+    };
+    public_key.verify_strict(payload, &signature).is_ok()
+}
 
-    for item in &req.keypackages {
-        let ref_bytes: Vec<u8> = match general_purpose::STANDARD.decode(&item.keypackage_ref) {
-            Ok(bytes) => bytes,
-            Err(err) => {
-                log::warn!("Invalid keypackage_ref for user {}: {}", req.username, err);
-                rejected.push(item.keypackage_ref.clone());
-                continue;
-            }
-        };
+/// Public identity carried by a TLS-serialized `KeyPackage`
+///
+/// Returns the TLS-serialized credential and the signature public key of the
+/// KeyPackage's leaf node, or `None` if the bytes are not an MLS 1.0
+/// KeyPackage. Only the fields in front of the credential are parsed.
+pub fn keypackage_identity(key_package: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    use crate::handlers::websocket::split_vl_bytes;
+    const MLS10: [u8; 2] = [0, 1];
 
-        let package_bytes: Vec<u8> = match general_purpose::STANDARD.decode(&item.keypackage) {
-            Ok(bytes) => bytes,
-            Err(err) => {
-                log::warn!(
-                    "Invalid keypackage bytes for user {}: {}",
-                    req.username,
-                    err
-                );
-                rejected.push(item.keypackage_ref.clone());
-                continue;
-            }
-        };
+    if key_package.get(0..2)? != MLS10 {
+        return None;
+    }
+    // Skip the cipher suite, init key and leaf encryption key
+    let (_init_key, rest) = split_vl_bytes(key_package.get(4..)?)?;
+    let (_encryption_key, rest) = split_vl_bytes(rest)?;
+    let (signature_key, rest) = split_vl_bytes(rest)?;
 
-        match KeyPackageStore::save_key_package(
-            &pool,
-            &req.username,
-            &ref_bytes,
-            &package_bytes,
-            item.not_after,
-            None,
-            None,
-        )
-        .await
-        {
-            Ok(_) => {
-                accepted += 1;
-                log::debug!(
-                    "Stored keypackage for user {} (ref={})",
-                    req.username,
-                    item.keypackage_ref
-                );
-            }
-            Err(err) => {
-                log::warn!(
-                    "Failed to store keypackage for user {}: {}",
-                    req.username,
-                    err
-                );
-                rejected.push(item.keypackage_ref.clone());
-            }
+    // Credential: a 16-bit type followed by its contents as a vector
+    let (_content, after_credential) = split_vl_bytes(rest.get(2..)?)?;
+    let credential = &rest[..rest.len() - after_credential.len()];
+    Some((credential.to_vec(), signature_key.to_vec()))
+}
+
+/// Get a user's public identity for the user directory
+/// GET /users/{username}/identity
+///
+/// Read from the KeyPackage the user registered with, so it holds only
+/// public material and always matches what that KeyPackage binds: the
+/// base64 TLS-serialized credential and signature public key. Returns 422
+/// if the registered KeyPackage cannot be parsed.
+pub async fn get_user_identity(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::get_user(&pool, &username).await {
+        Ok(Some(user)) => match keypackage_identity(&user.key_package) {
+            Some((credential, signature_key)) => Ok(HttpResponse::Ok().json(json!({
+                "username": user.username,
+                "credential": general_purpose::STANDARD.encode(credential),
+                "signature_key": general_purpose::STANDARD.encode(signature_key),
+            }))),
+            None => Ok(HttpResponse::UnprocessableEntity().json(json!({
+                "error": "Registered key package has no readable identity"
+            }))),
+        },
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        }))),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            Ok(internal_error(&e, "Failed to retrieve user"))
         }
     }
+}
 
-    let pool_size =
-        KeyPackageStore::count_by_status(&pool, &req.username, KeyPackageStatus::Available)
-            .await
-            .unwrap_or(0);
+/// Register a group name
+/// POST /groups
+///
+/// Names are claimed by the first group that registers them. Registering the
+/// same group again succeeds; registering a different group under a taken name
+/// returns 409 with the existing group id, so a second device creating the
+/// same group finds out instead of silently splitting the conversation.
+pub async fn register_group(
+    pool: web::Data<DbPool>,
+    req: web::Json<RegisterGroupRequest>,
+) -> ActixResult<HttpResponse> {
+    match Database::claim_group_name(&pool, &req.group_id, &req.name).await {
+        Ok(owner) if owner == req.group_id => Ok(HttpResponse::Ok().json(json!({
+            "group_id": owner,
+            "name": req.name
+        }))),
+        Ok(owner) => Ok(HttpResponse::Conflict().json(json!({
+            "error": "Group name already registered",
+            "existing_group_id": owner
+        }))),
+        Err(e) => {
+            log::error!("Failed to register group {}: {}", req.name, e);
+            Ok(internal_error(&e, "Failed to register group"))
+        }
+    }
+}
 
-    Ok(HttpResponse::Ok().json(UploadKeyPackagesResponse {
-        accepted,
-        rejected,
-        pool_size,
+/// 403 response for a user who is already in the maximum number of groups
+fn group_limit_reached(username: &str, limit: usize) -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "error": "group limit reached",
+        "username": username,
+        "limit": limit
     }))
 }
 
-/// Reserve a KeyPackage for an invitation
-/// POST /keypackages/reserve
-pub async fn reserve_key_package(
+/// Record that a user created or joined a group
+/// POST /groups/members
+///
+/// The request must be signed by the user (see `JoinGroupRequest`), 401
+/// otherwise. A user can only record themselves in a group nobody is in yet,
+/// i.e. one they are creating, or in a group they are already in (a no-op);
+/// anything else is 403, since invitees are recorded when a member reserves
+/// their KeyPackage. Fails with 403 "group limit reached" if the user is
+/// already in `max_groups_per_user` other groups.
+pub async fn join_group(
     pool: web::Data<DbPool>,
     config: web::Data<crate::handlers::ServerConfig>,
-    req: web::Json<ReserveKeyPackageRequest>,
+    req: web::Json<JoinGroupRequest>,
 ) -> ActixResult<HttpResponse> {
-    let group_id_bytes = match general_purpose::STANDARD.decode(&req.group_id) {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            return Ok(HttpResponse::BadRequest().json(json!({
-                "error": format!("Invalid group_id: {}", err)
-            })));
-        }
-    };
-
-    match KeyPackageStore::reserve_key_package_with_timeout(
+    if let Some(refused) = require_signature(
         &pool,
-        &req.target_username,
-        &group_id_bytes,
-        &req.reserved_by,
-        config.reservation_timeout_seconds,
+        &req.username,
+        &join_group_payload(&req.username, &req.group_id, req.timestamp),
+        req.timestamp,
+        &req.signature,
+        "Failed to join group",
     )
     .await
     {
-        Ok(Some(reserved)) => {
-            let response = ReserveKeyPackageResponse {
-                keypackage_ref: general_purpose::STANDARD.encode(&reserved.keypackage_ref),
-                keypackage: general_purpose::STANDARD.encode(&reserved.keypackage_bytes),
-                reservation_id: reserved.reservation_id,
-                reservation_expires_at: reserved.reservation_expires_at,
-                not_after: reserved.not_after,
-            };
+        return Ok(refused);
+    }
 
-            Ok(HttpResponse::Ok().json(response))
-        }
-        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
-            "error": "No available KeyPackage for target user"
+    match Database::claim_group_membership(
+        &pool,
+        &req.username,
+        &req.group_id,
+        config.max_groups_per_user,
+    )
+    .await
+    {
+        Ok(GroupClaim::Joined) => Ok(HttpResponse::Ok().json(json!({
+            "username": req.username,
+            "group_id": req.group_id
         }))),
-        Err(err) => {
-            log::error!(
-                "Failed to reserve keypackage for {}: {}",
-                req.target_username,
-                err
+        Ok(GroupClaim::GroupLimitReached) => Ok(group_limit_reached(
+            &req.username,
+            config.max_groups_per_user,
+        )),
+        Ok(GroupClaim::NotInvited) => {
+            log::warn!(
+                "Refused {} joining {}: not invited",
+                req.username,
+                req.group_id
             );
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to reserve keypackage"
+            Ok(HttpResponse::Forbidden().json(json!({
+                "error": "Not a member of this group"
             })))
         }
+        Err(e) => {
+            log::error!(
+                "Failed to record {} joining {}: {}",
+                req.username,
+                req.group_id,
+                e
+            );
+            Ok(internal_error(&e, "Failed to join group"))
+        }
     }
 }
 
-/// Mark a reserved KeyPackage as spent
-/// POST /keypackages/spend
-pub async fn spend_key_package(
+/// Sequence a Commit: accept it only if no other Commit holds its epoch
+/// POST /groups/commits
+///
+/// Members submit a Commit here before merging it. Returns 200
+/// `{"accepted": true}` for the first Commit of an epoch. Any later one gets
+/// 409 with the accepted Commits from that epoch onward, so the member can
+/// apply them and rebuild its change in the new epoch.
+pub async fn sequence_commit(
     pool: web::Data<DbPool>,
-    req: web::Json<SpendKeyPackageRequest>,
+    req: web::Json<SequenceCommitRequest>,
 ) -> ActixResult<HttpResponse> {
-    let keypackage_ref = match general_purpose::STANDARD.decode(&req.keypackage_ref) {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            return Ok(HttpResponse::BadRequest().json(json!({
-                "error": format!("Invalid keypackage_ref: {}", err)
-            })));
-        }
-    };
-
-    let group_id = match general_purpose::STANDARD.decode(&req.group_id) {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            return Ok(HttpResponse::BadRequest().json(json!({
-                "error": format!("Invalid group_id: {}", err)
-            })));
+    let accepted = match Database::sequence_commit(
+        &pool,
+        &req.group_id,
+        req.epoch,
+        &req.sender,
+        &req.commit_blob,
+    )
+    .await
+    {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            log::error!("Failed to sequence commit for {}: {}", req.group_id, e);
+            return Ok(internal_error(&e, "Failed to sequence commit"));
         }
     };
+    if accepted {
+        // The accepted Commit moves the group into the next epoch
+        if let Err(e) = Database::record_group_epoch(&pool, &req.group_id, req.epoch + 1).await {
+            log::warn!("Failed to record epoch of {}: {}", req.group_id, e);
+        }
+        return Ok(HttpResponse::Ok().json(json!({ "accepted": true })));
+    }
 
-    match KeyPackageStore::spend_key_package(&pool, &keypackage_ref, &group_id, &req.spent_by).await
+    log::info!(
+        "Commit from {} for epoch {} of {} was superseded",
+        req.sender,
+        req.epoch,
+        req.group_id
+    );
+    match Database::commits_since(&pool, &req.group_id, req.epoch).await {
+        Ok(commits) => Ok(HttpResponse::Conflict().json(json!({
+            "error": "epoch conflict",
+            "commits": commits
+        }))),
+        Err(e) => {
+            log::error!("Failed to list commits of {}: {}", req.group_id, e);
+            Ok(internal_error(&e, "Failed to sequence commit"))
+        }
+    }
+}
+
+/// Publish a group's public GroupInfo for external joiners
+/// POST /groups/info
+///
+/// The blob is stored as given; the server cannot read group secrets from it.
+/// Returns 409 if a GroupInfo for a later epoch is already stored.
+pub async fn publish_group_info(
+    pool: web::Data<DbPool>,
+    req: web::Json<PublishedGroupInfo>,
+) -> ActixResult<HttpResponse> {
+    if general_purpose::STANDARD
+        .decode(&req.group_info_blob)
+        .is_err()
     {
-        Ok(()) => Ok(HttpResponse::Ok().json(SpendKeyPackageResponse { spent: true })),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(HttpResponse::NotFound().json(json!({
-            "error": "KeyPackage not found"
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "group_info_blob must be base64"
+        })));
+    }
+
+    match Database::publish_group_info(&pool, &req).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(json!({
+            "group_id": req.group_id,
+            "epoch": req.epoch
         }))),
-        Err(rusqlite::Error::ExecuteReturnedResults) => Ok(HttpResponse::Conflict().json(json!({
-            "error": "KeyPackage already spent"
+        Ok(false) => Ok(HttpResponse::Conflict().json(json!({
+            "error": "a GroupInfo for a later epoch is already published"
         }))),
-        Err(err) => {
-            log::error!("Failed to spend keypackage: {}", err);
-            Ok(HttpResponse::InternalServerError().json(json!({
-                "error": "Failed to spend keypackage"
-            })))
+        Err(e) => {
+            log::error!("Failed to publish GroupInfo for {}: {}", req.group_id, e);
+            Ok(internal_error(&e, "Failed to publish GroupInfo"))
         }
     }
 }
 
-/// Get aggregate status for a user's KeyPackage pool
-/// GET /keypackages/status/{username}
-pub async fn get_keypackage_status(
+/// Fetch the latest published GroupInfo of a group
+/// GET /groups/info?group_id=
+pub async fn get_group_info(
     pool: web::Data<DbPool>,
-    username: web::Path<String>,
+    query: web::Query<GroupInfoQuery>,
 ) -> ActixResult<HttpResponse> {
-    use rusqlite::OptionalExtension;
+    match Database::get_group_info(&pool, &query.group_id).await {
+        Ok(Some(info)) => Ok(HttpResponse::Ok().json(info)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No GroupInfo published for this group"
+        }))),
+        Err(e) => {
+            log::error!("Failed to load GroupInfo for {}: {}", query.group_id, e);
+            Ok(internal_error(&e, "Failed to load GroupInfo"))
+        }
+    }
+}
 
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+/// Report the highest epoch the server has seen a Commit move a group into
+/// GET /groups/{group_id}/epoch
+///
+/// Lets a reconnecting member notice it is behind without waiting for a
+/// decryption failure. The value is advisory: it comes from Commit headers,
+/// which the server cannot verify. 404 if the server saw no Commit for the
+/// group.
+pub async fn get_group_epoch(
+    pool: web::Data<DbPool>,
+    group_id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::group_epoch(&pool, &group_id).await {
+        Ok(Some(epoch)) => Ok(HttpResponse::Ok().json(json!({
+            "group_id": group_id.as_str(),
+            "epoch": epoch
+        }))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No Commit seen for this group"
+        }))),
+        Err(e) => {
+            log::error!("Failed to load epoch of {}: {}", group_id, e);
+            Ok(internal_error(&e, "Failed to load group epoch"))
+        }
+    }
+}
 
-    let expiring_threshold = now + 172_800; // 48 hours
+/// List the users the server has recorded as members of a group
+/// GET /groups/{group_id}/members
+///
+/// Best effort: memberships are recorded when users create, join or are
+/// added to a group, and the server cannot see removals inside encrypted
+/// Commits. The MLS roster of a member's group state is authoritative; this
+/// list lets a client show the server's view before it has caught up. 404
+/// if no member is recorded for the group.
+pub async fn get_group_members(
+    pool: web::Data<DbPool>,
+    group_id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::list_group_members(&pool, &group_id).await {
+        Ok(members) if members.is_empty() => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No members recorded for this group"
+        }))),
+        Ok(members) => Ok(HttpResponse::Ok().json(json!({
+            "group_id": group_id.as_str(),
+            "members": members
+        }))),
+        Err(e) => {
+            log::error!("Failed to list members of {}: {}", group_id, e);
+            Ok(internal_error(&e, "Failed to list group members"))
+        }
+    }
+}
 
-    let conn = pool.lock().await;
+/// Look up a group the server knows
+/// GET /groups/{group_id}
+///
+/// Returns the public metadata the server stores: the name the group was
+/// registered under (its id if it never was) and when the server first saw
+/// it. 404 if the server has never seen the group.
+pub async fn get_group(
+    pool: web::Data<DbPool>,
+    group_id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::get_group(&pool, &group_id).await {
+        Ok(Some(group)) => Ok(HttpResponse::Ok().json(json!({
+            "group_id": group.group_id,
+            "name": group.name,
+            "created_at": group.created_at
+        }))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "Unknown group"
+        }))),
+        Err(e) => {
+            log::error!("Failed to load group {}: {}", group_id, e);
+            Ok(internal_error(&e, "Failed to load group"))
+        }
+    }
+}
 
-    let available: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'available' AND not_after > ?2",
-            (&username.as_str(), now),
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
+/// List the accepted Commits of a group from an epoch onward
+/// GET /groups/commits?group_id=&epoch=
+///
+/// Lets a member that missed Commits (e.g. while disconnected) catch up to
+/// the group's current epoch.
+pub async fn get_commits_since(
+    pool: web::Data<DbPool>,
+    query: web::Query<CommitsSinceQuery>,
+) -> ActixResult<HttpResponse> {
+    match Database::commits_since(&pool, &query.group_id, query.epoch).await {
+        Ok(commits) => Ok(HttpResponse::Ok().json(json!({ "commits": commits }))),
+        Err(e) => {
+            log::error!("Failed to list commits of {}: {}", query.group_id, e);
+            Ok(internal_error(&e, "Failed to list commits"))
+        }
+    }
+}
 
-    let reserved: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'reserved'",
-            (&username.as_str(),),
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
+/// List a group's stored messages after a sequence number, oldest first
+/// GET /groups/messages?group_id=&username=&after=&limit=
+///
+/// Used by members to fetch what they missed while disconnected. Only
+/// members of the group may read it (403 otherwise). At most
+/// `MAX_MESSAGES_PER_PAGE` messages are returned; ask again after the last
+/// `seq` for more.
+pub async fn get_messages_since(
+    pool: web::Data<DbPool>,
+    query: web::Query<MessagesSinceQuery>,
+) -> ActixResult<HttpResponse> {
+    match Database::is_group_member(&pool, &query.username, &query.group_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(HttpResponse::Forbidden().json(json!({
+                "error": "Not a member of this group"
+            })));
+        }
+        Err(e) => {
+            log::error!(
+                "Failed to check membership of {} in {}: {}",
+                query.username,
+                query.group_id,
+                e
+            );
+            return Ok(internal_error(&e, "Failed to list messages"));
+        }
+    }
 
-    let spent: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'spent'",
-            (&username.as_str(),),
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(MAX_MESSAGES_PER_PAGE)
+        .clamp(1, MAX_MESSAGES_PER_PAGE);
+    match Database::messages_since(&pool, &query.group_id, query.after, limit).await {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({ "messages": messages }))),
+        Err(e) => {
+            log::error!("Failed to list messages of {}: {}", query.group_id, e);
+            Ok(internal_error(&e, "Failed to list messages"))
+        }
+    }
+}
 
-    let expired: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND not_after <= ?2",
-            (&username.as_str(), now),
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
+/// A sender's messages in a group, for moderators
+/// GET /groups/messages/by-sender?group_id=&sender=&moderator=&timestamp=&signature=&limit=
+///
+/// Only usernames configured with `--moderator` who are also members of the
+/// group may ask (403 otherwise); membership is what lets their client
+/// decrypt the ciphertexts returned. The request must be signed with the
+/// signature key of the KeyPackage the moderator registered with (see
+/// `MessagesBySenderQuery`), within `DEREGISTER_MAX_SKEW_SECS` of now (401
+/// otherwise). Messages come newest first, at most `MAX_MESSAGES_PER_PAGE`
+/// of them.
+pub async fn get_messages_by_sender(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    query: web::Query<MessagesBySenderQuery>,
+) -> ActixResult<HttpResponse> {
+    if !config.moderators.contains(&query.moderator) {
+        log::warn!(
+            "Refused message listing by sender for {}: not a moderator",
+            query.moderator
+        );
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "Not a moderator"
+        })));
+    }
 
-    let expiring_soon: i64 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'available' AND not_after BETWEEN ?2 AND ?3",
-            (&username.as_str(), now, expiring_threshold),
-            |row| row.get(0),
-        )
-        .unwrap_or(0);
+    if chrono::Utc::now().timestamp().abs_diff(query.timestamp) > DEREGISTER_MAX_SKEW_SECS {
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Request expired"
+        })));
+    }
+    let moderator = match Database::get_user(&pool, &query.moderator).await {
+        Ok(moderator) => moderator,
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(internal_error(&e, "Failed to retrieve user"));
+        }
+    };
+    let verified = moderator
+        .and_then(|moderator| keypackage_identity(&moderator.key_package))
+        .is_some_and(|(_, signature_key)| {
+            verify_ed25519(
+                &signature_key,
+                &messages_by_sender_payload(
+                    &query.moderator,
+                    &query.group_id,
+                    &query.sender,
+                    query.timestamp,
+                ),
+                &query.signature,
+            )
+        });
+    if !verified {
+        log::warn!(
+            "Refused message listing by sender for {}: bad signature",
+            query.moderator
+        );
+        return Ok(HttpResponse::Unauthorized().json(json!({
+            "error": "Invalid signature"
+        })));
+    }
 
-    let last_upload: Option<i64> = conn
-        .query_row(
-            "SELECT MAX(uploaded_at) FROM keypackages WHERE username = ?1",
-            (&username.as_str(),),
-            |row| row.get(0),
-        )
-        .optional()
-        .unwrap_or(None);
+    if let Some(refused) = require_member(
+        &pool,
+        &query.moderator,
+        &query.group_id,
+        "Failed to list messages",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
 
-    drop(conn);
+    let limit = query
+        .limit
+        .unwrap_or(MAX_MESSAGES_PER_PAGE)
+        .clamp(1, MAX_MESSAGES_PER_PAGE);
+    match Database::get_messages_by_sender(&pool, &query.group_id, &query.sender, limit).await {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({ "messages": messages }))),
+        Err(e) => {
+            log::error!(
+                "Failed to list messages of {} in {}: {}",
+                query.sender,
+                query.group_id,
+                e
+            );
+            Ok(internal_error(&e, "Failed to list messages"))
+        }
+    }
+}
 
-    let total = (available + reserved + spent) as usize;
+/// Keep a Welcome for an invitee who may be offline
+/// POST /welcomes
+///
+/// The blobs are stored as given; the server cannot read group secrets from
+/// them. The Welcome is delivered by `GET /welcomes/{username}` until the
+/// invitee deletes it or `welcome_ttl_seconds` pass.
+pub async fn store_welcome(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    req: web::Json<StoreWelcomeRequest>,
+) -> ActixResult<HttpResponse> {
+    if [&req.welcome_blob, &req.ratchet_tree_blob]
+        .iter()
+        .any(|blob| general_purpose::STANDARD.decode(blob).is_err())
+    {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "welcome_blob and ratchet_tree_blob must be base64"
+        })));
+    }
 
-    let pool_health = if available == 0 {
-        "empty"
-    } else if available < 8 {
-        "low"
-    } else {
-        "healthy"
-    };
+    match Database::get_user(&pool, &req.invitee).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "Invitee not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to look up invitee {}: {}", req.invitee, e);
+            return Ok(internal_error(&e, "Failed to store Welcome"));
+        }
+    }
 
-    let recommended_action = if available == 0 {
-        Some("Upload new key packages immediately".to_string())
-    } else if available < 8 {
-        Some("Replenish key package pool".to_string())
-    } else {
-        None
-    };
+    let expires_at = chrono::Utc::now().timestamp() + config.welcome_ttl_seconds;
+    match Database::store_welcome(
+        &pool,
+        &req.invitee,
+        &req.inviter,
+        &req.welcome_blob,
+        &req.ratchet_tree_blob,
+        expires_at,
+    )
+    .await
+    {
+        Ok(id) => Ok(HttpResponse::Created().json(json!({
+            "id": id,
+            "expires_at": expires_at
+        }))),
+        Err(e) => {
+            log::error!("Failed to store Welcome for {}: {}", req.invitee, e);
+            Ok(internal_error(&e, "Failed to store Welcome"))
+        }
+    }
+}
 
-    let last_upload_str = last_upload
-        .and_then(|ts| chrono::DateTime::<chrono::Utc>::from_timestamp(ts, 0))
-        .map(|dt| dt.to_rfc3339());
+/// Lifetime of a join token created without `ttl_seconds`
+const DEFAULT_JOIN_TOKEN_TTL_SECS: i64 = 24 * 60 * 60;
 
-    let response = KeyPackageStatusResponse {
-        username: username.into_inner(),
-        available: available as usize,
-        reserved: reserved as usize,
-        spent: spent as usize,
-        expired: expired as usize,
-        total,
-        expiring_soon: expiring_soon as usize,
-        pool_health: pool_health.to_string(),
-        recommended_action,
-        last_upload: last_upload_str,
+/// Longest lifetime a join token can be created with
+const MAX_JOIN_TOKEN_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Check that `username` is a member of `group_id`, or build the response refusing them
+async fn require_member(
+    pool: &DbPool,
+    username: &str,
+    group_id: &str,
+    failure: &str,
+) -> Option<HttpResponse> {
+    match Database::is_group_member(pool, username, group_id).await {
+        Ok(true) => None,
+        Ok(false) => Some(HttpResponse::Forbidden().json(json!({
+            "error": "Not a member of this group"
+        }))),
+        Err(e) => {
+            log::error!(
+                "Failed to check membership of {} in {}: {}",
+                username,
+                group_id,
+                e
+            );
+            Some(internal_error(&e, failure))
+        }
+    }
+}
+
+/// Check that a request was signed by `username` within
+/// `DEREGISTER_MAX_SKEW_SECS` of now, or build the 401 refusing it
+///
+/// The signature must verify under the signature key of the KeyPackage
+/// `username` registered with.
+pub(crate) async fn require_signature(
+    pool: &DbPool,
+    username: &str,
+    payload: &[u8],
+    timestamp: i64,
+    signature: &str,
+    failure: &str,
+) -> Option<HttpResponse> {
+    if chrono::Utc::now().timestamp().abs_diff(timestamp) > DEREGISTER_MAX_SKEW_SECS {
+        return Some(HttpResponse::Unauthorized().json(json!({
+            "error": "Request expired"
+        })));
+    }
+    let user = match Database::get_user(pool, username).await {
+        Ok(user) => user,
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Some(internal_error(&e, failure));
+        }
     };
+    let verified = user
+        .and_then(|user| keypackage_identity(&user.key_package))
+        .is_some_and(|(_, signature_key)| verify_ed25519(&signature_key, payload, signature));
+    if verified {
+        None
+    } else {
+        log::warn!("Refused request from {}: bad signature", username);
+        Some(HttpResponse::Unauthorized().json(json!({
+            "error": "Invalid signature"
+        })))
+    }
+}
 
-    Ok(HttpResponse::Ok().json(response))
+/// Create a single-use token that lets its holder ask to join a group
+/// POST /groups/tokens
+///
+/// Only members of the group can create tokens (403 otherwise). The token
+/// expires after `ttl_seconds` (default one day, at most seven days).
+pub async fn create_join_token(
+    pool: web::Data<DbPool>,
+    req: web::Json<CreateJoinTokenRequest>,
+) -> ActixResult<HttpResponse> {
+    let ttl = req.ttl_seconds.unwrap_or(DEFAULT_JOIN_TOKEN_TTL_SECS);
+    if !(1..=MAX_JOIN_TOKEN_TTL_SECS).contains(&ttl) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": format!("ttl_seconds must be between 1 and {}", MAX_JOIN_TOKEN_TTL_SECS)
+        })));
+    }
+    if let Some(refused) = require_member(
+        &pool,
+        &req.created_by,
+        &req.group_id,
+        "Failed to create join token",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
+
+    let expires_at = chrono::Utc::now().timestamp() + ttl;
+    match Database::create_join_token(&pool, &req.group_id, &req.created_by, expires_at).await {
+        Ok(token) => Ok(HttpResponse::Created().json(json!({
+            "token": token,
+            "expires_at": expires_at
+        }))),
+        Err(e) => {
+            log::error!("Failed to create join token for {}: {}", req.group_id, e);
+            Ok(internal_error(&e, "Failed to create join token"))
+        }
+    }
+}
+
+/// Redeem a join token, asking to be added to its group
+/// POST /groups/tokens/redeem
+///
+/// The redeemer must be registered and have KeyPackages uploaded; a member
+/// of the group then completes the add with an ordinary invite. Unknown
+/// tokens are 404; expired, revoked and already redeemed ones are 410 with
+/// `reason` set accordingly.
+pub async fn redeem_join_token(
+    pool: web::Data<DbPool>,
+    req: web::Json<RedeemJoinTokenRequest>,
+) -> ActixResult<HttpResponse> {
+    match Database::get_user(&pool, &req.username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "User not found"
+            })));
+        }
+        Err(e) => {
+            log::error!("Failed to look up {}: {}", req.username, e);
+            return Ok(internal_error(&e, "Failed to redeem join token"));
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let reason = match Database::redeem_join_token(&pool, &req.token, &req.username, now).await {
+        Ok(JoinTokenRedemption::Redeemed { group_id }) => {
+            log::info!("{} redeemed a join token for {}", req.username, group_id);
+            return Ok(HttpResponse::Ok().json(json!({ "group_id": group_id })));
+        }
+        Ok(JoinTokenRedemption::NotFound) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "Join token not found"
+            })));
+        }
+        Ok(JoinTokenRedemption::Expired) => "expired",
+        Ok(JoinTokenRedemption::Revoked) => "revoked",
+        Ok(JoinTokenRedemption::AlreadyRedeemed) => "already_redeemed",
+        Err(e) => {
+            log::error!("Failed to redeem join token: {}", e);
+            return Ok(internal_error(&e, "Failed to redeem join token"));
+        }
+    };
+    Ok(HttpResponse::Gone().json(json!({
+        "error": "Join token can no longer be used",
+        "reason": reason
+    })))
+}
+
+/// Revoke a join token that has not been redeemed
+/// POST /groups/tokens/revoke
+///
+/// Any member of the token's group can revoke it. 404 if there is no such
+/// unredeemed token in a group of `username`.
+pub async fn revoke_join_token(
+    pool: web::Data<DbPool>,
+    req: web::Json<JoinTokenActionRequest>,
+) -> ActixResult<HttpResponse> {
+    match Database::revoke_join_token(&pool, &req.token, &req.username).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "Join token not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to revoke join token: {}", e);
+            Ok(internal_error(&e, "Failed to revoke join token"))
+        }
+    }
+}
+
+/// List the redeemed join tokens of a group waiting for their redeemer to be added
+/// GET /groups/{group_id}/join-requests?username=
+///
+/// Only members of the group may list them (403 otherwise).
+pub async fn get_join_requests(
+    pool: web::Data<DbPool>,
+    group_id: web::Path<String>,
+    query: web::Query<JoinRequestsQuery>,
+) -> ActixResult<HttpResponse> {
+    if let Some(refused) = require_member(
+        &pool,
+        &query.username,
+        &group_id,
+        "Failed to list join requests",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
+
+    match Database::join_requests(&pool, &group_id).await {
+        Ok(requests) => Ok(HttpResponse::Ok().json(json!({ "requests": requests }))),
+        Err(e) => {
+            log::error!("Failed to list join requests of {}: {}", group_id, e);
+            Ok(internal_error(&e, "Failed to list join requests"))
+        }
+    }
+}
+
+/// Mark a join request as done once its redeemer was added to the group
+/// POST /groups/tokens/complete
+pub async fn complete_join_request(
+    pool: web::Data<DbPool>,
+    req: web::Json<JoinTokenActionRequest>,
+) -> ActixResult<HttpResponse> {
+    match Database::complete_join_request(&pool, &req.token, &req.username).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "Join request not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to complete join request: {}", e);
+            Ok(internal_error(&e, "Failed to complete join request"))
+        }
+    }
+}
+
+/// List the unexpired Welcomes waiting for a user, oldest first
+/// GET /welcomes/{username}
+pub async fn get_pending_welcomes(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let now = chrono::Utc::now().timestamp();
+    match Database::pending_welcomes(&pool, &username, now).await {
+        Ok(welcomes) => Ok(HttpResponse::Ok().json(json!({ "welcomes": welcomes }))),
+        Err(e) => {
+            log::error!("Failed to list Welcomes for {}: {}", username, e);
+            Ok(internal_error(&e, "Failed to list Welcomes"))
+        }
+    }
+}
+
+/// Delete a stored Welcome once its invitee has joined
+/// DELETE /welcomes/{username}/{id}
+pub async fn delete_welcome(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i64)>,
+) -> ActixResult<HttpResponse> {
+    let (username, id) = path.into_inner();
+    match Database::delete_welcome(&pool, &username, id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "Welcome not found"
+        }))),
+        Err(e) => {
+            log::error!("Failed to delete Welcome {} of {}: {}", id, username, e);
+            Ok(internal_error(&e, "Failed to delete Welcome"))
+        }
+    }
+}
+
+/// List the groups a user has joined, with the per-user limit
+/// GET /users/{username}/groups
+pub async fn get_user_groups(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::list_user_groups(&pool, &username).await {
+        Ok(groups) => Ok(HttpResponse::Ok().json(json!({
+            "username": username.into_inner(),
+            "groups": groups,
+            "limit": config.max_groups_per_user
+        }))),
+        Err(e) => {
+            log::error!("Failed to list groups for {}: {}", username, e);
+            Ok(internal_error(&e, "Failed to list groups"))
+        }
+    }
+}
+
+/// Set a group's message retention
+/// POST /groups/retention
+///
+/// Messages stored afterwards expire `message_ttl_secs` after they were sent
+/// and are pruned periodically; earlier messages keep their own expiry.
+pub async fn set_group_message_ttl(
+    pool: web::Data<DbPool>,
+    req: web::Json<SetMessageTtlRequest>,
+) -> ActixResult<HttpResponse> {
+    if req.message_ttl_secs.is_some_and(|ttl| ttl <= 0) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "message_ttl_secs must be positive"
+        })));
+    }
+
+    match Database::set_message_ttl(&pool, &req.group_id, req.message_ttl_secs).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(json!({
+            "group_id": req.group_id,
+            "message_ttl_secs": req.message_ttl_secs
+        }))),
+        Err(e) => {
+            log::error!("Failed to set message TTL for {}: {}", req.group_id, e);
+            Ok(internal_error(&e, "Failed to set message TTL"))
+        }
+    }
+}
+
+/// Store encrypted state backup as the user's next version
+/// POST /backup/:username
+///
+/// Only the newest `ServerConfig::max_backup_versions` versions are kept.
+pub async fn store_backup(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    username: web::Path<String>,
+    req: web::Json<StoreBackupRequest>,
+) -> ActixResult<HttpResponse> {
+    // Verify user exists
+    match Database::get_user(&pool, &username).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "User not found"
+            })))
+        }
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            return Ok(internal_error(&e, "Failed to check user"));
+        }
+    }
+
+    let backup = match Database::store_backup(&pool, &username, &req.encrypted_state).await {
+        Ok(backup) => backup,
+        Err(e) => {
+            log::error!("Failed to store backup: {}", e);
+            return Ok(internal_error(&e, "Failed to store backup"));
+        }
+    };
+
+    // The new version is stored; failing to prune only delays the cleanup
+    match Database::prune_backup_versions(&pool, &username, config.max_backup_versions).await {
+        Ok(0) => {}
+        Ok(pruned) => log::debug!("Pruned {} old backup versions of {}", pruned, username),
+        Err(e) => log::warn!("Failed to prune backup versions of {}: {}", username, e),
+    }
+
+    Ok(HttpResponse::Ok().json(backup_response(backup)))
+}
+
+/// Retrieve the latest encrypted state backup
+/// GET /backup/:username
+pub async fn get_backup(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::get_backup(&pool, &username).await {
+        Ok(Some(backup)) => Ok(HttpResponse::Ok().json(backup_response(backup))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No backup found"
+        }))),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            Ok(internal_error(&e, "Failed to retrieve backup"))
+        }
+    }
+}
+
+/// Retrieve one backup version, or the latest without `version`
+/// GET /backups/{username}?version=
+///
+/// Lets a client roll back to an earlier backup when the latest is corrupt.
+pub async fn get_backup_version(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+    query: web::Query<BackupVersionQuery>,
+) -> ActixResult<HttpResponse> {
+    let backup = match query.version {
+        Some(version) => Database::get_backup_version(&pool, &username, version).await,
+        None => Database::get_backup(&pool, &username).await,
+    };
+    match backup {
+        Ok(Some(backup)) => Ok(HttpResponse::Ok().json(backup_response(backup))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No backup found"
+        }))),
+        Err(e) => {
+            log::error!("Database error: {}", e);
+            Ok(internal_error(&e, "Failed to retrieve backup"))
+        }
+    }
+}
+
+/// List the backup versions kept for a user, newest first
+/// GET /backups/{username}/versions
+pub async fn list_backup_versions(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match Database::list_backup_versions(&pool, &username).await {
+        Ok(versions) => Ok(HttpResponse::Ok().json(json!({
+            "username": username.as_str(),
+            "versions": versions
+        }))),
+        Err(e) => {
+            log::error!("Failed to list backup versions of {}: {}", username, e);
+            Ok(internal_error(&e, "Failed to list backup versions"))
+        }
+    }
+}
+
+fn backup_response(backup: Backup) -> BackupResponse {
+    BackupResponse {
+        username: backup.username,
+        version: backup.version,
+        encrypted_state: backup.encrypted_state,
+        timestamp: backup.timestamp,
+    }
+}
+
+/// Health check endpoint
+/// GET /health
+pub async fn health() -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "ok"
+    })))
+}
+
+/// Message metrics in the Prometheus text format
+/// GET /metrics
+///
+/// Answers 404 unless the server was built with the `metrics` feature.
+pub async fn get_metrics(ws_server: web::Data<WsServer>) -> ActixResult<HttpResponse> {
+    if !Metrics::ENABLED {
+        return Ok(HttpResponse::NotFound().json(json!({
+            "error": "Metrics are not enabled in this build"
+        })));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(ws_server.metrics.render()))
+}
+
+/// Upload a batch of KeyPackages for a user
+/// POST /keypackages/upload
+///
+/// Enforces `ServerConfig::max_keypackages_per_user`: expired packages are swept
+/// first, then items beyond the remaining quota are reported in `rejected` with
+/// `error_code` set to `keypackage_quota_exceeded`.
+pub async fn upload_key_packages(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    req: web::Json<UploadKeyPackagesRequest>,
+) -> ActixResult<HttpResponse> {
+    if req.keypackages.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "No keypackages provided"
+        })));
+    }
+
+    // Expired packages must not count toward the quota
+    if let Err(err) = KeyPackageStore::sweep_expired_for_user(&pool, &req.username).await {
+        log::warn!(
+            "Failed to sweep expired keypackages for user {}: {}",
+            req.username,
+            err
+        );
+    }
+
+    let stored = match KeyPackageStore::count_unspent(&pool, &req.username).await {
+        Ok(count) => count,
+        Err(err) => {
+            log::error!(
+                "Failed to count keypackages for user {}: {}",
+                req.username,
+                err
+            );
+            return Ok(internal_error(&err, "Failed to upload keypackages"));
+        }
+    };
+    let remaining = config.max_keypackages_per_user.saturating_sub(stored);
+
+    let mut accepted = 0usize;
+    let mut rejected = Vec::new();
+    let mut error_code = None;
+
+    for item in &req.keypackages {
+        if accepted >= remaining {
+            log::warn!(
+                "KeyPackage quota ({}) reached for user {}, rejecting ref={}",
+                config.max_keypackages_per_user,
+                req.username,
+                item.keypackage_ref
+            );
+            rejected.push(item.keypackage_ref.clone());
+            error_code = Some(KEYPACKAGE_QUOTA_EXCEEDED.to_string());
+            continue;
+        }
+
+        let ref_bytes: Vec<u8> = match general_purpose::STANDARD.decode(&item.keypackage_ref) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                log::warn!("Invalid keypackage_ref for user {}: {}", req.username, err);
+                rejected.push(item.keypackage_ref.clone());
+                continue;
+            }
+        };
+
+        let package_bytes: Vec<u8> = match general_purpose::STANDARD.decode(&item.keypackage) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                log::warn!(
+                    "Invalid keypackage bytes for user {}: {}",
+                    req.username,
+                    err
+                );
+                rejected.push(item.keypackage_ref.clone());
+                continue;
+            }
+        };
+
+        match KeyPackageStore::save_key_package(
+            &pool,
+            &req.username,
+            &ref_bytes,
+            &package_bytes,
+            item.not_after,
+            None,
+            None,
+        )
+        .await
+        {
+            Ok(_) => {
+                accepted += 1;
+                log::debug!(
+                    "Stored keypackage for user {} (ref={})",
+                    req.username,
+                    item.keypackage_ref
+                );
+            }
+            Err(err) => {
+                log::warn!(
+                    "Failed to store keypackage for user {}: {}",
+                    req.username,
+                    err
+                );
+                rejected.push(item.keypackage_ref.clone());
+            }
+        }
+    }
+
+    let pool_size =
+        KeyPackageStore::count_by_status(&pool, &req.username, KeyPackageStatus::Available)
+            .await
+            .unwrap_or(0);
+
+    Ok(HttpResponse::Ok().json(UploadKeyPackagesResponse {
+        accepted,
+        rejected,
+        pool_size,
+        error_code,
+    }))
+}
+
+/// Reserve a KeyPackage for an invitation
+/// POST /keypackages/reserve
+///
+/// The request must be signed by `reserved_by` (see
+/// `ReserveKeyPackageRequest`), 401 otherwise, who must be a member of the
+/// group (403 otherwise): the target is recorded as a member here. Fails
+/// with 403 "group limit reached" if the target user could not join another
+/// group.
+pub async fn reserve_key_package(
+    pool: web::Data<DbPool>,
+    config: web::Data<crate::handlers::ServerConfig>,
+    req: web::Json<ReserveKeyPackageRequest>,
+) -> ActixResult<HttpResponse> {
+    let group_id_bytes = match general_purpose::STANDARD.decode(&req.group_id) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": format!("Invalid group_id: {}", err)
+            })));
+        }
+    };
+
+    if let Some(refused) = require_signature(
+        &pool,
+        &req.reserved_by,
+        &reserve_key_package_payload(
+            &req.reserved_by,
+            &req.target_username,
+            &req.group_id,
+            req.timestamp,
+        ),
+        req.timestamp,
+        &req.signature,
+        "Failed to reserve keypackage",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
+    if let Some(refused) = require_member(
+        &pool,
+        &req.reserved_by,
+        &req.group_id,
+        "Failed to reserve keypackage",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
+
+    // Fail the invitation early rather than after the Welcome is sent
+    match Database::can_join_group(
+        &pool,
+        &req.target_username,
+        &req.group_id,
+        config.max_groups_per_user,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(group_limit_reached(
+                &req.target_username,
+                config.max_groups_per_user,
+            ))
+        }
+        Err(err) => {
+            log::error!(
+                "Failed to check group limit for {}: {}",
+                req.target_username,
+                err
+            );
+            return Ok(internal_error(&err, "Failed to reserve keypackage"));
+        }
+    }
+
+    match KeyPackageStore::reserve_key_package_with_timeout(
+        &pool,
+        &req.target_username,
+        &group_id_bytes,
+        &req.reserved_by,
+        config.reservation_timeout_seconds,
+    )
+    .await
+    {
+        Ok(Some(reserved)) => {
+            // The invitee subscribes as soon as it processes the Welcome, so
+            // it must count as a member before the Commit and Welcome go out
+            if let Err(err) =
+                Database::add_group_member(&pool, &req.target_username, &req.group_id).await
+            {
+                log::error!(
+                    "Failed to record {} as a member of the group: {}",
+                    req.target_username,
+                    err
+                );
+                return Ok(internal_error(&err, "Failed to record group membership"));
+            }
+
+            let response = ReserveKeyPackageResponse {
+                keypackage_ref: general_purpose::STANDARD.encode(&reserved.keypackage_ref),
+                keypackage: general_purpose::STANDARD.encode(&reserved.keypackage_bytes),
+                reservation_id: reserved.reservation_id,
+                reservation_expires_at: reserved.reservation_expires_at,
+                not_after: reserved.not_after,
+            };
+
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "No available KeyPackage for target user"
+        }))),
+        Err(err) => {
+            log::error!(
+                "Failed to reserve keypackage for {}: {}",
+                req.target_username,
+                err
+            );
+            Ok(internal_error(&err, "Failed to reserve keypackage"))
+        }
+    }
+}
+
+/// Mark a reserved KeyPackage as spent
+/// POST /keypackages/spend
+pub async fn spend_key_package(
+    pool: web::Data<DbPool>,
+    req: web::Json<SpendKeyPackageRequest>,
+) -> ActixResult<HttpResponse> {
+    let keypackage_ref = match general_purpose::STANDARD.decode(&req.keypackage_ref) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": format!("Invalid keypackage_ref: {}", err)
+            })));
+        }
+    };
+
+    let group_id = match general_purpose::STANDARD.decode(&req.group_id) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": format!("Invalid group_id: {}", err)
+            })));
+        }
+    };
+
+    match KeyPackageStore::spend_key_package(&pool, &keypackage_ref, &group_id, &req.spent_by).await
+    {
+        Ok(owner) => {
+            // Normally recorded on reservation already; covers KeyPackages
+            // spent without one
+            if let Err(err) = Database::add_group_member(&pool, &owner, &req.group_id).await {
+                log::error!(
+                    "Failed to record {} as a member of the group: {}",
+                    owner,
+                    err
+                );
+                return Ok(internal_error(&err, "Failed to record group membership"));
+            }
+            Ok(HttpResponse::Ok().json(SpendKeyPackageResponse { spent: true }))
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(HttpResponse::NotFound().json(json!({
+            "error": "KeyPackage not found"
+        }))),
+        Err(rusqlite::Error::ExecuteReturnedResults) => Ok(HttpResponse::Conflict().json(json!({
+            "error": "KeyPackage already spent"
+        }))),
+        Err(err) => {
+            log::error!("Failed to spend keypackage: {}", err);
+            Ok(internal_error(&err, "Failed to spend keypackage"))
+        }
+    }
+}
+
+/// Release a reservation made for an invite that was aborted
+/// POST /keypackages/release
+///
+/// The invitee recorded as a member when the KeyPackage was reserved is
+/// removed from the group again. Always succeeds; `released` is false when
+/// the reservation was already spent, released or expired.
+pub async fn release_reservation(
+    pool: web::Data<DbPool>,
+    req: web::Json<ReleaseReservationRequest>,
+) -> ActixResult<HttpResponse> {
+    let released = match KeyPackageStore::release_reservation(&pool, &req.reservation_id).await {
+        Ok(released) => released,
+        Err(err) => {
+            log::error!("Failed to release reservation: {}", err);
+            return Ok(internal_error(&err, "Failed to release reservation"));
+        }
+    };
+    let Some(released) = released else {
+        return Ok(HttpResponse::Ok().json(ReleaseReservationResponse { released: false }));
+    };
+
+    let group_id = general_purpose::STANDARD.encode(&released.group_id);
+    if let Err(err) = Database::remove_group_member(&pool, &released.username, &group_id).await {
+        log::error!(
+            "Failed to remove {} from the group after releasing their reservation: {}",
+            released.username,
+            err
+        );
+        return Ok(internal_error(&err, "Failed to release reservation"));
+    }
+    Ok(HttpResponse::Ok().json(ReleaseReservationResponse { released: true }))
+}
+
+/// Revoke KeyPackages a client discarded from its local pool
+/// POST /keypackages/revoke
+///
+/// Deletes the user's listed KeyPackages unless they were already spent, so
+/// they can no longer be reserved; an inviter holding a reservation on one
+/// gets "KeyPackage not found" when spending it. `revoked` counts the
+/// KeyPackages actually deleted.
+pub async fn revoke_key_packages(
+    pool: web::Data<DbPool>,
+    req: web::Json<RevokeKeyPackagesRequest>,
+) -> ActixResult<HttpResponse> {
+    let mut keypackage_refs = Vec::with_capacity(req.keypackage_refs.len());
+    for encoded in &req.keypackage_refs {
+        match general_purpose::STANDARD.decode(encoded) {
+            Ok(bytes) => keypackage_refs.push(bytes),
+            Err(err) => {
+                return Ok(HttpResponse::BadRequest().json(json!({
+                    "error": format!("Invalid keypackage_ref: {}", err)
+                })));
+            }
+        }
+    }
+
+    match KeyPackageStore::revoke_for_user(&pool, &req.username, &keypackage_refs).await {
+        Ok(revoked) => {
+            log::info!("Revoked {} keypackages for {}", revoked, req.username);
+            Ok(HttpResponse::Ok().json(RevokeKeyPackagesResponse { revoked }))
+        }
+        Err(err) => {
+            log::error!("Failed to revoke keypackages for {}: {}", req.username, err);
+            Ok(internal_error(&err, "Failed to revoke keypackages"))
+        }
+    }
+}
+
+/// Get the number of KeyPackages available for inviting a user
+/// GET /users/{username}/keypackages/count
+///
+/// Only exposes the count, so inviters can fail fast without learning anything
+/// else about the invitee's pool.
+pub async fn get_available_keypackage_count(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match KeyPackageStore::count_available(&pool, &username).await {
+        Ok(available) => Ok(HttpResponse::Ok().json(KeyPackageCountResponse {
+            username: username.into_inner(),
+            available,
+        })),
+        Err(err) => {
+            log::error!("Failed to count keypackages for {}: {}", username, err);
+            Ok(internal_error(&err, "Failed to count keypackages"))
+        }
+    }
+}
+
+/// List the refs, statuses and expiry of a user's KeyPackages
+/// GET /keypackages/list/{username}
+///
+/// For debugging divergence between the server and a client's local pool.
+/// Only metadata is returned, never the KeyPackage bytes.
+pub async fn list_keypackages(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    match KeyPackageStore::list_for_user(&pool, &username).await {
+        Ok(summaries) => Ok(HttpResponse::Ok().json(KeyPackageListResponse {
+            username: username.into_inner(),
+            keypackages: summaries
+                .into_iter()
+                .map(|summary| KeyPackageListEntry {
+                    keypackage_ref: general_purpose::STANDARD.encode(&summary.keypackage_ref),
+                    status: summary.status.as_str(),
+                    not_after: summary.not_after,
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            log::error!("Failed to list keypackages for {}: {}", username, err);
+            Ok(internal_error(&err, "Failed to list keypackages"))
+        }
+    }
+}
+
+/// Get aggregate status for a user's KeyPackage pool
+/// GET /keypackages/status/{username}
+pub async fn get_keypackage_status(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    use rusqlite::OptionalExtension;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let expiring_threshold = now + 172_800; // 48 hours
+
+    let conn = pool.lock().await;
+
+    let available: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'available' AND not_after > ?2",
+            (&username.as_str(), now),
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    let reserved: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'reserved'",
+            (&username.as_str(),),
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    let spent: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'spent'",
+            (&username.as_str(),),
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    let expired: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND not_after <= ?2",
+            (&username.as_str(), now),
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    let expiring_soon: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM keypackages WHERE username = ?1 AND status = 'available' AND not_after BETWEEN ?2 AND ?3",
+            (&username.as_str(), now, expiring_threshold),
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    let last_upload: Option<i64> = conn
+        .query_row(
+            "SELECT MAX(uploaded_at) FROM keypackages WHERE username = ?1",
+            (&username.as_str(),),
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap_or(None);
+
+    drop(conn);
+
+    let total = (available + reserved + spent) as usize;
+
+    let pool_health = if available == 0 {
+        "empty"
+    } else if available < 8 {
+        "low"
+    } else {
+        "healthy"
+    };
+
+    let recommended_action = if available == 0 {
+        Some("Upload new key packages immediately".to_string())
+    } else if available < 8 {
+        Some("Replenish key package pool".to_string())
+    } else {
+        None
+    };
+
+    let last_upload_str = last_upload
+        .and_then(|ts| chrono::DateTime::<chrono::Utc>::from_timestamp(ts, 0))
+        .map(|dt| dt.to_rfc3339());
+
+    let response = KeyPackageStatusResponse {
+        username: username.into_inner(),
+        available: available as usize,
+        reserved: reserved as usize,
+        spent: spent as usize,
+        expired: expired as usize,
+        total,
+        expiring_soon: expiring_soon as usize,
+        pool_health: pool_health.to_string(),
+        recommended_action,
+        last_upload: last_upload_str,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ServerConfig;
+    use actix_web::{test, App};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    /// Register `username` with a KeyPackage whose signature key is derived
+    /// from `seed`, and return that key for signing requests
+    async fn register_signer(pool: &DbPool, username: &str, seed: u8) -> SigningKey {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        // version, cipher suite, init key, encryption key, signature key,
+        // Basic credential
+        let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 32];
+        key_package.extend_from_slice(signing_key.verifying_key().as_bytes());
+        key_package.extend_from_slice(&[0, 1, 1, seed]);
+        Database::register_user(pool, username, &key_package)
+            .await
+            .unwrap();
+        signing_key
+    }
+
+    fn sign_b64(signer: &SigningKey, payload: &[u8]) -> String {
+        general_purpose::STANDARD.encode(signer.sign(payload).to_bytes())
+    }
+
+    /// Body of a `POST /groups/members` signed by `signer`
+    fn join_request(username: &str, group_id: &str, signer: &SigningKey) -> serde_json::Value {
+        let timestamp = chrono::Utc::now().timestamp();
+        json!({
+            "username": username,
+            "group_id": group_id,
+            "timestamp": timestamp,
+            "signature": sign_b64(signer, &join_group_payload(username, group_id, timestamp)),
+        })
+    }
+
+    /// Body of a `POST /keypackages/reserve` signed by `signer`
+    fn reserve_request(
+        target_username: &str,
+        reserved_by: &str,
+        group_id: &str,
+        signer: &SigningKey,
+    ) -> serde_json::Value {
+        let timestamp = chrono::Utc::now().timestamp();
+        let payload =
+            reserve_key_package_payload(reserved_by, target_username, group_id, timestamp);
+        json!({
+            "target_username": target_username,
+            "reserved_by": reserved_by,
+            "group_id": group_id,
+            "timestamp": timestamp,
+            "signature": sign_b64(signer, &payload),
+        })
+    }
+
+    fn upload_item(id: u8, not_after: i64) -> serde_json::Value {
+        json!({
+            "keypackage_ref": general_purpose::STANDARD.encode([id]),
+            "keypackage": general_purpose::STANDARD.encode([id, id]),
+            "not_after": not_after,
+        })
+    }
+
+    async fn upload(
+        pool: &DbPool,
+        max_keypackages_per_user: usize,
+        items: Vec<serde_json::Value>,
+    ) -> serde_json::Value {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig {
+                    max_keypackages_per_user,
+                    ..ServerConfig::default()
+                }))
+                .route("/keypackages/upload", web::post().to(upload_key_packages)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/keypackages/upload")
+            .set_json(json!({ "username": "alice", "keypackages": items }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        test::read_body_json(resp).await
+    }
+
+    #[actix_web::test]
+    async fn test_busy_database_maps_to_retry_after() {
+        let busy = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        let resp = internal_error(&busy, "Failed to store backup");
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            resp.headers().get("Retry-After").unwrap(),
+            &BUSY_RETRY_AFTER_SECS.to_string()
+        );
+
+        let other = rusqlite::Error::InvalidQuery;
+        let resp = internal_error(&other, "Failed to store backup");
+        assert_eq!(
+            resp.status(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(resp.headers().get("Retry-After").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_upload_up_to_quota_accepts_all() {
+        let pool = crate::db::create_test_pool();
+        let items = (1..=3).map(|id| upload_item(id, 9999999999)).collect();
+
+        let body = upload(&pool, 3, items).await;
+
+        assert_eq!(body["accepted"], 3);
+        assert_eq!(body["rejected"].as_array().unwrap().len(), 0);
+        assert_eq!(body["pool_size"], 3);
+        assert!(body.get("error_code").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_upload_beyond_quota_rejects_excess() {
+        let pool = crate::db::create_test_pool();
+        let items = (1..=5).map(|id| upload_item(id, 9999999999)).collect();
+
+        let body = upload(&pool, 3, items).await;
+
+        assert_eq!(body["accepted"], 3);
+        assert_eq!(body["pool_size"], 3);
+        assert_eq!(body["error_code"], KEYPACKAGE_QUOTA_EXCEEDED);
+        let rejected: Vec<&str> = body["rejected"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r.as_str().unwrap())
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                general_purpose::STANDARD.encode([4]),
+                general_purpose::STANDARD.encode([5])
+            ]
+        );
+
+        // A later upload is rejected entirely while the pool is full
+        let body = upload(&pool, 3, vec![upload_item(6, 9999999999)]).await;
+        assert_eq!(body["accepted"], 0);
+        assert_eq!(body["error_code"], KEYPACKAGE_QUOTA_EXCEEDED);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_sweeps_expired_before_quota_check() {
+        let pool = crate::db::create_test_pool();
+        for id in 1..=3u8 {
+            KeyPackageStore::save_key_package(&pool, "alice", &[id], &[id], 1, None, None)
+                .await
+                .unwrap();
+        }
+
+        let items = (4..=6).map(|id| upload_item(id, 9999999999)).collect();
+        let body = upload(&pool, 3, items).await;
+
+        assert_eq!(body["accepted"], 3);
+        assert_eq!(body["rejected"].as_array().unwrap().len(), 0);
+        assert_eq!(
+            KeyPackageStore::count_unspent(&pool, "alice")
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    async fn available_count(pool: &DbPool, username: &str) -> serde_json::Value {
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).route(
+            "/users/{username}/keypackages/count",
+            web::get().to(get_available_keypackage_count),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/keypackages/count", username))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        test::read_body_json(resp).await
+    }
+
+    #[actix_web::test]
+    async fn test_available_count_with_several_packages() {
+        let pool = crate::db::create_test_pool();
+        for id in 1..=3u8 {
+            KeyPackageStore::save_key_package(&pool, "bob", &[id], &[id], 9999999999, None, None)
+                .await
+                .unwrap();
+        }
+        // Expired and reserved packages are not available
+        KeyPackageStore::save_key_package(&pool, "bob", &[4], &[4], 1, None, None)
+            .await
+            .unwrap();
+        KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[0xaa], "alice", 60)
+            .await
+            .unwrap();
+
+        let body = available_count(&pool, "bob").await;
+
+        assert_eq!(body, json!({ "username": "bob", "available": 2 }));
+    }
+
+    #[actix_web::test]
+    async fn test_available_count_with_one_package() {
+        let pool = crate::db::create_test_pool();
+        KeyPackageStore::save_key_package(&pool, "bob", &[1], &[1], 9999999999, None, None)
+            .await
+            .unwrap();
+
+        let body = available_count(&pool, "bob").await;
+
+        assert_eq!(body["available"], 1);
+    }
+
+    #[actix_web::test]
+    async fn test_available_count_with_no_packages() {
+        let pool = crate::db::create_test_pool();
+
+        let body = available_count(&pool, "nobody").await;
+
+        assert_eq!(body, json!({ "username": "nobody", "available": 0 }));
+    }
+
+    #[actix_web::test]
+    async fn test_reregistering_user_returns_existing_id() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/users", web::post().to(register_user)),
+        )
+        .await;
+
+        let mut ids = Vec::new();
+        for (key_package, expected) in [(vec![1u8, 2], 201), (vec![1, 2], 200), (vec![3], 409)] {
+            let req = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({ "username": "alice", "key_package": key_package }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), expected);
+            if expected != 409 {
+                let body: RegisterUserResponse = test::read_body_json(resp).await;
+                assert_eq!(body.username, "alice");
+                ids.push(body.id);
+            }
+        }
+        assert_eq!(ids[0], ids[1]);
+    }
+
+    #[actix_web::test]
+    async fn test_register_group_conflict_returns_existing_id() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups", web::post().to(register_group)),
+        )
+        .await;
+
+        for (group_id, expected) in [("g1", 200), ("g1", 200), ("g2", 409)] {
+            let req = test::TestRequest::post()
+                .uri("/groups")
+                .set_json(json!({ "group_id": group_id, "name": "alice:team" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), expected);
+            if expected == 409 {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["existing_group_id"], "g1");
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_set_group_message_ttl() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups/retention", web::post().to(set_group_message_ttl)),
+        )
+        .await;
+
+        for (ttl, expected) in [(json!(3600), 200), (json!(null), 200), (json!(0), 400)] {
+            let req = test::TestRequest::post()
+                .uri("/groups/retention")
+                .set_json(json!({ "group_id": "g1", "message_ttl_secs": ttl }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), expected);
+        }
+
+        // The group row exists even though no message was stored yet
+        let group = Database::get_group(&pool, "g1")
+            .await
+            .expect("Failed to get group");
+        assert!(group.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_backup_versions_can_be_listed_and_rolled_back() {
+        let pool = crate::db::create_test_pool();
+        Database::register_user(&pool, "alice", b"kp")
+            .await
+            .expect("Failed to register user");
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig {
+                    max_backup_versions: 2,
+                    ..ServerConfig::default()
+                }))
+                .route("/backup/{username}", web::post().to(store_backup))
+                .route("/backups/{username}", web::get().to(get_backup_version))
+                .route(
+                    "/backups/{username}/versions",
+                    web::get().to(list_backup_versions),
+                ),
+        )
+        .await;
+
+        for state in ["good-1", "good-2", "corrupt"] {
+            let req = test::TestRequest::post()
+                .uri("/backup/alice")
+                .set_json(json!({ "encrypted_state": state }))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+
+        // Only the two newest versions are kept
+        let req = test::TestRequest::get()
+            .uri("/backups/alice/versions")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let versions: Vec<i64> = body["versions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["version"].as_i64().unwrap())
+            .collect();
+        assert_eq!(versions, vec![3, 2]);
+        assert_eq!(body["versions"][0]["size"], "corrupt".len());
+
+        let req = test::TestRequest::get().uri("/backups/alice").to_request();
+        let latest: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(latest["encrypted_state"], "corrupt");
+
+        // Roll back to the last good version
+        let req = test::TestRequest::get()
+            .uri("/backups/alice?version=2")
+            .to_request();
+        let older: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(older["version"], 2);
+        assert_eq!(older["encrypted_state"], "good-2");
+
+        // Pruned versions are gone
+        let req = test::TestRequest::get()
+            .uri("/backups/alice?version=1")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_join_group_beyond_limit_is_rejected() {
+        let pool = crate::db::create_test_pool();
+        let alice = register_signer(&pool, "alice", 1).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig {
+                    max_groups_per_user: 2,
+                    ..ServerConfig::default()
+                }))
+                .route("/groups/members", web::post().to(join_group))
+                .route("/users/{username}/groups", web::get().to(get_user_groups)),
+        )
+        .await;
+
+        // Under the limit, and rejoining, succeed; a third group does not
+        for (group_id, expected) in [("g1", 200), ("g2", 200), ("g1", 200), ("g3", 403)] {
+            let req = test::TestRequest::post()
+                .uri("/groups/members")
+                .set_json(join_request("alice", group_id, &alice))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), expected, "joining {}", group_id);
+            if expected == 403 {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["error"], "group limit reached");
+                assert_eq!(body["limit"], 2);
+            }
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/users/alice/groups")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["groups"], json!(["g1", "g2"]));
+        assert_eq!(body["limit"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_join_group_requires_a_signature_and_an_open_group() {
+        let pool = crate::db::create_test_pool();
+        let alice = register_signer(&pool, "alice", 1).await;
+        let mallory = register_signer(&pool, "mallory", 2).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig::default()))
+                .route("/groups/members", web::post().to(join_group)),
+        )
+        .await;
+        let join = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/groups/members")
+                .set_json(body)
+                .to_request()
+        };
+
+        // Unsigned, or signed by someone else: 401
+        let unsigned = json!({
+            "username": "alice",
+            "group_id": "Z3JvdXAx",
+            "timestamp": chrono::Utc::now().timestamp(),
+            "signature": "",
+        });
+        let resp = test::call_service(&app, join(unsigned)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp =
+            test::call_service(&app, join(join_request("alice", "Z3JvdXAx", &mallory))).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Alice creates the group; Mallory cannot add herself to it
+        let resp = test::call_service(&app, join(join_request("alice", "Z3JvdXAx", &alice))).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp =
+            test::call_service(&app, join(join_request("mallory", "Z3JvdXAx", &mallory))).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        assert!(!Database::is_group_member(&pool, "mallory", "Z3JvdXAx")
+            .await
+            .unwrap());
+
+        // Once invited (recorded on reservation), joining again is a no-op
+        Database::add_group_member(&pool, "mallory", "Z3JvdXAx")
+            .await
+            .unwrap();
+        let resp =
+            test::call_service(&app, join(join_request("mallory", "Z3JvdXAx", &mallory))).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_reserve_for_user_at_group_limit_is_rejected() {
+        let pool = crate::db::create_test_pool();
+        KeyPackageStore::save_key_package(&pool, "bob", &[1], &[1, 1], 9999999999, None, None)
+            .await
+            .unwrap();
+        for group_id in ["Z3JvdXAx", "Z3JvdXAy"] {
+            Database::add_group_member(&pool, "alice", group_id)
+                .await
+                .unwrap();
+        }
+        Database::add_group_member(&pool, "bob", "Z3JvdXAx")
+            .await
+            .unwrap();
+        let alice = register_signer(&pool, "alice", 1).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig {
+                    max_groups_per_user: 1,
+                    ..ServerConfig::default()
+                }))
+                .route("/keypackages/reserve", web::post().to(reserve_key_package)),
+        )
+        .await;
+
+        let reserve = |group_id: &str| {
+            test::TestRequest::post()
+                .uri("/keypackages/reserve")
+                .set_json(reserve_request("bob", "alice", group_id, &alice))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, reserve("Z3JvdXAy")).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "group limit reached");
+
+        // Inviting bob to a group already counted for them is still allowed
+        let resp = test::call_service(&app, reserve("Z3JvdXAx")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_reserve_records_invitee_membership() {
+        let pool = crate::db::create_test_pool();
+        KeyPackageStore::save_key_package(&pool, "bob", &[6], &[6, 6], 9999999999, None, None)
+            .await
+            .unwrap();
+        let alice = register_signer(&pool, "alice", 1).await;
+        let mallory = register_signer(&pool, "mallory", 2).await;
+        Database::add_group_member(&pool, "alice", "Z3JvdXAx")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig::default()))
+                .route("/keypackages/reserve", web::post().to(reserve_key_package)),
+        )
+        .await;
+
+        let reserve = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/keypackages/reserve")
+                .set_json(body)
+                .to_request()
+        };
+
+        // A non-member cannot add anyone, nor claim to be a member
+        let resp = test::call_service(
+            &app,
+            reserve(reserve_request("bob", "mallory", "Z3JvdXAx", &mallory)),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = test::call_service(
+            &app,
+            reserve(reserve_request("bob", "alice", "Z3JvdXAx", &mallory)),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(!Database::is_group_member(&pool, "bob", "Z3JvdXAx")
+            .await
+            .unwrap());
+
+        let resp = test::call_service(
+            &app,
+            reserve(reserve_request("bob", "alice", "Z3JvdXAx", &alice)),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // Bob is a member before the Welcome is sent, not once it is spent
+        assert!(Database::is_group_member(&pool, "bob", "Z3JvdXAx")
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_spend_records_invitee_membership() {
+        let pool = crate::db::create_test_pool();
+        KeyPackageStore::save_key_package(&pool, "bob", &[7], &[7, 7], 9999999999, None, None)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/keypackages/spend", web::post().to(spend_key_package)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/keypackages/spend")
+            .set_json(json!({
+                "keypackage_ref": general_purpose::STANDARD.encode([7]),
+                "group_id": "Z3JvdXAx",
+                "spent_by": "alice",
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // The invitee (not the inviter) is now a member of the group
+        assert!(Database::is_group_member(&pool, "bob", "Z3JvdXAx")
+            .await
+            .unwrap());
+        assert!(!Database::is_group_member(&pool, "alice", "Z3JvdXAx")
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_release_reservation_removes_the_invitee() {
+        let pool = crate::db::create_test_pool();
+        KeyPackageStore::save_key_package(&pool, "bob", &[9], &[9, 9], 9999999999, None, None)
+            .await
+            .unwrap();
+        let reserved =
+            KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[1], "alice", 60)
+                .await
+                .unwrap()
+                .unwrap();
+        // As recorded by `reserve_key_package`
+        let group_id = general_purpose::STANDARD.encode([1]);
+        Database::add_group_member(&pool, "bob", &group_id)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/keypackages/release", web::post().to(release_reservation)),
+        )
+        .await;
+
+        for expected in [true, false] {
+            let req = test::TestRequest::post()
+                .uri("/keypackages/release")
+                .set_json(json!({ "reservation_id": reserved.reservation_id }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 200);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["released"], expected);
+        }
+        assert!(!Database::is_group_member(&pool, "bob", &group_id)
+            .await
+            .unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_endpoint() {
+        let pool = web::Data::new(crate::db::create_test_pool());
+        let ws_server = web::Data::new(WsServer::new(std::sync::Arc::new(pool.clone())));
+        ws_server
+            .metrics
+            .record_received(crate::metrics::MessageKind::Application, 1200);
+        let app = test::init_service(
+            App::new()
+                .app_data(ws_server.clone())
+                .route("/metrics", web::get().to(get_metrics)),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        if !Metrics::ENABLED {
+            assert_eq!(resp.status(), 404);
+            return;
+        }
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/plain; version=0.0.4"
+        );
+        let body = test::read_body(resp).await;
+        let text = std::str::from_utf8(&body).unwrap();
+        assert!(text.contains("# TYPE mls_chat_message_size_bytes histogram\n"));
+        assert!(text
+            .lines()
+            .any(|line| line == "mls_chat_messages_received_total{type=\"application\"} 1"));
+        assert!(text
+            .lines()
+            .any(|line| line
+                == "mls_chat_message_size_bytes_bucket{type=\"application\",le=\"4096\"} 1"));
+    }
+
+    #[actix_web::test]
+    async fn test_messages_by_sender_only_for_moderators() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let pool = crate::db::create_test_pool();
+        let mut ids = std::collections::HashMap::new();
+        let mut signing_keys = std::collections::HashMap::new();
+        for (user, seed) in [("alice", 1), ("bob", 2), ("mod", 3)] {
+            let signing_key = SigningKey::from_bytes(&[seed; 32]);
+            // version, cipher suite, init key, encryption key, signature key,
+            // Basic credential
+            let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 32];
+            key_package.extend_from_slice(signing_key.verifying_key().as_bytes());
+            key_package.extend_from_slice(&[0, 1, 1, seed]);
+            let registered = Database::register_user(&pool, user, &key_package)
+                .await
+                .unwrap();
+            ids.insert(user, registered.id);
+            signing_keys.insert(user, signing_key);
+        }
+        for user in ["alice", "mod"] {
+            Database::add_group_member(&pool, user, "Z3JvdXAx")
+                .await
+                .unwrap();
+        }
+        let group = Database::create_group(&pool, "Z3JvdXAx", "team")
+            .await
+            .unwrap();
+        for (sender, content) in [("alice", "YQ=="), ("bob", "Yg=="), ("alice", "YWE=")] {
+            Database::store_message(&pool, group.id, ids[sender], content, ContentType::Text)
+                .await
+                .unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig {
+                    moderators: vec!["mod".to_string(), "bob".to_string()],
+                    ..ServerConfig::default()
+                }))
+                .route(
+                    "/groups/messages/by-sender",
+                    web::get().to(get_messages_by_sender),
+                ),
+        )
+        .await;
+        let get = |moderator: &str, timestamp: i64, signer: &SigningKey| {
+            let payload = messages_by_sender_payload(moderator, "Z3JvdXAx", "alice", timestamp);
+            let signature = general_purpose::STANDARD
+                .encode(signer.sign(&payload).to_bytes())
+                .replace('+', "%2B")
+                .replace('/', "%2F")
+                .replace('=', "%3D");
+            test::TestRequest::get()
+                .uri(&format!(
+                    "/groups/messages/by-sender?group_id=Z3JvdXAx&sender=alice&moderator={}\
+                     &timestamp={}&signature={}",
+                    moderator, timestamp, signature
+                ))
+                .to_request()
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        // Naming a moderator is not enough: the request must be theirs, and fresh
+        let resp = test::call_service(&app, get("mod", now, &signing_keys["alice"])).await;
+        assert_eq!(resp.status(), 401);
+        let resp = test::call_service(&app, get("mod", now - 3600, &signing_keys["mod"])).await;
+        assert_eq!(resp.status(), 401);
+
+        let resp = test::call_service(&app, get("mod", now, &signing_keys["mod"])).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["encrypted_content"], "YWE=");
+        assert_eq!(messages[1]["encrypted_content"], "YQ==");
+        assert!(messages.iter().all(|m| m["sender"] == "alice"));
+
+        // A member who is not a moderator
+        let resp = test::call_service(&app, get("alice", now, &signing_keys["alice"])).await;
+        assert_eq!(resp.status(), 403);
+        // A moderator who is not a member
+        let resp = test::call_service(&app, get("bob", now, &signing_keys["bob"])).await;
+        assert_eq!(resp.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_join_token_endpoints() {
+        let pool = crate::db::create_test_pool();
+        for user in ["alice", "bob", "carol"] {
+            Database::register_user(&pool, user, &[1]).await.unwrap();
+        }
+        Database::add_group_member(&pool, "alice", "Z3JvdXAx")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups/tokens", web::post().to(create_join_token))
+                .route("/groups/tokens/redeem", web::post().to(redeem_join_token))
+                .route("/groups/tokens/revoke", web::post().to(revoke_join_token))
+                .route(
+                    "/groups/tokens/complete",
+                    web::post().to(complete_join_request),
+                )
+                .route(
+                    "/groups/{group_id}/join-requests",
+                    web::get().to(get_join_requests),
+                ),
+        )
+        .await;
+        let create = |created_by: &str, ttl_seconds: Option<i64>| {
+            test::TestRequest::post()
+                .uri("/groups/tokens")
+                .set_json(json!({
+                    "group_id": "Z3JvdXAx",
+                    "created_by": created_by,
+                    "ttl_seconds": ttl_seconds,
+                }))
+                .to_request()
+        };
+        let redeem = |token: &str, username: &str| {
+            test::TestRequest::post()
+                .uri("/groups/tokens/redeem")
+                .set_json(json!({ "token": token, "username": username }))
+                .to_request()
+        };
+
+        // Non-members cannot create tokens; TTLs are bounded
+        let resp = test::call_service(&app, create("bob", None)).await;
+        assert_eq!(resp.status(), 403);
+        let resp = test::call_service(&app, create("alice", Some(0))).await;
+        assert_eq!(resp.status(), 400);
+
+        let resp = test::call_service(&app, create("alice", None)).await;
+        assert_eq!(resp.status(), 201);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let resp = test::call_service(&app, redeem(&token, "bob")).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["group_id"], "Z3JvdXAx");
+
+        // Single use
+        let resp = test::call_service(&app, redeem(&token, "carol")).await;
+        assert_eq!(resp.status(), 410);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["reason"], "already_redeemed");
+        let resp = test::call_service(&app, redeem("no-such-token", "carol")).await;
+        assert_eq!(resp.status(), 404);
+
+        // Only members see and complete the request
+        let req = test::TestRequest::get()
+            .uri("/groups/Z3JvdXAx/join-requests?username=carol")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+        let req = test::TestRequest::get()
+            .uri("/groups/Z3JvdXAx/join-requests?username=alice")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["requests"][0]["username"], "bob");
+        let req = test::TestRequest::post()
+            .uri("/groups/tokens/complete")
+            .set_json(json!({ "token": token, "username": "alice" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 204);
+
+        // A revoked token is refused
+        let resp = test::call_service(&app, create("alice", Some(60))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let req = test::TestRequest::post()
+            .uri("/groups/tokens/revoke")
+            .set_json(json!({ "token": token, "username": "alice" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 204);
+        let resp = test::call_service(&app, redeem(&token, "carol")).await;
+        assert_eq!(resp.status(), 410);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["reason"], "revoked");
+    }
+
+    #[actix_web::test]
+    async fn test_revoke_keypackages_keeps_spent_and_foreign_ones() {
+        let pool = crate::db::create_test_pool();
+        for id in 1..=4u8 {
+            KeyPackageStore::save_key_package(
+                &pool,
+                "bob",
+                &[id],
+                &[id, id],
+                9999999999,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        KeyPackageStore::save_key_package(&pool, "carol", &[5], &[5, 5], 9999999999, None, None)
+            .await
+            .unwrap();
+        KeyPackageStore::spend_key_package(&pool, &[3], &[0xaa], "alice")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/keypackages/revoke", web::post().to(revoke_key_packages))
+                .route("/keypackages/spend", web::post().to(spend_key_package)),
+        )
+        .await;
+
+        let keypackage_refs: Vec<String> = [1u8, 2, 3, 5]
+            .iter()
+            .map(|id| general_purpose::STANDARD.encode([*id]))
+            .collect();
+        let req = test::TestRequest::post()
+            .uri("/keypackages/revoke")
+            .set_json(json!({
+                "username": "bob",
+                "keypackage_refs": keypackage_refs,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["revoked"], 2);
+
+        let remaining: Vec<Vec<u8>> = KeyPackageStore::list_for_user(&pool, "bob")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.keypackage_ref)
+            .collect();
+        assert_eq!(remaining, vec![vec![3], vec![4]]);
+        assert_eq!(
+            KeyPackageStore::count_unspent(&pool, "carol")
+                .await
+                .unwrap(),
+            1
+        );
+
+        // Spending a revoked KeyPackage fails as if it never existed
+        let req = test::TestRequest::post()
+            .uri("/keypackages/spend")
+            .set_json(json!({
+                "keypackage_ref": general_purpose::STANDARD.encode([1]),
+                "group_id": "Z3JvdXAx",
+                "spent_by": "alice",
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let req = test::TestRequest::post()
+            .uri("/keypackages/revoke")
+            .set_json(json!({ "username": "bob", "keypackage_refs": ["not base64!"] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_list_keypackages_reports_mixed_statuses_without_bytes() {
+        let pool = crate::db::create_test_pool();
+        for id in 1..=4u8 {
+            KeyPackageStore::save_key_package(
+                &pool,
+                "bob",
+                &[id],
+                &[id, id],
+                9999999999,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        KeyPackageStore::save_key_package(&pool, "bob", &[5], &[5, 5], 1, None, None)
+            .await
+            .unwrap();
+        let reserved =
+            KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[0xaa], "alice", 60)
+                .await
+                .unwrap()
+                .unwrap();
+        let spent =
+            KeyPackageStore::reserve_key_package_with_timeout(&pool, "bob", &[0xaa], "alice", 60)
+                .await
+                .unwrap()
+                .unwrap();
+        KeyPackageStore::spend_key_package(&pool, &spent.keypackage_ref, &[0xaa], "alice")
+            .await
+            .unwrap();
+
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).route(
+            "/keypackages/list/{username}",
+            web::get().to(list_keypackages),
+        ))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/keypackages/list/bob")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+
+        assert_eq!(body["username"], "bob");
+        let entries = body["keypackages"].as_array().unwrap();
+        assert_eq!(entries.len(), 5);
+        let status_of = |kp_ref: &[u8]| {
+            let encoded = general_purpose::STANDARD.encode(kp_ref);
+            entries
+                .iter()
+                .find(|entry| entry["keypackage_ref"] == encoded.as_str())
+                .map(|entry| entry["status"].as_str().unwrap().to_string())
+                .unwrap()
+        };
+        assert_eq!(status_of(&reserved.keypackage_ref), "reserved");
+        assert_eq!(status_of(&spent.keypackage_ref), "spent");
+        assert_eq!(status_of(&[5]), "available");
+        let available = entries
+            .iter()
+            .filter(|entry| entry["status"] == "available")
+            .count();
+        assert_eq!(available, 3);
+
+        // Only metadata is exposed
+        for entry in entries {
+            let mut keys: Vec<&str> = entry
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            keys.sort();
+            assert_eq!(keys, vec!["keypackage_ref", "not_after", "status"]);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/keypackages/list/nobody")
+            .to_request();
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["keypackages"], json!([]));
+    }
+
+    #[actix_web::test]
+    async fn test_superseded_commit_gets_the_accepted_ones() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups/commits", web::post().to(sequence_commit)),
+        )
+        .await;
+        let submit = |epoch: i64, sender: &str, blob: &str| {
+            test::TestRequest::post()
+                .uri("/groups/commits")
+                .set_json(json!({
+                    "group_id": "Z3JvdXA=",
+                    "epoch": epoch,
+                    "sender": sender,
+                    "commit_blob": blob,
+                }))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, submit(1, "alice", "add-carol")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["accepted"], true);
+
+        // Bob built his Commit in the same epoch: he gets alice's to catch up
+        let resp = test::call_service(&app, submit(1, "bob", "add-dave")).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body["commits"],
+            json!([{ "epoch": 1, "sender": "alice", "commit_blob": "add-carol" }])
+        );
+
+        // Rebuilt in the next epoch, it is accepted
+        let resp = test::call_service(&app, submit(2, "bob", "add-dave-retry")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_published_group_info_can_be_fetched() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups/info", web::post().to(publish_group_info))
+                .route("/groups/info", web::get().to(get_group_info)),
+        )
+        .await;
+        let publish = |epoch: i64, blob: &str| {
+            test::TestRequest::post()
+                .uri("/groups/info")
+                .set_json(json!({
+                    "group_id": "Z3JvdXA=",
+                    "epoch": epoch,
+                    "group_info_blob": blob,
+                }))
+                .to_request()
+        };
+        let fetch = || {
+            test::TestRequest::get()
+                .uri("/groups/info?group_id=Z3JvdXA%3D")
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = test::call_service(&app, publish(4, "aW5mby00")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = test::call_service(&app, publish(3, "aW5mby0z")).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let resp = test::call_service(&app, publish(5, "not base64!")).await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body,
+            json!({ "group_id": "Z3JvdXA=", "epoch": 4, "group_info_blob": "aW5mby00" })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_group_epoch_advances_with_accepted_commits() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups/commits", web::post().to(sequence_commit))
+                .route("/groups/{group_id}/epoch", web::get().to(get_group_epoch)),
+        )
+        .await;
+        let submit = |epoch: i64, sender: &str| {
+            test::TestRequest::post()
+                .uri("/groups/commits")
+                .set_json(json!({
+                    "group_id": "Z3Jv/XA=",
+                    "epoch": epoch,
+                    "sender": sender,
+                    "commit_blob": "commit",
+                }))
+                .to_request()
+        };
+        // Base64 group ids are percent-encoded as a path segment
+        let fetch = || {
+            test::TestRequest::get()
+                .uri("/groups/Z3Jv%2FXA%3D/epoch")
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        test::call_service(&app, submit(1, "alice")).await;
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&app, fetch()).await).await;
+        assert_eq!(body, json!({ "group_id": "Z3Jv/XA=", "epoch": 2 }));
+
+        // A superseded Commit does not move the epoch
+        let resp = test::call_service(&app, submit(1, "bob")).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&app, fetch()).await).await;
+        assert_eq!(body["epoch"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_get_group_returns_registered_metadata() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/groups", web::post().to(register_group))
+                .route("/groups/{group_id}", web::get().to(get_group)),
+        )
+        .await;
+        let fetch = || {
+            test::TestRequest::get()
+                .uri("/groups/Z3Jv%2FXA%3D")
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Unknown group");
+
+        let req = test::TestRequest::post()
+            .uri("/groups")
+            .set_json(json!({ "group_id": "Z3Jv/XA=", "name": "alice:team" }))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["group_id"], "Z3Jv/XA=");
+        assert_eq!(body["name"], "alice:team");
+        assert!(body["created_at"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn test_group_members_lists_recorded_memberships() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ServerConfig::default()))
+                .route("/groups/members", web::post().to(join_group))
+                .route(
+                    "/groups/{group_id}/members",
+                    web::get().to(get_group_members),
+                ),
+        )
+        .await;
+        let fetch = || {
+            test::TestRequest::get()
+                .uri("/groups/Z3Jv%2FXA%3D/members")
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        // Bob creates the group and invites Alice, who then joins it herself
+        for (username, group_id, seed) in [
+            ("bob", "Z3Jv/XA=", 1),
+            ("alice", "Z3Jv/XA=", 2),
+            ("carol", "b3RoZXI=", 3),
+        ] {
+            if username == "alice" {
+                Database::add_group_member(&pool, username, group_id)
+                    .await
+                    .unwrap();
+            }
+            let signer = register_signer(&pool, username, seed).await;
+            let req = test::TestRequest::post()
+                .uri("/groups/members")
+                .set_json(join_request(username, group_id, &signer))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        Database::add_group_member(&pool, "dave", "Z3Jv/XA=")
+            .await
+            .unwrap();
+
+        let resp = test::call_service(&app, fetch()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["group_id"], "Z3Jv/XA=");
+        let members = body["members"].as_array().unwrap();
+        let usernames: Vec<&str> = members
+            .iter()
+            .map(|member| member["username"].as_str().unwrap())
+            .collect();
+        assert_eq!(usernames, vec!["alice", "bob", "dave"]);
+        assert!(members.iter().all(|member| member["joined_at"].is_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_user_identity_is_read_from_the_key_package() {
+        let pool = crate::db::create_test_pool();
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).route(
+            "/users/{username}/identity",
+            web::get().to(get_user_identity),
+        ))
+        .await;
+
+        // version, cipher suite, init key, encryption key, signature key,
+        // Basic credential, then the rest of the leaf node
+        let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 4];
+        key_package.extend_from_slice(&[0xa0, 0xa1, 0xa2, 0xa3]);
+        let credential = [0, 1, 5, b'c', b'a', b'r', b'o', b'l'];
+        key_package.extend_from_slice(&credential);
+        key_package.extend_from_slice(b"capabilities, lifetime, signature");
+        Database::register_user(&pool, "carol", &key_package)
+            .await
+            .unwrap();
+        Database::register_user(&pool, "mallory", b"not a key package")
+            .await
+            .unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/users/carol/identity")
+            .to_request();
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body,
+            json!({
+                "username": "carol",
+                "credential": general_purpose::STANDARD.encode(credential),
+                "signature_key": general_purpose::STANDARD.encode([0xa0, 0xa1, 0xa2, 0xa3]),
+            })
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/users/mallory/identity")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 422);
+
+        let req = test::TestRequest::get()
+            .uri("/users/nobody/identity")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_deregister_deletes_keypackages_and_backups() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let pool = crate::db::create_test_pool();
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+
+        // version, cipher suite, init key, encryption key, signature key,
+        // Basic credential
+        let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 32];
+        key_package.extend_from_slice(signing_key.verifying_key().as_bytes());
+        key_package.extend_from_slice(&[0, 1, 5, b'c', b'a', b'r', b'o', b'l']);
+        Database::register_user(&pool, "carol", &key_package)
+            .await
+            .unwrap();
+        Database::register_user(&pool, "dave", b"kp").await.unwrap();
+        for (user, id) in [("carol", 1), ("carol", 2), ("dave", 3)] {
+            KeyPackageStore::save_key_package(&pool, user, &[id], &[id], 9999999999, None, None)
+                .await
+                .unwrap();
+            Database::store_backup(&pool, user, "state").await.unwrap();
+        }
+        Database::add_group_member(&pool, "carol", "Z3JvdXAx")
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/users/{username}", web::delete().to(deregister_user)),
+        )
+        .await;
+        let delete = |username: &str, timestamp: i64, signer: &SigningKey| {
+            let payload = deregister_payload(username, timestamp);
+            test::TestRequest::delete()
+                .uri(&format!("/users/{}", username))
+                .set_json(json!({
+                    "timestamp": timestamp,
+                    "signature": general_purpose::STANDARD.encode(signer.sign(&payload).to_bytes()),
+                }))
+                .to_request()
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        // Someone else's key, a replayed old request, and another user's name
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let resp = test::call_service(&app, delete("carol", now, &other_key)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = test::call_service(&app, delete("carol", now - 3600, &signing_key)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = test::call_service(&app, delete("dave", now, &signing_key)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "carol")
+                .await
+                .unwrap(),
+            2
+        );
+
+        let resp = test::call_service(&app, delete("carol", now, &signing_key)).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert!(Database::get_user(&pool, "carol").await.unwrap().is_none());
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "carol")
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(Database::get_backup(&pool, "carol")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(Database::list_user_groups(&pool, "carol")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Other users keep their data
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "dave")
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(Database::get_backup(&pool, "dave").await.unwrap().is_some());
+
+        let resp = test::call_service(&app, delete("carol", now, &signing_key)).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_rotate_user_key_replaces_registration_and_revokes_keypackages() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        // version, cipher suite, init key, encryption key, signature key,
+        // Basic credential
+        let key_package_for = |signing_key: &SigningKey| {
+            let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 32];
+            key_package.extend_from_slice(signing_key.verifying_key().as_bytes());
+            key_package.extend_from_slice(&[0, 1, 5, b'c', b'a', b'r', b'o', b'l']);
+            key_package
+        };
+        let pool = crate::db::create_test_pool();
+        let old_key = SigningKey::from_bytes(&[7; 32]);
+        let new_key = SigningKey::from_bytes(&[9; 32]);
+        Database::register_user(&pool, "carol", &key_package_for(&old_key))
+            .await
+            .unwrap();
+        for id in [1, 2] {
+            KeyPackageStore::save_key_package(&pool, "carol", &[id], &[id], 9999999999, None, None)
+                .await
+                .unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/users/{username}", web::put().to(rotate_user_key)),
+        )
+        .await;
+        let rotate = |key_package: Vec<u8>, signer: &SigningKey| {
+            let timestamp = chrono::Utc::now().timestamp();
+            let payload = rotate_key_payload("carol", timestamp, &key_package);
+            test::TestRequest::put()
+                .uri("/users/carol")
+                .set_json(json!({
+                    "key_package": key_package,
+                    "timestamp": timestamp,
+                    "signature": general_purpose::STANDARD.encode(signer.sign(&payload).to_bytes()),
+                }))
+                .to_request()
+        };
+
+        // Only the registered key may rotate, and only to a new key
+        let resp = test::call_service(&app, rotate(key_package_for(&new_key), &new_key)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = test::call_service(&app, rotate(key_package_for(&old_key), &old_key)).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "carol")
+                .await
+                .unwrap(),
+            2
+        );
+
+        let resp = test::call_service(&app, rotate(key_package_for(&new_key), &old_key)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["revoked"], 2);
+        let user = Database::get_user(&pool, "carol").await.unwrap().unwrap();
+        assert_eq!(user.key_package, key_package_for(&new_key));
+        assert_eq!(
+            KeyPackageStore::count_available(&pool, "carol")
+                .await
+                .unwrap(),
+            0
+        );
+
+        // The old key no longer speaks for carol
+        let newer_key = SigningKey::from_bytes(&[10; 32]);
+        let resp = test::call_service(&app, rotate(key_package_for(&newer_key), &old_key)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
 }
@@ -1,19 +1,48 @@
 /// WebSocket handler for real-time message distribution.
 /// Manages client connections, group subscriptions, and message broadcasting.
-use crate::db::{Database, DbPool};
+use crate::db::{
+    models::{ws_connect_payload, ContentType},
+    Database, DbPool,
+};
+use crate::handlers::framing;
+use crate::handlers::rest::require_signature;
+use crate::metrics::{MessageKind, Metrics};
 use actix::prelude::*;
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use base64::{engine::general_purpose, Engine as _};
 use serde_json::json;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+type ClientMap = Arc<RwLock<HashMap<String, tokio::sync::mpsc::UnboundedSender<String>>>>;
+type GroupMap = Arc<RwLock<HashMap<String, HashSet<String>>>>;
+type StreamMap = Arc<std::sync::Mutex<HashMap<String, GroupStream>>>;
+
+/// How long a group's delivery stream waits for a message before it closes
+const STREAM_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Ordered delivery stream of one group (see `WsServer::publish_to_group`)
+struct GroupStream {
+    tx: tokio::sync::mpsc::UnboundedSender<(u64, String)>,
+    next_seq: u64,
+}
+
 /// WebSocket server state - manages client connections and routing
 pub struct WsServer {
-    pub clients: Arc<RwLock<HashMap<String, tokio::sync::mpsc::UnboundedSender<String>>>>,
-    pub groups: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    pub clients: ClientMap,
+    pub groups: GroupMap,
+    /// Open connections per authenticated username
+    pub connections: Arc<RwLock<HashMap<String, usize>>>,
     pub pool: Arc<web::Data<DbPool>>,
+    /// Message counters served at `/metrics`
+    pub metrics: Arc<Metrics>,
+    /// Whether group messages go through a per-group ordered stream
+    pub ordered_fan_out: bool,
+    streams: StreamMap,
+    /// How long an idle group stream is kept (see `publish_to_group`)
+    stream_idle_timeout: std::time::Duration,
 }
 
 impl WsServer {
@@ -21,7 +50,76 @@ impl WsServer {
         WsServer {
             clients: Arc::new(RwLock::new(HashMap::new())),
             groups: Arc::new(RwLock::new(HashMap::new())),
+            connections: Arc::new(RwLock::new(HashMap::new())),
             pool,
+            metrics: Arc::new(Metrics::default()),
+            ordered_fan_out: true,
+            streams: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            stream_idle_timeout: STREAM_IDLE_TIMEOUT,
+        }
+    }
+
+    /// Turn the per-group ordered fan-out on or off (on by default)
+    ///
+    /// Without it each message is broadcast by its own task, and two
+    /// subscribers may see concurrent messages of a group in different
+    /// orders.
+    pub fn with_ordered_fan_out(mut self, ordered: bool) -> Self {
+        self.ordered_fan_out = ordered;
+        self
+    }
+
+    /// Take one of `username`'s `max_connections` connection slots
+    ///
+    /// Returns `false`, leaving the count unchanged, if all of them are in
+    /// use. Every successful call must be paired with `release_connection`.
+    pub async fn acquire_connection(&self, username: &str, max_connections: usize) -> bool {
+        let mut connections = self.connections.write().await;
+        let open = connections.entry(username.to_string()).or_insert(0);
+        if *open >= max_connections {
+            log::warn!(
+                "[CONNECTION_LIMIT] '{}' already has {} open connections (limit {})",
+                username,
+                open,
+                max_connections
+            );
+            return false;
+        }
+        *open += 1;
+        true
+    }
+
+    /// Give back a slot taken by `acquire_connection`
+    pub async fn release_connection(&self, username: &str) {
+        let mut connections = self.connections.write().await;
+        if let Some(open) = connections.get_mut(username) {
+            *open = open.saturating_sub(1);
+            if *open == 0 {
+                connections.remove(username);
+            }
+        }
+    }
+
+    /// Number of open connections of `username`
+    #[cfg(test)]
+    pub async fn connection_count(&self, username: &str) -> usize {
+        self.connections
+            .read()
+            .await
+            .get(username)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Record that a broadcast Commit moved `group_id` into `epoch`
+    pub async fn record_commit_epoch(&self, group_id: &str, epoch: u64) {
+        let Ok(epoch) = i64::try_from(epoch) else {
+            return;
+        };
+        if let Err(e) =
+            Database::record_group_epoch(self.pool.as_ref().as_ref(), group_id, epoch).await
+        {
+            log::warn!("Failed to record epoch of {}: {}", group_id, e);
         }
     }
 
@@ -81,6 +179,188 @@ impl WsServer {
         );
     }
 
+    /// Subscribe a client to a group on behalf of `username`, if it is a member
+    ///
+    /// Membership comes from the `group_members` table: creators record it
+    /// themselves and invitees are recorded when their KeyPackage is reserved.
+    /// Every user may subscribe to their own `user_channel`, which carries
+    /// their Welcomes. Messages deferred for the user in the group are then
+    /// delivered (see `deliver_deferred`). Returns whether the subscription
+    /// was honored.
+    pub async fn subscribe_member(
+        &self,
+        client_id: String,
+        username: &str,
+        group_id: String,
+    ) -> bool {
+        let is_member = if group_id == user_channel(username) {
+            Ok(true)
+        } else {
+            Database::is_group_member(&self.pool, username, &group_id).await
+        };
+        match is_member {
+            Ok(true) => {
+                self.subscribe(client_id.clone(), group_id.clone()).await;
+                self.deliver_deferred(&client_id, username, &group_id).await;
+                true
+            }
+            Ok(false) => {
+                log::warn!(
+                    "[SUBSCRIBE] Rejected: user '{}' is not a member of group '{}'",
+                    username,
+                    group_id
+                );
+                false
+            }
+            Err(e) => {
+                log::error!(
+                    "[SUBSCRIBE] Failed to check membership of '{}' in group '{}': {}",
+                    username,
+                    group_id,
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Defer delivery of message `seq` of `group_id` to the members who
+    /// have no connection subscribed to the group
+    ///
+    /// The message is already stored; this records that those members still
+    /// have to receive it, so it is pushed when they next subscribe (see
+    /// `deliver_deferred`). The sender is never included. Returns the number
+    /// of deliveries deferred.
+    pub async fn defer_for_offline_members(&self, group_id: &str, sender: &str, seq: i64) -> usize {
+        // Client ids are "<username>_<uuid>" (see `ws_connect`)
+        let online: HashSet<String> = self
+            .groups
+            .read()
+            .await
+            .get(group_id)
+            .map(|members| {
+                members
+                    .iter()
+                    .filter_map(|client_id| client_id.rsplit_once('_'))
+                    .map(|(username, _)| username.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let members = match Database::list_group_members(&self.pool, group_id).await {
+            Ok(members) => members,
+            Err(e) => {
+                log::error!(
+                    "[DEFERRED] Failed to list members of group '{}': {}",
+                    group_id,
+                    e
+                );
+                return 0;
+            }
+        };
+        let offline: Vec<String> = members
+            .into_iter()
+            .map(|member| member.username)
+            .filter(|username| username != sender && !online.contains(username))
+            .collect();
+        if offline.is_empty() {
+            return 0;
+        }
+        match Database::defer_message(&self.pool, group_id, seq, &offline).await {
+            Ok(deferred) => {
+                log::info!(
+                    "[DEFERRED] Message #{} of group '{}' deferred for {} offline members",
+                    seq,
+                    group_id,
+                    deferred
+                );
+                self.metrics.record_deferred(deferred);
+                deferred
+            }
+            Err(e) => {
+                log::error!(
+                    "[DEFERRED] Failed to defer message #{} of group '{}': {}",
+                    seq,
+                    group_id,
+                    e
+                );
+                0
+            }
+        }
+    }
+
+    /// Push the messages of `group_id` deferred for `username` to the client
+    ///
+    /// They are sent in `seq` order as ordinary application envelopes, so
+    /// the client drops any it already received by `seq`, e.g. from a
+    /// concurrent live delivery or its own backfill. A deferral is only
+    /// removed once its message was handed to the connection; if the
+    /// connection goes away part-way, the rest stays for the next subscribe.
+    /// Returns the number of messages sent.
+    pub async fn deliver_deferred(&self, client_id: &str, username: &str, group_id: &str) -> usize {
+        let Some(tx) = self.clients.read().await.get(client_id).cloned() else {
+            // Keep the backlog for a connection that can receive it
+            return 0;
+        };
+        let messages = match Database::deferred_messages(&self.pool, username, group_id).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                log::error!(
+                    "[DEFERRED] Failed to load deferred messages of '{}' in group '{}': {}",
+                    username,
+                    group_id,
+                    e
+                );
+                return 0;
+            }
+        };
+        let mut delivered = 0;
+        let mut last_seq = None;
+        for message in messages {
+            let envelope = application_envelope(
+                &message.sender,
+                group_id,
+                &message.encrypted_content,
+                message.content_type,
+                message.seq,
+                None,
+            );
+            if tx.send(envelope).is_err() {
+                log::warn!(
+                    "[DEFERRED] Connection '{}' closed while delivering the backlog of group '{}'",
+                    client_id,
+                    group_id
+                );
+                break;
+            }
+            delivered += 1;
+            last_seq = Some(message.seq);
+        }
+        if let Some(seq) = last_seq {
+            if let Err(e) =
+                Database::clear_deferred_messages(&self.pool, username, group_id, seq).await
+            {
+                // The messages go out again on the next subscribe; the client
+                // drops them by seq
+                log::error!(
+                    "[DEFERRED] Failed to clear delivered messages of '{}' in group '{}': {}",
+                    username,
+                    group_id,
+                    e
+                );
+            }
+        }
+        if delivered > 0 {
+            log::info!(
+                "[DEFERRED] Delivered {} deferred messages of group '{}' to '{}'",
+                delivered,
+                group_id,
+                client_id
+            );
+        }
+        self.metrics.record_delivered(delivered);
+        delivered
+    }
+
     /// Unsubscribe a client from a group
     pub async fn unsubscribe(&self, client_id: &str, group_id: &str) {
         let mut groups = self.groups.write().await;
@@ -101,51 +381,95 @@ impl WsServer {
         }
     }
 
-    /// Broadcast message to all clients in a group
-    pub async fn broadcast_to_group(&self, group_id: &str, message: &str) {
-        let groups = self.groups.read().await;
-        log::debug!(
-            "[BROADCAST] Looking for group: '{}', available groups: {:?}",
-            group_id,
-            groups.keys().collect::<Vec<_>>()
-        );
+    /// Deliver a group message to its subscribers in a consistent order
+    ///
+    /// With `ordered_fan_out`, the message is enqueued on the group's stream
+    /// and gets the next delivery sequence number of the group, which is
+    /// returned. One task per group drains its stream in sequence order, so
+    /// every subscriber receives the group's messages in the same order. A
+    /// slow group only delays its own stream, never another group's.
+    /// Without `ordered_fan_out`, the message is broadcast right away and
+    /// `None` is returned.
+    ///
+    /// A stream that gets no message for `STREAM_IDLE_TIMEOUT` is closed and
+    /// its task exits; the group's next message opens a new one, whose
+    /// sequence numbers start again at 0.
+    pub async fn publish_to_group(&self, group_id: &str, message: String) -> Option<u64> {
+        if !self.ordered_fan_out {
+            self.broadcast_to_group(group_id, &message).await;
+            return None;
+        }
 
-        if let Some(members) = groups.get(group_id) {
-            log::info!(
-                "[BROADCAST] Broadcasting to group '{}' with {} members: {:?}",
-                group_id,
-                members.len(),
-                members
-            );
-            let clients = self.clients.read().await;
-            for member in members {
-                log::debug!("[BROADCAST] Checking if member '{}' is registered in clients (total clients: {})", member, clients.len());
-                if let Some(tx) = clients.get(member) {
-                    log::info!("[BROADCAST] Sending message to client: {}", member);
-                    let _ = tx.send(message.to_string());
-                } else {
-                    log::warn!(
-                        "[BROADCAST] Client '{}' is in group but not in clients map!",
-                        member
-                    );
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = streams.entry(group_id.to_string()).or_insert_with(|| {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(u64, String)>();
+            let clients = self.clients.clone();
+            let groups = self.groups.clone();
+            let metrics = self.metrics.clone();
+            let streams = self.streams.clone();
+            let idle_timeout = self.stream_idle_timeout;
+            let group_id = group_id.to_string();
+            tokio::spawn(async move {
+                loop {
+                    let (seq, message) = match tokio::time::timeout(idle_timeout, rx.recv()).await {
+                        Ok(Some(next)) => next,
+                        Ok(None) => break,
+                        Err(_) => {
+                            // Publishers enqueue while holding the lock, so
+                            // nothing can be sent to a stream once removed
+                            let mut streams = streams.lock().unwrap_or_else(|e| e.into_inner());
+                            match rx.try_recv() {
+                                Ok(next) => next,
+                                Err(_) => {
+                                    streams.remove(&group_id);
+                                    log::debug!(
+                                        "[FAN_OUT] Closed idle stream of group '{}'",
+                                        group_id
+                                    );
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                    log::debug!("[FAN_OUT] Delivering #{} of group '{}'", seq, group_id);
+                    fan_out(&clients, &groups, &metrics, &group_id, &message).await;
                 }
-            }
-        } else {
-            log::warn!(
-                "[BROADCAST] No members found for group '{}'. Groups in system: {:?}",
-                group_id,
-                groups.keys().collect::<Vec<_>>()
-            );
+            });
+            GroupStream { tx, next_seq: 0 }
+        });
+        let seq = stream.next_seq;
+        stream.next_seq += 1;
+        if stream.tx.send((seq, message)).is_err() {
+            log::error!("[FAN_OUT] Delivery stream of group '{}' is gone", group_id);
+            streams.remove(group_id);
+            return None;
         }
+        Some(seq)
+    }
+
+    /// Broadcast message to all clients in a group
+    pub async fn broadcast_to_group(&self, group_id: &str, message: &str) {
+        fan_out(
+            &self.clients,
+            &self.groups,
+            &self.metrics,
+            group_id,
+            message,
+        )
+        .await;
     }
 
     /// Store message to database
+    ///
+    /// Returns the stored message's id, its `seq` in the group's history, or
+    /// `None` if it could not be stored.
     pub async fn persist_message(
         &self,
         group_id: &str,
         sender: &str,
         encrypted_content: &str,
-    ) -> bool {
+        content_type: ContentType,
+    ) -> Option<i64> {
         // Get or create group
         let group = match Database::get_group(self.pool.as_ref().as_ref(), group_id).await {
             Ok(Some(g)) => g,
@@ -155,13 +479,13 @@ impl WsServer {
                     Ok(g) => g,
                     Err(e) => {
                         log::error!("Failed to create group: {}", e);
-                        return false;
+                        return None;
                     }
                 }
             }
             Err(e) => {
                 log::error!("Failed to get group: {}", e);
-                return false;
+                return None;
             }
         };
 
@@ -170,11 +494,11 @@ impl WsServer {
             Ok(Some(u)) => u,
             Ok(None) => {
                 log::warn!("Sender not found: {}", sender);
-                return false;
+                return None;
             }
             Err(e) => {
                 log::error!("Failed to get user: {}", e);
-                return false;
+                return None;
             }
         };
 
@@ -184,29 +508,133 @@ impl WsServer {
             group.id,
             user.id,
             encrypted_content,
+            content_type,
         )
         .await
         {
-            Ok(_) => true,
+            Ok(message) => Some(message.id),
             Err(e) => {
                 log::error!("Failed to store message: {}", e);
-                false
+                None
+            }
+        }
+    }
+}
+
+/// Channel carrying `username`'s Welcomes
+///
+/// Group ids are base64, so the `user:` prefix never names a group.
+pub fn user_channel(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+/// JSON of an application message as pushed to subscribers
+fn application_envelope(
+    sender: &str,
+    group_id: &str,
+    encrypted_content: &str,
+    content_type: ContentType,
+    seq: i64,
+    epoch: Option<u64>,
+) -> String {
+    let mut msg = json!({
+        "type": "application",
+        "sender": sender,
+        "group_id": group_id,
+        "encrypted_content": encrypted_content,
+        "content_type": content_type,
+        "seq": seq
+    });
+    if let Some(epoch) = epoch {
+        msg["epoch"] = json!(epoch);
+    }
+    msg.to_string()
+}
+
+/// Send `message` to every client subscribed to `group_id`
+async fn fan_out(
+    clients: &ClientMap,
+    groups: &GroupMap,
+    metrics: &Metrics,
+    group_id: &str,
+    message: &str,
+) {
+    let groups = groups.read().await;
+    log::debug!(
+        "[BROADCAST] Looking for group: '{}', available groups: {:?}",
+        group_id,
+        groups.keys().collect::<Vec<_>>()
+    );
+
+    if let Some(members) = groups.get(group_id) {
+        log::info!(
+            "[BROADCAST] Broadcasting to group '{}' with {} members: {:?}",
+            group_id,
+            members.len(),
+            members
+        );
+        let clients = clients.read().await;
+        let mut delivered = 0;
+        for member in members {
+            log::debug!(
+                "[BROADCAST] Checking if member '{}' is registered in clients (total clients: {})",
+                member,
+                clients.len()
+            );
+            if let Some(tx) = clients.get(member) {
+                log::info!("[BROADCAST] Sending message to client: {}", member);
+                if tx.send(message.to_string()).is_ok() {
+                    delivered += 1;
+                }
+            } else {
+                log::warn!(
+                    "[BROADCAST] Client '{}' is in group but not in clients map!",
+                    member
+                );
             }
         }
+        metrics.record_delivered(delivered);
+    } else {
+        log::warn!(
+            "[BROADCAST] No members found for group '{}'. Groups in system: {:?}",
+            group_id,
+            groups.keys().collect::<Vec<_>>()
+        );
     }
 }
 
+/// Close code sent to a connection beyond the per-user limit (policy violation)
+pub const CONNECTION_LIMIT_CLOSE_CODE: ws::CloseCode = ws::CloseCode::Policy;
+
 /// WebSocket actor for individual client connections
 pub struct WsActor {
     pub client_id: String,
     pub username: String,
     pub server: web::Data<WsServer>,
+    /// Whether the connection holds one of its user's connection slots.
+    /// A connection without one is closed as soon as it starts.
+    pub admitted: bool,
+    /// Whether the client negotiated binary frames (see `framing`)
+    pub binary: bool,
 }
 
 impl Actor for WsActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        if !self.admitted {
+            log::warn!(
+                "WebSocket connection rejected: {} is over the connection limit",
+                self.client_id
+            );
+            ctx.close(Some(ws::CloseReason {
+                code: CONNECTION_LIMIT_CLOSE_CODE,
+                description: Some("Too many connections for this user".to_string()),
+            }));
+            ctx.stop();
+            return;
+        }
+
         log::info!("WebSocket connection started: {}", self.client_id);
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
@@ -226,11 +654,17 @@ impl Actor for WsActor {
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if !self.admitted {
+            return;
+        }
+
         log::info!("WebSocket connection stopped: {}", self.client_id);
         let server = self.server.clone();
         let client_id = self.client_id.clone();
+        let username = self.username.clone();
         let fut = async move {
             server.unregister(&client_id).await;
+            server.release_connection(&username).await;
         };
         drop(actix::spawn(fut));
     }
@@ -238,6 +672,23 @@ impl Actor for WsActor {
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        // Binary envelopes are handled as the JSON they stand for
+        let msg = match msg {
+            Ok(ws::Message::Binary(bytes)) => match framing::binary_to_json(&bytes) {
+                Ok(text) => Ok(ws::Message::Text(text.into())),
+                Err(e) => {
+                    log::error!("Failed to parse binary WebSocket message: {}", e);
+                    ctx.text(
+                        json!({
+                            "error": "Invalid message format"
+                        })
+                        .to_string(),
+                    );
+                    return;
+                }
+            },
+            msg => msg,
+        };
         match msg {
             Ok(ws::Message::Text(text)) => {
                 match serde_json::from_str::<serde_json::Value>(&text) {
@@ -251,9 +702,12 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                     {
                                         let server = self.server.clone();
                                         let client_id = self.client_id.clone();
+                                        let username = self.username.clone();
                                         let group_id = group_id.to_string();
                                         actix::spawn(async move {
-                                            server.subscribe(client_id, group_id).await;
+                                            server
+                                                .subscribe_member(client_id, &username, group_id)
+                                                .await;
                                         });
                                     }
                                 }
@@ -285,10 +739,23 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                         {
                                             log::info!("[MESSAGE_RECEIVED] Application message from user '{}' to group '{}' (payload size: {})",
                                                       self.username, group_id, encrypted_content.len());
+                                            self.server.metrics.record_received(
+                                                MessageKind::Application,
+                                                encrypted_content.len(),
+                                            );
                                             let server = self.server.clone();
                                             let username = self.username.clone();
                                             let group_id = group_id.to_string();
                                             let encrypted_content = encrypted_content.to_string();
+                                            // Older clients don't send a content type: their
+                                            // messages are all chat text
+                                            let content_type = value
+                                                .get("content_type")
+                                                .and_then(|c| c.as_str())
+                                                .and_then(ContentType::parse)
+                                                .unwrap_or_default();
+                                            // Advisory copy of the MLS epoch, passed on as is
+                                            let epoch = value.get("epoch").and_then(|e| e.as_u64());
                                             actix::spawn(async move {
                                                 log::debug!("[MESSAGE_PROCESSING] Processing message from '{}' for group '{}'", username, group_id);
                                                 let persisted = server
@@ -296,22 +763,27 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                                         &group_id,
                                                         &username,
                                                         &encrypted_content,
+                                                        content_type,
                                                     )
                                                     .await;
 
-                                                if persisted {
+                                                if let Some(seq) = persisted {
                                                     log::info!("[MESSAGE_PERSISTED] Message from '{}' persisted to group '{}'", username, group_id);
-                                                    let msg = json!({
-                                                        "type": "application",
-                                                        "sender": username.clone(),
-                                                        "group_id": group_id.clone(),
-                                                        "encrypted_content": encrypted_content
-                                                    })
-                                                    .to_string();
-                                                    log::info!("[MESSAGE_BROADCASTING] About to broadcast message from '{}' to group '{}'", username, group_id);
                                                     server
-                                                        .broadcast_to_group(&group_id, &msg)
+                                                        .defer_for_offline_members(
+                                                            &group_id, &username, seq,
+                                                        )
                                                         .await;
+                                                    let msg = application_envelope(
+                                                        &username,
+                                                        &group_id,
+                                                        &encrypted_content,
+                                                        content_type,
+                                                        seq,
+                                                        epoch,
+                                                    );
+                                                    log::info!("[MESSAGE_BROADCASTING] About to broadcast message from '{}' to group '{}'", username, group_id);
+                                                    server.publish_to_group(&group_id, msg).await;
                                                 } else {
                                                     log::error!("[MESSAGE_FAILED] Failed to persist message from '{}' to group '{}'", username, group_id);
                                                 }
@@ -335,6 +807,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                                 {
                                                     log::info!("[WELCOME_RECEIVED] Welcome message from '{}' to '{}' (welcome blob size: {})",
                                                               inviter, invitee, welcome_blob.len());
+                                                    self.server.metrics.record_received(
+                                                        MessageKind::Welcome,
+                                                        welcome_blob.len(),
+                                                    );
                                                     let server = self.server.clone();
                                                     let inviter = inviter.to_string();
                                                     let invitee = invitee.to_string();
@@ -352,7 +828,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                                         // Send Welcome message directly to the invitee
                                                         log::info!("[WELCOME_BROADCASTING] Broadcasting welcome to invitee '{}'", invitee);
                                                         server
-                                                            .broadcast_to_group(&invitee, &msg)
+                                                            .broadcast_to_group(
+                                                                &user_channel(&invitee),
+                                                                &msg,
+                                                            )
                                                             .await;
                                                     });
                                                 }
@@ -372,22 +851,43 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
                                             {
                                                 log::info!("[COMMIT_RECEIVED] Commit message from '{}' to group '{}' (commit blob size: {})",
                                                           sender, group_id, commit_blob.len());
+                                                self.server.metrics.record_received(
+                                                    MessageKind::Commit,
+                                                    commit_blob.len(),
+                                                );
                                                 let server = self.server.clone();
                                                 let group_id = group_id.to_string();
                                                 let commit_blob = commit_blob.to_string();
                                                 let sender = sender.to_string();
+                                                // Lets the sender's other devices tell
+                                                // the Commit from their own
+                                                let device_id = value
+                                                    .get("device_id")
+                                                    .and_then(|d| d.as_str())
+                                                    .map(str::to_string);
                                                 actix::spawn(async move {
+                                                    // The Commit moves the group into the next epoch
+                                                    if let Some(epoch) = commit_epoch(&commit_blob)
+                                                    {
+                                                        server
+                                                            .record_commit_epoch(
+                                                                &group_id,
+                                                                epoch + 1,
+                                                            )
+                                                            .await;
+                                                    }
                                                     log::info!("[COMMIT_BROADCASTING] Broadcasting commit from '{}' to group '{}'", sender, group_id);
-                                                    let msg = json!({
+                                                    let mut msg = json!({
                                                         "type": "commit",
                                                         "group_id": group_id.clone(),
                                                         "sender": sender,
                                                         "commit_blob": commit_blob
-                                                    })
-                                                    .to_string();
-                                                    server
-                                                        .broadcast_to_group(&group_id, &msg)
-                                                        .await;
+                                                    });
+                                                    if let Some(device_id) = device_id {
+                                                        msg["device_id"] = json!(device_id);
+                                                    }
+                                                    let msg = msg.to_string();
+                                                    server.publish_to_group(&group_id, msg).await;
                                                 });
                                             }
                                         }
@@ -430,27 +930,142 @@ impl Handler<IncomingMessage> for WsActor {
     type Result = ();
 
     fn handle(&mut self, msg: IncomingMessage, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+        match self
+            .binary
+            .then(|| framing::json_to_binary(&msg.0))
+            .flatten()
+        {
+            Some(bytes) => ctx.binary(bytes),
+            None => ctx.text(msg.0),
+        }
+    }
+}
+
+/// Epoch a Commit was sent in, read from the clear header of its framing
+///
+/// `commit_blob` is the base64 TLS-serialized `MLSMessage`. Public and
+/// private messages both start with the group id and epoch in the clear,
+/// so no group secret is involved. Returns `None` for anything else.
+pub fn commit_epoch(commit_blob: &str) -> Option<u64> {
+    const MLS10: [u8; 2] = [0, 1];
+    const PUBLIC_MESSAGE: [u8; 2] = [0, 1];
+    const PRIVATE_MESSAGE: [u8; 2] = [0, 2];
+
+    let bytes = general_purpose::STANDARD.decode(commit_blob).ok()?;
+    if bytes.get(0..2)? != MLS10 {
+        return None;
+    }
+    let wire_format = bytes.get(2..4)?;
+    if wire_format != PUBLIC_MESSAGE && wire_format != PRIVATE_MESSAGE {
+        return None;
     }
+
+    // The group id is a vector with an MLS variable-length size prefix
+    let (_group_id, rest) = split_vl_bytes(bytes.get(4..)?)?;
+    let epoch = rest.get(0..8)?;
+    Some(u64::from_be_bytes(epoch.try_into().ok()?))
+}
+
+/// Split a TLS vector with an MLS variable-length size prefix off `bytes`
+///
+/// Returns the vector's contents and the bytes after it, or `None` if the
+/// prefix is invalid or the vector is truncated.
+pub(crate) fn split_vl_bytes(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let first = *bytes.first()?;
+    let (prefix_len, len) = match first >> 6 {
+        0 => (1usize, usize::from(first & 0x3f)),
+        1 => (
+            2usize,
+            usize::from(u16::from_be_bytes(bytes.get(0..2)?.try_into().ok()?) & 0x3fff),
+        ),
+        2 => (
+            4usize,
+            (u32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?) & 0x3fff_ffff) as usize,
+        ),
+        _ => return None,
+    };
+    let end = prefix_len.checked_add(len)?;
+    Some((bytes.get(prefix_len..end)?, bytes.get(end..)?))
+}
+
+/// Query parameters of the WebSocket upgrade request
+#[derive(Debug, serde::Deserialize)]
+pub struct WsConnectQuery {
+    /// `binary` to ask for binary frames
+    pub framing: Option<String>,
+    /// Unix seconds when the connection request was signed
+    pub timestamp: i64,
+    /// Base64 Ed25519 signature of `ws_connect_payload(username, timestamp)`
+    pub signature: String,
 }
 
 /// WebSocket connection handler
+///
+/// The request must be signed by the signature key of the KeyPackage the
+/// user registered with (see `ws_connect_payload`); otherwise it is refused
+/// with 401 before it counts against the user's connections. Each username
+/// may hold at most `ServerConfig::max_connections_per_user` connections at
+/// once (one per device). A connection beyond that is still upgraded, so the
+/// client gets a proper close frame, and then closed with
+/// `CONNECTION_LIMIT_CLOSE_CODE`.
+///
+/// `?framing=binary` switches the connection to binary frames, confirmed
+/// with the `framing::FRAMING_HEADER` response header; without it the
+/// connection uses JSON text frames.
 pub async fn ws_connect(
     req: HttpRequest,
     stream: web::Payload,
     username: web::Path<String>,
+    query: web::Query<WsConnectQuery>,
     server: web::Data<WsServer>,
+    config: web::Data<crate::handlers::ServerConfig>,
 ) -> actix_web::Result<HttpResponse> {
+    let username = username.into_inner();
+    if let Some(refused) = require_signature(
+        &server.pool,
+        &username,
+        &ws_connect_payload(&username, query.timestamp),
+        query.timestamp,
+        &query.signature,
+        "Failed to open WebSocket",
+    )
+    .await
+    {
+        return Ok(refused);
+    }
+
     let client_id = format!("{}_{}", username, uuid::Uuid::new_v4());
+    let binary = framing::wants_binary(query.framing.as_deref());
+    let admitted = server
+        .acquire_connection(&username, config.max_connections_per_user)
+        .await;
 
     let actor = WsActor {
         client_id: client_id.clone(),
-        username: username.into_inner(),
+        username: username.clone(),
         server: server.clone(),
+        admitted,
+        binary,
     };
 
-    let resp = ws::start(actor, &req, stream)?;
-    Ok(resp)
+    match ws::start(actor, &req, stream) {
+        Ok(mut resp) => {
+            if binary {
+                resp.headers_mut().insert(
+                    actix_web::http::header::HeaderName::from_static(framing::FRAMING_HEADER),
+                    actix_web::http::header::HeaderValue::from_static("binary"),
+                );
+            }
+            Ok(resp)
+        }
+        Err(e) => {
+            // The handshake failed, so the actor never runs to free the slot
+            if admitted {
+                server.release_connection(&username).await;
+            }
+            Err(e)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -518,4 +1133,289 @@ mod tests {
         assert_eq!(rx1.recv().await, Some("test message".to_string()));
         assert_eq!(rx2.recv().await, Some("test message".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_broadcast_counts_delivered_messages() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let server = Arc::new(WsServer::new(pool));
+
+        let (tx1, _rx1) = tokio::sync::mpsc::unbounded_channel();
+        let (tx2, rx2) = tokio::sync::mpsc::unbounded_channel();
+        server.register("client1".to_string(), tx1).await;
+        server.register("client2".to_string(), tx2).await;
+        server
+            .subscribe("client1".to_string(), "group1".to_string())
+            .await;
+        server
+            .subscribe("client2".to_string(), "group1".to_string())
+            .await;
+
+        // A client whose receiver is gone is not counted
+        drop(rx2);
+        server.broadcast_to_group("group1", "test message").await;
+
+        let expected = if Metrics::ENABLED { 1 } else { 0 };
+        assert!(server
+            .metrics
+            .render()
+            .lines()
+            .any(|line| line == format!("mls_chat_messages_delivered_total {}", expected)));
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_group_messages_reach_subscribers_in_one_order() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let server = Arc::new(WsServer::new(pool));
+
+        let (tx1, mut rx1) = tokio::sync::mpsc::unbounded_channel();
+        let (tx2, mut rx2) = tokio::sync::mpsc::unbounded_channel();
+        let (tx3, mut rx3) = tokio::sync::mpsc::unbounded_channel();
+        server.register("client1".to_string(), tx1).await;
+        server.register("client2".to_string(), tx2).await;
+        server.register("client3".to_string(), tx3).await;
+        for client in ["client1", "client2"] {
+            server
+                .subscribe(client.to_string(), "group1".to_string())
+                .await;
+        }
+        server
+            .subscribe("client3".to_string(), "group2".to_string())
+            .await;
+
+        // Two senders interleave application messages and Commits
+        let mut sends = Vec::new();
+        for sender in ["alice", "bob"] {
+            let server = server.clone();
+            sends.push(tokio::spawn(async move {
+                let mut seqs = Vec::new();
+                for i in 0..50 {
+                    let kind = if i % 10 == 0 { "commit" } else { "application" };
+                    let message = format!("{}:{}:{}", sender, kind, i);
+                    seqs.push(server.publish_to_group("group1", message).await.unwrap());
+                    server
+                        .publish_to_group("group2", format!("{}:other:{}", sender, i))
+                        .await;
+                    tokio::task::yield_now().await;
+                }
+                seqs
+            }));
+        }
+        let mut seqs: Vec<u64> = Vec::new();
+        for send in sends {
+            seqs.extend(send.await.unwrap());
+        }
+        seqs.sort_unstable();
+        assert_eq!(seqs, (0..100).collect::<Vec<u64>>());
+
+        let mut received1 = Vec::new();
+        let mut received2 = Vec::new();
+        for _ in 0..100 {
+            received1.push(rx1.recv().await.unwrap());
+            received2.push(rx2.recv().await.unwrap());
+        }
+        assert_eq!(received1, received2);
+        // Each sender's own messages keep their order
+        let own = |sender: &str| {
+            received1
+                .iter()
+                .filter(|message| message.starts_with(sender))
+                .map(|message| message.rsplit(':').next().unwrap().parse::<u32>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(own("alice"), (0..50).collect::<Vec<_>>());
+        assert_eq!(own("bob"), (0..50).collect::<Vec<_>>());
+
+        // The other group has its own stream
+        for _ in 0..100 {
+            assert!(rx3.recv().await.unwrap().contains(":other:"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_group_stream_is_closed() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let mut server = WsServer::new(pool);
+        server.stream_idle_timeout = std::time::Duration::from_millis(50);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        server.register("client1".to_string(), tx).await;
+        server
+            .subscribe("client1".to_string(), "group1".to_string())
+            .await;
+
+        assert_eq!(
+            server.publish_to_group("group1", "first".to_string()).await,
+            Some(0)
+        );
+        assert_eq!(rx.recv().await.unwrap(), "first");
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(server.streams.lock().unwrap().is_empty());
+
+        // The next message opens a new stream
+        assert_eq!(
+            server
+                .publish_to_group("group1", "second".to_string())
+                .await,
+            Some(0)
+        );
+        assert_eq!(rx.recv().await.unwrap(), "second");
+        assert_eq!(server.streams.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_messages_for_offline_members_are_delivered_on_subscribe() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let server = WsServer::new(pool.clone());
+        for username in ["alice", "bob"] {
+            Database::register_user(&pool, username, b"kp")
+                .await
+                .unwrap();
+            Database::add_group_member(&pool, username, "group1")
+                .await
+                .unwrap();
+        }
+
+        // Only alice is online while she sends
+        let (alice_tx, _alice_rx) = tokio::sync::mpsc::unbounded_channel();
+        server.register("alice_1".to_string(), alice_tx).await;
+        assert!(
+            server
+                .subscribe_member("alice_1".to_string(), "alice", "group1".to_string())
+                .await
+        );
+        let mut seqs = Vec::new();
+        for content in ["first", "second"] {
+            let seq = server
+                .persist_message("group1", "alice", content, ContentType::Text)
+                .await
+                .unwrap();
+            assert_eq!(
+                server
+                    .defer_for_offline_members("group1", "alice", seq)
+                    .await,
+                1
+            );
+            seqs.push(seq);
+        }
+        // Deferring the same message again records nothing new
+        assert_eq!(
+            server
+                .defer_for_offline_members("group1", "alice", seqs[1])
+                .await,
+            0
+        );
+
+        // bob subscribes and gets the backlog in seq order
+        let (bob_tx, mut bob_rx) = tokio::sync::mpsc::unbounded_channel();
+        server.register("bob_1".to_string(), bob_tx).await;
+        assert!(
+            server
+                .subscribe_member("bob_1".to_string(), "bob", "group1".to_string())
+                .await
+        );
+        for (seq, content) in seqs.iter().zip(["first", "second"]) {
+            let message: serde_json::Value =
+                serde_json::from_str(&bob_rx.recv().await.unwrap()).unwrap();
+            assert_eq!(message["type"], "application");
+            assert_eq!(message["sender"], "alice");
+            assert_eq!(message["seq"], *seq);
+            assert_eq!(message["encrypted_content"], content);
+        }
+        assert!(bob_rx.try_recv().is_err());
+
+        // The backlog is handed out once
+        assert_eq!(server.deliver_deferred("bob_1", "bob", "group1").await, 0);
+        let expected = if Metrics::ENABLED { 2 } else { 0 };
+        assert!(server
+            .metrics
+            .render()
+            .lines()
+            .any(|line| line == format!("mls_chat_messages_deferred_total {}", expected)));
+    }
+
+    #[tokio::test]
+    async fn test_deferred_messages_survive_a_closed_connection() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let server = WsServer::new(pool.clone());
+        for username in ["alice", "bob"] {
+            Database::register_user(&pool, username, b"kp")
+                .await
+                .unwrap();
+            Database::add_group_member(&pool, username, "group1")
+                .await
+                .unwrap();
+        }
+        let mut seqs = Vec::new();
+        for content in ["first", "second"] {
+            let seq = server
+                .persist_message("group1", "alice", content, ContentType::Text)
+                .await
+                .unwrap();
+            server
+                .defer_for_offline_members("group1", "alice", seq)
+                .await;
+            seqs.push(seq);
+        }
+
+        // bob's connection is gone before the backlog is flushed
+        let (bob_tx, bob_rx) = tokio::sync::mpsc::unbounded_channel();
+        server.register("bob_1".to_string(), bob_tx).await;
+        drop(bob_rx);
+        assert_eq!(server.deliver_deferred("bob_1", "bob", "group1").await, 0);
+
+        // Nothing was lost: the next connection gets the whole backlog
+        let (bob_tx, mut bob_rx) = tokio::sync::mpsc::unbounded_channel();
+        server.register("bob_2".to_string(), bob_tx).await;
+        assert_eq!(server.deliver_deferred("bob_2", "bob", "group1").await, 2);
+        for seq in &seqs {
+            let message: serde_json::Value =
+                serde_json::from_str(&bob_rx.recv().await.unwrap()).unwrap();
+            assert_eq!(message["seq"], *seq);
+        }
+        assert_eq!(server.deliver_deferred("bob_2", "bob", "group1").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ws_server_connection_slots() {
+        let pool = Arc::new(web::Data::new(crate::db::create_test_pool()));
+        let server = WsServer::new(pool);
+
+        assert!(server.acquire_connection("alice", 1).await);
+        assert!(!server.acquire_connection("alice", 1).await);
+        assert_eq!(server.connection_count("alice").await, 1);
+
+        server.release_connection("alice").await;
+        assert_eq!(server.connection_count("alice").await, 0);
+        assert!(!server.connections.read().await.contains_key("alice"));
+
+        // Releasing a slot that was never taken is harmless
+        server.release_connection("bob").await;
+        assert_eq!(server.connection_count("bob").await, 0);
+    }
+
+    #[test]
+    fn test_commit_epoch_reads_the_clear_header() {
+        let framed = |wire_format: u8, group_id_prefix: &[u8], group_id: &[u8], epoch: u64| {
+            let mut bytes = vec![0, 1, 0, wire_format];
+            bytes.extend_from_slice(group_id_prefix);
+            bytes.extend_from_slice(group_id);
+            bytes.extend_from_slice(&epoch.to_be_bytes());
+            bytes.extend_from_slice(b"rest of the message");
+            general_purpose::STANDARD.encode(bytes)
+        };
+
+        assert_eq!(commit_epoch(&framed(2, &[5], b"group", 7)), Some(7));
+        assert_eq!(commit_epoch(&framed(1, &[5], b"group", 3)), Some(3));
+        // Two-byte size prefix
+        let long_id = [0xaa; 100];
+        assert_eq!(commit_epoch(&framed(2, &[0x40, 100], &long_id, 9)), Some(9));
+
+        // Welcomes, truncated messages and non-base64 carry no epoch
+        assert_eq!(commit_epoch(&framed(3, &[5], b"group", 7)), None);
+        assert_eq!(
+            commit_epoch(&general_purpose::STANDARD.encode([0, 1, 0, 2, 5])),
+            None
+        );
+        assert_eq!(commit_epoch("not base64!"), None);
+    }
 }
@@ -3,4 +3,5 @@
 pub mod config;
 pub mod db;
 pub mod handlers;
+pub mod metrics;
 pub mod server;
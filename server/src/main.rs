@@ -7,6 +7,7 @@
 mod config;
 mod db;
 mod handlers;
+mod metrics;
 mod server;
 
 use actix_web::web;
@@ -15,6 +16,7 @@ use handlers::{ServerConfig, WsServer};
 use std::fs;
 use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -25,12 +27,36 @@ async fn main() -> std::io::Result<()> {
     let config = Config::from_args();
 
     log::info!("Starting MLS Chat Server");
-    log::info!("Database: {:?}", config.database);
+    log::info!("Database: {}", config.database);
     log::info!("Port: {}", config.port);
     log::info!(
         "KeyPackage reservation timeout: {}s",
         config.reservation_timeout_seconds
     );
+    log::info!(
+        "KeyPackage quota per user: {}",
+        config.max_keypackages_per_user
+    );
+    log::info!("Group limit per user: {}", config.max_groups_per_user);
+    log::info!("Backup versions per user: {}", config.max_backup_versions);
+    log::info!(
+        "Expired message prune interval: {}s",
+        config.message_prune_interval_seconds
+    );
+    log::info!("Stored Welcome TTL: {}s", config.welcome_ttl_seconds);
+    log::info!(
+        "WebSocket connections per user: {}",
+        config.max_connections_per_user
+    );
+    log::info!(
+        "Group fan-out: {}",
+        if config.unordered_fan_out {
+            "unordered"
+        } else {
+            "ordered per group"
+        }
+    );
+    log::info!("Moderators: {:?}", config.moderators);
 
     // Write PID file if specified
     if let Some(pidfile) = &config.pidfile {
@@ -40,15 +66,52 @@ async fn main() -> std::io::Result<()> {
     }
 
     // Initialize database
-    let pool =
-        db::create_pool(config.database.to_str().unwrap()).expect("Failed to create database pool");
+    let database_path = match config.database_path() {
+        Ok(path) => path,
+        Err(e) => {
+            log::error!("Invalid --database: {}", e);
+            process::exit(2);
+        }
+    };
+    let pool = db::create_pool(database_path.to_str().expect("Database path is not UTF-8"))
+        .expect("Failed to create database pool");
 
     log::info!("Database initialized");
 
+    // Prune messages of groups with a message TTL (disappearing messages)
+    // and Welcomes nobody picked up
+    let prune_pool = pool.clone();
+    let prune_interval = Duration::from_secs(config.message_prune_interval_seconds.max(1));
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(prune_interval);
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now().timestamp();
+            match db::Database::prune_expired_messages(&prune_pool, now).await {
+                Ok(0) => {}
+                Ok(pruned) => log::info!("Pruned {} expired messages", pruned),
+                Err(e) => log::error!("Failed to prune expired messages: {}", e),
+            }
+            match db::Database::prune_expired_welcomes(&prune_pool, now).await {
+                Ok(0) => {}
+                Ok(pruned) => log::info!("Pruned {} expired Welcomes", pruned),
+                Err(e) => log::error!("Failed to prune expired Welcomes: {}", e),
+            }
+        }
+    });
+
     let pool_data = web::Data::new(pool.clone());
-    let ws_server = web::Data::new(WsServer::new(Arc::new(pool_data.clone())));
+    let ws_server = web::Data::new(
+        WsServer::new(Arc::new(pool_data.clone())).with_ordered_fan_out(!config.unordered_fan_out),
+    );
     let server_config = web::Data::new(ServerConfig {
         reservation_timeout_seconds: config.reservation_timeout_seconds,
+        max_keypackages_per_user: config.max_keypackages_per_user,
+        max_groups_per_user: config.max_groups_per_user,
+        max_backup_versions: config.max_backup_versions.max(1),
+        welcome_ttl_seconds: config.welcome_ttl_seconds.max(1),
+        max_connections_per_user: config.max_connections_per_user.max(1),
+        moderators: config.moderators.clone(),
     });
 
     // Start HTTP server
@@ -0,0 +1,256 @@
+/// Message metrics for capacity planning, exported at `/metrics`.
+///
+/// Recording only happens in builds with the `metrics` feature; without it
+/// every `record_*` call is a no-op and `/metrics` answers 404. Rates are
+/// left to the scraper (e.g. Prometheus `rate()` over the counters).
+///
+/// Labels are fixed strings chosen here (message types and bucket bounds),
+/// never anything taken from a message: no payload, username or group id is
+/// ever part of a metric.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds in bytes of the message size histogram buckets
+pub const SIZE_BUCKETS: [u64; 7] = [256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+/// Kinds of messages clients send over the WebSocket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Application,
+    Welcome,
+    Commit,
+}
+
+impl MessageKind {
+    const ALL: [MessageKind; 3] = [
+        MessageKind::Application,
+        MessageKind::Welcome,
+        MessageKind::Commit,
+    ];
+
+    /// Value of the `type` label
+    pub fn label(self) -> &'static str {
+        match self {
+            MessageKind::Application => "application",
+            MessageKind::Welcome => "welcome",
+            MessageKind::Commit => "commit",
+        }
+    }
+}
+
+/// Size histogram with one count per bucket plus an overflow bucket
+#[derive(Default)]
+struct SizeHistogram {
+    buckets: [AtomicU64; SIZE_BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+impl SizeHistogram {
+    fn observe(&self, size: u64) {
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|bound| size <= *bound)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Append the `_bucket`, `_sum` and `_count` samples (buckets cumulative)
+    fn render(&self, out: &mut String, name: &str, kind: &str) {
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = SIZE_BUCKETS
+                .get(i)
+                .map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(
+                out,
+                "{}_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                name, kind, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum{{type=\"{}\"}} {}",
+            name,
+            kind,
+            self.sum.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "{}_count{{type=\"{}\"}} {}", name, kind, cumulative);
+    }
+}
+
+/// Counters shared by all WebSocket connections (see `WsServer::metrics`)
+///
+/// Updates are single relaxed atomic additions, cheap enough for every
+/// message.
+#[derive(Default)]
+pub struct Metrics {
+    received: [AtomicU64; 3],
+    sizes: [SizeHistogram; 3],
+    delivered: AtomicU64,
+    deferred: AtomicU64,
+}
+
+impl Metrics {
+    /// Whether this build records metrics (the `metrics` feature)
+    pub const ENABLED: bool = cfg!(feature = "metrics");
+
+    /// Count a message received from a client, with its payload size
+    pub fn record_received(&self, kind: MessageKind, size: usize) {
+        if Self::ENABLED {
+            self.received[kind as usize].fetch_add(1, Ordering::Relaxed);
+            self.sizes[kind as usize].observe(size as u64);
+        }
+    }
+
+    /// Count messages pushed to connected clients
+    pub fn record_delivered(&self, count: usize) {
+        if Self::ENABLED {
+            self.delivered.fetch_add(count as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count deliveries deferred because nobody was subscribed to the group
+    pub fn record_deferred(&self, count: usize) {
+        if Self::ENABLED {
+            self.deferred.fetch_add(count as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format (0.0.4)
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP mls_chat_messages_received_total Messages received from clients, by type\n",
+        );
+        out.push_str("# TYPE mls_chat_messages_received_total counter\n");
+        for kind in MessageKind::ALL {
+            let _ = writeln!(
+                out,
+                "mls_chat_messages_received_total{{type=\"{}\"}} {}",
+                kind.label(),
+                self.received[kind as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str(
+            "# HELP mls_chat_message_size_bytes Size of received message payloads in bytes\n",
+        );
+        out.push_str("# TYPE mls_chat_message_size_bytes histogram\n");
+        for kind in MessageKind::ALL {
+            self.sizes[kind as usize].render(&mut out, "mls_chat_message_size_bytes", kind.label());
+        }
+
+        out.push_str(
+            "# HELP mls_chat_messages_delivered_total Messages pushed to connected clients\n",
+        );
+        out.push_str("# TYPE mls_chat_messages_delivered_total counter\n");
+        let _ = writeln!(
+            out,
+            "mls_chat_messages_delivered_total {}",
+            self.delivered.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP mls_chat_messages_deferred_total Deliveries deferred until the recipient subscribes\n",
+        );
+        out.push_str("# TYPE mls_chat_messages_deferred_total counter\n");
+        let _ = writeln!(
+            out,
+            "mls_chat_messages_deferred_total {}",
+            self.deferred.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check each sample line is `name{labels} value` and belongs to a family
+    /// declared by a preceding `# TYPE` line
+    fn assert_valid_exposition(text: &str) {
+        let mut families: Vec<&str> = Vec::new();
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let mut parts = rest.split(' ');
+                families.push(parts.next().unwrap());
+                assert!(matches!(
+                    parts.next(),
+                    Some("counter" | "gauge" | "histogram")
+                ));
+                continue;
+            }
+            if line.starts_with("# HELP ") {
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').expect("sample without value");
+            value.parse::<f64>().expect("sample value is not a number");
+            let name = series.split('{').next().unwrap();
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+            let labels = &series[name.len()..];
+            assert!(labels.is_empty() || (labels.starts_with('{') && labels.ends_with('}')));
+            assert!(
+                families.iter().any(|family| name == *family
+                    || ["_bucket", "_sum", "_count"]
+                        .iter()
+                        .any(|suffix| name == format!("{}{}", family, suffix))),
+                "sample {} has no # TYPE",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_is_valid_exposition_format() {
+        let metrics = Metrics::default();
+        metrics.record_received(MessageKind::Application, 100);
+        metrics.record_received(MessageKind::Application, 5000);
+        metrics.record_received(MessageKind::Commit, 2_000_000);
+        metrics.record_delivered(3);
+        metrics.record_deferred(2);
+
+        let text = metrics.render();
+        assert_valid_exposition(&text);
+
+        let expected = |application: u64, commit: u64, delivered: u64, deferred: u64| {
+            [
+                format!(
+                    "mls_chat_messages_received_total{{type=\"application\"}} {}",
+                    application
+                ),
+                format!(
+                    "mls_chat_messages_received_total{{type=\"commit\"}} {}",
+                    commit
+                ),
+                format!("mls_chat_messages_delivered_total {}", delivered),
+                format!("mls_chat_messages_deferred_total {}", deferred),
+            ]
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        if Metrics::ENABLED {
+            for line in expected(2, 1, 3, 2) {
+                assert!(lines.contains(&line.as_str()), "missing {}", line);
+            }
+            // Buckets are cumulative; the commit only fits the +Inf bucket
+            assert!(lines.contains(
+                &"mls_chat_message_size_bytes_bucket{type=\"application\",le=\"256\"} 1"
+            ));
+            assert!(lines.contains(
+                &"mls_chat_message_size_bytes_bucket{type=\"application\",le=\"16384\"} 2"
+            ));
+            assert!(lines
+                .contains(&"mls_chat_message_size_bytes_bucket{type=\"commit\",le=\"1048576\"} 0"));
+            assert!(lines
+                .contains(&"mls_chat_message_size_bytes_bucket{type=\"commit\",le=\"+Inf\"} 1"));
+            assert!(lines.contains(&"mls_chat_message_size_bytes_sum{type=\"application\"} 5100"));
+        } else {
+            // Disabled builds record nothing
+            for line in expected(0, 0, 0, 0) {
+                assert!(lines.contains(&line.as_str()), "missing {}", line);
+            }
+        }
+    }
+}
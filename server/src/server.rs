@@ -1,7 +1,14 @@
 use crate::db::DbPool;
 use crate::handlers::{
-    get_backup, get_keypackage_status, get_user_key, health, register_user, reserve_key_package,
-    spend_key_package, store_backup, upload_key_packages, ws_connect, ServerConfig, WsServer,
+    complete_join_request, create_join_token, delete_welcome, deregister_user,
+    get_available_keypackage_count, get_backup, get_backup_version, get_commits_since, get_group,
+    get_group_epoch, get_group_info, get_group_members, get_join_requests, get_keypackage_status,
+    get_messages_by_sender, get_messages_since, get_metrics, get_pending_welcomes, get_user_groups,
+    get_user_identity, get_user_key, health, join_group, list_backup_versions, list_keypackages,
+    publish_group_info, redeem_join_token, register_group, register_user, release_reservation,
+    reserve_key_package, revoke_join_token, revoke_key_packages, rotate_user_key, sequence_commit,
+    set_group_message_ttl, spend_key_package, store_backup, store_welcome, upload_key_packages,
+    ws_connect, ServerConfig, WsServer,
 };
 /// HTTP server factory and configuration.
 /// Provides a reusable function to create and configure the HTTP server
@@ -47,13 +54,72 @@ pub fn create_http_server(
             .wrap(middleware::Logger::default())
             // REST endpoints
             .route("/health", web::get().to(health))
+            .route("/metrics", web::get().to(get_metrics))
             .route("/users", web::post().to(register_user))
             .route("/users/{username}", web::get().to(get_user_key))
+            .route("/users/{username}", web::delete().to(deregister_user))
+            .route("/users/{username}", web::put().to(rotate_user_key))
+            .route(
+                "/users/{username}/identity",
+                web::get().to(get_user_identity),
+            )
+            .route(
+                "/users/{username}/keypackages/count",
+                web::get().to(get_available_keypackage_count),
+            )
+            .route("/users/{username}/groups", web::get().to(get_user_groups))
+            .route("/groups", web::post().to(register_group))
+            .route("/groups/members", web::post().to(join_group))
+            .route("/groups/commits", web::post().to(sequence_commit))
+            .route("/groups/commits", web::get().to(get_commits_since))
+            .route("/groups/messages", web::get().to(get_messages_since))
+            .route(
+                "/groups/messages/by-sender",
+                web::get().to(get_messages_by_sender),
+            )
+            .route("/groups/info", web::post().to(publish_group_info))
+            .route("/groups/info", web::get().to(get_group_info))
+            .route("/groups/{group_id}/epoch", web::get().to(get_group_epoch))
+            .route(
+                "/groups/{group_id}/members",
+                web::get().to(get_group_members),
+            )
+            .route("/groups/retention", web::post().to(set_group_message_ttl))
+            .route("/groups/tokens", web::post().to(create_join_token))
+            .route("/groups/tokens/redeem", web::post().to(redeem_join_token))
+            .route("/groups/tokens/revoke", web::post().to(revoke_join_token))
+            .route(
+                "/groups/tokens/complete",
+                web::post().to(complete_join_request),
+            )
+            .route(
+                "/groups/{group_id}/join-requests",
+                web::get().to(get_join_requests),
+            )
+            // After the fixed /groups/... paths, which it would otherwise shadow
+            .route("/groups/{group_id}", web::get().to(get_group))
+            .route("/welcomes", web::post().to(store_welcome))
+            .route("/welcomes/{username}", web::get().to(get_pending_welcomes))
+            .route(
+                "/welcomes/{username}/{id}",
+                web::delete().to(delete_welcome),
+            )
             .route("/backup/{username}", web::post().to(store_backup))
             .route("/backup/{username}", web::get().to(get_backup))
+            .route("/backups/{username}", web::get().to(get_backup_version))
+            .route(
+                "/backups/{username}/versions",
+                web::get().to(list_backup_versions),
+            )
             .route("/keypackages/upload", web::post().to(upload_key_packages))
             .route("/keypackages/reserve", web::post().to(reserve_key_package))
             .route("/keypackages/spend", web::post().to(spend_key_package))
+            .route("/keypackages/release", web::post().to(release_reservation))
+            .route("/keypackages/revoke", web::post().to(revoke_key_packages))
+            .route(
+                "/keypackages/list/{username}",
+                web::get().to(list_keypackages),
+            )
             .route(
                 "/keypackages/status/{username}",
                 web::get().to(get_keypackage_status),
@@ -100,9 +166,29 @@ pub fn create_http_server(
 #[cfg(any(test, feature = "test_utils"))]
 pub fn create_test_http_server_with_pool(
     pool: web::Data<DbPool>,
+) -> std::io::Result<(actix_web::dev::Server, String)> {
+    bind_test_http_server(pool, ServerConfig::default())
+}
+
+/// Create a test HTTP server with an in-memory database and custom limits.
+/// Lets tests exercise configuration such as `max_groups_per_user` without
+/// creating hundreds of objects first.
+#[cfg(any(test, feature = "test_utils"))]
+pub fn create_test_http_server_with_config(
+    server_config: ServerConfig,
+) -> std::io::Result<(actix_web::dev::Server, String)> {
+    let pool = web::Data::new(crate::db::create_test_pool());
+    bind_test_http_server(pool, server_config)
+}
+
+/// Create a test HTTP server with both a shared pool and custom limits
+#[cfg(any(test, feature = "test_utils"))]
+pub fn bind_test_http_server(
+    pool: web::Data<DbPool>,
+    server_config: ServerConfig,
 ) -> std::io::Result<(actix_web::dev::Server, String)> {
     let ws_server = web::Data::new(WsServer::new(Arc::new(pool.clone())));
-    let server_config = web::Data::new(ServerConfig::default());
+    let server_config = web::Data::new(server_config);
 
     // Bind to 127.0.0.1:0 to get a random available port
     let bind_addr = "127.0.0.1:0";
@@ -118,13 +204,72 @@ pub fn create_test_http_server_with_pool(
             .wrap(middleware::Logger::default())
             // REST endpoints
             .route("/health", web::get().to(health))
+            .route("/metrics", web::get().to(get_metrics))
             .route("/users", web::post().to(register_user))
             .route("/users/{username}", web::get().to(get_user_key))
+            .route("/users/{username}", web::delete().to(deregister_user))
+            .route("/users/{username}", web::put().to(rotate_user_key))
+            .route(
+                "/users/{username}/identity",
+                web::get().to(get_user_identity),
+            )
+            .route(
+                "/users/{username}/keypackages/count",
+                web::get().to(get_available_keypackage_count),
+            )
+            .route("/users/{username}/groups", web::get().to(get_user_groups))
+            .route("/groups", web::post().to(register_group))
+            .route("/groups/members", web::post().to(join_group))
+            .route("/groups/commits", web::post().to(sequence_commit))
+            .route("/groups/commits", web::get().to(get_commits_since))
+            .route("/groups/messages", web::get().to(get_messages_since))
+            .route(
+                "/groups/messages/by-sender",
+                web::get().to(get_messages_by_sender),
+            )
+            .route("/groups/info", web::post().to(publish_group_info))
+            .route("/groups/info", web::get().to(get_group_info))
+            .route("/groups/{group_id}/epoch", web::get().to(get_group_epoch))
+            .route(
+                "/groups/{group_id}/members",
+                web::get().to(get_group_members),
+            )
+            .route("/groups/retention", web::post().to(set_group_message_ttl))
+            .route("/groups/tokens", web::post().to(create_join_token))
+            .route("/groups/tokens/redeem", web::post().to(redeem_join_token))
+            .route("/groups/tokens/revoke", web::post().to(revoke_join_token))
+            .route(
+                "/groups/tokens/complete",
+                web::post().to(complete_join_request),
+            )
+            .route(
+                "/groups/{group_id}/join-requests",
+                web::get().to(get_join_requests),
+            )
+            // After the fixed /groups/... paths, which it would otherwise shadow
+            .route("/groups/{group_id}", web::get().to(get_group))
+            .route("/welcomes", web::post().to(store_welcome))
+            .route("/welcomes/{username}", web::get().to(get_pending_welcomes))
+            .route(
+                "/welcomes/{username}/{id}",
+                web::delete().to(delete_welcome),
+            )
             .route("/backup/{username}", web::post().to(store_backup))
             .route("/backup/{username}", web::get().to(get_backup))
+            .route("/backups/{username}", web::get().to(get_backup_version))
+            .route(
+                "/backups/{username}/versions",
+                web::get().to(list_backup_versions),
+            )
             .route("/keypackages/upload", web::post().to(upload_key_packages))
             .route("/keypackages/reserve", web::post().to(reserve_key_package))
             .route("/keypackages/spend", web::post().to(spend_key_package))
+            .route("/keypackages/release", web::post().to(release_reservation))
+            .route("/keypackages/revoke", web::post().to(revoke_key_packages))
+            .route(
+                "/keypackages/list/{username}",
+                web::get().to(list_keypackages),
+            )
             .route(
                 "/keypackages/status/{username}",
                 web::get().to(get_keypackage_status),
@@ -268,6 +413,24 @@ mod tests {
         assert!(!port_part.is_empty(), "Port should be assigned");
     }
 
+    #[tokio::test]
+    async fn test_create_test_http_server_with_config() {
+        let result = create_test_http_server_with_config(ServerConfig {
+            max_groups_per_user: 1,
+            ..ServerConfig::default()
+        });
+        assert!(
+            result.is_ok(),
+            "create_test_http_server_with_config should succeed"
+        );
+
+        let (_server, addr) = result.unwrap();
+        assert!(
+            addr.contains("127.0.0.1:"),
+            "Address should contain 127.0.0.1:"
+        );
+    }
+
     #[tokio::test]
     async fn test_create_test_http_server_assigns_random_port() {
         let (_, addr1) = create_test_http_server().expect("First server creation should succeed");
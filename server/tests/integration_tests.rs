@@ -1,5 +1,6 @@
 /// Integration tests for REST API endpoints
 /// Tests database operations and error handling through direct DB calls
+use mls_chat_server::db::models::ContentType;
 use mls_chat_server::db::Database;
 
 #[tokio::test]
@@ -169,15 +170,27 @@ async fn test_message_storage_and_retrieval() {
         .expect("Failed to create group");
 
     // Store messages
-    let msg1 = Database::store_message(&pool, group.id, user1.id, "hello from alice")
-        .await
-        .expect("Failed to store message");
+    let msg1 = Database::store_message(
+        &pool,
+        group.id,
+        user1.id,
+        "hello from alice",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store message");
     assert_eq!(msg1.encrypted_content, "hello from alice");
     assert_eq!(msg1.sender_id, user1.id);
 
-    let msg2 = Database::store_message(&pool, group.id, user2.id, "hello from bob")
-        .await
-        .expect("Failed to store message");
+    let msg2 = Database::store_message(
+        &pool,
+        group.id,
+        user2.id,
+        "hello from bob",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store message");
     assert_eq!(msg2.encrypted_content, "hello from bob");
     assert_eq!(msg2.sender_id, user2.id);
 
@@ -260,19 +273,43 @@ async fn test_complex_workflow() {
         .expect("Failed to create group");
 
     // Store messages to different groups
-    Database::store_message(&pool, project_a.id, alice.id, "alice msg in a")
-        .await
-        .expect("Failed to store");
-    Database::store_message(&pool, project_a.id, bob.id, "bob msg in a")
-        .await
-        .expect("Failed to store");
-
-    Database::store_message(&pool, project_b.id, alice.id, "alice msg in b")
-        .await
-        .expect("Failed to store");
-    Database::store_message(&pool, project_b.id, bob.id, "bob msg in b")
-        .await
-        .expect("Failed to store");
+    Database::store_message(
+        &pool,
+        project_a.id,
+        alice.id,
+        "alice msg in a",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store");
+    Database::store_message(
+        &pool,
+        project_a.id,
+        bob.id,
+        "bob msg in a",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store");
+
+    Database::store_message(
+        &pool,
+        project_b.id,
+        alice.id,
+        "alice msg in b",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store");
+    Database::store_message(
+        &pool,
+        project_b.id,
+        bob.id,
+        "bob msg in b",
+        ContentType::Text,
+    )
+    .await
+    .expect("Failed to store");
 
     // Store backups
     Database::store_backup(&pool, "alice", "alice backup 1")
@@ -1,9 +1,97 @@
 use actix_web::web;
 /// WebSocket integration tests
 /// Tests WebSocket connections, message broadcasting, and group subscriptions
-use mls_chat_server::db::Database;
-use mls_chat_server::handlers::WsServer;
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+use futures::StreamExt;
+use mls_chat_server::db::models::{ws_connect_payload, ContentType};
+use mls_chat_server::db::{Database, DbPool};
+use mls_chat_server::handlers::websocket::user_channel;
+use mls_chat_server::handlers::{ServerConfig, WsServer};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
+
+type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Register `username` with a KeyPackage whose signature key is derived
+/// from `seed`, and return that key for signing connections
+async fn register_signer(pool: &DbPool, username: &str, seed: u8) -> SigningKey {
+    let signing_key = SigningKey::from_bytes(&[seed; 32]);
+    // version, cipher suite, init key, encryption key, signature key,
+    // Basic credential
+    let mut key_package = vec![0, 1, 0, 1, 3, 1, 2, 3, 2, 4, 5, 32];
+    key_package.extend_from_slice(signing_key.verifying_key().as_bytes());
+    key_package.extend_from_slice(&[0, 1, 1, seed]);
+    Database::register_user(pool, username, &key_package)
+        .await
+        .unwrap();
+    signing_key
+}
+
+/// URL of `username`'s WebSocket, signed by `signer`
+fn ws_url(addr: &str, username: &str, signer: &SigningKey) -> String {
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = general_purpose::STANDARD
+        .encode(
+            signer
+                .sign(&ws_connect_payload(username, timestamp))
+                .to_bytes(),
+        )
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D");
+    format!(
+        "ws://{}/ws/{}?timestamp={}&signature={}",
+        addr, username, timestamp, signature
+    )
+}
+
+/// Start a server allowing `max_connections_per_user` connections per user,
+/// with alice and bob registered
+async fn start_server_with_connection_limit(
+    max_connections_per_user: usize,
+) -> (String, SigningKey, SigningKey) {
+    let pool = web::Data::new(mls_chat_server::db::create_test_pool());
+    let alice = register_signer(&pool, "alice", 1).await;
+    let bob = register_signer(&pool, "bob", 2).await;
+    let (server, addr) = mls_chat_server::server::bind_test_http_server(
+        pool,
+        ServerConfig {
+            max_connections_per_user,
+            ..ServerConfig::default()
+        },
+    )
+    .expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    (addr, alice, bob)
+}
+
+async fn open_connection(addr: &str, username: &str, signer: &SigningKey) -> WsStream {
+    let (ws, _) = tokio_tungstenite::connect_async(ws_url(addr, username, signer))
+        .await
+        .expect("WebSocket handshake");
+    ws
+}
+
+/// Close code the server sent on `ws`, or `None` if it kept the connection open
+async fn server_close_code(ws: &mut WsStream) -> Option<CloseCode> {
+    match tokio::time::timeout(Duration::from_millis(300), ws.next()).await {
+        Ok(Some(Ok(Message::Close(frame)))) => frame.map(|frame| frame.code),
+        _ => None,
+    }
+}
+
+/// Next message on `ws`, failing the test if none arrives in time
+async fn next_message(ws: &mut WsStream) -> Message {
+    match tokio::time::timeout(Duration::from_secs(2), ws.next()).await {
+        Ok(Some(Ok(msg))) => msg,
+        other => panic!("no message: {:?}", other),
+    }
+}
 
 #[tokio::test]
 async fn test_websocket_client_lifecycle() {
@@ -52,6 +140,107 @@ async fn test_websocket_group_subscription() {
     assert!(groups.get("group1").unwrap().contains("client2"));
 }
 
+#[tokio::test]
+async fn test_websocket_subscription_requires_membership() {
+    let pool = Arc::new(web::Data::new(mls_chat_server::db::create_test_pool()));
+    let server = WsServer::new(pool.clone());
+
+    let (tx1, _rx1) = tokio::sync::mpsc::unbounded_channel();
+    let (tx2, _rx2) = tokio::sync::mpsc::unbounded_channel();
+    server.register("alice_client".to_string(), tx1).await;
+    server.register("mallory_client".to_string(), tx2).await;
+
+    Database::add_group_member(&pool, "alice", "group1")
+        .await
+        .unwrap();
+
+    // A member's subscription is honored
+    assert!(
+        server
+            .subscribe_member("alice_client".to_string(), "alice", "group1".to_string())
+            .await
+    );
+
+    // A non-member's is rejected, even for a group that exists
+    assert!(
+        !server
+            .subscribe_member(
+                "mallory_client".to_string(),
+                "mallory",
+                "group1".to_string()
+            )
+            .await
+    );
+    assert!(
+        !server
+            .subscribe_member(
+                "mallory_client".to_string(),
+                "mallory",
+                "group2".to_string()
+            )
+            .await
+    );
+
+    let groups = server.groups.read().await;
+    let members = groups.get("group1").unwrap();
+    assert!(members.contains("alice_client"));
+    assert!(!members.contains("mallory_client"));
+    assert!(!groups.contains_key("group2"));
+}
+
+#[tokio::test]
+async fn test_websocket_user_can_subscribe_to_own_channel() {
+    let pool = Arc::new(web::Data::new(mls_chat_server::db::create_test_pool()));
+    let server = WsServer::new(pool.clone());
+
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    server.register("bob_client".to_string(), tx).await;
+
+    // Welcomes are broadcast to the invitee's own channel
+    assert!(
+        server
+            .subscribe_member("bob_client".to_string(), "bob", user_channel("bob"))
+            .await
+    );
+    assert!(
+        !server
+            .subscribe_member("bob_client".to_string(), "bob", user_channel("alice"))
+            .await
+    );
+
+    // A group id equal to a username is an ordinary group
+    assert!(
+        !server
+            .subscribe_member("bob_client".to_string(), "bob", "bob".to_string())
+            .await
+    );
+}
+
+#[tokio::test]
+async fn test_websocket_invitee_can_subscribe_once_added() {
+    let pool = Arc::new(web::Data::new(mls_chat_server::db::create_test_pool()));
+    let server = WsServer::new(pool.clone());
+
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    server.register("bob_client".to_string(), tx).await;
+
+    assert!(
+        !server
+            .subscribe_member("bob_client".to_string(), "bob", "group1".to_string())
+            .await
+    );
+
+    // Reserving bob's KeyPackage for the invite records them as a member
+    Database::add_group_member(&pool, "bob", "group1")
+        .await
+        .unwrap();
+    assert!(
+        server
+            .subscribe_member("bob_client".to_string(), "bob", "group1".to_string())
+            .await
+    );
+}
+
 #[tokio::test]
 async fn test_websocket_message_broadcast() {
     let pool = Arc::new(web::Data::new(mls_chat_server::db::create_test_pool()));
@@ -153,10 +342,10 @@ async fn test_websocket_persist_message() {
 
     // Persist a message
     let persisted = server
-        .persist_message("group1", "alice", "encrypted_content")
+        .persist_message("group1", "alice", "encrypted_content", ContentType::Text)
         .await;
 
-    assert!(persisted);
+    assert!(persisted.is_some());
 
     // Verify message was stored
     let groups = Database::get_group(pool.as_ref(), "group1")
@@ -178,10 +367,10 @@ async fn test_websocket_persist_nonexistent_user() {
 
     // Try to persist message from non-existent user
     let persisted = server
-        .persist_message("group1", "nonexistent", "content")
+        .persist_message("group1", "nonexistent", "content", ContentType::Text)
         .await;
 
-    assert!(!persisted);
+    assert!(persisted.is_none());
 }
 
 #[tokio::test]
@@ -216,12 +405,17 @@ async fn test_websocket_multiple_clients_same_group() {
         .await;
 
     // Alice persists a message
-    let persisted = server.persist_message("team", "alice", "alice_msg").await;
-    assert!(persisted);
+    let alice_seq = server
+        .persist_message("team", "alice", "alice_msg", ContentType::Text)
+        .await
+        .expect("alice's message should persist");
 
-    // Bob persists a message
-    let persisted = server.persist_message("team", "bob", "bob_msg").await;
-    assert!(persisted);
+    // Bob persists a message, which comes later in the group's history
+    let bob_seq = server
+        .persist_message("team", "bob", "bob_msg", ContentType::Text)
+        .await
+        .expect("bob's message should persist");
+    assert!(bob_seq > alice_seq);
 
     // Verify both messages were stored
     let group = Database::get_group(pool.as_ref(), "team")
@@ -281,10 +475,10 @@ async fn test_websocket_envelope_application_message() {
 
     // Persist an application message using envelope format
     let persisted = server
-        .persist_message("team", "alice", "encrypted_app_msg")
+        .persist_message("team", "alice", "encrypted_app_msg", ContentType::Text)
         .await;
 
-    assert!(persisted, "Should persist application message");
+    assert!(persisted.is_some(), "Should persist application message");
 
     // Verify message was stored
     let group = Database::get_group(pool.as_ref(), "team")
@@ -347,14 +541,14 @@ async fn test_websocket_envelope_message_isolation_between_groups() {
 
     // Send application messages to different groups
     let msg_group1 = server
-        .persist_message("group1", "alice", "msg_for_group1")
+        .persist_message("group1", "alice", "msg_for_group1", ContentType::Text)
         .await;
     let msg_group2 = server
-        .persist_message("group2", "alice", "msg_for_group2")
+        .persist_message("group2", "alice", "msg_for_group2", ContentType::Text)
         .await;
 
-    assert!(msg_group1, "Should persist message to group1");
-    assert!(msg_group2, "Should persist message to group2");
+    assert!(msg_group1.is_some(), "Should persist message to group1");
+    assert!(msg_group2.is_some(), "Should persist message to group2");
 
     // Verify messages were stored in separate groups
     let group1 = Database::get_group(pool.as_ref(), "group1")
@@ -406,3 +600,162 @@ async fn test_websocket_subscription_only_protocol() {
     let group_members = groups.get("chat_group").cloned();
     assert!(group_members.is_none() || !group_members.unwrap().contains("client1"));
 }
+
+#[tokio::test]
+async fn test_websocket_connections_beyond_the_limit_are_closed() {
+    let (addr, alice, bob) = start_server_with_connection_limit(3).await;
+
+    // Several devices of the same user are fine up to the limit
+    let mut devices = Vec::new();
+    for _ in 0..3 {
+        let mut ws = open_connection(&addr, "alice", &alice).await;
+        assert_eq!(server_close_code(&mut ws).await, None);
+        devices.push(ws);
+    }
+
+    // The next one is closed with a policy violation
+    let mut extra = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(server_close_code(&mut extra).await, Some(CloseCode::Policy));
+
+    // Other users have their own slots
+    let mut bob = open_connection(&addr, "bob", &bob).await;
+    assert_eq!(server_close_code(&mut bob).await, None);
+}
+
+#[tokio::test]
+async fn test_websocket_unsigned_connections_are_refused_without_a_slot() {
+    let (addr, alice, bob) = start_server_with_connection_limit(1).await;
+    let refused = |url: String| async move {
+        match tokio_tungstenite::connect_async(url).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status(),
+            other => panic!("handshake should be refused, got {:?}", other.map(|_| ())),
+        }
+    };
+
+    // Unsigned, signed by someone else, or for an unknown user
+    assert_eq!(
+        refused(format!("ws://{}/ws/alice", addr)).await.as_u16(),
+        400
+    );
+    assert_eq!(refused(ws_url(&addr, "alice", &bob)).await.as_u16(), 401);
+    assert_eq!(refused(ws_url(&addr, "carol", &bob)).await.as_u16(), 401);
+
+    // None of them took alice's only slot
+    let mut laptop = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(server_close_code(&mut laptop).await, None);
+}
+
+#[tokio::test]
+async fn test_websocket_closing_a_connection_frees_its_slot() {
+    let (addr, alice, _) = start_server_with_connection_limit(2).await;
+
+    let mut laptop = open_connection(&addr, "alice", &alice).await;
+    let mut phone = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(server_close_code(&mut laptop).await, None);
+    assert_eq!(server_close_code(&mut phone).await, None);
+
+    let mut tablet = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(
+        server_close_code(&mut tablet).await,
+        Some(CloseCode::Policy)
+    );
+
+    // Close the phone and wait for the server to acknowledge it
+    phone.close(None).await.expect("close phone");
+    while let Some(Ok(_)) = phone.next().await {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut tablet = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(server_close_code(&mut tablet).await, None);
+
+    // Rejected connections did not take a slot either
+    let mut extra = open_connection(&addr, "alice", &alice).await;
+    assert_eq!(server_close_code(&mut extra).await, Some(CloseCode::Policy));
+}
+
+#[tokio::test]
+async fn test_websocket_binary_framing_is_negotiated_per_connection() {
+    use futures::SinkExt;
+
+    let pool = web::Data::new(mls_chat_server::db::create_test_pool());
+    let alice_key = register_signer(&pool, "alice", 1).await;
+    let bob_key = register_signer(&pool, "bob", 2).await;
+    for username in ["alice", "bob"] {
+        Database::add_group_member(&pool, username, "Z3JvdXAx")
+            .await
+            .unwrap();
+    }
+    let (server, addr) = mls_chat_server::server::create_test_http_server_with_pool(pool)
+        .expect("Failed to create test server");
+    tokio::spawn(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut alice, response) =
+        tokio_tungstenite::connect_async(ws_url(&addr, "alice", &alice_key) + "&framing=binary")
+            .await
+            .expect("WebSocket handshake");
+    assert_eq!(
+        response
+            .headers()
+            .get("x-mls-framing")
+            .map(|v| v.as_bytes()),
+        Some(&b"binary"[..])
+    );
+    let (mut bob, response) = tokio_tungstenite::connect_async(ws_url(&addr, "bob", &bob_key))
+        .await
+        .expect("WebSocket handshake");
+    assert!(response.headers().get("x-mls-framing").is_none());
+
+    let subscribe = r#"{"action":"subscribe","group_id":"Z3JvdXAx"}"#;
+    alice.send(Message::Text(subscribe.into())).await.unwrap();
+    bob.send(Message::Text(subscribe.into())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Bob sends JSON; alice receives the same envelope as a binary frame
+    let ciphertext = general_purpose::STANDARD.encode([7u8; 64]);
+    let envelope = serde_json::json!({
+        "type": "application",
+        "sender": "bob",
+        "group_id": "Z3JvdXAx",
+        "encrypted_content": ciphertext,
+        "content_type": "text"
+    });
+    bob.send(Message::Text(envelope.to_string().into()))
+        .await
+        .unwrap();
+
+    let Message::Binary(binary) = next_message(&mut alice).await else {
+        panic!("alice should get a binary frame");
+    };
+    let Message::Text(text) = next_message(&mut bob).await else {
+        panic!("bob should get a text frame");
+    };
+    assert!(binary.len() < text.len());
+    // The raw ciphertext is in the binary frame instead of its base64
+    assert!(binary.windows(64).any(|w| w == [7u8; 64]));
+    let received: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(received["encrypted_content"], envelope["encrypted_content"]);
+
+    // Alice's binary frame is routed like JSON and reaches bob as JSON
+    alice.send(Message::Binary(binary)).await.unwrap();
+    let Message::Text(text) = next_message(&mut bob).await else {
+        panic!("bob should get a text frame");
+    };
+    let received: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(received["sender"], "alice");
+    assert_eq!(received["encrypted_content"], envelope["encrypted_content"]);
+
+    // Garbage binary frames get the usual error, as text
+    alice.send(Message::Binary(vec![9].into())).await.unwrap();
+    loop {
+        match next_message(&mut alice).await {
+            Message::Text(text) => {
+                assert!(text.contains("Invalid message format"));
+                break;
+            }
+            // Alice's own message echoed back
+            Message::Binary(_) => continue,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}